@@ -17,6 +17,9 @@ use fonttools::tables::avar::{avar, SegmentMap};
 use fonttools::tables::fvar::{fvar, InstanceRecord, VariationAxisRecord};
 use fonttools::tables::name::NameRecord;
 
+/// Building variable fonts from interpolatable masters
+pub mod varlib;
+
 /// Loads and parses a designspace file
 pub fn from_file(filename: &str) -> Result<Designspace, serde_xml_rs::Error> {
     from_reader(File::open(filename).unwrap())
@@ -207,17 +210,27 @@ impl Designspace {
         NormalizedLocation(v)
     }
 
+    /// Returns a location in normalized coordinates, keyed by axis tag
+    pub fn normalized_tag_location(&self, loc: &Location) -> OTVarLocation {
+        let designspace_loc = self
+            .location_to_tuple(loc)
+            .iter()
+            .map(|x| *x as i32)
+            .collect();
+        self.axis_order()
+            .into_iter()
+            .zip(self.normalize_location(designspace_loc).0)
+            .collect()
+    }
+
     /// Constructs a fonttools variation model for this designspace
     pub fn variation_model(&self) -> VariationModel {
-        let mut locations: Vec<OTVarLocation> = vec![];
-        for source in self.sources.source.iter() {
-            let source_loc = self.normalize_location(self.source_location(source));
-            let mut loc = OTVarLocation::new();
-            for (ax, iter_l) in self.axes.axis.iter().zip(source_loc.0.iter()) {
-                loc.insert(ax.tag_as_tag(), *iter_l);
-            }
-            locations.push(loc);
-        }
+        let locations: Vec<OTVarLocation> = self
+            .sources
+            .source
+            .iter()
+            .map(|source| self.normalized_tag_location(&source.location))
+            .collect();
         VariationModel::new(locations, self.axis_order())
     }
 }
@@ -0,0 +1,417 @@
+use std::ops::{Mul, Sub};
+
+use fonttools::font::Font;
+use fonttools::otvar::{
+    ItemVariationData, ItemVariationStore, Location as OTVarLocation, RegionAxisCoordinates,
+    Support, VariationModel,
+};
+use fonttools::tables::glyf::Glyph;
+use fonttools::tables::gvar::{gvar, DeltaSet, GlyphVariationData};
+use fonttools::tables::hmtx::Metric;
+use fonttools::tables::HVAR::HVAR;
+use fonttools::tables::STAT::{AxisRecord, STAT};
+use fonttools::types::*;
+
+use crate::{Designspace, Location};
+
+/// An error raised when a variable font can't be built from its masters
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarLibError(pub String);
+
+impl std::fmt::Display for VarLibError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for VarLibError {}
+
+/// The coordinates of a glyph's points, components and phantom points, as
+/// interpolated by the variation model
+#[derive(Debug, Clone, PartialEq)]
+struct GlyphPoints(Vec<(f32, f32)>);
+
+impl Sub for GlyphPoints {
+    type Output = GlyphPoints;
+
+    fn sub(self, other: Self) -> Self {
+        GlyphPoints(
+            self.0
+                .iter()
+                .zip(other.0.iter())
+                .map(|(a, b)| (a.0 - b.0, a.1 - b.1))
+                .collect(),
+        )
+    }
+}
+
+impl Mul<f32> for GlyphPoints {
+    type Output = GlyphPoints;
+
+    fn mul(self, factor: f32) -> Self {
+        GlyphPoints(
+            self.0
+                .iter()
+                .map(|(x, y)| (x * factor, y * factor))
+                .collect(),
+        )
+    }
+}
+
+// The points of a glyph in the order of its gvar deltas: the outline points,
+// the component offsets, then the four phantom points. There are no vertical
+// metrics, so the vertical phantom points stay at zero.
+fn glyph_points(glyph: &Glyph, metric: &Metric) -> GlyphPoints {
+    let mut points: Vec<(f32, f32)> = glyph
+        .contours
+        .iter()
+        .flatten()
+        .map(|pt| (pt.x as f32, pt.y as f32))
+        .collect();
+    for component in &glyph.components {
+        let [_, _, _, _, translate_x, translate_y] = component.transformation.as_coeffs();
+        points.push((translate_x as f32, translate_y as f32));
+    }
+    let left_side_x = glyph.xMin as f32 - metric.lsb as f32;
+    points.push((left_side_x, 0.0));
+    points.push((left_side_x + metric.advanceWidth as f32, 0.0));
+    points.push((0.0, 0.0));
+    points.push((0.0, 0.0));
+    GlyphPoints(points)
+}
+
+fn check_glyph_compatibility(
+    gid: usize,
+    master_index: usize,
+    default: &Glyph,
+    master: &Glyph,
+) -> Result<(), VarLibError> {
+    let contour_lengths = |g: &Glyph| g.contours.iter().map(|c| c.len()).collect::<Vec<_>>();
+    let component_glyphs = |g: &Glyph| {
+        g.components
+            .iter()
+            .map(|c| c.glyph_index)
+            .collect::<Vec<_>>()
+    };
+    if contour_lengths(default) != contour_lengths(master) {
+        return Err(VarLibError(format!(
+            "Glyph {} has incompatible contours in master {}",
+            gid, master_index
+        )));
+    }
+    if component_glyphs(default) != component_glyphs(master) {
+        return Err(VarLibError(format!(
+            "Glyph {} has incompatible components in master {}",
+            gid, master_index
+        )));
+    }
+    Ok(())
+}
+
+// Converts a support into the start, peak and end tuples of a region
+fn support_tuples(support: &Support, axis_order: &[Tag]) -> (Tuple, Tuple, Tuple) {
+    let tents: Vec<(f32, f32, f32)> = axis_order
+        .iter()
+        .map(|tag| support.get(tag).copied().unwrap_or((0.0, 0.0, 0.0)))
+        .collect();
+    (
+        tents.iter().map(|t| t.0).collect(),
+        tents.iter().map(|t| t.1).collect(),
+        tents.iter().map(|t| t.2).collect(),
+    )
+}
+
+fn build_gvar(
+    model: &VariationModel,
+    axis_order: &[Tag],
+    glyphs: &[Vec<&Glyph>],
+    metrics: &[Vec<Metric>],
+) -> gvar {
+    let variations = glyphs
+        .iter()
+        .enumerate()
+        .map(|(gid, master_glyphs)| {
+            let master_points: Vec<Option<GlyphPoints>> = master_glyphs
+                .iter()
+                .zip(metrics.iter())
+                .map(|(glyph, metrics)| Some(glyph_points(glyph, &metrics[gid])))
+                .collect();
+            let deltasets: Vec<DeltaSet> = model
+                .get_deltas_and_supports(&master_points)
+                .into_iter()
+                .filter(|(_, support)| !support.is_empty())
+                .map(|(points, support)| {
+                    let (start, peak, end) = support_tuples(&support, axis_order);
+                    DeltaSet {
+                        peak,
+                        start,
+                        end,
+                        deltas: points
+                            .0
+                            .iter()
+                            .map(|&(x, y)| (ot_round(x) as int16, ot_round(y) as int16))
+                            .collect(),
+                    }
+                })
+                .filter(|ds| ds.deltas.iter().any(|&d| d != (0, 0)))
+                .collect();
+            if deltasets.is_empty() {
+                None
+            } else {
+                Some(GlyphVariationData { deltasets })
+            }
+        })
+        .collect();
+    gvar { variations }
+}
+
+// Builds the advance width deltas of each glyph, directly indexed by glyph
+// ID in a single item variation data subtable.
+fn build_hvar(model: &VariationModel, axis_order: &[Tag], metrics: &[Vec<Metric>]) -> HVAR {
+    let supports: Vec<&Support> = model.supports.iter().filter(|s| !s.is_empty()).collect();
+    let variation_regions = supports
+        .iter()
+        .map(|support| {
+            let (start, peak, end) = support_tuples(support, axis_order);
+            start
+                .iter()
+                .zip(peak.iter())
+                .zip(end.iter())
+                .map(|((&lower, &peak), &upper)| RegionAxisCoordinates {
+                    startCoord: lower,
+                    peakCoord: peak,
+                    endCoord: upper,
+                })
+                .collect()
+        })
+        .collect();
+    let delta_values = (0..metrics[0].len())
+        .map(|gid| {
+            let advances: Vec<Option<f32>> = metrics
+                .iter()
+                .map(|m| Some(m[gid].advanceWidth as f32))
+                .collect();
+            let mut row = vec![0; supports.len()];
+            for (delta, support) in model.get_deltas_and_supports(&advances) {
+                if let Some(ix) = supports.iter().position(|&s| *s == support) {
+                    row[ix] = ot_round(delta) as int16;
+                }
+            }
+            row
+        })
+        .collect();
+    HVAR {
+        variation_store: ItemVariationStore {
+            format: 1,
+            axisCount: axis_order.len() as uint16,
+            variationRegions: variation_regions,
+            variationData: vec![ItemVariationData {
+                region_indexes: (0..supports.len() as uint16).collect(),
+                delta_values,
+            }],
+        },
+        advance_mapping: None,
+        lsb_mapping: None,
+        rsb_mapping: None,
+    }
+}
+
+/// Builds a variable TrueType font from interpolatable masters.
+///
+/// The default font is the master at the designspace's default location;
+/// it becomes the variable font, gaining `fvar`, `avar`, `gvar`, `HVAR` and
+/// (if it has none) `STAT` tables. The other masters are given with their
+/// locations in designspace coordinates, and must have the same glyphs as
+/// the default, with the same contours and components.
+pub fn build_variable_font(
+    designspace: &Designspace,
+    mut default: Font,
+    masters: &[(&Location, &Font)],
+) -> Result<Font, VarLibError> {
+    let axis_order = designspace.axis_order();
+    let default_location: OTVarLocation = axis_order.iter().map(|&tag| (tag, 0.0)).collect();
+    let mut locations = vec![default_location];
+    for (ix, (location, _)) in masters.iter().enumerate() {
+        let location = designspace.normalized_tag_location(location);
+        if locations.contains(&location) {
+            return Err(VarLibError(format!(
+                "Master {} is at the same location as another master",
+                ix
+            )));
+        }
+        locations.push(location);
+    }
+    let model = VariationModel::new(locations, axis_order.clone());
+
+    let table_error = |e: otspec::DeserializationError| VarLibError(e.to_string());
+    let mut glyfs = vec![];
+    let mut metrics = vec![];
+    for font in std::iter::once(&default).chain(masters.iter().map(|(_, font)| *font)) {
+        glyfs.push(
+            font.tables
+                .glyf()
+                .map_err(table_error)?
+                .ok_or_else(|| VarLibError("Master has no glyf table".to_string()))?,
+        );
+        metrics.push(
+            font.tables
+                .hmtx()
+                .map_err(table_error)?
+                .ok_or_else(|| VarLibError("Master has no hmtx table".to_string()))?
+                .metrics
+                .clone(),
+        );
+    }
+    let glyph_count = glyfs[0].glyphs.len();
+    for (ix, (glyf, metrics)) in glyfs.iter().zip(metrics.iter()).enumerate() {
+        if glyf.glyphs.len() != glyph_count || metrics.len() != glyph_count {
+            return Err(VarLibError(format!(
+                "Master {} has a different number of glyphs to the default",
+                ix
+            )));
+        }
+    }
+    let glyphs: Vec<Vec<&Glyph>> = (0..glyph_count)
+        .map(|gid| glyfs.iter().map(|glyf| &glyf.glyphs[gid]).collect())
+        .collect();
+    for (gid, master_glyphs) in glyphs.iter().enumerate() {
+        for (ix, glyph) in master_glyphs.iter().enumerate().skip(1) {
+            check_glyph_compatibility(gid, ix, master_glyphs[0], glyph)?;
+        }
+    }
+
+    designspace
+        .add_to_font(&mut default)
+        .map_err(|e| VarLibError(e.to_string()))?;
+    let gvar = build_gvar(&model, &axis_order, &glyphs, &metrics);
+    let hvar = build_hvar(&model, &axis_order, &metrics);
+    default.tables.insert(gvar);
+    default.tables.insert(hvar);
+    if !default.tables.contains(b"STAT") {
+        let fvar = default.tables.fvar().map_err(table_error)?.unwrap();
+        default.tables.insert(STAT {
+            elided_fallback_name_id: Some(2),
+            design_axes: fvar
+                .axes
+                .iter()
+                .enumerate()
+                .map(|(ix, axis)| AxisRecord {
+                    axisTag: axis.axisTag,
+                    axisNameID: axis.axisNameID,
+                    axisOrdering: ix as uint16,
+                })
+                .collect(),
+            axis_values: vec![],
+        });
+    }
+    Ok(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_reader;
+    use fonttools::font::SfntVersion;
+    use fonttools::tables::glyf::{glyf, Point};
+    use fonttools::tables::head;
+    use fonttools::tables::hmtx::hmtx;
+    use fonttools::tables::name::name;
+
+    const DESIGNSPACE: &str = r##"
+<designspace format="3">
+    <axes>
+        <axis default="400" maximum="900" minimum="100" name="Weight" tag="wght" />
+    </axes>
+    <sources>
+        <source filename="Test-Regular.ufo">
+            <location><dimension name="Weight" xvalue="400" /></location>
+        </source>
+        <source filename="Test-Black.ufo">
+            <location><dimension name="Weight" xvalue="900" /></location>
+        </source>
+    </sources>
+</designspace>
+"##;
+
+    // A font with a single one-point glyph at the given x coordinate
+    fn master(x: int16, advance_width: uint16) -> Font {
+        let mut font = Font::new(SfntVersion::TrueType);
+        font.tables.insert(glyf {
+            glyphs: vec![Glyph {
+                xMin: x,
+                xMax: x,
+                yMin: 0,
+                yMax: 0,
+                contours: vec![vec![Point {
+                    x,
+                    y: 0,
+                    on_curve: true,
+                }]],
+                instructions: vec![],
+                components: vec![],
+                overlap: false,
+            }],
+        });
+        font.tables.insert(hmtx {
+            metrics: vec![Metric {
+                advanceWidth: advance_width,
+                lsb: x,
+            }],
+        });
+        font.tables.insert(name { records: vec![] });
+        font.tables.insert(head::new(1.0, 1000, 0, 0, 100, 0));
+        font
+    }
+
+    #[test]
+    fn test_build_variable_font() {
+        let designspace: Designspace = from_reader(DESIGNSPACE.as_bytes()).unwrap();
+        let bold = master(100, 550);
+        let location = &designspace.sources.source[1].location;
+        let mut font =
+            build_variable_font(&designspace, master(0, 500), &[(location, &bold)]).unwrap();
+
+        let fvar = font.tables.fvar().unwrap().unwrap();
+        assert_eq!(fvar.axes[0].axisTag, fonttools::tag!("wght"));
+        let gvar = font.tables.gvar().unwrap().unwrap();
+        let deltasets = &gvar.variations[0].as_ref().unwrap().deltasets;
+        assert_eq!(deltasets.len(), 1);
+        assert_eq!(
+            (&deltasets[0].start, &deltasets[0].peak, &deltasets[0].end),
+            (&vec![0.0], &vec![1.0], &vec![1.0])
+        );
+        assert_eq!(
+            deltasets[0].deltas,
+            vec![(100, 0), (0, 0), (50, 0), (0, 0), (0, 0)]
+        );
+        let hvar = font.tables.HVAR().unwrap().unwrap();
+        assert_eq!(
+            hvar.variation_store.variationData[0].delta_values,
+            vec![vec![50]]
+        );
+        let stat = font.tables.STAT().unwrap().unwrap();
+        assert_eq!(stat.design_axes.len(), 1);
+        assert!(font.tables.contains(b"avar"));
+
+        let mut data = vec![];
+        font.write(&mut data).unwrap();
+        let font = Font::from_bytes(&data).unwrap();
+        assert!(font.tables.gvar().unwrap().unwrap().has_variations());
+    }
+
+    #[test]
+    fn test_incompatible_masters() {
+        let designspace: Designspace = from_reader(DESIGNSPACE.as_bytes()).unwrap();
+        let mut bold = master(100, 550);
+        let mut glyf = bold.tables.glyf().unwrap().unwrap();
+        glyf.glyphs[0].contours[0].push(Point {
+            x: 200,
+            y: 0,
+            on_curve: true,
+        });
+        bold.tables.insert(glyf);
+        let location = &designspace.sources.source[1].location;
+        let result = build_variable_font(&designspace, master(0, 500), &[(location, &bold)]);
+        assert!(result.is_err());
+    }
+}
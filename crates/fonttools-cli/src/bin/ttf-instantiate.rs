@@ -1,6 +1,7 @@
 use clap::{App, Arg};
 use fonttools::otvar::instancer::{
-    instantiate_variable_font, AxisRange, UserAxisLimit, UserAxisLimits,
+    instantiate_variable_font_with_options, AxisRange, InstancerError, InstancerOptions,
+    UserAxisLimit, UserAxisLimits,
 };
 use fonttools::tag;
 use fonttools::types::*;
@@ -24,7 +25,7 @@ fn main() {
                 .required(true),
         ).arg(
             Arg::with_name("loc-args")
-                .help("List of space separated locations. A location consists of the tag of a variation axis, followed by '=' and one of number, number:number or the literal string 'drop'. E.g.: wdth=100 or wght=75.0:125.0 or wght=drop")
+                .help("List of space separated locations. A location consists of the tag of a variation axis, followed by '=' and one of number, number:number, number:number:number or the literal string 'drop'. E.g.: wdth=100 or wght=75.0:125.0 or wght=75.0:100.0:125.0 or wght=drop. A range of three numbers also sets a new default")
                  .multiple(true)
                 .required(true),
         )
@@ -39,7 +40,13 @@ fn main() {
 
     let locargs: Vec<&str> = matches.values_of("loc-args").unwrap().collect();
     let locarg_len = locargs.len();
-    let limits = parse_locargs(locargs);
+    let limits = match parse_locargs(locargs) {
+        Ok(limits) => limits,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
     if limits.len() != locarg_len {
         println!("Multiple limits for same axis");
         return;
@@ -52,29 +59,35 @@ fn main() {
     }
 
     log::debug!("Axis limits = {:?}", limits);
-    if instantiate_variable_font(&mut infont, limits) {
-        if let Some(out_fn) = matches.value_of("output") {
-            log::info!("Saving on {}", out_fn);
-            infont.save(out_fn)
-        } else {
-            let input_filename = matches.value_of("INPUT").unwrap();
-            let out_fn = Path::new(input_filename)
-                .with_extension("")
-                .with_extension("partial.ttf");
-            log::info!("Saving on {}", out_fn.to_str().unwrap());
-            infont.save(out_fn)
-        }
-        .unwrap();
+    let options = InstancerOptions {
+        update_name_table: matches.is_present("update-name-table"),
+    };
+    if let Err(e) = instantiate_variable_font_with_options(&mut infont, limits, &options) {
+        println!("Could not instantiate font: {}", e);
+        return;
+    }
+    if let Some(out_fn) = matches.value_of("output") {
+        log::info!("Saving on {}", out_fn);
+        infont.save(out_fn)
+    } else {
+        let input_filename = matches.value_of("INPUT").unwrap();
+        let out_fn = Path::new(input_filename)
+            .with_extension("")
+            .with_extension("partial.ttf");
+        log::info!("Saving on {}", out_fn.to_str().unwrap());
+        infont.save(out_fn)
     }
+    .unwrap();
 }
 
 fn str_to_fixed_to_float(s: &str) -> f32 {
     Fixed::round(str::parse::<f32>(s).unwrap())
 }
 
-fn parse_locargs(locargs: Vec<&str>) -> UserAxisLimits {
+fn parse_locargs(locargs: Vec<&str>) -> Result<UserAxisLimits, InstancerError> {
     let mut res = BTreeMap::new();
-    let matcher = Regex::new(r"^(\w{1,4})=(?:(drop)|(?:([^:]+)(?:[:](.+))?))$").unwrap();
+    let matcher =
+        Regex::new(r"^(\w{1,4})=(?:(drop)|(?:([^:]+)(?:[:]([^:]+))?(?:[:]([^:]+))?))$").unwrap();
     for limit_string in locargs {
         let captures = matcher
             .captures(limit_string)
@@ -89,10 +102,21 @@ fn parse_locargs(locargs: Vec<&str>) -> UserAxisLimits {
         if let Some(ustr) = captures.get(4) {
             upper = Some(str_to_fixed_to_float(ustr.as_str()));
         }
-        if upper != lower {
+        if let Some(ustr) = captures.get(5) {
+            let default = upper.unwrap();
+            upper = Some(str_to_fixed_to_float(ustr.as_str()));
+            res.insert(
+                btag,
+                UserAxisLimit::Partial(AxisRange::with_default(
+                    lower.unwrap(),
+                    default,
+                    upper.unwrap(),
+                )?),
+            );
+        } else if upper != lower {
             res.insert(
                 btag,
-                UserAxisLimit::Partial(AxisRange::new(lower.unwrap(), upper.unwrap())),
+                UserAxisLimit::Partial(AxisRange::new(lower.unwrap(), upper.unwrap())?),
             );
         } else if let Some(l) = lower {
             res.insert(btag, UserAxisLimit::Full(l));
@@ -100,5 +124,5 @@ fn parse_locargs(locargs: Vec<&str>) -> UserAxisLimits {
             res.insert(btag, UserAxisLimit::Drop);
         }
     }
-    UserAxisLimits(res)
+    Ok(UserAxisLimits(res))
 }
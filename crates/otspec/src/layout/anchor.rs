@@ -1,3 +1,4 @@
+use crate::layout::device::Device;
 use crate::types::*;
 use crate::{
     DeserializationError, Deserialize, Deserializer, ReaderContext, SerializationError, Serialize,
@@ -6,14 +7,16 @@ use crate::{
 
 // These things have to be serialized/deserialized by hand because of annoying
 // format switching things.
-#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[derive(Debug, Clone, PartialEq, Default)]
 #[allow(missing_docs, non_snake_case, non_camel_case_types)]
 pub struct Anchor {
     pub xCoordinate: int16,
     pub yCoordinate: int16,
     pub anchorPoint: Option<uint16>,
-    // xDeviceOffset: Option<Offset16<Device>>,
-    // yDeviceOffset: Option<Offset16<Device>>,
+    // Offsets from the start of the anchor table. An anchor with either of
+    // these is written as format 3.
+    pub xDevice: Option<Offset16<Device>>,
+    pub yDevice: Option<Offset16<Device>>,
 }
 
 impl Anchor {
@@ -22,47 +25,110 @@ impl Anchor {
         Anchor {
             xCoordinate: x,
             yCoordinate: y,
-            anchorPoint: None,
+            ..Default::default()
         }
     }
+
+    /// Returns true if the anchor has device or variation index tables
+    pub fn has_devices(&self) -> bool {
+        self.xDevice.is_some() || self.yDevice.is_some()
+    }
 }
+
 impl Deserialize for Anchor {
     #[allow(non_snake_case)]
     fn from_bytes(c: &mut ReaderContext) -> Result<Self, DeserializationError> {
+        c.push();
         let format: uint16 = c.de()?;
         let xCoordinate: int16 = c.de()?;
         let yCoordinate: int16 = c.de()?;
-        if format == 1 {
-            Ok(Anchor {
-                xCoordinate,
-                yCoordinate,
-                anchorPoint: None,
-            })
-        } else if format == 2 {
-            let anchorPoint: uint16 = c.de()?;
-            Ok(Anchor {
-                xCoordinate,
-                yCoordinate,
-                anchorPoint: Some(anchorPoint),
-            })
-        } else {
-            Err(DeserializationError(format!(
-                "Invalid anchor format {:}",
-                format
-            )))
+        let mut anchor = Anchor::new(xCoordinate, yCoordinate);
+        match format {
+            1 => {}
+            2 => {
+                anchor.anchorPoint = Some(c.de()?);
+            }
+            3 => {
+                let xDevice: Offset16<Device> = c.de()?;
+                let yDevice: Offset16<Device> = c.de()?;
+                anchor.xDevice = xDevice.link.is_some().then_some(xDevice);
+                anchor.yDevice = yDevice.link.is_some().then_some(yDevice);
+            }
+            _ => {
+                c.pop();
+                return Err(DeserializationError(format!(
+                    "Invalid anchor format {:}",
+                    format
+                )));
+            }
         }
+        c.pop();
+        Ok(anchor)
     }
 }
 
 impl Serialize for Anchor {
     fn to_bytes(&self, data: &mut Vec<u8>) -> Result<(), SerializationError> {
-        let format: uint16 = if self.anchorPoint.is_some() { 2 } else { 1 };
+        let format: uint16 = if self.has_devices() {
+            3
+        } else if self.anchorPoint.is_some() {
+            2
+        } else {
+            1
+        };
         data.put(format)?;
         data.put(self.xCoordinate)?;
         data.put(self.yCoordinate)?;
-        if let Some(anchor) = self.anchorPoint {
+        if format == 3 {
+            for device in [&self.xDevice, &self.yDevice] {
+                match device {
+                    Some(device) => device.to_bytes(data)?,
+                    None => data.put(0_u16)?,
+                }
+            }
+        } else if let Some(anchor) = self.anchorPoint {
             data.put(anchor)?;
         }
         Ok(())
     }
+
+    fn ot_binary_size(&self) -> usize {
+        if self.has_devices() || self.anchorPoint.is_some() {
+            10
+        } else {
+            6
+        }
+    }
+
+    fn offset_fields(&self) -> Vec<&dyn OffsetMarkerTrait> {
+        let mut fields: Vec<&dyn OffsetMarkerTrait> = vec![];
+        if let Some(device) = &self.xDevice {
+            fields.push(device);
+        }
+        if let Some(device) = &self.yDevice {
+            fields.push(device);
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::offsetmanager::resolve_offsets_and_serialize;
+
+    #[test]
+    fn anchor_format3_serde() {
+        let binary_anchor = vec![
+            0x00, 0x03, 0x01, 0x2c, 0x02, 0x44, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+            0x80, 0x00,
+        ];
+        let deserialized: Anchor = otspec::de::from_bytes(&binary_anchor).unwrap();
+        let mut expected = Anchor::new(300, 580);
+        expected.xDevice = Some(Offset16::to(Device::variation_index(0, 1)));
+        assert_eq!(deserialized, expected);
+        let mut serialized = vec![];
+        resolve_offsets_and_serialize(deserialized, &mut serialized, true).unwrap();
+        assert_eq!(serialized, binary_anchor);
+    }
 }
@@ -40,7 +40,8 @@ impl ClassDef {
     pub fn get_glyphs(&self, class_id: uint16, max_glyph_id: uint16) -> BTreeSet<GlyphID> {
         if class_id == 0 {
             let mut glyphs: BTreeSet<GlyphID> = (0..=max_glyph_id).collect();
-            glyphs.retain(|g| !self.classes.contains_key(g));
+            // Format 1 tables can list glyphs in class 0 explicitly
+            glyphs.retain(|g| self.classes.get(g).is_none_or(|&c| c == 0));
             return glyphs;
         }
         // "Doing linear scans over an associative array is like trying to
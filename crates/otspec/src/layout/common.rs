@@ -2,7 +2,7 @@ use crate::{Serialize, Serializer, Tag};
 use bitflags::bitflags;
 use otspec::layout::anchor::Anchor;
 use otspec::types::*;
-use otspec::Deserializer;
+use otspec::{DeserializationError, Deserializer, ReaderContext, SerializationError};
 use otspec_macros::{tables, Deserialize, Serialize};
 use std::fmt::Debug;
 
@@ -36,12 +36,13 @@ tables!(
         [embed]
         Counted(FeatureRecord) featureRecords
     }
-    FeatureRecord [embedded] {
+    FeatureRecord [embedded] [nodeserialize] {
             Tag	featureTag
             Offset16(FeatureTable)	feature
     }
-    FeatureTable {
-            uint16	featureParamsOffset
+    FeatureTable [nodeserialize] {
+            [offset_base]
+            Offset16(FeatureParams)	featureParams
             Counted(uint16) lookupListIndices
     }
     cvFeatureParams {
@@ -51,8 +52,7 @@ tables!(
         uint16  sampleTextNameId
         uint16  numNamedParameters
         uint16  firstParamUiLabelNameId
-        // everything is horrible
-        // Counted(uint24) character
+        Counted(uint24) characters
     }
     sizeFeatureParams {
         uint16 designSize
@@ -73,15 +73,18 @@ tables!(
         Offset16(Anchor) markAnchor
     }
     FeatureVariations {
+        [offset_base]
         uint16 majorVersion
         uint16 minorVersion
+        [embed]
         Counted32(FeatureVariationRecord) featureVariationRecords
     }
-    FeatureVariationRecord {
+    FeatureVariationRecord [embedded] {
         Offset32(ConditionSet) conditionSet
         Offset32(FeatureTableSubstitution) featureTableSubstitution
     }
     ConditionSet {
+        [offset_base]
         CountedOffset32(ConditionFormat1) conditions
     }
     ConditionFormat1 {
@@ -91,11 +94,13 @@ tables!(
         F2DOT14 filterRangeMaxValue
     }
     FeatureTableSubstitution {
+        [offset_base]
         uint16 majorVersion
         uint16 minorVersion
+        [embed]
         Counted(FeatureTableSubstitutionRecord) substitutions
     }
-    FeatureTableSubstitutionRecord {
+    FeatureTableSubstitutionRecord [embedded] {
         uint16  featureIndex
         Offset32(FeatureTable) alternateFeature
     }
@@ -148,6 +153,103 @@ pub enum FeatureParams {
     CharacterVariant(cvFeatureParams),
 }
 
+impl FeatureParams {
+    /// Reads the feature parameters of the feature with the given tag.
+    ///
+    /// The layout of feature parameters depends on the feature, so parameters
+    /// of features other than `size`, `ssXX` and `cvXX` are skipped.
+    pub fn from_bytes_with_tag(
+        tag: Tag,
+        c: &mut ReaderContext,
+    ) -> Result<Option<Self>, DeserializationError> {
+        if tag == "size" {
+            Ok(Some(FeatureParams::SizeFeature(c.de()?)))
+        } else if is_numbered_feature(tag, "ss") {
+            Ok(Some(FeatureParams::StylisticSet(c.de()?, c.de()?)))
+        } else if is_numbered_feature(tag, "cv") {
+            Ok(Some(FeatureParams::CharacterVariant(c.de()?)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Whether a feature tag is the given two-letter prefix followed by two
+/// digits, like `ss01` or `cv99`. Features such as `ssty` don't match.
+pub fn is_numbered_feature(tag: Tag, prefix: &str) -> bool {
+    tag.starts_with(prefix) && tag.as_bytes()[2..].iter().all(u8::is_ascii_digit)
+}
+
+impl Serialize for FeatureParams {
+    fn to_bytes(&self, data: &mut Vec<u8>) -> Result<(), SerializationError> {
+        match self {
+            FeatureParams::StylisticSet(version, name_id) => {
+                data.put(version)?;
+                data.put(name_id)
+            }
+            FeatureParams::SizeFeature(params) => data.put(params),
+            FeatureParams::CharacterVariant(params) => data.put(params),
+        }
+    }
+}
+
+impl FeatureTable {
+    fn from_bytes_with_tag(
+        tag: Option<Tag>,
+        c: &mut ReaderContext,
+    ) -> Result<Self, DeserializationError> {
+        c.push();
+        let params_offset: uint16 = c.de()?;
+        let count: uint16 = c.de()?;
+        let lookup_list_indices: Vec<uint16> = c.de_counted(count as usize)?;
+        let params = match tag {
+            Some(tag) if params_offset != 0 => {
+                c.follow_offset::<FeatureParams>(params_offset)?;
+                FeatureParams::from_bytes_with_tag(tag, c)?
+            }
+            _ => None,
+        };
+        c.pop();
+        Ok(FeatureTable {
+            featureParams: match params {
+                Some(params) => Offset16::new(params_offset, params),
+                None => Offset16::to_nothing(),
+            },
+            lookupListIndices: lookup_list_indices,
+        })
+    }
+}
+
+// Without the feature tag, the feature parameters can't be interpreted, so
+// they are only read through the feature record. Alternate feature tables in
+// feature variations are never expected to have parameters of their own.
+impl crate::Deserialize for FeatureTable {
+    fn from_bytes(c: &mut ReaderContext) -> Result<Self, DeserializationError> {
+        FeatureTable::from_bytes_with_tag(None, c)
+    }
+}
+
+impl crate::Deserialize for FeatureRecord {
+    fn from_bytes(c: &mut ReaderContext) -> Result<Self, DeserializationError> {
+        let tag: Tag = c.de()?;
+        let offset: uint16 = c.de()?;
+        if offset == 0 {
+            return Ok(FeatureRecord {
+                featureTag: tag,
+                feature: Offset16::to_nothing(),
+            });
+        }
+        let oldptr = c.ptr;
+        c.follow_offset::<FeatureTable>(offset)?;
+        let table = FeatureTable::from_bytes_with_tag(Some(tag), c)?;
+        c.ptr = oldptr;
+        Ok(FeatureRecord {
+            featureTag: tag,
+            feature: Offset16::new(offset, table),
+        })
+    }
+}
+
 bitflags! {
     /// Lookup qualifiers
     #[derive(Serialize, Deserialize)]
@@ -92,10 +92,11 @@ impl Deserialize for SequenceRule {
 #[allow(missing_docs, non_snake_case)]
 #[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct SequenceContextFormat3 {
+    #[otspec(offset_base)]
     pub format: uint16,
     pub glyphCount: uint16,
     pub seqLookupCount: uint16,
-    pub coverages: Vec<Offset16<Coverage>>,
+    pub coverages: VecOffset16<Coverage>,
     pub seqLookupRecords: Vec<SequenceLookupRecord>,
 }
 
@@ -113,7 +114,7 @@ impl Deserialize for SequenceContextFormat3 {
             format,
             glyphCount,
             seqLookupCount,
-            coverages,
+            coverages: coverages.into(),
             seqLookupRecords,
         })
     }
@@ -298,7 +299,7 @@ mod tests {
                     featureRecords: vec![FeatureRecord {
                         featureTag: Tag::from_raw("gp71").unwrap(),
                         feature: Offset16::to(FeatureTable {
-                            featureParamsOffset: 0,
+                            featureParams: Offset16::to_nothing(),
                             lookupListIndices: vec![0]
                         })
                     }],
@@ -22,7 +22,7 @@ impl Deserialize for Device {
         let endSize: uint16 = c.de()?;
         let format: uint16 = c.de()?;
         let mut values: Vec<i8> = vec![];
-        if format != 0x8000 {
+        if format != VARIATION_INDEX_FORMAT {
             let mut count = endSize - startSize + 1;
             let num_bits = 1 << format;
             let minus_offset: i16 = 1 << num_bits;
@@ -53,7 +53,30 @@ impl Deserialize for Device {
     }
 }
 
+/// The delta format marking a VariationIndex table
+pub const VARIATION_INDEX_FORMAT: uint16 = 0x8000;
+
 impl Device {
+    /// Creates a VariationIndex table, referring to an item of the `GDEF`
+    /// item variation store by its outer and inner index.
+    ///
+    /// A VariationIndex table shares the layout of a device table, with
+    /// the outer and inner index in place of the start and end size.
+    pub fn variation_index(outer: uint16, inner: uint16) -> Device {
+        Device {
+            startSize: outer,
+            endSize: inner,
+            deltaFormat: Some(VARIATION_INDEX_FORMAT),
+            deltaValues: vec![],
+        }
+    }
+
+    /// Returns the outer and inner index of a VariationIndex table, or
+    /// `None` if this is a device table.
+    pub fn as_variation_index(&self) -> Option<(uint16, uint16)> {
+        (self.deltaFormat == Some(VARIATION_INDEX_FORMAT)).then_some((self.startSize, self.endSize))
+    }
+
     fn suggest_format(&self) -> uint16 {
         for &val in &self.deltaValues {
             if val < -9 || val > 8 {
@@ -72,6 +95,9 @@ impl Serialize for Device {
         data.put(self.endSize)?;
         let format = self.deltaFormat.unwrap_or_else(|| self.suggest_format());
         data.put(format)?;
+        if format == VARIATION_INDEX_FORMAT {
+            return Ok(());
+        }
         // Horrible bit-packing time
         let num_bits = 1 << format;
         let mask: i16 = (1 << num_bits) - 1;
@@ -121,4 +147,13 @@ mod tests {
         let binary_device = vec![0x00, 0x0b, 0x00, 0x0f, 0x00, 0x01, 0xf5, 0x40];
         assert_eq!(otspec::ser::to_bytes(&device).unwrap(), binary_device);
     }
+
+    #[test]
+    fn variation_index_serde() {
+        let binary_device = vec![0x00, 0x01, 0x00, 0x02, 0x80, 0x00];
+        let deserialized: Device = otspec::de::from_bytes(&binary_device).unwrap();
+        assert_eq!(deserialized, Device::variation_index(1, 2));
+        assert_eq!(deserialized.as_variation_index(), Some((1, 2)));
+        assert_eq!(otspec::ser::to_bytes(&deserialized).unwrap(), binary_device);
+    }
 }
@@ -23,6 +23,7 @@ pub struct SinglePosFormat2 {
     pub posFormat: uint16,
     pub coverage: Offset16<Coverage>,
     pub valueFormat: ValueRecordFlags,
+    #[otspec(embed)]
     #[otspec(with = "Counted")]
     pub valueRecords: Vec<ValueRecord>,
 }
@@ -131,7 +132,7 @@ mod tests {
                     featureRecords: vec![FeatureRecord {
                         featureTag: Tag::from_raw("kern").unwrap(),
                         feature: Offset16::to(FeatureTable {
-                            featureParamsOffset: 0,
+                            featureParams: Offset16::to_nothing(),
                             lookupListIndices: vec![0]
                         })
                     }],
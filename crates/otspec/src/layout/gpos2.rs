@@ -22,12 +22,14 @@ pub struct PairPosFormat1 {
 #[allow(missing_docs, non_snake_case, non_camel_case_types)]
 pub struct PairSet {
     #[otspec(offset_base)]
+    #[otspec(embed)]
     #[otspec(with = "Counted")]
     pub pairValueRecords: Vec<PairValueRecord>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize)]
 #[allow(missing_docs, non_snake_case, non_camel_case_types)]
+#[otspec(embedded)]
 pub struct PairValueRecord {
     pub secondGlyph: GlyphID,
     #[otspec(embed)]
@@ -48,17 +50,21 @@ pub struct PairPosFormat2 {
     pub classDef2: Offset16<ClassDef>,
     pub classCount1: uint16,
     pub classCount2: uint16,
+    #[otspec(embed)]
     pub class1Records: Vec<Class1Record>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize)]
 #[allow(missing_docs, non_snake_case, non_camel_case_types)]
+#[otspec(embedded)]
 pub struct Class1Record {
+    #[otspec(embed)]
     pub class2Records: Vec<Class2Record>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize)]
 #[allow(missing_docs, non_snake_case, non_camel_case_types)]
+#[otspec(embedded)]
 pub struct Class2Record {
     #[otspec(embed)]
     pub valueRecord1: ValueRecord,
@@ -84,6 +90,8 @@ pub(crate) fn deserialize_gpos2(
                 coverage.as_ref().unwrap().glyphs.iter().zip(offsets.iter())
             {
                 c.ptr = c.top_of_table() + offset as usize;
+                // Device offsets in the value records are from the pair set
+                c.push();
                 let pair_vr_count: uint16 = c.de()?;
                 let mut pair_value_records = vec![];
                 for _ in 0..pair_vr_count {
@@ -96,6 +104,7 @@ pub(crate) fn deserialize_gpos2(
                         valueRecord2: vr2,
                     })
                 }
+                c.pop();
                 pair_sets.push(Offset16::new(
                     offset,
                     PairSet {
@@ -329,14 +338,14 @@ mod tests {
                     FeatureRecord {
                         featureTag: Tag::from_raw("gp21").unwrap(),
                         feature: Offset16::to(FeatureTable {
-                            featureParamsOffset: 0,
+                            featureParams: Offset16::to_nothing(),
                             lookupListIndices: vec![0],
                         }),
                     },
                     FeatureRecord {
                         featureTag: Tag::from_raw("gp22").unwrap(),
                         feature: Offset16::to(FeatureTable {
-                            featureParamsOffset: 0,
+                            featureParams: Offset16::to_nothing(),
                             lookupListIndices: vec![1],
                         }),
                     },
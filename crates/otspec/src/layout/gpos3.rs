@@ -39,11 +39,7 @@ mod tests {
             }),
             entryExitRecord: vec![
                 EntryExitRecord {
-                    entryAnchor: Offset16::to(Anchor {
-                        xCoordinate: 100,
-                        yCoordinate: 200,
-                        anchorPoint: None,
-                    }),
+                    entryAnchor: Offset16::to(Anchor::new(100, 200)),
                     exitAnchor: Offset16::to_nothing(),
                 },
                 EntryExitRecord {
@@ -52,23 +48,11 @@ mod tests {
                 },
                 EntryExitRecord {
                     entryAnchor: Offset16::to_nothing(),
-                    exitAnchor: Offset16::to(Anchor {
-                        xCoordinate: -300,
-                        yCoordinate: -400,
-                        anchorPoint: None,
-                    }),
+                    exitAnchor: Offset16::to(Anchor::new(-300, -400)),
                 },
                 EntryExitRecord {
-                    entryAnchor: Offset16::to(Anchor {
-                        xCoordinate: 1,
-                        yCoordinate: 2,
-                        anchorPoint: None,
-                    }),
-                    exitAnchor: Offset16::to(Anchor {
-                        xCoordinate: 3,
-                        yCoordinate: 4,
-                        anchorPoint: None,
-                    }),
+                    entryAnchor: Offset16::to(Anchor::new(1, 2)),
+                    exitAnchor: Offset16::to(Anchor::new(3, 4)),
                 },
             ],
         };
@@ -172,35 +172,19 @@ mod tests {
                 markRecords: vec![
                     MarkRecord {
                         markClass: 0,
-                        markAnchor: Offset16::to(Anchor {
-                            xCoordinate: 346,
-                            yCoordinate: -98,
-                            anchorPoint: None,
-                        }),
+                        markAnchor: Offset16::to(Anchor::new(346, -98)),
                     },
                     MarkRecord {
                         markClass: 1,
-                        markAnchor: Offset16::to(Anchor {
-                            xCoordinate: 261,
-                            yCoordinate: 88,
-                            anchorPoint: None,
-                        }),
+                        markAnchor: Offset16::to(Anchor::new(261, 88)),
                     },
                 ],
             }),
             baseArray: Offset16::to(BaseArray {
                 baseRecords: vec![BaseRecord {
                     baseAnchors: vec![
-                        Offset16::to(Anchor {
-                            xCoordinate: 830,
-                            yCoordinate: 1600,
-                            anchorPoint: None,
-                        }),
-                        Offset16::to(Anchor {
-                            xCoordinate: 830,
-                            yCoordinate: -83,
-                            anchorPoint: None,
-                        }),
+                        Offset16::to(Anchor::new(830, 1600)),
+                        Offset16::to(Anchor::new(830, -83)),
                     ],
                 }],
             }),
@@ -215,19 +215,11 @@ mod tests {
                 markRecords: vec![
                     MarkRecord {
                         markClass: 0,
-                        markAnchor: Offset16::to(Anchor {
-                            xCoordinate: 346,
-                            yCoordinate: -98,
-                            anchorPoint: None,
-                        }),
+                        markAnchor: Offset16::to(Anchor::new(346, -98)),
                     },
                     MarkRecord {
                         markClass: 1,
-                        markAnchor: Offset16::to(Anchor {
-                            xCoordinate: 261,
-                            yCoordinate: 488,
-                            anchorPoint: None,
-                        }),
+                        markAnchor: Offset16::to(Anchor::new(261, 488)),
                     },
                 ],
             }),
@@ -236,22 +228,14 @@ mod tests {
                     componentRecords: vec![
                         ComponentRecord {
                             ligatureAnchors: vec![
-                                Offset16::to(Anchor {
-                                    xCoordinate: 625,
-                                    yCoordinate: 1800,
-                                    anchorPoint: None,
-                                }),
+                                Offset16::to(Anchor::new(625, 1800)),
                                 Offset16::to_nothing(),
                             ],
                         },
                         ComponentRecord {
                             ligatureAnchors: vec![
                                 Offset16::to_nothing(),
-                                Offset16::to(Anchor {
-                                    xCoordinate: 376,
-                                    yCoordinate: -368,
-                                    anchorPoint: None,
-                                }),
+                                Offset16::to(Anchor::new(376, -368)),
                             ],
                         },
                         ComponentRecord {
@@ -90,7 +90,7 @@ impl ValueRecord {
         if self.xPlaDevice.is_some() {
             f |= ValueRecordFlags::X_PLACEMENT_DEVICE
         }
-        if self.xPlaDevice.is_some() {
+        if self.yPlaDevice.is_some() {
             f |= ValueRecordFlags::Y_PLACEMENT_DEVICE
         }
         if self.xAdvDevice.is_some() {
@@ -136,8 +136,11 @@ impl ValueRecord {
         Ok(vr)
     }
 
-    // Only goes "up", never "down"!
-    fn coerce_to_format(&mut self, flags: ValueRecordFlags) {
+    /// Adds zero values for any fields in the given format which this record
+    /// does not have, so that it can be serialized with that format.
+    ///
+    /// Only goes "up", never "down"!
+    pub fn coerce_to_format(&mut self, flags: ValueRecordFlags) {
         if flags.contains(ValueRecordFlags::X_PLACEMENT) && self.xPlacement.is_none() {
             self.xPlacement = Some(0);
         }
@@ -2,11 +2,45 @@ use crate::offsets::{Offset16, OffsetMarkerTrait};
 use crate::{SerializationError, Serialize};
 
 use petgraph::dot::Dot;
-use petgraph::graph::{Graph, NodeIndex};
-use petgraph::visit::{EdgeRef, Topo};
+use petgraph::graph::{EdgeIndex, Graph, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction::{Incoming, Outgoing};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// The order in which the objects of a graph are laid out.
+///
+/// Offsets are relative to the object containing them, so the order decides
+/// how far each object is from the objects pointing to it, and so whether
+/// its offsets fit into 16 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Packing {
+    /// Each object is followed by all of its descendants in turn.
+    #[default]
+    DepthFirst,
+    /// As `DepthFirst`, but objects reached through 32-bit offsets are laid
+    /// out after everything reachable through 16-bit offsets. This keeps
+    /// extension subtables out of the way of the lookups pointing to them.
+    LongOffsetsLast,
+    /// Each object's children are laid out before any of its grandchildren,
+    /// and objects reached through 32-bit offsets after everything else.
+    BreadthFirst,
+}
+
+// The edges of both graphs are labelled with the node of the tree whose
+// offset field they stand for.
+type ObjectGraph<'a> = Graph<&'a dyn OffsetMarkerTrait, NodeIndex>;
 
 pub struct OffsetManager<'a> {
-    dag: Graph<&'a dyn OffsetMarkerTrait, Option<usize>>,
+    // One node for each offset in the object tree
+    dag: ObjectGraph<'a>,
+    // One node for each distinct subgraph; identical subgraphs are shared
+    objects: ObjectGraph<'a>,
+    packing: Packing,
+    order: Vec<NodeIndex>,
+    overflows: usize,
+    // The offset fields which overflowed, or which point to an object below
+    // which an offset overflowed
+    overflowed_fields: HashSet<usize>,
     resolved: bool,
 }
 
@@ -17,6 +51,11 @@ impl<'a> OffsetManager<'a> {
     {
         let mut mgr = OffsetManager {
             dag: Graph::new(),
+            objects: Graph::new(),
+            packing: Packing::default(),
+            order: vec![],
+            overflows: 0,
+            overflowed_fields: HashSet::new(),
             resolved: false,
         };
         mgr.add_object_graph(obj);
@@ -29,7 +68,7 @@ impl<'a> OffsetManager<'a> {
         }
         let node = self.dag.add_node(obj);
         for child in children {
-            self.dag.add_edge(node, child, None);
+            self.dag.add_edge(node, child, child);
         }
         node
     }
@@ -37,79 +76,239 @@ impl<'a> OffsetManager<'a> {
         println!("{:#?}", Dot::new(&self.dag));
     }
 
-    pub fn resolve(&mut self) {
-        // First pass over the graph works out where everything's going to go.
-        let mut topo = Topo::new(&self.dag);
-        let mut node = topo.next(&self.dag);
-        let mut offset_counter = 0;
+    /// Sets the order in which objects are laid out. Must be called before
+    /// `resolve`.
+    pub fn set_packing(&mut self, packing: Packing) {
+        self.packing = packing;
+        self.resolved = false;
+    }
+
+    /// The number of offsets which were too large for their type when the
+    /// graph was last resolved. These offsets are left unset.
+    pub fn overflows(&self) -> usize {
+        self.overflows
+    }
+
+    /// Whether the given offset, or any offset in the subgraph it points to,
+    /// overflowed when the graph was last resolved.
+    ///
+    /// Unlike checking whether the offset still needs resolving, this is
+    /// accurate for offsets within identical subgraphs which were shared:
+    /// only one copy of a shared subgraph has its offsets set.
+    pub fn overflowed(&self, field: &dyn OffsetMarkerTrait) -> bool {
+        self.overflowed_fields.contains(&field.field_id())
+    }
+
+    /// The number of objects which will be serialized, once identical
+    /// subgraphs have been shared.
+    pub fn object_count(&self) -> usize {
+        self.objects.node_count()
+    }
+
+    // The children of a node, in the order of their fields, with the tree
+    // node of the offset to each
+    fn children(graph: &ObjectGraph<'a>, node: NodeIndex) -> Vec<(NodeIndex, NodeIndex)> {
+        let mut children: Vec<(NodeIndex, NodeIndex)> = graph
+            .edges_directed(node, Outgoing)
+            .map(|edge| (edge.target(), *edge.weight()))
+            .collect();
+        children.reverse();
+        children
+    }
 
-        while node.is_some() {
-            let this_offset = self.dag.node_weight(node.unwrap()).unwrap();
-            // Set the edge to this
-            let size = this_offset.object_size();
-            if offset_counter > 0 {
-                let parent = self
-                    .dag
-                    .edges_directed(node.unwrap(), petgraph::Direction::Incoming)
-                    .next()
-                    .unwrap()
-                    .source();
-                self.dag
-                    .update_edge(parent, node.unwrap(), Some(offset_counter));
+    // Orders the nodes so that every object comes after all of the objects
+    // pointing to it.
+    fn layout_order(&self, graph: &ObjectGraph<'a>, packing: Packing) -> Vec<NodeIndex> {
+        let mut waiting: Vec<usize> = graph
+            .node_indices()
+            .map(|node| graph.edges_directed(node, Incoming).count())
+            .collect();
+        let mut order = vec![];
+        let mut deferred: VecDeque<NodeIndex> = graph.externals(Incoming).collect();
+        while let Some(root) = deferred.pop_front() {
+            let mut pending = VecDeque::from(vec![root]);
+            while let Some(node) = pending.pop_front() {
+                order.push(node);
+                let mut ready = vec![];
+                for (child, offset) in Self::children(graph, node) {
+                    waiting[child.index()] -= 1;
+                    if waiting[child.index()] > 0 {
+                        continue;
+                    }
+                    if packing != Packing::DepthFirst && self.dag[offset].offset_size() > 2 {
+                        deferred.push_back(child);
+                    } else {
+                        ready.push(child);
+                    }
+                }
+                if packing == Packing::BreadthFirst {
+                    pending.extend(ready);
+                } else {
+                    for child in ready.into_iter().rev() {
+                        pending.push_front(child);
+                    }
+                }
             }
-            offset_counter += size; // Pad to multiple of 4 or whatever
-            node = topo.next(&self.dag);
         }
+        order
+    }
 
-        // Second pass over the graph works out the offsets, resetting to zero
-        // at the top of each subtable.
-        let mut topo = Topo::new(&self.dag);
-        let mut node = topo.next(&self.dag);
-        let mut base = 0;
-        while node.is_some() {
-            let children_edges = self
-                .dag
-                .edges_directed(node.unwrap(), petgraph::Direction::Outgoing);
-            let mut parent_edges = self
-                .dag
-                .edges_directed(node.unwrap(), petgraph::Direction::Incoming);
-            if let Some(p) = parent_edges.next() {
-                base = p.weight().unwrap();
+    // Sets the offsets for the given layout, returning the edges whose
+    // offsets overflowed.
+    fn assign_offsets(&self, graph: &ObjectGraph<'a>, order: &[NodeIndex]) -> Vec<EdgeIndex> {
+        let mut positions = vec![0; graph.node_count()];
+        let mut offset_counter = 0;
+        for &node in order {
+            positions[node.index()] = offset_counter;
+            offset_counter += graph[node].object_size(); // Pad to multiple of 4 or whatever
+        }
+        let mut overflows = vec![];
+        for edge in graph.edge_references() {
+            let offset_field = self.dag[*edge.weight()];
+            if offset_field.is_explicitly_zero() {
+                continue;
+            }
+            let offset = positions[edge.target().index()] - positions[edge.source().index()];
+            if offset >= 1 << (8 * offset_field.offset_size()) {
+                overflows.push(edge.id());
             }
+            offset_field.set(offset as u32);
+        }
+        overflows
+    }
 
-            for c in children_edges {
-                let offset = c.weight().unwrap() - base;
-                let target_id = c.target();
-                let target_node = self.dag.node_weight(target_id).unwrap();
-                if !target_node.is_explicitly_zero() {
-                    target_node.set(offset as u32);
-                }
+    // Identifies each subgraph of the tree by its contents, so that identical
+    // subgraphs get the same key.
+    fn subgraph_keys(&self) -> Vec<usize> {
+        // Laid out depth first, an object's offsets only depend on the
+        // subgraph below it.
+        let order = self.layout_order(&self.dag, Packing::DepthFirst);
+        self.assign_offsets(&self.dag, &order);
+        let mut ids: HashMap<(Vec<u8>, Vec<usize>), usize> = HashMap::new();
+        let mut keys = vec![0; self.dag.node_count()];
+        for (unique, &node) in order.iter().rev().enumerate() {
+            let mut contents = vec![];
+            // The root is never shared, and may not know how to serialize
+            // itself shallowly.
+            let is_root = self.dag.edges_directed(node, Incoming).next().is_none();
+            keys[node.index()] =
+                if !is_root && self.dag[node].serialize_contents(&mut contents).is_ok() {
+                    let children = Self::children(&self.dag, node)
+                        .iter()
+                        .map(|(child, _)| keys[child.index()])
+                        .collect();
+                    let next_id = ids.len();
+                    *ids.entry((contents, children)).or_insert(next_id)
+                } else {
+                    // Never shared
+                    usize::MAX - unique
+                };
+        }
+        keys
+    }
+
+    // Builds the graph of objects to be serialized, sharing identical
+    // subgraphs except where they are reached through the given tree nodes.
+    fn build_objects(&mut self, keys: &[usize], unshared: &HashSet<NodeIndex>) {
+        let mut canonical: HashMap<usize, NodeIndex> = HashMap::new();
+        for node in self.dag.node_indices() {
+            if !unshared.contains(&node) {
+                canonical.entry(keys[node.index()]).or_insert(node);
             }
-            node = topo.next(&self.dag);
+        }
+        let mut objects: ObjectGraph<'a> = Graph::new();
+        let mut object_of: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+        let mut stack = vec![];
+        for root in self.dag.externals(Incoming) {
+            let object = objects.add_node(self.dag[root]);
+            object_of.insert(root, object);
+            stack.push(root);
+        }
+        while let Some(node) = stack.pop() {
+            let object = object_of[&node];
+            for (child, offset) in Self::children(&self.dag, node) {
+                let shared = if unshared.contains(&child) {
+                    child
+                } else {
+                    canonical[&keys[child.index()]]
+                };
+                let child_object = match object_of.get(&shared) {
+                    Some(&child_object) => child_object,
+                    None => {
+                        let child_object = objects.add_node(self.dag[shared]);
+                        object_of.insert(shared, child_object);
+                        stack.push(shared);
+                        child_object
+                    }
+                };
+                objects.add_edge(object, child_object, offset);
+            }
+        }
+        self.objects = objects;
+    }
+
+    pub fn resolve(&mut self) {
+        let keys = self.subgraph_keys();
+        let mut unshared: HashSet<NodeIndex> = HashSet::new();
+        loop {
+            self.build_objects(&keys, &unshared);
+            self.order = self.layout_order(&self.objects, self.packing);
+            let overflows = self.assign_offsets(&self.objects, &self.order);
+            // Shared objects which are out of reach of one of the objects
+            // pointing to them get a copy of their own for that object.
+            let out_of_reach: Vec<NodeIndex> = overflows
+                .iter()
+                .filter(|&&edge| {
+                    let (_, target) = self.objects.edge_endpoints(edge).unwrap();
+                    self.objects.edges_directed(target, Incoming).count() > 1
+                })
+                .map(|&edge| self.objects[edge])
+                .collect();
+            if out_of_reach.is_empty() {
+                self.overflows = overflows.len();
+                self.overflowed_fields = self.fields_above(&overflows);
+                break;
+            }
+            unshared.extend(out_of_reach);
         }
 
         // self.dump_graph();
         self.resolved = true;
     }
 
+    // The offset fields of the given edges of the object graph, and of all
+    // of the edges leading to them.
+    fn fields_above(&self, edges: &[EdgeIndex]) -> HashSet<usize> {
+        let mut fields = HashSet::new();
+        let mut stack = edges.to_vec();
+        while let Some(edge) = stack.pop() {
+            if fields.insert(self.dag[self.objects[edge]].field_id()) {
+                let (source, _) = self.objects.edge_endpoints(edge).unwrap();
+                stack.extend(
+                    self.objects
+                        .edges_directed(source, Incoming)
+                        .map(|e| e.id()),
+                );
+            }
+        }
+        fields
+    }
+
     pub fn serialize(
         &mut self,
         output: &mut Vec<u8>,
         do_top: bool,
     ) -> Result<(), SerializationError> {
         assert!(self.resolved);
-        let mut topo = Topo::new(&self.dag);
-        let mut node = topo.next(&self.dag);
-        if !do_top {
-            if node.is_none() {
-                return Ok(());
-            }
-            node = topo.next(&self.dag);
+        if self.overflows > 0 {
+            return Err(SerializationError(format!(
+                "{} offsets overflowed",
+                self.overflows
+            )));
         }
-        while node.is_some() {
-            let this_offset = self.dag.node_weight(node.unwrap()).unwrap();
-            this_offset.serialize_contents(output)?;
-            node = topo.next(&self.dag);
+        let skip = if do_top { 0 } else { 1 };
+        for &node in self.order.iter().skip(skip) {
+            self.objects[node].serialize_contents(output)?;
         }
         Ok(())
     }
@@ -645,4 +844,132 @@ mod tests {
             ]
         );
     }
+
+    tables!(
+        HasLongOffset {
+            Offset32(Three) far
+            Offset16(Two) near
+        }
+        Big {
+            Counted(uint16) values
+        }
+        HasBigOffsets {
+            Offset16(Big) first
+            Offset16(Big) second
+        }
+        SharesFarAway {
+            Offset16(Two) first
+            Offset16(Big) big
+            Offset32(Two) second
+        }
+    );
+
+    #[test]
+    fn test_long_offsets_last() {
+        let long = HasLongOffset {
+            far: Offset32::to(Three { blah: 0x3030 }),
+            near: Offset16::to(Two {
+                test1: 0x0a,
+                deep: Offset16::to(Three { blah: 0x1010 }),
+                test2: 0x0b,
+            }),
+        };
+        let mut output = vec![];
+        let root = Offset16::to(long);
+        let mut mgr = OffsetManager::new(&root);
+        mgr.set_packing(Packing::LongOffsetsLast);
+        mgr.resolve();
+        mgr.serialize(&mut output, true).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                0x00, 0x00, 0x00, 0x0e, // offset 14 to Three = 0x3030
+                0x00, 0x06, // offset 6 to Two
+                0x00, 0x0a, // test1
+                0x00, 0x06, // offset 6 to Three = 0x1010
+                0x00, 0x0b, // test2
+                0x10, 0x10, // near.deep = Three
+                0x30, 0x30, // far = Three
+            ]
+        );
+    }
+
+    #[test]
+    fn test_overflow() {
+        let big = |value| Big {
+            values: vec![value; 0x8000],
+        };
+        let root = Offset16::to(HasBigOffsets {
+            first: Offset16::to(big(0)),
+            second: Offset16::to(big(1)),
+        });
+        let mut mgr = OffsetManager::new(&root);
+        mgr.resolve();
+        assert_eq!(mgr.overflows(), 1);
+        let has_big_offsets = root.link.as_ref().unwrap();
+        assert!(!mgr.overflowed(&has_big_offsets.first));
+        assert!(mgr.overflowed(&has_big_offsets.second));
+        assert!(mgr.serialize(&mut vec![], true).is_err());
+    }
+
+    #[test]
+    fn test_shared_subtables() {
+        let big = || Big { values: vec![1, 2] };
+        let root = Offset16::to(HasBigOffsets {
+            first: Offset16::to(big()),
+            second: Offset16::to(big()),
+        });
+        let mut mgr = OffsetManager::new(&root);
+        mgr.resolve();
+        assert_eq!(mgr.object_count(), 2);
+        let mut output = vec![];
+        mgr.serialize(&mut output, true).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                0x00, 0x04, // offset 4 to Big
+                0x00, 0x04, // offset 4 to the same Big
+                0x00, 0x02, 0x00, 0x01, 0x00, 0x02, // Big
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shared_out_of_reach() {
+        let two = || Two {
+            test1: 0x0a,
+            deep: Offset16::to(Three { blah: 0x1010 }),
+            test2: 0x0b,
+        };
+        // The Three shared by both Twos would end up after the Big, out of
+        // reach of the first Two.
+        let root = Offset16::to(SharesFarAway {
+            first: Offset16::to(two()),
+            big: Offset16::to(Big {
+                values: vec![0; 0x7ffc],
+            }),
+            second: Offset32::to(two()),
+        });
+        let mut mgr = OffsetManager::new(&root);
+        mgr.resolve();
+        assert_eq!(mgr.overflows(), 0);
+        assert_eq!(mgr.object_count(), 6);
+        let mut output = vec![];
+        mgr.serialize(&mut output, true).unwrap();
+        assert_eq!(output.len(), 0x10012);
+        assert_eq!(
+            output[8..16],
+            [
+                0x00, 0x0a, 0x00, 0x06, 0x00, 0x0b, // first Two
+                0x10, 0x10, // its own Three
+            ]
+        );
+        assert_eq!(
+            output[0x1000a..],
+            [
+                0x00, 0x0a, 0x00, 0x06, 0x00, 0x0b, // second Two
+                0x10, 0x10, // its own Three
+            ]
+        );
+    }
 }
@@ -61,6 +61,11 @@ pub trait OffsetMarkerTrait: Serialize + Debug {
     fn total_size_with_descendants(&self) -> usize;
     fn needs_resolving(&self) -> bool;
     fn is_explicitly_zero(&self) -> bool;
+    // The size of the offset itself, in bytes
+    fn offset_size(&self) -> usize;
+    // Identifies the offset field, however it is referred to. Nested offsets
+    // can share the address of the marker, but not of the offset value.
+    fn field_id(&self) -> usize;
     // This is gross. Having polymorphic offset marker traits would make everything horrible,
     // so we have to specify the highest offset we need and cast downwards.
     fn set(&self, off: u32);
@@ -111,8 +116,17 @@ where
             && self.off.borrow().unwrap() == U::zero()
     }
 
+    fn offset_size(&self) -> usize {
+        ::std::mem::size_of::<U>()
+    }
+
+    fn field_id(&self) -> usize {
+        self.off.as_ptr() as usize
+    }
+
     // Finally, when we have resolved all the offsets, we use interior
-    // mutability to replace the offset within the `Offset` struct.
+    // mutability to replace the offset within the `Offset` struct. An offset
+    // too large for its type leaves it unset.
     fn set(&self, off: u32) {
         self.off.replace(U::from_u32(off));
     }
@@ -377,14 +391,17 @@ where
         self.as_ref().map_or(true, |x| x.is_explicitly_zero())
     }
 
+    fn offset_size(&self) -> usize {
+        ::std::mem::size_of::<U>()
+    }
+
+    fn field_id(&self) -> usize {
+        self.as_ref().map_or(0, |x| x.field_id())
+    }
+
     fn set(&self, off: u32) {
         if let Some(x) = self {
-            let new_off: Result<U, <u32 as std::convert::TryInto<U>>::Error> = off.try_into();
-            if let Ok(new_off) = new_off {
-                x.off.replace(Some(new_off));
-            } else {
-                panic!("Oops, 32 bit offset didn't fit")
-            }
+            x.set(off)
         } else {
             panic!(
                 "Attempted to set an offset on a None Option<Offset16> {:?}",
@@ -0,0 +1,294 @@
+//! TrueType and OpenType font collections.
+//!
+//! A font collection (`.ttc` or `.otc` file) bundles several fonts into one
+//! file, allowing fonts which have tables in common - typically the glyph
+//! outlines of CJK families - to store those tables only once.
+//!
+//! # Example
+//! ```no_run
+//! use fonttools::collection::FontCollection;
+//!
+//! let mut collection = FontCollection::load("NotoSansCJK.ttc").expect("Could not load");
+//! for font in collection.fonts.iter() {
+//!     let name = font.tables.name().unwrap().unwrap();
+//!     // ...
+//! }
+//! collection.save("NotoSansCJK-modified.ttc").expect("Could not save");
+//! ```
+
+use crate::font::{get_search_range, Font, TableRecord};
+use crate::woff::WoffOptions;
+use crate::woff2;
+use otspec::types::*;
+use otspec::{
+    DeserializationError, Deserialize, Deserializer, ReaderContext, SerializationError, Serialize,
+    Serializer,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::error::Error;
+use std::path::Path;
+use std::rc::Rc;
+
+/// The tag which begins a font collection file.
+pub const TAG: Tag = crate::tag!("ttcf");
+
+/// A collection of fonts, as stored in a `.ttc` or `.otc` file.
+#[derive(Debug, PartialEq, Default)]
+pub struct FontCollection {
+    /// The fonts in this collection.
+    ///
+    /// Fonts read from a collection share the binary data of any tables they
+    /// have in common. When the collection is written, tables with identical
+    /// content are stored only once.
+    pub fonts: Vec<Font>,
+}
+
+impl FontCollection {
+    /// Create a collection from a list of fonts.
+    pub fn new(fonts: Vec<Font>) -> Self {
+        FontCollection { fonts }
+    }
+
+    /// Attempt to load a font collection from disk.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let bytes = std::fs::read(path.as_ref())?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Attempt to load a font collection from a raw byte slice.
+    ///
+    /// Both TrueType/OpenType collections and WOFF2 files are supported.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if woff2::is_woff2(bytes) {
+            return woff2::decode_collection(bytes)
+                .map(|(collection, _)| collection)
+                .map_err(|e| e.into());
+        }
+        otspec::de::from_bytes(bytes).map_err(|e| e.into())
+    }
+
+    /// Attempt to load a font collection from any reader.
+    pub fn from_reader(mut reader: impl std::io::Read) -> Result<Self, Box<dyn Error>> {
+        let mut buf = Vec::new();
+        let _ = reader.read_to_end(&mut buf)?;
+        Self::from_bytes(&buf)
+    }
+
+    /// Returns `true` if the given binary data starts with a font collection header.
+    pub fn is_collection(bytes: &[u8]) -> bool {
+        bytes.len() >= 4 && bytes[0..4] == *TAG.as_bytes()
+    }
+
+    /// The number of fonts in this collection.
+    pub fn len(&self) -> usize {
+        self.fonts.len()
+    }
+
+    /// Returns `true` if the collection contains no fonts.
+    pub fn is_empty(&self) -> bool {
+        self.fonts.is_empty()
+    }
+
+    /// Attempt to save the collection to the provided path.
+    pub fn save(&mut self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let file = std::fs::File::create(path)?;
+        self.write(file)
+    }
+
+    /// Attempt to write the collection into the provided [`Writer`][std::io::Write].
+    ///
+    /// A version 1.0 header is always written; any digital signature in the
+    /// original collection would be invalidated by rewriting it, and is dropped.
+    pub fn write(&mut self, mut writer: impl std::io::Write) -> Result<(), Box<dyn Error>> {
+        for font in self.fonts.iter_mut() {
+            font.compile()?;
+        }
+        let mut bytes = Vec::new();
+        self.to_bytes(&mut bytes)?;
+        writer.write_all(&bytes).map_err(Into::into)
+    }
+
+    /// Attempt to save the collection to the provided path as a WOFF2 file.
+    pub fn save_woff2(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: &WoffOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = std::fs::File::create(path)?;
+        self.write_woff2(file, options)
+    }
+
+    /// Attempt to write the collection as a WOFF2 file into the provided
+    /// [`Writer`][std::io::Write].
+    ///
+    /// Tables which are identical between fonts are stored only once.
+    pub fn write_woff2(
+        &mut self,
+        mut writer: impl std::io::Write,
+        options: &WoffOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        for font in self.fonts.iter_mut() {
+            font.compile()?;
+        }
+        let bytes = woff2::encode(&self.fonts, options, true)?;
+        writer.write_all(&bytes).map_err(Into::into)
+    }
+}
+
+impl Serialize for FontCollection {
+    fn to_bytes(&self, data: &mut Vec<u8>) -> Result<(), SerializationError> {
+        let directories = self
+            .fonts
+            .iter()
+            .map(|font| Ok((font.sfnt_version(), font.compiled_tables()?)))
+            .collect::<Result<Vec<_>, SerializationError>>()?;
+        let num_fonts: u32 = directories.len().try_into().unwrap();
+
+        let header_size = 12 + 4 * directories.len();
+        let mut directory_offsets: Vec<u32> = Vec::with_capacity(directories.len());
+        let mut pos = header_size;
+        for (_, tables) in &directories {
+            directory_offsets.push(pos as u32);
+            pos += 12 + 16 * tables.len();
+        }
+
+        // Lay out the table data, storing identical tables once.
+        let data_start = pos;
+        let mut table_data: Vec<u8> = vec![];
+        let mut table_offsets: HashMap<Rc<[u8]>, u32> = HashMap::new();
+        let mut output_directories: Vec<u8> = vec![];
+        for (sfnt_version, tables) in &directories {
+            let num_tables: u16 = tables.len().try_into().unwrap();
+            let (search_range, max_pow2, range_shift) = get_search_range(num_tables, 16);
+            output_directories.put(*sfnt_version as u32)?;
+            output_directories.put(num_tables)?;
+            output_directories.put(search_range)?;
+            output_directories.put(max_pow2)?;
+            output_directories.put(range_shift)?;
+            for table in tables {
+                let offset = *table_offsets.entry(table.data.clone()).or_insert_with(|| {
+                    let offset = (data_start + table_data.len()) as u32;
+                    table_data.extend_from_slice(&table.data);
+                    while !table_data.len().is_multiple_of(4) {
+                        table_data.push(0);
+                    }
+                    offset
+                });
+                output_directories.put(TableRecord {
+                    tag: table.tag,
+                    checksum: table.checksum,
+                    offset,
+                    length: table.data.len() as u32,
+                })?;
+            }
+        }
+
+        data.put(TAG)?;
+        data.put(1_u16)?; // majorVersion
+        data.put(0_u16)?; // minorVersion
+        data.put(num_fonts)?;
+        data.put(directory_offsets)?;
+        data.extend(output_directories);
+        data.extend(table_data);
+        Ok(())
+    }
+}
+
+impl Deserialize for FontCollection {
+    fn from_bytes(c: &mut ReaderContext) -> Result<Self, DeserializationError> {
+        let tag: Tag = c.de()?;
+        if tag != TAG {
+            return Err(DeserializationError(
+                "Font collection must begin with 'ttcf'".to_string(),
+            ));
+        }
+        let major_version: uint16 = c.de()?;
+        let _minor_version: uint16 = c.de()?;
+        if major_version != 1 && major_version != 2 {
+            return Err(DeserializationError(format!(
+                "Unknown font collection version {}",
+                major_version
+            )));
+        }
+        let num_fonts: uint32 = c.de()?;
+        let offsets: Vec<uint32> = c.de_counted(num_fonts as usize)?;
+        // Version 2 headers go on to describe a DSIG table, which we ignore.
+
+        let mut shared = BTreeMap::new();
+        let mut fonts = Vec::with_capacity(offsets.len());
+        for offset in offsets {
+            c.ptr = offset as usize;
+            fonts.push(Font::from_table_directory(c, &mut shared)?);
+        }
+        Ok(FontCollection { fonts })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tables;
+    use crate::testing;
+
+    #[test]
+    fn test_collection_roundtrip() {
+        let mut second = testing::test_font(5);
+        second.tables.insert(testing::hhea(750));
+        let mut collection = FontCollection::new(vec![testing::test_font(5), second]);
+        let mut binary = vec![];
+        collection.write(&mut binary).unwrap();
+        assert!(FontCollection::is_collection(&binary));
+
+        let reloaded = FontCollection::from_bytes(&binary).unwrap();
+        assert_eq!(reloaded.len(), 2);
+        for (font, original) in reloaded.fonts.iter().zip(collection.fonts.iter()) {
+            font.fully_deserialize();
+            assert_eq!(
+                *font.tables.hhea().unwrap().unwrap(),
+                *original.tables.hhea().unwrap().unwrap()
+            );
+            assert_eq!(
+                *font.tables.maxp().unwrap().unwrap(),
+                *original.tables.maxp().unwrap().unwrap()
+            );
+        }
+
+        // The maxp tables are identical, so are stored once; the hhea tables
+        // differ, so they are stored separately.
+        let mut c = ReaderContext::new(binary);
+        c.ptr = 12;
+        let offsets: Vec<u32> = c.de_counted(2).unwrap();
+        let records: Vec<Vec<TableRecord>> = offsets
+            .iter()
+            .map(|&off| {
+                c.ptr = off as usize + 12;
+                c.de_counted(3).unwrap()
+            })
+            .collect();
+        let offset_of = |font: usize, tag: Tag| {
+            records[font]
+                .iter()
+                .find(|tr| tr.tag == tag)
+                .unwrap()
+                .offset
+        };
+        assert_eq!(
+            offset_of(0, tables::maxp::TAG),
+            offset_of(1, tables::maxp::TAG)
+        );
+        assert_ne!(
+            offset_of(0, tables::hhea::TAG),
+            offset_of(1, tables::hhea::TAG)
+        );
+    }
+
+    #[test]
+    fn test_not_a_collection() {
+        let mut font = testing::test_font(5);
+        let mut binary = vec![];
+        font.write(&mut binary).unwrap();
+        assert!(!FontCollection::is_collection(&binary));
+        assert!(FontCollection::from_bytes(&binary).is_err());
+    }
+}
@@ -0,0 +1,521 @@
+//! Parsing and compilation of Adobe feature files
+//!
+//! This module compiles the AFDKO feature file syntax into `GSUB`, `GPOS`
+//! and `GDEF` tables, together with the `name`, `STAT`, `OS/2`, `head` and
+//! `hhea` values which feature files can also specify. Glyph names are
+//! resolved through a supplied glyph order, or through the `post` table of
+//! a font. Going the other way, [`GSUB::to_fea`] and [`GPOS::to_fea`]
+//! decompile layout tables back to feature file syntax.
+//!
+//! # Example
+//! ```
+//! use fonttools::feature_file;
+//!
+//! let glyphs: Vec<String> = [".notdef", "f", "i", "f_i"]
+//!     .iter()
+//!     .map(|s| s.to_string())
+//!     .collect();
+//! let tables = feature_file::compile("feature liga { sub f i by f_i; } liga;", &glyphs)
+//!     .expect("Could not compile features");
+//! assert_eq!(tables.gsub.unwrap().lookups.len(), 1);
+//! ```
+use crate::font::Font;
+use crate::tables::name::{name, NameRecord};
+use crate::tables::GDEF::GDEF;
+use crate::tables::GPOS::GPOS;
+use crate::tables::GSUB::GSUB;
+use crate::tables::STAT::STAT;
+use otspec::types::*;
+use std::error::Error;
+
+mod builder;
+mod lexer;
+mod parser;
+mod writer;
+
+pub(crate) use writer::to_fea;
+
+/// The first name ID used for names defined in a feature file
+const FIRST_NAME_ID: u16 = 256;
+
+/// An error found while compiling a feature file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeaError {
+    /// The line of the feature file where the error was found
+    pub line: usize,
+    /// A description of the error
+    pub message: String,
+}
+
+impl FeaError {
+    pub(crate) fn new(line: usize, message: &str) -> Self {
+        FeaError {
+            line,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for FeaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for FeaError {}
+
+/// A value set in a `table` block of a feature file
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TableField {
+    FsType(u16),
+    Panose([u8; 10]),
+    UnicodeRange(Vec<u8>),
+    CodePageRange(Vec<u8>),
+    TypoAscender(i16),
+    TypoDescender(i16),
+    TypoLineGap(i16),
+    WinAscent(u16),
+    WinDescent(u16),
+    XHeight(i16),
+    CapHeight(i16),
+    WeightClass(u16),
+    WidthClass(u16),
+    Vendor(Tag),
+    LowerOpSize(u16),
+    UpperOpSize(u16),
+    FamilyClass(i16),
+    FontRevision(f32),
+    CaretOffset(i16),
+    Ascender(i16),
+    Descender(i16),
+    LineGap(i16),
+}
+
+/// The tables compiled from a feature file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureTables {
+    /// The glyph substitution table, if the feature file has substitutions
+    pub gsub: Option<GSUB>,
+    /// The glyph positioning table, if the feature file has positioning rules
+    pub gpos: Option<GPOS>,
+    /// The glyph definition table, either given explicitly or inferred from
+    /// the positioning rules
+    pub gdef: Option<GDEF>,
+    /// The style attributes table, if the feature file has a `STAT` block
+    pub stat: Option<STAT>,
+    /// Name records defined by the feature file
+    pub names: Vec<NameRecord>,
+    fields: Vec<TableField>,
+}
+
+/// Compiles a feature file, resolving glyph names through the given glyph order.
+///
+/// Names defined by feature parameters and `STAT` blocks are given IDs
+/// starting from 256.
+pub fn compile(source: &str, glyph_names: &[String]) -> Result<FeatureTables, FeaError> {
+    parser::Parser::new(source, glyph_names, FIRST_NAME_ID)?.parse()
+}
+
+/// Compiles a feature file and adds the resulting tables to a font.
+///
+/// Glyph names are taken from the font's `post` table. Names defined by
+/// the feature file are given IDs after any font-specific IDs already in
+/// the `name` table.
+pub fn add_features(font: &mut Font, source: &str) -> Result<(), Box<dyn Error>> {
+    let num_glyphs = font.num_glyphs() as usize;
+    let glyph_names = crate::flavor::glyph_names(font, num_glyphs)?;
+    let first_name_id = font
+        .tables
+        .name()?
+        .and_then(|table| table.records.iter().map(|r| r.nameID).max())
+        .map_or(FIRST_NAME_ID, |max| (max + 1).max(FIRST_NAME_ID));
+    let tables = parser::Parser::new(source, &glyph_names, first_name_id)?.parse()?;
+    tables.apply_to(font)
+}
+
+impl FeatureTables {
+    /// Adds the compiled tables to a font, replacing any existing layout
+    /// tables and updating the `name`, `OS/2`, `head` and `hhea` tables
+    /// with the values given in the feature file.
+    pub fn apply_to(self, font: &mut Font) -> Result<(), Box<dyn Error>> {
+        if let Some(gsub) = self.gsub {
+            font.tables.insert(gsub);
+        }
+        if let Some(gpos) = self.gpos {
+            font.tables.insert(gpos);
+        }
+        if let Some(gdef) = self.gdef {
+            font.tables.insert(gdef);
+        }
+        if let Some(stat) = self.stat {
+            font.tables.insert(stat);
+        }
+        if !self.names.is_empty() {
+            let mut table = font
+                .tables
+                .name()?
+                .map(|table| (*table).clone())
+                .unwrap_or(name { records: vec![] });
+            for record in self.names {
+                table.records.retain(|r| {
+                    (r.platformID, r.encodingID, r.languageID, r.nameID)
+                        != (
+                            record.platformID,
+                            record.encodingID,
+                            record.languageID,
+                            record.nameID,
+                        )
+                });
+                table.records.push(record);
+            }
+            font.tables.insert(table);
+        }
+        if self.fields.is_empty() {
+            return Ok(());
+        }
+        let mut os2 = font.tables.os2()?.map(|table| (*table).clone());
+        let mut head = font.tables.head()?.map(|table| (*table).clone());
+        let mut hhea = font.tables.hhea()?.map(|table| (*table).clone());
+        for field in self.fields {
+            match field {
+                TableField::FontRevision(revision) => {
+                    head.as_mut().ok_or("No head table")?.fontRevision = revision
+                }
+                TableField::CaretOffset(_)
+                | TableField::Ascender(_)
+                | TableField::Descender(_)
+                | TableField::LineGap(_) => {
+                    let hhea = hhea.as_mut().ok_or("No hhea table")?;
+                    match field {
+                        TableField::CaretOffset(v) => hhea.caretOffset = v,
+                        TableField::Ascender(v) => hhea.ascender = v,
+                        TableField::Descender(v) => hhea.descender = v,
+                        TableField::LineGap(v) => hhea.lineGap = v,
+                        _ => unreachable!(),
+                    }
+                }
+                field => {
+                    let os2 = os2.as_mut().ok_or("No OS/2 table")?;
+                    match field {
+                        TableField::FsType(v) => os2.fsType = v,
+                        TableField::Panose(p) => {
+                            os2.panose.panose0 = p[0];
+                            os2.panose.panose1 = p[1];
+                            os2.panose.panose2 = p[2];
+                            os2.panose.panose3 = p[3];
+                            os2.panose.panose4 = p[4];
+                            os2.panose.panose5 = p[5];
+                            os2.panose.panose6 = p[6];
+                            os2.panose.panose7 = p[7];
+                            os2.panose.panose8 = p[8];
+                            os2.panose.panose9 = p[9];
+                        }
+                        TableField::UnicodeRange(bits) => {
+                            let mut ranges = [0_u32; 4];
+                            for bit in bits {
+                                ranges[bit as usize / 32] |= 1 << (bit % 32);
+                            }
+                            os2.ulUnicodeRange1 = ranges[0];
+                            os2.ulUnicodeRange2 = ranges[1];
+                            os2.ulUnicodeRange3 = ranges[2];
+                            os2.ulUnicodeRange4 = ranges[3];
+                        }
+                        TableField::CodePageRange(bits) => {
+                            let mut ranges = [0_u32; 2];
+                            for bit in bits {
+                                ranges[bit as usize / 32] |= 1 << (bit % 32);
+                            }
+                            os2.version = os2.version.max(1);
+                            os2.ulCodePageRange1 = Some(ranges[0]);
+                            os2.ulCodePageRange2 = Some(ranges[1]);
+                        }
+                        TableField::TypoAscender(v) => os2.sTypoAscender = v,
+                        TableField::TypoDescender(v) => os2.sTypoDescender = v,
+                        TableField::TypoLineGap(v) => os2.sTypoLineGap = v,
+                        TableField::WinAscent(v) => os2.usWinAscent = v,
+                        TableField::WinDescent(v) => os2.usWinDescent = v,
+                        TableField::XHeight(v) => {
+                            os2.version = os2.version.max(2);
+                            os2.sxHeight = Some(v)
+                        }
+                        TableField::CapHeight(v) => {
+                            os2.version = os2.version.max(2);
+                            os2.sCapHeight = Some(v)
+                        }
+                        TableField::WeightClass(v) => os2.usWeightClass = v,
+                        TableField::WidthClass(v) => os2.usWidthClass = v,
+                        TableField::Vendor(v) => os2.achVendID = v,
+                        TableField::LowerOpSize(v) => {
+                            os2.version = os2.version.max(5);
+                            os2.usLowerOpticalPointSize = Some(v)
+                        }
+                        TableField::UpperOpSize(v) => {
+                            os2.version = os2.version.max(5);
+                            os2.usUpperOpticalPointSize = Some(v)
+                        }
+                        TableField::FamilyClass(v) => os2.sFamilyClass = v,
+                        _ => unreachable!(),
+                    }
+                }
+            }
+        }
+        if let Some(os2) = os2 {
+            font.tables.insert(os2);
+        }
+        if let Some(head) = head {
+            font.tables.insert(head);
+        }
+        if let Some(hhea) = hhea {
+            font.tables.insert(hhea);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::common::{LookupFlags, ValueRecord};
+    use crate::layout::feature_params::{
+        CharacterVariantParams, FeatureParams, StylisticSetParams,
+    };
+    use crate::tables::GDEF::GlyphClass;
+    use crate::tables::GPOS::Positioning;
+    use crate::tables::GSUB::Substitution;
+    use crate::tag;
+    use otspec::layout::anchor::Anchor;
+
+    fn glyph_order() -> Vec<String> {
+        [
+            ".notdef", "a", "b", "c", "f", "i", "f_i", "a.sc", "b.sc", "c.sc", "acute", "grave",
+            "a.alt1", "a.alt2", "T", "o",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+    }
+
+    #[test]
+    fn test_compile_gsub() {
+        let fea = "
+            languagesystem DFLT dflt;
+            languagesystem latn dflt;
+            languagesystem latn TRK;
+            @lower = [a-c];
+            feature smcp {
+                sub @lower by [a.sc - c.sc];
+            } smcp;
+            feature liga {
+                sub f i by f_i;
+                sub f_i by f i;
+            } liga;
+            feature calt {
+                ignore sub a b' c;
+                sub a b' c by b.sc;
+                sub [a b] c' by c.sc;
+                script latn;
+                language TRK exclude_dflt;
+                sub a from [a.alt1 a.alt2];
+            } calt;
+        ";
+        let tables = compile(fea, &glyph_order()).unwrap();
+        let gsub = tables.gsub.unwrap();
+        assert!(tables.gpos.is_none());
+        assert!(tables.gdef.is_none());
+        // smcp, liga (two lookups), calt chain, calt nested single, TRK alternate
+        assert_eq!(gsub.lookups.len(), 6);
+        match &gsub.lookups[0].rule {
+            Substitution::Single(v) => {
+                assert_eq!(v[0].mapping.get(&1), Some(&7));
+                assert_eq!(v[0].mapping.get(&3), Some(&9));
+            }
+            _ => panic!("Expected a single substitution"),
+        }
+        match &gsub.lookups[1].rule {
+            Substitution::Ligature(v) => assert_eq!(v[0].mapping.get(&vec![4, 5]), Some(&6)),
+            _ => panic!("Expected a ligature substitution"),
+        }
+        match &gsub.lookups[2].rule {
+            Substitution::Multiple(v) => assert_eq!(v[0].mapping.get(&6), Some(&vec![4, 5])),
+            _ => panic!("Expected a multiple substitution"),
+        }
+        match &gsub.lookups[3].rule {
+            Substitution::ChainedContextual(v) => {
+                let rules = &v[0].rules;
+                assert_eq!(rules.len(), 3);
+                assert!(rules[0].input[0].1.is_empty());
+                assert_eq!(rules[1].input[0].1, vec![4]);
+                assert_eq!(rules[2].input[0].1, vec![4]);
+                assert_eq!(rules[2].backtrack[0].len(), 2);
+            }
+            _ => panic!("Expected a chained contextual substitution"),
+        }
+        match &gsub.lookups[4].rule {
+            Substitution::Single(v) => {
+                assert_eq!(v[0].mapping.get(&2), Some(&8));
+                assert_eq!(v[0].mapping.get(&3), Some(&9));
+            }
+            _ => panic!("Expected a single substitution"),
+        }
+        match &gsub.lookups[5].rule {
+            Substitution::Alternate(v) => assert_eq!(v[0].mapping.get(&1), Some(&vec![12, 13])),
+            _ => panic!("Expected an alternate substitution"),
+        }
+
+        let latn = &gsub.scripts.scripts[&tag!("latn")];
+        let trk = &latn.language_systems[&tag!("TRK ")];
+        let trk_calt: Vec<usize> = trk
+            .feature_indices
+            .iter()
+            .filter(|&&ix| gsub.features.get(ix).unwrap().0 == tag!("calt"))
+            .flat_map(|&ix| gsub.features.get(ix).unwrap().1.clone())
+            .collect();
+        assert_eq!(trk_calt, vec![5]);
+        assert!(latn.default_language_system.is_some());
+        assert!(gsub.scripts.scripts[&tag!("DFLT")]
+            .default_language_system
+            .is_some());
+    }
+
+    #[test]
+    fn test_compile_gpos() {
+        let fea = "
+            markClass [acute grave] <anchor 150 -10> @TOP;
+            @round = [o c];
+            feature kern {
+                pos T @round -80;
+                pos T o -100;
+                enum pos a [b c] 10;
+                pos f <0 0 20 0> i <5 0 5 0>;
+            } kern;
+            feature mark {
+                lookupflag UseMarkFilteringSet [acute];
+                pos base [a b] <anchor 250 450> mark @TOP;
+            } mark;
+            feature ss01 {
+                featureNames { name \"Alternate a\"; name 1 \"Alternate a\"; };
+                sub a by a.alt1;
+            } ss01;
+            feature cv01 {
+                cvParameters {
+                    FeatUILabelNameID { name \"Small b\"; };
+                    Character 0x62;
+                };
+                sub b by b.sc;
+            } cv01;
+        ";
+        let tables = compile(fea, &glyph_order()).unwrap();
+        let gpos = tables.gpos.unwrap();
+        assert_eq!(gpos.lookups.len(), 2);
+        match &gpos.lookups[0].rule {
+            Positioning::Pair(v) => {
+                let mapping = &v[0].mapping;
+                let kern = |x| {
+                    let mut record = ValueRecord::new();
+                    record.xAdvance = Some(x);
+                    record
+                };
+                assert_eq!(mapping[&(14, 15)].0, kern(-100));
+                assert_eq!(mapping[&(14, 3)].0, kern(-80));
+                assert_eq!(mapping[&(1, 2)].0, kern(10));
+                assert_eq!(mapping[&(4, 5)].1.xPlacement, Some(5));
+            }
+            _ => panic!("Expected a pair positioning lookup"),
+        }
+        let lookup = &gpos.lookups[1];
+        assert_eq!(lookup.flags, LookupFlags::USE_MARK_FILTERING_SET);
+        assert_eq!(lookup.mark_filtering_set, Some(0));
+        match &lookup.rule {
+            Positioning::MarkToBase(v) => {
+                assert_eq!(v[0].marks[&10], (0, Anchor::new(150, -10)));
+                assert_eq!(v[0].bases[&2][&0], Anchor::new(250, 450));
+            }
+            _ => panic!("Expected a mark-to-base lookup"),
+        }
+
+        let gdef = tables.gdef.unwrap();
+        assert_eq!(gdef.glyph_class.get(&1), Some(&GlyphClass::BaseGlyph));
+        assert_eq!(gdef.glyph_class.get(&11), Some(&GlyphClass::MarkGlyph));
+        assert_eq!(gdef.mark_glyph_sets.unwrap()[0].len(), 1);
+
+        let gsub = tables.gsub.unwrap();
+        let params = |feature| {
+            gsub.features
+                .iter()
+                .find(|(tag, _, _)| *tag == feature)
+                .and_then(|(_, _, params)| params.clone())
+        };
+        assert_eq!(
+            params(tag!("ss01")),
+            Some(FeatureParams::StylisticSet(StylisticSetParams {
+                ui_name_id: 256
+            }))
+        );
+        assert_eq!(
+            params(tag!("cv01")),
+            Some(FeatureParams::CharacterVariant(CharacterVariantParams {
+                label_name_id: 257,
+                characters: vec![0x62],
+                ..Default::default()
+            }))
+        );
+        assert_eq!(tables.names.len(), 3);
+        assert_eq!(tables.names[1].platformID, 1);
+        assert_eq!(tables.names[1].nameID, 256);
+    }
+
+    #[test]
+    fn test_compile_tables() {
+        let fea = "
+            table GDEF {
+                GlyphClassDef [a b], [f_i], [acute grave], ;
+                LigatureCaretByPos f_i 300;
+            } GDEF;
+            table name {
+                nameid 9 \"Ren\\00e9\";
+            } name;
+            table STAT {
+                ElidedFallbackNameID 2;
+                DesignAxis wght 0 { name \"Weight\"; };
+                AxisValue { location wght 400; name \"Regular\"; flag ElidableAxisValueName; };
+                AxisValue { location wght 700 400; name \"Bold\"; };
+            } STAT;
+            table OS/2 {
+                FSType 0;
+                Vendor \"TEST\";
+            } OS/2;
+        ";
+        let tables = compile(fea, &glyph_order()).unwrap();
+        let gdef = tables.gdef.unwrap();
+        assert_eq!(gdef.glyph_class.len(), 5);
+        assert_eq!(gdef.glyph_class.get(&6), Some(&GlyphClass::LigatureGlyph));
+        assert_eq!(gdef.ligature_caret_list[&6].len(), 1);
+        assert_eq!(tables.names[0].string, "René");
+        assert_eq!(tables.names[0].nameID, 9);
+        let stat = tables.stat.unwrap();
+        assert_eq!(stat.design_axes[0].axisNameID, 256);
+        assert_eq!(stat.axis_values.len(), 2);
+        assert_eq!(stat.axis_values[1].linked_value, Some(400.0));
+        assert_eq!(tables.fields.len(), 2);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err =
+            compile("feature liga {\n  sub f x by f_i;\n} liga;", &glyph_order()).unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(err.message, "Unknown glyph x");
+        let err = compile("feature liga { sub f i by f_i; } calt;", &glyph_order()).unwrap_err();
+        assert_eq!(err.message, "Expected liga");
+        let err = compile("lookup foo { sub a by b; pos a 10; } foo;", &glyph_order()).unwrap_err();
+        assert_eq!(
+            err.message,
+            "Lookup blocks cannot contain rules of different types"
+        );
+        let err = compile("feature salt { sub a from []; } salt;", &glyph_order()).unwrap_err();
+        assert_eq!(
+            err.message,
+            "Alternate substitutions need at least one alternate"
+        );
+    }
+}
@@ -0,0 +1,873 @@
+use crate::layout::common::{LanguageSystem, Lookup, LookupFlags, Script, GPOSGSUB};
+use crate::layout::contextual::ChainedSequenceContext;
+use crate::layout::feature_params::FeatureParams;
+use crate::layout::gpos1::SinglePos;
+use crate::layout::gpos2::PairPos;
+use crate::layout::gpos3::CursivePos;
+use crate::layout::gpos4::MarkBasePos;
+use crate::layout::gpos5::MarkLigPos;
+use crate::layout::gpos6::MarkMarkPos;
+use crate::layout::gsub1::SingleSubst;
+use crate::layout::gsub2::MultipleSubst;
+use crate::layout::gsub3::AlternateSubst;
+use crate::layout::gsub4::LigatureSubst;
+use crate::layout::gsub8::ReverseChainSubst;
+use crate::tables::GDEF::{CaretValue, GlyphClass, GDEF};
+use crate::tables::GPOS::{Positioning, GPOS};
+use crate::tables::GSUB::{Substitution, GSUB};
+use crate::tag;
+use otspec::layout::anchor::Anchor;
+use otspec::types::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The default script tag
+pub(crate) const DFLT: Tag = tag!("DFLT");
+/// The default language tag
+pub(crate) const DFLT_LANGUAGE: Tag = tag!("dflt");
+const AALT: Tag = tag!("aalt");
+
+/// The type of a lookup being built
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Kind {
+    Single,
+    Multiple,
+    Alternate,
+    Ligature,
+    ChainSub,
+    ReverseSub,
+    SinglePos,
+    PairPos,
+    Cursive,
+    MarkBase,
+    MarkLig,
+    MarkMark,
+    ChainPos,
+}
+
+impl Kind {
+    fn is_gpos(self) -> bool {
+        matches!(
+            self,
+            Kind::SinglePos
+                | Kind::PairPos
+                | Kind::Cursive
+                | Kind::MarkBase
+                | Kind::MarkLig
+                | Kind::MarkMark
+                | Kind::ChainPos
+        )
+    }
+
+    fn empty_substitution(self) -> Substitution {
+        match self {
+            Kind::Single => Substitution::Single(vec![SingleSubst::default()]),
+            Kind::Multiple => Substitution::Multiple(vec![MultipleSubst::default()]),
+            Kind::Alternate => Substitution::Alternate(vec![AlternateSubst::default()]),
+            Kind::Ligature => Substitution::Ligature(vec![LigatureSubst::default()]),
+            Kind::ChainSub => {
+                Substitution::ChainedContextual(vec![ChainedSequenceContext::default()])
+            }
+            _ => Substitution::ReverseChainContextual(vec![]),
+        }
+    }
+
+    fn empty_positioning(self) -> Positioning {
+        match self {
+            Kind::SinglePos => Positioning::Single(vec![SinglePos::default()]),
+            Kind::PairPos => Positioning::Pair(vec![PairPos::default()]),
+            Kind::Cursive => Positioning::Cursive(vec![CursivePos::default()]),
+            Kind::MarkBase => Positioning::MarkToBase(vec![MarkBasePos::default()]),
+            Kind::MarkLig => Positioning::MarkToLig(vec![MarkLigPos::default()]),
+            Kind::MarkMark => Positioning::MarkToMark(vec![MarkMarkPos::default()]),
+            _ => Positioning::ChainedContextual(vec![ChainedSequenceContext::default()]),
+        }
+    }
+}
+
+/// A lookup, identified by its table and its index in that table's lookup list
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct LookupRef {
+    pub gpos: bool,
+    pub index: usize,
+}
+
+/// The lookups registered for a (feature, script, language) triple
+#[derive(Debug, Clone, Default)]
+struct FeatureLookups {
+    gsub: Vec<usize>,
+    gpos: Vec<usize>,
+}
+
+/// The `aalt` feature, which is assembled from other features at the end
+#[derive(Debug, Clone, Default)]
+struct Aalt {
+    features: Vec<Tag>,
+    langsys: Vec<(Tag, Tag)>,
+    alternates: BTreeMap<GlyphID, Vec<GlyphID>>,
+}
+
+/// Accumulates lookups, features and glyph definitions while a feature file
+/// is parsed, and assembles them into layout tables.
+#[derive(Debug, Default)]
+pub(crate) struct Builder {
+    gsub_lookups: Vec<Lookup<Substitution>>,
+    gpos_lookups: Vec<Lookup<Positioning>>,
+    named_lookups: BTreeMap<String, LookupRef>,
+    default_langsys: Vec<(Tag, Tag)>,
+    features: BTreeMap<(Tag, Tag, Tag), FeatureLookups>,
+    required: BTreeSet<(Tag, Tag, Tag)>,
+    pub feature_params: BTreeMap<Tag, FeatureParams>,
+    aalt: Option<Aalt>,
+
+    feature: Option<Tag>,
+    script: Option<Tag>,
+    langsys: Vec<(Tag, Tag)>,
+    lookup_name: Option<String>,
+    current: Option<(Kind, usize)>,
+    flags: LookupFlags,
+    mark_filtering_set: Option<uint16>,
+    /// Mark classes used by the current mark attachment lookup, in class order
+    lookup_mark_classes: Vec<String>,
+    /// Single substitution or positioning lookups nested in the current
+    /// contextual lookup, which later rules may reuse
+    nested_lookups: Vec<usize>,
+
+    pub mark_classes: BTreeMap<String, BTreeMap<GlyphID, Anchor>>,
+    pub glyph_class_def: Option<BTreeMap<GlyphID, GlyphClass>>,
+    base_glyphs: BTreeSet<GlyphID>,
+    ligature_glyphs: BTreeSet<GlyphID>,
+    pub attachment_points: BTreeMap<GlyphID, Vec<uint16>>,
+    pub ligature_carets: BTreeMap<GlyphID, Vec<CaretValue>>,
+    mark_attachment_classes: Vec<BTreeSet<GlyphID>>,
+    mark_glyph_sets: Vec<BTreeSet<GlyphID>>,
+}
+
+macro_rules! subtable_accessor {
+    ($name:ident, $kind:ident, $table:ident, $variant:path, $subtable:ty) => {
+        pub(crate) fn $name(&mut self) -> Result<&mut $subtable, String> {
+            let index = self.lookup(Kind::$kind)?;
+            match &mut self.$table[index].rule {
+                $variant(v) => Ok(v.last_mut().unwrap()),
+                _ => unreachable!(),
+            }
+        }
+    };
+}
+
+impl Builder {
+    /// Adds a `languagesystem` statement.
+    pub(crate) fn add_language_system(&mut self, script: Tag, language: Tag) -> Result<(), String> {
+        if self.features.keys().next().is_some() {
+            return Err("languagesystem statements must precede feature blocks".to_string());
+        }
+        if self.default_langsys.contains(&(script, language)) {
+            return Err(format!(
+                "Duplicate languagesystem {} {}",
+                script.as_ref() as &str,
+                language.as_ref() as &str
+            ));
+        }
+        if script == DFLT && language == DFLT_LANGUAGE && !self.default_langsys.is_empty() {
+            return Err("languagesystem DFLT dflt must be first".to_string());
+        }
+        self.default_langsys.push((script, language));
+        Ok(())
+    }
+
+    fn end_lookup(&mut self) {
+        self.current = None;
+        self.lookup_mark_classes.clear();
+        self.nested_lookups.clear();
+    }
+
+    /// Starts a feature block.
+    pub(crate) fn start_feature(&mut self, tag: Tag) {
+        self.feature = Some(tag);
+        self.script = None;
+        self.langsys = if self.default_langsys.is_empty() {
+            vec![(DFLT, DFLT_LANGUAGE)]
+        } else {
+            self.default_langsys.clone()
+        };
+        self.flags = LookupFlags::empty();
+        self.mark_filtering_set = None;
+        self.end_lookup();
+        if tag == AALT {
+            self.aalt.get_or_insert_with(Aalt::default).langsys = self.langsys.clone();
+        }
+    }
+
+    /// Ends a feature block.
+    pub(crate) fn end_feature(&mut self) {
+        self.feature = None;
+        self.flags = LookupFlags::empty();
+        self.mark_filtering_set = None;
+        self.end_lookup();
+    }
+
+    /// The tag of the feature block being parsed.
+    pub(crate) fn feature(&self) -> Option<Tag> {
+        self.feature
+    }
+
+    /// Starts a named lookup block.
+    pub(crate) fn start_lookup(&mut self, name: &str) -> Result<(), String> {
+        if self.lookup_name.is_some() {
+            return Err("Lookup blocks cannot be nested".to_string());
+        }
+        if self.named_lookups.contains_key(name) {
+            return Err(format!("Lookup {} is already defined", name));
+        }
+        if self.feature.is_none() {
+            self.flags = LookupFlags::empty();
+            self.mark_filtering_set = None;
+        }
+        self.end_lookup();
+        self.lookup_name = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Ends a named lookup block.
+    pub(crate) fn end_lookup_block(&mut self) {
+        if let (Some(name), Some((kind, index))) = (self.lookup_name.take(), self.current) {
+            self.named_lookups.insert(
+                name,
+                LookupRef {
+                    gpos: kind.is_gpos(),
+                    index,
+                },
+            );
+        }
+        if self.feature.is_none() {
+            self.flags = LookupFlags::empty();
+            self.mark_filtering_set = None;
+        }
+        self.end_lookup();
+    }
+
+    /// Whether a named lookup block is being parsed.
+    pub(crate) fn in_lookup_block(&self) -> bool {
+        self.lookup_name.is_some()
+    }
+
+    /// Finds a previously defined named lookup.
+    pub(crate) fn named_lookup(&self, name: &str) -> Result<LookupRef, String> {
+        self.named_lookups
+            .get(name)
+            .copied()
+            .ok_or_else(|| format!("Unknown lookup {}", name))
+    }
+
+    /// Adds a reference to a named lookup to the current feature.
+    pub(crate) fn reference_lookup(&mut self, name: &str) -> Result<(), String> {
+        let lookup = self.named_lookup(name)?;
+        if self.feature.is_none() {
+            return Err("Lookup references must be inside a feature block".to_string());
+        }
+        self.register(lookup);
+        self.end_lookup();
+        Ok(())
+    }
+
+    /// Handles a `script` statement.
+    pub(crate) fn set_script(&mut self, script: Tag) -> Result<(), String> {
+        if self.feature.is_none() {
+            return Err("script statements must be inside a feature block".to_string());
+        }
+        if self.lookup_name.is_some() {
+            return Err("script statements are not allowed in lookup blocks".to_string());
+        }
+        self.script = Some(script);
+        self.langsys = vec![(script, DFLT_LANGUAGE)];
+        self.flags = LookupFlags::empty();
+        self.mark_filtering_set = None;
+        self.end_lookup();
+        Ok(())
+    }
+
+    /// Handles a `language` statement.
+    pub(crate) fn set_language(
+        &mut self,
+        language: Tag,
+        include_default: bool,
+        required: bool,
+    ) -> Result<(), String> {
+        let feature = self
+            .feature
+            .ok_or("language statements must be inside a feature block")?;
+        if self.lookup_name.is_some() {
+            return Err("language statements are not allowed in lookup blocks".to_string());
+        }
+        let script = self.script.unwrap_or(DFLT);
+        self.langsys = vec![(script, language)];
+        if language != DFLT_LANGUAGE {
+            let defaults = if include_default {
+                self.features
+                    .get(&(feature, script, DFLT_LANGUAGE))
+                    .cloned()
+                    .unwrap_or_default()
+            } else {
+                FeatureLookups::default()
+            };
+            self.features.insert((feature, script, language), defaults);
+        }
+        if required {
+            self.required.insert((feature, script, language));
+        }
+        self.end_lookup();
+        Ok(())
+    }
+
+    /// Handles a `lookupflag` statement.
+    pub(crate) fn set_lookup_flags(&mut self, flags: LookupFlags, mark_filtering_set: Option<u16>) {
+        self.flags = flags;
+        self.mark_filtering_set = mark_filtering_set;
+        if self.lookup_name.is_none() {
+            self.end_lookup();
+        }
+    }
+
+    /// The class number of a `MarkAttachmentType` glyph class
+    pub(crate) fn mark_attachment_class(
+        &mut self,
+        glyphs: BTreeSet<GlyphID>,
+    ) -> Result<u16, String> {
+        if let Some(ix) = self
+            .mark_attachment_classes
+            .iter()
+            .position(|class| *class == glyphs)
+        {
+            return Ok(ix as u16 + 1);
+        }
+        if self
+            .mark_attachment_classes
+            .iter()
+            .any(|class| !class.is_disjoint(&glyphs))
+        {
+            return Err("Glyphs cannot be in more than one mark attachment class".to_string());
+        }
+        if self.mark_attachment_classes.len() == 255 {
+            return Err("Too many mark attachment classes".to_string());
+        }
+        self.mark_attachment_classes.push(glyphs);
+        Ok(self.mark_attachment_classes.len() as u16)
+    }
+
+    /// The index of a `UseMarkFilteringSet` glyph set
+    pub(crate) fn mark_filtering_set(&mut self, glyphs: BTreeSet<GlyphID>) -> u16 {
+        if let Some(ix) = self.mark_glyph_sets.iter().position(|set| *set == glyphs) {
+            return ix as u16;
+        }
+        self.mark_glyph_sets.push(glyphs);
+        (self.mark_glyph_sets.len() - 1) as u16
+    }
+
+    /// Handles a `subtable` statement.
+    pub(crate) fn add_subtable_break(&mut self) {
+        if let Some((kind, index)) = self.current {
+            if kind.is_gpos() {
+                self.gpos_lookups[index].rule.add_subtable_break();
+            } else {
+                self.gsub_lookups[index].rule.add_subtable_break();
+            }
+            self.lookup_mark_classes.clear();
+        }
+    }
+
+    fn register(&mut self, lookup: LookupRef) {
+        let feature = match self.feature {
+            Some(feature) => feature,
+            None => return,
+        };
+        for &(script, language) in &self.langsys {
+            let entry = self
+                .features
+                .entry((feature, script, language))
+                .or_default();
+            if lookup.gpos {
+                entry.gpos.push(lookup.index);
+            } else {
+                entry.gsub.push(lookup.index);
+            }
+        }
+    }
+
+    /// Makes sure the current feature appears in the feature list, even
+    /// if it has no lookups.
+    pub(crate) fn touch_feature(&mut self) {
+        if let Some(feature) = self.feature {
+            for &(script, language) in &self.langsys {
+                self.features
+                    .entry((feature, script, language))
+                    .or_default();
+            }
+        }
+    }
+
+    /// The index of the lookup receiving rules of the given kind, creating
+    /// it if needed.
+    fn lookup(&mut self, kind: Kind) -> Result<usize, String> {
+        match self.current {
+            Some((current_kind, index)) if current_kind == kind => return Ok(index),
+            Some(_) if self.lookup_name.is_some() => {
+                return Err("Lookup blocks cannot contain rules of different types".to_string())
+            }
+            _ => {}
+        }
+        if self.feature.is_none() && self.lookup_name.is_none() {
+            return Err("Rules must be inside a feature or lookup block".to_string());
+        }
+        if self.feature == Some(AALT) && self.lookup_name.is_none() {
+            return Err("Only single and alternate substitutions are allowed in aalt".to_string());
+        }
+        self.end_lookup();
+        let index = self.new_lookup(kind);
+        if self.lookup_name.is_none() {
+            self.register(LookupRef {
+                gpos: kind.is_gpos(),
+                index,
+            });
+        }
+        self.current = Some((kind, index));
+        Ok(index)
+    }
+
+    /// Adds a lookup which is not attached to any feature, with the current flags.
+    fn new_lookup(&mut self, kind: Kind) -> usize {
+        if kind.is_gpos() {
+            self.gpos_lookups.push(Lookup {
+                flags: self.flags,
+                mark_filtering_set: self.mark_filtering_set,
+                rule: kind.empty_positioning(),
+            });
+            self.gpos_lookups.len() - 1
+        } else {
+            self.gsub_lookups.push(Lookup {
+                flags: self.flags,
+                mark_filtering_set: self.mark_filtering_set,
+                rule: kind.empty_substitution(),
+            });
+            self.gsub_lookups.len() - 1
+        }
+    }
+
+    subtable_accessor!(
+        single_subst,
+        Single,
+        gsub_lookups,
+        Substitution::Single,
+        SingleSubst
+    );
+    subtable_accessor!(
+        multiple_subst,
+        Multiple,
+        gsub_lookups,
+        Substitution::Multiple,
+        MultipleSubst
+    );
+    subtable_accessor!(
+        alternate_subst,
+        Alternate,
+        gsub_lookups,
+        Substitution::Alternate,
+        AlternateSubst
+    );
+    subtable_accessor!(
+        ligature_subst,
+        Ligature,
+        gsub_lookups,
+        Substitution::Ligature,
+        LigatureSubst
+    );
+    subtable_accessor!(
+        chain_subst,
+        ChainSub,
+        gsub_lookups,
+        Substitution::ChainedContextual,
+        ChainedSequenceContext
+    );
+    subtable_accessor!(
+        single_pos,
+        SinglePos,
+        gpos_lookups,
+        Positioning::Single,
+        SinglePos
+    );
+    subtable_accessor!(pair_pos, PairPos, gpos_lookups, Positioning::Pair, PairPos);
+    subtable_accessor!(
+        cursive_pos,
+        Cursive,
+        gpos_lookups,
+        Positioning::Cursive,
+        CursivePos
+    );
+    subtable_accessor!(
+        mark_base_pos,
+        MarkBase,
+        gpos_lookups,
+        Positioning::MarkToBase,
+        MarkBasePos
+    );
+    subtable_accessor!(
+        mark_lig_pos,
+        MarkLig,
+        gpos_lookups,
+        Positioning::MarkToLig,
+        MarkLigPos
+    );
+    subtable_accessor!(
+        mark_mark_pos,
+        MarkMark,
+        gpos_lookups,
+        Positioning::MarkToMark,
+        MarkMarkPos
+    );
+    subtable_accessor!(
+        chain_pos,
+        ChainPos,
+        gpos_lookups,
+        Positioning::ChainedContextual,
+        ChainedSequenceContext
+    );
+
+    /// Adds a reverse chaining substitution rule, which always gets a
+    /// subtable of its own.
+    pub(crate) fn add_reverse_subst(&mut self, subtable: ReverseChainSubst) -> Result<(), String> {
+        let index = self.lookup(Kind::ReverseSub)?;
+        match &mut self.gsub_lookups[index].rule {
+            Substitution::ReverseChainContextual(v) => v.push(subtable),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Adds alternates to the `aalt` feature.
+    pub(crate) fn add_aalt_alternates(&mut self, glyph: GlyphID, alternates: &[GlyphID]) {
+        let entry = self
+            .aalt
+            .get_or_insert_with(Aalt::default)
+            .alternates
+            .entry(glyph)
+            .or_default();
+        for alternate in alternates {
+            if !entry.contains(alternate) {
+                entry.push(*alternate);
+            }
+        }
+    }
+
+    /// Adds a `feature` reference inside the `aalt` feature.
+    pub(crate) fn add_aalt_feature(&mut self, feature: Tag) {
+        self.aalt
+            .get_or_insert_with(Aalt::default)
+            .features
+            .push(feature);
+    }
+
+    /// Returns the class index of a mark class in the current mark attachment lookup.
+    pub(crate) fn lookup_mark_class(&mut self, name: &str) -> u16 {
+        if let Some(ix) = self.lookup_mark_classes.iter().position(|n| n == name) {
+            return ix as u16;
+        }
+        self.lookup_mark_classes.push(name.to_string());
+        (self.lookup_mark_classes.len() - 1) as u16
+    }
+
+    /// Notes glyphs used as bases in mark-to-base rules, for GDEF inference.
+    pub(crate) fn add_base_glyphs(&mut self, glyphs: &[GlyphID]) {
+        self.base_glyphs.extend(glyphs);
+    }
+
+    /// Notes glyphs used as ligatures in mark-to-ligature rules, for GDEF inference.
+    pub(crate) fn add_ligature_glyphs(&mut self, glyphs: &[GlyphID]) {
+        self.ligature_glyphs.extend(glyphs);
+    }
+
+    /// Returns a single substitution lookup, nested in the current contextual
+    /// lookup, which can take the given mapping without conflicts.
+    pub(crate) fn nested_single_subst(
+        &mut self,
+        mapping: &BTreeMap<GlyphID, GlyphID>,
+    ) -> Result<usize, String> {
+        for &index in &self.nested_lookups {
+            if let Substitution::Single(v) = &mut self.gsub_lookups[index].rule {
+                let subtable = v.last_mut().unwrap();
+                if mapping
+                    .iter()
+                    .all(|(k, v)| subtable.mapping.get(k).is_none_or(|old| old == v))
+                {
+                    subtable.mapping.extend(mapping);
+                    return Ok(index);
+                }
+            }
+        }
+        let index = self.new_lookup(Kind::Single);
+        if let Substitution::Single(v) = &mut self.gsub_lookups[index].rule {
+            v[0].mapping.extend(mapping);
+        }
+        self.nested_lookups.push(index);
+        Ok(index)
+    }
+
+    /// Adds a multiple substitution lookup nested in a contextual lookup.
+    pub(crate) fn nested_multiple_subst(&mut self, subtable: MultipleSubst) -> usize {
+        let index = self.new_lookup(Kind::Multiple);
+        self.gsub_lookups[index].rule = Substitution::Multiple(vec![subtable]);
+        index
+    }
+
+    /// Adds a ligature substitution lookup nested in a contextual lookup.
+    pub(crate) fn nested_ligature_subst(&mut self, subtable: LigatureSubst) -> usize {
+        let index = self.new_lookup(Kind::Ligature);
+        self.gsub_lookups[index].rule = Substitution::Ligature(vec![subtable]);
+        index
+    }
+
+    /// Adds an alternate substitution lookup nested in a contextual lookup.
+    pub(crate) fn nested_alternate_subst(&mut self, subtable: AlternateSubst) -> usize {
+        let index = self.new_lookup(Kind::Alternate);
+        self.gsub_lookups[index].rule = Substitution::Alternate(vec![subtable]);
+        index
+    }
+
+    /// Returns a single positioning lookup, nested in the current contextual
+    /// lookup, which can take the given values without conflicts.
+    pub(crate) fn nested_single_pos(&mut self, subtable: SinglePos) -> usize {
+        for &index in &self.nested_lookups {
+            if let Positioning::Single(v) = &mut self.gpos_lookups[index].rule {
+                let existing = v.last_mut().unwrap();
+                if subtable
+                    .mapping
+                    .iter()
+                    .all(|(k, v)| existing.mapping.get(k).is_none_or(|old| old == v))
+                {
+                    existing.mapping.extend(subtable.mapping);
+                    return index;
+                }
+            }
+        }
+        let index = self.new_lookup(Kind::SinglePos);
+        self.gpos_lookups[index].rule = Positioning::Single(vec![subtable]);
+        self.nested_lookups.push(index);
+        index
+    }
+
+    /// Builds the `aalt` lookups from the features it references.
+    fn build_aalt(&mut self) {
+        let aalt = match self.aalt.take() {
+            Some(aalt) => aalt,
+            None => return,
+        };
+        let mut alternates = aalt.alternates;
+        for feature in &aalt.features {
+            let lookups: BTreeSet<usize> = self
+                .features
+                .iter()
+                .filter(|((tag, _, _), _)| tag == feature)
+                .flat_map(|(_, lookups)| lookups.gsub.iter().copied())
+                .collect();
+            for index in lookups {
+                let mut add = |glyph: GlyphID, alts: &[GlyphID]| {
+                    let entry = alternates.entry(glyph).or_default();
+                    for alt in alts {
+                        if !entry.contains(alt) {
+                            entry.push(*alt);
+                        }
+                    }
+                };
+                match &self.gsub_lookups[index].rule {
+                    Substitution::Single(v) => {
+                        for subtable in v {
+                            for (glyph, alt) in &subtable.mapping {
+                                add(*glyph, &[*alt]);
+                            }
+                        }
+                    }
+                    Substitution::Alternate(v) => {
+                        for subtable in v {
+                            for (glyph, alts) in &subtable.mapping {
+                                add(*glyph, alts);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let mut single = SingleSubst::default();
+        let mut alternate = AlternateSubst::default();
+        for (glyph, alts) in alternates {
+            if alts.len() == 1 {
+                single.mapping.insert(glyph, alts[0]);
+            } else if !alts.is_empty() {
+                alternate.mapping.insert(glyph, alts);
+            }
+        }
+        self.feature = Some(AALT);
+        self.langsys = aalt.langsys;
+        self.flags = LookupFlags::empty();
+        self.mark_filtering_set = None;
+        if !single.mapping.is_empty() {
+            let index = self.new_lookup(Kind::Single);
+            self.gsub_lookups[index].rule = Substitution::Single(vec![single]);
+            self.register(LookupRef { gpos: false, index });
+        }
+        if !alternate.mapping.is_empty() {
+            let index = self.new_lookup(Kind::Alternate);
+            self.gsub_lookups[index].rule = Substitution::Alternate(vec![alternate]);
+            self.register(LookupRef { gpos: false, index });
+        }
+        self.feature = None;
+    }
+
+    /// Assembles the GSUB, GPOS and GDEF tables.
+    pub(crate) fn build(mut self) -> (Option<GSUB>, Option<GPOS>, Option<GDEF>) {
+        self.build_aalt();
+        let params = &self.feature_params;
+        let gsub_features = self
+            .features
+            .iter()
+            .filter(|((tag, _, _), lookups)| {
+                !lookups.gsub.is_empty()
+                    || matches!(
+                        params.get(tag),
+                        Some(FeatureParams::StylisticSet(..))
+                            | Some(FeatureParams::CharacterVariant(_))
+                    )
+            })
+            .map(|(&key, lookups)| (key, lookups.gsub.clone()))
+            .collect();
+        let gpos_features = self
+            .features
+            .iter()
+            .filter(|((tag, _, _), lookups)| {
+                !lookups.gpos.is_empty() || matches!(params.get(tag), Some(FeatureParams::Size(_)))
+            })
+            .map(|(&key, lookups)| (key, lookups.gpos.clone()))
+            .collect();
+        let gsub = assemble(
+            std::mem::take(&mut self.gsub_lookups),
+            gsub_features,
+            &self.required,
+            params,
+        );
+        let gpos = assemble(
+            std::mem::take(&mut self.gpos_lookups),
+            gpos_features,
+            &self.required,
+            params,
+        );
+        let gdef = self.build_gdef();
+        (gsub, gpos, gdef)
+    }
+
+    fn build_gdef(self) -> Option<GDEF> {
+        let glyph_class = match self.glyph_class_def {
+            Some(classes) => classes,
+            None => {
+                let mut classes = BTreeMap::new();
+                for glyph in self.base_glyphs {
+                    classes.insert(glyph, GlyphClass::BaseGlyph);
+                }
+                for glyph in self.ligature_glyphs {
+                    classes.insert(glyph, GlyphClass::LigatureGlyph);
+                }
+                for glyph in self.mark_classes.values().flat_map(|class| class.keys()) {
+                    classes.insert(*glyph, GlyphClass::MarkGlyph);
+                }
+                classes
+            }
+        };
+        let mut mark_attachment_class = BTreeMap::new();
+        for (ix, class) in self.mark_attachment_classes.iter().enumerate() {
+            for glyph in class {
+                mark_attachment_class.insert(*glyph, ix as u16 + 1);
+            }
+        }
+        if glyph_class.is_empty()
+            && self.attachment_points.is_empty()
+            && self.ligature_carets.is_empty()
+            && mark_attachment_class.is_empty()
+            && self.mark_glyph_sets.is_empty()
+        {
+            return None;
+        }
+        Some(GDEF {
+            glyph_class,
+            attachment_point_list: self.attachment_points,
+            ligature_caret_list: self.ligature_carets,
+            mark_attachment_class,
+            mark_glyph_sets: if self.mark_glyph_sets.is_empty() {
+                None
+            } else {
+                Some(self.mark_glyph_sets)
+            },
+            item_variation_store: None,
+        })
+    }
+}
+
+/// Builds a layout table from its lookups and the lookups registered for
+/// each (feature, script, language) triple.
+fn assemble<T>(
+    lookups: Vec<Lookup<T>>,
+    features: BTreeMap<(Tag, Tag, Tag), Vec<usize>>,
+    required: &BTreeSet<(Tag, Tag, Tag)>,
+    params: &BTreeMap<Tag, FeatureParams>,
+) -> Option<GPOSGSUB<T>> {
+    if lookups.is_empty() && features.is_empty() {
+        return None;
+    }
+    let mut table = GPOSGSUB {
+        lookups,
+        ..Default::default()
+    };
+    let mut unique: BTreeMap<(Tag, Vec<usize>), usize> = BTreeMap::new();
+    let features: Vec<((Tag, Tag, Tag), Vec<usize>)> = features
+        .into_iter()
+        .map(|(key, mut lookups)| {
+            lookups.sort_unstable();
+            lookups.dedup();
+            unique.insert((key.0, lookups.clone()), 0);
+            (key, lookups)
+        })
+        .collect();
+    for (ix, ((tag, lookups), index)) in unique.iter_mut().enumerate() {
+        *index = ix;
+        table
+            .features
+            .push((*tag, lookups.clone(), params.get(tag).cloned()));
+    }
+    for ((tag, script_tag, language), lookups) in features {
+        let index = unique[&(tag, lookups)];
+        let script = table
+            .scripts
+            .scripts
+            .entry(script_tag)
+            .or_insert_with(Script::default);
+        let langsys = if language == DFLT_LANGUAGE {
+            script
+                .default_language_system
+                .get_or_insert_with(empty_language_system)
+        } else {
+            script
+                .language_systems
+                .entry(language)
+                .or_insert_with(empty_language_system)
+        };
+        if required.contains(&(tag, script_tag, language)) {
+            langsys.required_feature = Some(index);
+        } else {
+            langsys.feature_indices.push(index);
+            langsys.feature_indices.sort_unstable();
+        }
+    }
+    Some(table)
+}
+
+fn empty_language_system() -> LanguageSystem {
+    LanguageSystem {
+        required_feature: None,
+        feature_indices: vec![],
+    }
+}
@@ -0,0 +1,181 @@
+use super::FeaError;
+
+/// A token in a feature file
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    /// A keyword or glyph name
+    Name(String),
+    /// A backslash-escaped glyph name, which is never a keyword
+    Glyph(String),
+    /// A backslash-escaped CID
+    Cid(u32),
+    /// A glyph class or mark class name, without its `@`
+    Class(String),
+    /// An integer
+    Number(i64),
+    /// A number with a decimal point
+    Float(f64),
+    /// A double-quoted string, without its quotes
+    String(String),
+    /// Punctuation
+    Symbol(char),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Name(s) => write!(f, "{}", s),
+            Token::Glyph(s) => write!(f, "\\{}", s),
+            Token::Cid(c) => write!(f, "\\{}", c),
+            Token::Class(s) => write!(f, "@{}", s),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Float(n) => write!(f, "{}", n),
+            Token::String(s) => write!(f, "\"{}\"", s),
+            Token::Symbol(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || "_.*+:^|~!".contains(c)
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.*+:^|~!-/".contains(c)
+}
+
+fn take_while(chars: &[char], mut i: usize, pred: impl Fn(char) -> bool) -> usize {
+    while i < chars.len() && pred(chars[i]) {
+        i += 1;
+    }
+    i
+}
+
+/// Splits a feature file into tokens, each with its line number.
+pub(crate) fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, FeaError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '#' {
+            i = take_while(&chars, i, |c| c != '\n');
+        } else if c == '"' {
+            let end = take_while(&chars, i + 1, |c| c != '"');
+            if end == chars.len() {
+                return Err(FeaError::new(line, "Unterminated string"));
+            }
+            let string: String = chars[i + 1..end].iter().collect();
+            line += string.matches('\n').count();
+            tokens.push((Token::String(string.replace('\n', "")), line));
+            i = end + 1;
+        } else if c == '@' {
+            let end = take_while(&chars, i + 1, is_name_char);
+            tokens.push((Token::Class(chars[i + 1..end].iter().collect()), line));
+            i = end;
+        } else if c == '\\' {
+            if chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) {
+                let end = take_while(&chars, i + 1, |c| c.is_ascii_digit());
+                let cid: String = chars[i + 1..end].iter().collect();
+                let cid = cid.parse().map_err(|_| FeaError::new(line, "Bad CID"))?;
+                tokens.push((Token::Cid(cid), line));
+                i = end;
+            } else {
+                let end = take_while(&chars, i + 1, is_name_char);
+                tokens.push((Token::Glyph(chars[i + 1..end].iter().collect()), line));
+                i = end;
+            }
+        } else if c.is_ascii_digit()
+            || (c == '-' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()))
+        {
+            if c == '0' && matches!(chars.get(i + 1), Some('x') | Some('X')) {
+                let end = take_while(&chars, i + 2, |c| c.is_ascii_hexdigit());
+                let hex: String = chars[i + 2..end].iter().collect();
+                let number = i64::from_str_radix(&hex, 16)
+                    .map_err(|_| FeaError::new(line, "Bad hexadecimal number"))?;
+                tokens.push((Token::Number(number), line));
+                i = end;
+                continue;
+            }
+            let end = take_while(&chars, i + 1, |c| c.is_ascii_digit() || c == '.');
+            let number: String = chars[i..end].iter().collect();
+            let token = if number.contains('.') {
+                Token::Float(
+                    number
+                        .parse()
+                        .map_err(|_| FeaError::new(line, "Bad number"))?,
+                )
+            } else {
+                Token::Number(
+                    number
+                        .parse()
+                        .map_err(|_| FeaError::new(line, "Bad number"))?,
+                )
+            };
+            tokens.push((token, line));
+            i = end;
+        } else if is_name_start(c) {
+            let end = take_while(&chars, i, is_name_char);
+            tokens.push((Token::Name(chars[i..end].iter().collect()), line));
+            i = end;
+        } else if ";,{}[]<>'=-()".contains(c) {
+            tokens.push((Token::Symbol(c), line));
+            i += 1;
+        } else {
+            return Err(FeaError::new(
+                line,
+                &format!("Unexpected character '{}'", c),
+            ));
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let tokens: Vec<Token> =
+            tokenize("@Caps = [A-Z \\sub]; # comment\nsub f' i by f_i;\npos A <0 -20 0x10 1.5>;")
+                .unwrap()
+                .into_iter()
+                .map(|(token, _)| token)
+                .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Class("Caps".to_string()),
+                Token::Symbol('='),
+                Token::Symbol('['),
+                Token::Name("A-Z".to_string()),
+                Token::Glyph("sub".to_string()),
+                Token::Symbol(']'),
+                Token::Symbol(';'),
+                Token::Name("sub".to_string()),
+                Token::Name("f".to_string()),
+                Token::Symbol('\''),
+                Token::Name("i".to_string()),
+                Token::Name("by".to_string()),
+                Token::Name("f_i".to_string()),
+                Token::Symbol(';'),
+                Token::Name("pos".to_string()),
+                Token::Name("A".to_string()),
+                Token::Symbol('<'),
+                Token::Number(0),
+                Token::Number(-20),
+                Token::Number(16),
+                Token::Float(1.5),
+                Token::Symbol('>'),
+                Token::Symbol(';'),
+            ]
+        );
+    }
+}
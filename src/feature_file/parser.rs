@@ -0,0 +1,1819 @@
+use super::builder::{Builder, Kind, LookupRef};
+use super::lexer::{tokenize, Token};
+use super::{FeaError, FeatureTables, TableField};
+use crate::layout::common::{LookupFlags, ValueRecord};
+use crate::layout::contextual::{ChainedSequenceContextRule, Slot};
+use crate::layout::feature_params::{
+    CharacterVariantParams, FeatureParams, SizeParams, StylisticSetParams,
+};
+use crate::layout::gpos1::SinglePos;
+use crate::layout::gsub2::MultipleSubst;
+use crate::layout::gsub3::AlternateSubst;
+use crate::layout::gsub4::LigatureSubst;
+use crate::layout::gsub8::ReverseChainSubst;
+use crate::tables::name::NameRecord;
+use crate::tables::GDEF::{CaretValue, GlyphClass};
+use crate::tables::STAT::{AxisRecord, AxisValue, AxisValueFlags, STAT};
+use crate::tag;
+use otspec::layout::anchor::Anchor;
+use otspec::layout::common::is_numbered_feature;
+use otspec::types::*;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+
+/// Code page numbers and their bits in the `OS/2` code page range fields
+const CODE_PAGES: [(u16, u8); 31] = [
+    (1252, 0),
+    (1250, 1),
+    (1251, 2),
+    (1253, 3),
+    (1254, 4),
+    (1255, 5),
+    (1256, 6),
+    (1257, 7),
+    (1258, 8),
+    (874, 16),
+    (932, 17),
+    (936, 18),
+    (949, 19),
+    (950, 20),
+    (1361, 21),
+    (869, 48),
+    (866, 49),
+    (865, 50),
+    (864, 51),
+    (863, 52),
+    (862, 53),
+    (861, 54),
+    (860, 55),
+    (857, 56),
+    (855, 57),
+    (852, 58),
+    (775, 59),
+    (737, 60),
+    (708, 61),
+    (850, 62),
+    (437, 63),
+];
+
+const VERTICAL_FEATURES: [Tag; 4] = [tag!("vkrn"), tag!("vpal"), tag!("vhal"), tag!("valt")];
+
+/// One element of a rule: a glyph class, possibly marked, with the lookups
+/// and value record which follow it
+struct Item {
+    glyphs: Vec<GlyphID>,
+    is_class: bool,
+    marked: bool,
+    lookups: Vec<String>,
+    value: Option<ValueRecord>,
+}
+
+impl Item {
+    fn slot(&self) -> Slot {
+        self.glyphs.iter().copied().collect()
+    }
+}
+
+/// An axis value in a `STAT` block, whose axis tags are resolved at the
+/// end of the block
+struct PendingAxisValue {
+    locations: Vec<(Tag, Vec<f32>)>,
+    flags: AxisValueFlags,
+    name_id: u16,
+}
+
+fn at(line: usize) -> impl Fn(String) -> FeaError {
+    move |message| FeaError::new(line, &message)
+}
+
+/// Replaces the middle of a glyph name, keeping its first `prefix` and
+/// last `suffix` characters.
+fn splice(name: &[char], prefix: usize, suffix: usize, middle: &str) -> String {
+    name[..prefix]
+        .iter()
+        .copied()
+        .chain(middle.chars())
+        .chain(name[name.len() - suffix..].iter().copied())
+        .collect()
+}
+
+/// Expands a glyph range such as `a-z`, `a.sc-z.sc` or `cid00001-cid00010`
+/// into glyph names.
+fn range_names(start: &str, end: &str) -> Option<Vec<String>> {
+    let start: Vec<char> = start.chars().collect();
+    let end: Vec<char> = end.chars().collect();
+    let mut prefix = start.iter().zip(&end).take_while(|(a, b)| a == b).count();
+    let max_suffix = start.len().min(end.len()) - prefix;
+    let mut suffix = start
+        .iter()
+        .rev()
+        .zip(end.iter().rev())
+        .take(max_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (
+        &start[prefix..start.len() - suffix],
+        &end[prefix..end.len() - suffix],
+    );
+    if a.len() == 1 && b.len() == 1 && a[0].is_ascii_alphabetic() && b[0].is_ascii_alphabetic() {
+        if a[0] > b[0] || a[0].is_ascii_uppercase() != b[0].is_ascii_uppercase() {
+            return None;
+        }
+        return Some(
+            (a[0]..=b[0])
+                .map(|c| splice(&start, prefix, suffix, &c.to_string()))
+                .collect(),
+        );
+    }
+    while prefix > 0 && start[prefix - 1].is_ascii_digit() {
+        prefix -= 1;
+    }
+    while suffix > 0 && start[start.len() - suffix].is_ascii_digit() {
+        suffix -= 1;
+    }
+    let (a, b): (String, String) = (
+        start[prefix..start.len() - suffix].iter().collect(),
+        end[prefix..end.len() - suffix].iter().collect(),
+    );
+    if a.len() != b.len() || !a.chars().chain(b.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (first, last): (u32, u32) = (a.parse().ok()?, b.parse().ok()?);
+    if first > last {
+        return None;
+    }
+    let width = a.len();
+    Some(
+        (first..=last)
+            .map(|n| {
+                splice(
+                    &start,
+                    prefix,
+                    suffix,
+                    &format!("{:0width$}", n, width = width),
+                )
+            })
+            .collect(),
+    )
+}
+
+/// Maps each glyph of a class to the corresponding glyph of a replacement
+/// class, or to a single replacement glyph.
+fn single_mapping(from: &[GlyphID], to: &[GlyphID]) -> Result<BTreeMap<GlyphID, GlyphID>, String> {
+    if to.len() == 1 {
+        Ok(from.iter().map(|&g| (g, to[0])).collect())
+    } else if to.len() == from.len() {
+        Ok(from.iter().copied().zip(to.iter().copied()).collect())
+    } else {
+        Err(format!(
+            "Cannot substitute {} glyphs with {} glyphs",
+            from.len(),
+            to.len()
+        ))
+    }
+}
+
+/// The replacement sequence of a multiple substitution
+fn replacement_sequence(by: &[Vec<GlyphID>]) -> Result<Vec<GlyphID>, String> {
+    by.iter()
+        .map(|glyphs| {
+            if glyphs.len() == 1 {
+                Ok(glyphs[0])
+            } else {
+                Err("Multiple substitution replacements must be single glyphs".to_string())
+            }
+        })
+        .collect()
+}
+
+/// All the glyph sequences matched by a sequence of glyph classes
+fn sequences(classes: &[&[GlyphID]]) -> Vec<Vec<GlyphID>> {
+    let mut sequences = vec![vec![]];
+    for class in classes {
+        sequences = sequences
+            .into_iter()
+            .flat_map(|sequence| {
+                class.iter().map(move |&g| {
+                    let mut sequence = sequence.clone();
+                    sequence.push(g);
+                    sequence
+                })
+            })
+            .collect();
+    }
+    sequences
+}
+
+/// The backtrack, input and lookahead items of a contextual rule
+type Context = (Vec<Item>, Vec<Item>, Vec<Item>);
+
+/// Splits a contextual rule into its backtrack, input and lookahead items.
+fn split_context(mut items: Vec<Item>) -> Result<Context, String> {
+    if !items.iter().any(|item| item.marked) {
+        for item in items.iter_mut() {
+            item.marked = true;
+        }
+    }
+    let first = items.iter().position(|item| item.marked).unwrap();
+    let last = items.iter().rposition(|item| item.marked).unwrap();
+    if items[first..=last].iter().any(|item| !item.marked) {
+        return Err("Marked glyphs must be contiguous".to_string());
+    }
+    let lookahead = items.split_off(last + 1);
+    let input = items.split_off(first);
+    if items
+        .iter()
+        .chain(lookahead.iter())
+        .any(|item| !item.lookups.is_empty() || item.value.is_some())
+    {
+        return Err("Lookups and values can only follow marked glyphs".to_string());
+    }
+    Ok((items, input, lookahead))
+}
+
+/// Decodes the backslash escapes of a name string: four hex digits for the
+/// Windows platform, two for the Macintosh platform.
+fn decode_string(string: &str, platform: u16) -> Result<String, String> {
+    let width = if platform == 1 { 2 } else { 4 };
+    let chars: Vec<char> = string.chars().collect();
+    let mut units: Vec<u16> = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '\\' {
+            let hex: String = chars.iter().skip(i + 1).take(width).collect();
+            let unit = u16::from_str_radix(&hex, 16)
+                .map_err(|_| format!("Bad escape in string \"{}\"", string))?;
+            units.push(unit);
+            i += width + 1;
+        } else {
+            let mut buf = [0; 2];
+            units.extend_from_slice(chars[i].encode_utf16(&mut buf));
+            i += 1;
+        }
+    }
+    String::from_utf16(&units).map_err(|_| format!("Bad string \"{}\"", string))
+}
+
+/// A feature file parser, which passes the statements to a [`Builder`]
+/// as they are read.
+pub(crate) struct Parser<'a> {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    glyph_ids: HashMap<&'a str, GlyphID>,
+    glyph_classes: BTreeMap<String, Vec<GlyphID>>,
+    anchors: BTreeMap<String, Anchor>,
+    value_records: BTreeMap<String, ValueRecord>,
+    builder: Builder,
+    names: Vec<NameRecord>,
+    next_name_id: u16,
+    size_name_id: Option<u16>,
+    fields: Vec<TableField>,
+    stat: Option<STAT>,
+}
+
+impl<'a> Parser<'a> {
+    /// Creates a parser for a feature file, allocating name IDs from the
+    /// given ID onwards.
+    pub(crate) fn new(
+        source: &str,
+        glyph_names: &'a [String],
+        first_name_id: u16,
+    ) -> Result<Self, FeaError> {
+        Ok(Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+            glyph_ids: glyph_names
+                .iter()
+                .enumerate()
+                .map(|(gid, name)| (name.as_str(), gid as GlyphID))
+                .collect(),
+            glyph_classes: BTreeMap::new(),
+            anchors: BTreeMap::new(),
+            value_records: BTreeMap::new(),
+            builder: Builder::default(),
+            names: vec![],
+            next_name_id: first_name_id,
+            size_name_id: None,
+            fields: vec![],
+            stat: None,
+        })
+    }
+
+    /// Parses the feature file and builds its tables.
+    pub(crate) fn parse(mut self) -> Result<FeatureTables, FeaError> {
+        while let Some(token) = self.peek().cloned() {
+            match token {
+                Token::Class(_) => self.glyph_class_definition()?,
+                Token::Symbol(';') => self.pos += 1,
+                Token::Name(ref name) => match name.as_str() {
+                    "languagesystem" => self.language_system()?,
+                    "feature" => self.feature_block()?,
+                    "lookup" => self.lookup_block()?,
+                    "markClass" => self.mark_class()?,
+                    "anchorDef" => self.anchor_definition()?,
+                    "valueRecordDef" => self.value_record_definition()?,
+                    "table" => self.table_block()?,
+                    "include" => return Err(self.err("include statements are not supported")),
+                    _ => return Err(self.unexpected(&token)),
+                },
+                _ => return Err(self.unexpected(&token)),
+            }
+        }
+        if let (Some(name_id), Some(FeatureParams::Size(params))) = (
+            self.size_name_id,
+            self.builder.feature_params.get_mut(&tag!("size")),
+        ) {
+            params.subfamily_name_id = name_id;
+        }
+        let (gsub, gpos, gdef) = self.builder.build();
+        Ok(FeatureTables {
+            gsub,
+            gpos,
+            gdef,
+            stat: self.stat,
+            names: self.names,
+            fields: self.fields,
+        })
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map_or(1, |(_, line)| *line)
+    }
+
+    fn err(&self, message: &str) -> FeaError {
+        FeaError::new(self.line(), message)
+    }
+
+    fn unexpected(&self, token: &Token) -> FeaError {
+        self.err(&format!("Unexpected {}", token))
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Result<Token, FeaError> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| self.err("Unexpected end of file"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn is_symbol(&self, symbol: char) -> bool {
+        self.peek() == Some(&Token::Symbol(symbol))
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Name(name)) if name == keyword)
+    }
+
+    fn eat_symbol(&mut self, symbol: char) -> bool {
+        let found = self.is_symbol(symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<(), FeaError> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.expected(&format!("'{}'", symbol)))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), FeaError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.expected(keyword))
+        }
+    }
+
+    fn expected(&self, what: &str) -> FeaError {
+        match self.peek() {
+            Some(token) => self.err(&format!("Expected {}, found {}", what, token)),
+            None => self.err(&format!("Expected {}, found end of file", what)),
+        }
+    }
+
+    fn expect_name(&mut self) -> Result<String, FeaError> {
+        match self.peek().cloned() {
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.expected("a name")),
+        }
+    }
+
+    fn expect_class_name(&mut self) -> Result<String, FeaError> {
+        match self.peek().cloned() {
+            Some(Token::Class(name)) => {
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.expected("a class name")),
+        }
+    }
+
+    fn expect_string(&mut self) -> Result<String, FeaError> {
+        match self.peek().cloned() {
+            Some(Token::String(string)) => {
+                self.pos += 1;
+                Ok(string)
+            }
+            _ => Err(self.expected("a string")),
+        }
+    }
+
+    fn expect_number(&mut self) -> Result<i64, FeaError> {
+        match self.peek().cloned() {
+            Some(Token::Number(number)) => {
+                self.pos += 1;
+                Ok(number)
+            }
+            _ => Err(self.expected("a number")),
+        }
+    }
+
+    fn expect_float(&mut self) -> Result<f64, FeaError> {
+        match self.peek().cloned() {
+            Some(Token::Number(number)) => {
+                self.pos += 1;
+                Ok(number as f64)
+            }
+            Some(Token::Float(number)) => {
+                self.pos += 1;
+                Ok(number)
+            }
+            _ => Err(self.expected("a number")),
+        }
+    }
+
+    fn expect_i16(&mut self) -> Result<i16, FeaError> {
+        let number = self.expect_number()?;
+        i16::try_from(number).map_err(|_| self.err(&format!("{} is out of range", number)))
+    }
+
+    fn expect_u16(&mut self) -> Result<u16, FeaError> {
+        let number = self.expect_number()?;
+        u16::try_from(number).map_err(|_| self.err(&format!("{} is out of range", number)))
+    }
+
+    /// Reads a size in decipoints: integers are decipoints, and numbers
+    /// with a decimal point are points.
+    fn expect_decipoints(&mut self) -> Result<u16, FeaError> {
+        let size = match self.next()? {
+            Token::Number(number) => number as f64,
+            Token::Float(number) => (number * 10.0).round(),
+            token => return Err(self.unexpected(&token)),
+        };
+        if !(0.0..=65535.0).contains(&size) {
+            return Err(self.err(&format!("{} is out of range", size)));
+        }
+        Ok(size as u16)
+    }
+
+    fn expect_tag(&mut self) -> Result<Tag, FeaError> {
+        let name = self.expect_name()?;
+        Tag::from_raw(&name).map_err(|_| self.err(&format!("Bad tag {}", name)))
+    }
+
+    fn glyph(&self, name: &str) -> Result<GlyphID, FeaError> {
+        self.glyph_ids
+            .get(name)
+            .copied()
+            .ok_or_else(|| self.err(&format!("Unknown glyph {}", name)))
+    }
+
+    fn glyph_name(&self, token: &Token) -> Result<String, FeaError> {
+        match token {
+            Token::Name(name) | Token::Glyph(name) => Ok(name.clone()),
+            Token::Cid(cid) => Ok(format!("cid{:05}", cid)),
+            _ => Err(self.unexpected(token)),
+        }
+    }
+
+    fn range(&self, start: &str, end: &str) -> Result<Vec<GlyphID>, FeaError> {
+        range_names(start, end)
+            .ok_or_else(|| self.err(&format!("Bad glyph range {}-{}", start, end)))?
+            .iter()
+            .map(|name| self.glyph(name))
+            .collect()
+    }
+
+    /// Resolves a bare name, which may be a glyph name containing hyphens
+    /// or a glyph range written without spaces.
+    fn glyph_or_range(&self, name: &str) -> Result<(Vec<GlyphID>, bool), FeaError> {
+        if let Some(&gid) = self.glyph_ids.get(name) {
+            return Ok((vec![gid], false));
+        }
+        for (ix, _) in name.match_indices('-') {
+            let (start, end) = (&name[..ix], &name[ix + 1..]);
+            if self.glyph_ids.contains_key(start) && self.glyph_ids.contains_key(end) {
+                return Ok((self.range(start, end)?, true));
+            }
+        }
+        Err(self.err(&format!("Unknown glyph {}", name)))
+    }
+
+    fn named_class(&self, name: &str) -> Result<Vec<GlyphID>, FeaError> {
+        if let Some(glyphs) = self.glyph_classes.get(name) {
+            Ok(glyphs.clone())
+        } else if let Some(marks) = self.builder.mark_classes.get(name) {
+            Ok(marks.keys().copied().collect())
+        } else {
+            Err(self.err(&format!("Unknown glyph class @{}", name)))
+        }
+    }
+
+    /// Reads a glyph or glyph class, returning its glyphs and whether it
+    /// was written as a class.
+    fn glyph_class(&mut self) -> Result<(Vec<GlyphID>, bool), FeaError> {
+        match self.next()? {
+            Token::Symbol('[') => {
+                let mut glyphs = vec![];
+                loop {
+                    match self.next()? {
+                        Token::Symbol(']') => break,
+                        Token::Class(name) => glyphs.extend(self.named_class(&name)?),
+                        token @ (Token::Name(_) | Token::Glyph(_) | Token::Cid(_)) => {
+                            let first = self.glyph_name(&token)?;
+                            if self.eat_symbol('-') {
+                                let token = self.next()?;
+                                let last = self.glyph_name(&token)?;
+                                glyphs.extend(self.range(&first, &last)?);
+                            } else if let Token::Name(name) = token {
+                                glyphs.extend(self.glyph_or_range(&name)?.0);
+                            } else {
+                                glyphs.push(self.glyph(&first)?);
+                            }
+                        }
+                        token => return Err(self.unexpected(&token)),
+                    }
+                }
+                Ok((glyphs, true))
+            }
+            Token::Class(name) => Ok((self.named_class(&name)?, true)),
+            Token::Name(name) => self.glyph_or_range(&name),
+            token @ (Token::Glyph(_) | Token::Cid(_)) => {
+                let name = self.glyph_name(&token)?;
+                Ok((vec![self.glyph(&name)?], false))
+            }
+            token => Err(self.unexpected(&token)),
+        }
+    }
+
+    fn glyph_class_definition(&mut self) -> Result<(), FeaError> {
+        let name = self.expect_class_name()?;
+        self.expect_symbol('=')?;
+        let (glyphs, _) = self.glyph_class()?;
+        self.expect_symbol(';')?;
+        if self.builder.mark_classes.contains_key(&name) {
+            return Err(self.err(&format!("@{} is already a mark class", name)));
+        }
+        self.glyph_classes.insert(name, glyphs);
+        Ok(())
+    }
+
+    fn language_system(&mut self) -> Result<(), FeaError> {
+        self.expect_keyword("languagesystem")?;
+        let script = self.expect_tag()?;
+        let language = self.expect_tag()?;
+        self.expect_symbol(';')?;
+        let line = self.line();
+        self.builder
+            .add_language_system(script, language)
+            .map_err(at(line))
+    }
+
+    fn anchor_definition(&mut self) -> Result<(), FeaError> {
+        self.expect_keyword("anchorDef")?;
+        let x = self.expect_i16()?;
+        let y = self.expect_i16()?;
+        let mut anchor = Anchor::new(x, y);
+        if self.eat_keyword("contourpoint") {
+            anchor.anchorPoint = Some(self.expect_u16()?);
+        }
+        let name = self.expect_name()?;
+        self.expect_symbol(';')?;
+        self.anchors.insert(name, anchor);
+        Ok(())
+    }
+
+    fn value_record_definition(&mut self) -> Result<(), FeaError> {
+        self.expect_keyword("valueRecordDef")?;
+        let record = self.value_record()?;
+        let name = self.expect_name()?;
+        self.expect_symbol(';')?;
+        self.value_records.insert(name, record);
+        Ok(())
+    }
+
+    /// Reads an anchor, which is `None` for `<anchor NULL>`.
+    fn anchor(&mut self) -> Result<Option<Anchor>, FeaError> {
+        self.expect_symbol('<')?;
+        self.expect_keyword("anchor")?;
+        let anchor = match self.next()? {
+            Token::Name(name) if name == "NULL" => None,
+            Token::Name(name) => Some(
+                self.anchors
+                    .get(&name)
+                    .cloned()
+                    .ok_or_else(|| self.err(&format!("Unknown anchor {}", name)))?,
+            ),
+            Token::Number(_) => {
+                self.pos -= 1;
+                let x = self.expect_i16()?;
+                let y = self.expect_i16()?;
+                let mut anchor = Anchor::new(x, y);
+                if self.eat_keyword("contourpoint") {
+                    anchor.anchorPoint = Some(self.expect_u16()?);
+                }
+                if self.is_symbol('<') {
+                    return Err(self.err("Device tables are not supported"));
+                }
+                Some(anchor)
+            }
+            token => return Err(self.unexpected(&token)),
+        };
+        self.expect_symbol('>')?;
+        Ok(anchor)
+    }
+
+    /// Reads a value record: a single advance, or a `<...>` record.
+    fn value_record(&mut self) -> Result<ValueRecord, FeaError> {
+        let vertical = self
+            .builder
+            .feature()
+            .is_some_and(|tag| VERTICAL_FEATURES.contains(&tag));
+        let advance = |value: i16| {
+            let mut record = ValueRecord::new();
+            if vertical {
+                record.yAdvance = Some(value);
+            } else {
+                record.xAdvance = Some(value);
+            }
+            record
+        };
+        if let Some(Token::Number(_)) = self.peek() {
+            return Ok(advance(self.expect_i16()?));
+        }
+        self.expect_symbol('<')?;
+        let record = match self.next()? {
+            Token::Name(name) if name == "NULL" => ValueRecord::new(),
+            Token::Name(name) => self
+                .value_records
+                .get(&name)
+                .cloned()
+                .ok_or_else(|| self.err(&format!("Unknown value record {}", name)))?,
+            Token::Number(_) => {
+                self.pos -= 1;
+                let first = self.expect_i16()?;
+                if self.is_symbol('>') {
+                    advance(first)
+                } else {
+                    let mut record = ValueRecord::new();
+                    record.xPlacement = Some(first);
+                    record.yPlacement = Some(self.expect_i16()?);
+                    record.xAdvance = Some(self.expect_i16()?);
+                    record.yAdvance = Some(self.expect_i16()?);
+                    if self.is_symbol('<') {
+                        return Err(self.err("Device tables are not supported"));
+                    }
+                    record
+                }
+            }
+            token => return Err(self.unexpected(&token)),
+        };
+        self.expect_symbol('>')?;
+        Ok(record)
+    }
+
+    fn mark_class(&mut self) -> Result<(), FeaError> {
+        self.expect_keyword("markClass")?;
+        let (glyphs, _) = self.glyph_class()?;
+        let anchor = self
+            .anchor()?
+            .ok_or_else(|| self.err("Mark classes cannot have NULL anchors"))?;
+        let name = self.expect_class_name()?;
+        self.expect_symbol(';')?;
+        if self.glyph_classes.contains_key(&name) {
+            return Err(self.err(&format!("@{} is already a glyph class", name)));
+        }
+        let class = self.builder.mark_classes.entry(name).or_default();
+        for glyph in glyphs {
+            class.insert(glyph, anchor.clone());
+        }
+        Ok(())
+    }
+
+    fn feature_block(&mut self) -> Result<(), FeaError> {
+        self.expect_keyword("feature")?;
+        let tag = self.expect_tag()?;
+        self.eat_keyword("useExtension");
+        self.expect_symbol('{')?;
+        self.builder.start_feature(tag);
+        while !self.eat_symbol('}') {
+            self.statement()?;
+        }
+        if self.expect_tag()? != tag {
+            return Err(self.err(&format!("Expected {}", tag.as_ref() as &str)));
+        }
+        self.expect_symbol(';')?;
+        self.builder.end_feature();
+        Ok(())
+    }
+
+    fn lookup_block(&mut self) -> Result<(), FeaError> {
+        self.expect_keyword("lookup")?;
+        let name = self.expect_name()?;
+        if self.builder.feature().is_some() && self.eat_symbol(';') {
+            let line = self.line();
+            return self.builder.reference_lookup(&name).map_err(at(line));
+        }
+        self.eat_keyword("useExtension");
+        self.expect_symbol('{')?;
+        let line = self.line();
+        self.builder.start_lookup(&name).map_err(at(line))?;
+        while !self.eat_symbol('}') {
+            self.statement()?;
+        }
+        if self.expect_name()? != name {
+            return Err(self.err(&format!("Expected {}", name)));
+        }
+        self.expect_symbol(';')?;
+        self.builder.end_lookup_block();
+        Ok(())
+    }
+
+    /// Parses a statement inside a feature or lookup block.
+    fn statement(&mut self) -> Result<(), FeaError> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| self.err("Unexpected end of file"))?;
+        let line = self.line();
+        match token {
+            Token::Class(_) => self.glyph_class_definition(),
+            Token::Symbol(';') => {
+                self.pos += 1;
+                Ok(())
+            }
+            Token::Name(ref name) => match name.as_str() {
+                "sub" | "substitute" | "rsub" | "reversesub" => self.substitution(),
+                "pos" | "position" => self.positioning(false),
+                "enum" | "enumerate" => {
+                    self.pos += 1;
+                    self.positioning(true)
+                }
+                "ignore" => self.ignore(),
+                "lookupflag" => self.lookup_flags(),
+                "lookup" => self.lookup_block(),
+                "markClass" => self.mark_class(),
+                "subtable" => {
+                    self.pos += 1;
+                    self.expect_symbol(';')?;
+                    self.builder.add_subtable_break();
+                    Ok(())
+                }
+                "script" => {
+                    self.pos += 1;
+                    let script = self.expect_tag()?;
+                    self.expect_symbol(';')?;
+                    self.builder.set_script(script).map_err(at(line))
+                }
+                "language" => {
+                    self.pos += 1;
+                    let language = self.expect_tag()?;
+                    let mut include_default = true;
+                    if self.eat_keyword("exclude_dflt") || self.eat_keyword("excludeDFLT") {
+                        include_default = false;
+                    } else if !self.eat_keyword("include_dflt") {
+                        self.eat_keyword("includeDFLT");
+                    }
+                    let required = self.eat_keyword("required");
+                    self.expect_symbol(';')?;
+                    self.builder
+                        .set_language(language, include_default, required)
+                        .map_err(at(line))
+                }
+                "feature" => {
+                    self.pos += 1;
+                    let feature = self.expect_tag()?;
+                    self.expect_symbol(';')?;
+                    if self.builder.feature() != Some(tag!("aalt")) {
+                        return Err(self.err("Feature references are only allowed in aalt"));
+                    }
+                    self.builder.add_aalt_feature(feature);
+                    Ok(())
+                }
+                "featureNames" => self.feature_names(),
+                "cvParameters" => self.cv_parameters(),
+                "parameters" => self.size_parameters(),
+                "sizemenuname" => {
+                    self.pos += 1;
+                    let name_id = match self.size_name_id {
+                        Some(name_id) => name_id,
+                        None => {
+                            let name_id = self.allocate_name_id();
+                            self.size_name_id = Some(name_id);
+                            name_id
+                        }
+                    };
+                    self.name_record(name_id)
+                }
+                _ => Err(self.unexpected(&token)),
+            },
+            _ => Err(self.unexpected(&token)),
+        }
+    }
+
+    /// Reads the glyph classes of a rule up to `by`, `from`, `,` or `;`,
+    /// with their marks, lookups and (for positioning rules) value records.
+    fn items(&mut self, positioning: bool) -> Result<Vec<Item>, FeaError> {
+        let mut items = vec![];
+        while !(self.is_symbol(';')
+            || self.is_symbol(',')
+            || self.is_keyword("by")
+            || self.is_keyword("from"))
+        {
+            let (glyphs, is_class) = self.glyph_class()?;
+            let marked = self.eat_symbol('\'');
+            let mut lookups = vec![];
+            while self.eat_keyword("lookup") {
+                lookups.push(self.expect_name()?);
+            }
+            let value = if positioning
+                && (matches!(self.peek(), Some(Token::Number(_))) || self.is_symbol('<'))
+            {
+                Some(self.value_record()?)
+            } else {
+                None
+            };
+            items.push(Item {
+                glyphs,
+                is_class,
+                marked,
+                lookups,
+                value,
+            });
+        }
+        if items.is_empty() {
+            return Err(self.expected("glyphs"));
+        }
+        Ok(items)
+    }
+
+    /// Resolves the named lookups of a contextual rule slot.
+    fn slot_lookups(&self, item: &Item, gpos: bool) -> Result<Vec<u16>, FeaError> {
+        item.lookups
+            .iter()
+            .map(|name| {
+                let LookupRef {
+                    gpos: is_gpos,
+                    index,
+                } = self.builder.named_lookup(name).map_err(|m| self.err(&m))?;
+                if is_gpos != gpos {
+                    return Err(self.err(&format!(
+                        "Lookup {} is not a {} lookup",
+                        name,
+                        if gpos { "GPOS" } else { "GSUB" }
+                    )));
+                }
+                Ok(index as u16)
+            })
+            .collect()
+    }
+
+    fn substitution(&mut self) -> Result<(), FeaError> {
+        let reverse =
+            matches!(self.next()?, Token::Name(name) if name == "rsub" || name == "reversesub");
+        let items = self.items(false)?;
+        let mut replacement: Option<Vec<Vec<GlyphID>>> = None;
+        let mut alternates: Option<Vec<GlyphID>> = None;
+        if self.eat_keyword("by") {
+            let mut by = vec![];
+            if !self.eat_keyword("NULL") {
+                while !self.is_symbol(';') {
+                    by.push(self.glyph_class()?.0);
+                }
+            }
+            replacement = Some(by);
+        } else if self.eat_keyword("from") {
+            let glyphs = self.glyph_class()?.0;
+            if glyphs.is_empty() {
+                return Err(self.err("Alternate substitutions need at least one alternate"));
+            }
+            alternates = Some(glyphs);
+        }
+        self.expect_symbol(';')?;
+        let line = self.line();
+        if reverse {
+            let (backtrack, input, lookahead) = split_context(items).map_err(at(line))?;
+            let by = match replacement {
+                Some(by) if by.len() == 1 && input.len() == 1 => by,
+                _ => return Err(self.err("Reverse substitutions must replace one glyph class")),
+            };
+            let subtable = ReverseChainSubst {
+                mapping: single_mapping(&input[0].glyphs, &by[0]).map_err(at(line))?,
+                backtrack: backtrack.iter().rev().map(Item::slot).collect(),
+                lookahead: lookahead.iter().map(Item::slot).collect(),
+            };
+            return self.builder.add_reverse_subst(subtable).map_err(at(line));
+        }
+
+        if !items.iter().any(|item| item.marked) {
+            if items.iter().any(|item| !item.lookups.is_empty()) {
+                return Err(self.err("Lookups can only follow marked glyphs"));
+            }
+            if self.builder.feature() == Some(tag!("aalt")) && !self.builder.in_lookup_block() {
+                let alternates = match (alternates, replacement) {
+                    (Some(alternates), _) => alternates,
+                    (None, Some(by)) if by.len() == 1 => by[0].clone(),
+                    _ => {
+                        return Err(
+                            self.err("Only single and alternate substitutions are allowed in aalt")
+                        )
+                    }
+                };
+                if items.len() != 1 {
+                    return Err(
+                        self.err("Only single and alternate substitutions are allowed in aalt")
+                    );
+                }
+                for glyph in &items[0].glyphs {
+                    self.builder.add_aalt_alternates(*glyph, &alternates);
+                }
+                return Ok(());
+            }
+            if let Some(alternates) = alternates {
+                if items.len() != 1 {
+                    return Err(self.err("Alternate substitutions must replace one glyph class"));
+                }
+                let subtable = self.builder.alternate_subst().map_err(at(line))?;
+                for glyph in &items[0].glyphs {
+                    subtable.mapping.insert(*glyph, alternates.clone());
+                }
+                return Ok(());
+            }
+            let by = replacement.ok_or_else(|| self.err("Expected 'by' or 'from'"))?;
+            match (items.len(), by.len()) {
+                (1, 1) => {
+                    let mapping = single_mapping(&items[0].glyphs, &by[0]).map_err(at(line))?;
+                    self.builder
+                        .single_subst()
+                        .map_err(at(line))?
+                        .mapping
+                        .extend(mapping);
+                }
+                (1, _) => {
+                    let sequence = replacement_sequence(&by).map_err(at(line))?;
+                    let subtable = self.builder.multiple_subst().map_err(at(line))?;
+                    for glyph in &items[0].glyphs {
+                        subtable.mapping.insert(*glyph, sequence.clone());
+                    }
+                }
+                (_, 1) => {
+                    if by[0].len() != 1 {
+                        return Err(self.err("Ligature substitutions must produce a single glyph"));
+                    }
+                    let classes: Vec<&[GlyphID]> =
+                        items.iter().map(|item| item.glyphs.as_slice()).collect();
+                    let subtable = self.builder.ligature_subst().map_err(at(line))?;
+                    for sequence in sequences(&classes) {
+                        subtable.mapping.entry(sequence).or_insert(by[0][0]);
+                    }
+                }
+                _ => return Err(self.err("Unsupported substitution")),
+            }
+            return Ok(());
+        }
+
+        let (backtrack, input, lookahead) = split_context(items).map_err(at(line))?;
+        self.builder.chain_subst().map_err(at(line))?;
+        let mut slots = vec![];
+        for item in &input {
+            slots.push((item.slot(), self.slot_lookups(item, false)?));
+        }
+        if (alternates.is_some() || replacement.is_some())
+            && input.iter().any(|item| !item.lookups.is_empty())
+        {
+            return Err(self.err("Rules cannot have both lookups and replacements"));
+        }
+        if let Some(alternates) = alternates {
+            if input.len() != 1 {
+                return Err(self.err("Alternate substitutions must replace one glyph class"));
+            }
+            let subtable = AlternateSubst {
+                mapping: input[0]
+                    .glyphs
+                    .iter()
+                    .map(|&g| (g, alternates.clone()))
+                    .collect(),
+            };
+            let index = self.builder.nested_alternate_subst(subtable);
+            slots[0].1.push(index as u16);
+        }
+        if let Some(by) = replacement {
+            let index = match (input.len(), by.len()) {
+                (1, 1) => {
+                    let mapping = single_mapping(&input[0].glyphs, &by[0]).map_err(at(line))?;
+                    self.builder
+                        .nested_single_subst(&mapping)
+                        .map_err(at(line))?
+                }
+                (1, _) => {
+                    let sequence = replacement_sequence(&by).map_err(at(line))?;
+                    let subtable = MultipleSubst {
+                        mapping: input[0]
+                            .glyphs
+                            .iter()
+                            .map(|&g| (g, sequence.clone()))
+                            .collect(),
+                    };
+                    self.builder.nested_multiple_subst(subtable)
+                }
+                (_, 1) if by[0].len() == 1 => {
+                    let classes: Vec<&[GlyphID]> =
+                        input.iter().map(|item| item.glyphs.as_slice()).collect();
+                    let subtable = LigatureSubst {
+                        mapping: sequences(&classes)
+                            .into_iter()
+                            .map(|sequence| (sequence, by[0][0]))
+                            .collect(),
+                    };
+                    self.builder.nested_ligature_subst(subtable)
+                }
+                _ => return Err(self.err("Unsupported contextual substitution")),
+            };
+            slots[0].1.push(index as u16);
+        }
+        let rule = ChainedSequenceContextRule {
+            backtrack: backtrack.iter().rev().map(Item::slot).collect(),
+            lookahead: lookahead.iter().map(Item::slot).collect(),
+            input: slots,
+        };
+        self.builder
+            .chain_subst()
+            .map_err(at(line))?
+            .rules
+            .push(rule);
+        Ok(())
+    }
+
+    fn ignore(&mut self) -> Result<(), FeaError> {
+        self.expect_keyword("ignore")?;
+        let gpos = match self.next()? {
+            Token::Name(name) if name == "sub" || name == "substitute" => false,
+            Token::Name(name) if name == "pos" || name == "position" => true,
+            token => return Err(self.unexpected(&token)),
+        };
+        let line = self.line();
+        loop {
+            let items = self.items(false)?;
+            let (backtrack, input, lookahead) = split_context(items).map_err(at(line))?;
+            if input.iter().any(|item| !item.lookups.is_empty()) {
+                return Err(self.err("Ignore rules cannot have lookups"));
+            }
+            let rule = ChainedSequenceContextRule {
+                backtrack: backtrack.iter().rev().map(Item::slot).collect(),
+                lookahead: lookahead.iter().map(Item::slot).collect(),
+                input: input.iter().map(|item| (item.slot(), vec![])).collect(),
+            };
+            let subtable = if gpos {
+                self.builder.chain_pos()
+            } else {
+                self.builder.chain_subst()
+            };
+            subtable.map_err(at(line))?.rules.push(rule);
+            if !self.eat_symbol(',') {
+                break;
+            }
+        }
+        self.expect_symbol(';')
+    }
+
+    fn positioning(&mut self, enumerate: bool) -> Result<(), FeaError> {
+        match self.next()? {
+            Token::Name(name) if name == "pos" || name == "position" => {}
+            token => return Err(self.unexpected(&token)),
+        }
+        if !enumerate {
+            if self.eat_keyword("cursive") {
+                return self.cursive_positioning();
+            } else if self.eat_keyword("base") {
+                return self.mark_attachment(Kind::MarkBase);
+            } else if self.eat_keyword("ligature") {
+                return self.mark_to_ligature();
+            } else if self.eat_keyword("mark") {
+                return self.mark_attachment(Kind::MarkMark);
+            }
+        }
+        let items = self.items(true)?;
+        self.expect_symbol(';')?;
+        let line = self.line();
+        if !items.iter().any(|item| item.marked) {
+            if items.iter().any(|item| !item.lookups.is_empty()) {
+                return Err(self.err("Lookups can only follow marked glyphs"));
+            }
+            match items.len() {
+                1 => {
+                    let value = items[0]
+                        .value
+                        .clone()
+                        .ok_or_else(|| self.err("Expected a value record"))?;
+                    let subtable = self.builder.single_pos().map_err(at(line))?;
+                    for glyph in &items[0].glyphs {
+                        subtable.mapping.insert(*glyph, value.clone());
+                    }
+                }
+                2 => {
+                    let (first, second) = match (&items[0].value, &items[1].value) {
+                        (None, Some(value)) => (value.clone(), ValueRecord::new()),
+                        (Some(first), second) => {
+                            (first.clone(), second.clone().unwrap_or_default())
+                        }
+                        (None, None) => return Err(self.err("Expected a value record")),
+                    };
+                    let specific = enumerate || !(items[0].is_class || items[1].is_class);
+                    let subtable = self.builder.pair_pos().map_err(at(line))?;
+                    for left in &items[0].glyphs {
+                        for right in &items[1].glyphs {
+                            let values = (first.clone(), second.clone());
+                            if specific {
+                                subtable.mapping.insert((*left, *right), values);
+                            } else {
+                                subtable.mapping.entry((*left, *right)).or_insert(values);
+                            }
+                        }
+                    }
+                }
+                _ => return Err(self.err("Unsupported positioning rule")),
+            }
+            return Ok(());
+        }
+
+        let (backtrack, input, lookahead) = split_context(items).map_err(at(line))?;
+        self.builder.chain_pos().map_err(at(line))?;
+        let mut slots = vec![];
+        for item in &input {
+            let mut lookups = self.slot_lookups(item, true)?;
+            if let Some(value) = &item.value {
+                let subtable = SinglePos {
+                    mapping: item.glyphs.iter().map(|&g| (g, value.clone())).collect(),
+                };
+                lookups.push(self.builder.nested_single_pos(subtable) as u16);
+            }
+            slots.push((item.slot(), lookups));
+        }
+        let rule = ChainedSequenceContextRule {
+            backtrack: backtrack.iter().rev().map(Item::slot).collect(),
+            lookahead: lookahead.iter().map(Item::slot).collect(),
+            input: slots,
+        };
+        self.builder.chain_pos().map_err(at(line))?.rules.push(rule);
+        Ok(())
+    }
+
+    fn cursive_positioning(&mut self) -> Result<(), FeaError> {
+        let (glyphs, _) = self.glyph_class()?;
+        let entry = self.anchor()?;
+        let exit = self.anchor()?;
+        self.expect_symbol(';')?;
+        let line = self.line();
+        let subtable = self.builder.cursive_pos().map_err(at(line))?;
+        for glyph in glyphs {
+            subtable
+                .mapping
+                .insert(glyph, (entry.clone(), exit.clone()));
+        }
+        Ok(())
+    }
+
+    /// Reads the `<anchor> mark @CLASS` pairs of a mark attachment rule.
+    fn mark_anchors(&mut self) -> Result<Vec<(Anchor, String)>, FeaError> {
+        let mut anchors = vec![];
+        while !self.is_symbol(';') && !self.is_keyword("ligComponent") {
+            let anchor = self.anchor()?;
+            match anchor {
+                Some(anchor) => {
+                    self.expect_keyword("mark")?;
+                    let class = self.expect_class_name()?;
+                    if !self.builder.mark_classes.contains_key(&class) {
+                        return Err(self.err(&format!("Unknown mark class @{}", class)));
+                    }
+                    anchors.push((anchor, class));
+                }
+                None if anchors.is_empty() => break,
+                None => return Err(self.err("Unexpected NULL anchor")),
+            }
+        }
+        Ok(anchors)
+    }
+
+    /// The glyphs of the given mark classes, with their class indices in
+    /// the current lookup and their anchors.
+    fn marks(&mut self, classes: &[String]) -> BTreeMap<GlyphID, (u16, Anchor)> {
+        let mut marks = BTreeMap::new();
+        for class in classes {
+            let index = self.builder.lookup_mark_class(class);
+            for (glyph, anchor) in &self.builder.mark_classes[class] {
+                marks.insert(*glyph, (index, anchor.clone()));
+            }
+        }
+        marks
+    }
+
+    fn mark_attachment(&mut self, kind: Kind) -> Result<(), FeaError> {
+        let (bases, _) = self.glyph_class()?;
+        let anchors = self.mark_anchors()?;
+        self.expect_symbol(';')?;
+        let line = self.line();
+        if kind == Kind::MarkBase {
+            self.builder.mark_base_pos().map_err(at(line))?;
+        } else {
+            self.builder.mark_mark_pos().map_err(at(line))?;
+        }
+        let classes: Vec<String> = anchors.iter().map(|(_, class)| class.clone()).collect();
+        let marks = self.marks(&classes);
+        let base_anchors: BTreeMap<u16, Anchor> = anchors
+            .into_iter()
+            .map(|(anchor, class)| (self.builder.lookup_mark_class(&class), anchor))
+            .collect();
+        if kind == Kind::MarkBase {
+            self.builder.add_base_glyphs(&bases);
+            let subtable = self.builder.mark_base_pos().map_err(at(line))?;
+            subtable.marks.extend(marks);
+            for base in bases {
+                subtable
+                    .bases
+                    .entry(base)
+                    .or_default()
+                    .extend(base_anchors.clone());
+            }
+        } else {
+            let subtable = self.builder.mark_mark_pos().map_err(at(line))?;
+            subtable.combining_marks.extend(marks);
+            for base in bases {
+                subtable
+                    .base_marks
+                    .entry(base)
+                    .or_default()
+                    .extend(base_anchors.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn mark_to_ligature(&mut self) -> Result<(), FeaError> {
+        let (ligatures, _) = self.glyph_class()?;
+        let mut components = vec![self.mark_anchors()?];
+        while self.eat_keyword("ligComponent") {
+            components.push(self.mark_anchors()?);
+        }
+        self.expect_symbol(';')?;
+        let line = self.line();
+        self.builder.mark_lig_pos().map_err(at(line))?;
+        let classes: Vec<String> = components
+            .iter()
+            .flatten()
+            .map(|(_, class)| class.clone())
+            .collect();
+        let marks = self.marks(&classes);
+        let component_anchors: Vec<BTreeMap<u16, Anchor>> = components
+            .into_iter()
+            .map(|anchors| {
+                anchors
+                    .into_iter()
+                    .map(|(anchor, class)| (self.builder.lookup_mark_class(&class), anchor))
+                    .collect()
+            })
+            .collect();
+        self.builder.add_ligature_glyphs(&ligatures);
+        let subtable = self.builder.mark_lig_pos().map_err(at(line))?;
+        subtable.marks.extend(marks);
+        for ligature in ligatures {
+            subtable
+                .ligatures
+                .insert(ligature, component_anchors.clone());
+        }
+        Ok(())
+    }
+
+    fn lookup_flags(&mut self) -> Result<(), FeaError> {
+        self.expect_keyword("lookupflag")?;
+        let mut flags = LookupFlags::empty();
+        let mut mark_filtering_set = None;
+        if let Some(Token::Number(_)) = self.peek() {
+            flags = LookupFlags::from_bits_truncate(self.expect_u16()?);
+        } else {
+            while !self.is_symbol(';') {
+                let line = self.line();
+                match self.expect_name()?.as_str() {
+                    "RightToLeft" => flags |= LookupFlags::RIGHT_TO_LEFT,
+                    "IgnoreBaseGlyphs" => flags |= LookupFlags::IGNORE_BASE_GLYPHS,
+                    "IgnoreLigatures" => flags |= LookupFlags::IGNORE_LIGATURES,
+                    "IgnoreMarks" => flags |= LookupFlags::IGNORE_MARKS,
+                    "MarkAttachmentType" => {
+                        let glyphs = self.glyph_class()?.0.into_iter().collect();
+                        let class = self
+                            .builder
+                            .mark_attachment_class(glyphs)
+                            .map_err(at(line))?;
+                        flags |= LookupFlags::from_bits_truncate(class << 8);
+                    }
+                    "UseMarkFilteringSet" => {
+                        let glyphs = self.glyph_class()?.0.into_iter().collect();
+                        mark_filtering_set = Some(self.builder.mark_filtering_set(glyphs));
+                        flags |= LookupFlags::USE_MARK_FILTERING_SET;
+                    }
+                    other => {
+                        return Err(FeaError::new(
+                            line,
+                            &format!("Unknown lookup flag {}", other),
+                        ))
+                    }
+                }
+            }
+        }
+        self.expect_symbol(';')?;
+        self.builder.set_lookup_flags(flags, mark_filtering_set);
+        Ok(())
+    }
+
+    fn allocate_name_id(&mut self) -> u16 {
+        let name_id = self.next_name_id;
+        self.next_name_id += 1;
+        name_id
+    }
+
+    /// Reads the platform, encoding, language and string of a `name` or
+    /// `nameid` statement, and adds the record.
+    fn name_record(&mut self, name_id: u16) -> Result<(), FeaError> {
+        let mut platform = 3;
+        let mut ids = None;
+        if let Some(Token::Number(_)) = self.peek() {
+            platform = self.expect_u16()?;
+            if platform != 1 && platform != 3 {
+                return Err(self.err(&format!("Unsupported platform {}", platform)));
+            }
+            if let Some(Token::Number(_)) = self.peek() {
+                ids = Some((self.expect_u16()?, self.expect_u16()?));
+            }
+        }
+        let (encoding, language) = ids.unwrap_or(if platform == 1 { (0, 0) } else { (1, 0x409) });
+        let string = self.expect_string()?;
+        self.expect_symbol(';')?;
+        let string = decode_string(&string, platform).map_err(|m| self.err(&m))?;
+        self.names.push(NameRecord {
+            platformID: platform,
+            encodingID: encoding,
+            languageID: language,
+            nameID: name_id,
+            string,
+        });
+        Ok(())
+    }
+
+    /// Reads a `{ name "..."; ... };` block, returning the allocated name ID.
+    fn names_block(&mut self) -> Result<u16, FeaError> {
+        let name_id = self.allocate_name_id();
+        self.expect_symbol('{')?;
+        while !self.eat_symbol('}') {
+            self.expect_keyword("name")?;
+            self.name_record(name_id)?;
+        }
+        self.expect_symbol(';')?;
+        Ok(name_id)
+    }
+
+    fn feature_names(&mut self) -> Result<(), FeaError> {
+        self.expect_keyword("featureNames")?;
+        let feature = self
+            .builder
+            .feature()
+            .filter(|&tag| is_numbered_feature(tag, "ss"))
+            .ok_or_else(|| self.err("featureNames is only allowed in ssXX features"))?;
+        let name_id = self.names_block()?;
+        self.builder.feature_params.insert(
+            feature,
+            FeatureParams::StylisticSet(StylisticSetParams {
+                ui_name_id: name_id,
+            }),
+        );
+        self.builder.touch_feature();
+        Ok(())
+    }
+
+    fn cv_parameters(&mut self) -> Result<(), FeaError> {
+        self.expect_keyword("cvParameters")?;
+        let feature = self
+            .builder
+            .feature()
+            .filter(|&tag| is_numbered_feature(tag, "cv"))
+            .ok_or_else(|| self.err("cvParameters is only allowed in cvXX features"))?;
+        let mut params = CharacterVariantParams::default();
+        self.expect_symbol('{')?;
+        while !self.eat_symbol('}') {
+            match self.expect_name()?.as_str() {
+                "FeatUILabelNameID" => params.label_name_id = self.names_block()?,
+                "FeatUITooltipTextNameID" => params.tooltip_name_id = self.names_block()?,
+                "SampleTextNameID" => params.sample_text_name_id = self.names_block()?,
+                "ParamUILabelNameID" => {
+                    let name_id = self.names_block()?;
+                    if params.num_named_parameters == 0 {
+                        params.first_param_label_name_id = name_id;
+                    }
+                    params.num_named_parameters += 1;
+                }
+                "Character" => {
+                    let codepoint = self.expect_number()?;
+                    let codepoint =
+                        u32::try_from(codepoint).map_err(|_| self.err("Invalid character"))?;
+                    params.characters.push(codepoint);
+                    self.expect_symbol(';')?;
+                }
+                other => return Err(self.err(&format!("Unexpected {}", other))),
+            }
+        }
+        self.expect_symbol(';')?;
+        self.builder
+            .feature_params
+            .insert(feature, FeatureParams::CharacterVariant(params));
+        self.builder.touch_feature();
+        Ok(())
+    }
+
+    fn size_parameters(&mut self) -> Result<(), FeaError> {
+        self.expect_keyword("parameters")?;
+        if self.builder.feature() != Some(tag!("size")) {
+            return Err(self.err("parameters is only allowed in the size feature"));
+        }
+        let design_size = self.expect_decipoints()?;
+        let subfamily = self.expect_u16()?;
+        let (mut smallest, mut largest) = (0, 0);
+        if !self.is_symbol(';') {
+            smallest = self.expect_decipoints()?;
+            largest = self.expect_decipoints()?;
+        }
+        self.expect_symbol(';')?;
+        self.builder.feature_params.insert(
+            tag!("size"),
+            FeatureParams::Size(SizeParams {
+                design_size,
+                subfamily_id: subfamily,
+                subfamily_name_id: 0,
+                smallest,
+                largest,
+            }),
+        );
+        self.builder.touch_feature();
+        Ok(())
+    }
+
+    fn table_block(&mut self) -> Result<(), FeaError> {
+        self.expect_keyword("table")?;
+        let tag = self.expect_tag()?;
+        self.expect_symbol('{')?;
+        match tag.as_ref() {
+            "GDEF" => self.gdef_table()?,
+            "OS/2" => self.os2_table()?,
+            "name" => self.name_table()?,
+            "head" | "hhea" => self.metrics_table(tag)?,
+            "STAT" => self.stat_table()?,
+            _ => {
+                return Err(self.err(&format!(
+                    "The {} table is not supported",
+                    tag.as_ref() as &str
+                )))
+            }
+        }
+        if self.expect_tag()? != tag {
+            return Err(self.err(&format!("Expected {}", tag.as_ref() as &str)));
+        }
+        self.expect_symbol(';')
+    }
+
+    fn gdef_table(&mut self) -> Result<(), FeaError> {
+        while !self.eat_symbol('}') {
+            match self.expect_name()?.as_str() {
+                "GlyphClassDef" => {
+                    let classes = [
+                        GlyphClass::BaseGlyph,
+                        GlyphClass::LigatureGlyph,
+                        GlyphClass::MarkGlyph,
+                        GlyphClass::ComponentGlyph,
+                    ];
+                    let mut glyph_classes = BTreeMap::new();
+                    for (ix, class) in classes.iter().enumerate() {
+                        if ix > 0 {
+                            self.expect_symbol(',')?;
+                        }
+                        if !self.is_symbol(',') && !self.is_symbol(';') {
+                            for glyph in self.glyph_class()?.0 {
+                                glyph_classes.insert(glyph, *class);
+                            }
+                        }
+                    }
+                    self.builder.glyph_class_def = Some(glyph_classes);
+                }
+                "Attach" => {
+                    let (glyphs, _) = self.glyph_class()?;
+                    let mut points = vec![];
+                    while !self.is_symbol(';') {
+                        points.push(self.expect_u16()?);
+                    }
+                    for glyph in glyphs {
+                        self.builder
+                            .attachment_points
+                            .entry(glyph)
+                            .or_default()
+                            .extend(points.iter().copied());
+                    }
+                }
+                keyword @ ("LigatureCaretByPos" | "LigatureCaretByIndex") => {
+                    let by_position = keyword == "LigatureCaretByPos";
+                    let (glyphs, _) = self.glyph_class()?;
+                    let mut carets = vec![];
+                    while !self.is_symbol(';') {
+                        carets.push(if by_position {
+                            CaretValue::Format1 {
+                                coordinate: self.expect_i16()?,
+                            }
+                        } else {
+                            CaretValue::Format2 {
+                                pointIndex: self.expect_u16()?,
+                            }
+                        });
+                    }
+                    for glyph in glyphs {
+                        self.builder
+                            .ligature_carets
+                            .entry(glyph)
+                            .or_insert_with(|| carets.clone());
+                    }
+                }
+                other => return Err(self.err(&format!("Unexpected {}", other))),
+            }
+            self.expect_symbol(';')?;
+        }
+        Ok(())
+    }
+
+    fn os2_table(&mut self) -> Result<(), FeaError> {
+        while !self.eat_symbol('}') {
+            let field = match self.expect_name()?.as_str() {
+                "FSType" => TableField::FsType(self.expect_u16()?),
+                "Panose" => {
+                    let mut panose = [0_u8; 10];
+                    for value in panose.iter_mut() {
+                        let number = self.expect_number()?;
+                        *value = u8::try_from(number)
+                            .map_err(|_| self.err(&format!("{} is out of range", number)))?;
+                    }
+                    TableField::Panose(panose)
+                }
+                "UnicodeRange" => {
+                    let mut bits = vec![];
+                    while !self.is_symbol(';') {
+                        match self.expect_number()? {
+                            bit @ 0..=127 => bits.push(bit as u8),
+                            bit => return Err(self.err(&format!("Bad Unicode range bit {}", bit))),
+                        }
+                    }
+                    TableField::UnicodeRange(bits)
+                }
+                "CodePageRange" => {
+                    let mut bits = vec![];
+                    while !self.is_symbol(';') {
+                        let code_page = self.expect_number()?;
+                        let bit = CODE_PAGES
+                            .iter()
+                            .find(|(page, _)| *page as i64 == code_page)
+                            .map(|(_, bit)| *bit)
+                            .ok_or_else(|| self.err(&format!("Unknown code page {}", code_page)))?;
+                        bits.push(bit);
+                    }
+                    TableField::CodePageRange(bits)
+                }
+                "TypoAscender" => TableField::TypoAscender(self.expect_i16()?),
+                "TypoDescender" => TableField::TypoDescender(self.expect_i16()?),
+                "TypoLineGap" => TableField::TypoLineGap(self.expect_i16()?),
+                "winAscent" => TableField::WinAscent(self.expect_u16()?),
+                "winDescent" => TableField::WinDescent(self.expect_u16()?),
+                "XHeight" => TableField::XHeight(self.expect_i16()?),
+                "CapHeight" => TableField::CapHeight(self.expect_i16()?),
+                "WeightClass" => TableField::WeightClass(self.expect_u16()?),
+                "WidthClass" => TableField::WidthClass(self.expect_u16()?),
+                "Vendor" => {
+                    let vendor = self.expect_string()?;
+                    TableField::Vendor(
+                        Tag::from_raw(&vendor)
+                            .map_err(|_| self.err(&format!("Bad vendor ID {}", vendor)))?,
+                    )
+                }
+                "LowerOpSize" => TableField::LowerOpSize(self.expect_u16()?),
+                "UpperOpSize" => TableField::UpperOpSize(self.expect_u16()?),
+                "FamilyClass" => TableField::FamilyClass(self.expect_i16()?),
+                other => return Err(self.err(&format!("Unexpected {}", other))),
+            };
+            self.expect_symbol(';')?;
+            self.fields.push(field);
+        }
+        Ok(())
+    }
+
+    fn name_table(&mut self) -> Result<(), FeaError> {
+        while !self.eat_symbol('}') {
+            self.expect_keyword("nameid")?;
+            let name_id = self.expect_u16()?;
+            self.name_record(name_id)?;
+        }
+        Ok(())
+    }
+
+    fn metrics_table(&mut self, tag: Tag) -> Result<(), FeaError> {
+        while !self.eat_symbol('}') {
+            let line = self.line();
+            let field = match (tag.as_ref(), self.expect_name()?.as_str()) {
+                ("head", "FontRevision") => TableField::FontRevision(self.expect_float()? as f32),
+                ("hhea", "CaretOffset") => TableField::CaretOffset(self.expect_i16()?),
+                ("hhea", "Ascender") => TableField::Ascender(self.expect_i16()?),
+                ("hhea", "Descender") => TableField::Descender(self.expect_i16()?),
+                ("hhea", "LineGap") => TableField::LineGap(self.expect_i16()?),
+                (_, other) => return Err(FeaError::new(line, &format!("Unexpected {}", other))),
+            };
+            self.expect_symbol(';')?;
+            self.fields.push(field);
+        }
+        Ok(())
+    }
+
+    fn stat_table(&mut self) -> Result<(), FeaError> {
+        let mut stat = STAT {
+            elided_fallback_name_id: None,
+            design_axes: vec![],
+            axis_values: vec![],
+        };
+        let mut values = vec![];
+        while !self.eat_symbol('}') {
+            match self.expect_name()?.as_str() {
+                "ElidedFallbackName" => stat.elided_fallback_name_id = Some(self.names_block()?),
+                "ElidedFallbackNameID" => {
+                    stat.elided_fallback_name_id = Some(self.expect_u16()?);
+                    self.expect_symbol(';')?;
+                }
+                "DesignAxis" => {
+                    let axis_tag = self.expect_tag()?;
+                    let ordering = self.expect_u16()?;
+                    let name_id = self.names_block()?;
+                    stat.design_axes.push(AxisRecord {
+                        axisTag: axis_tag,
+                        axisNameID: name_id,
+                        axisOrdering: ordering,
+                    });
+                }
+                "AxisValue" => values.push(self.axis_value()?),
+                other => return Err(self.err(&format!("Unexpected {}", other))),
+            }
+        }
+        for value in values {
+            let index = |tag: Tag| -> Result<u16, FeaError> {
+                stat.design_axes
+                    .iter()
+                    .position(|axis| axis.axisTag == tag)
+                    .map(|ix| ix as u16)
+                    .ok_or_else(|| self.err(&format!("Unknown axis {}", tag.as_ref() as &str)))
+            };
+            let axis_value = match value.locations.as_slice() {
+                [(tag, location)] => match location.as_slice() {
+                    [nominal] => {
+                        AxisValue::new_format1(index(*tag)?, value.flags, value.name_id, *nominal)
+                    }
+                    [nominal, linked] => AxisValue::new_format3(
+                        index(*tag)?,
+                        value.flags,
+                        value.name_id,
+                        *nominal,
+                        *linked,
+                    ),
+                    [nominal, min, max] => AxisValue::new_format2(
+                        index(*tag)?,
+                        value.flags,
+                        value.name_id,
+                        *nominal,
+                        *min,
+                        *max,
+                    ),
+                    _ => return Err(self.err("Bad axis value location")),
+                },
+                locations => {
+                    let mut mapping = BTreeMap::new();
+                    for (tag, location) in locations {
+                        if location.len() != 1 {
+                            return Err(self.err(
+                                "Axis values with several locations must have single values",
+                            ));
+                        }
+                        mapping.insert(index(*tag)?, location[0]);
+                    }
+                    AxisValue::new_format4(value.flags, value.name_id, mapping)
+                }
+            };
+            stat.axis_values.push(axis_value);
+        }
+        self.stat = Some(stat);
+        Ok(())
+    }
+
+    fn axis_value(&mut self) -> Result<PendingAxisValue, FeaError> {
+        let mut value = PendingAxisValue {
+            locations: vec![],
+            flags: AxisValueFlags::empty(),
+            name_id: 0,
+        };
+        let mut has_name = false;
+        self.expect_symbol('{')?;
+        while !self.eat_symbol('}') {
+            match self.expect_name()?.as_str() {
+                "location" => {
+                    let tag = self.expect_tag()?;
+                    let mut location = vec![];
+                    while !self.is_symbol(';') {
+                        location.push(self.expect_float()? as f32);
+                    }
+                    self.expect_symbol(';')?;
+                    value.locations.push((tag, location));
+                }
+                "flag" => {
+                    while !self.is_symbol(';') {
+                        match self.expect_name()?.as_str() {
+                            "OlderSiblingFontAttribute" => {
+                                value.flags |= AxisValueFlags::OLDER_SIBLING_FONT_ATTRIBUTE
+                            }
+                            "ElidableAxisValueName" => {
+                                value.flags |= AxisValueFlags::ELIDABLE_AXIS_VALUE_NAME
+                            }
+                            other => return Err(self.err(&format!("Unknown flag {}", other))),
+                        }
+                    }
+                    self.expect_symbol(';')?;
+                }
+                "name" => {
+                    if !has_name {
+                        value.name_id = self.allocate_name_id();
+                        has_name = true;
+                    }
+                    self.name_record(value.name_id)?;
+                }
+                other => return Err(self.err(&format!("Unexpected {}", other))),
+            }
+        }
+        self.expect_symbol(';')?;
+        if value.locations.is_empty() || !has_name {
+            return Err(self.err("Axis values need a location and a name"));
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_names() {
+        assert_eq!(
+            range_names("a.sc", "c.sc").unwrap(),
+            vec!["a.sc", "b.sc", "c.sc"]
+        );
+        assert_eq!(
+            range_names("cid00009", "cid00011").unwrap(),
+            vec!["cid00009", "cid00010", "cid00011"]
+        );
+        assert_eq!(range_names("a11", "a21").unwrap().len(), 11);
+        assert!(range_names("a", "Z").is_none());
+    }
+
+    #[test]
+    fn test_decode_string() {
+        assert_eq!(decode_string("Caf\\00e9", 3).unwrap(), "Café");
+        assert_eq!(decode_string("Caf\\e9", 1).unwrap(), "Café");
+    }
+}
@@ -0,0 +1,1048 @@
+use super::builder::DFLT_LANGUAGE;
+use crate::font::Font;
+use crate::layout::common::{LanguageSystem, Lookup, LookupFlags, ValueRecord, GPOSGSUB};
+use crate::layout::contextual::{
+    ChainedSequenceContext, ChainedSequenceContextRule, SequenceContext, Slot,
+};
+use crate::layout::gpos2::PairPositioningMap;
+use crate::tables::GDEF::GDEF;
+use crate::tables::GPOS::Positioning;
+use crate::tables::GSUB::Substitution;
+use otspec::layout::anchor::Anchor;
+use otspec::types::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+/// Glyph names which would be read as keywords, and so must be escaped
+const KEYWORDS: [&str; 30] = [
+    "NULL",
+    "anchor",
+    "base",
+    "by",
+    "contourpoint",
+    "cursive",
+    "enum",
+    "enumerate",
+    "exclude_dflt",
+    "feature",
+    "from",
+    "ignore",
+    "include",
+    "include_dflt",
+    "language",
+    "ligComponent",
+    "ligature",
+    "lookup",
+    "lookupflag",
+    "mark",
+    "markClass",
+    "pos",
+    "position",
+    "required",
+    "reversesub",
+    "rsub",
+    "script",
+    "sub",
+    "substitute",
+    "subtable",
+];
+
+/// The rules of a lookup, written as feature file statements
+pub(crate) trait FeaRules {
+    /// The table tag, used to name the table's lookups
+    const TABLE: &'static str;
+
+    /// Whether the lookup has no rules at all
+    fn is_empty(&self) -> bool;
+
+    /// The lookups called from contextual rules
+    fn nested_lookups(&self) -> BTreeSet<usize>;
+
+    /// Writes the statements of the lookup's rules.
+    fn write_rules(&self, writer: &mut Writer, lookup: usize);
+}
+
+/// Decompiles a layout table to feature file syntax.
+pub(crate) fn to_fea<T: FeaRules>(
+    table: &GPOSGSUB<T>,
+    font: &Font,
+) -> Result<String, Box<dyn Error>> {
+    let num_glyphs = font
+        .tables
+        .maxp()?
+        .map_or(0, |maxp| maxp.num_glyphs() as usize);
+    let mut writer = Writer {
+        glyph_names: crate::flavor::glyph_names(font, num_glyphs)?,
+        gdef: font.tables.GDEF()?.map(|gdef| (*gdef).clone()),
+        lookup_names: vec![],
+        definitions: vec![],
+        statements: vec![],
+    };
+    Ok(writer.table(table))
+}
+
+/// The order in which lookups are written: each lookup follows the
+/// lookups its contextual rules call, since feature files can only refer
+/// to lookups which have already been defined.
+fn lookup_order(nested: &[BTreeSet<usize>]) -> Vec<usize> {
+    fn visit(
+        ix: usize,
+        nested: &[BTreeSet<usize>],
+        seen: &mut BTreeSet<usize>,
+        order: &mut Vec<usize>,
+    ) {
+        if ix >= nested.len() || !seen.insert(ix) {
+            return;
+        }
+        for &dependency in &nested[ix] {
+            visit(dependency, nested, seen, order);
+        }
+        order.push(ix);
+    }
+    let mut seen = BTreeSet::new();
+    let mut order = vec![];
+    for ix in 0..nested.len() {
+        visit(ix, nested, &mut seen, &mut order);
+    }
+    order
+}
+
+fn tag_str(tag: &Tag) -> &str {
+    (tag.as_ref() as &str).trim_end()
+}
+
+/// All the language systems of a table, with their script and language tags.
+fn language_systems<T>(table: &GPOSGSUB<T>) -> Vec<(Tag, Tag, &LanguageSystem)> {
+    let mut language_systems = vec![];
+    for (script_tag, script) in &table.scripts.scripts {
+        if let Some(langsys) = &script.default_language_system {
+            language_systems.push((*script_tag, DFLT_LANGUAGE, langsys));
+        }
+        for (language, langsys) in &script.language_systems {
+            language_systems.push((*script_tag, *language, langsys));
+        }
+    }
+    language_systems
+}
+
+fn anchor(anchor: &Option<Anchor>) -> String {
+    match anchor {
+        None => "<anchor NULL>".to_string(),
+        Some(Anchor {
+            xCoordinate,
+            yCoordinate,
+            anchorPoint: Some(point),
+            ..
+        }) => format!(
+            "<anchor {} {} contourpoint {}>",
+            xCoordinate, yCoordinate, point
+        ),
+        Some(anchor) => format!("<anchor {} {}>", anchor.xCoordinate, anchor.yCoordinate),
+    }
+}
+
+/// A value record, written as a single advance where possible.
+fn value_record(record: &ValueRecord) -> String {
+    let x_placement = record.xPlacement.unwrap_or(0);
+    let y_placement = record.yPlacement.unwrap_or(0);
+    let x_advance = record.xAdvance.unwrap_or(0);
+    let y_advance = record.yAdvance.unwrap_or(0);
+    if x_placement == 0 && y_placement == 0 && y_advance == 0 {
+        if x_advance == 0 {
+            "<NULL>".to_string()
+        } else {
+            x_advance.to_string()
+        }
+    } else {
+        format!(
+            "<{} {} {} {}>",
+            x_placement, y_placement, x_advance, y_advance
+        )
+    }
+}
+
+fn has_values(record: &ValueRecord) -> bool {
+    [
+        record.xPlacement,
+        record.yPlacement,
+        record.xAdvance,
+        record.yAdvance,
+    ]
+    .iter()
+    .any(|value| value.unwrap_or(0) != 0)
+}
+
+/// Groups keys which map to equal values, keeping the groups in the order
+/// of their first key.
+fn group_by_value<'a, K: Copy, V: PartialEq>(
+    items: impl Iterator<Item = (K, &'a V)>,
+) -> Vec<(&'a V, Vec<K>)> {
+    let mut groups: Vec<(&V, Vec<K>)> = vec![];
+    for (key, value) in items {
+        match groups.iter_mut().find(|(v, _)| *v == value) {
+            Some((_, keys)) => keys.push(key),
+            None => groups.push((value, vec![key])),
+        }
+    }
+    groups
+}
+
+/// The rules of a pair positioning subtable, as glyph classes which share
+/// the same adjustments.
+///
+/// Right glyphs which are adjusted the same way after every left glyph form
+/// a second class, and the left glyphs sharing an adjustment before each
+/// second class form a first class. Every pair of the subtable is covered
+/// by exactly one rule.
+#[allow(clippy::type_complexity)]
+fn pair_classes(
+    mapping: &PairPositioningMap,
+) -> Vec<(Vec<GlyphID>, Vec<GlyphID>, &(ValueRecord, ValueRecord))> {
+    let mut values: Vec<&(ValueRecord, ValueRecord)> = vec![];
+    let mut columns: BTreeMap<GlyphID, Vec<(GlyphID, usize)>> = BTreeMap::new();
+    for ((left, right), value) in mapping {
+        let ix = match values.iter().position(|v| *v == value) {
+            Some(ix) => ix,
+            None => {
+                values.push(value);
+                values.len() - 1
+            }
+        };
+        columns.entry(*right).or_default().push((*left, ix));
+    }
+    let mut second_classes: BTreeMap<Vec<(GlyphID, usize)>, Vec<GlyphID>> = BTreeMap::new();
+    for (right, column) in columns {
+        second_classes.entry(column).or_default().push(right);
+    }
+    let mut rules = vec![];
+    for (column, second_class) in second_classes {
+        let mut first_classes: BTreeMap<usize, Vec<GlyphID>> = BTreeMap::new();
+        for (left, value) in column {
+            first_classes.entry(value).or_default().push(left);
+        }
+        for (value, first_class) in first_classes {
+            rules.push((first_class, second_class.clone(), values[value]));
+        }
+    }
+    rules.sort_by_key(|(first, second, _)| (first[0], second[0]));
+    rules
+}
+
+/// Writes layout tables as feature file syntax.
+pub(crate) struct Writer {
+    glyph_names: Vec<String>,
+    gdef: Option<GDEF>,
+    /// The names of the lookups of the table, or `None` for empty lookups
+    lookup_names: Vec<Option<String>>,
+    /// Top-level statements needed by the lookup being written
+    definitions: Vec<String>,
+    /// The statements of the lookup being written
+    statements: Vec<String>,
+}
+
+impl Writer {
+    fn table<T: FeaRules>(&mut self, table: &GPOSGSUB<T>) -> String {
+        let mut out = String::new();
+        let language_systems = language_systems(table);
+        for (script, language, _) in &language_systems {
+            out.push_str(&format!(
+                "languagesystem {} {};\n",
+                tag_str(script),
+                tag_str(language)
+            ));
+        }
+
+        self.lookup_names = table
+            .lookups
+            .iter()
+            .enumerate()
+            .map(|(ix, lookup)| {
+                if lookup.rule.is_empty() {
+                    None
+                } else {
+                    Some(format!("{}_{}", T::TABLE, ix))
+                }
+            })
+            .collect();
+        let nested: Vec<BTreeSet<usize>> = table
+            .lookups
+            .iter()
+            .map(|lookup| lookup.rule.nested_lookups())
+            .collect();
+        for ix in lookup_order(&nested) {
+            let name = match &self.lookup_names[ix] {
+                Some(name) => name.clone(),
+                None => continue,
+            };
+            let lookup = &table.lookups[ix];
+            self.definitions.clear();
+            self.statements.clear();
+            if let Some(flags) = self.lookup_flags(lookup) {
+                self.statements.push(flags);
+            }
+            lookup.rule.write_rules(self, ix);
+            out.push('\n');
+            for definition in self.definitions.drain(..) {
+                out.push_str(&definition);
+                out.push('\n');
+            }
+            out.push_str(&format!("lookup {} {{\n", name));
+            for statement in self.statements.drain(..) {
+                out.push_str(&format!("    {}\n", statement));
+            }
+            out.push_str(&format!("}} {};\n", name));
+        }
+
+        let mut tags: Vec<Tag> = vec![];
+        for (tag, _, _) in table.features.iter() {
+            if !tags.contains(tag) {
+                tags.push(*tag);
+            }
+        }
+        for tag in tags {
+            let statements = self.feature_statements(table, tag, &language_systems);
+            if statements.is_empty() {
+                continue;
+            }
+            out.push_str(&format!("\nfeature {} {{\n", tag_str(&tag)));
+            for statement in statements {
+                out.push_str(&format!("    {}\n", statement));
+            }
+            out.push_str(&format!("}} {};\n", tag_str(&tag)));
+        }
+        out
+    }
+
+    /// The statements of a feature block. Features which use the same
+    /// lookups in every language system just list them; otherwise each
+    /// script and language is given explicitly.
+    fn feature_statements<T>(
+        &self,
+        table: &GPOSGSUB<T>,
+        tag: Tag,
+        language_systems: &[(Tag, Tag, &LanguageSystem)],
+    ) -> Vec<String> {
+        let mut entries = vec![];
+        for (script, language, langsys) in language_systems {
+            let mut lookups = BTreeSet::new();
+            let mut required = false;
+            for ix in langsys.all_feature_indices() {
+                if let Some((feature_tag, feature_lookups, _)) = table.features.get(ix) {
+                    if *feature_tag == tag {
+                        lookups.extend(feature_lookups.iter().copied());
+                        required |= langsys.required_feature == Some(ix);
+                    }
+                }
+            }
+            let names: Vec<&String> = lookups
+                .iter()
+                .filter_map(|&ix| self.lookup_names.get(ix).and_then(|name| name.as_ref()))
+                .collect();
+            if !names.is_empty() {
+                entries.push((*script, *language, required, names));
+            }
+        }
+        let references = |names: &[&String]| -> Vec<String> {
+            names
+                .iter()
+                .map(|name| format!("lookup {};", name))
+                .collect()
+        };
+        if entries.len() == language_systems.len()
+            && entries
+                .iter()
+                .all(|(_, _, required, names)| !required && *names == entries[0].3)
+        {
+            return references(&entries[0].3);
+        }
+        let mut statements = vec![];
+        let mut current_script = None;
+        for (script, language, required, names) in entries {
+            if current_script != Some(script) {
+                statements.push(format!("script {};", tag_str(&script)));
+                current_script = Some(script);
+            }
+            let required = if required { " required" } else { "" };
+            if language != DFLT_LANGUAGE {
+                statements.push(format!(
+                    "language {} exclude_dflt{};",
+                    tag_str(&language),
+                    required
+                ));
+            } else if !required.is_empty() {
+                statements.push(format!("language dflt{};", required));
+            }
+            statements.extend(references(&names));
+        }
+        statements
+    }
+
+    /// The `lookupflag` statement of a lookup, if it has any flags.
+    fn lookup_flags<T>(&self, lookup: &Lookup<T>) -> Option<String> {
+        let flags = lookup.flags;
+        let mut parts = vec![];
+        for (flag, name) in [
+            (LookupFlags::RIGHT_TO_LEFT, "RightToLeft"),
+            (LookupFlags::IGNORE_BASE_GLYPHS, "IgnoreBaseGlyphs"),
+            (LookupFlags::IGNORE_LIGATURES, "IgnoreLigatures"),
+            (LookupFlags::IGNORE_MARKS, "IgnoreMarks"),
+        ]
+        .iter()
+        {
+            if flags.contains(*flag) {
+                parts.push(name.to_string());
+            }
+        }
+        let numeric = Some(format!("lookupflag {};", flags.bits()));
+        let class = (flags & LookupFlags::MARK_ATTACHMENT_TYPE_MASK).bits() >> 8;
+        if class != 0 {
+            let glyphs: Vec<GlyphID> = self
+                .gdef
+                .iter()
+                .flat_map(|gdef| gdef.mark_attachment_class.iter())
+                .filter(|(_, &c)| c == class)
+                .map(|(&glyph, _)| glyph)
+                .collect();
+            if glyphs.is_empty() {
+                return numeric;
+            }
+            parts.push(format!("MarkAttachmentType {}", self.class(&glyphs)));
+        }
+        if flags.contains(LookupFlags::USE_MARK_FILTERING_SET) {
+            let set = lookup.mark_filtering_set.and_then(|ix| {
+                self.gdef
+                    .as_ref()
+                    .and_then(|gdef| gdef.mark_glyph_sets.as_ref())
+                    .and_then(|sets| sets.get(ix as usize))
+            });
+            match set {
+                Some(set) => {
+                    let glyphs: Vec<GlyphID> = set.iter().copied().collect();
+                    parts.push(format!("UseMarkFilteringSet {}", self.class(&glyphs)));
+                }
+                None => return numeric,
+            }
+        }
+        if parts.is_empty() {
+            None
+        } else {
+            Some(format!("lookupflag {};", parts.join(" ")))
+        }
+    }
+
+    fn statement(&mut self, statement: String) {
+        self.statements.push(statement);
+    }
+
+    /// Writes each non-empty subtable, separated by `subtable` statements.
+    fn subtables<S>(&mut self, subtables: &[S], mut write: impl FnMut(&mut Self, usize, &S)) {
+        let mut written = false;
+        for (ix, subtable) in subtables.iter().enumerate() {
+            let start = self.statements.len();
+            if written {
+                self.statement("subtable;".to_string());
+            }
+            let before = self.statements.len();
+            write(self, ix, subtable);
+            if self.statements.len() == before {
+                self.statements.truncate(start);
+            } else {
+                written = true;
+            }
+        }
+    }
+
+    fn glyph(&self, glyph: GlyphID) -> String {
+        let name = self
+            .glyph_names
+            .get(glyph as usize)
+            .cloned()
+            .unwrap_or_else(|| format!("glyph{:05}", glyph));
+        if KEYWORDS.contains(&name.as_str()) {
+            format!("\\{}", name)
+        } else {
+            name
+        }
+    }
+
+    fn class(&self, glyphs: &[GlyphID]) -> String {
+        let names: Vec<String> = glyphs.iter().map(|&g| self.glyph(g)).collect();
+        format!("[{}]", names.join(" "))
+    }
+
+    /// A single glyph, or a class of several glyphs.
+    fn glyphs(&self, glyphs: &[GlyphID]) -> String {
+        if glyphs.len() == 1 {
+            self.glyph(glyphs[0])
+        } else {
+            self.class(glyphs)
+        }
+    }
+
+    fn slot(&self, slot: &Slot) -> String {
+        self.glyphs(&slot.iter().copied().collect::<Vec<GlyphID>>())
+    }
+
+    fn chained_rule(&mut self, keyword: &str, rule: &ChainedSequenceContextRule) {
+        let mut items: Vec<String> = rule.backtrack.iter().rev().map(|s| self.slot(s)).collect();
+        let mut has_lookups = false;
+        for (slot, lookups) in &rule.input {
+            let mut item = format!("{}'", self.slot(slot));
+            for lookup in lookups {
+                if let Some(Some(name)) = self.lookup_names.get(*lookup as usize) {
+                    item.push_str(&format!(" lookup {}", name));
+                    has_lookups = true;
+                }
+            }
+            items.push(item);
+        }
+        items.extend(rule.lookahead.iter().map(|s| self.slot(s)));
+        let ignore = if has_lookups { "" } else { "ignore " };
+        self.statement(format!("{}{} {};", ignore, keyword, items.join(" ")));
+    }
+
+    fn contextual(&mut self, keyword: &str, subtables: &[SequenceContext]) {
+        self.subtables(subtables, |writer, _, subtable| {
+            for input in &subtable.rules {
+                let rule = ChainedSequenceContextRule {
+                    backtrack: vec![],
+                    lookahead: vec![],
+                    input: input.clone(),
+                };
+                writer.chained_rule(keyword, &rule);
+            }
+        });
+    }
+
+    fn chained_contextual(&mut self, keyword: &str, subtables: &[ChainedSequenceContext]) {
+        self.subtables(subtables, |writer, _, subtable| {
+            for rule in &subtable.rules {
+                writer.chained_rule(keyword, rule);
+            }
+        });
+    }
+
+    /// Defines the mark classes of a mark attachment subtable, returning
+    /// their names by class index.
+    fn mark_classes(
+        &mut self,
+        lookup: usize,
+        subtable: usize,
+        marks: &BTreeMap<GlyphID, (u16, Anchor)>,
+    ) -> BTreeMap<u16, String> {
+        let mut names = BTreeMap::new();
+        let prefix = self.lookup_names[lookup].clone().unwrap_or_default();
+        for (&(class, ref anchor_), glyphs) in group_by_value(marks.iter().map(|(g, v)| (*g, v))) {
+            let name = names
+                .entry(class)
+                .or_insert_with(|| format!("@{}_{}_mark{}", prefix, subtable, class));
+            let definition = format!(
+                "markClass {} {} {};",
+                self.glyphs(&glyphs),
+                anchor(&Some(anchor_.clone())),
+                name
+            );
+            self.definitions.push(definition);
+        }
+        names
+    }
+
+    /// The `<anchor> mark @CLASS` pairs of a base, ligature component or
+    /// base mark.
+    fn mark_anchors(
+        &self,
+        anchors: &BTreeMap<u16, Anchor>,
+        names: &BTreeMap<u16, String>,
+    ) -> String {
+        if anchors.is_empty() {
+            return anchor(&None);
+        }
+        anchors
+            .iter()
+            .filter_map(|(class, a)| {
+                names
+                    .get(class)
+                    .map(|name| format!("{} mark {}", anchor(&Some(a.clone())), name))
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    fn mark_attachment(
+        &mut self,
+        keyword: &str,
+        lookup: usize,
+        subtable: usize,
+        bases: &BTreeMap<GlyphID, BTreeMap<u16, Anchor>>,
+        marks: &BTreeMap<GlyphID, (u16, Anchor)>,
+    ) {
+        let names = self.mark_classes(lookup, subtable, marks);
+        let bases = bases.iter().filter(|(_, anchors)| !anchors.is_empty());
+        for (anchors, glyphs) in group_by_value(bases.map(|(g, v)| (*g, v))) {
+            let statement = format!(
+                "pos {} {} {};",
+                keyword,
+                self.glyphs(&glyphs),
+                self.mark_anchors(anchors, &names)
+            );
+            self.statement(statement);
+        }
+    }
+}
+
+impl FeaRules for Substitution {
+    const TABLE: &'static str = "GSUB";
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Substitution::Single(v) => v.iter().all(|s| s.mapping.is_empty()),
+            Substitution::Multiple(v) => v.iter().all(|s| s.mapping.is_empty()),
+            Substitution::Alternate(v) => v.iter().all(|s| s.mapping.is_empty()),
+            Substitution::Ligature(v) => v.iter().all(|s| s.mapping.is_empty()),
+            Substitution::Contextual(v) => v.iter().all(|s| s.rules.is_empty()),
+            Substitution::ChainedContextual(v) => v.iter().all(|s| s.rules.is_empty()),
+            Substitution::ReverseChainContextual(v) => v.iter().all(|s| s.mapping.is_empty()),
+        }
+    }
+
+    fn nested_lookups(&self) -> BTreeSet<usize> {
+        match self {
+            Substitution::Contextual(v) => v
+                .iter()
+                .flat_map(|s| s.rules.iter().flatten())
+                .flat_map(|(_, lookups)| lookups.iter().map(|&l| l as usize))
+                .collect(),
+            Substitution::ChainedContextual(v) => v
+                .iter()
+                .flat_map(|s| s.rules.iter().flat_map(|r| r.input.iter()))
+                .flat_map(|(_, lookups)| lookups.iter().map(|&l| l as usize))
+                .collect(),
+            _ => BTreeSet::new(),
+        }
+    }
+
+    fn write_rules(&self, writer: &mut Writer, _lookup: usize) {
+        match self {
+            Substitution::Single(subtables) => {
+                writer.subtables(subtables, |writer, _, subtable| {
+                    for (&from, &to) in &subtable.mapping {
+                        let statement =
+                            format!("sub {} by {};", writer.glyph(from), writer.glyph(to));
+                        writer.statement(statement);
+                    }
+                })
+            }
+            Substitution::Multiple(subtables) => {
+                writer.subtables(subtables, |writer, _, subtable| {
+                    for (&from, to) in &subtable.mapping {
+                        let to = if to.is_empty() {
+                            "NULL".to_string()
+                        } else {
+                            let names: Vec<String> = to.iter().map(|&g| writer.glyph(g)).collect();
+                            names.join(" ")
+                        };
+                        let statement = format!("sub {} by {};", writer.glyph(from), to);
+                        writer.statement(statement);
+                    }
+                })
+            }
+            Substitution::Alternate(subtables) => {
+                writer.subtables(subtables, |writer, _, subtable| {
+                    for (&from, alternates) in &subtable.mapping {
+                        let statement = format!(
+                            "sub {} from {};",
+                            writer.glyph(from),
+                            writer.class(alternates)
+                        );
+                        writer.statement(statement);
+                    }
+                })
+            }
+            Substitution::Ligature(subtables) => {
+                writer.subtables(subtables, |writer, _, subtable| {
+                    // A one-glyph ligature would be read as a single substitution
+                    for (from, &to) in subtable.mapping.iter().filter(|(from, _)| from.len() > 1) {
+                        let names: Vec<String> = from.iter().map(|&g| writer.glyph(g)).collect();
+                        let statement = format!("sub {} by {};", names.join(" "), writer.glyph(to));
+                        writer.statement(statement);
+                    }
+                })
+            }
+            Substitution::Contextual(subtables) => writer.contextual("sub", subtables),
+            Substitution::ChainedContextual(subtables) => {
+                writer.chained_contextual("sub", subtables)
+            }
+            Substitution::ReverseChainContextual(subtables) => {
+                writer.subtables(subtables, |writer, _, subtable| {
+                    if subtable.mapping.is_empty() {
+                        return;
+                    }
+                    let mut items: Vec<String> = subtable
+                        .backtrack
+                        .iter()
+                        .rev()
+                        .map(|s| writer.slot(s))
+                        .collect();
+                    let from: Vec<GlyphID> = subtable.mapping.keys().copied().collect();
+                    let to: Vec<GlyphID> = subtable.mapping.values().copied().collect();
+                    items.push(format!("{}'", writer.glyphs(&from)));
+                    items.extend(subtable.lookahead.iter().map(|s| writer.slot(s)));
+                    let statement = format!("rsub {} by {};", items.join(" "), writer.glyphs(&to));
+                    writer.statement(statement);
+                })
+            }
+        }
+    }
+}
+
+impl FeaRules for Positioning {
+    const TABLE: &'static str = "GPOS";
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Positioning::Single(v) => v.iter().all(|s| s.mapping.is_empty()),
+            Positioning::Pair(v) => v.iter().all(|s| s.mapping.is_empty()),
+            Positioning::Cursive(v) => v.iter().all(|s| s.mapping.is_empty()),
+            Positioning::MarkToBase(v) => v.iter().all(|s| s.bases.is_empty()),
+            Positioning::MarkToLig(v) => v.iter().all(|s| s.ligatures.is_empty()),
+            Positioning::MarkToMark(v) => v.iter().all(|s| s.base_marks.is_empty()),
+            Positioning::Contextual(v) => v.iter().all(|s| s.rules.is_empty()),
+            Positioning::ChainedContextual(v) => v.iter().all(|s| s.rules.is_empty()),
+        }
+    }
+
+    fn nested_lookups(&self) -> BTreeSet<usize> {
+        match self {
+            Positioning::Contextual(v) => v
+                .iter()
+                .flat_map(|s| s.rules.iter().flatten())
+                .flat_map(|(_, lookups)| lookups.iter().map(|&l| l as usize))
+                .collect(),
+            Positioning::ChainedContextual(v) => v
+                .iter()
+                .flat_map(|s| s.rules.iter().flat_map(|r| r.input.iter()))
+                .flat_map(|(_, lookups)| lookups.iter().map(|&l| l as usize))
+                .collect(),
+            _ => BTreeSet::new(),
+        }
+    }
+
+    fn write_rules(&self, writer: &mut Writer, lookup: usize) {
+        match self {
+            Positioning::Single(subtables) => writer.subtables(subtables, |writer, _, subtable| {
+                for (&glyph, value) in &subtable.mapping {
+                    let statement = format!("pos {} {};", writer.glyph(glyph), value_record(value));
+                    writer.statement(statement);
+                }
+            }),
+            Positioning::Pair(subtables) => writer.subtables(subtables, |writer, _, subtable| {
+                for (first, second, (value1, value2)) in pair_classes(&subtable.mapping) {
+                    let statement = if has_values(value2) {
+                        format!(
+                            "pos {} {} {} {};",
+                            writer.glyphs(&first),
+                            value_record(value1),
+                            writer.glyphs(&second),
+                            value_record(value2)
+                        )
+                    } else {
+                        format!(
+                            "pos {} {} {};",
+                            writer.glyphs(&first),
+                            writer.glyphs(&second),
+                            value_record(value1)
+                        )
+                    };
+                    writer.statement(statement);
+                }
+            }),
+            Positioning::Cursive(subtables) => {
+                writer.subtables(subtables, |writer, _, subtable| {
+                    for (&glyph, (entry, exit)) in &subtable.mapping {
+                        let statement = format!(
+                            "pos cursive {} {} {};",
+                            writer.glyph(glyph),
+                            anchor(entry),
+                            anchor(exit)
+                        );
+                        writer.statement(statement);
+                    }
+                })
+            }
+            Positioning::MarkToBase(subtables) => {
+                writer.subtables(subtables, |writer, ix, subtable| {
+                    writer.mark_attachment("base", lookup, ix, &subtable.bases, &subtable.marks)
+                })
+            }
+            Positioning::MarkToLig(subtables) => {
+                writer.subtables(subtables, |writer, ix, subtable| {
+                    let names = writer.mark_classes(lookup, ix, &subtable.marks);
+                    for (components, glyphs) in
+                        group_by_value(subtable.ligatures.iter().map(|(g, v)| (*g, v)))
+                    {
+                        let components: Vec<String> = components
+                            .iter()
+                            .map(|anchors| writer.mark_anchors(anchors, &names))
+                            .collect();
+                        let statement = format!(
+                            "pos ligature {} {};",
+                            writer.glyphs(&glyphs),
+                            components.join(" ligComponent ")
+                        );
+                        writer.statement(statement);
+                    }
+                })
+            }
+            Positioning::MarkToMark(subtables) => {
+                writer.subtables(subtables, |writer, ix, subtable| {
+                    writer.mark_attachment(
+                        "mark",
+                        lookup,
+                        ix,
+                        &subtable.base_marks,
+                        &subtable.combining_marks,
+                    )
+                })
+            }
+            Positioning::Contextual(subtables) => writer.contextual("pos", subtables),
+            Positioning::ChainedContextual(subtables) => {
+                writer.chained_contextual("pos", subtables)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::Font;
+    use crate::tables;
+    use crate::testing;
+
+    fn font() -> Font {
+        let glyphs: Vec<String> = [
+            ".notdef", "a", "b", "c", "f", "i", "f_i", "a.sc", "b.sc", "c.sc", "acute", "grave",
+            "T", "o", "sub",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let mut font = testing::test_font(glyphs.len() as u16);
+        font.tables.insert(tables::post::post::new(
+            2.0,
+            0.0,
+            -100,
+            50,
+            false,
+            Some(glyphs),
+        ));
+        font
+    }
+
+    fn compile(font: &Font, fea: &str) -> super::super::FeatureTables {
+        let names = font
+            .tables
+            .post()
+            .unwrap()
+            .unwrap()
+            .glyphnames
+            .clone()
+            .unwrap();
+        super::super::compile(fea, &names).unwrap()
+    }
+
+    #[test]
+    fn test_gsub_to_fea() {
+        let font = font();
+        let tables = compile(
+            &font,
+            "
+            languagesystem DFLT dflt;
+            languagesystem latn dflt;
+            languagesystem latn TRK;
+            feature smcp {
+                sub [a b c] by [a.sc b.sc c.sc];
+            } smcp;
+            feature liga {
+                lookupflag IgnoreMarks;
+                sub f' i' by f_i;
+                sub f i by f_i;
+                script latn;
+                language TRK exclude_dflt;
+                sub \\sub by a;
+            } liga;
+            ",
+        );
+        let fea = tables.gsub.unwrap().to_fea(&font).unwrap();
+        assert_eq!(
+            fea,
+            "languagesystem DFLT dflt;
+languagesystem latn dflt;
+languagesystem latn TRK;
+
+lookup GSUB_0 {
+    sub a by a.sc;
+    sub b by b.sc;
+    sub c by c.sc;
+} GSUB_0;
+
+lookup GSUB_2 {
+    lookupflag IgnoreMarks;
+    sub f i by f_i;
+} GSUB_2;
+
+lookup GSUB_1 {
+    lookupflag IgnoreMarks;
+    sub f' lookup GSUB_2 i';
+} GSUB_1;
+
+lookup GSUB_3 {
+    lookupflag IgnoreMarks;
+    sub f i by f_i;
+} GSUB_3;
+
+lookup GSUB_4 {
+    sub \\sub by a;
+} GSUB_4;
+
+feature liga {
+    script DFLT;
+    lookup GSUB_1;
+    lookup GSUB_3;
+    script latn;
+    lookup GSUB_1;
+    lookup GSUB_3;
+    language TRK exclude_dflt;
+    lookup GSUB_4;
+} liga;
+
+feature smcp {
+    lookup GSUB_0;
+} smcp;
+"
+        );
+    }
+
+    #[test]
+    fn test_gpos_to_fea() {
+        let font = font();
+        let tables = compile(
+            &font,
+            "
+            markClass [acute grave] <anchor 150 -10> @TOP;
+            feature kern {
+                pos T [a b c] -80;
+                pos T o -100;
+                pos f <0 0 20 0> i <5 0 5 0>;
+            } kern;
+            feature mark {
+                lookupflag UseMarkFilteringSet [acute];
+                pos base [a b] <anchor 250 450> mark @TOP;
+                pos base c <anchor 300 450> mark @TOP;
+            } mark;
+            ",
+        );
+        let mut font = font;
+        font.tables.insert(tables.gdef.unwrap());
+        let fea = tables.gpos.unwrap().to_fea(&font).unwrap();
+        assert_eq!(
+            fea,
+            "languagesystem DFLT dflt;
+
+lookup GPOS_0 {
+    pos f 20 i <5 0 5 0>;
+    pos T [a b c] -80;
+    pos T o -100;
+} GPOS_0;
+
+markClass [acute grave] <anchor 150 -10> @GPOS_1_0_mark0;
+lookup GPOS_1 {
+    lookupflag UseMarkFilteringSet [acute];
+    pos base [a b] <anchor 250 450> mark @GPOS_1_0_mark0;
+    pos base c <anchor 300 450> mark @GPOS_1_0_mark0;
+} GPOS_1;
+
+feature kern {
+    lookup GPOS_0;
+} kern;
+
+feature mark {
+    lookup GPOS_1;
+} mark;
+"
+        );
+    }
+
+    #[test]
+    fn test_pair_classes() {
+        let value = |x: i16| {
+            let mut record = ValueRecord::new();
+            record.xAdvance = Some(x);
+            (record, ValueRecord::new())
+        };
+        let mapping: PairPositioningMap = [
+            ((1, 3), value(-10)),
+            ((1, 4), value(-10)),
+            ((2, 3), value(-10)),
+            ((2, 4), value(-10)),
+            ((2, 5), value(-20)),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        let rules: Vec<(Vec<GlyphID>, Vec<GlyphID>, i16)> = pair_classes(&mapping)
+            .into_iter()
+            .map(|(first, second, value)| (first, second, value.0.xAdvance.unwrap()))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![(vec![1, 2], vec![3, 4], -10), (vec![2], vec![5], -20),]
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let font = font();
+        let tables = compile(
+            &font,
+            "
+            markClass acute <anchor 150 -10> @TOP;
+            markClass grave <anchor 100 -10> @TOP;
+            lookup kerns {
+                pos T o -50;
+            } kerns;
+            feature kern {
+                pos [a b] [c o] -20;
+                pos a' lookup kerns b T;
+                ignore pos T' f;
+            } kern;
+            feature mark {
+                pos ligature f_i <anchor 100 500> mark @TOP ligComponent <anchor NULL>;
+                pos mark grave <anchor 0 600> mark @TOP;
+                pos cursive a <anchor 0 0> <anchor NULL>;
+            } mark;
+            lookup other {
+                sub c by a.sc;
+            } other;
+            feature calt {
+                rsub a [b c] o' T by c;
+                sub [a b]' lookup other c;
+                sub f from [a b c];
+                sub c by a b;
+            } calt;
+            ",
+        );
+        let gsub = tables.gsub.unwrap().to_fea(&font).unwrap();
+        let gpos = tables.gpos.unwrap().to_fea(&font).unwrap();
+        let recompiled = compile(
+            &font,
+            &format!(
+                "{}\n{}",
+                gsub,
+                gpos.replace("languagesystem DFLT dflt;\n", "")
+            ),
+        );
+        assert_eq!(recompiled.gsub.unwrap().to_fea(&font).unwrap(), gsub);
+        assert_eq!(recompiled.gpos.unwrap().to_fea(&font).unwrap(), gpos);
+    }
+}
@@ -0,0 +1,353 @@
+use crate::font::Font;
+use crate::tables::glyf;
+use crate::tables::glyf::{contourutils, cu2qu, Glyph, Point};
+use crate::tables::name::NameRecord;
+use crate::tables::CFF::{
+    charstring, operators, Charset, Dict, Encoding, Operand, PrivateDict, CFF,
+};
+use crate::tag;
+use kurbo::{BezPath, PathEl};
+use otspec::types::Tag;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Tables which only make sense with TrueType outlines.
+pub(crate) const TRUETYPE_TABLES: [Tag; 7] = [
+    tag!("glyf"),
+    tag!("loca"),
+    tag!("cvt "),
+    tag!("fpgm"),
+    tag!("prep"),
+    tag!("hdmx"),
+    tag!("LTSH"),
+];
+
+pub(crate) fn error(message: &str) -> Box<dyn Error> {
+    message.to_string().into()
+}
+
+/// The glyph names of the font, from the `post` table where possible.
+pub(crate) fn glyph_names(font: &Font, num_glyphs: usize) -> Result<Vec<String>, Box<dyn Error>> {
+    let names = font
+        .tables
+        .post()?
+        .and_then(|post| post.glyphnames.clone())
+        .filter(|names| names.len() == num_glyphs);
+    Ok(names.unwrap_or_else(|| {
+        (0..num_glyphs)
+            .map(|gid| {
+                if gid == 0 {
+                    ".notdef".to_string()
+                } else {
+                    format!("glyph{:05}", gid)
+                }
+            })
+            .collect()
+    }))
+}
+
+/// Finds a name table entry, preferring the Windows English version.
+fn name_string(font: &Font, name_id: u16) -> Result<Option<String>, Box<dyn Error>> {
+    let table = match font.tables.name()? {
+        Some(table) => table,
+        None => return Ok(None),
+    };
+    let mut records: Vec<&NameRecord> = table
+        .records
+        .iter()
+        .filter(|r| r.nameID == name_id)
+        .collect();
+    records.sort_by_key(|r| !(r.platformID == 3 && r.languageID == 0x409));
+    Ok(records.first().map(|r| r.string.clone()))
+}
+
+/// Converts a TrueType contour to a cubic path with PostScript (counter-
+/// clockwise) direction.
+fn contour_to_cubic_path(contour: &[Point]) -> BezPath {
+    let mut contour = contour.to_vec();
+    // Reverse the direction, keeping the start point
+    contour[1..].reverse();
+    let quadratic = contourutils::glyf_contour_to_kurbo_contour(&contour);
+    let mut path = BezPath::new();
+    let mut current = kurbo::Point::ZERO;
+    for el in quadratic.elements() {
+        match *el {
+            PathEl::QuadTo(p1, p2) => {
+                let cubic = kurbo::QuadBez::new(current, p1, p2).raise();
+                path.curve_to(cubic.p1, cubic.p2, cubic.p3);
+                current = p2;
+            }
+            PathEl::MoveTo(p) | PathEl::LineTo(p) | PathEl::CurveTo(_, _, p) => {
+                path.push(*el);
+                current = p;
+            }
+            PathEl::ClosePath => path.close_path(),
+        }
+    }
+    path
+}
+
+/// Builds a CFF table from a font's TrueType outlines.
+pub(crate) fn glyf_to_cff(font: &Font) -> Result<CFF, Box<dyn Error>> {
+    let mut glyf = font
+        .tables
+        .glyf()?
+        .ok_or_else(|| error("Font has no glyf table"))?;
+    let glyf: &mut glyf::glyf = &mut glyf;
+    glyf.flatten_components();
+    let num_glyphs = glyf.glyphs.len();
+    let widths: Vec<f64> = match font.tables.hmtx()? {
+        Some(hmtx) => hmtx.metrics.iter().map(|m| m.advanceWidth as f64).collect(),
+        None => vec![0.0; num_glyphs],
+    };
+    let head = font
+        .tables
+        .head()?
+        .ok_or_else(|| error("Font has no head table"))?;
+
+    // The most common width needs no width in the charstring
+    let mut width_counts: BTreeMap<i64, usize> = BTreeMap::new();
+    for &width in &widths {
+        *width_counts.entry(width as i64).or_default() += 1;
+    }
+    let default_width = width_counts
+        .iter()
+        .max_by_key(|(&width, &count)| (count, -width))
+        .map_or(0.0, |(&width, _)| width as f64);
+
+    let mut charstrings = vec![];
+    for (gid, glyph) in glyf.glyphs.iter().enumerate() {
+        let decomposed = glyph.decompose(&glyf.glyphs);
+        let mut path = BezPath::new();
+        for contour in decomposed.contours.iter().filter(|c| !c.is_empty()) {
+            path.extend(contour_to_cubic_path(contour));
+        }
+        let width = widths.get(gid).copied().unwrap_or(default_width);
+        let width = if width == default_width {
+            None
+        } else {
+            Some(width - default_width)
+        };
+        let mut tokens = charstring::path_to_tokens(&path, width);
+        tokens.push(charstring::Token::Operator(charstring::operators::ENDCHAR));
+        charstrings.push(charstring::encode(&tokens));
+    }
+
+    let mut private = PrivateDict::default();
+    private.dict.set(
+        operators::DEFAULT_WIDTH_X,
+        vec![Operand::from(default_width)],
+    );
+    private.dict.set(
+        operators::NOMINAL_WIDTH_X,
+        vec![Operand::from(default_width)],
+    );
+    let mut cff = CFF {
+        name: name_string(font, 6)?.unwrap_or_else(|| "Untitled".to_string()),
+        top_dict: Dict::new(),
+        strings: vec![],
+        global_subrs: vec![],
+        charstrings,
+        charset: Charset::Custom(vec![]),
+        encoding: Some(Encoding::Standard),
+        private: Some(private),
+        fd_array: vec![],
+        fd_select: vec![],
+    };
+    for (op, name_id) in &[
+        (operators::FULL_NAME, 4),
+        (operators::FAMILY_NAME, 1),
+        (operators::NOTICE, 7),
+    ] {
+        if let Some(string) = name_string(font, *name_id)? {
+            let sid = cff.add_string(&string);
+            cff.top_dict.set(*op, vec![(sid as i32).into()]);
+        }
+    }
+    if let Some(post) = font.tables.post()? {
+        cff.top_dict.set(
+            operators::ITALIC_ANGLE,
+            vec![Operand::from(post.italicAngle as f64)],
+        );
+        cff.top_dict.set(
+            operators::UNDERLINE_POSITION,
+            vec![(post.underlinePosition as i32).into()],
+        );
+        cff.top_dict.set(
+            operators::UNDERLINE_THICKNESS,
+            vec![(post.underlineThickness as i32).into()],
+        );
+        if post.isFixedPitch != 0 {
+            cff.top_dict.set(operators::IS_FIXED_PITCH, vec![1.into()]);
+        }
+    }
+    cff.top_dict.set(
+        operators::FONT_BBOX,
+        vec![
+            (head.xMin as i32).into(),
+            (head.yMin as i32).into(),
+            (head.xMax as i32).into(),
+            (head.yMax as i32).into(),
+        ],
+    );
+    if head.unitsPerEm != 1000 {
+        let scale = 1.0 / head.unitsPerEm as f64;
+        cff.top_dict.set(
+            operators::FONT_MATRIX,
+            vec![
+                scale.into(),
+                0.into(),
+                0.into(),
+                scale.into(),
+                0.into(),
+                0.into(),
+            ],
+        );
+    }
+    let names = glyph_names(font, num_glyphs)?;
+    let sids = names.iter().skip(1).map(|n| cff.add_string(n)).collect();
+    cff.charset = Charset::Custom(sids);
+    cff.subroutinize()?;
+    Ok(cff)
+}
+
+/// Builds a glyf table from a font's CFF outlines, approximating cubic
+/// curves within the given error.
+pub(crate) fn cff_to_glyf(cff: &CFF, error: f32) -> Result<glyf::glyf, Box<dyn Error>> {
+    let mut glyphs = vec![];
+    for gid in 0..cff.num_glyphs() {
+        let outline = cff.outline(gid)?;
+        let mut contours = cu2qu::paths_to_quadratic(&[outline.path], error as f64)?
+            .contours
+            .remove(0);
+        for contour in contours.iter_mut() {
+            // Reverse the direction, keeping the start point
+            contour[1..].reverse();
+            contourutils::remove_implied_oncurves(contour);
+        }
+        glyphs.push(Glyph {
+            xMin: 0,
+            xMax: 0,
+            yMin: 0,
+            yMax: 0,
+            contours,
+            instructions: vec![],
+            components: vec![],
+            overlap: false,
+        });
+    }
+    let mut glyf = glyf::glyf { glyphs };
+    glyf.recalc_bounds();
+    Ok(glyf)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::font::{Font, SfntVersion};
+    use crate::tables;
+    use crate::tables::glyf::{Glyph, Point};
+    use crate::tables::hmtx::Metric;
+    use crate::testing;
+
+    fn glyph(contours: Vec<Vec<(i16, i16, bool)>>) -> Glyph {
+        Glyph {
+            xMin: 0,
+            xMax: 0,
+            yMin: 0,
+            yMax: 0,
+            contours: contours
+                .into_iter()
+                .map(|c| {
+                    c.into_iter()
+                        .map(|(x, y, on_curve)| Point { x, y, on_curve })
+                        .collect()
+                })
+                .collect(),
+            instructions: vec![],
+            components: vec![],
+            overlap: false,
+        }
+    }
+
+    fn truetype_font() -> Font {
+        let mut font = testing::test_font(2);
+        #[rustfmt::skip]
+        let glyphs = vec![
+            glyph(vec![]),
+            glyph(vec![
+                vec![(20, 0, true), (220, 0, true), (100, 200, true)],
+                vec![
+                    (514, 290, false), (568, 236, false), (568, 164, false),
+                    (514, 110, false), (440, 110, false), (386, 164, false),
+                    (386, 236, false), (440, 290, false),
+                ],
+            ]),
+        ];
+        let mut glyf = tables::glyf::glyf { glyphs };
+        glyf.recalc_bounds();
+        font.tables.insert(glyf);
+        font.tables
+            .insert(tables::head::new(1.0, 1000, 0, 0, 568, 290));
+        font.tables.insert(testing::hhea(800));
+        font.tables.insert(tables::hmtx::hmtx {
+            metrics: vec![
+                Metric {
+                    advanceWidth: 500,
+                    lsb: 0,
+                },
+                Metric {
+                    advanceWidth: 600,
+                    lsb: 20,
+                },
+            ],
+        });
+        font.tables
+            .insert(tables::maxp::maxp::new10(2, 12, 2, 0, 0, 0, 0));
+        font.tables.insert(tables::post::post::new(
+            2.0,
+            0.0,
+            -100,
+            50,
+            false,
+            Some(vec![".notdef".to_string(), "a".to_string()]),
+        ));
+        font
+    }
+
+    #[test]
+    fn test_flavor_roundtrip() {
+        let mut font = truetype_font();
+        let mut expected = font.tables.glyf().unwrap().unwrap().glyphs.clone();
+        // A contour without on-curve points starts at an implied point
+        expected[1].contours[1].rotate_left(1);
+        font.to_cff_flavor().unwrap();
+        assert_eq!(font.sfnt_version(), SfntVersion::OpenType);
+        assert!(!font.tables.contains(b"glyf"));
+        assert!(!font.tables.contains(b"loca"));
+        assert_eq!(
+            font.tables.maxp().unwrap().unwrap().version,
+            otspec::types::U16F16::from_num(0.5)
+        );
+
+        let mut data = vec![];
+        font.write(&mut data).unwrap();
+        let mut font = Font::from_bytes(&data).unwrap();
+        let cff = font.tables.CFF().unwrap().unwrap();
+        assert_eq!(cff.glyph_names(), vec![".notdef", "a"]);
+        assert_eq!(cff.outline(0).unwrap().width, 500.0);
+        assert_eq!(cff.outline(1).unwrap().width, 600.0);
+        drop(cff);
+
+        font.to_truetype_flavor(1.0).unwrap();
+        assert_eq!(font.sfnt_version(), SfntVersion::TrueType);
+        assert!(!font.tables.contains(b"CFF "));
+        let glyf = font.tables.glyf().unwrap().unwrap();
+        assert_eq!(glyf.glyphs, expected);
+        let post = font.tables.post().unwrap().unwrap();
+        assert_eq!(
+            post.glyphnames,
+            Some(vec![".notdef".to_string(), "a".to_string()])
+        );
+        assert_eq!(font.tables.hmtx().unwrap().unwrap().metrics[1].lsb, 20);
+    }
+}
@@ -1,4 +1,8 @@
+use crate::flavor;
+use crate::subset;
 use crate::tables;
+use crate::woff;
+use crate::woff2;
 use otspec::types::*;
 use otspec::{
     DeserializationError, Deserialize, Deserializer, ReaderContext, SerializationError, Serialize,
@@ -7,11 +11,13 @@ use otspec::{
 use otspec_macros::{Deserialize, Serialize};
 
 use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::{TryFrom, TryInto};
 use std::error::Error;
 use std::io::Read;
 use std::num::Wrapping;
 use std::path::Path;
+use std::rc::Rc;
 
 /// Magic number used to identify the font type
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -36,12 +42,19 @@ impl TryFrom<u32> for SfntVersion {
 
 /// Low-level structure used for serializing/deserializing entries in the table directory
 #[derive(Serialize, Deserialize, Debug)]
-struct TableRecord {
-    tag: Tag,
-    checksum: uint32,
-    offset: uint32,
-    length: uint32,
+pub(crate) struct TableRecord {
+    pub(crate) tag: Tag,
+    pub(crate) checksum: uint32,
+    pub(crate) offset: uint32,
+    pub(crate) length: uint32,
 }
+/// A binary table, along with its checksum, ready to be written into a font file
+pub(crate) struct CompiledTable {
+    pub(crate) tag: Tag,
+    pub(crate) checksum: uint32,
+    pub(crate) data: Rc<[u8]>,
+}
+
 /// The header of the font's table directory
 #[derive(Deserialize)]
 #[allow(non_snake_case)]
@@ -72,7 +85,19 @@ impl Font {
     }
 
     /// Attempt to load a font from a raw byte slice.
+    ///
+    /// OpenType, WOFF and WOFF2 files are supported.
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if woff::is_woff(bytes) {
+            return woff::decode(bytes)
+                .map(|(font, _)| font)
+                .map_err(|e| e.into());
+        }
+        if woff2::is_woff2(bytes) {
+            return woff2::decode(bytes)
+                .map(|(font, _)| font)
+                .map_err(|e| e.into());
+        }
         otspec::de::from_bytes(bytes).map_err(|e| e.into())
     }
 
@@ -92,6 +117,11 @@ impl Font {
         }
     }
 
+    /// The font's version (TrueType/OpenType)
+    pub fn sfnt_version(&self) -> SfntVersion {
+        self.sfntVersion
+    }
+
     //FIXME: do we want to keep this? do we want top-level methods generally?
     /// Returns `true` if the font contains a table with this `Tag`.
     pub fn contains_table(&self, tag: Tag) -> bool {
@@ -115,13 +145,93 @@ impl Font {
 
     /// Attempt to write the font into the provided [`Writer`][std::io::Write];
     pub fn write(&mut self, mut writer: impl std::io::Write) -> Result<(), Box<dyn Error>> {
-        self.tables.compile_glyf_loca_maxp();
-        self.tables.compile_gsub_gpos();
+        self.compile()?;
         let mut bytes = Vec::new();
         self.to_bytes(&mut bytes)?;
         writer.write_all(&bytes).map_err(Into::into)
     }
 
+    /// Attempt to save the font to the provided path as a WOFF file.
+    pub fn save_woff(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: &woff::WoffOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = std::fs::File::create(path)?;
+        self.write_woff(file, options)
+    }
+
+    /// Attempt to write the font as a WOFF file into the provided [`Writer`][std::io::Write].
+    ///
+    /// The `options` provide the WOFF file's version and its optional
+    /// metadata and private data blocks.
+    pub fn write_woff(
+        &mut self,
+        mut writer: impl std::io::Write,
+        options: &woff::WoffOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        self.compile()?;
+        let bytes = woff::encode(self, options)?;
+        writer.write_all(&bytes).map_err(Into::into)
+    }
+
+    /// Attempt to save the font to the provided path as a WOFF2 file.
+    pub fn save_woff2(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: &woff::WoffOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = std::fs::File::create(path)?;
+        self.write_woff2(file, options)
+    }
+
+    /// Attempt to write the font as a WOFF2 file into the provided [`Writer`][std::io::Write].
+    ///
+    /// The `options` provide the WOFF2 file's version and its optional
+    /// metadata and private data blocks.
+    pub fn write_woff2(
+        &mut self,
+        mut writer: impl std::io::Write,
+        options: &woff::WoffOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        self.compile()?;
+        let bytes = woff2::encode(std::slice::from_ref(self), options, false)?;
+        writer.write_all(&bytes).map_err(Into::into)
+    }
+
+    /// Brings interdependent tables up to date before serialization.
+    pub(crate) fn compile(&mut self) -> Result<(), SerializationError> {
+        self.tables.compile_glyf_loca_maxp()?;
+        self.tables.compile_hhea()?;
+        self.tables.compile_vhea()?;
+        self.tables.compile_gsub_gpos()
+    }
+
+    /// Serializes the font and splits the result back into its binary tables.
+    ///
+    /// The tables are exactly as they would be written to a standalone font
+    /// file, including the `head` table's `checksumAdjustment`; this is what
+    /// container formats such as collections and WOFF need to store.
+    pub(crate) fn compiled_tables(&self) -> Result<Vec<CompiledTable>, SerializationError> {
+        let standalone = otspec::ser::to_bytes(self)?;
+        let mut c = ReaderContext::new(standalone);
+        let header: TableHeader = c.de().map_err(|e| SerializationError(e.0))?;
+        let records: Vec<TableRecord> = c
+            .de_counted(header.numTables as usize)
+            .map_err(|e| SerializationError(e.0))?;
+        Ok(records
+            .into_iter()
+            .map(|tr| {
+                let start = tr.offset as usize;
+                CompiledTable {
+                    tag: tr.tag,
+                    checksum: tr.checksum,
+                    data: c.input[start..start + tr.length as usize].into(),
+                }
+            })
+            .collect())
+    }
+
     /// Total number of glyphs in the font, from the maxp table.
     ///
     /// Deserializes the maxp table if this is not already done.
@@ -136,6 +246,106 @@ impl Font {
         }
         self._numGlyphs.unwrap()
     }
+
+    /// Subsets the font to the given Unicode codepoints and glyph IDs.
+    ///
+    /// The glyph closure of the codepoints and glyphs is computed (see
+    /// [`subset::closure`]), the retained glyphs are renumbered, and the
+    /// tables which refer to glyphs are rewritten. Tables which refer to
+    /// glyphs but which cannot be subset are removed. Returns the mapping
+    /// from old to new glyph IDs.
+    pub fn subset(
+        &mut self,
+        unicodes: &BTreeSet<u32>,
+        glyphs: &BTreeSet<GlyphID>,
+        options: &subset::SubsetOptions,
+    ) -> Result<BTreeMap<GlyphID, GlyphID>, Box<dyn Error>> {
+        let glyph_map = subset::subset(self, unicodes, glyphs, options)?;
+        self._numGlyphs = None;
+        Ok(glyph_map)
+    }
+
+    /// Converts a font with TrueType outlines to CFF outlines.
+    ///
+    /// The `glyf` outlines are converted to cubic curves and stored in a
+    /// subroutinized `CFF ` table, with glyph names from the `post` table
+    /// (which becomes version 3.0). The `glyf` and `loca` tables and the
+    /// TrueType hinting tables are removed, `maxp` becomes version 0.5, and
+    /// the font's `SfntVersion` becomes `OpenType`. Variable fonts cannot
+    /// be converted.
+    pub fn to_cff_flavor(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.tables.contains(b"CFF ") {
+            return Ok(());
+        }
+        if self.tables.contains(b"gvar") {
+            return Err(flavor::error(
+                "Cannot convert a variable TrueType font to CFF",
+            ));
+        }
+        let cff = flavor::glyf_to_cff(self)?;
+        let num_glyphs = cff.num_glyphs() as u16;
+        for tag in flavor::TRUETYPE_TABLES.iter() {
+            self.tables.remove(*tag);
+        }
+        self.tables.insert(cff);
+        self.tables.insert(tables::maxp::maxp::new05(num_glyphs));
+        if let Some(mut post) = self.tables.post()? {
+            post.set_version(3.0);
+            post.glyphnames = None;
+            self.tables.insert(post);
+        }
+        self.sfntVersion = SfntVersion::OpenType;
+        Ok(())
+    }
+
+    /// Converts a font with CFF outlines to TrueType outlines.
+    ///
+    /// Cubic curves are approximated by quadratic curves within `error`
+    /// font units. The glyph names from the CFF charset are stored in the
+    /// `post` table (which becomes version 2.0), the `CFF ` and `VORG`
+    /// tables are removed, `maxp` becomes version 1.0, the horizontal
+    /// metrics' left side bearings are updated to match the new outlines,
+    /// and the font's `SfntVersion` becomes `TrueType`. Fonts with a `CFF2`
+    /// table cannot be converted.
+    pub fn to_truetype_flavor(&mut self, error: f32) -> Result<(), Box<dyn Error>> {
+        if self.tables.contains(b"glyf") {
+            return Ok(());
+        }
+        if self.tables.contains(b"CFF2") {
+            return Err(flavor::error("Cannot convert a CFF2 font to TrueType"));
+        }
+        let cff = self
+            .tables
+            .CFF()?
+            .ok_or_else(|| flavor::error("Font has no CFF table"))?;
+        let glyf = flavor::cff_to_glyf(&cff, error)?;
+
+        if let Some(mut hmtx) = self.tables.hmtx()? {
+            for (metric, glyph) in hmtx.metrics.iter_mut().zip(glyf.glyphs.iter()) {
+                metric.lsb = glyph.xMin;
+            }
+            self.tables.insert(hmtx);
+        }
+        self.tables.insert(glyf.as_maxp10());
+        let names = cff.glyph_names();
+        let mut post = self
+            .tables
+            .post()?
+            .map(|p| (*p).clone())
+            .unwrap_or_else(|| tables::post::post::new(2.0, 0.0, -100, 50, false, None));
+        post.set_version(2.0);
+        post.glyphnames = Some(names);
+        self.tables.insert(post);
+        if let Some(mut head) = self.tables.head()? {
+            head.glyphDataFormat = 0;
+            self.tables.insert(head);
+        }
+        self.tables.remove(tables::CFF::TAG);
+        self.tables.remove(crate::tag!("VORG"));
+        self.tables.insert(glyf);
+        self.sfntVersion = SfntVersion::TrueType;
+        Ok(())
+    }
 }
 
 /// Loads a binary font from the given filehandle.
@@ -191,7 +401,7 @@ impl Serialize for Font {
 
         let mut output: Vec<u8> = vec![];
         let mut output_tables: Vec<u8> = vec![];
-        let mut temp = Vec::new();
+        let mut temp;
 
         output.extend(&(self.sfntVersion as u32).to_be_bytes());
         output.extend(&lenu16.to_be_bytes());
@@ -201,8 +411,7 @@ impl Serialize for Font {
         let mut pos = 16 * self.tables.len() + 12;
         let mut head_pos: Option<usize> = None;
         for tag in self.tables.keys() {
-            temp.clear();
-            self.tables.write_table(tag, &mut temp)?;
+            temp = self.tables.serialize_table(tag)?.unwrap_or_default();
             if tag == tables::head::TAG {
                 head_pos = Some(pos);
                 temp[8..12].fill(0);
@@ -232,6 +441,20 @@ impl Serialize for Font {
 
 impl Deserialize for Font {
     fn from_bytes(c: &mut ReaderContext) -> Result<Self, DeserializationError> {
+        Font::from_table_directory(c, &mut BTreeMap::new())
+    }
+}
+
+impl Font {
+    /// Reads a font from the table directory at the current position.
+    ///
+    /// Table offsets are relative to the start of the input. Table data is
+    /// looked up in (and added to) `shared` by position, so that fonts in
+    /// a collection which refer to the same table share its data.
+    pub(crate) fn from_table_directory(
+        c: &mut ReaderContext,
+        shared: &mut BTreeMap<(u32, u32), Rc<[u8]>>,
+    ) -> Result<Self, DeserializationError> {
         let header: TableHeader = c.de()?;
         let version = TryInto::<SfntVersion>::try_into(header.sfntVersion).map_err(|_| {
             DeserializationError("Font must begin with a valid version".to_string())
@@ -249,8 +472,17 @@ impl Deserialize for Font {
         table_records.sort_by_key(|tr| tr.offset);
         for tr in table_records {
             let start = tr.offset as usize;
-            let this_table = &c.input[start..start + tr.length as usize];
-            raw_tables.add(tr.tag, this_table.into());
+            let end = start + tr.length as usize;
+            if end > c.input.len() {
+                return Err(DeserializationError(format!(
+                    "Table {} extends beyond end of file",
+                    tr.tag
+                )));
+            }
+            let this_table = shared
+                .entry((tr.offset, tr.length))
+                .or_insert_with(|| c.input[start..end].into());
+            raw_tables.add(tr.tag, this_table.clone());
         }
         Ok(Font {
             sfntVersion: version,
@@ -399,6 +631,37 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_hmetrics_without_glyf() {
+        // A CFF-flavoured font has no glyf table to trigger the hhea update
+        let mut font = crate::testing::test_font(3);
+        font.sfntVersion = SfntVersion::OpenType;
+        font.tables.insert(crate::tables::hmtx::hmtx {
+            metrics: [500, 600, 600]
+                .iter()
+                .map(|&advance| crate::tables::hmtx::Metric {
+                    advanceWidth: advance,
+                    lsb: 0,
+                })
+                .collect(),
+        });
+        let mut binary_font = vec![];
+        font.write(&mut binary_font).unwrap();
+
+        let font = Font::from_bytes(&binary_font).unwrap();
+        assert_eq!(font.tables.hhea().unwrap().unwrap().numberOfHMetrics, 2);
+        let advances: Vec<uint16> = font
+            .tables
+            .hmtx()
+            .unwrap()
+            .unwrap()
+            .metrics
+            .iter()
+            .map(|m| m.advanceWidth)
+            .collect();
+        assert_eq!(advances, vec![500, 600, 600]);
+    }
+
     // #[test]
     // fn test_load() {
     //     let f = font::load("data/test1.ttf").unwrap();
@@ -1,7 +1,11 @@
+/// Applying GSUB and GPOS lookups to a sequence of glyphs
+pub mod apply;
 /// High-level common structures for OpenType Layout
 pub mod common;
 /// Common tables for contextual lookup subtables
 pub mod contextual;
+/// Parameters of the size, stylistic set and character variant features
+pub mod feature_params;
 /// GPOS1 single positioning
 pub mod gpos1;
 /// GPOS2 pair positioning
@@ -25,3 +29,5 @@ pub mod gsub4;
 /// GSUB8 reverse chaining contextual single substitution
 pub mod gsub8;
 pub(crate) mod macros;
+/// Resolving offset overflows when compiling GSUB and GPOS tables
+pub(crate) mod overflow;
@@ -0,0 +1,983 @@
+use crate::font::Font;
+use crate::layout::common::{Lookup, LookupFlags, ValueRecord, GPOSGSUB};
+use crate::layout::contextual::{SequenceContextRule, Slot};
+use crate::tables::hmtx::hmtx;
+use crate::tables::GDEF::{GlyphClass, GDEF};
+use crate::tables::GPOS::{Positioning, GPOS};
+use crate::tables::GSUB::{Substitution, GSUB};
+use crate::tag;
+use otspec::layout::anchor::Anchor;
+use otspec::types::*;
+use otspec::DeserializationError;
+use std::collections::BTreeSet;
+
+/// How deeply contextual lookups may call other lookups
+const MAX_NESTING: usize = 64;
+
+/// The features which are applied by default.
+pub const DEFAULT_FEATURES: [Tag; 14] = [
+    tag!("abvm"),
+    tag!("blwm"),
+    tag!("calt"),
+    tag!("ccmp"),
+    tag!("clig"),
+    tag!("curs"),
+    tag!("dist"),
+    tag!("kern"),
+    tag!("liga"),
+    tag!("locl"),
+    tag!("mark"),
+    tag!("mkmk"),
+    tag!("rclt"),
+    tag!("rlig"),
+];
+
+/// The script, language and features to use when applying layout.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutSettings {
+    /// The script tag. If the table has no entry for this script, the
+    /// `DFLT` script is used.
+    pub script: Tag,
+    /// The language tag, or `None` for the default language system.
+    pub language: Option<Tag>,
+    /// The features to apply. Required features are always applied.
+    pub features: BTreeSet<Tag>,
+}
+
+impl Default for LayoutSettings {
+    fn default() -> Self {
+        LayoutSettings {
+            script: tag!("DFLT"),
+            language: None,
+            features: DEFAULT_FEATURES.iter().copied().collect(),
+        }
+    }
+}
+
+/// A glyph resulting from applying layout, with its position.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PositionedGlyph {
+    /// The glyph ID
+    pub glyph: GlyphID,
+    /// The index of the input glyph which this glyph came from. Glyphs
+    /// produced by a ligature take the index of its first component.
+    pub cluster: usize,
+    /// The horizontal advance
+    pub x_advance: i32,
+    /// The vertical advance
+    pub y_advance: i32,
+    /// The horizontal offset from the glyph's nominal position
+    pub x_offset: i32,
+    /// The vertical offset from the glyph's nominal position
+    pub y_offset: i32,
+}
+
+/// A glyph in the buffer during layout.
+#[derive(Debug, Clone, Copy)]
+struct Item {
+    glyph: PositionedGlyph,
+    /// Identifies the ligature this glyph is, or was attached to (0 if none)
+    lig_id: usize,
+    /// For marks attached to a ligature, the component they belong to,
+    /// counting from 1 (0 for the ligature itself)
+    lig_component: usize,
+}
+
+/// The lookups to apply for the given settings, in lookup order.
+fn selected_lookups<T>(table: &GPOSGSUB<T>, settings: &LayoutSettings) -> BTreeSet<usize> {
+    let scripts = &table.scripts.scripts;
+    let script = scripts
+        .get(&settings.script)
+        .or_else(|| scripts.get(&tag!("DFLT")));
+    let langsys = script.and_then(|script| {
+        settings
+            .language
+            .and_then(|language| script.language_systems.get(&language))
+            .or(script.default_language_system.as_ref())
+    });
+    let mut lookups = BTreeSet::new();
+    if let Some(langsys) = langsys {
+        for ix in langsys.all_feature_indices() {
+            if let Some((tag, feature_lookups, _)) = table.features.get(ix) {
+                if Some(ix) == langsys.required_feature || settings.features.contains(tag) {
+                    lookups.extend(
+                        feature_lookups
+                            .iter()
+                            .copied()
+                            .filter(|&l| l < table.lookups.len()),
+                    );
+                }
+            }
+        }
+    }
+    lookups
+}
+
+/// The state of the glyph buffer while applying lookups from one table.
+struct Context<'a, T> {
+    table: &'a GPOSGSUB<T>,
+    gdef: Option<&'a GDEF>,
+    buffer: Vec<Item>,
+    next_lig_id: usize,
+}
+
+/// A lookup type which can be applied to a glyph buffer.
+trait Apply: Sized {
+    /// Applies the lookup at a position in the buffer. If it applied,
+    /// returns the position at which processing should continue, which is
+    /// the same position if the glyph there was deleted.
+    fn apply(
+        ctx: &mut Context<Self>,
+        lookup: &Lookup<Self>,
+        i: usize,
+        depth: usize,
+    ) -> Option<usize>;
+
+    /// Whether the lookup is applied from the end of the buffer to the start.
+    fn is_reverse(&self) -> bool {
+        false
+    }
+}
+
+impl<'a, T: Apply> Context<'a, T> {
+    fn glyph(&self, i: usize) -> GlyphID {
+        self.buffer[i].glyph.glyph
+    }
+
+    fn glyph_class(&self, glyph: GlyphID) -> Option<GlyphClass> {
+        self.gdef
+            .and_then(|gdef| gdef.glyph_class.get(&glyph))
+            .copied()
+    }
+
+    fn is_mark(&self, i: usize) -> bool {
+        self.glyph_class(self.glyph(i)) == Some(GlyphClass::MarkGlyph)
+    }
+
+    /// Whether the lookup flags say that this glyph should be skipped.
+    fn skip(&self, lookup: &Lookup<T>, i: usize) -> bool {
+        let glyph = self.glyph(i);
+        let flags = lookup.flags;
+        match self.glyph_class(glyph) {
+            Some(GlyphClass::BaseGlyph) => flags.contains(LookupFlags::IGNORE_BASE_GLYPHS),
+            Some(GlyphClass::LigatureGlyph) => flags.contains(LookupFlags::IGNORE_LIGATURES),
+            Some(GlyphClass::MarkGlyph) => {
+                let gdef = self.gdef.unwrap();
+                if flags.contains(LookupFlags::IGNORE_MARKS) {
+                    return true;
+                }
+                if flags.contains(LookupFlags::USE_MARK_FILTERING_SET) {
+                    return !lookup
+                        .mark_filtering_set
+                        .and_then(|set| gdef.mark_glyph_sets.as_ref()?.get(set as usize))
+                        .is_some_and(|set| set.contains(&glyph));
+                }
+                let mark_type = (flags & LookupFlags::MARK_ATTACHMENT_TYPE_MASK).bits() >> 8;
+                mark_type != 0 && gdef.mark_attachment_class.get(&glyph) != Some(&mark_type)
+            }
+            _ => false,
+        }
+    }
+
+    fn next(&self, lookup: &Lookup<T>, i: usize) -> Option<usize> {
+        (i + 1..self.buffer.len()).find(|&j| !self.skip(lookup, j))
+    }
+
+    fn prev(&self, lookup: &Lookup<T>, i: usize) -> Option<usize> {
+        (0..i).rev().find(|&j| !self.skip(lookup, j))
+    }
+
+    /// Matches a sequence of glyph classes starting at position `i`,
+    /// returning the matched positions.
+    fn match_input<'s>(
+        &self,
+        lookup: &Lookup<T>,
+        i: usize,
+        mut slots: impl Iterator<Item = &'s Slot>,
+    ) -> Option<Vec<usize>> {
+        let first = slots.next()?;
+        if !first.contains(&self.glyph(i)) {
+            return None;
+        }
+        let mut positions = vec![i];
+        for slot in slots {
+            let j = self.next(lookup, *positions.last().unwrap())?;
+            if !slot.contains(&self.glyph(j)) {
+                return None;
+            }
+            positions.push(j);
+        }
+        Some(positions)
+    }
+
+    /// Matches glyph classes before position `i`, closest first.
+    fn match_backtrack(&self, lookup: &Lookup<T>, i: usize, slots: &[Slot]) -> bool {
+        let mut j = i;
+        slots.iter().all(|slot| match self.prev(lookup, j) {
+            Some(prev) if slot.contains(&self.glyph(prev)) => {
+                j = prev;
+                true
+            }
+            _ => false,
+        })
+    }
+
+    /// Matches glyph classes after position `i`.
+    fn match_lookahead(&self, lookup: &Lookup<T>, i: usize, slots: &[Slot]) -> bool {
+        let mut j = i;
+        slots.iter().all(|slot| match self.next(lookup, j) {
+            Some(next) if slot.contains(&self.glyph(next)) => {
+                j = next;
+                true
+            }
+            _ => false,
+        })
+    }
+
+    fn match_rule(
+        &self,
+        lookup: &Lookup<T>,
+        i: usize,
+        rule: &SequenceContextRule,
+        backtrack: &[Slot],
+        lookahead: &[Slot],
+    ) -> Option<Vec<usize>> {
+        let positions = self.match_input(lookup, i, rule.iter().map(|(slot, _)| slot))?;
+        if self.match_backtrack(lookup, i, backtrack)
+            && self.match_lookahead(lookup, *positions.last().unwrap(), lookahead)
+        {
+            Some(positions)
+        } else {
+            None
+        }
+    }
+
+    /// Applies the lookups of a matched contextual rule, returning the
+    /// position after the matched input.
+    fn apply_nested(
+        &mut self,
+        rule: &SequenceContextRule,
+        mut positions: Vec<usize>,
+        depth: usize,
+    ) -> usize {
+        let table = self.table;
+        let mut end = positions.last().map_or(0, |&p| p as isize + 1);
+        for (k, (_, lookups)) in rule.iter().enumerate() {
+            for &ix in lookups {
+                let nested = match table.lookups.get(ix as usize) {
+                    Some(nested) if depth < MAX_NESTING => nested,
+                    _ => continue,
+                };
+                let at = positions[k];
+                if at >= self.buffer.len() || self.skip(nested, at) {
+                    continue;
+                }
+                let before = self.buffer.len() as isize;
+                T::apply(self, nested, at, depth + 1);
+                let delta = self.buffer.len() as isize - before;
+                for position in positions[k + 1..].iter_mut() {
+                    *position = (*position as isize + delta).max(0) as usize;
+                }
+                end += delta;
+            }
+        }
+        end.max(0) as usize
+    }
+
+    /// Applies a lookup across the whole buffer.
+    fn run_lookup(&mut self, ix: usize) {
+        let table = self.table;
+        let lookup = &table.lookups[ix];
+        if lookup.rule.is_reverse() {
+            for i in (0..self.buffer.len()).rev() {
+                if !self.skip(lookup, i) {
+                    T::apply(self, lookup, i, 0);
+                }
+            }
+            return;
+        }
+        let mut i = 0;
+        while i < self.buffer.len() {
+            if self.skip(lookup, i) {
+                i += 1;
+                continue;
+            }
+            let before = self.buffer.len();
+            i = match T::apply(self, lookup, i, 0) {
+                // The glyph at `i` was deleted, so look at its successor
+                Some(next) if next <= i && self.buffer.len() < before => i,
+                Some(next) => next.max(i + 1),
+                None => i + 1,
+            };
+        }
+    }
+
+    fn run(&mut self, settings: &LayoutSettings) {
+        for ix in selected_lookups(self.table, settings) {
+            self.run_lookup(ix);
+        }
+    }
+}
+
+impl Context<'_, Substitution> {
+    fn set_glyph(&mut self, i: usize, glyph: GlyphID) {
+        self.buffer[i].glyph.glyph = glyph;
+    }
+
+    fn replace(&mut self, i: usize, glyphs: &[GlyphID]) {
+        let item = self.buffer[i];
+        self.buffer.splice(
+            i..i + 1,
+            glyphs.iter().map(|&glyph| {
+                let mut new = item;
+                new.glyph.glyph = glyph;
+                new
+            }),
+        );
+    }
+
+    /// Replaces the glyphs at the given positions with a ligature. Skipped
+    /// glyphs between the components are kept after the ligature, and
+    /// remember which component they followed.
+    fn ligate(&mut self, positions: &[usize], glyph: GlyphID) {
+        self.next_lig_id += 1;
+        let lig_id = self.next_lig_id;
+        let first = positions[0];
+        let last = *positions.last().unwrap();
+        let mut component = 1;
+        for j in first + 1..=last {
+            if positions.contains(&j) {
+                component += 1;
+            } else {
+                self.buffer[j].lig_id = lig_id;
+                self.buffer[j].lig_component = component;
+            }
+        }
+        self.buffer[first].glyph.glyph = glyph;
+        self.buffer[first].lig_id = lig_id;
+        for &j in positions[1..].iter().rev() {
+            self.buffer.remove(j);
+        }
+    }
+}
+
+impl Apply for Substitution {
+    fn apply(
+        ctx: &mut Context<Self>,
+        lookup: &Lookup<Self>,
+        i: usize,
+        depth: usize,
+    ) -> Option<usize> {
+        let glyph = ctx.glyph(i);
+        match &lookup.rule {
+            Substitution::Single(subtables) => {
+                let new = subtables.iter().find_map(|st| st.mapping.get(&glyph))?;
+                ctx.set_glyph(i, *new);
+                Some(i + 1)
+            }
+            Substitution::Multiple(subtables) => {
+                let new = subtables.iter().find_map(|st| st.mapping.get(&glyph))?;
+                ctx.replace(i, new);
+                Some(i + new.len())
+            }
+            Substitution::Alternate(subtables) => {
+                let new = subtables.iter().find_map(|st| st.mapping.get(&glyph))?;
+                ctx.set_glyph(i, *new.first()?);
+                Some(i + 1)
+            }
+            Substitution::Ligature(subtables) => {
+                for st in subtables {
+                    let mut candidates: Vec<_> = st
+                        .mapping
+                        .iter()
+                        .filter(|(components, _)| components.first() == Some(&glyph))
+                        .collect();
+                    // Longest ligatures first
+                    candidates.sort_by_key(|(components, _)| std::cmp::Reverse(components.len()));
+                    for (components, ligature) in candidates {
+                        let slots: Vec<Slot> = components
+                            .iter()
+                            .map(|&g| std::iter::once(g).collect())
+                            .collect();
+                        if let Some(positions) = ctx.match_input(lookup, i, slots.iter()) {
+                            ctx.ligate(&positions, *ligature);
+                            return Some(i + 1);
+                        }
+                    }
+                }
+                None
+            }
+            Substitution::Contextual(subtables) => {
+                for rule in subtables.iter().flat_map(|st| st.rules.iter()) {
+                    if let Some(positions) = ctx.match_rule(lookup, i, rule, &[], &[]) {
+                        return Some(ctx.apply_nested(rule, positions, depth));
+                    }
+                }
+                None
+            }
+            Substitution::ChainedContextual(subtables) => {
+                for rule in subtables.iter().flat_map(|st| st.rules.iter()) {
+                    if let Some(positions) =
+                        ctx.match_rule(lookup, i, &rule.input, &rule.backtrack, &rule.lookahead)
+                    {
+                        return Some(ctx.apply_nested(&rule.input, positions, depth));
+                    }
+                }
+                None
+            }
+            Substitution::ReverseChainContextual(subtables) => {
+                for st in subtables {
+                    if let Some(&new) = st.mapping.get(&glyph) {
+                        if ctx.match_backtrack(lookup, i, &st.backtrack)
+                            && ctx.match_lookahead(lookup, i, &st.lookahead)
+                        {
+                            ctx.set_glyph(i, new);
+                            return Some(i + 1);
+                        }
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn is_reverse(&self) -> bool {
+        matches!(self, Substitution::ReverseChainContextual(_))
+    }
+}
+
+impl Context<'_, Positioning> {
+    fn adjust(&mut self, i: usize, value: &ValueRecord) {
+        let glyph = &mut self.buffer[i].glyph;
+        glyph.x_offset += value.xPlacement.unwrap_or(0) as i32;
+        glyph.y_offset += value.yPlacement.unwrap_or(0) as i32;
+        glyph.x_advance += value.xAdvance.unwrap_or(0) as i32;
+        glyph.y_advance += value.yAdvance.unwrap_or(0) as i32;
+    }
+
+    /// Positions the mark at `i` so that its anchor lies on the base's.
+    fn attach(&mut self, i: usize, base: usize, base_anchor: &Anchor, mark_anchor: &Anchor) {
+        let advance: i32 = self.buffer[base..i]
+            .iter()
+            .map(|item| item.glyph.x_advance)
+            .sum();
+        let base_glyph = self.buffer[base].glyph;
+        let mark = &mut self.buffer[i].glyph;
+        mark.x_offset = base_glyph.x_offset + base_anchor.xCoordinate as i32
+            - mark_anchor.xCoordinate as i32
+            - advance;
+        mark.y_offset =
+            base_glyph.y_offset + base_anchor.yCoordinate as i32 - mark_anchor.yCoordinate as i32;
+    }
+
+    /// The nearest glyph before `i` which is not a mark and is not skipped
+    /// by the lookup flags.
+    fn prev_base(&self, lookup: &Lookup<Positioning>, i: usize) -> Option<usize> {
+        (0..i)
+            .rev()
+            .find(|&j| !self.is_mark(j) && !self.skip(lookup, j))
+    }
+
+    /// Whether two marks may attach to each other: they must belong to the
+    /// same base, or the same component of a ligature, unless one of them
+    /// is itself a ligature.
+    fn same_component(&self, i: usize, j: usize) -> bool {
+        let (a, b) = (self.buffer[i], self.buffer[j]);
+        if a.lig_id == b.lig_id {
+            a.lig_id == 0 || a.lig_component == b.lig_component
+        } else {
+            (a.lig_id != 0 && a.lig_component == 0) || (b.lig_id != 0 && b.lig_component == 0)
+        }
+    }
+
+    fn connect(
+        &mut self,
+        lookup: &Lookup<Positioning>,
+        i: usize,
+        j: usize,
+        exit: &Anchor,
+        entry: &Anchor,
+    ) {
+        let exit_x = exit.xCoordinate as i32;
+        let entry_x = entry.xCoordinate as i32;
+        let dy = exit.yCoordinate as i32 - entry.yCoordinate as i32;
+        self.buffer[i].glyph.x_advance = exit_x + self.buffer[i].glyph.x_offset;
+        let d = entry_x + self.buffer[j].glyph.x_offset;
+        self.buffer[j].glyph.x_advance -= d;
+        self.buffer[j].glyph.x_offset -= d;
+        if lookup.flags.contains(LookupFlags::RIGHT_TO_LEFT) {
+            self.buffer[i].glyph.y_offset = self.buffer[j].glyph.y_offset - dy;
+        } else {
+            self.buffer[j].glyph.y_offset = self.buffer[i].glyph.y_offset + dy;
+        }
+    }
+}
+
+impl Apply for Positioning {
+    fn apply(
+        ctx: &mut Context<Self>,
+        lookup: &Lookup<Self>,
+        i: usize,
+        depth: usize,
+    ) -> Option<usize> {
+        let glyph = ctx.glyph(i);
+        match &lookup.rule {
+            Positioning::Single(subtables) => {
+                let value = subtables.iter().find_map(|st| st.mapping.get(&glyph))?;
+                ctx.adjust(i, value);
+                Some(i + 1)
+            }
+            Positioning::Pair(subtables) => {
+                let j = ctx.next(lookup, i)?;
+                let pair = (glyph, ctx.glyph(j));
+                let (first, second) = subtables.iter().find_map(|st| st.mapping.get(&pair))?;
+                ctx.adjust(i, first);
+                ctx.adjust(j, second);
+                Some(if second.has_any() { j + 1 } else { j })
+            }
+            Positioning::Cursive(subtables) => {
+                let j = ctx.next(lookup, i)?;
+                let next_glyph = ctx.glyph(j);
+                for st in subtables {
+                    let exit = st.mapping.get(&glyph).and_then(|(_, exit)| exit.as_ref());
+                    let entry = st
+                        .mapping
+                        .get(&next_glyph)
+                        .and_then(|(entry, _)| entry.as_ref());
+                    if let (Some(exit), Some(entry)) = (exit, entry) {
+                        ctx.connect(lookup, i, j, exit, entry);
+                        return Some(j);
+                    }
+                }
+                None
+            }
+            Positioning::MarkToBase(subtables) => {
+                let base = ctx.prev_base(lookup, i)?;
+                let base_glyph = ctx.glyph(base);
+                for st in subtables {
+                    if let Some((class, mark_anchor)) = st.marks.get(&glyph) {
+                        if let Some(base_anchor) =
+                            st.bases.get(&base_glyph).and_then(|a| a.get(class))
+                        {
+                            ctx.attach(i, base, base_anchor, mark_anchor);
+                            return Some(i + 1);
+                        }
+                    }
+                }
+                None
+            }
+            Positioning::MarkToLig(subtables) => {
+                let base = ctx.prev_base(lookup, i)?;
+                let base_glyph = ctx.glyph(base);
+                let (mark_item, lig_item) = (ctx.buffer[i], ctx.buffer[base]);
+                for st in subtables {
+                    let (class, mark_anchor) = match st.marks.get(&glyph) {
+                        Some(mark) => mark,
+                        None => continue,
+                    };
+                    let components = match st.ligatures.get(&base_glyph) {
+                        Some(components) if !components.is_empty() => components,
+                        _ => continue,
+                    };
+                    let component = if mark_item.lig_id != 0 && mark_item.lig_id == lig_item.lig_id
+                    {
+                        mark_item
+                            .lig_component
+                            .saturating_sub(1)
+                            .min(components.len() - 1)
+                    } else {
+                        components.len() - 1
+                    };
+                    if let Some(base_anchor) = components[component].get(class) {
+                        ctx.attach(i, base, base_anchor, mark_anchor);
+                        return Some(i + 1);
+                    }
+                }
+                None
+            }
+            Positioning::MarkToMark(subtables) => {
+                let base = ctx.prev(lookup, i)?;
+                if !ctx.is_mark(base) || !ctx.same_component(i, base) {
+                    return None;
+                }
+                let base_glyph = ctx.glyph(base);
+                for st in subtables {
+                    if let Some((class, mark_anchor)) = st.combining_marks.get(&glyph) {
+                        if let Some(base_anchor) =
+                            st.base_marks.get(&base_glyph).and_then(|a| a.get(class))
+                        {
+                            ctx.attach(i, base, base_anchor, mark_anchor);
+                            return Some(i + 1);
+                        }
+                    }
+                }
+                None
+            }
+            Positioning::Contextual(subtables) => {
+                for rule in subtables.iter().flat_map(|st| st.rules.iter()) {
+                    if let Some(positions) = ctx.match_rule(lookup, i, rule, &[], &[]) {
+                        return Some(ctx.apply_nested(rule, positions, depth));
+                    }
+                }
+                None
+            }
+            Positioning::ChainedContextual(subtables) => {
+                for rule in subtables.iter().flat_map(|st| st.rules.iter()) {
+                    if let Some(positions) =
+                        ctx.match_rule(lookup, i, &rule.input, &rule.backtrack, &rule.lookahead)
+                    {
+                        return Some(ctx.apply_nested(&rule.input, positions, depth));
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+/// The tables used to apply layout to a sequence of glyphs.
+///
+/// Any table may be missing; substitution is skipped without a `GSUB`
+/// table and positioning without a `GPOS` table. Glyph advances are taken
+/// from `hmtx`, and glyph classes, mark attachment classes and mark
+/// filtering sets from `GDEF`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LayoutEngine<'a> {
+    /// The glyph substitution table
+    pub gsub: Option<&'a GSUB>,
+    /// The glyph positioning table
+    pub gpos: Option<&'a GPOS>,
+    /// The glyph definition table
+    pub gdef: Option<&'a GDEF>,
+    /// The horizontal metrics table
+    pub hmtx: Option<&'a hmtx>,
+}
+
+impl LayoutEngine<'_> {
+    /// Applies the GSUB lookups and then the GPOS lookups selected by the
+    /// settings to a sequence of glyphs.
+    ///
+    /// All selected features are applied to the whole sequence; no
+    /// script-specific shaping is done.
+    pub fn apply(&self, glyphs: &[GlyphID], settings: &LayoutSettings) -> Vec<PositionedGlyph> {
+        let mut buffer: Vec<Item> = glyphs
+            .iter()
+            .enumerate()
+            .map(|(cluster, &glyph)| Item {
+                glyph: PositionedGlyph {
+                    glyph,
+                    cluster,
+                    ..Default::default()
+                },
+                lig_id: 0,
+                lig_component: 0,
+            })
+            .collect();
+        let mut next_lig_id = 0;
+        if let Some(gsub) = self.gsub {
+            let mut ctx = Context {
+                table: gsub,
+                gdef: self.gdef,
+                buffer,
+                next_lig_id,
+            };
+            ctx.run(settings);
+            buffer = ctx.buffer;
+            next_lig_id = ctx.next_lig_id;
+        }
+        if let Some(hmtx) = self.hmtx {
+            for item in buffer.iter_mut() {
+                item.glyph.x_advance = hmtx
+                    .metrics
+                    .get(item.glyph.glyph as usize)
+                    .map_or(0, |m| m.advanceWidth as i32);
+            }
+        }
+        if let Some(gpos) = self.gpos {
+            let mut ctx = Context {
+                table: gpos,
+                gdef: self.gdef,
+                buffer,
+                next_lig_id,
+            };
+            ctx.run(settings);
+            buffer = ctx.buffer;
+        }
+        buffer.into_iter().map(|item| item.glyph).collect()
+    }
+}
+
+/// Applies a font's layout features to a sequence of glyphs.
+///
+/// See [`LayoutEngine::apply`].
+pub fn apply(
+    font: &Font,
+    glyphs: &[GlyphID],
+    settings: &LayoutSettings,
+) -> Result<Vec<PositionedGlyph>, DeserializationError> {
+    let gsub = font.tables.GSUB()?;
+    let gpos = font.tables.GPOS()?;
+    let gdef = font.tables.GDEF()?;
+    let hmtx = font.tables.hmtx()?;
+    let engine = LayoutEngine {
+        gsub: gsub.as_deref(),
+        gpos: gpos.as_deref(),
+        gdef: gdef.as_deref(),
+        hmtx: hmtx.as_deref(),
+    };
+    Ok(engine.apply(glyphs, settings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::common::{FeatureList, LanguageSystem, Script, ScriptList};
+    use crate::layout::gpos2::PairPos;
+    use crate::layout::gpos4::MarkBasePos;
+    use crate::layout::gpos6::MarkMarkPos;
+    use crate::layout::gsub2::MultipleSubst;
+    use crate::layout::gsub4::LigatureSubst;
+    use crate::tables::hmtx::Metric;
+    use otspec::btreemap;
+    use std::collections::BTreeMap;
+    use std::iter::FromIterator;
+
+    const F: GlyphID = 1;
+    const I: GlyphID = 2;
+    const F_I: GlyphID = 3;
+    const A: GlyphID = 4;
+    const ACUTE: GlyphID = 5;
+    const V: GlyphID = 6;
+    const GRAVE: GlyphID = 7;
+
+    fn table<T>(features: Vec<(Tag, Lookup<T>)>) -> GPOSGSUB<T> {
+        let mut lookups = vec![];
+        let mut feature_list = vec![];
+        for (ix, (tag, lookup)) in features.into_iter().enumerate() {
+            lookups.push(lookup);
+            feature_list.push((tag, vec![ix], None));
+        }
+        GPOSGSUB {
+            scripts: ScriptList {
+                scripts: btreemap!(tag!("DFLT") => Script {
+                    default_language_system: Some(LanguageSystem {
+                        required_feature: None,
+                        feature_indices: (0..lookups.len()).collect(),
+                    }),
+                    language_systems: BTreeMap::new(),
+                }),
+            },
+            lookups,
+            features: FeatureList::new(feature_list),
+            feature_variations: vec![],
+        }
+    }
+
+    fn lookup<T>(flags: LookupFlags, rule: T) -> Lookup<T> {
+        Lookup {
+            flags,
+            mark_filtering_set: None,
+            rule,
+        }
+    }
+
+    fn test_tables() -> (GSUB, GPOS, GDEF, hmtx) {
+        let gsub = table(vec![(
+            tag!("liga"),
+            lookup(
+                LookupFlags::IGNORE_MARKS,
+                Substitution::Ligature(vec![LigatureSubst {
+                    mapping: btreemap!(vec![F, I] => F_I),
+                }]),
+            ),
+        )]);
+        let kern = ValueRecord {
+            xAdvance: Some(-80),
+            ..Default::default()
+        };
+        let gpos = table(vec![
+            (
+                tag!("kern"),
+                lookup(
+                    LookupFlags::IGNORE_MARKS,
+                    Positioning::Pair(vec![PairPos {
+                        mapping: btreemap!((V, A) => (kern, ValueRecord::default())),
+                    }]),
+                ),
+            ),
+            (
+                tag!("mark"),
+                lookup(
+                    LookupFlags::empty(),
+                    Positioning::MarkToBase(vec![MarkBasePos {
+                        bases: btreemap!(A => btreemap!(0 => Anchor::new(250, 500))),
+                        marks: btreemap!(ACUTE => (0, Anchor::new(100, 0))),
+                    }]),
+                ),
+            ),
+        ]);
+        let gdef = GDEF {
+            glyph_class: btreemap!(
+                F => GlyphClass::BaseGlyph,
+                I => GlyphClass::BaseGlyph,
+                F_I => GlyphClass::LigatureGlyph,
+                A => GlyphClass::BaseGlyph,
+                ACUTE => GlyphClass::MarkGlyph,
+                V => GlyphClass::BaseGlyph
+            ),
+            attachment_point_list: BTreeMap::new(),
+            ligature_caret_list: BTreeMap::new(),
+            mark_attachment_class: BTreeMap::new(),
+            mark_glyph_sets: None,
+            item_variation_store: None,
+        };
+        let hmtx = hmtx {
+            metrics: (0..7)
+                .map(|gid| Metric {
+                    advanceWidth: if gid == ACUTE { 0 } else { 500 },
+                    lsb: 0,
+                })
+                .collect(),
+        };
+        (gsub, gpos, gdef, hmtx)
+    }
+
+    fn glyph(glyph: GlyphID, cluster: usize, x_advance: i32) -> PositionedGlyph {
+        PositionedGlyph {
+            glyph,
+            cluster,
+            x_advance,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_ligature_skips_marks() {
+        let (gsub, gpos, gdef, hmtx) = test_tables();
+        let engine = LayoutEngine {
+            gsub: Some(&gsub),
+            gpos: Some(&gpos),
+            gdef: Some(&gdef),
+            hmtx: Some(&hmtx),
+        };
+        let output = engine.apply(&[F, ACUTE, I], &LayoutSettings::default());
+        let glyphs: Vec<GlyphID> = output.iter().map(|g| g.glyph).collect();
+        assert_eq!(glyphs, vec![F_I, ACUTE]);
+        assert_eq!(output[0].cluster, 0);
+        assert_eq!(output[1].cluster, 1);
+
+        // Without GDEF, the mark is not known to be a mark
+        let engine = LayoutEngine {
+            gdef: None,
+            ..engine
+        };
+        let output = engine.apply(&[F, ACUTE, I], &LayoutSettings::default());
+        assert_eq!(output.len(), 3);
+
+        let settings = LayoutSettings {
+            features: BTreeSet::from_iter(vec![tag!("kern")]),
+            ..Default::default()
+        };
+        let output = engine.apply(&[F, I], &settings);
+        assert_eq!(output, vec![glyph(F, 0, 500), glyph(I, 1, 500)]);
+    }
+
+    #[test]
+    fn test_kerning_and_marks() {
+        let (gsub, gpos, gdef, hmtx) = test_tables();
+        let engine = LayoutEngine {
+            gsub: Some(&gsub),
+            gpos: Some(&gpos),
+            gdef: Some(&gdef),
+            hmtx: Some(&hmtx),
+        };
+        let output = engine.apply(&[V, A, ACUTE], &LayoutSettings::default());
+        assert_eq!(
+            output,
+            vec![
+                glyph(V, 0, 420),
+                glyph(A, 1, 500),
+                PositionedGlyph {
+                    glyph: ACUTE,
+                    cluster: 2,
+                    x_advance: 0,
+                    y_advance: 0,
+                    x_offset: 250 - 100 - 500,
+                    y_offset: 500,
+                },
+            ]
+        );
+
+        // Kerning applies across a mark, as the lookup ignores marks
+        let output = engine.apply(&[V, ACUTE, A], &LayoutSettings::default());
+        assert_eq!(output[0].x_advance, 420);
+    }
+
+    #[test]
+    fn test_mark_to_mark_skips_filtered_marks() {
+        let (gsub, mut gpos, mut gdef, mut hmtx) = test_tables();
+        gdef.glyph_class.insert(GRAVE, GlyphClass::MarkGlyph);
+        gdef.mark_glyph_sets = Some(vec![BTreeSet::from_iter(vec![ACUTE])]);
+        hmtx.metrics.push(Metric {
+            advanceWidth: 0,
+            lsb: 0,
+        });
+        gpos.lookups.push(Lookup {
+            flags: LookupFlags::USE_MARK_FILTERING_SET,
+            mark_filtering_set: Some(0),
+            rule: Positioning::MarkToMark(vec![MarkMarkPos {
+                base_marks: btreemap!(ACUTE => btreemap!(0 => Anchor::new(100, 700))),
+                combining_marks: btreemap!(ACUTE => (0, Anchor::new(100, 500))),
+            }]),
+        });
+        gpos.features = FeatureList::new(vec![(tag!("mkmk"), vec![2], None)]);
+        let engine = LayoutEngine {
+            gsub: Some(&gsub),
+            gpos: Some(&gpos),
+            gdef: Some(&gdef),
+            hmtx: Some(&hmtx),
+        };
+        let settings = LayoutSettings {
+            features: BTreeSet::from_iter(vec![tag!("liga"), tag!("mkmk")]),
+            ..Default::default()
+        };
+
+        // The grave is not in the mark filtering set, so is skipped
+        let output = engine.apply(&[A, ACUTE, GRAVE, ACUTE], &settings);
+        assert_eq!(output[3].x_offset, output[1].x_offset);
+        assert_eq!(output[3].y_offset, output[1].y_offset + 200);
+
+        // Marks on different ligature components do not attach
+        let output = engine.apply(&[F, ACUTE, I, ACUTE], &settings);
+        let glyphs: Vec<GlyphID> = output.iter().map(|g| g.glyph).collect();
+        assert_eq!(glyphs, vec![F_I, ACUTE, ACUTE]);
+        assert_eq!(output[2].y_offset, 0);
+    }
+
+    #[test]
+    fn test_deletion_processes_following_glyph() {
+        let gsub = table(vec![(
+            tag!("ccmp"),
+            lookup(
+                LookupFlags::empty(),
+                Substitution::Multiple(vec![MultipleSubst {
+                    mapping: BTreeMap::from_iter(vec![(A, vec![])]),
+                }]),
+            ),
+        )]);
+        let engine = LayoutEngine {
+            gsub: Some(&gsub),
+            gpos: None,
+            gdef: None,
+            hmtx: None,
+        };
+        let settings = LayoutSettings {
+            features: BTreeSet::from_iter(vec![tag!("ccmp")]),
+            ..Default::default()
+        };
+        let output = engine.apply(&[A, A, V, A], &settings);
+        let glyphs: Vec<GlyphID> = output.iter().map(|g| g.glyph).collect();
+        assert_eq!(glyphs, vec![V]);
+        assert_eq!(output[0].cluster, 2);
+    }
+}
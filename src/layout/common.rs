@@ -1,13 +1,18 @@
+use crate::layout::feature_params::{FeatureParams, StylisticSetParams};
+use crate::tables::name::name;
 use otspec::layout::common::{
-    FeatureList as FeatureListLowLevel, FeatureParams, LangSys, LangSysRecord,
+    ConditionFormat1, ConditionSet as ConditionSetLowLevel, FeatureList as FeatureListLowLevel,
+    FeatureTable, FeatureTableSubstitution as FeatureTableSubstitutionLowLevel,
+    FeatureTableSubstitutionRecord, FeatureVariationRecord, LangSys, LangSysRecord,
     Script as ScriptLowLevel, ScriptList as ScriptListLowLevel, ScriptRecord,
 };
 use otspec::layout::coverage::Coverage;
 use otspec::types::*;
+use otspec::{SerializationError, Serialize};
 
 pub use otspec::layout::common::LookupFlags;
 pub use otspec::layout::valuerecord::{ValueRecord, ValueRecordFlags};
-use std::collections::BTreeMap; // For predictable ordering
+use std::collections::{BTreeMap, BTreeSet}; // For predictable ordering
 use std::fmt::Debug;
 
 // A trait for moving things from the otspec representation to our representation.
@@ -60,6 +65,33 @@ pub struct LanguageSystem {
     pub feature_indices: Vec<usize>,
 }
 
+impl LanguageSystem {
+    /// The indices of the features used by this language system, including
+    /// its required feature.
+    pub fn all_feature_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.required_feature
+            .iter()
+            .chain(self.feature_indices.iter())
+            .copied()
+    }
+}
+
+impl ScriptList {
+    /// The indices of the features used by any language system of any script.
+    pub fn feature_indices(&self) -> BTreeSet<usize> {
+        self.scripts
+            .values()
+            .flat_map(|script| {
+                script
+                    .default_language_system
+                    .iter()
+                    .chain(script.language_systems.values())
+            })
+            .flat_map(|langsys| langsys.all_feature_indices())
+            .collect()
+    }
+}
+
 impl From<&LangSys> for LanguageSystem {
     fn from(langsys: &LangSys) -> Self {
         LanguageSystem {
@@ -220,7 +252,12 @@ impl From<FeatureListLowLevel> for FeatureList {
             let tag = fr.featureTag;
             let feature_table = fr.feature.link.unwrap();
             let indices = feature_table.lookupListIndices;
-            features.push((tag, indices.iter().map(|x| usize::from(*x)).collect(), None));
+            let params = feature_table.featureParams.link.as_ref().map(|p| p.into());
+            features.push((
+                tag,
+                indices.iter().map(|x| usize::from(*x)).collect(),
+                params,
+            ));
         }
         FeatureList(features)
     }
@@ -231,12 +268,15 @@ impl From<&FeatureList> for FeatureListLowLevel {
         let mut out = FeatureListLowLevel {
             featureRecords: vec![],
         };
-        for (tag, lookups, _params) in val.iter() {
+        for (tag, lookups, params) in val.iter() {
             out.featureRecords
                 .push(otspec::layout::common::FeatureRecord {
                     featureTag: *tag,
                     feature: Offset16::to(otspec::layout::common::FeatureTable {
-                        featureParamsOffset: 0,
+                        featureParams: match params {
+                            Some(params) => Offset16::to(params.into()),
+                            None => Offset16::to_nothing(),
+                        },
                         lookupListIndices: lookups.iter().map(|x| *x as uint16).collect(),
                     }),
                 })
@@ -245,6 +285,170 @@ impl From<&FeatureList> for FeatureListLowLevel {
     }
 }
 
+/// A condition on a location in the design space: the normalized value of an
+/// axis must lie within a range.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Condition {
+    /// The index of the axis in the `fvar` table
+    pub axis_index: uint16,
+    /// The lowest normalized axis value at which the condition holds
+    pub min: f32,
+    /// The highest normalized axis value at which the condition holds
+    pub max: f32,
+}
+
+/// A set of conditions, all of which must hold
+pub type ConditionSet = Vec<Condition>;
+
+/// A mapping between feature indices and the lookups which replace the
+/// feature's own lookups
+pub type FeatureTableSubstitution = BTreeMap<usize, Vec<usize>>;
+
+/// Alternate lookups for some features, used in a region of the design space
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FeatureVariation {
+    /// The conditions describing the region
+    pub conditions: ConditionSet,
+    /// The features whose lookups are replaced within the region
+    pub substitution: FeatureTableSubstitution,
+}
+
+impl From<&FeatureVariationRecord> for FeatureVariation {
+    fn from(record: &FeatureVariationRecord) -> Self {
+        let conditions = record
+            .conditionSet
+            .link
+            .iter()
+            .flat_map(|set| set.conditions.v.iter())
+            .filter_map(|condition| condition.link.as_ref())
+            .map(|condition| Condition {
+                axis_index: condition.axisIndex,
+                min: condition.filterRangeMinValue,
+                max: condition.filterRangeMaxValue,
+            })
+            .collect();
+        let substitution = record
+            .featureTableSubstitution
+            .link
+            .iter()
+            .flat_map(|substitution| substitution.substitutions.iter())
+            .map(|record| {
+                let lookups = record
+                    .alternateFeature
+                    .link
+                    .iter()
+                    .flat_map(|feature| feature.lookupListIndices.iter())
+                    .map(|&ix| ix as usize)
+                    .collect();
+                (record.featureIndex as usize, lookups)
+            })
+            .collect();
+        FeatureVariation {
+            conditions,
+            substitution,
+        }
+    }
+}
+
+impl From<&FeatureVariation> for FeatureVariationRecord {
+    fn from(variation: &FeatureVariation) -> Self {
+        let conditions: Vec<Offset32<ConditionFormat1>> = variation
+            .conditions
+            .iter()
+            .map(|condition| {
+                Offset32::to(ConditionFormat1 {
+                    format: 1,
+                    axisIndex: condition.axis_index,
+                    filterRangeMinValue: condition.min,
+                    filterRangeMaxValue: condition.max,
+                })
+            })
+            .collect();
+        let substitutions = variation
+            .substitution
+            .iter()
+            .map(|(&feature, lookups)| FeatureTableSubstitutionRecord {
+                featureIndex: feature as uint16,
+                alternateFeature: Offset32::to(FeatureTable {
+                    featureParams: Offset16::to_nothing(),
+                    lookupListIndices: lookups.iter().map(|&ix| ix as uint16).collect(),
+                }),
+            })
+            .collect();
+        FeatureVariationRecord {
+            conditionSet: Offset32::to(ConditionSetLowLevel {
+                conditions: conditions.into(),
+            }),
+            featureTableSubstitution: Offset32::to(FeatureTableSubstitutionLowLevel {
+                majorVersion: 1,
+                minorVersion: 0,
+                substitutions,
+            }),
+        }
+    }
+}
+
+/// A lowlevel GPOS or GSUB table: version 1.0, or version 1.1 if it has
+/// feature variations.
+#[derive(Debug)]
+pub(crate) enum Versioned<V10, V11> {
+    V10(V10),
+    V11(V11),
+}
+
+impl<V10: Serialize, V11: Serialize> Versioned<V10, V11> {
+    fn table(&self) -> &dyn Serialize {
+        match self {
+            Versioned::V10(table) => table,
+            Versioned::V11(table) => table,
+        }
+    }
+}
+
+impl<V10: Serialize, V11: Serialize> Serialize for Versioned<V10, V11> {
+    fn to_bytes(&self, data: &mut Vec<u8>) -> Result<(), SerializationError> {
+        self.table().to_bytes(data)
+    }
+
+    fn to_bytes_shallow(&self, data: &mut Vec<u8>) -> Result<(), SerializationError> {
+        self.table().to_bytes_shallow(data)
+    }
+
+    fn ot_binary_size(&self) -> usize {
+        self.table().ot_binary_size()
+    }
+
+    fn offset_fields(&self) -> Vec<&dyn OffsetMarkerTrait> {
+        self.table().offset_fields()
+    }
+}
+
+/// The region in which both sets of conditions hold, with one condition per
+/// axis in axis order, or `None` if they never hold together.
+fn intersect_conditions(a: &[Condition], b: &[Condition]) -> Option<ConditionSet> {
+    let mut ranges: BTreeMap<uint16, (f32, f32)> = BTreeMap::new();
+    for condition in a.iter().chain(b) {
+        let range = ranges
+            .entry(condition.axis_index)
+            .or_insert((condition.min, condition.max));
+        range.0 = range.0.max(condition.min);
+        range.1 = range.1.min(condition.max);
+    }
+    if ranges.values().any(|(min, max)| min > max) {
+        return None;
+    }
+    Some(
+        ranges
+            .into_iter()
+            .map(|(axis_index, (min, max))| Condition {
+                axis_index,
+                min,
+                max,
+            })
+            .collect(),
+    )
+}
+
 #[derive(Debug, PartialEq, Clone)]
 #[allow(clippy::upper_case_acronyms)]
 /// The Glyph Positioning table
@@ -256,6 +460,218 @@ pub struct GPOSGSUB<T> {
     /// The association between feature tags and the list of indices into the
     /// lookup table used to process this feature, together with any feature parameters.
     pub features: FeatureList,
+    /// Alternate lookups for features in regions of the design space. The
+    /// first variation whose conditions hold is used. A table with feature
+    /// variations is written as version 1.1.
+    pub feature_variations: Vec<FeatureVariation>,
+}
+
+impl<T> GPOSGSUB<T> {
+    /// The indices of the lookups used by the features which are reachable
+    /// through the script list, optionally restricted to features with the
+    /// given tags. This includes the lookups which replace them in any
+    /// feature variation.
+    pub fn feature_lookups(&self, features: Option<&[Tag]>) -> BTreeSet<usize> {
+        self.scripts
+            .feature_indices()
+            .into_iter()
+            .filter(|&ix| {
+                self.features
+                    .get(ix)
+                    .is_some_and(|(tag, _, _)| features.is_none_or(|tags| tags.contains(tag)))
+            })
+            .flat_map(|ix| self.all_feature_lookups(ix))
+            .filter(|&ix| ix < self.lookups.len())
+            .collect()
+    }
+
+    /// The lookups of the feature at the given index, together with the
+    /// lookups which replace them in any feature variation.
+    pub fn all_feature_lookups(&self, feature: usize) -> BTreeSet<usize> {
+        self.features
+            .get(feature)
+            .iter()
+            .flat_map(|(_, lookups, _)| lookups.iter())
+            .chain(
+                self.feature_variations
+                    .iter()
+                    .filter_map(|variation| variation.substitution.get(&feature))
+                    .flatten(),
+            )
+            .copied()
+            .collect()
+    }
+
+    /// Adds a lookup which is applied by the given feature only where all the
+    /// conditions hold, returning the index of the new lookup.
+    ///
+    /// If no feature has the tag, one is created with no lookups of its own
+    /// and added to every language system, creating a default script if
+    /// there are none.
+    ///
+    /// As only the first variation whose conditions hold is used, the lookup
+    /// is added to every existing variation which overlaps the conditions.
+    /// Where a variation's region is not entirely within the conditions, the
+    /// overlap is split off into a new variation placed before it. A new
+    /// variation with the conditions covers the rest of the region.
+    pub fn add_feature_variation(
+        &mut self,
+        conditions: ConditionSet,
+        feature: Tag,
+        lookup: Lookup<T>,
+    ) -> usize {
+        let lookup_ix = self.lookups.len();
+        self.lookups.push(lookup);
+
+        let mut features: Vec<usize> = self
+            .features
+            .iter()
+            .enumerate()
+            .filter(|(_, (tag, _, _))| *tag == feature)
+            .map(|(ix, _)| ix)
+            .collect();
+        if features.is_empty() {
+            let feature_ix = self.features.len();
+            self.features.push((feature, vec![], None));
+            if self.scripts.scripts.is_empty() {
+                self.scripts.scripts.insert(
+                    crate::tag!("DFLT"),
+                    Script {
+                        default_language_system: Some(LanguageSystem {
+                            required_feature: None,
+                            feature_indices: vec![],
+                        }),
+                        language_systems: BTreeMap::new(),
+                    },
+                );
+            }
+            for script in self.scripts.scripts.values_mut() {
+                for langsys in script
+                    .default_language_system
+                    .iter_mut()
+                    .chain(script.language_systems.values_mut())
+                {
+                    langsys.feature_indices.push(feature_ix);
+                }
+            }
+            features.push(feature_ix);
+        }
+
+        let default_lookups: Vec<(usize, Vec<usize>)> = features
+            .into_iter()
+            .map(|ix| (ix, self.features.get(ix).unwrap().1.clone()))
+            .collect();
+        let add_lookup = |variation: &mut FeatureVariation| {
+            for (feature_ix, lookups) in &default_lookups {
+                variation
+                    .substitution
+                    .entry(*feature_ix)
+                    .or_insert_with(|| lookups.clone())
+                    .push(lookup_ix);
+            }
+        };
+
+        let region = intersect_conditions(&conditions, &[]);
+        let mut covered = false;
+        let mut ix = 0;
+        while ix < self.feature_variations.len() {
+            let existing = &self.feature_variations[ix];
+            if let Some(overlap) = intersect_conditions(&existing.conditions, &conditions) {
+                covered |= Some(&overlap) == region.as_ref();
+                if Some(&overlap) != intersect_conditions(&existing.conditions, &[]).as_ref() {
+                    let split = FeatureVariation {
+                        conditions: overlap,
+                        substitution: existing.substitution.clone(),
+                    };
+                    self.feature_variations.insert(ix, split);
+                    add_lookup(&mut self.feature_variations[ix]);
+                    // Move on past the variation the overlap was split from
+                    ix += 1;
+                } else {
+                    add_lookup(&mut self.feature_variations[ix]);
+                }
+            }
+            ix += 1;
+        }
+        if !covered {
+            let mut variation = FeatureVariation {
+                conditions,
+                substitution: BTreeMap::new(),
+            };
+            add_lookup(&mut variation);
+            self.feature_variations.push(variation);
+        }
+        lookup_ix
+    }
+
+    /// The name IDs used by feature parameters which have no record in the
+    /// given `name` table, together with the tags of their features.
+    pub fn missing_feature_names(&self, names: &name) -> Vec<(Tag, uint16)> {
+        self.features
+            .iter()
+            .filter_map(|(tag, _, params)| params.as_ref().map(|params| (tag, params)))
+            .flat_map(|(tag, params)| {
+                params
+                    .missing_name_ids(names)
+                    .into_iter()
+                    .map(move |id| (*tag, id))
+            })
+            .collect()
+    }
+
+    /// The user interface name of a stylistic set feature, if it has one.
+    pub fn stylistic_set_name<'a>(&self, feature: Tag, names: &'a name) -> Option<&'a str> {
+        self.features
+            .iter()
+            .filter(|(tag, _, _)| *tag == feature)
+            .find_map(|(_, _, params)| match params {
+                Some(FeatureParams::StylisticSet(set)) => names.get(set.ui_name_id),
+                _ => None,
+            })
+    }
+
+    /// Names a stylistic set feature, returning the name ID of the name, or
+    /// `None` if no feature has the tag.
+    ///
+    /// If the feature already refers to a font-specific name, that name is
+    /// updated; otherwise a new name is added to the `name` table.
+    pub fn set_stylistic_set_name(
+        &mut self,
+        feature: Tag,
+        names: &mut name,
+        ui_name: &str,
+    ) -> Option<uint16> {
+        if !self.features.iter().any(|(tag, _, _)| *tag == feature) {
+            return None;
+        }
+        let existing = self
+            .features
+            .iter()
+            .filter(|(tag, _, _)| *tag == feature)
+            .find_map(|(_, _, params)| match params {
+                Some(FeatureParams::StylisticSet(set)) if set.ui_name_id >= 256 => {
+                    Some(set.ui_name_id)
+                }
+                _ => None,
+            });
+        let name_id = match existing {
+            Some(name_id) => {
+                names.set(name_id, ui_name);
+                name_id
+            }
+            None => names.add(ui_name),
+        };
+        for (_, _, params) in self
+            .features
+            .iter_mut()
+            .filter(|(tag, _, _)| *tag == feature)
+        {
+            *params = Some(FeatureParams::StylisticSet(StylisticSetParams {
+                ui_name_id: name_id,
+            }));
+        }
+        Some(name_id)
+    }
 }
 
 impl<T> Default for GPOSGSUB<T> {
@@ -264,6 +680,7 @@ impl<T> Default for GPOSGSUB<T> {
             lookups: Default::default(),
             scripts: Default::default(),
             features: Default::default(),
+            feature_variations: Default::default(),
         }
     }
 }
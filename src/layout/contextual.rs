@@ -1,13 +1,16 @@
 use crate::layout::common::{coverage_or_nah, FromLowlevel};
+use otspec::layout::classdef::ClassDef;
 use otspec::layout::contextual::{
     ChainedSequenceContextFormat1, ChainedSequenceContextFormat2, ChainedSequenceContextFormat3,
-    SequenceContextFormat1, SequenceContextFormat2, SequenceContextFormat3, SequenceLookupRecord,
+    ChainedSequenceRule, ChainedSequenceRuleSet, SequenceContextFormat1, SequenceContextFormat2,
+    SequenceContextFormat3, SequenceLookupRecord, SequenceRule, SequenceRuleSet,
 };
 use otspec::layout::coverage::Coverage;
 use otspec::tables::GPOS::GPOSSubtable;
 use otspec::tables::GSUB::GSUBSubtable;
 use otspec::types::*;
-use std::collections::BTreeSet;
+use otspec::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
 
 /// A helpful alias which makes the type a bit more self-documenting
 pub type LookupID = uint16;
@@ -52,6 +55,119 @@ fn collate_lookup_records(
     rule
 }
 
+fn single_glyph(slot: &Slot) -> Option<GlyphID> {
+    match slot.len() {
+        1 => slot.iter().next().copied(),
+        _ => None,
+    }
+}
+
+fn lookup_records(rule: &[(Slot, Vec<LookupID>)]) -> Vec<SequenceLookupRecord> {
+    rule.iter()
+        .enumerate()
+        .flat_map(|(ix, (_, lookup_ids))| {
+            lookup_ids
+                .iter()
+                .map(move |lookup_id| SequenceLookupRecord {
+                    sequenceIndex: ix as uint16,
+                    lookupIndex: *lookup_id,
+                })
+        })
+        .collect()
+}
+
+fn slot_coverage(slot: &Slot) -> Offset16<Coverage> {
+    Offset16::to(Coverage {
+        glyphs: slot.iter().copied().collect(),
+    })
+}
+
+/// Assigns a class to each distinct slot, as long as the slots do not
+/// overlap. Class 0 is never assigned, as it would match any glyph not in
+/// the class definition.
+#[derive(Default)]
+struct ClassAssigner {
+    slots: Vec<Slot>,
+    classes: BTreeMap<GlyphID, uint16>,
+}
+
+impl ClassAssigner {
+    fn class(&mut self, slot: &Slot) -> Option<uint16> {
+        let first = slot.iter().next()?;
+        if let Some(&class) = self.classes.get(first) {
+            return (self.slots[class as usize - 1] == *slot).then_some(class);
+        }
+        if slot.iter().any(|glyph| self.classes.contains_key(glyph)) {
+            return None;
+        }
+        self.slots.push(slot.clone());
+        let class = self.slots.len() as uint16;
+        self.classes
+            .extend(slot.iter().map(|&glyph| (glyph, class)));
+        Some(class)
+    }
+
+    fn classes(&mut self, slots: &[Slot]) -> Option<Vec<uint16>> {
+        slots.iter().map(|slot| self.class(slot)).collect()
+    }
+
+    fn classdef(self) -> Offset16<ClassDef> {
+        Offset16::to(ClassDef {
+            classes: self.classes,
+        })
+    }
+}
+
+/// Lays out rules grouped by their first glyph or class as an array of rule
+/// sets, with null offsets for the gaps.
+fn rule_sets<R, S>(
+    mut grouped: BTreeMap<uint16, Vec<Offset16<R>>>,
+    dense: bool,
+    rule_set: impl Fn(VecOffset16<R>) -> S,
+) -> VecOffset16<S> {
+    let rule_sets: Vec<Offset16<S>> = if dense {
+        let count = grouped.keys().last().map_or(0, |&last| last + 1);
+        (0..count)
+            .map(|key| {
+                grouped
+                    .remove(&key)
+                    .map_or_else(Offset16::to_nothing, |rules| {
+                        Offset16::to(rule_set(rules.into()))
+                    })
+            })
+            .collect()
+    } else {
+        grouped
+            .into_values()
+            .map(|rules| Offset16::to(rule_set(rules.into())))
+            .collect()
+    };
+    rule_sets.into()
+}
+
+/// Picks the encoding which takes the fewest bytes, preferring earlier
+/// candidates when they are the same size.
+fn most_compact<T: Serialize + std::fmt::Debug>(candidates: Vec<Option<Vec<T>>>) -> Vec<T> {
+    candidates
+        .into_iter()
+        .flatten()
+        .map(|subtables| {
+            let subtables: Vec<Offset16<T>> = subtables.into_iter().map(Offset16::to).collect();
+            let size: usize = subtables
+                .iter()
+                .map(|subtable| subtable.offset_size() + subtable.total_size_with_descendants())
+                .sum();
+            (size, subtables)
+        })
+        .min_by_key(|(size, _)| *size)
+        .map_or_else(Vec::new, |(_, subtables)| {
+            subtables
+                .into_iter()
+                .filter_map(|subtable| subtable.link)
+                .collect()
+        })
+}
+
 impl SequenceContext {
     fn from_lowlevel_format1(st: SequenceContextFormat1, _max_glyph_id: GlyphID) -> Self {
         let mut sequence_context = SequenceContext::default();
@@ -82,12 +198,19 @@ impl SequenceContext {
 
     fn from_lowlevel_format2(st: SequenceContextFormat2, max_glyph_id: GlyphID) -> Self {
         let mut sequence_context = SequenceContext::default();
+        let coverage: Slot = coverage_to_slot(st.coverage);
         let classdef = st.classDef.link.unwrap_or_default();
         for (first_class, ruleset) in st.classSeqRuleSets.v.iter().enumerate() {
             if let Some(ruleset) = &ruleset.link {
+                // Only glyphs in the coverage can start a rule
+                let first_slot: Slot = classdef
+                    .get_glyphs(first_class as u16, max_glyph_id)
+                    .intersection(&coverage)
+                    .copied()
+                    .collect();
                 for rule in ruleset.sequenceRules.v.iter() {
                     if let Some(rule) = &rule.link {
-                        let mut slots = vec![classdef.get_glyphs(first_class as u16, max_glyph_id)];
+                        let mut slots = vec![first_slot.clone()];
                         slots.extend(
                             rule.inputSequence
                                 .iter()
@@ -104,7 +227,7 @@ impl SequenceContext {
     }
     fn from_lowlevel_format3(st: SequenceContextFormat3, _max_glyph_id: GlyphID) -> Self {
         let mut sequence_context = SequenceContext::default();
-        let slots: Vec<Slot> = st.coverages.into_iter().map(coverage_to_slot).collect();
+        let slots: Vec<Slot> = st.coverages.v.into_iter().map(coverage_to_slot).collect();
         sequence_context
             .rules
             .push(collate_lookup_records(slots, &st.seqLookupRecords));
@@ -146,47 +269,123 @@ impl FromLowlevel<GSUBSubtable> for SequenceContext {
 }
 
 impl SequenceContext {
+    /// Splits the subtable in two by rule, if it has more than one.
+    pub(crate) fn split(&self) -> Option<(SequenceContext, SequenceContext)> {
+        if self.rules.len() < 2 {
+            return None;
+        }
+        let mut start = self.rules.clone();
+        let end = start.split_off(self.rules.len() / 2);
+        Some((
+            SequenceContext { rules: start },
+            SequenceContext { rules: end },
+        ))
+    }
+
+    fn sequence_rule(rule: &SequenceContextRule, later: Vec<uint16>) -> Offset16<SequenceRule> {
+        let seq_lookup_records = lookup_records(rule);
+        Offset16::to(SequenceRule {
+            glyphCount: rule.len() as uint16,
+            seqLookupCount: seq_lookup_records.len() as uint16,
+            inputSequence: later,
+            seqLookupRecords: seq_lookup_records,
+        })
+    }
+
+    /// Encodes the rules by glyph, if every slot is a single glyph.
+    fn to_format1(&self) -> Option<SequenceContextFormat1> {
+        let mut rules: BTreeMap<GlyphID, Vec<Offset16<SequenceRule>>> = BTreeMap::new();
+        for rule in &self.rules {
+            let glyphs: Vec<GlyphID> = rule
+                .iter()
+                .map(|(slot, _)| single_glyph(slot))
+                .collect::<Option<_>>()?;
+            let (first, later) = glyphs.split_first()?;
+            rules
+                .entry(*first)
+                .or_default()
+                .push(Self::sequence_rule(rule, later.to_vec()));
+        }
+        Some(SequenceContextFormat1 {
+            format: 1,
+            coverage: Offset16::to(Coverage {
+                glyphs: rules.keys().copied().collect(),
+            }),
+            seqRuleSets: rule_sets(rules, false, |sequence_rules| SequenceRuleSet {
+                sequenceRules: sequence_rules,
+            }),
+        })
+    }
+
+    /// Encodes the rules by class, if the slots can be partitioned into
+    /// classes.
+    fn to_format2(&self) -> Option<SequenceContextFormat2> {
+        let mut classes = ClassAssigner::default();
+        let mut coverage: Slot = BTreeSet::new();
+        let mut rules: BTreeMap<uint16, Vec<Offset16<SequenceRule>>> = BTreeMap::new();
+        for rule in &self.rules {
+            let slots: Vec<Slot> = rule.iter().map(|(slot, _)| slot.clone()).collect();
+            let input = classes.classes(&slots)?;
+            let (first, later) = input.split_first()?;
+            coverage.extend(slots[0].iter().copied());
+            rules
+                .entry(*first)
+                .or_default()
+                .push(Self::sequence_rule(rule, later.to_vec()));
+        }
+        Some(SequenceContextFormat2 {
+            format: 2,
+            coverage: slot_coverage(&coverage),
+            classDef: classes.classdef(),
+            classSeqRuleSets: rule_sets(rules, true, |sequence_rules| SequenceRuleSet {
+                sequenceRules: sequence_rules,
+            }),
+        })
+    }
+
     fn to_format3(&self) -> Vec<SequenceContextFormat3> {
         self.rules
             .iter()
             .map(|rule| {
-                let mut coverages: Vec<Offset16<Coverage>> = vec![];
-
-                let mut sequence_lookup_records: Vec<SequenceLookupRecord> = vec![];
-
-                for (ix, (slot, lookup_ids)) in rule.iter().enumerate() {
-                    coverages.push(Offset16::to(Coverage {
-                        glyphs: slot.iter().copied().collect(),
-                    }));
-                    for lookup_id in lookup_ids {
-                        sequence_lookup_records.push(SequenceLookupRecord {
-                            sequenceIndex: ix as uint16,
-                            lookupIndex: *lookup_id,
-                        });
-                    }
-                }
+                let sequence_lookup_records = lookup_records(rule);
                 SequenceContextFormat3 {
                     format: 3,
                     glyphCount: rule.len() as uint16,
                     seqLookupCount: sequence_lookup_records.len() as uint16,
                     seqLookupRecords: sequence_lookup_records,
-                    coverages,
+                    coverages: rule
+                        .iter()
+                        .map(|(slot, _)| slot_coverage(slot))
+                        .collect::<Vec<_>>()
+                        .into(),
                 }
             })
             .collect()
     }
 
     pub(crate) fn to_lowlevel_subtables_gpos(&self, _max_glyph_id: GlyphID) -> Vec<GPOSSubtable> {
-        self.to_format3()
-            .into_iter()
-            .map(GPOSSubtable::GPOS7_3)
-            .collect()
+        most_compact(vec![
+            self.to_format1().map(|st| vec![GPOSSubtable::GPOS7_1(st)]),
+            self.to_format2().map(|st| vec![GPOSSubtable::GPOS7_2(st)]),
+            Some(
+                self.to_format3()
+                    .into_iter()
+                    .map(GPOSSubtable::GPOS7_3)
+                    .collect(),
+            ),
+        ])
     }
     pub(crate) fn to_lowlevel_subtables_gsub(&self, _max_glyph_id: GlyphID) -> Vec<GSUBSubtable> {
-        self.to_format3()
-            .into_iter()
-            .map(GSUBSubtable::GSUB5_3)
-            .collect()
+        most_compact(vec![
+            self.to_format1().map(|st| vec![GSUBSubtable::GSUB5_1(st)]),
+            self.to_format2().map(|st| vec![GSUBSubtable::GSUB5_2(st)]),
+            Some(
+                self.to_format3()
+                    .into_iter()
+                    .map(GSUBSubtable::GSUB5_3)
+                    .collect(),
+            ),
+        ])
     }
 }
 
@@ -252,14 +451,21 @@ impl ChainedSequenceContext {
     }
     fn from_lowlevel_format2(st: ChainedSequenceContextFormat2, max_glyph_id: GlyphID) -> Self {
         let mut chained_sequence_context = ChainedSequenceContext::default();
+        let coverage: Slot = coverage_to_slot(st.coverage);
         let classdef = st.inputClassDef.link.unwrap_or_default();
         let backtrack_classdef = st.backtrackClassDef.link.unwrap_or_default();
         let lookahead_classdef = st.lookaheadClassDef.link.unwrap_or_default();
         for (first_class, ruleset) in st.chainedClassSeqRuleSets.v.iter().enumerate() {
             if let Some(ruleset) = &ruleset.link {
+                // Only glyphs in the coverage can start a rule
+                let first_slot: Slot = classdef
+                    .get_glyphs(first_class as u16, max_glyph_id)
+                    .intersection(&coverage)
+                    .copied()
+                    .collect();
                 for rule in ruleset.chainedSequenceRules.v.iter() {
                     if let Some(rule) = &rule.link {
-                        let mut slots = vec![classdef.get_glyphs(first_class as u16, max_glyph_id)];
+                        let mut slots = vec![first_slot.clone()];
                         slots.extend(
                             rule.inputSequence
                                 .iter()
@@ -354,65 +560,139 @@ impl FromLowlevel<GSUBSubtable> for ChainedSequenceContext {
 }
 
 impl ChainedSequenceContext {
+    /// Splits the subtable in two by rule, if it has more than one.
+    pub(crate) fn split(&self) -> Option<(ChainedSequenceContext, ChainedSequenceContext)> {
+        if self.rules.len() < 2 {
+            return None;
+        }
+        let mut start = self.rules.clone();
+        let end = start.split_off(self.rules.len() / 2);
+        Some((
+            ChainedSequenceContext { rules: start },
+            ChainedSequenceContext { rules: end },
+        ))
+    }
+
+    fn chained_rule(
+        rule: &ChainedSequenceContextRule,
+        backtrack: Vec<uint16>,
+        later: Vec<uint16>,
+        lookahead: Vec<uint16>,
+    ) -> Offset16<ChainedSequenceRule> {
+        Offset16::to(ChainedSequenceRule {
+            backtrackSequence: backtrack,
+            inputGlyphCount: rule.input.len() as uint16,
+            inputSequence: later,
+            lookaheadSequence: lookahead,
+            seqLookupRecords: lookup_records(&rule.input),
+        })
+    }
+
+    /// Encodes the rules by glyph, if every slot is a single glyph.
+    fn to_format1(&self) -> Option<ChainedSequenceContextFormat1> {
+        let glyphs =
+            |slots: &[Slot]| -> Option<Vec<GlyphID>> { slots.iter().map(single_glyph).collect() };
+        let mut rules: BTreeMap<GlyphID, Vec<Offset16<ChainedSequenceRule>>> = BTreeMap::new();
+        for rule in &self.rules {
+            let slots: Vec<Slot> = rule.input.iter().map(|(slot, _)| slot.clone()).collect();
+            let input = glyphs(&slots)?;
+            let (first, later) = input.split_first()?;
+            let chained_rule = Self::chained_rule(
+                rule,
+                glyphs(&rule.backtrack)?,
+                later.to_vec(),
+                glyphs(&rule.lookahead)?,
+            );
+            rules.entry(*first).or_default().push(chained_rule);
+        }
+        Some(ChainedSequenceContextFormat1 {
+            format: 1,
+            coverage: Offset16::to(Coverage {
+                glyphs: rules.keys().copied().collect(),
+            }),
+            chainedSeqRuleSets: rule_sets(rules, false, |chained_rules| ChainedSequenceRuleSet {
+                chainedSequenceRules: chained_rules,
+            }),
+        })
+    }
+
+    /// Encodes the rules by class, if the slots of the backtrack, input and
+    /// lookahead sequences can each be partitioned into classes.
+    fn to_format2(&self) -> Option<ChainedSequenceContextFormat2> {
+        let mut backtrack_classes = ClassAssigner::default();
+        let mut classes = ClassAssigner::default();
+        let mut lookahead_classes = ClassAssigner::default();
+        let mut coverage: Slot = BTreeSet::new();
+        let mut rules: BTreeMap<uint16, Vec<Offset16<ChainedSequenceRule>>> = BTreeMap::new();
+        for rule in &self.rules {
+            let slots: Vec<Slot> = rule.input.iter().map(|(slot, _)| slot.clone()).collect();
+            let input = classes.classes(&slots)?;
+            let (first, later) = input.split_first()?;
+            coverage.extend(slots[0].iter().copied());
+            let chained_rule = Self::chained_rule(
+                rule,
+                backtrack_classes.classes(&rule.backtrack)?,
+                later.to_vec(),
+                lookahead_classes.classes(&rule.lookahead)?,
+            );
+            rules.entry(*first).or_default().push(chained_rule);
+        }
+        Some(ChainedSequenceContextFormat2 {
+            format: 2,
+            coverage: slot_coverage(&coverage),
+            backtrackClassDef: backtrack_classes.classdef(),
+            inputClassDef: classes.classdef(),
+            lookaheadClassDef: lookahead_classes.classdef(),
+            chainedClassSeqRuleSets: rule_sets(rules, true, |chained_rules| {
+                ChainedSequenceRuleSet {
+                    chainedSequenceRules: chained_rules,
+                }
+            }),
+        })
+    }
+
     fn to_format3(&self) -> Vec<ChainedSequenceContextFormat3> {
         self.rules
             .iter()
             .map(|rule| {
-                let mut coverages: Vec<Offset16<Coverage>> = vec![];
-                let lookahead_coverages: Vec<Offset16<Coverage>> = rule
-                    .lookahead
-                    .iter()
-                    .map(|slot| {
-                        Offset16::to(Coverage {
-                            glyphs: slot.iter().copied().collect(),
-                        })
-                    })
-                    .collect();
-                let backtrack_coverages: Vec<Offset16<Coverage>> = rule
-                    .backtrack
-                    .iter()
-                    .map(|slot| {
-                        Offset16::to(Coverage {
-                            glyphs: slot.iter().copied().collect(),
-                        })
-                    })
-                    .collect();
-
-                let mut sequence_lookup_records: Vec<SequenceLookupRecord> = vec![];
-
-                for (ix, (slot, lookup_ids)) in rule.input.iter().enumerate() {
-                    coverages.push(Offset16::to(Coverage {
-                        glyphs: slot.iter().copied().collect(),
-                    }));
-                    for lookup_id in lookup_ids {
-                        sequence_lookup_records.push(SequenceLookupRecord {
-                            sequenceIndex: ix as uint16,
-                            lookupIndex: *lookup_id,
-                        });
-                    }
-                }
+                let coverages = |slots: &[Slot]| -> Vec<Offset16<Coverage>> {
+                    slots.iter().map(slot_coverage).collect()
+                };
+                let input: Vec<Slot> = rule.input.iter().map(|(slot, _)| slot.clone()).collect();
                 ChainedSequenceContextFormat3 {
                     format: 3,
-                    inputCoverages: coverages.into(),
-                    seqLookupRecords: sequence_lookup_records,
-                    backtrackCoverages: backtrack_coverages.into(),
-                    lookaheadCoverages: lookahead_coverages.into(),
+                    inputCoverages: coverages(&input).into(),
+                    seqLookupRecords: lookup_records(&rule.input),
+                    backtrackCoverages: coverages(&rule.backtrack).into(),
+                    lookaheadCoverages: coverages(&rule.lookahead).into(),
                 }
             })
             .collect()
     }
 
     pub(crate) fn to_lowlevel_subtables_gpos(&self, _max_glyph_id: GlyphID) -> Vec<GPOSSubtable> {
-        self.to_format3()
-            .into_iter()
-            .map(GPOSSubtable::GPOS8_3)
-            .collect()
+        most_compact(vec![
+            self.to_format1().map(|st| vec![GPOSSubtable::GPOS8_1(st)]),
+            self.to_format2().map(|st| vec![GPOSSubtable::GPOS8_2(st)]),
+            Some(
+                self.to_format3()
+                    .into_iter()
+                    .map(GPOSSubtable::GPOS8_3)
+                    .collect(),
+            ),
+        ])
     }
     pub(crate) fn to_lowlevel_subtables_gsub(&self, _max_glyph_id: GlyphID) -> Vec<GSUBSubtable> {
-        self.to_format3()
-            .into_iter()
-            .map(GSUBSubtable::GSUB6_3)
-            .collect()
+        most_compact(vec![
+            self.to_format1().map(|st| vec![GSUBSubtable::GSUB6_1(st)]),
+            self.to_format2().map(|st| vec![GSUBSubtable::GSUB6_2(st)]),
+            Some(
+                self.to_format3()
+                    .into_iter()
+                    .map(GSUBSubtable::GSUB6_3)
+                    .collect(),
+            ),
+        ])
     }
 }
 
@@ -421,10 +701,18 @@ mod tests {
     use super::*;
     use crate::layout::common::{Lookup, LookupFlags};
     use crate::tables::GPOS::tests::{assert_can_deserialize, assert_can_roundtrip, expected_gpos};
-    use crate::tables::GPOS::Positioning;
+    use crate::tables::GPOS::{from_bytes, to_bytes, Positioning, GPOS};
     use otspec::btreeset;
+    use otspec::ReaderContext;
     use std::iter::FromIterator;
 
+    fn assert_can_reserialize(expected: &GPOS) {
+        let mut data = vec![];
+        to_bytes(expected, &mut data, 200).unwrap();
+        let gpos: GPOS = from_bytes(&mut ReaderContext::new(data), 200).unwrap();
+        assert_eq!(&gpos, expected);
+    }
+
     #[test]
     fn test_gpos_format_1() {
         /*
@@ -459,7 +747,7 @@ mod tests {
                 rules: vec![rule_one, rule_two, rule_three],
             }]),
         }]);
-        assert_can_deserialize(binary_gpos, &expected);
+        assert_can_roundtrip(binary_gpos, &expected);
     }
 
     #[test]
@@ -504,6 +792,14 @@ mod tests {
             }]),
         }]);
         assert_can_deserialize(binary_gpos, &expected);
+        assert_can_reserialize(&expected);
+        match &expected.lookups[0].rule {
+            Positioning::Contextual(subtables) => assert!(matches!(
+                subtables[0].to_lowlevel_subtables_gpos(200)[..],
+                [GPOSSubtable::GPOS7_2(_)]
+            )),
+            _ => unreachable!(),
+        }
     }
     #[test]
     fn test_gsub_format_3() {
@@ -583,7 +879,7 @@ mod tests {
                 ],
             }]),
         }]);
-        assert_can_deserialize(binary_gpos, &expected);
+        assert_can_roundtrip(binary_gpos, &expected);
     }
 
     #[test]
@@ -645,6 +941,14 @@ mod tests {
             }]),
         }]);
         assert_can_deserialize(binary_gpos, &expected);
+        assert_can_reserialize(&expected);
+        match &expected.lookups[0].rule {
+            Positioning::ChainedContextual(subtables) => assert!(matches!(
+                subtables[0].to_lowlevel_subtables_gpos(200)[..],
+                [GPOSSubtable::GPOS8_2(_)]
+            )),
+            _ => unreachable!(),
+        }
     }
     #[test]
     fn test_gpos_chained_format3() {
@@ -677,4 +981,40 @@ mod tests {
         }]);
         assert_can_roundtrip(binary_gpos, &expected);
     }
+
+    #[test]
+    fn test_overlapping_slots() {
+        // The slots overlap, so neither glyphs nor classes can express these
+        // rules, and each gets a format 3 subtable of its own.
+        let rule_one: SequenceContextRule =
+            vec![(btreeset!(66, 67), vec![0]), (btreeset!(68), vec![])];
+        let rule_two: SequenceContextRule = vec![(btreeset!(67, 68), vec![0])];
+        let context = SequenceContext {
+            rules: vec![rule_one.clone(), rule_two.clone()],
+        };
+        let subtables = context.to_lowlevel_subtables_gpos(200);
+        assert_eq!(subtables.len(), 2);
+        assert!(matches!(subtables[0], GPOSSubtable::GPOS7_3(_)));
+
+        let lookup = |rule| Lookup {
+            flags: LookupFlags::empty(),
+            mark_filtering_set: None,
+            rule,
+        };
+        let mut data = vec![];
+        let gpos = expected_gpos(vec![lookup(Positioning::Contextual(vec![context]))]);
+        to_bytes(&gpos, &mut data, 200).unwrap();
+        let gpos: GPOS = from_bytes(&mut ReaderContext::new(data), 200).unwrap();
+        assert_eq!(
+            gpos,
+            expected_gpos(vec![lookup(Positioning::Contextual(vec![
+                SequenceContext {
+                    rules: vec![rule_one]
+                },
+                SequenceContext {
+                    rules: vec![rule_two]
+                },
+            ]))])
+        );
+    }
 }
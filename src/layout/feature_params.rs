@@ -0,0 +1,149 @@
+use crate::tables::name::name;
+use otspec::layout::common::{
+    cvFeatureParams, sizeFeatureParams, FeatureParams as FeatureParamsLowLevel,
+};
+use otspec::types::*;
+
+/// Parameters of the `size` feature
+///
+/// Sizes are given in decipoints. This feature has been superseded by the
+/// `opsz` axis of the `STAT` table, but is still found in older fonts.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SizeParams {
+    /// The size for which the font was designed
+    pub design_size: uint16,
+    /// Identifies the fonts of a family which differ only in their intended
+    /// size range, or 0 if there is no such range
+    pub subfamily_id: uint16,
+    /// The name ID of the name of the subfamily for menus, or 0
+    pub subfamily_name_id: uint16,
+    /// The smallest size (exclusive) for which the font is intended
+    pub smallest: uint16,
+    /// The largest size (inclusive) for which the font is intended
+    pub largest: uint16,
+}
+
+/// Parameters of a stylistic set feature (`ss01`-`ss20`)
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct StylisticSetParams {
+    /// The name ID of the name of the stylistic set for user interfaces
+    pub ui_name_id: uint16,
+}
+
+/// Parameters of a character variant feature (`cv01`-`cv99`)
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct CharacterVariantParams {
+    /// The name ID of the name of the feature for user interfaces, or 0
+    pub label_name_id: uint16,
+    /// The name ID of a tooltip describing the feature, or 0
+    pub tooltip_name_id: uint16,
+    /// The name ID of sample text illustrating the feature, or 0
+    pub sample_text_name_id: uint16,
+    /// The name ID of the label of the first named parameter. Labels of the
+    /// other parameters follow consecutively.
+    pub first_param_label_name_id: uint16,
+    /// The number of named parameters
+    pub num_named_parameters: uint16,
+    /// The Unicode codepoints of the characters which the feature affects
+    pub characters: Vec<u32>,
+}
+
+impl CharacterVariantParams {
+    /// The name IDs of the labels of the named parameters
+    pub fn param_label_name_ids(&self) -> impl Iterator<Item = uint16> + '_ {
+        (0..self.num_named_parameters).map(move |i| self.first_param_label_name_id + i)
+    }
+}
+
+/// Feature parameters, tying features to records of the `name` table
+#[derive(Debug, PartialEq, Clone)]
+pub enum FeatureParams {
+    /// Parameters of the `size` feature
+    Size(SizeParams),
+    /// Parameters of a stylistic set feature
+    StylisticSet(StylisticSetParams),
+    /// Parameters of a character variant feature
+    CharacterVariant(CharacterVariantParams),
+}
+
+impl FeatureParams {
+    /// The name IDs of the `name` table records used by the parameters
+    pub fn name_ids(&self) -> Vec<uint16> {
+        let ids = match self {
+            FeatureParams::Size(size) => vec![size.subfamily_name_id],
+            FeatureParams::StylisticSet(set) => vec![set.ui_name_id],
+            FeatureParams::CharacterVariant(cv) => {
+                let mut ids = vec![cv.label_name_id, cv.tooltip_name_id, cv.sample_text_name_id];
+                ids.extend(cv.param_label_name_ids());
+                ids
+            }
+        };
+        ids.into_iter().filter(|&id| id != 0).collect()
+    }
+
+    /// The name IDs used by the parameters which have no record in the
+    /// given `name` table
+    pub fn missing_name_ids(&self, names: &name) -> Vec<uint16> {
+        self.name_ids()
+            .into_iter()
+            .filter(|&id| !names.contains(id))
+            .collect()
+    }
+}
+
+impl From<&FeatureParamsLowLevel> for FeatureParams {
+    fn from(params: &FeatureParamsLowLevel) -> Self {
+        match params {
+            FeatureParamsLowLevel::SizeFeature(size) => FeatureParams::Size(SizeParams {
+                design_size: size.designSize,
+                subfamily_id: size.subfamilyIdentifier,
+                subfamily_name_id: size.subfamilyNameID,
+                smallest: size.smallest,
+                largest: size.largest,
+            }),
+            FeatureParamsLowLevel::StylisticSet(_, name_id) => {
+                FeatureParams::StylisticSet(StylisticSetParams {
+                    ui_name_id: *name_id,
+                })
+            }
+            FeatureParamsLowLevel::CharacterVariant(cv) => {
+                FeatureParams::CharacterVariant(CharacterVariantParams {
+                    label_name_id: cv.featUiLabelNameId,
+                    tooltip_name_id: cv.featUiTooltipTextNameId,
+                    sample_text_name_id: cv.sampleTextNameId,
+                    first_param_label_name_id: cv.firstParamUiLabelNameId,
+                    num_named_parameters: cv.numNamedParameters,
+                    characters: cv.characters.iter().map(|&c| c.into()).collect(),
+                })
+            }
+        }
+    }
+}
+
+impl From<&FeatureParams> for FeatureParamsLowLevel {
+    fn from(params: &FeatureParams) -> Self {
+        match params {
+            FeatureParams::Size(size) => FeatureParamsLowLevel::SizeFeature(sizeFeatureParams {
+                designSize: size.design_size,
+                subfamilyIdentifier: size.subfamily_id,
+                subfamilyNameID: size.subfamily_name_id,
+                smallest: size.smallest,
+                largest: size.largest,
+            }),
+            FeatureParams::StylisticSet(set) => {
+                FeatureParamsLowLevel::StylisticSet(0, set.ui_name_id)
+            }
+            FeatureParams::CharacterVariant(cv) => {
+                FeatureParamsLowLevel::CharacterVariant(cvFeatureParams {
+                    format: 0,
+                    featUiLabelNameId: cv.label_name_id,
+                    featUiTooltipTextNameId: cv.tooltip_name_id,
+                    sampleTextNameId: cv.sample_text_name_id,
+                    numNamedParameters: cv.num_named_parameters,
+                    firstParamUiLabelNameId: cv.first_param_label_name_id,
+                    characters: cv.characters.iter().map(|&c| c.into()).collect(),
+                })
+            }
+        }
+    }
+}
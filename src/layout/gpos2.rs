@@ -1,10 +1,14 @@
 use crate::layout::common::{coverage_or_nah, FromLowlevel};
+use otspec::layout::classdef::ClassDef;
 use otspec::layout::coverage::Coverage;
-use otspec::layout::gpos2::{PairPosFormat1, PairSet, PairValueRecord};
-use otspec::layout::valuerecord::{highest_format, ValueRecord};
+use otspec::layout::gpos2::{
+    Class1Record, Class2Record, PairPosFormat1, PairPosFormat2, PairSet, PairValueRecord,
+};
+use otspec::layout::valuerecord::{highest_format, ValueRecord, ValueRecordFlags};
 use otspec::tables::GPOS::GPOSSubtable;
 use otspec::types::*;
-use std::collections::BTreeMap;
+use otspec::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
 
 /// User-friendly mapping between glyph pairs and value record adjustments
 pub type PairPositioningMap = BTreeMap<(GlyphID, GlyphID), (ValueRecord, ValueRecord)>;
@@ -37,13 +41,15 @@ impl FromLowlevel<GPOSSubtable> for PairPos {
                 }
             }
             GPOSSubtable::GPOS2_2(pairpos2) => {
+                let coverage: BTreeSet<GlyphID> =
+                    coverage_or_nah(pairpos2.coverage).into_iter().collect();
                 let classdef_1 = pairpos2.classDef1.link.unwrap_or_default();
                 let classdef_2 = pairpos2.classDef2.link.unwrap_or_default();
 
                 for (c1, class1_record) in pairpos2.class1Records.iter().enumerate() {
                     let left_glyphs: Vec<GlyphID> = classdef_1
                         .get_glyphs(c1 as u16, max_glyph_id)
-                        .iter()
+                        .intersection(&coverage)
                         .copied()
                         .collect();
                     for (c2, class2_record) in class1_record.class2Records.iter().enumerate() {
@@ -76,71 +82,391 @@ impl FromLowlevel<GPOSSubtable> for PairPos {
     }
 }
 
-fn split_into_two_layer(in_hash: PairPositioningMap) -> SplitPairPositioningMap {
-    let mut out_hash = BTreeMap::new();
-    for (&(l, r), vs) in in_hash.iter() {
-        out_hash
-            .entry(l)
-            .or_insert_with(BTreeMap::new)
-            .insert(r, vs.clone());
-    }
-    out_hash
+/// The largest size of a pair positioning subtable, beyond which the
+/// offsets to its coverage, class definitions and pair sets could overflow
+const MAX_SUBTABLE_SIZE: usize = 0xFFFF;
+
+/// The number of times the choice of format for each first class is revised
+const FORMAT_PASSES: usize = 4;
+
+/// The adjustments after a first glyph, as second glyphs and indices into
+/// the distinct adjustments of the subtable
+type Row = Vec<(GlyphID, usize)>;
+
+fn value_size(format: ValueRecordFlags) -> usize {
+    let fields = ValueRecordFlags::X_PLACEMENT
+        | ValueRecordFlags::Y_PLACEMENT
+        | ValueRecordFlags::X_ADVANCE
+        | ValueRecordFlags::Y_ADVANCE;
+    2 * (format & fields).bits().count_ones() as usize
 }
 
-fn best_format(_: &PairPositioningMap) -> uint16 {
-    1
+fn coerced(record: &ValueRecord, format: ValueRecordFlags) -> ValueRecord {
+    let mut record = record.clone();
+    record.coerce_to_format(format);
+    record
 }
 
-// We may generate more than one subtable if we go down the format2 route.
-impl PairPos {
-    pub(crate) fn to_lowlevel_subtables(&self, _max_glyph_id: GlyphID) -> Vec<GPOSSubtable> {
-        if best_format(&self.mapping) == 1 {
-            return vec![self.to_format_1()];
+/// The classes of a format 2 subtable. First class 0 is the largest class,
+/// and second class 0 holds the glyphs which are never adjusted.
+struct ClassMatrix {
+    first: Vec<Vec<GlyphID>>,
+    second: Vec<Vec<GlyphID>>,
+}
+
+/// A lowlevel subtable to be written, with the glyphs it covers
+enum Plan {
+    Format1(Vec<GlyphID>),
+    Format2(ClassMatrix),
+}
+
+/// A pair positioning subtable prepared for serialization
+struct PairTable {
+    values: Vec<(ValueRecord, ValueRecord)>,
+    rows: BTreeMap<GlyphID, Row>,
+    record_size: usize,
+}
+
+impl PairTable {
+    fn new(mapping: &PairPositioningMap) -> Self {
+        let mut values: Vec<(ValueRecord, ValueRecord)> = vec![];
+        let mut rows: BTreeMap<GlyphID, Row> = BTreeMap::new();
+        for (&(left, right), (vr1, vr2)) in mapping {
+            let mut value = (vr1.clone(), vr2.clone());
+            value.0.simplify();
+            value.1.simplify();
+            let ix = match values.iter().position(|v| *v == value) {
+                Some(ix) => ix,
+                None => {
+                    values.push(value);
+                    values.len() - 1
+                }
+            };
+            rows.entry(left).or_default().push((right, ix));
+        }
+        let record_size = value_size(highest_format(values.iter().map(|v| &v.0)))
+            + value_size(highest_format(values.iter().map(|v| &v.1)));
+        PairTable {
+            values,
+            rows,
+            record_size,
         }
-        // let mut subtables: Vec<GPOSSubtable> = vec![];
-        unimplemented!()
     }
 
-    fn to_format_1(&self) -> GPOSSubtable {
-        let mut mapping = self.mapping.clone();
-        for (_, (val1, val2)) in mapping.iter_mut() {
-            (*val1).simplify();
-            (*val2).simplify();
+    /// The first glyphs grouped by their rows
+    fn first_classes(&self) -> Vec<Vec<GlyphID>> {
+        let mut classes: BTreeMap<&Row, Vec<GlyphID>> = BTreeMap::new();
+        for (left, row) in &self.rows {
+            classes.entry(row).or_default().push(*left);
         }
-        let split_mapping = split_into_two_layer(mapping);
+        let mut classes: Vec<Vec<GlyphID>> = classes.into_values().collect();
+        classes.sort();
+        classes
+    }
+
+    fn class_matrix(&self, classes: &[&Vec<GlyphID>]) -> ClassMatrix {
+        let mut first: Vec<Vec<GlyphID>> = classes.iter().map(|&c| c.clone()).collect();
+        first.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+        let mut columns: BTreeMap<GlyphID, Vec<(usize, usize)>> = BTreeMap::new();
+        for (class, glyphs) in first.iter().enumerate() {
+            for &(right, value) in &self.rows[&glyphs[0]] {
+                columns.entry(right).or_default().push((class, value));
+            }
+        }
+        let mut second_classes: BTreeMap<Vec<(usize, usize)>, Vec<GlyphID>> = BTreeMap::new();
+        for (right, column) in columns {
+            second_classes.entry(column).or_default().push(right);
+        }
+        let mut second: Vec<Vec<GlyphID>> = second_classes.into_values().collect();
+        second.sort();
+        second.insert(0, vec![]);
+        ClassMatrix { first, second }
+    }
+
+    fn format1_size(&self, lefts: &[GlyphID]) -> usize {
         let coverage = Coverage {
-            glyphs: split_mapping.keys().copied().collect(),
+            glyphs: lefts.to_vec(),
         };
-        let all_pair_vrs: Vec<&(ValueRecord, ValueRecord)> = split_mapping
-            .values()
-            .map(|x| x.values())
-            .flatten()
+        10 + coverage.ot_binary_size()
+            + lefts
+                .iter()
+                .map(|left| 4 + self.rows[left].len() * (2 + self.record_size))
+                .sum::<usize>()
+    }
+
+    fn format2_size(&self, matrix: &ClassMatrix) -> usize {
+        let (coverage, classdef1, classdef2) = matrix.tables();
+        16 + coverage.ot_binary_size()
+            + classdef1.ot_binary_size()
+            + classdef2.ot_binary_size()
+            + matrix.first.len() * matrix.second.len() * self.record_size
+    }
+
+    fn size(&self, plan: &Plan) -> usize {
+        match plan {
+            Plan::Format1(lefts) => self.format1_size(lefts),
+            Plan::Format2(matrix) => self.format2_size(matrix),
+        }
+    }
+
+    /// Format 1 subtables for the given first glyphs, split as needed to
+    /// keep each of them within the maximum size.
+    fn plan_format1(&self, lefts: &[GlyphID], plans: &mut Vec<Plan>) {
+        if lefts.len() > 1 && self.format1_size(lefts) > MAX_SUBTABLE_SIZE {
+            let (start, end) = lefts.split_at(lefts.len() / 2);
+            self.plan_format1(start, plans);
+            self.plan_format1(end, plans);
+        } else if !lefts.is_empty() {
+            plans.push(Plan::Format1(lefts.to_vec()));
+        }
+    }
+
+    /// Format 2 subtables for the given first classes, split as needed to
+    /// keep each of them within the maximum size.
+    fn plan_format2(&self, classes: &[&Vec<GlyphID>], plans: &mut Vec<Plan>) {
+        if classes.is_empty() {
+            return;
+        }
+        let matrix = self.class_matrix(classes);
+        if classes.len() > 1 && self.format2_size(&matrix) > MAX_SUBTABLE_SIZE {
+            let (start, end) = classes.split_at(classes.len() / 2);
+            self.plan_format2(start, plans);
+            self.plan_format2(end, plans);
+        } else {
+            plans.push(Plan::Format2(matrix));
+        }
+    }
+
+    /// Plans the subtables, with the given first classes in format 2 and
+    /// the remaining first glyphs in format 1.
+    fn plan(&self, classes: &[&Vec<GlyphID>], lefts: &[GlyphID]) -> Vec<Plan> {
+        let mut plans = vec![];
+        self.plan_format1(lefts, &mut plans);
+        self.plan_format2(classes, &mut plans);
+        plans
+    }
+
+    /// Chooses between formats 1 and 2 for each first class, to minimize
+    /// the size of the subtables. First glyphs in `format1_only` are always
+    /// written in format 1.
+    fn best_plan(&self, format1_only: &BTreeSet<GlyphID>) -> Vec<Plan> {
+        let classes: Vec<Vec<GlyphID>> = self
+            .first_classes()
+            .into_iter()
+            .map(|class| {
+                class
+                    .into_iter()
+                    .filter(|g| !format1_only.contains(g))
+                    .collect::<Vec<GlyphID>>()
+            })
+            .filter(|class| !class.is_empty())
             .collect();
-        let value_format_1 = highest_format(all_pair_vrs.iter().map(|x| &x.0));
-        let value_format_2 = highest_format(all_pair_vrs.iter().map(|x| &x.1));
-
-        let mut pair_sets: Vec<Offset16<PairSet>> = vec![];
-        for left in &coverage.glyphs {
-            let mut pair_value_records: Vec<PairValueRecord> = vec![];
-            for (right, (vr1, vr2)) in split_mapping.get(left).unwrap() {
-                pair_value_records.push(PairValueRecord {
-                    secondGlyph: *right,
-                    valueRecord1: vr1.clone(),
-                    valueRecord2: vr2.clone(),
+        let forced: Vec<GlyphID> = self
+            .rows
+            .keys()
+            .filter(|g| format1_only.contains(g))
+            .copied()
+            .collect();
+        let split = |class_based: &[bool]| {
+            let chosen: Vec<&Vec<GlyphID>> = classes
+                .iter()
+                .zip(class_based)
+                .filter(|(_, &c)| c)
+                .map(|(class, _)| class)
+                .collect();
+            let mut lefts: Vec<GlyphID> = forced.clone();
+            for (class, _) in classes.iter().zip(class_based).filter(|(_, &c)| !c) {
+                lefts.extend(class);
+            }
+            lefts.sort_unstable();
+            (chosen, lefts)
+        };
+
+        let mut class_based: Vec<bool> = classes.iter().map(|class| class.len() > 1).collect();
+        for _ in 0..FORMAT_PASSES {
+            let (chosen, _) = split(&class_based);
+            let second_classes = if chosen.is_empty() {
+                1
+            } else {
+                self.class_matrix(&chosen).second.len()
+            };
+            let next: Vec<bool> = classes
+                .iter()
+                .map(|class| {
+                    let row = &self.rows[&class[0]];
+                    let format1 = class.len() * (6 + row.len() * (2 + self.record_size));
+                    let format2 = second_classes * self.record_size + class.len() * 4;
+                    format2 < format1
                 })
+                .collect();
+            if next == class_based {
+                break;
             }
-            pair_sets.push(Offset16::to(PairSet {
-                pairValueRecords: pair_value_records,
-            }));
+            class_based = next;
         }
-        let format1: PairPosFormat1 = PairPosFormat1 {
+
+        let candidates = [
+            class_based,
+            vec![false; classes.len()],
+            vec![true; classes.len()],
+        ];
+        candidates
+            .iter()
+            .map(|class_based| {
+                let (chosen, lefts) = split(class_based);
+                self.plan(&chosen, &lefts)
+            })
+            .min_by_key(|plans| plans.iter().map(|p| self.size(p)).sum::<usize>())
+            .unwrap()
+    }
+
+    fn format1(&self, lefts: &[GlyphID]) -> GPOSSubtable {
+        let used: Vec<&(ValueRecord, ValueRecord)> = lefts
+            .iter()
+            .flat_map(|left| self.rows[left].iter().map(|&(_, ix)| &self.values[ix]))
+            .collect();
+        let value_format_1 = highest_format(used.iter().map(|v| &v.0));
+        let value_format_2 = highest_format(used.iter().map(|v| &v.1));
+        let pair_sets: Vec<Offset16<PairSet>> = lefts
+            .iter()
+            .map(|left| {
+                Offset16::to(PairSet {
+                    pairValueRecords: self.rows[left]
+                        .iter()
+                        .map(|&(right, ix)| PairValueRecord {
+                            secondGlyph: right,
+                            valueRecord1: coerced(&self.values[ix].0, value_format_1),
+                            valueRecord2: coerced(&self.values[ix].1, value_format_2),
+                        })
+                        .collect(),
+                })
+            })
+            .collect();
+        GPOSSubtable::GPOS2_1(PairPosFormat1 {
             posFormat: 1,
-            coverage: Offset16::to(coverage),
+            coverage: Offset16::to(Coverage {
+                glyphs: lefts.to_vec(),
+            }),
             valueFormat1: value_format_1,
             valueFormat2: value_format_2,
             pairSets: VecOffset16 { v: pair_sets },
+        })
+    }
+
+    fn format2(&self, matrix: &ClassMatrix) -> GPOSSubtable {
+        // The adjustment of each cell, if any, found through the row of the
+        // first glyph of its first class
+        let cells: Vec<Vec<Option<&(ValueRecord, ValueRecord)>>> = matrix
+            .first
+            .iter()
+            .map(|first| {
+                let row = &self.rows[&first[0]];
+                matrix
+                    .second
+                    .iter()
+                    .map(|second| {
+                        second.first().and_then(|right| {
+                            row.iter()
+                                .find(|(r, _)| r == right)
+                                .map(|&(_, ix)| &self.values[ix])
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+        let used = cells.iter().flatten().flatten();
+        let value_format_1 = highest_format(used.clone().map(|v| &v.0));
+        let value_format_2 = highest_format(used.map(|v| &v.1));
+        let empty = ValueRecord::new();
+        let class1_records = cells
+            .iter()
+            .map(|row| Class1Record {
+                class2Records: row
+                    .iter()
+                    .map(|cell| Class2Record {
+                        valueRecord1: coerced(cell.map_or(&empty, |v| &v.0), value_format_1),
+                        valueRecord2: coerced(cell.map_or(&empty, |v| &v.1), value_format_2),
+                    })
+                    .collect(),
+            })
+            .collect();
+        let (coverage, classdef1, classdef2) = matrix.tables();
+        GPOSSubtable::GPOS2_2(PairPosFormat2 {
+            posFormat: 2,
+            coverage: Offset16::to(coverage),
+            valueFormat1: value_format_1,
+            valueFormat2: value_format_2,
+            classDef1: Offset16::to(classdef1),
+            classDef2: Offset16::to(classdef2),
+            classCount1: matrix.first.len() as uint16,
+            classCount2: matrix.second.len() as uint16,
+            class1Records: class1_records,
+        })
+    }
+}
+
+impl ClassMatrix {
+    /// The coverage and class definitions of the subtable
+    fn tables(&self) -> (Coverage, ClassDef, ClassDef) {
+        let mut glyphs: Vec<GlyphID> = self.first.iter().flatten().copied().collect();
+        glyphs.sort_unstable();
+        let classdef = |classes: &[Vec<GlyphID>]| ClassDef {
+            classes: classes
+                .iter()
+                .enumerate()
+                .skip(1)
+                .flat_map(|(class, glyphs)| glyphs.iter().map(move |&g| (g, class as uint16)))
+                .collect(),
         };
-        GPOSSubtable::GPOS2_1(format1)
+        (
+            Coverage { glyphs },
+            classdef(&self.first),
+            classdef(&self.second),
+        )
+    }
+}
+
+impl PairPos {
+    /// Splits the subtable in two by first glyph, if it has more than one.
+    pub(crate) fn split(&self) -> Option<(PairPos, PairPos)> {
+        let lefts: BTreeSet<GlyphID> = self.mapping.keys().map(|&(left, _)| left).collect();
+        if lefts.len() < 2 {
+            return None;
+        }
+        let middle = *lefts.iter().nth(lefts.len() / 2).unwrap();
+        let mut start = self.mapping.clone();
+        let end = start.split_off(&(middle, 0));
+        Some((PairPos { mapping: start }, PairPos { mapping: end }))
+    }
+
+    /// Converts the subtable to one or more lowlevel subtables, choosing
+    /// between glyph pairs (format 1) and class pairs (format 2) to minimize
+    /// their size, and splitting them where they would overflow.
+    ///
+    /// A format 2 subtable handles every pair starting with a glyph it covers,
+    /// so first glyphs which also appear in the later subtables of the
+    /// lookup are kept in format 1, where unmatched pairs fall through.
+    pub(crate) fn to_lowlevel_subtables(
+        &self,
+        _max_glyph_id: GlyphID,
+        later_subtables: &[PairPos],
+    ) -> Vec<GPOSSubtable> {
+        if self.mapping.is_empty() {
+            return vec![PairTable::new(&self.mapping).format1(&[])];
+        }
+        let table = PairTable::new(&self.mapping);
+        let later_lefts: BTreeSet<GlyphID> = later_subtables
+            .iter()
+            .flat_map(|subtable| subtable.mapping.keys().map(|&(left, _)| left))
+            .collect();
+        table
+            .best_plan(&later_lefts)
+            .iter()
+            .map(|plan| match plan {
+                Plan::Format1(lefts) => table.format1(lefts),
+                Plan::Format2(matrix) => table.format2(matrix),
+            })
+            .collect()
     }
 }
 
@@ -182,4 +508,129 @@ mod tests {
         }]);
         assert_can_roundtrip(binary_gpos, &expected);
     }
+
+    fn read_back(subtables: Vec<GPOSSubtable>) -> PairPositioningMap {
+        let mut mapping = BTreeMap::new();
+        for subtable in subtables {
+            mapping.extend(PairPos::from_lowlevel(subtable, 100).mapping);
+        }
+        mapping
+    }
+
+    #[test]
+    fn gpos22_class_inference() {
+        // A, B and C kern identically against D-L
+        let mut mapping = BTreeMap::new();
+        for left in 1..4 {
+            for right in 4..13 {
+                let kern = if right < 8 { -20 } else { -40 };
+                mapping.insert(
+                    (left, right),
+                    (valuerecord!(xAdvance = kern), valuerecord!()),
+                );
+            }
+        }
+        let pairpos = PairPos {
+            mapping: mapping.clone(),
+        };
+        let subtables = pairpos.to_lowlevel_subtables(100, &[]);
+        assert_eq!(subtables.len(), 1);
+        if let GPOSSubtable::GPOS2_2(format2) = &subtables[0] {
+            assert_eq!(format2.classCount1, 1);
+            assert_eq!(format2.classCount2, 3);
+        } else {
+            panic!("Expected a format 2 subtable");
+        }
+        assert_eq!(read_back(subtables), mapping);
+    }
+
+    #[test]
+    fn gpos22_fallthrough() {
+        let mut mapping = BTreeMap::new();
+        for left in 1..4 {
+            for right in 4..13 {
+                mapping.insert(
+                    (left, right),
+                    (valuerecord!(xAdvance = -20), valuerecord!()),
+                );
+            }
+        }
+        let pairpos = PairPos {
+            mapping: mapping.clone(),
+        };
+        let later = PairPos {
+            mapping: btreemap!((1, 20) => (valuerecord!(xAdvance = -10), valuerecord!())),
+        };
+        let subtables = pairpos.to_lowlevel_subtables(100, &[later]);
+        let format1_lefts: Vec<Vec<GlyphID>> = subtables
+            .iter()
+            .filter_map(|subtable| match subtable {
+                GPOSSubtable::GPOS2_1(format1) => {
+                    Some(format1.coverage.link.as_ref().unwrap().glyphs.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(format1_lefts, vec![vec![1]]);
+        assert_eq!(read_back(subtables), mapping);
+    }
+
+    #[test]
+    fn gpos2_overflow_split() {
+        let mut mapping = BTreeMap::new();
+        for left in 1..200 {
+            for right in 1..200 {
+                let kern = ((left * right + left * 31 + right * 17) % 251) as i16 + 1;
+                mapping.insert(
+                    (left, right),
+                    (valuerecord!(xAdvance = kern), valuerecord!(xPlacement = 1)),
+                );
+            }
+        }
+        let pairpos = PairPos {
+            mapping: mapping.clone(),
+        };
+        let subtables = pairpos.to_lowlevel_subtables(200, &[]);
+        assert!(subtables.len() > 1);
+        for subtable in &subtables {
+            assert!(otspec::ser::to_bytes(subtable).unwrap().len() <= 0xFFFF);
+        }
+        let mut read = BTreeMap::new();
+        for subtable in subtables {
+            read.extend(PairPos::from_lowlevel(subtable, 200).mapping);
+        }
+        assert_eq!(read, mapping);
+    }
+
+    #[test]
+    fn gpos22_class0_roundtrip() {
+        // Three interleaved first classes, so glyphs of the first class sit
+        // inside the range covered by classDef1
+        let mut mapping = BTreeMap::new();
+        for left in 1..10 {
+            for right in 20..40 {
+                let kern = -10 * (left % 3 + 1) as i16 - if right < 30 { 0 } else { 5 };
+                mapping.insert(
+                    (left, right),
+                    (valuerecord!(xAdvance = kern), valuerecord!()),
+                );
+            }
+        }
+        let pairpos = PairPos {
+            mapping: mapping.clone(),
+        };
+        let subtables = pairpos.to_lowlevel_subtables(100, &[]);
+        assert!(matches!(subtables.as_slice(), [GPOSSubtable::GPOS2_2(_)]));
+
+        let gpos = expected_gpos(vec![Lookup {
+            flags: LookupFlags::empty(),
+            mark_filtering_set: None,
+            rule: Positioning::Pair(vec![pairpos]),
+        }]);
+        let mut binary_gpos = vec![];
+        crate::tables::GPOS::to_bytes(&gpos, &mut binary_gpos, 100).unwrap();
+        let mut rc = otspec::ReaderContext::new(binary_gpos);
+        let read = crate::tables::GPOS::from_bytes(&mut rc, 100).unwrap();
+        assert_eq!(read, gpos);
+    }
 }
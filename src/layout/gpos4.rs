@@ -6,7 +6,7 @@ use otspec::layout::gpos4::{BaseArray, BaseRecord, MarkBasePosFormat1};
 use otspec::tables::GPOS::GPOSSubtable;
 use otspec::types::*;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Debug, PartialEq, Clone, Default)]
 /// A mark-to-base subtable.
@@ -36,7 +36,7 @@ impl FromLowlevel<GPOSSubtable> for MarkBasePos {
                         mark_glyph,
                         (
                             mark_record.markClass,
-                            mark_record.markAnchor.link.unwrap_or_default(),
+                            mark_record.markAnchor.link.clone().unwrap_or_default(),
                         ),
                     );
                 }
@@ -44,8 +44,11 @@ impl FromLowlevel<GPOSSubtable> for MarkBasePos {
                     base_glyphs.iter().zip(base_array.baseRecords.iter())
                 {
                     let mut anchor_list: BTreeMap<uint16, Anchor> = BTreeMap::new();
-                    for (class, base_anchor) in
-                        base_record.baseAnchors.iter().map(|x| x.link).enumerate()
+                    for (class, base_anchor) in base_record
+                        .baseAnchors
+                        .iter()
+                        .map(|x| x.link.clone())
+                        .enumerate()
                     {
                         if let Some(anchor) = base_anchor {
                             anchor_list.insert(class as u16, anchor);
@@ -59,6 +62,67 @@ impl FromLowlevel<GPOSSubtable> for MarkBasePos {
         }
     }
 }
+impl MarkBasePos {
+    /// Splits the subtable in two by mark class, or by base glyph if there is
+    /// only one mark class.
+    pub(crate) fn split(&self) -> Option<(MarkBasePos, MarkBasePos)> {
+        let classes: Vec<uint16> = self
+            .marks
+            .values()
+            .map(|&(class, _)| class)
+            .collect::<BTreeSet<uint16>>()
+            .into_iter()
+            .collect();
+        if classes.len() < 2 {
+            if self.bases.len() < 2 {
+                return None;
+            }
+            let middle = *self.bases.keys().nth(self.bases.len() / 2).unwrap();
+            let mut start = self.clone();
+            let end = MarkBasePos {
+                bases: start.bases.split_off(&middle),
+                marks: self.marks.clone(),
+            };
+            return Some((start, end));
+        }
+        // Each half renumbers its classes from zero
+        let half = |classes: &[uint16]| {
+            let renumber: BTreeMap<uint16, uint16> = classes
+                .iter()
+                .enumerate()
+                .map(|(new, &old)| (old, new as uint16))
+                .collect();
+            MarkBasePos {
+                marks: self
+                    .marks
+                    .iter()
+                    .filter_map(|(&mark, (class, anchor))| {
+                        renumber
+                            .get(class)
+                            .map(|&class| (mark, (class, anchor.clone())))
+                    })
+                    .collect(),
+                bases: self
+                    .bases
+                    .iter()
+                    .map(|(&base, anchors)| {
+                        let anchors: BTreeMap<uint16, Anchor> = anchors
+                            .iter()
+                            .filter_map(|(class, anchor)| {
+                                renumber.get(class).map(|&class| (class, anchor.clone()))
+                            })
+                            .collect();
+                        (base, anchors)
+                    })
+                    .filter(|(_, anchors)| !anchors.is_empty())
+                    .collect(),
+            }
+        };
+        let (start, end) = classes.split_at(classes.len() / 2);
+        Some((half(start), half(end)))
+    }
+}
+
 impl ToLowlevel<GPOSSubtable> for MarkBasePos {
     fn to_lowlevel(&self, _max_glyph_id: GlyphID) -> GPOSSubtable {
         let mut mark_class_count = 0;
@@ -66,13 +130,13 @@ impl ToLowlevel<GPOSSubtable> for MarkBasePos {
             markRecords: self
                 .marks
                 .values()
-                .map(|&(class, anchor)| {
+                .map(|(class, anchor)| {
                     if class + 1 > mark_class_count {
                         mark_class_count = class + 1;
                     }
                     MarkRecord {
-                        markClass: class,
-                        markAnchor: Offset16::to(anchor),
+                        markClass: *class,
+                        markAnchor: Offset16::to(anchor.clone()),
                     }
                 })
                 .collect(),
@@ -85,7 +149,7 @@ impl ToLowlevel<GPOSSubtable> for MarkBasePos {
                 baseAnchors: (0..mark_class_count)
                     .map(|i| {
                         base.get(&i)
-                            .copied()
+                            .cloned()
                             .map(Offset16::to)
                             .unwrap_or_else(Offset16::to_nothing)
                     })
@@ -193,4 +257,43 @@ mod tests {
         }]);
         assert_can_roundtrip(binary_gpos, &expected);
     }
+
+    #[test]
+    fn test_markbase_split() {
+        let markbase = MarkBasePos {
+            marks: btreemap!(10 => (0, Anchor::new(0, 0)), 11 => (1, Anchor::new(0, 500)), 12 => (2, Anchor::new(0, -50))),
+            bases: btreemap!(
+                1 => btreemap!(0 => Anchor::new(1, 1), 1 => Anchor::new(1, 2)),
+                2 => btreemap!(2 => Anchor::new(2, 3))
+            ),
+        };
+        let (start, end) = markbase.split().unwrap();
+        assert_eq!(
+            start,
+            MarkBasePos {
+                marks: btreemap!(10 => (0, Anchor::new(0, 0))),
+                bases: btreemap!(1 => btreemap!(0 => Anchor::new(1, 1))),
+            }
+        );
+        assert_eq!(
+            end,
+            MarkBasePos {
+                marks: btreemap!(11 => (0, Anchor::new(0, 500)), 12 => (1, Anchor::new(0, -50))),
+                bases: btreemap!(
+                    1 => btreemap!(0 => Anchor::new(1, 2)),
+                    2 => btreemap!(1 => Anchor::new(2, 3))
+                ),
+            }
+        );
+        assert!(start.split().is_none());
+
+        let one_class = MarkBasePos {
+            marks: start.marks.clone(),
+            bases: btreemap!(1 => btreemap!(0 => Anchor::new(1, 1)), 2 => btreemap!(0 => Anchor::new(2, 2))),
+        };
+        let (start, end) = one_class.split().unwrap();
+        assert_eq!(start.marks, end.marks);
+        assert_eq!(start.bases.keys().collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(end.bases.keys().collect::<Vec<_>>(), vec![&2]);
+    }
 }
@@ -37,7 +37,7 @@ impl FromLowlevel<GPOSSubtable> for MarkLigPos {
                         mark_glyph,
                         (
                             mark_record.markClass,
-                            mark_record.markAnchor.link.unwrap_or_default(),
+                            mark_record.markAnchor.link.clone().unwrap_or_default(),
                         ),
                     );
                 }
@@ -53,8 +53,11 @@ impl FromLowlevel<GPOSSubtable> for MarkLigPos {
                     // XXX clone
                     {
                         let mut anchor_list: BTreeMap<uint16, Anchor> = BTreeMap::new();
-                        for (class, ligature_anchor) in
-                            component.ligatureAnchors.iter().map(|x| x.link).enumerate()
+                        for (class, ligature_anchor) in component
+                            .ligatureAnchors
+                            .iter()
+                            .map(|x| x.link.clone())
+                            .enumerate()
                         {
                             if let Some(anchor) = ligature_anchor {
                                 anchor_list.insert(class as u16, anchor);
@@ -77,13 +80,13 @@ impl ToLowlevel<GPOSSubtable> for MarkLigPos {
             markRecords: self
                 .marks
                 .values()
-                .map(|&(class, anchor)| {
+                .map(|(class, anchor)| {
                     if class + 1 > mark_class_count {
                         mark_class_count = class + 1;
                     }
                     MarkRecord {
-                        markClass: class,
-                        markAnchor: Offset16::to(anchor),
+                        markClass: *class,
+                        markAnchor: Offset16::to(anchor.clone()),
                     }
                 })
                 .collect(),
@@ -101,7 +104,7 @@ impl ToLowlevel<GPOSSubtable> for MarkLigPos {
                                 .map(|i| {
                                     component
                                         .get(&i)
-                                        .copied()
+                                        .cloned()
                                         .map(Offset16::to)
                                         .unwrap_or_else(Offset16::to_nothing)
                                 })
@@ -222,17 +225,17 @@ mod tests {
             mark_filtering_set: None,
             rule: Positioning::MarkToLig(vec![MarkLigPos {
                 ligatures: btreemap!(564 => vec![
-                   btreemap!(0 => Anchor { xCoordinate: 625, yCoordinate: 1800, anchorPoint: None },
+                   btreemap!(0 => Anchor::new(625, 1800),
                     ),
 
                    btreemap!(
-                    1 => Anchor { xCoordinate: 376, yCoordinate: -368, anchorPoint: None },
+                    1 => Anchor::new(376, -368),
                    ),
                    btreemap!(),
                 ]),
                 marks: btreemap!(
-                    828 => (0, Anchor { xCoordinate: 346, yCoordinate: -98, anchorPoint: None }),
-                    831 => (1, Anchor { xCoordinate: 261, yCoordinate: 488, anchorPoint: None })
+                    828 => (0, Anchor::new(346, -98)),
+                    831 => (1, Anchor::new(261, 488))
                 ),
             }]),
         }]);
@@ -37,7 +37,11 @@ impl FromLowlevel<GPOSSubtable> for MarkMarkPos {
                         combining_mark_glyph,
                         (
                             combining_mark_record.markClass,
-                            combining_mark_record.markAnchor.link.unwrap_or_default(),
+                            combining_mark_record
+                                .markAnchor
+                                .link
+                                .clone()
+                                .unwrap_or_default(),
                         ),
                     );
                 }
@@ -49,7 +53,7 @@ impl FromLowlevel<GPOSSubtable> for MarkMarkPos {
                     for (class, base_anchor) in base_mark_record
                         .mark2Anchors
                         .iter()
-                        .map(|x| x.link)
+                        .map(|x| x.link.clone())
                         .enumerate()
                     {
                         if let Some(anchor) = base_anchor {
@@ -74,13 +78,13 @@ impl ToLowlevel<GPOSSubtable> for MarkMarkPos {
             markRecords: self
                 .combining_marks
                 .values()
-                .map(|&(class, anchor)| {
+                .map(|(class, anchor)| {
                     if class + 1 > mark_class_count {
                         mark_class_count = class + 1;
                     }
                     MarkRecord {
-                        markClass: class,
-                        markAnchor: Offset16::to(anchor),
+                        markClass: *class,
+                        markAnchor: Offset16::to(anchor.clone()),
                     }
                 })
                 .collect(),
@@ -93,7 +97,7 @@ impl ToLowlevel<GPOSSubtable> for MarkMarkPos {
                 mark2Anchors: (0..mark_class_count)
                     .map(|i| {
                         base.get(&i)
-                            .copied()
+                            .cloned()
                             .map(Offset16::to)
                             .unwrap_or_else(Offset16::to_nothing)
                     })
@@ -3,7 +3,7 @@ use otspec::layout::coverage::Coverage;
 use otspec::layout::gsub4::{Ligature, LigatureSet, LigatureSubstFormat1};
 use otspec::tables::GSUB::GSUBSubtable;
 use otspec::types::*;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Debug, PartialEq, Clone, Default)]
 /// A ligature substitution (many-to-one) subtable.
@@ -12,6 +12,23 @@ pub struct LigatureSubst {
     pub mapping: BTreeMap<Vec<GlyphID>, GlyphID>,
 }
 
+impl LigatureSubst {
+    /// Splits the subtable in two by first glyph, if it has more than one.
+    pub(crate) fn split(&self) -> Option<(LigatureSubst, LigatureSubst)> {
+        let firsts: BTreeSet<GlyphID> = self.mapping.keys().map(|input| input[0]).collect();
+        if firsts.len() < 2 {
+            return None;
+        }
+        let middle = *firsts.iter().nth(firsts.len() / 2).unwrap();
+        let mut start = self.mapping.clone();
+        let end = start.split_off(&vec![middle]);
+        Some((
+            LigatureSubst { mapping: start },
+            LigatureSubst { mapping: end },
+        ))
+    }
+}
+
 impl ToLowlevel<GSUBSubtable> for LigatureSubst {
     fn to_lowlevel(&self, _max_glyph_id: GlyphID) -> GSUBSubtable {
         let mut split_map: BTreeMap<u16, Vec<Vec<u16>>> = BTreeMap::new();
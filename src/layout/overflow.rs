@@ -0,0 +1,265 @@
+use crate::layout::common::GPOSGSUB;
+use otspec::offsetmanager::{OffsetManager, Packing};
+use otspec::types::*;
+use otspec::{SerializationError, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+
+/// The rules of a GSUB or GPOS table, as far as laying out the table without
+/// overflowing its offsets is concerned.
+pub(crate) trait Packable: Clone + Sized {
+    /// The lowlevel table
+    type Lowlevel: Serialize;
+    /// The table's tag, for error messages
+    const TAG: Tag;
+
+    /// Converts the table to its lowlevel form, promoting the given lookups to
+    /// extension lookups. Also returns, for each lowlevel subtable of each
+    /// lookup, the index of the subtable of the rule it came from.
+    fn to_lowlevel_packed(
+        table: &GPOSGSUB<Self>,
+        max_glyph_id: GlyphID,
+        extensions: &BTreeSet<usize>,
+    ) -> (Self::Lowlevel, Vec<Vec<usize>>);
+
+    /// The offsets to the lookups of a lowlevel table, each with the offsets
+    /// to its subtables.
+    fn lookup_offsets(
+        table: &Self::Lowlevel,
+    ) -> Vec<(&dyn OffsetMarkerTrait, Vec<&dyn OffsetMarkerTrait>)>;
+
+    /// Splits the subtable at the given index in two, returning whether the
+    /// subtable could be split.
+    fn split_subtable(&mut self, index: usize) -> bool;
+}
+
+/// Replaces the subtable at the given index with the two halves returned by
+/// `split`, if any.
+pub(crate) fn split_subtable<T>(
+    subtables: &mut Vec<T>,
+    index: usize,
+    split: impl Fn(&T) -> Option<(T, T)>,
+) -> bool {
+    match subtables.get(index).and_then(split) {
+        Some((start, end)) => {
+            subtables.splice(index..=index, vec![start, end]);
+            true
+        }
+        None => false,
+    }
+}
+
+/// The index of the subtable each lowlevel subtable came from, given the
+/// lowlevel subtables of each subtable of a lookup.
+pub(crate) fn subtable_origins<T>(subtables: &[Vec<T>]) -> Vec<usize> {
+    subtables
+        .iter()
+        .enumerate()
+        .flat_map(|(ix, lowlevel)| (0..lowlevel.len()).map(move |_| ix))
+        .collect()
+}
+
+/// Where offsets overflowed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Overflow {
+    /// The offset from the lookup list to a lookup
+    LookupList(usize),
+    /// The offset from a lookup to one of its subtables
+    Lookup(usize),
+    /// An offset within a (lowlevel) subtable of a lookup
+    Subtable(usize, usize),
+    /// Anything outside the lookups
+    Header,
+}
+
+fn find_overflow(
+    mgr: &OffsetManager,
+    lookups: &[(&dyn OffsetMarkerTrait, Vec<&dyn OffsetMarkerTrait>)],
+) -> Overflow {
+    for (ix, (lookup, subtables)) in lookups.iter().enumerate() {
+        for (subtable_ix, subtable) in subtables.iter().enumerate() {
+            if subtable.children().iter().any(|&c| mgr.overflowed(c)) {
+                return Overflow::Subtable(ix, subtable_ix);
+            }
+        }
+        if subtables.iter().any(|&subtable| mgr.overflowed(subtable)) {
+            return Overflow::Lookup(ix);
+        }
+        if mgr.overflowed(*lookup) {
+            return Overflow::LookupList(ix);
+        }
+    }
+    Overflow::Header
+}
+
+/// Serializes a GSUB or GPOS table, resolving any offset overflows.
+///
+/// Overflows are resolved by trying, in turn, to promote lookups to
+/// extension lookups, to split subtables in two, and to lay out the table
+/// breadth first. If none of these work, the error names the lookup which
+/// overflowed.
+pub(crate) fn to_bytes<T: Packable>(
+    table: &GPOSGSUB<T>,
+    data: &mut Vec<u8>,
+    max_glyph_id: GlyphID,
+) -> Result<(), SerializationError> {
+    let mut table = Cow::Borrowed(table);
+    let mut extensions: BTreeSet<usize> = BTreeSet::new();
+    let mut packing = Packing::DepthFirst;
+    loop {
+        let (lowlevel, origins) = T::to_lowlevel_packed(&table, max_glyph_id, &extensions);
+        let root = Offset16::to(lowlevel);
+        let mut mgr = OffsetManager::new(&root);
+        mgr.set_packing(packing);
+        mgr.resolve();
+        if mgr.overflows() == 0 {
+            return mgr.serialize(data, true);
+        }
+
+        let lookups = T::lookup_offsets(root.link.as_ref().unwrap());
+        let overflow = find_overflow(&mgr, &lookups);
+        let fixed = match overflow {
+            Overflow::Lookup(ix) => extensions.insert(ix),
+            // Move the largest of the earlier lookups out of the way
+            Overflow::LookupList(ix) => (0..ix)
+                .filter(|earlier| !extensions.contains(earlier))
+                .max_by_key(|&earlier| lookups[earlier].0.total_size_with_descendants())
+                .is_some_and(|earlier| extensions.insert(earlier)),
+            Overflow::Subtable(ix, subtable) => table.to_mut().lookups[ix]
+                .rule
+                .split_subtable(origins[ix][subtable]),
+            Overflow::Header => false,
+        };
+        // Extension subtables only help if they are out of the way
+        if !extensions.is_empty() && packing == Packing::DepthFirst {
+            packing = Packing::LongOffsetsLast;
+        }
+        if fixed {
+            continue;
+        }
+        if packing != Packing::BreadthFirst {
+            packing = Packing::BreadthFirst;
+            continue;
+        }
+        return Err(SerializationError(match overflow {
+            Overflow::LookupList(ix) | Overflow::Lookup(ix) => {
+                format!("Offset overflow in {} lookup {}", T::TAG, ix)
+            }
+            Overflow::Subtable(ix, subtable) => format!(
+                "Offset overflow in {} lookup {}, subtable {}",
+                T::TAG,
+                ix,
+                subtable
+            ),
+            Overflow::Header => format!("Offset overflow in {} script or feature list", T::TAG),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::common::{Lookup, LookupFlags, ToLowlevel};
+    use crate::layout::gsub1::SingleSubst;
+    use crate::layout::gsub2::MultipleSubst;
+    use crate::layout::gsub4::LigatureSubst;
+    use crate::tables::GSUB::tests::expected_gsub;
+    use crate::tables::GSUB::{from_bytes, Substitution, GSUB};
+    use otspec::ReaderContext;
+    use std::collections::BTreeMap;
+
+    fn lookup(rule: Substitution) -> Lookup<Substitution> {
+        Lookup {
+            flags: LookupFlags::empty(),
+            mark_filtering_set: None,
+            rule,
+        }
+    }
+
+    fn round_trip(gsub: &GSUB) -> (Vec<u8>, GSUB) {
+        let mut data = vec![];
+        to_bytes(gsub, &mut data, 30000).unwrap();
+        let read = from_bytes(&mut ReaderContext::new(data.clone()), 30000).unwrap();
+        (data, read)
+    }
+
+    #[test]
+    fn test_extension_promotion() {
+        // Three lookups of about 40k each
+        let single = |offset: u16| {
+            let mapping: BTreeMap<GlyphID, GlyphID> = (0..20000)
+                .map(|g: u16| (g, ((g as u32 * 7 + offset as u32) % 20011) as u16))
+                .collect();
+            lookup(Substitution::Single(vec![SingleSubst { mapping }]))
+        };
+        let gsub = expected_gsub(vec![single(1), single(2), single(3)]);
+        assert!(gsub.to_lowlevel(30000).to_bytes(&mut vec![]).is_err());
+
+        let (data, read) = round_trip(&gsub);
+        assert!(data.len() > 0x10000);
+        assert_eq!(read, gsub);
+    }
+
+    #[test]
+    fn test_split_ligatures() {
+        let mut mapping = BTreeMap::new();
+        for first in 0..2000 {
+            for ligature in 0..10 {
+                mapping.insert(vec![first, ligature, ligature + 1, 3000], 20000 + first);
+            }
+        }
+        let gsub = expected_gsub(vec![lookup(Substitution::Ligature(vec![LigatureSubst {
+            mapping: mapping.clone(),
+        }]))]);
+
+        let (_, read) = round_trip(&gsub);
+        match &read.lookups[0].rule {
+            Substitution::Ligature(subtables) => {
+                assert!(subtables.len() > 1);
+                let merged: BTreeMap<Vec<GlyphID>, GlyphID> = subtables
+                    .iter()
+                    .flat_map(|subtable| subtable.mapping.clone())
+                    .collect();
+                assert_eq!(merged, mapping);
+            }
+            _ => panic!("Expected a ligature lookup"),
+        }
+    }
+
+    #[test]
+    fn test_unresolvable_overflow() {
+        let mapping: BTreeMap<GlyphID, Vec<GlyphID>> =
+            (0..400).map(|g| (g, (g..g + 100).collect())).collect();
+        let gsub = expected_gsub(vec![
+            lookup(Substitution::Single(vec![SingleSubst::default()])),
+            lookup(Substitution::Multiple(vec![MultipleSubst { mapping }])),
+        ]);
+        let err = to_bytes(&gsub, &mut vec![], 30000).unwrap_err();
+        assert_eq!(err.0, "Offset overflow in GSUB lookup 1, subtable 0");
+    }
+
+    #[test]
+    fn test_overflow_beside_shared_subtable() {
+        // Lookups 0 and 1 share a subtable whose identical sequences only
+        // fit once they are shared too; lookup 2 really overflows.
+        let shared: BTreeMap<GlyphID, Vec<GlyphID>> =
+            (0..400).map(|g| (g, (0..100).collect())).collect();
+        let overflowing: BTreeMap<GlyphID, Vec<GlyphID>> =
+            (0..400).map(|g| (g, (g..g + 100).collect())).collect();
+        let mut ignore_marks = lookup(Substitution::Multiple(vec![MultipleSubst {
+            mapping: shared.clone(),
+        }]));
+        ignore_marks.flags = LookupFlags::IGNORE_MARKS;
+        let gsub = expected_gsub(vec![
+            lookup(Substitution::Multiple(vec![MultipleSubst {
+                mapping: shared,
+            }])),
+            ignore_marks,
+            lookup(Substitution::Multiple(vec![MultipleSubst {
+                mapping: overflowing,
+            }])),
+        ]);
+        let err = to_bytes(&gsub, &mut vec![], 30000).unwrap_err();
+        assert_eq!(err.0, "Offset overflow in GSUB lookup 2, subtable 0");
+    }
+}
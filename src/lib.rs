@@ -36,15 +36,29 @@
 //! the [font] module as the entry point to creating, parsing and
 //! saving an OpenType font.
 
+/// TrueType and OpenType font collections
+pub mod collection;
+/// AFDKO feature file parsing and compilation
+pub mod feature_file;
+/// Conversion between TrueType and CFF outlines
+mod flavor;
 /// The main font object. Start here.
 pub mod font;
 /// OpenType Layout common tables
 pub mod layout;
 /// OpenType Variations common tables
 pub mod otvar;
+/// Font subsetting
+pub mod subset;
 pub mod table_store;
 /// OpenType table definitions.
 pub mod tables;
+#[cfg(test)]
+mod testing;
+/// WOFF 1.0 web fonts
+pub mod woff;
+/// WOFF2 web fonts
+pub mod woff2;
 
 pub use otspec::types;
 pub use otspec_macros::tag;
@@ -1,5 +1,7 @@
 ///! OpenType Variations common tables
 
+/// Mappings from glyph IDs to item variation store entries (used in `HVAR`, etc.)
+mod deltasetindexmap;
 /// Item Variation Store (used in `MVAR`, etc.)
 mod itemvariationstore;
 /// Utilities for Interpolation of Unreferenced Points
@@ -17,8 +19,11 @@ mod tuplevariationstore;
 
 pub mod instancer;
 
-pub use itemvariationstore::{ItemVariationData, ItemVariationStore, RegionAxisCoordinates};
-pub use locations::{support_scalar, Location, NormalizedLocation, VariationModel};
+pub use deltasetindexmap::DeltaSetIndexMap;
+pub use itemvariationstore::{
+    ItemVariationData, ItemVariationStore, PinnedRegions, RegionAxisCoordinates,
+};
+pub use locations::{support_scalar, Location, NormalizedLocation, Support, VariationModel};
 use otspec::types::int16;
 pub use packeddeltas::PackedDeltas;
 pub use packedpoints::PackedPoints;
@@ -92,4 +97,73 @@ mod tests {
         let binary_ser = otspec::ser::to_bytes(&fivs).unwrap();
         assert_eq!(binary_ser, binary_ivs);
     }
+
+    #[test]
+    fn otvar_ivs_pin_axes() {
+        let region = |wght: f32, wdth: f32| {
+            vec![
+                RegionAxisCoordinates {
+                    startCoord: 0.0,
+                    peakCoord: wght,
+                    endCoord: wght,
+                },
+                RegionAxisCoordinates {
+                    startCoord: 0.0,
+                    peakCoord: wdth,
+                    endCoord: wdth,
+                },
+            ]
+        };
+        let ivs = ItemVariationStore {
+            format: 1,
+            axisCount: 2,
+            variationRegions: vec![region(1.0, 0.0), region(0.0, 1.0), region(1.0, 1.0)],
+            variationData: vec![ItemVariationData {
+                region_indexes: vec![0, 1, 2],
+                delta_values: vec![vec![100, 50, 20]],
+            }],
+        };
+        let location = NormalizedLocation(vec![0.5, 0.5]);
+        assert_eq!(ivs.region_scalars(0, &location), vec![0.5, 0.5, 0.25]);
+        assert_eq!(ivs.get_delta(0, 0, &location), 80.0);
+
+        let pinned: std::collections::BTreeMap<usize, f32> = vec![(0, 0.5)].into_iter().collect();
+        let (new_ivs, mappings) = ivs.pin_axes(&pinned);
+        assert_eq!(new_ivs.axisCount, 1);
+        assert_eq!(
+            new_ivs.variationRegions,
+            vec![vec![region(0.0, 1.0)[1].clone()]]
+        );
+        assert_eq!(
+            new_ivs.variationData,
+            vec![ItemVariationData {
+                region_indexes: vec![0],
+                delta_values: vec![vec![60]],
+            }]
+        );
+        assert_eq!(mappings[0].apply(&[100.0, 50.0, 20.0]), (50.0, vec![60.0]));
+        // The pinned store gives the same result at the remaining location
+        assert_eq!(
+            new_ivs.get_delta(0, 0, &NormalizedLocation(vec![0.5])) + 50.0,
+            80.0
+        );
+    }
+
+    #[test]
+    fn otvar_serde_delta_set_index_map() {
+        let binary_map = vec![
+            0x00, /* format 0 */
+            0x01, /* one byte per entry, two bits of inner index */
+            0x00, 0x03, /* three entries */
+            0x00, 0x01, 0x06,
+        ];
+        let map = DeltaSetIndexMap {
+            entries: vec![(0, 0), (0, 1), (1, 2)],
+        };
+        let deserialized: DeltaSetIndexMap = otspec::de::from_bytes(&binary_map).unwrap();
+        assert_eq!(deserialized, map);
+        assert_eq!(otspec::ser::to_bytes(&map).unwrap(), binary_map);
+        // Indices past the end of the map use the last entry
+        assert_eq!(map.get(10), Some((1, 2)));
+    }
 }
@@ -0,0 +1,94 @@
+use otspec::types::*;
+use otspec::{
+    DeserializationError, Deserialize, Deserializer, ReaderContext, SerializationError, Serialize,
+    Serializer,
+};
+
+const INNER_INDEX_BIT_COUNT_MASK: u8 = 0x0F;
+const MAP_ENTRY_SIZE_MASK: u8 = 0x30;
+
+/// Maps glyph IDs (or other indices) to entries of an item variation store
+///
+/// Each entry is an (outer, inner) pair, indexing first the item variation
+/// data subtable and then the row within it. Indices beyond the end of the
+/// map use the last entry.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct DeltaSetIndexMap {
+    /// The (outer, inner) delta set index of each item
+    pub entries: Vec<(uint16, uint16)>,
+}
+
+impl DeltaSetIndexMap {
+    /// Returns the (outer, inner) delta set index of an item, if the map
+    /// has any entries.
+    pub fn get(&self, index: usize) -> Option<(uint16, uint16)> {
+        self.entries
+            .get(index)
+            .or_else(|| self.entries.last())
+            .copied()
+    }
+}
+
+fn bit_count(value: u32) -> u32 {
+    32 - value.leading_zeros()
+}
+
+impl Deserialize for DeltaSetIndexMap {
+    fn from_bytes(c: &mut ReaderContext) -> Result<Self, DeserializationError> {
+        let format: u8 = c.de()?;
+        let entry_format: u8 = c.de()?;
+        let map_count: u32 = match format {
+            0 => {
+                let count: uint16 = c.de()?;
+                count.into()
+            }
+            1 => c.de()?,
+            _ => {
+                return Err(DeserializationError(format!(
+                    "Unknown DeltaSetIndexMap format {}",
+                    format
+                )))
+            }
+        };
+        let inner_bits = (entry_format & INNER_INDEX_BIT_COUNT_MASK) + 1;
+        let entry_size = ((entry_format & MAP_ENTRY_SIZE_MASK) >> 4) + 1;
+        let mut entries = Vec::with_capacity(map_count as usize);
+        for _ in 0..map_count {
+            let bytes = c.peek(entry_size as usize)?;
+            let entry = bytes.iter().fold(0_u32, |acc, &b| (acc << 8) | b as u32);
+            c.skip(entry_size as usize);
+            entries.push((
+                (entry >> inner_bits) as uint16,
+                (entry & ((1 << inner_bits) - 1)) as uint16,
+            ));
+        }
+        Ok(DeltaSetIndexMap { entries })
+    }
+}
+
+impl Serialize for DeltaSetIndexMap {
+    fn to_bytes(&self, data: &mut Vec<u8>) -> Result<(), SerializationError> {
+        let (max_outer, max_inner) = self.entries.iter().fold((0, 0), |(o, i), &(outer, inner)| {
+            (o.max(outer), i.max(inner))
+        });
+        let inner_bits = bit_count(max_inner.into()).max(1);
+        let total_bits = inner_bits + bit_count(max_outer.into());
+        let entry_size = total_bits.div_ceil(8).max(1);
+        if self.entries.len() > uint16::MAX as usize {
+            data.put(1_u8)?;
+        } else {
+            data.put(0_u8)?;
+        }
+        data.put((((entry_size - 1) << 4) | (inner_bits - 1)) as u8)?;
+        if self.entries.len() > uint16::MAX as usize {
+            data.put(self.entries.len() as u32)?;
+        } else {
+            data.put(self.entries.len() as uint16)?;
+        }
+        for &(outer, inner) in &self.entries {
+            let entry = ((outer as u32) << inner_bits) | inner as u32;
+            data.extend(&entry.to_be_bytes()[4 - entry_size as usize..]);
+        }
+        Ok(())
+    }
+}
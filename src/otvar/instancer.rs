@@ -1,33 +1,88 @@
 #![allow(missing_docs)]
 use std::collections::BTreeMap;
 
-use super::support_scalar;
+use super::{
+    support_scalar, ItemVariationData, ItemVariationStore, PinnedRegions, RegionAxisCoordinates,
+};
 use crate::font::Font;
+use crate::layout::common::{Condition, ConditionSet, FeatureVariation, GPOSGSUB};
 use crate::tables::avar::{self, SegmentMap};
 use crate::tables::gvar::{self, Coords, DeltaSet, GlyphVariationData};
-use crate::tables::{fvar, glyf};
+use crate::tables::GDEF::CaretValue;
+use crate::tables::GPOS::Positioning;
+use crate::tables::{cvar, cvt, fvar, glyf, HVAR, MVAR, VVAR};
 use crate::tag;
 use crate::types::*;
+use otspec::layout::anchor::Anchor;
+use otspec::layout::device::Device;
+use otspec::layout::valuerecord::ValueRecord;
+
+/// Updating names and style bits for an instance
+mod names;
+/// Rebasing variation regions onto restricted axis ranges
+mod solver;
 
 type Location = BTreeMap<Tag, f32>;
 
+/// An error raised when a variable font can't be instantiated
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstancerError(pub String);
+
+impl std::fmt::Display for InstancerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InstancerError {}
+
+impl From<otspec::DeserializationError> for InstancerError {
+    fn from(e: otspec::DeserializationError) -> Self {
+        InstancerError(e.to_string())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct AxisRange {
     minimum: f32,
     maximum: f32,
+    default: Option<f32>,
 }
 
 impl AxisRange {
-    pub fn new(minimum: f32, maximum: f32) -> Self {
+    /// A range keeping the axis default, or the nearest value to it within
+    /// the range if the range doesn't include it.
+    pub fn new(minimum: f32, maximum: f32) -> Result<Self, InstancerError> {
         if maximum < minimum {
-            panic!("Range minimum must be more than maximum")
+            return Err(InstancerError(
+                "Range minimum must not be more than maximum".to_string(),
+            ));
+        }
+        Ok(AxisRange {
+            minimum,
+            maximum,
+            default: None,
+        })
+    }
+
+    /// A range with a new default
+    pub fn with_default(minimum: f32, default: f32, maximum: f32) -> Result<Self, InstancerError> {
+        if !(minimum <= default && default <= maximum) {
+            return Err(InstancerError(
+                "Range default must be between minimum and maximum".to_string(),
+            ));
         }
-        AxisRange { minimum, maximum }
+        Ok(AxisRange {
+            minimum,
+            maximum,
+            default: Some(default),
+        })
     }
 }
 #[derive(Debug, Clone, PartialEq)]
 struct NormalizedAxisRange {
     minimum: f32,
+    default: f32,
     maximum: f32,
 }
 
@@ -40,7 +95,7 @@ enum NormalizedAxisLimit {
 #[derive(Debug)]
 pub struct NormalizedAxisLimits(BTreeMap<Tag, NormalizedAxisLimit>);
 type FullNormalizedAxisLimits = Location;
-type PartialNormalizedAxisLimits = BTreeMap<Tag, (f32, f32)>;
+type PartialNormalizedAxisLimits = BTreeMap<Tag, solver::AxisTriple>;
 
 impl NormalizedAxisLimits {
     pub fn split_up(&self) -> (FullNormalizedAxisLimits, PartialNormalizedAxisLimits) {
@@ -51,8 +106,12 @@ impl NormalizedAxisLimits {
                 NormalizedAxisLimit::Full(loc) => {
                     full.insert(tag, *loc);
                 }
-                NormalizedAxisLimit::Partial(NormalizedAxisRange { minimum, maximum }) => {
-                    partial.insert(tag, (*minimum, *maximum));
+                NormalizedAxisLimit::Partial(NormalizedAxisRange {
+                    minimum,
+                    default,
+                    maximum,
+                }) => {
+                    partial.insert(tag, (*minimum, *default, *maximum));
                 }
             };
         }
@@ -88,7 +147,9 @@ impl UserAxisLimits {
                 UserAxisLimit::Full(loc) => {
                     full.insert(tag, *loc);
                 }
-                UserAxisLimit::Partial(AxisRange { minimum, maximum }) => {
+                UserAxisLimit::Partial(AxisRange {
+                    minimum, maximum, ..
+                }) => {
                     partial.insert(tag, (*minimum, *maximum));
                 }
                 UserAxisLimit::Drop => {}
@@ -106,34 +167,121 @@ impl UserAxisLimits {
 //     RemoveAndIgnoreErrors,
 // }
 
-fn instantiate_gvar_data(
-    variations: &mut GlyphVariationData,
+/// Deltas which apply within a region of the design space
+///
+/// The region is given by start, peak and end tuples over the font's axes.
+trait RegionDeltas: Clone + std::fmt::Debug {
+    fn tuples(&self) -> (&Tuple, &Tuple, &Tuple);
+    fn tuples_mut(&mut self) -> (&mut Tuple, &mut Tuple, &mut Tuple);
+    fn scale_deltas(&mut self, factor: f32);
+    fn combine(&self, other: &Self) -> Self;
+
+    fn tent(&self, axis: usize) -> solver::Tent {
+        let (start, peak, end) = self.tuples();
+        (start[axis], peak[axis], end[axis])
+    }
+
+    fn set_tent(&mut self, axis: usize, (lower, peak, upper): solver::Tent) {
+        let (start, peak_tuple, end) = self.tuples_mut();
+        start[axis] = lower;
+        peak_tuple[axis] = peak;
+        end[axis] = upper;
+    }
+
+    fn retain_axes(&mut self, axes: &[usize]) {
+        let keep = |tuple: &mut Tuple| *tuple = axes.iter().map(|&ix| tuple[ix]).collect();
+        let (start, peak, end) = self.tuples_mut();
+        keep(start);
+        keep(peak);
+        keep(end);
+    }
+}
+
+impl RegionDeltas for DeltaSet {
+    fn tuples(&self) -> (&Tuple, &Tuple, &Tuple) {
+        (&self.start, &self.peak, &self.end)
+    }
+    fn tuples_mut(&mut self) -> (&mut Tuple, &mut Tuple, &mut Tuple) {
+        (&mut self.start, &mut self.peak, &mut self.end)
+    }
+    fn scale_deltas(&mut self, factor: f32) {
+        DeltaSet::scale_deltas(self, factor)
+    }
+    fn combine(&self, other: &Self) -> Self {
+        DeltaSet::combine(self, other)
+    }
+}
+
+impl RegionDeltas for cvar::DeltaSet {
+    fn tuples(&self) -> (&Tuple, &Tuple, &Tuple) {
+        (&self.start, &self.peak, &self.end)
+    }
+    fn tuples_mut(&mut self) -> (&mut Tuple, &mut Tuple, &mut Tuple) {
+        (&mut self.start, &mut self.peak, &mut self.end)
+    }
+    fn scale_deltas(&mut self, factor: f32) {
+        cvar::DeltaSet::scale_deltas(self, factor)
+    }
+    fn combine(&self, other: &Self) -> Self {
+        cvar::DeltaSet::combine(self, other)
+    }
+}
+
+/// A region of an item variation store, with the factor by which the deltas
+/// of the original region are scaled within it
+#[derive(Debug, Clone)]
+struct ScaledRegion {
+    start: Tuple,
+    peak: Tuple,
+    end: Tuple,
+    scalar: f32,
+}
+
+impl RegionDeltas for ScaledRegion {
+    fn tuples(&self) -> (&Tuple, &Tuple, &Tuple) {
+        (&self.start, &self.peak, &self.end)
+    }
+    fn tuples_mut(&mut self) -> (&mut Tuple, &mut Tuple, &mut Tuple) {
+        (&mut self.start, &mut self.peak, &mut self.end)
+    }
+    fn scale_deltas(&mut self, factor: f32) {
+        self.scalar *= factor
+    }
+    fn combine(&self, other: &Self) -> Self {
+        let mut new = self.clone();
+        new.scalar += other.scalar;
+        new
+    }
+}
+
+// Pins and limits the axes of a set of regional deltas, returning the new
+// deltas (whose tuples only cover the remaining axes) and the deltas which
+// now apply at the default location.
+fn instantiate_tuple_variations<T: RegionDeltas>(
+    deltasets: Vec<T>,
     axis_tags: &[Tag],
     axis_limits: &NormalizedAxisLimits,
-) -> Coords {
-    let mut new_variations = variations.clone();
+) -> (Vec<T>, Option<T>) {
+    let mut new_variations = deltasets;
     let (pinned, axis_ranges): (FullNormalizedAxisLimits, PartialNormalizedAxisLimits) =
         axis_limits.split_up();
 
     if !pinned.is_empty() {
-        new_variations = pin_tuple_variation_axes(&mut new_variations, &pinned, axis_tags)
+        new_variations = pin_tuple_variation_axes(new_variations, &pinned, axis_tags)
     }
     if !axis_ranges.is_empty() {
-        new_variations = limit_tuple_variation_axis_ranges(new_variations, axis_ranges)
+        new_variations = limit_tuple_variation_axis_ranges(new_variations, &axis_ranges, axis_tags)
     }
-    let mut merged_variations: BTreeMap<Vec<(Tag, F2DOT14, F2DOT14, F2DOT14)>, DeltaSet> =
-        BTreeMap::new();
-    for deltaset in &new_variations.deltasets {
+    let mut merged_variations: BTreeMap<Vec<(Tag, F2DOT14, F2DOT14, F2DOT14)>, T> = BTreeMap::new();
+    for deltaset in &new_variations {
         // We don't need to IUP here as Python does, because we're working on "cooked" delta sets
         let mut tent = vec![];
         for (ix, ax) in axis_tags.iter().enumerate() {
-            let peak = deltaset.peak.get(ix).expect("Where'd my axis go?");
-            if pinned.contains_key(ax) {
+            let (start, peak, end) = deltaset.tent(ix);
+            if pinned.contains_key(ax) || peak == 0.0 {
                 continue;
             }
-            let start = deltaset.start.get(ix).expect("Where'd my axis go?");
-            let end = deltaset.end.get(ix).expect("Where'd my axis go?");
-            tent.push((*ax, F2DOT14(*start), F2DOT14(*peak), F2DOT14(*end)))
+            tent.push((*ax, F2DOT14(start), F2DOT14(peak), F2DOT14(end)))
         }
 
         let new_var = match merged_variations.get(&tent) {
@@ -143,8 +291,7 @@ fn instantiate_gvar_data(
         merged_variations.insert(tent, new_var);
     }
 
-    println!("Merged variations: {:?}", merged_variations);
-    // XXX - wait - axis_tags has the old set of axes...
+    // A tent with no axes applies everywhere, so belongs in the default.
     let default_tent: Vec<(Tag, F2DOT14, F2DOT14, F2DOT14)> = vec![];
     let default_var = merged_variations.remove(&default_tent);
 
@@ -154,7 +301,31 @@ fn instantiate_gvar_data(
     // v.round_deltas();
     // }
 
-    variations.deltasets = merged_variations.values().cloned().collect();
+    // Pinned axes are dropped from the font, so drop them from the tuples.
+    let kept_axes: Vec<usize> = axis_tags
+        .iter()
+        .enumerate()
+        .filter(|(_, ax)| !pinned.contains_key(*ax))
+        .map(|(ix, _)| ix)
+        .collect();
+    let deltasets = merged_variations
+        .into_values()
+        .map(|mut deltaset| {
+            deltaset.retain_axes(&kept_axes);
+            deltaset
+        })
+        .collect();
+    (deltasets, default_var)
+}
+
+fn instantiate_gvar_data(
+    variations: &mut GlyphVariationData,
+    axis_tags: &[Tag],
+    axis_limits: &NormalizedAxisLimits,
+) -> Coords {
+    let (deltasets, default_var) =
+        instantiate_tuple_variations(variations.deltasets.clone(), axis_tags, axis_limits);
+    variations.deltasets = deltasets;
     if let Some(default) = default_var {
         default.deltas
     } else {
@@ -162,14 +333,14 @@ fn instantiate_gvar_data(
     }
 }
 
-fn pin_tuple_variation_axes(
-    variations: &mut GlyphVariationData,
+fn pin_tuple_variation_axes<T: RegionDeltas>(
+    variations: Vec<T>,
     location: &FullNormalizedAxisLimits,
     axis_tags: &[Tag],
-) -> GlyphVariationData {
-    let mut new_deltas: Vec<gvar::DeltaSet> = vec![];
-    for var in variations.deltasets.iter_mut() {
-        println!("Deltaset : {:?}", var);
+) -> Vec<T> {
+    let mut new_deltas: Vec<T> = vec![];
+    for mut var in variations {
+        log::debug!("Deltaset : {:?}", var);
 
         // Deltaset is a set of tuples using the font's existing axes
         let mut support = BTreeMap::new();
@@ -178,38 +349,216 @@ fn pin_tuple_variation_axes(
                 .iter()
                 .position(|t| t == tag)
                 .expect("Axis in location wasn't in font");
-            let support_for_this_axis = (var.start[index], var.peak[index], var.end[index]);
-            println!("Support for {}: {:?}", tag, support_for_this_axis);
+            let support_for_this_axis = var.tent(index);
+            log::debug!("Support for {}: {:?}", tag, support_for_this_axis);
             support.insert(*tag, support_for_this_axis);
         }
         let scalar = support_scalar(location, &support);
-        println!("Support scalar for {:?}: {:?}", location, scalar);
+        log::debug!("Support scalar for {:?}: {:?}", location, scalar);
         if scalar == 0.0 {
             continue;
         }
         var.scale_deltas(scalar);
-        new_deltas.push(var.clone());
+        new_deltas.push(var);
     }
-    println!("Pinned deltas: {:?}", new_deltas);
-    GlyphVariationData {
-        deltasets: new_deltas,
+    log::debug!("Pinned deltas: {:?}", new_deltas);
+    new_deltas
+}
+
+fn limit_tuple_variation_axis_ranges<T: RegionDeltas>(
+    variations: Vec<T>,
+    axis_ranges: &PartialNormalizedAxisLimits,
+    axis_tags: &[Tag],
+) -> Vec<T> {
+    let mut deltasets = variations;
+    for (tag, &limit) in axis_ranges {
+        let index = axis_tags
+            .iter()
+            .position(|t| t == tag)
+            .expect("Axis in limits wasn't in font");
+        deltasets = deltasets
+            .into_iter()
+            .flat_map(|deltaset| limit_tuple_variation_axis_range(deltaset, index, limit))
+            .collect();
     }
+    deltasets
 }
 
-fn limit_tuple_variation_axis_ranges(
-    _tvs: GlyphVariationData,
-    _axis_ranges: PartialNormalizedAxisLimits,
-) -> GlyphVariationData {
-    unimplemented!()
+// Rebases a delta set onto the new limits of one axis, which may take
+// several delta sets; a delta set which no longer varies along the axis
+// applies at the new default.
+fn limit_tuple_variation_axis_range<T: RegionDeltas>(
+    deltaset: T,
+    index: usize,
+    limit: solver::AxisTriple,
+) -> Vec<T> {
+    let tent = deltaset.tent(index);
+    let (lower, peak, upper) = tent;
+    if peak == 0.0 {
+        return vec![deltaset];
+    }
+    // Drop delta sets whose region isn't well-formed
+    if lower > peak || peak > upper || (lower < 0.0 && upper > 0.0) {
+        return vec![];
+    }
+    solver::rebase_tent(tent, limit)
+        .into_iter()
+        .map(|(scalar, tent)| {
+            let mut new = deltaset.clone();
+            new.set_tent(index, tent.unwrap_or((0.0, 0.0, 0.0)));
+            new.scale_deltas(scalar);
+            new
+        })
+        .collect()
 }
 
-fn sanity_check(font: &Font) {
-    if !font.contains_table(tag!("fvar")) {
-        panic!("Missing required table fvar")
+/// Instantiates an item variation store, returning the new store and the
+/// delta of each item at the new default location, indexed by outer and
+/// inner index. Items keep their indices in the new store.
+fn instantiate_item_variation_store(
+    store: &ItemVariationStore,
+    axis_tags: &[Tag],
+    axis_limits: &NormalizedAxisLimits,
+) -> (ItemVariationStore, Vec<Vec<f32>>) {
+    let (new_store, mappings) = rebase_item_variation_store(store, axis_tags, axis_limits);
+    let defaults = store
+        .variationData
+        .iter()
+        .zip(mappings.iter())
+        .map(|(data, mapping)| {
+            data.delta_values
+                .iter()
+                .map(|row| {
+                    let row: Vec<f32> = row.iter().map(|&d| d as f32).collect();
+                    mapping.apply(&row).0
+                })
+                .collect()
+        })
+        .collect();
+    (new_store, defaults)
+}
+
+/// Instantiates the regions of an item variation store, returning the new
+/// store and how the deltas of each item variation data subtable were
+/// redistributed into it.
+fn rebase_item_variation_store(
+    store: &ItemVariationStore,
+    axis_tags: &[Tag],
+    axis_limits: &NormalizedAxisLimits,
+) -> (ItemVariationStore, Vec<PinnedRegions>) {
+    // Each region becomes a set of new regions, plus a contribution to the
+    // default.
+    let mut new_regions: Vec<Vec<RegionAxisCoordinates>> = vec![];
+    let region_map: Vec<(Vec<(usize, f32)>, f32)> = store
+        .variationRegions
+        .iter()
+        .map(|region| {
+            let scaled = ScaledRegion {
+                start: region.iter().map(|c| c.startCoord).collect(),
+                peak: region.iter().map(|c| c.peakCoord).collect(),
+                end: region.iter().map(|c| c.endCoord).collect(),
+                scalar: 1.0,
+            };
+            let (regions, default) =
+                instantiate_tuple_variations(vec![scaled], axis_tags, axis_limits);
+            let targets = regions
+                .into_iter()
+                .map(|r| {
+                    let coords: Vec<RegionAxisCoordinates> = (0..r.peak.len())
+                        .map(|ix| RegionAxisCoordinates {
+                            startCoord: r.start[ix],
+                            peakCoord: r.peak[ix],
+                            endCoord: r.end[ix],
+                        })
+                        .collect();
+                    let index = new_regions
+                        .iter()
+                        .position(|existing| *existing == coords)
+                        .unwrap_or_else(|| {
+                            new_regions.push(coords);
+                            new_regions.len() - 1
+                        });
+                    (index, r.scalar)
+                })
+                .collect();
+            (targets, default.map_or(0.0, |d| d.scalar))
+        })
+        .collect();
+
+    let mut mappings = vec![];
+    let mut variation_data = vec![];
+    for data in &store.variationData {
+        let mut region_indexes: Vec<uint16> = vec![];
+        let columns: Vec<(Vec<(usize, f32)>, f32)> = data
+            .region_indexes
+            .iter()
+            .map(|&ix| match region_map.get(ix as usize) {
+                Some((targets, default)) => (
+                    targets
+                        .iter()
+                        .map(|&(region, scalar)| {
+                            let column = region_indexes
+                                .iter()
+                                .position(|&r| r as usize == region)
+                                .unwrap_or_else(|| {
+                                    region_indexes.push(region as uint16);
+                                    region_indexes.len() - 1
+                                });
+                            (column, scalar)
+                        })
+                        .collect(),
+                    *default,
+                ),
+                None => (vec![], 0.0),
+            })
+            .collect();
+        let mapping = PinnedRegions {
+            columns,
+            region_count: region_indexes.len(),
+        };
+        let delta_values = data
+            .delta_values
+            .iter()
+            .map(|row| {
+                let row: Vec<f32> = row.iter().map(|&d| d as f32).collect();
+                mapping
+                    .apply(&row)
+                    .1
+                    .iter()
+                    .map(|&d| ot_round(d) as int16)
+                    .collect()
+            })
+            .collect();
+        mappings.push(mapping);
+        variation_data.push(ItemVariationData {
+            region_indexes,
+            delta_values,
+        });
     }
-    if font.contains_table(tag!("CFF2")) {
-        panic!("I don't speak CFF")
+    let (pinned, _) = axis_limits.split_up();
+    (
+        ItemVariationStore {
+            format: store.format,
+            axisCount: store.axisCount - pinned.len() as uint16,
+            variationRegions: new_regions,
+            variationData: variation_data,
+        },
+        mappings,
+    )
+}
+
+fn sanity_check(font: &Font) -> Result<(), InstancerError> {
+    if !font.contains_table(fvar::TAG) {
+        return Err(missing_table(fvar::TAG));
+    }
+    if font.contains_table(cvar::TAG) && !font.contains_table(cvt::TAG) {
+        return Err(missing_table(cvt::TAG));
     }
+    Ok(())
+}
+
+fn missing_table(tag: Tag) -> InstancerError {
+    InstancerError(format!("Missing required table {}", tag))
 }
 
 fn instantiate_gvar_glyph(
@@ -220,16 +569,32 @@ fn instantiate_gvar_glyph(
     axis_limits: &NormalizedAxisLimits,
 ) {
     let glyph = glyf.glyphs.get_mut(ix).unwrap();
-    println!("Handling glyph {:?}", ix);
+    log::debug!("Handling glyph {:?}", ix);
 
     if let Some(var) = gvar.variations.get_mut(ix).unwrap() {
-        let mut deltas = instantiate_gvar_data(var, axis_tags, axis_limits).into_iter();
-        println!("New deltas: {:?}", deltas);
-        for contour in glyph.contours.iter_mut() {
-            for point in contour.iter_mut() {
-                let delta = deltas.next().expect("Not enough deltas for glyph");
-                point.x += delta.0;
-                point.y += delta.1;
+        let deltas = instantiate_gvar_data(var, axis_tags, axis_limits);
+        log::debug!("New deltas: {:?}", deltas);
+        // If no deltas apply at the new default, the outline is unchanged
+        if !deltas.is_empty() {
+            let mut deltas = deltas.into_iter();
+            for contour in glyph.contours.iter_mut() {
+                for point in contour.iter_mut() {
+                    let delta = deltas.next().expect("Not enough deltas for glyph");
+                    point.x += delta.0;
+                    point.y += delta.1;
+                }
+            }
+            if !glyph.has_components() && !glyph.is_empty() {
+                let (xs, ys): (Vec<i16>, Vec<i16>) = glyph
+                    .contours
+                    .iter()
+                    .flatten()
+                    .map(|pt| (pt.x, pt.y))
+                    .unzip();
+                glyph.xMin = *xs.iter().min().unwrap_or(&0);
+                glyph.xMax = *xs.iter().max().unwrap_or(&0);
+                glyph.yMin = *ys.iter().min().unwrap_or(&0);
+                glyph.yMax = *ys.iter().max().unwrap_or(&0);
             }
         }
         // XXX phantom points
@@ -267,6 +632,517 @@ fn instantiate_gvar(font: &mut Font, axis_limits: &NormalizedAxisLimits) {
     font.tables.insert(glyf);
 }
 
+fn instantiate_cff2(
+    font: &mut Font,
+    axis_limits: &NormalizedAxisLimits,
+) -> Result<(), InstancerError> {
+    log::info!("Instantiating CFF2 table");
+    let axis_tags = axis_tags(font)?;
+    let mut cff2 = font.tables.CFF2()?.unwrap();
+    if let Some(store) = &cff2.variation_store {
+        let (new_store, mappings) = rebase_item_variation_store(store, &axis_tags, axis_limits);
+        cff2.rebase_blends(new_store, &mappings)
+            .map_err(|e| InstancerError(format!("Could not instantiate CFF2 table: {}", e)))?;
+    }
+    font.tables.insert(cff2);
+    Ok(())
+}
+
+fn axis_tags(font: &Font) -> Result<Vec<Tag>, InstancerError> {
+    let fvar = font
+        .tables
+        .fvar()?
+        .ok_or_else(|| missing_table(fvar::TAG))?;
+    Ok(fvar.axes.iter().map(|x| x.axisTag).collect())
+}
+
+// Looks up the delta of an item at the new default location.
+fn default_delta(defaults: &[Vec<f32>], (outer, inner): (uint16, uint16)) -> f32 {
+    defaults
+        .get(outer as usize)
+        .and_then(|data| data.get(inner as usize))
+        .copied()
+        .unwrap_or(0.0)
+}
+
+fn instantiate_cvar(
+    font: &mut Font,
+    axis_limits: &NormalizedAxisLimits,
+) -> Result<(), InstancerError> {
+    log::info!("Instantiating cvt/cvar tables");
+    let axis_tags = axis_tags(font)?;
+    let cvar = font
+        .tables
+        .cvar()?
+        .ok_or_else(|| missing_table(cvar::TAG))?;
+    let (deltasets, default_var) =
+        instantiate_tuple_variations(cvar.deltasets.clone(), &axis_tags, axis_limits);
+    if let Some(default) = default_var {
+        let mut cvt = font.tables.cvt()?.ok_or_else(|| missing_table(cvt::TAG))?;
+        for (value, delta) in cvt.0.iter_mut().zip(default.deltas) {
+            *value += delta;
+        }
+        font.tables.insert(cvt);
+    }
+    let deltasets: Vec<cvar::DeltaSet> = deltasets
+        .into_iter()
+        .filter(|ds| ds.deltas.iter().any(|&d| d != 0))
+        .collect();
+    if deltasets.is_empty() {
+        log::info!("Dropping cvar table");
+        font.tables.remove(cvar::TAG);
+    } else {
+        font.tables.insert(cvar::cvar { deltasets });
+    }
+    Ok(())
+}
+
+#[allow(non_snake_case)]
+fn instantiate_MVAR(
+    font: &mut Font,
+    axis_limits: &NormalizedAxisLimits,
+) -> Result<(), InstancerError> {
+    log::info!("Instantiating MVAR table");
+    let axis_tags = axis_tags(font)?;
+    let mut mvar = font
+        .tables
+        .MVAR()?
+        .ok_or_else(|| missing_table(MVAR::TAG))?;
+    let (store, defaults) = match &mvar.variation_store {
+        Some(store) => instantiate_item_variation_store(store, &axis_tags, axis_limits),
+        None => {
+            font.tables.remove(MVAR::TAG);
+            return Ok(());
+        }
+    };
+    let deltas: BTreeMap<Tag, f32> = mvar
+        .values
+        .iter()
+        .map(|(&tag, &index)| (tag, default_delta(&defaults, index)))
+        .filter(|&(_, delta)| delta != 0.0)
+        .collect();
+    apply_mvar_deltas(font, &deltas)?;
+    if store.axisCount == 0 {
+        log::info!("Dropping MVAR table");
+        font.tables.remove(MVAR::TAG);
+    } else {
+        mvar.variation_store = Some(store);
+        font.tables.insert(mvar);
+    }
+    Ok(())
+}
+
+// Applies the deltas of font-wide metrics, keyed by their MVAR value tags.
+fn apply_mvar_deltas(font: &mut Font, deltas: &BTreeMap<Tag, f32>) -> Result<(), InstancerError> {
+    let shift = |value: &mut int16, value_tag: Tag| {
+        if let Some(delta) = deltas.get(&value_tag) {
+            *value = ot_round(*value as f32 + delta) as int16;
+        }
+    };
+    let shift_unsigned = |value: &mut uint16, value_tag: Tag| {
+        if let Some(delta) = deltas.get(&value_tag) {
+            *value = ot_round(*value as f32 + delta).max(0) as uint16;
+        }
+    };
+
+    if let Some(table) = font.tables.os2()? {
+        let mut os2 = table.clone().into_owned();
+        shift(&mut os2.sTypoAscender, tag!("hasc"));
+        shift(&mut os2.sTypoDescender, tag!("hdsc"));
+        shift(&mut os2.sTypoLineGap, tag!("hlgp"));
+        shift_unsigned(&mut os2.usWinAscent, tag!("hcla"));
+        shift_unsigned(&mut os2.usWinDescent, tag!("hcld"));
+        if let Some(x_height) = os2.sxHeight.as_mut() {
+            shift(x_height, tag!("xhgt"));
+        }
+        if let Some(cap_height) = os2.sCapHeight.as_mut() {
+            shift(cap_height, tag!("cpht"));
+        }
+        shift(&mut os2.ySubscriptXSize, tag!("sbxs"));
+        shift(&mut os2.ySubscriptYSize, tag!("sbys"));
+        shift(&mut os2.ySubscriptXOffset, tag!("sbxo"));
+        shift(&mut os2.ySubscriptYOffset, tag!("sbyo"));
+        shift(&mut os2.ySuperscriptXSize, tag!("spxs"));
+        shift(&mut os2.ySuperscriptYSize, tag!("spys"));
+        shift(&mut os2.ySuperscriptXOffset, tag!("spxo"));
+        shift(&mut os2.ySuperscriptYOffset, tag!("spyo"));
+        shift(&mut os2.yStrikeoutSize, tag!("strs"));
+        shift(&mut os2.yStrikeoutPosition, tag!("stro"));
+        if os2 != *table {
+            font.tables.insert(os2);
+        }
+    }
+    if let Some(table) = font.tables.hhea()? {
+        let mut hhea = table.clone().into_owned();
+        shift(&mut hhea.caretSlopeRise, tag!("hcrs"));
+        shift(&mut hhea.caretSlopeRun, tag!("hcrn"));
+        shift(&mut hhea.caretOffset, tag!("hcof"));
+        if hhea != *table {
+            font.tables.insert(hhea);
+        }
+    }
+    if let Some(table) = font.tables.post()? {
+        let mut post = table.clone().into_owned();
+        shift(&mut post.underlinePosition, tag!("undo"));
+        shift(&mut post.underlineThickness, tag!("unds"));
+        if post != *table {
+            font.tables.insert(post);
+        }
+    }
+    Ok(())
+}
+
+#[allow(non_snake_case)]
+fn instantiate_HVAR(
+    font: &mut Font,
+    axis_limits: &NormalizedAxisLimits,
+) -> Result<(), InstancerError> {
+    log::info!("Instantiating HVAR/hmtx tables");
+    let axis_tags = axis_tags(font)?;
+    let mut hvar = font
+        .tables
+        .HVAR()?
+        .ok_or_else(|| missing_table(HVAR::TAG))?;
+    let (store, defaults) =
+        instantiate_item_variation_store(&hvar.variation_store, &axis_tags, axis_limits);
+
+    if let Some(mut hmtx) = font.tables.hmtx()? {
+        let glyf = font.tables.glyf()?;
+        for (gid, metric) in hmtx.metrics.iter_mut().enumerate() {
+            let gid = gid as GlyphID;
+            let advance_delta = default_delta(&defaults, hvar.advance_index(gid));
+            metric.advanceWidth =
+                ot_round(metric.advanceWidth as f32 + advance_delta).max(0) as uint16;
+            if let Some(index) = hvar.lsb_index(gid) {
+                metric.lsb = ot_round(metric.lsb as f32 + default_delta(&defaults, index)) as int16;
+            } else if let Some(glyph) = glyf.as_ref().and_then(|g| g.glyphs.get(gid as usize)) {
+                // A TrueType glyph's left side bearing is its xMin
+                if !glyph.is_empty() && !glyph.has_components() {
+                    metric.lsb = glyph.xMin;
+                }
+            }
+        }
+        font.tables.insert(hmtx);
+    }
+
+    if store.axisCount == 0 {
+        log::info!("Dropping HVAR table");
+        font.tables.remove(HVAR::TAG);
+    } else {
+        hvar.variation_store = store;
+        font.tables.insert(hvar);
+    }
+    Ok(())
+}
+
+#[allow(non_snake_case)]
+fn instantiate_VVAR(
+    font: &mut Font,
+    axis_limits: &NormalizedAxisLimits,
+) -> Result<(), InstancerError> {
+    log::info!("Instantiating VVAR/vmtx tables");
+    let axis_tags = axis_tags(font)?;
+    let mut vvar = font
+        .tables
+        .VVAR()?
+        .ok_or_else(|| missing_table(VVAR::TAG))?;
+    let (store, defaults) =
+        instantiate_item_variation_store(&vvar.variation_store, &axis_tags, axis_limits);
+
+    if let Some(mut vmtx) = font.tables.vmtx()? {
+        for (gid, metric) in vmtx.metrics.iter_mut().enumerate() {
+            let gid = gid as GlyphID;
+            let advance_delta = default_delta(&defaults, vvar.advance_index(gid));
+            metric.advanceHeight =
+                ot_round(metric.advanceHeight as f32 + advance_delta).max(0) as uint16;
+            if let Some(index) = vvar.tsb_index(gid) {
+                metric.tsb = ot_round(metric.tsb as f32 + default_delta(&defaults, index)) as int16;
+            }
+        }
+        font.tables.insert(vmtx);
+    }
+    if store.axisCount == 0 {
+        log::info!("Dropping VVAR table");
+        font.tables.remove(VVAR::TAG);
+    } else {
+        vvar.variation_store = store;
+        font.tables.insert(vvar);
+    }
+    Ok(())
+}
+
+// Returns the delta at the new default location of a VariationIndex table,
+// dropping the table unless it is to be kept. Device tables are left alone.
+fn variation_index_delta(
+    device: &mut Option<Offset16<Device>>,
+    defaults: &[Vec<f32>],
+    keep: bool,
+) -> int16 {
+    let index = match device
+        .as_ref()
+        .and_then(|d| d.link.as_ref())
+        .and_then(|d| d.as_variation_index())
+    {
+        Some(index) => index,
+        None => return 0,
+    };
+    if !keep {
+        *device = None;
+    }
+    ot_round(default_delta(defaults, index)) as int16
+}
+
+fn instantiate_value_record(vr: &mut ValueRecord, defaults: &[Vec<f32>], keep: bool) {
+    for (value, device) in [
+        (&mut vr.xPlacement, &mut vr.xPlaDevice),
+        (&mut vr.yPlacement, &mut vr.yPlaDevice),
+        (&mut vr.xAdvance, &mut vr.xAdvDevice),
+        (&mut vr.yAdvance, &mut vr.yAdvDevice),
+    ] {
+        let delta = variation_index_delta(device, defaults, keep);
+        if delta != 0 {
+            *value = Some(value.unwrap_or(0) + delta);
+        }
+    }
+}
+
+fn instantiate_anchor(anchor: &mut Anchor, defaults: &[Vec<f32>], keep: bool) {
+    anchor.xCoordinate += variation_index_delta(&mut anchor.xDevice, defaults, keep);
+    anchor.yCoordinate += variation_index_delta(&mut anchor.yDevice, defaults, keep);
+}
+
+fn instantiate_positioning(rule: &mut Positioning, defaults: &[Vec<f32>], keep: bool) {
+    let value_records: Vec<&mut ValueRecord> = match rule {
+        Positioning::Single(subtables) => subtables
+            .iter_mut()
+            .flat_map(|st| st.mapping.values_mut())
+            .collect(),
+        Positioning::Pair(subtables) => subtables
+            .iter_mut()
+            .flat_map(|st| st.mapping.values_mut())
+            .flat_map(|(first, second)| [first, second])
+            .collect(),
+        _ => vec![],
+    };
+    for vr in value_records {
+        instantiate_value_record(vr, defaults, keep);
+    }
+    let anchors: Vec<&mut Anchor> = match rule {
+        Positioning::Cursive(subtables) => subtables
+            .iter_mut()
+            .flat_map(|st| st.mapping.values_mut())
+            .flat_map(|(entry, exit)| entry.iter_mut().chain(exit.iter_mut()))
+            .collect(),
+        Positioning::MarkToBase(subtables) => subtables
+            .iter_mut()
+            .flat_map(|st| {
+                st.marks
+                    .values_mut()
+                    .map(|(_, anchor)| anchor)
+                    .chain(st.bases.values_mut().flat_map(|a| a.values_mut()))
+            })
+            .collect(),
+        Positioning::MarkToLig(subtables) => subtables
+            .iter_mut()
+            .flat_map(|st| {
+                st.marks.values_mut().map(|(_, anchor)| anchor).chain(
+                    st.ligatures
+                        .values_mut()
+                        .flatten()
+                        .flat_map(|a| a.values_mut()),
+                )
+            })
+            .collect(),
+        Positioning::MarkToMark(subtables) => subtables
+            .iter_mut()
+            .flat_map(|st| {
+                st.combining_marks
+                    .values_mut()
+                    .map(|(_, anchor)| anchor)
+                    .chain(st.base_marks.values_mut().flat_map(|a| a.values_mut()))
+            })
+            .collect(),
+        _ => vec![],
+    };
+    for anchor in anchors {
+        instantiate_anchor(anchor, defaults, keep);
+    }
+}
+
+/// Instantiates the variations of positioning values and ligature carets,
+/// which refer to items of the `GDEF` table's item variation store through
+/// VariationIndex tables.
+///
+/// The deltas at the new default location are added to the static values.
+/// If the font is no longer variable, the VariationIndex tables and the
+/// store are dropped; otherwise they are kept, as the items keep their
+/// indices in the instantiated store.
+fn instantiate_otl(
+    font: &mut Font,
+    axis_limits: &NormalizedAxisLimits,
+) -> Result<(), InstancerError> {
+    let mut gdef = match font.tables.GDEF()? {
+        Some(gdef) if gdef.item_variation_store.is_some() => gdef,
+        _ => return Ok(()),
+    };
+    log::info!("Instantiating GDEF and GPOS tables");
+    let axis_tags = axis_tags(font)?;
+    let (store, defaults) = instantiate_item_variation_store(
+        gdef.item_variation_store.as_ref().unwrap(),
+        &axis_tags,
+        axis_limits,
+    );
+    let keep = store.axisCount > 0;
+
+    for carets in gdef.ligature_caret_list.values_mut() {
+        for caret in carets.iter_mut() {
+            if let CaretValue::Format3 { coordinate, device } = caret {
+                let index = device.link.as_ref().and_then(|d| d.as_variation_index());
+                if let Some(index) = index {
+                    *coordinate += ot_round(default_delta(&defaults, index)) as int16;
+                    if !keep {
+                        *caret = CaretValue::Format1 {
+                            coordinate: *coordinate,
+                        };
+                    }
+                }
+            }
+        }
+    }
+    if keep {
+        gdef.item_variation_store = Some(store);
+    } else {
+        log::info!("Dropping GDEF item variation store");
+        gdef.item_variation_store = None;
+    }
+    font.tables.insert(gdef);
+
+    if let Some(mut gpos) = font.tables.GPOS()? {
+        for lookup in gpos.lookups.iter_mut() {
+            instantiate_positioning(&mut lookup.rule, &defaults, keep);
+        }
+        font.tables.insert(gpos);
+    }
+    Ok(())
+}
+
+// Instantiates the conditions of a feature variation, returning `None` if
+// it no longer applies anywhere. An empty set of conditions applies
+// everywhere. Axis indices are renumbered to skip the pinned axes.
+fn instantiate_conditions(
+    conditions: &[Condition],
+    axis_tags: &[Tag],
+    axis_limits: &NormalizedAxisLimits,
+) -> Option<ConditionSet> {
+    let (pinned, limited) = axis_limits.split_up();
+    let mut new_conditions = vec![];
+    for condition in conditions {
+        // A condition on an unknown axis can never hold
+        let tag = *axis_tags.get(condition.axis_index as usize)?;
+        if let Some(&value) = pinned.get(&tag) {
+            if value < condition.min || value > condition.max {
+                return None;
+            }
+            continue;
+        }
+        let (min, max) = match limited.get(&tag) {
+            Some(&triple) => {
+                let (axis_min, _, axis_max) = triple;
+                if condition.max < axis_min || condition.min > axis_max {
+                    return None;
+                }
+                // Conditions reaching beyond the new limits extend to the
+                // ends of the axis.
+                let renormalize = |value: f32| solver::renormalize_value(value, triple);
+                (
+                    if condition.min <= axis_min {
+                        -1.0
+                    } else {
+                        renormalize(condition.min)
+                    },
+                    if condition.max >= axis_max {
+                        1.0
+                    } else {
+                        renormalize(condition.max)
+                    },
+                )
+            }
+            None => (condition.min, condition.max),
+        };
+        // A condition covering the whole axis always holds
+        if min <= -1.0 && max >= 1.0 {
+            continue;
+        }
+        let axis_index = axis_tags[..condition.axis_index as usize]
+            .iter()
+            .filter(|&tag| !pinned.contains_key(tag))
+            .count() as uint16;
+        new_conditions.push(Condition {
+            axis_index,
+            min,
+            max,
+        });
+    }
+    Some(new_conditions)
+}
+
+fn instantiate_feature_variations_table<T>(
+    table: &mut GPOSGSUB<T>,
+    axis_tags: &[Tag],
+    axis_limits: &NormalizedAxisLimits,
+) {
+    let mut feature_variations = vec![];
+    for variation in table.feature_variations.drain(..) {
+        let conditions = match instantiate_conditions(&variation.conditions, axis_tags, axis_limits)
+        {
+            Some(conditions) => conditions,
+            None => continue,
+        };
+        if conditions.is_empty() {
+            // The variation now applies everywhere, so its substitutions
+            // become permanent and the variations after it are unreachable.
+            for (&feature, lookups) in &variation.substitution {
+                if let Some((_, feature_lookups, _)) = table.features.iter_mut().nth(feature) {
+                    *feature_lookups = lookups.clone();
+                }
+            }
+            break;
+        }
+        feature_variations.push(FeatureVariation {
+            conditions,
+            substitution: variation.substitution,
+        });
+    }
+    table.feature_variations = feature_variations;
+}
+
+/// Instantiates the feature variations of the `GSUB` and `GPOS` tables.
+///
+/// Variations whose conditions can no longer hold are dropped, and the
+/// substitutions of the first variation which now holds everywhere are
+/// applied to the features permanently.
+fn instantiate_feature_variations(
+    font: &mut Font,
+    axis_limits: &NormalizedAxisLimits,
+) -> Result<(), InstancerError> {
+    let axis_tags = axis_tags(font)?;
+    if let Some(mut gsub) = font.tables.GSUB()? {
+        if !gsub.feature_variations.is_empty() {
+            log::info!("Instantiating GSUB feature variations");
+            instantiate_feature_variations_table(&mut gsub, &axis_tags, axis_limits);
+            font.tables.insert(gsub);
+        }
+    }
+    if let Some(mut gpos) = font.tables.GPOS()? {
+        if !gpos.feature_variations.is_empty() {
+            log::info!("Instantiating GPOS feature variations");
+            instantiate_feature_variations_table(&mut gpos, &axis_tags, axis_limits);
+            font.tables.insert(gpos);
+        }
+    }
+    Ok(())
+}
+
 fn instantiate_avar(font: &mut Font, axis_limits: &UserAxisLimits) {
     let (location, _axis_ranges): (FullUserAxisLimits, PartialUserAxisLimits) =
         axis_limits.split_up();
@@ -306,38 +1182,27 @@ fn instantiate_avar(font: &mut Font, axis_limits: &UserAxisLimits) {
         if !segment.is_valid() {
             continue;
         }
-        if let Some(&(minimum, maximum)) = normalized_ranges.get(&axis_tag) {
-            let mapped_min = F2DOT14::round(segment.piecewise_linear_map(minimum));
-            let mapped_max = F2DOT14::round(segment.piecewise_linear_map(maximum));
-            let mut new_mapping: Vec<(f32, f32)> = vec![];
+        if let Some(&limit) = normalized_ranges.get(&axis_tag) {
+            let (minimum, default, maximum) = limit;
+            let mapped_limit = (
+                F2DOT14::round(segment.piecewise_linear_map(minimum)),
+                F2DOT14::round(segment.piecewise_linear_map(default)),
+                F2DOT14::round(segment.piecewise_linear_map(maximum)),
+            );
+            let mut new_mapping: Vec<(f32, f32)> = vec![(-1.0, -1.0), (0.0, 0.0), (1.0, 1.0)];
             for avm in &segment.0 {
-                let (mut from_coord, mut to_coord) = (avm.0, avm.1);
-                if from_coord < 0.0 {
-                    if minimum == 0.0 || from_coord < minimum {
-                        continue;
-                    } else {
-                        from_coord /= minimum.abs();
-                    }
-                } else if from_coord > 0.0 {
-                    if maximum == 0.0 || from_coord > maximum {
-                        continue;
-                    } else {
-                        from_coord /= maximum;
-                    }
+                let (from_coord, to_coord) = (avm.0, avm.1);
+                if from_coord < minimum || from_coord > maximum {
+                    continue;
                 }
-                if to_coord < 0.0 {
-                    assert!(mapped_min != 0.0);
-                    assert!(to_coord >= mapped_min);
-                    to_coord /= mapped_min.abs()
-                } else if to_coord > 0.0 {
-                    assert!(mapped_max != 0.0);
-                    assert!(to_coord <= mapped_max);
-                    to_coord /= mapped_max.abs()
+                assert!(mapped_limit.0 <= to_coord && to_coord <= mapped_limit.2);
+                let from_coord = F2DOT14::round(solver::renormalize_value(from_coord, limit));
+                let to_coord = F2DOT14::round(solver::renormalize_value(to_coord, mapped_limit));
+                if ![-1.0, 0.0, 1.0].contains(&from_coord) {
+                    new_mapping.push((from_coord, to_coord));
                 }
-                from_coord = F2DOT14::round(from_coord);
-                to_coord = F2DOT14::round(to_coord);
-                new_mapping.push((from_coord, to_coord));
             }
+            new_mapping.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
             new_segments.insert(axis_tag, avar::SegmentMap::new(new_mapping));
         } else {
             new_segments.insert(axis_tag, segment);
@@ -386,9 +1251,12 @@ fn instantiate_fvar(font: &mut Font, axis_limits: &UserAxisLimits) {
         if location.contains_key(&axis_tag) {
             continue;
         }
-        if let Some(&(minimum, maximum)) = axis_ranges.get(&axis_tag) {
-            axis.minValue = minimum;
-            axis.maxValue = maximum;
+        if let Some(UserAxisLimit::Partial(range)) = axis_limits.0.get(&axis_tag) {
+            axis.minValue = range.minimum;
+            axis.maxValue = range.maximum;
+            if let Some(default) = range.default {
+                axis.defaultValue = default;
+            }
         }
         new_axes.push(axis.clone());
     }
@@ -505,26 +1373,61 @@ fn set_mac_overlap_flags(glyf: &mut glyf::glyf) {
     }
 }
 
-fn populate_axis_defaults(font: &mut Font, mut limits: UserAxisLimits) -> UserAxisLimits {
+fn populate_axis_defaults(
+    font: &mut Font,
+    mut limits: UserAxisLimits,
+) -> Result<UserAxisLimits, InstancerError> {
     let fvar = font.tables.fvar().unwrap().unwrap();
     let defaults: Location = fvar
         .axes
         .iter()
         .map(|ax| (ax.axisTag, ax.defaultValue))
         .collect();
-    for (k, v) in limits
-        .0
-        .iter_mut()
-        .filter(|(_k, v)| matches!(v, UserAxisLimit::Drop))
-    {
-        *v = UserAxisLimit::Full(*defaults.get(k).expect("Unknown axis"));
+    for (k, v) in limits.0.iter_mut() {
+        let default = *defaults
+            .get(k)
+            .ok_or_else(|| InstancerError(format!("Can't limit {} - axis not in font", k)))?;
+        match v {
+            UserAxisLimit::Drop => *v = UserAxisLimit::Full(default),
+            // Keep the default, or the nearest value to it in the range
+            UserAxisLimit::Partial(range) => {
+                range
+                    .default
+                    .get_or_insert(default.clamp(range.minimum, range.maximum));
+            }
+            UserAxisLimit::Full(_) => {}
+        }
     }
-    limits
+    Ok(limits)
+}
+
+// The location of the instance's default in user coordinates: the pinned
+// and new default values of limited axes, and the defaults of other axes.
+fn new_default_location(font: &Font, limits: &UserAxisLimits) -> Location {
+    let fvar = font.tables.fvar().unwrap().unwrap();
+    fvar.axes
+        .iter()
+        .map(|ax| {
+            let value = match limits.0.get(&ax.axisTag) {
+                Some(UserAxisLimit::Full(value)) => *value,
+                Some(UserAxisLimit::Partial(range)) => range.default.unwrap_or(ax.defaultValue),
+                _ => ax.defaultValue,
+            };
+            (ax.axisTag, value)
+        })
+        .collect()
 }
 
 fn normalize(value: f32, triple: (f32, f32, f32), avar_segment: Option<&SegmentMap>) -> f32 {
-    let (minv, _default, maxv) = triple;
-    let mut value = (value.clamp(minv, maxv) - minv) / (maxv - minv);
+    let (minv, default, maxv) = triple;
+    let value = value.clamp(minv, maxv);
+    let mut value = if value < default {
+        (value - default) / (default - minv)
+    } else if value > default {
+        (value - default) / (maxv - default)
+    } else {
+        0.0
+    };
     if let Some(map) = avar_segment {
         value = map.piecewise_linear_map(value);
     }
@@ -537,12 +1440,8 @@ fn normalize_axis_limits(
     use_avar: bool,
 ) -> NormalizedAxisLimits {
     let fvar = font.tables.fvar().unwrap().unwrap();
+    // Unknown axes were already rejected by populate_axis_defaults
     let all_axes: Vec<Tag> = fvar.axes.iter().map(|x| x.axisTag).collect();
-    for ax in limits.0.keys() {
-        if !all_axes.contains(ax) {
-            panic!("Can't limit {} - axis not in font", ax,)
-        }
-    }
     let axes: BTreeMap<Tag, (f32, f32, f32)> = fvar
         .axes
         .iter()
@@ -561,27 +1460,22 @@ fn normalize_axis_limits(
         BTreeMap::new()
     };
 
-    for (tag, &(_, default, _)) in &axes {
-        if let Some(&UserAxisLimit::Partial(AxisRange { minimum, maximum })) = limits.0.get(tag) {
-            if minimum > default || maximum < default {
-                panic!(
-                    "Unsupported range {}:={}:{}; default position is {}",
-                    tag, minimum, maximum, default
-                )
-            }
-        }
-    }
-
     let mut normalized_limits = BTreeMap::new();
     for (tag, tuple) in axes {
         let avar_mapping = avar_segs.get(&tag).copied();
         let value = limits.0.get(&tag).unwrap();
         match value {
-            UserAxisLimit::Partial(AxisRange { minimum, maximum }) => {
+            UserAxisLimit::Partial(AxisRange {
+                minimum,
+                maximum,
+                default,
+            }) => {
+                let default = default.expect("Axis range default wasn't populated");
                 normalized_limits.insert(
                     tag,
                     NormalizedAxisLimit::Partial(NormalizedAxisRange {
                         minimum: normalize(*minimum, tuple, avar_mapping),
+                        default: normalize(default, tuple, avar_mapping),
                         maximum: normalize(*maximum, tuple, avar_mapping),
                     }),
                 );
@@ -600,48 +1494,791 @@ fn normalize_axis_limits(
     NormalizedAxisLimits(normalized_limits)
 }
 
-pub fn instantiate_variable_font(font: &mut Font, limits: UserAxisLimits) -> bool {
-    sanity_check(font);
-    let limits = populate_axis_defaults(font, limits);
+/// Options controlling how a variable font is instantiated.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct InstancerOptions {
+    /// Rename the font for the style of the instance, using the names of the
+    /// `STAT` table's axis values at its default location, and set the
+    /// bold and italic style bits to match.
+    pub update_name_table: bool,
+}
+
+// The OS/2 width class at each width axis value, with widths in between
+// mapped linearly.
+const WIDTH_CLASSES: [(f32, f32); 9] = [
+    (50.0, 1.0),
+    (62.5, 2.0),
+    (75.0, 3.0),
+    (87.5, 4.0),
+    (100.0, 5.0),
+    (112.5, 6.0),
+    (125.0, 7.0),
+    (150.0, 8.0),
+    (200.0, 9.0),
+];
+
+fn width_class(width: f32) -> uint16 {
+    let width = width.clamp(50.0, 200.0);
+    let class = WIDTH_CLASSES
+        .windows(2)
+        .find(|pair| width <= pair[1].0)
+        .map_or(9.0, |pair| {
+            let ((lower, lower_class), (upper, upper_class)) = (pair[0], pair[1]);
+            lower_class + (width - lower) / (upper - lower) * (upper_class - lower_class)
+        });
+    ot_round(class) as uint16
+}
+
+/// Sets the weight and width classes, italic angle and italic style bits
+/// from the location of the new default on those registered axes which
+/// were limited.
+fn set_default_weight_width_slant(font: &mut Font, location: &Location) {
+    if let Some(mut os2) = font.tables.os2().unwrap() {
+        if let Some(&weight) = location.get(&tag!("wght")) {
+            os2.usWeightClass = ot_round(weight.clamp(1.0, 1000.0)) as uint16;
+        }
+        if let Some(&width) = location.get(&tag!("wdth")) {
+            os2.usWidthClass = width_class(width);
+        }
+        font.tables.insert(os2);
+    }
+    if let Some(&slant) = location.get(&tag!("slnt")) {
+        if let Some(mut post) = font.tables.post().unwrap() {
+            post.italicAngle = slant.clamp(-90.0, 90.0);
+            font.tables.insert(post);
+        }
+    }
+    if let Some(&italic) = location.get(&tag!("ital")) {
+        let bold = font
+            .tables
+            .os2()
+            .unwrap()
+            .is_some_and(|os2| os2.fsSelection & (1 << 5) != 0);
+        names::set_style_bits(font, bold, italic >= 0.5);
+    }
+}
+
+/// Instantiates a variable font with the default options.
+///
+/// See [`instantiate_variable_font_with_options`].
+pub fn instantiate_variable_font(
+    font: &mut Font,
+    limits: UserAxisLimits,
+) -> Result<(), InstancerError> {
+    instantiate_variable_font_with_options(font, limits, &InstancerOptions::default())
+}
+
+/// Instantiates a variable font, pinning axes to a location or limiting them
+/// to a range.
+///
+/// Axes left out of the limits are kept as they are. When all axes are
+/// pinned, the result is a static font.
+///
+/// Returns an error if the font is not variable, if the limits name an axis
+/// which is not in the font, or if one of its tables can't be read.
+pub fn instantiate_variable_font_with_options(
+    font: &mut Font,
+    limits: UserAxisLimits,
+    options: &InstancerOptions,
+) -> Result<(), InstancerError> {
+    sanity_check(font)?;
+    let limits = populate_axis_defaults(font, limits)?;
     log::debug!("Full limits: {:?}", limits);
     let normalized_limits = normalize_axis_limits(font, &limits, true);
     log::debug!("Normalized limits: {:?}", normalized_limits);
-    font.tables.fvar().expect("Can't open fvar");
-    font.tables.glyf().expect("Can't open glyf");
-    font.tables.gvar().expect("Can't open gvar");
-    // update name table (can't)
+    font.tables.fvar()?;
+    font.tables.glyf()?;
+    font.tables.gvar()?;
+    font.tables.GDEF()?;
+    font.tables.GSUB()?;
+    font.tables.GPOS()?;
+    // The CFF2 table is the most likely to fail, so do it before modifying
+    // anything else
+    if font.tables.contains(b"CFF2") {
+        instantiate_cff2(font, &normalized_limits)?;
+    }
+    let default_location = new_default_location(font, &limits);
+    if options.update_name_table {
+        let fvar = font.tables.fvar().unwrap().unwrap();
+        let is_static = fvar
+            .axes
+            .iter()
+            .all(|ax| matches!(limits.0.get(&ax.axisTag), Some(UserAxisLimit::Full(_))));
+        match names::update_name_table(font, &default_location, is_static) {
+            Ok(()) => names::set_ribbi_bits(font),
+            Err(e) => log::warn!("Not updating the name table: {}", e),
+        }
+    }
     if font.tables.contains(b"gvar") {
         // Deserialize what we need
         instantiate_gvar(font, &normalized_limits);
     }
     if font.tables.contains(b"cvar") {
-        // instantiate_cvar(font, normalized_limits);
+        instantiate_cvar(font, &normalized_limits)?;
     }
     if font.tables.contains(b"MVAR") {
-        // instantiate_MVAR(font, normalized_limits);
+        instantiate_MVAR(font, &normalized_limits)?;
     }
     if font.tables.contains(b"HVAR") {
-        // instantiate_HVAR(font, normalized_limits);
+        instantiate_HVAR(font, &normalized_limits)?;
     }
     if font.tables.contains(b"VVAR") {
-        // instantiate_VVAR(font, normalized_limits);
+        instantiate_VVAR(font, &normalized_limits)?;
+    }
+    if font.tables.contains(b"GDEF") {
+        instantiate_otl(font, &normalized_limits)?;
+    }
+    if font.tables.contains(b"GSUB") || font.tables.contains(b"GPOS") {
+        instantiate_feature_variations(font, &normalized_limits)?;
     }
-    // instantiate_otl(font, normalized_limits);
-    // instantiate_feature_variations(font, normalized_limits);
     if font.tables.contains(b"avar") {
-        font.tables.avar().expect("Can't open avar");
+        font.tables.avar()?;
         instantiate_avar(font, &limits);
     }
     if font.tables.contains(b"STAT") {
         instantiate_STAT(font, &limits);
     }
     instantiate_fvar(font, &limits);
-    if !font.tables.contains(b"fvar") && !font.tables.contains(b"glyf") {
+    if !font.tables.contains(b"fvar") && font.tables.contains(b"glyf") {
         let mut glyf = font.tables.glyf().unwrap().unwrap();
         // set overlap flags
         set_mac_overlap_flags(&mut glyf);
+        font.tables.insert(glyf);
+    }
+    let limited_location: Location = default_location
+        .into_iter()
+        .filter(|(tag, _)| limits.0.contains_key(tag))
+        .collect();
+    set_default_weight_width_slant(font, &limited_location);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::common::{Lookup, LookupFlags};
+    use crate::layout::gpos2::PairPos;
+    use crate::layout::gpos4::MarkBasePos;
+    use crate::otvar::{
+        DeltaSetIndexMap, ItemVariationData, NormalizedLocation, RegionAxisCoordinates,
+    };
+    use crate::tables::glyf::{Glyph, Point};
+    use crate::tables::name::{name, NameRecord};
+    use crate::tables::CFF::{Dict, FontDict, PrivateDict};
+    use crate::tables::CFF2::CFF2;
+    use crate::tables::STAT::{AxisValue, AxisValueFlags, STAT};
+    use crate::tables::{cvt, hmtx, os2, post, vhea, vmtx, GDEF, GPOS, GSUB};
+    use crate::testing;
+    use kurbo::PathEl;
+    use otspec::ReaderContext;
+
+    // A font with a weight axis from 100 to 900, default 400, and a glyph
+    // whose single point moves 100 units right at the heaviest weight.
+    fn test_font() -> Font {
+        let mut font = testing::test_font(1);
+        font.tables.insert(fvar::fvar {
+            axes: vec![fvar::VariationAxisRecord {
+                axisTag: tag!("wght"),
+                flags: 0,
+                minValue: 100.0,
+                defaultValue: 400.0,
+                maxValue: 900.0,
+                axisNameID: 256,
+            }],
+            instances: vec![],
+        });
+        font.tables.insert(glyf::glyf {
+            glyphs: vec![Glyph {
+                xMin: 0,
+                xMax: 0,
+                yMin: 0,
+                yMax: 0,
+                contours: vec![vec![Point {
+                    x: 0,
+                    y: 0,
+                    on_curve: true,
+                }]],
+                instructions: vec![],
+                components: vec![],
+                overlap: false,
+            }],
+        });
+        font.tables.insert(gvar::gvar {
+            variations: vec![Some(GlyphVariationData {
+                deltasets: vec![DeltaSet {
+                    peak: vec![1.0],
+                    start: vec![0.0],
+                    end: vec![1.0],
+                    deltas: vec![(100, 0), (0, 0), (0, 0), (0, 0), (0, 0)],
+                }],
+            })],
+        });
+        font
+    }
+
+    fn limit(minimum: f32, maximum: f32) -> UserAxisLimits {
+        UserAxisLimits(BTreeMap::from([(
+            tag!("wght"),
+            UserAxisLimit::Partial(AxisRange::new(minimum, maximum).unwrap()),
+        )]))
+    }
+
+    #[test]
+    fn test_limit_axis_maximum() {
+        let mut font = test_font();
+        instantiate_variable_font(&mut font, limit(100.0, 650.0)).unwrap();
+        let fvar = font.tables.fvar().unwrap().unwrap();
+        assert_eq!(fvar.axes[0].maxValue, 650.0);
+        assert_eq!(fvar.axes[0].defaultValue, 400.0);
+        let glyf = font.tables.glyf().unwrap().unwrap();
+        assert_eq!(glyf.glyphs[0].contours[0][0].x, 0);
+        let gvar = font.tables.gvar().unwrap().unwrap();
+        let deltasets = &gvar.variations[0].as_ref().unwrap().deltasets;
+        assert_eq!(deltasets.len(), 1);
+        assert_eq!(deltasets[0].peak, vec![1.0]);
+        assert_eq!(deltasets[0].deltas[0], (50, 0));
+    }
+
+    #[test]
+    fn test_limit_moves_default() {
+        let mut font = test_font();
+        instantiate_variable_font(&mut font, limit(650.0, 900.0)).unwrap();
+        let fvar = font.tables.fvar().unwrap().unwrap();
+        assert_eq!(fvar.axes[0].minValue, 650.0);
+        assert_eq!(fvar.axes[0].defaultValue, 650.0);
+        assert_eq!(fvar.axes[0].maxValue, 900.0);
+        // The glyph at the old weight 650 is the new default...
+        let glyf = font.tables.glyf().unwrap().unwrap();
+        assert_eq!(glyf.glyphs[0].contours[0][0].x, 50);
+        // ...and the remaining deltas take it to the old weight 900.
+        let gvar = font.tables.gvar().unwrap().unwrap();
+        let deltasets = &gvar.variations[0].as_ref().unwrap().deltasets;
+        assert_eq!(deltasets.len(), 1);
+        assert_eq!(
+            (
+                deltasets[0].start[0],
+                deltasets[0].peak[0],
+                deltasets[0].end[0]
+            ),
+            (0.0, 1.0, 1.0)
+        );
+        assert_eq!(deltasets[0].deltas[0], (50, 0));
+    }
+
+    // Replaces the test font's outlines with a CFF2 table whose glyph, like
+    // the TrueType one, moves 100 units at the maximum weight.
+    fn cff2_test_font() -> Font {
+        let mut font = test_font();
+        font.tables.remove(glyf::TAG);
+        font.tables.remove(gvar::TAG);
+        font.tables.insert(CFF2 {
+            top_dict: Dict::new(),
+            global_subrs: vec![],
+            // 0 0 rmoveto 100 100 1 blend hlineto
+            charstrings: vec![vec![139, 139, 21, 239, 239, 140, 16, 6]],
+            variation_store: Some(ItemVariationStore {
+                format: 1,
+                axisCount: 1,
+                variationRegions: vec![vec![RegionAxisCoordinates {
+                    startCoord: 0.0,
+                    peakCoord: 1.0,
+                    endCoord: 1.0,
+                }]],
+                variationData: vec![ItemVariationData {
+                    region_indexes: vec![0],
+                    delta_values: vec![],
+                }],
+            }),
+            fd_array: vec![FontDict {
+                dict: Dict::new(),
+                private: PrivateDict::default(),
+            }],
+            fd_select: None,
+        });
+        font
+    }
+
+    #[test]
+    fn test_limit_cff2() {
+        let mut font = cff2_test_font();
+        instantiate_variable_font(&mut font, limit(650.0, 900.0)).unwrap();
+        let cff2 = font.tables.CFF2().unwrap().unwrap();
+        let store = cff2.variation_store.as_ref().unwrap();
+        assert_eq!(store.axisCount, 1);
+        assert_eq!(store.variationRegions[0][0].peakCoord, 1.0);
+        // 0 0 rmoveto 150 50 1 blend hlineto
+        assert_eq!(
+            cff2.charstrings[0],
+            vec![139, 139, 21, 247, 42, 189, 140, 16, 6]
+        );
+        let x_at = |location: f32| {
+            let outline = cff2
+                .outline_at(0, &NormalizedLocation(vec![location]))
+                .unwrap();
+            outline.path.elements()[1]
+        };
+        assert_eq!(x_at(0.0), PathEl::LineTo((150.0, 0.0).into()));
+        assert_eq!(x_at(1.0), PathEl::LineTo((200.0, 0.0).into()));
+    }
+
+    #[test]
+    fn test_invalid_limits() {
+        assert!(AxisRange::new(700.0, 300.0).is_err());
+        assert!(AxisRange::with_default(300.0, 800.0, 700.0).is_err());
+        let mut font = test_font();
+        let limits = UserAxisLimits(BTreeMap::from([(tag!("wdth"), UserAxisLimit::Full(100.0))]));
+        assert_eq!(
+            instantiate_variable_font(&mut font, limits),
+            Err(InstancerError(
+                "Can't limit wdth - axis not in font".to_string()
+            ))
+        );
+
+        // A broken layout table is reported before anything is changed
+        let mut font = test_font();
+        font.tables.insert_raw(GSUB::TAG, vec![0, 1]);
+        let limits = UserAxisLimits(BTreeMap::from([(tag!("wght"), UserAxisLimit::Full(900.0))]));
+        assert!(instantiate_variable_font(&mut font, limits).is_err());
+        assert!(font.tables.contains(&gvar::TAG));
+    }
+
+    // Adds metrics to the test font which, like its glyph, vary at the
+    // heaviest weight.
+    fn add_metrics_variations(font: &mut Font) {
+        let store = ItemVariationStore {
+            format: 1,
+            axisCount: 1,
+            variationRegions: vec![vec![RegionAxisCoordinates {
+                startCoord: 0.0,
+                peakCoord: 1.0,
+                endCoord: 1.0,
+            }]],
+            variationData: vec![ItemVariationData {
+                region_indexes: vec![0],
+                delta_values: vec![vec![100], vec![-40]],
+            }],
+        };
+        font.tables.insert(hmtx::hmtx {
+            metrics: vec![hmtx::Metric {
+                advanceWidth: 500,
+                lsb: 0,
+            }],
+        });
+        font.tables.insert(HVAR::HVAR {
+            variation_store: store.clone(),
+            advance_mapping: None,
+            lsb_mapping: None,
+            rsb_mapping: None,
+        });
+        font.tables
+            .insert(post::post::new(2.0, 0.0, -100, 50, false, None));
+        font.tables.insert(MVAR::MVAR {
+            variation_store: Some(store),
+            values: BTreeMap::from([(tag!("undo"), (0, 1))]),
+        });
+        font.tables.insert(cvt::cvt(vec![100, 200]));
+        font.tables.insert(cvar::cvar {
+            deltasets: vec![cvar::DeltaSet {
+                peak: vec![1.0],
+                start: vec![0.0],
+                end: vec![1.0],
+                deltas: vec![10, -20],
+            }],
+        });
+    }
+
+    #[test]
+    fn test_pin_metrics_variations() {
+        let mut font = test_font();
+        add_metrics_variations(&mut font);
+        let limits = UserAxisLimits(BTreeMap::from([(tag!("wght"), UserAxisLimit::Full(900.0))]));
+        instantiate_variable_font(&mut font, limits).unwrap();
+        let hmtx = font.tables.hmtx().unwrap().unwrap();
+        assert_eq!(hmtx.metrics[0].advanceWidth, 600);
+        assert_eq!(hmtx.metrics[0].lsb, 100);
+        let post = font.tables.post().unwrap().unwrap();
+        assert_eq!(post.underlinePosition, -140);
+        let cvt = font.tables.cvt().unwrap().unwrap();
+        assert_eq!(cvt.0, vec![110, 180]);
+        for table in [HVAR::TAG, MVAR::TAG, cvar::TAG] {
+            assert!(!font.tables.contains(&table));
+        }
+    }
+
+    #[test]
+    fn test_cvar_without_cvt() {
+        let mut font = test_font();
+        add_metrics_variations(&mut font);
+        font.tables.remove(cvt::TAG);
+        let limits = UserAxisLimits(BTreeMap::from([(tag!("wght"), UserAxisLimit::Full(900.0))]));
+        assert_eq!(
+            instantiate_variable_font(&mut font, limits),
+            Err(InstancerError("Missing required table cvt ".to_string()))
+        );
+        assert!(font.tables.contains(&HVAR::TAG));
+    }
+
+    #[test]
+    fn test_limit_metrics_variations() {
+        let mut font = test_font();
+        add_metrics_variations(&mut font);
+        instantiate_variable_font(&mut font, limit(100.0, 650.0)).unwrap();
+        let hmtx = font.tables.hmtx().unwrap().unwrap();
+        assert_eq!(hmtx.metrics[0].advanceWidth, 500);
+        let hvar = font.tables.HVAR().unwrap().unwrap();
+        assert_eq!(
+            hvar.variation_store.variationData[0].delta_values,
+            vec![vec![50], vec![-20]]
+        );
+        let cvar = font.tables.cvar().unwrap().unwrap();
+        assert_eq!(cvar.deltasets[0].deltas, vec![5, -10]);
+        let cvt = font.tables.cvt().unwrap().unwrap();
+        assert_eq!(cvt.0, vec![100, 200]);
+    }
+
+    #[test]
+    fn test_pin_vertical_metrics_variations() {
+        let mut font = test_font();
+        font.tables.insert(vhea::vhea {
+            majorVersion: 1,
+            minorVersion: 0,
+            ascent: 500,
+            descent: -500,
+            lineGap: 0,
+            advanceHeightMax: 1000,
+            minTopSideBearing: 50,
+            minBottomSideBearing: 0,
+            yMaxExtent: 950,
+            caretSlopeRise: 0,
+            caretSlopeRun: 1,
+            caretOffset: 0,
+            reserved0: 0,
+            reserved1: 0,
+            reserved2: 0,
+            reserved3: 0,
+            metricDataFormat: 0,
+            numOfLongVerMetrics: 1,
+        });
+        font.tables.insert(vmtx::vmtx {
+            metrics: vec![vmtx::Metric {
+                advanceHeight: 1000,
+                tsb: 50,
+            }],
+        });
+        font.tables.insert(VVAR::VVAR {
+            variation_store: ItemVariationStore {
+                format: 1,
+                axisCount: 1,
+                variationRegions: vec![vec![RegionAxisCoordinates {
+                    startCoord: 0.0,
+                    peakCoord: 1.0,
+                    endCoord: 1.0,
+                }]],
+                variationData: vec![ItemVariationData {
+                    region_indexes: vec![0],
+                    delta_values: vec![vec![100], vec![-40]],
+                }],
+            },
+            advance_mapping: None,
+            tsb_mapping: Some(DeltaSetIndexMap {
+                entries: vec![(0, 1)],
+            }),
+            bsb_mapping: None,
+            vorg_mapping: None,
+        });
+        let limits = UserAxisLimits(BTreeMap::from([(tag!("wght"), UserAxisLimit::Full(900.0))]));
+        instantiate_variable_font(&mut font, limits).unwrap();
+        assert!(!font.tables.contains(&VVAR::TAG));
+
+        let mut binary_font = vec![];
+        font.write(&mut binary_font).unwrap();
+        let font = Font::from_bytes(&binary_font).unwrap();
+        let vmtx = font.tables.vmtx().unwrap().unwrap();
+        assert_eq!(
+            vmtx.metrics,
+            vec![vmtx::Metric {
+                advanceHeight: 1100,
+                tsb: 10,
+            }]
+        );
+    }
+
+    // Adds a kern and a mark anchor which vary through the GDEF item
+    // variation store of the test font's metrics.
+    fn add_otl_variations(font: &mut Font) {
+        font.tables.insert(GDEF::GDEF {
+            glyph_class: BTreeMap::new(),
+            attachment_point_list: BTreeMap::new(),
+            ligature_caret_list: BTreeMap::new(),
+            mark_attachment_class: BTreeMap::new(),
+            mark_glyph_sets: None,
+            item_variation_store: Some(ItemVariationStore {
+                format: 1,
+                axisCount: 1,
+                variationRegions: vec![vec![RegionAxisCoordinates {
+                    startCoord: 0.0,
+                    peakCoord: 1.0,
+                    endCoord: 1.0,
+                }]],
+                variationData: vec![ItemVariationData {
+                    region_indexes: vec![0],
+                    delta_values: vec![vec![100], vec![-40]],
+                }],
+            }),
+        });
+        let mut kern = ValueRecord::new();
+        kern.xAdvance = Some(-50);
+        kern.xAdvDevice = Some(Offset16::to(Device::variation_index(0, 0)));
+        let mut anchor = Anchor::new(300, 600);
+        anchor.yDevice = Some(Offset16::to(Device::variation_index(0, 1)));
+        let gpos = GPOS::GPOS {
+            lookups: vec![
+                Lookup {
+                    flags: LookupFlags::empty(),
+                    mark_filtering_set: None,
+                    rule: Positioning::Pair(vec![PairPos {
+                        mapping: BTreeMap::from([((0, 0), (kern, ValueRecord::new()))]),
+                    }]),
+                },
+                Lookup {
+                    flags: LookupFlags::empty(),
+                    mark_filtering_set: None,
+                    rule: Positioning::MarkToBase(vec![MarkBasePos {
+                        bases: BTreeMap::from([(0, BTreeMap::from([(0, anchor)]))]),
+                        marks: BTreeMap::from([(0, (0, Anchor::new(0, 0)))]),
+                    }]),
+                },
+            ],
+            ..Default::default()
+        };
+        font.tables.insert(gpos);
+    }
+
+    fn kern_and_anchor(gpos: &GPOS::GPOS) -> (ValueRecord, Anchor) {
+        let kern = match &gpos.lookups[0].rule {
+            Positioning::Pair(subtables) => subtables[0].mapping[&(0, 0)].0.clone(),
+            _ => unreachable!(),
+        };
+        let anchor = match &gpos.lookups[1].rule {
+            Positioning::MarkToBase(subtables) => subtables[0].bases[&0][&0].clone(),
+            _ => unreachable!(),
+        };
+        (kern, anchor)
+    }
+
+    #[test]
+    fn test_pin_otl_variations() {
+        let mut font = test_font();
+        add_otl_variations(&mut font);
+        let limits = UserAxisLimits(BTreeMap::from([(tag!("wght"), UserAxisLimit::Full(900.0))]));
+        instantiate_variable_font(&mut font, limits).unwrap();
+        let gdef = font.tables.GDEF().unwrap().unwrap();
+        assert!(gdef.item_variation_store.is_none());
+        let (kern, anchor) = kern_and_anchor(&font.tables.GPOS().unwrap().unwrap());
+        assert_eq!(kern.xAdvance, Some(50));
+        assert!(kern.xAdvDevice.is_none());
+        assert_eq!((anchor.xCoordinate, anchor.yCoordinate), (300, 560));
+        assert!(!anchor.has_devices());
+    }
+
+    #[test]
+    fn test_limit_otl_variations() {
+        let mut font = test_font();
+        add_otl_variations(&mut font);
+        instantiate_variable_font(&mut font, limit(650.0, 900.0)).unwrap();
+        let gdef = font.tables.GDEF().unwrap().unwrap();
+        assert_eq!(
+            gdef.item_variation_store.as_ref().unwrap().variationData[0].delta_values,
+            vec![vec![50], vec![-20]]
+        );
+        // The defaults move to the old weight 650, and the VariationIndex
+        // tables survive a round trip.
+        let gpos = font.tables.GPOS().unwrap().unwrap();
+        let mut data = vec![];
+        GPOS::to_bytes(&gpos, &mut data, 1).unwrap();
+        let gpos = GPOS::from_bytes(&mut ReaderContext::new(data), 1).unwrap();
+        let (kern, anchor) = kern_and_anchor(&gpos);
+        assert_eq!(kern.xAdvance.unwrap_or(0), 0);
+        assert_eq!(
+            kern.xAdvDevice.unwrap().link.unwrap().as_variation_index(),
+            Some((0, 0))
+        );
+        assert_eq!(anchor.yCoordinate, 580);
+        assert_eq!(
+            anchor.yDevice.unwrap().link.unwrap().as_variation_index(),
+            Some((0, 1))
+        );
+    }
+
+    // Adds a substitution of glyph 0 by itself in the `rvrn` feature,
+    // above weight 650.
+    fn add_feature_variations(font: &mut Font) {
+        let mut gsub = GSUB::GSUB::default();
+        gsub.add_conditional_substitution(
+            vec![Condition {
+                axis_index: 0,
+                min: 0.5,
+                max: 1.0,
+            }],
+            tag!("rvrn"),
+            BTreeMap::from([(0, 0)]),
+        );
+        font.tables.insert(gsub);
+    }
+
+    #[test]
+    fn test_pin_feature_variations() {
+        let mut font = test_font();
+        add_feature_variations(&mut font);
+        let limits = UserAxisLimits(BTreeMap::from([(tag!("wght"), UserAxisLimit::Full(900.0))]));
+        instantiate_variable_font(&mut font, limits).unwrap();
+        let gsub = font.tables.GSUB().unwrap().unwrap();
+        assert!(gsub.feature_variations.is_empty());
+        assert_eq!(gsub.features.get(0).unwrap().1, vec![0]);
+
+        let mut font = test_font();
+        add_feature_variations(&mut font);
+        let limits = UserAxisLimits(BTreeMap::from([(tag!("wght"), UserAxisLimit::Full(400.0))]));
+        instantiate_variable_font(&mut font, limits).unwrap();
+        let gsub = font.tables.GSUB().unwrap().unwrap();
+        assert!(gsub.feature_variations.is_empty());
+        assert!(gsub.features.get(0).unwrap().1.is_empty());
+    }
+
+    #[test]
+    fn test_limit_feature_variations() {
+        let mut font = test_font();
+        add_feature_variations(&mut font);
+        instantiate_variable_font(&mut font, limit(400.0, 775.0)).unwrap();
+        let gsub = font.tables.GSUB().unwrap().unwrap();
+        assert_eq!(
+            gsub.feature_variations[0].conditions,
+            vec![Condition {
+                axis_index: 0,
+                min: 2.0 / 3.0,
+                max: 1.0,
+            }]
+        );
+
+        // Above the condition's minimum it holds everywhere
+        let mut font = test_font();
+        add_feature_variations(&mut font);
+        instantiate_variable_font(&mut font, limit(650.0, 900.0)).unwrap();
+        let gsub = font.tables.GSUB().unwrap().unwrap();
+        assert!(gsub.feature_variations.is_empty());
+        assert_eq!(gsub.features.get(0).unwrap().1, vec![0]);
+    }
+
+    // Adds names, a STAT table naming the weights 100, 400 (elidable), 700
+    // and 900, and an OS/2 table to the test font.
+    fn add_style_names(font: &mut Font) {
+        let records = [
+            (1_u16, "Test"),
+            (2, "Regular"),
+            (3, "1.000;NONE;Test-Regular"),
+            (4, "Test Regular"),
+            (6, "Test-Regular"),
+            (256, "Weight"),
+            (257, "Thin"),
+            (258, "Regular"),
+            (259, "Bold"),
+            (260, "Black"),
+        ];
+        font.tables.insert(name {
+            records: records
+                .iter()
+                .map(|&(id, string)| NameRecord::windows_unicode(id, string))
+                .collect(),
+        });
+        font.tables.insert(STAT {
+            elided_fallback_name_id: Some(2),
+            design_axes: vec![crate::tables::STAT::AxisRecord {
+                axisTag: tag!("wght"),
+                axisNameID: 256,
+                axisOrdering: 0,
+            }],
+            axis_values: vec![
+                AxisValue::new_format1(0, AxisValueFlags::empty(), 257, 100.0),
+                AxisValue::new_format1(0, AxisValueFlags::ELIDABLE_AXIS_VALUE_NAME, 258, 400.0),
+                AxisValue::new_format1(0, AxisValueFlags::empty(), 259, 700.0),
+                AxisValue::new_format1(0, AxisValueFlags::empty(), 260, 900.0),
+            ],
+        });
+        let mut os2_data = vec![0; 78];
+        os2_data[58..62].copy_from_slice(b"NONE");
+        let os2: os2::os2 = otspec::de::from_bytes(&os2_data).unwrap();
+        font.tables.insert(os2);
+    }
+
+    fn pin_with_names(font: &mut Font, weight: f32) {
+        let limits = UserAxisLimits(BTreeMap::from([(
+            tag!("wght"),
+            UserAxisLimit::Full(weight),
+        )]));
+        let options = InstancerOptions {
+            update_name_table: true,
+        };
+        instantiate_variable_font_with_options(font, limits, &options).unwrap();
+    }
+
+    #[test]
+    fn test_update_names_non_ribbi() {
+        let mut font = test_font();
+        add_style_names(&mut font);
+        pin_with_names(&mut font, 900.0);
+        let names = font.tables.name().unwrap().unwrap();
+        assert_eq!(names.get(1), Some("Test Black"));
+        assert_eq!(names.get(2), Some("Regular"));
+        assert_eq!(names.get(16), Some("Test"));
+        assert_eq!(names.get(17), Some("Black"));
+        assert_eq!(names.get(4), Some("Test Black"));
+        assert_eq!(names.get(6), Some("Test-Black"));
+        assert_eq!(names.get(3), Some("1.000;NONE;Test-Black"));
+        let os2 = font.tables.os2().unwrap().unwrap();
+        assert_eq!(os2.usWeightClass, 900);
+        assert_eq!(os2.fsSelection, 1 << 6);
+    }
+
+    #[test]
+    fn test_update_names_ribbi() {
+        let mut font = test_font();
+        add_style_names(&mut font);
+        pin_with_names(&mut font, 700.0);
+        let names = font.tables.name().unwrap().unwrap();
+        assert_eq!(names.get(1), Some("Test"));
+        assert_eq!(names.get(2), Some("Bold"));
+        assert!(!names.contains(16) && !names.contains(17));
+        assert_eq!(names.get(4), Some("Test Bold"));
+        assert_eq!(names.get(6), Some("Test-Bold"));
+        let os2 = font.tables.os2().unwrap().unwrap();
+        assert_eq!(os2.usWeightClass, 700);
+        assert_eq!(os2.fsSelection, 1 << 5);
+
+        // Only elidable names apply at the default, so the fallback is used
+        let mut font = test_font();
+        add_style_names(&mut font);
+        pin_with_names(&mut font, 400.0);
+        let names = font.tables.name().unwrap().unwrap();
+        assert_eq!(names.get(2), Some("Regular"));
+        assert_eq!(names.get(4), Some("Test Regular"));
+    }
+
+    #[test]
+    fn test_update_names_without_axis_value() {
+        let mut font = test_font();
+        add_style_names(&mut font);
+        pin_with_names(&mut font, 500.0);
+        let names = font.tables.name().unwrap().unwrap();
+        assert_eq!(names.get(4), Some("Test Regular"));
+        let os2 = font.tables.os2().unwrap().unwrap();
+        assert_eq!(os2.usWeightClass, 500);
+    }
+
+    #[test]
+    fn test_width_class() {
+        assert_eq!(width_class(100.0), 5);
+        assert_eq!(width_class(81.25), 4);
+        assert_eq!(width_class(30.0), 1);
+        assert_eq!(width_class(175.0), 9);
     }
-    // let (full, _) = limits.split_up();
-    // set_default_weight_width_slant(font, full);
-    true
 }
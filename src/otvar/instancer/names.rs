@@ -0,0 +1,380 @@
+use super::Location;
+use crate::font::Font;
+use crate::tables::name::{name, NameRecordID};
+use crate::tables::STAT::{AxisValue, AxisValueFlags, STAT};
+use crate::types::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A (platform ID, encoding ID, language ID) triple
+type Platform = (uint16, uint16, uint16);
+
+const WINDOWS_ENGLISH: Platform = (3, 1, 0x409);
+const RIBBI_STYLES: [&str; 4] = ["Regular", "Italic", "Bold", "Bold Italic"];
+
+fn id(name_id: NameRecordID) -> uint16 {
+    name_id.into()
+}
+
+// Styles which fit in the family/subfamily name model are named with the
+// English Windows strings "Regular", "Italic", "Bold" and "Bold Italic".
+fn is_ribbi(names: &name, name_id: uint16) -> bool {
+    names
+        .get_for_platform(name_id, WINDOWS_ENGLISH)
+        .is_some_and(|s| RIBBI_STYLES.contains(&s))
+}
+
+// The axis tags and values which an axis value names. Ranges (format 2)
+// are matched on their nominal value.
+fn axis_value_coordinates(stat: &STAT, value: &AxisValue) -> Vec<(Tag, f32)> {
+    let tag = |ix: uint16| stat.design_axes.get(ix as usize).map(|a| a.axisTag);
+    if let Some(locations) = &value.locations {
+        locations
+            .iter()
+            .filter_map(|(&ix, &v)| tag(ix).map(|t| (t, v)))
+            .collect()
+    } else {
+        value
+            .axis_index
+            .and_then(tag)
+            .zip(value.nominal_value)
+            .into_iter()
+            .collect()
+    }
+}
+
+// The axis values which apply at a location, failing if any axis of the
+// location is left without one.
+fn axis_values_at<'a>(stat: &'a STAT, location: &Location) -> Result<Vec<&'a AxisValue>, String> {
+    let values: Vec<&AxisValue> = stat
+        .axis_values
+        .iter()
+        .filter(|value| {
+            let coordinates = axis_value_coordinates(stat, value);
+            !coordinates.is_empty()
+                && coordinates.iter().all(|(tag, v)| {
+                    location
+                        .get(tag)
+                        .is_some_and(|l| (l - v).abs() <= f32::EPSILON)
+                })
+        })
+        .collect();
+    let named: BTreeSet<Tag> = values
+        .iter()
+        .flat_map(|v| axis_value_coordinates(stat, v))
+        .map(|(tag, _)| tag)
+        .collect();
+    let missing: Vec<String> = location
+        .keys()
+        .filter(|&tag| !named.contains(tag))
+        .map(|tag| tag.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!(
+            "Cannot find axis values for the axes {}",
+            missing.join(", ")
+        ));
+    }
+    Ok(values)
+}
+
+// Orders axis values the way their names are joined: multi-axis (format 4)
+// values first, with the most axes winning and no axis named twice, then
+// single-axis values for the remaining axes, all sorted by axis index.
+fn sort_axis_values(values: Vec<&AxisValue>) -> Vec<&AxisValue> {
+    let mut format4: Vec<&AxisValue> = values
+        .iter()
+        .copied()
+        .filter(|v| v.locations.is_some())
+        .collect();
+    format4.sort_by_key(|v| std::cmp::Reverse(v.locations.as_ref().map_or(0, |l| l.len())));
+
+    let mut seen: BTreeSet<uint16> = BTreeSet::new();
+    let mut results: Vec<(uint16, &AxisValue)> = vec![];
+    for value in format4 {
+        let axes: BTreeSet<uint16> = value
+            .locations
+            .iter()
+            .flat_map(|l| l.keys().copied())
+            .collect();
+        if let Some(&first) = axes.iter().next() {
+            if seen.is_disjoint(&axes) {
+                results.push((first, value));
+                seen.extend(axes);
+            }
+        }
+    }
+    for value in values.iter().filter(|v| v.locations.is_none()) {
+        if let Some(ix) = value.axis_index {
+            if seen.insert(ix) {
+                results.push((ix, value));
+            }
+        }
+    }
+    results.sort_by_key(|(ix, _)| *ix);
+    results.into_iter().map(|(_, v)| v).collect()
+}
+
+// The PostScript name: the variations PostScript name prefix (or else the
+// family name) and the style, keeping only the characters allowed.
+fn postscript_name(names: &name, family: &str, style: &str, platform: Platform) -> String {
+    let prefix = names
+        .get_for_platform(id(NameRecordID::VariationsPostScriptNamePrefix), platform)
+        .unwrap_or(family);
+    let ps_name: String = format!("{}-{}", prefix, style)
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect();
+    if ps_name.len() > 127 {
+        format!("{}...", &ps_name[..124])
+    } else {
+        ps_name
+    }
+}
+
+// The unique ID, updated in place if it contains the old full or PostScript
+// name, or else made up of the version, vendor and PostScript name.
+fn unique_id(
+    names: &name,
+    new_names: &BTreeMap<uint16, String>,
+    platform: Platform,
+    (font_revision, vendor): (f32, &str),
+) -> Option<String> {
+    let current = names.get_for_platform(id(NameRecordID::UniqueID), platform)?;
+    for name_id in [
+        id(NameRecordID::FullFontName),
+        id(NameRecordID::PostscriptName),
+    ] {
+        if let Some(old) = names.get_for_platform(name_id, platform) {
+            if !old.is_empty() && current.contains(old) {
+                return Some(current.replace(old, &new_names[&name_id]));
+            }
+        }
+    }
+    let version = match names.get_for_platform(id(NameRecordID::Version), platform) {
+        Some(version) => version
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim_start_matches("Version ")
+            .trim()
+            .to_string(),
+        None => format!("{:.3}", font_revision),
+    };
+    Some(format!(
+        "{};{};{}",
+        version,
+        vendor.trim(),
+        new_names[&id(NameRecordID::PostscriptName)]
+    ))
+}
+
+fn update_style_records(
+    names: &mut name,
+    platform: Platform,
+    family_suffix: &str,
+    subfamily: &str,
+    typo_subfamily: &str,
+    revision_and_vendor: (f32, &str),
+) {
+    let family_id = id(NameRecordID::FontFamilyName);
+    let subfamily_id = id(NameRecordID::FontSubfamilyName);
+    let typo_family_id = id(NameRecordID::PreferredFamilyName);
+    let typo_subfamily_id = id(NameRecordID::PreferredSubfamilyName);
+    let full_id = id(NameRecordID::FullFontName);
+    let ps_id = id(NameRecordID::PostscriptName);
+
+    let current_family = match names
+        .get_for_platform(typo_family_id, platform)
+        .or_else(|| names.get_for_platform(family_id, platform))
+    {
+        Some(family) => family.to_string(),
+        None => return,
+    };
+
+    let mut new_names: BTreeMap<uint16, String> = BTreeMap::new();
+    let subfamily = if subfamily.is_empty() {
+        "Regular"
+    } else {
+        subfamily
+    };
+    new_names.insert(family_id, current_family.clone());
+    new_names.insert(subfamily_id, subfamily.to_string());
+    if typo_subfamily.is_empty() {
+        names.records.retain(|r| {
+            (r.nameID != typo_family_id && r.nameID != typo_subfamily_id)
+                || (r.platformID, r.encodingID, r.languageID) != platform
+        });
+    } else {
+        new_names.insert(
+            family_id,
+            format!("{} {}", current_family, family_suffix)
+                .trim()
+                .to_string(),
+        );
+        new_names.insert(typo_family_id, current_family);
+        new_names.insert(typo_subfamily_id, typo_subfamily.to_string());
+    }
+
+    let family = new_names
+        .get(&typo_family_id)
+        .unwrap_or(&new_names[&family_id])
+        .clone();
+    let style = new_names
+        .get(&typo_subfamily_id)
+        .unwrap_or(&new_names[&subfamily_id])
+        .clone();
+    new_names.insert(full_id, format!("{} {}", family, style));
+    new_names.insert(ps_id, postscript_name(names, &family, &style, platform));
+    if let Some(unique) = unique_id(names, &new_names, platform, revision_and_vendor) {
+        new_names.insert(id(NameRecordID::UniqueID), unique);
+    }
+
+    for (name_id, string) in new_names {
+        names.set_for_platform(name_id, platform, &string);
+    }
+}
+
+/// Renames a font for the style at a location in user coordinates, joining
+/// the names of the `STAT` table's axis values at that location.
+///
+/// Non-elidable values whose names are "Regular", "Italic", "Bold" or "Bold
+/// Italic" make up the subfamily name; the others are added to the family
+/// name, with the full style recorded in the typographic names. The full,
+/// PostScript and unique names follow. If `is_static` is set, the variations
+/// PostScript name prefix is removed.
+pub(crate) fn update_name_table(
+    font: &mut Font,
+    location: &Location,
+    is_static: bool,
+) -> Result<(), String> {
+    let stat = font
+        .tables
+        .STAT()
+        .map_err(|e| e.to_string())?
+        .ok_or("The font has no STAT table")?;
+    if stat.axis_values.is_empty() {
+        return Err("The STAT table has no axis values".to_string());
+    }
+    let mut names = font
+        .tables
+        .name()
+        .map_err(|e| e.to_string())?
+        .ok_or("The font has no name table")?;
+    let font_revision = font
+        .tables
+        .head()
+        .map_err(|e| e.to_string())?
+        .map_or(1.0, |head| head.fontRevision);
+    let vendor = font
+        .tables
+        .os2()
+        .map_err(|e| e.to_string())?
+        .map(|os2| os2.achVendID.to_string())
+        .unwrap_or_default();
+
+    let values = axis_values_at(&stat, location)?;
+    let values = sort_axis_values(
+        values
+            .into_iter()
+            .filter(|v| !v.flags.contains(AxisValueFlags::ELIDABLE_AXIS_VALUE_NAME))
+            .collect(),
+    );
+    let name_ids: Vec<uint16> = values.iter().map(|v| v.name_id).collect();
+    let (ribbi, non_ribbi): (Vec<uint16>, Vec<uint16>) =
+        name_ids.iter().partition(|&&id| is_ribbi(&names, id));
+    // STAT version 1.0 has no elided fallback name, so the subfamily name
+    // stands in.
+    let elided = stat
+        .elided_fallback_name_id
+        .unwrap_or_else(|| id(NameRecordID::FontSubfamilyName));
+    let elided_is_ribbi = is_ribbi(&names, elided);
+
+    let platforms: BTreeSet<Platform> = names
+        .records
+        .iter()
+        .map(|r| (r.platformID, r.encodingID, r.languageID))
+        .collect();
+    for platform in platforms {
+        let join = |ids: &[uint16]| {
+            ids.iter()
+                .filter_map(|&id| names.get_for_platform(id, platform))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        // Platforms without the names of all the values are left alone
+        if name_ids
+            .iter()
+            .chain(std::iter::once(&elided))
+            .any(|&id| names.get_for_platform(id, platform).is_none())
+        {
+            continue;
+        }
+        let mut subfamily = join(&ribbi);
+        let mut typo_subfamily = if non_ribbi.is_empty() {
+            String::new()
+        } else {
+            join(&name_ids)
+        };
+        if subfamily.is_empty() && typo_subfamily.is_empty() {
+            let elided_name = join(&[elided]);
+            if elided_is_ribbi {
+                subfamily = elided_name;
+            } else {
+                typo_subfamily = elided_name;
+            }
+        }
+        let family_suffix = join(&non_ribbi);
+        update_style_records(
+            &mut names,
+            platform,
+            &family_suffix,
+            &subfamily,
+            &typo_subfamily,
+            (font_revision, &vendor),
+        );
+    }
+    if is_static {
+        names.remove(id(NameRecordID::VariationsPostScriptNamePrefix));
+    }
+    font.tables.insert(names);
+    Ok(())
+}
+
+/// Sets the bold and italic bits of `head.macStyle` and `OS/2.fsSelection`,
+/// and the regular bit of `OS/2.fsSelection`.
+pub(crate) fn set_style_bits(font: &mut Font, bold: bool, italic: bool) {
+    if let Some(mut head) = font.tables.head().unwrap() {
+        head.macStyle = (head.macStyle & !0b11) | (bold as uint16) | ((italic as uint16) << 1);
+        font.tables.insert(head);
+    }
+    if let Some(mut os2) = font.tables.os2().unwrap() {
+        os2.fsSelection &= !((1 << 0) | (1 << 5) | (1 << 6));
+        if italic {
+            os2.fsSelection |= 1 << 0;
+        }
+        if bold {
+            os2.fsSelection |= 1 << 5;
+        }
+        if !bold && !italic {
+            os2.fsSelection |= 1 << 6;
+        }
+        font.tables.insert(os2);
+    }
+}
+
+/// Sets the style bits to match the English Windows subfamily name, if it
+/// is one of "Regular", "Italic", "Bold" or "Bold Italic".
+pub(crate) fn set_ribbi_bits(font: &mut Font) {
+    let subfamily = font.tables.name().unwrap().and_then(|names| {
+        names
+            .get_for_platform(id(NameRecordID::FontSubfamilyName), WINDOWS_ENGLISH)
+            .map(|s| s.to_lowercase())
+    });
+    match subfamily.as_deref() {
+        Some("regular") => set_style_bits(font, false, false),
+        Some("italic") => set_style_bits(font, false, true),
+        Some("bold") => set_style_bits(font, true, false),
+        Some("bold italic") => set_style_bits(font, true, true),
+        _ => {}
+    }
+}
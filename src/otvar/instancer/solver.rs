@@ -0,0 +1,229 @@
+use super::super::support_scalar;
+use crate::tag;
+use std::collections::BTreeMap;
+
+/// A per-axis region of the design space: (lower, peak, upper)
+pub(crate) type Tent = (f32, f32, f32);
+/// New limits for an axis, normalized against the old limits: (minimum,
+/// default, maximum)
+pub(crate) type AxisTriple = (f32, f32, f32);
+
+/// The smallest step representable in F2DOT14
+const EPSILON: f32 = 1.0 / 16384.0;
+
+fn scalar_at(value: f32, tent: Tent) -> f32 {
+    let loc = BTreeMap::from([(tag!("axis"), value)]);
+    let support = BTreeMap::from([(tag!("axis"), tent)]);
+    support_scalar(&loc, &support)
+}
+
+fn reverse_negate((a, b, c): Tent) -> Tent {
+    (-c, -b, -a)
+}
+
+/// Normalizes a value against new axis limits, extrapolating beyond them.
+pub(crate) fn renormalize_value(value: f32, (minimum, default, maximum): AxisTriple) -> f32 {
+    if value == default {
+        0.0
+    } else if value < default {
+        (value - default) / (default - minimum)
+    } else {
+        (value - default) / (maximum - default)
+    }
+}
+
+// Solves the problem in the old coordinate system. A tent of `None` is a
+// "gain", applying everywhere.
+fn solve(tent: Tent, limit: AxisTriple) -> Vec<(f32, Option<Tent>)> {
+    let (axis_min, axis_def, axis_max) = limit;
+    let (lower, peak, mut upper) = tent;
+
+    // Mirror the problem so that the default is at or below the peak
+    if axis_def > peak {
+        return solve(reverse_negate(tent), reverse_negate(limit))
+            .into_iter()
+            .map(|(scalar, tent)| (scalar, tent.map(reverse_negate)))
+            .collect();
+    }
+
+    // The tent lies entirely beyond the new maximum: drop it.
+    if axis_max <= lower && axis_max < peak {
+        return vec![];
+    }
+
+    // The peak lies beyond the new maximum: move it to the maximum, scaling
+    // the deltas by the tent's value there.
+    if axis_max < peak {
+        let mult = scalar_at(axis_max, tent);
+        return solve((lower, axis_max, axis_max), limit)
+            .into_iter()
+            .map(|(scalar, tent)| (scalar * mult, tent))
+            .collect();
+    }
+
+    // From here on, lower <= axis_def <= peak <= axis_max. What the tent
+    // contributes at the new default becomes a gain, which the new tents
+    // must cancel out everywhere else.
+    let gain = scalar_at(axis_def, tent);
+    let mut out = vec![(gain, None)];
+
+    // The positive side. `out_gain` is the tent's value at the new maximum.
+    let out_gain = scalar_at(axis_max, tent);
+    if gain >= out_gain {
+        // The downslope crosses the gain level before the new maximum, so
+        // the part after the crossing must go down to the out_gain level.
+        let crossing = peak + (1.0 - gain) * (upper - peak);
+        out.push((1.0 - gain, Some((lower.max(axis_def), peak, crossing))));
+        if upper >= axis_max {
+            out.push((out_gain - gain, Some((crossing, axis_max, axis_max))));
+        } else {
+            // The tent ends before the new maximum; keep it down from there.
+            // A tent's peak can't fall on the default, so nudge it.
+            if upper == axis_def {
+                upper += EPSILON;
+            }
+            out.push((-gain, Some((crossing, upper, axis_max))));
+            out.push((-gain, Some((upper, axis_max, axis_max))));
+        }
+    } else {
+        // A triangle with one side cut off by the new maximum takes two
+        // tents to represent.
+        out.push((1.0 - gain, Some((lower.max(axis_def), peak, axis_max))));
+        if peak < axis_max {
+            out.push((out_gain - gain, Some((peak, axis_max, axis_max))));
+        }
+    }
+
+    // The negative side
+    if lower <= axis_min {
+        // The tent extends beyond the new minimum: chop it there.
+        let scalar = scalar_at(axis_min, tent);
+        out.push((scalar - gain, Some((axis_min, axis_min, axis_def))));
+    } else {
+        // The tent ends between the new minimum and default; keep it down
+        // all the way to the minimum.
+        let mut lower = lower;
+        if lower == axis_def {
+            lower -= EPSILON;
+        }
+        out.push((-gain, Some((axis_min, lower, axis_def))));
+        out.push((-gain, Some((axis_min, axis_min, lower))));
+    }
+    out
+}
+
+/// Expresses a tent in the coordinates of new axis limits.
+///
+/// Both the tent and the limits are normalized against the old limits. The
+/// result is a list of tents, each with a factor by which to scale the
+/// deltas of the original tent. A tent of `None` applies at every location,
+/// including the new default.
+pub(crate) fn rebase_tent(tent: Tent, limit: AxisTriple) -> Vec<(f32, Option<Tent>)> {
+    let (axis_min, axis_def, axis_max) = limit;
+    assert!(-1.0 <= axis_min && axis_min <= axis_def && axis_def <= axis_max && axis_max <= 1.0);
+    let (lower, peak, upper) = tent;
+    assert!(-2.0 <= lower && lower <= peak && peak <= upper && upper <= 2.0);
+    assert!(peak != 0.0);
+
+    let n = |v| renormalize_value(v, limit);
+    solve(tent, limit)
+        .into_iter()
+        .filter(|(scalar, _)| *scalar != 0.0)
+        .map(|(scalar, tent)| (scalar, tent.map(|(l, p, u)| (n(l), n(p), n(u)))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_solution(tent: Tent, limit: AxisTriple, expected: &[(f32, Option<Tent>)]) {
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+        let solution = rebase_tent(tent, limit);
+        assert_eq!(solution.len(), expected.len(), "{:?}", solution);
+        for ((scalar, tent), (exp_scalar, exp_tent)) in solution.iter().zip(expected.iter()) {
+            assert!(close(*scalar, *exp_scalar), "{:?}", solution);
+            match (tent, exp_tent) {
+                (None, None) => {}
+                (Some(t), Some(e)) => assert!(
+                    close(t.0, e.0) && close(t.1, e.1) && close(t.2, e.2),
+                    "{:?}",
+                    solution
+                ),
+                _ => panic!("{:?}", solution),
+            }
+        }
+    }
+
+    #[test]
+    fn test_outside_limits() {
+        assert_solution((0.5, 1.0, 1.0), (-1.0, 0.0, 0.5), &[]);
+    }
+
+    #[test]
+    fn test_peak_outside_limits() {
+        assert_solution(
+            (0.0, 1.0, 1.0),
+            (-1.0, 0.0, 0.5),
+            &[(0.5, Some((0.0, 1.0, 1.0)))],
+        );
+        assert_solution(
+            (-1.0, -1.0, 0.0),
+            (-0.5, 0.0, 1.0),
+            &[(0.5, Some((-1.0, -1.0, 0.0)))],
+        );
+    }
+
+    #[test]
+    fn test_unchanged() {
+        assert_solution(
+            (0.0, 1.0, 1.0),
+            (-1.0, 0.0, 1.0),
+            &[(1.0, Some((0.0, 1.0, 1.0)))],
+        );
+    }
+
+    #[test]
+    fn test_chop_at_maximum() {
+        assert_solution(
+            (0.0, 0.5, 1.0),
+            (-1.0, 0.0, 0.75),
+            &[
+                (1.0, Some((0.0, 2.0 / 3.0, 1.0))),
+                (0.5, Some((2.0 / 3.0, 1.0, 1.0))),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_move_default() {
+        assert_solution(
+            (0.0, 1.0, 1.0),
+            (0.5, 0.5, 1.0),
+            &[(0.5, None), (0.5, Some((0.0, 1.0, 1.0)))],
+        );
+        assert_solution(
+            (0.0, 0.5, 1.0),
+            (0.5, 0.75, 1.0),
+            &[
+                (0.5, None),
+                (0.5, Some((-1.0, -1.0, 0.0))),
+                (-0.5, Some((0.0, 1.0, 1.0))),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_crossing() {
+        assert_solution(
+            (0.0, 0.5, 1.0),
+            (0.0, 0.25, 1.0),
+            &[
+                (0.5, None),
+                (0.5, Some((0.0, 1.0 / 3.0, 2.0 / 3.0))),
+                (-0.5, Some((2.0 / 3.0, 1.0, 1.0))),
+                (-0.5, Some((-1.0, -1.0, 0.0))),
+            ],
+        );
+    }
+}
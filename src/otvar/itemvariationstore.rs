@@ -3,6 +3,9 @@ use otspec::{
     DeserializationError, Deserialize, Deserializer, ReaderContext, Serialize, Serializer,
 };
 use otspec_macros::tables;
+use std::collections::BTreeMap;
+
+use super::NormalizedLocation;
 
 tables!(
     RegionAxisCoordinates {
@@ -16,6 +19,7 @@ tables!(
         Counted(uint16) regionIndexes
     }
     ItemVariationStoreInternal {
+        [offset_base]
         uint16 format
         Offset32(VariationRegionList) variationRegionList
         CountedOffset32(ItemVariationData) itemVariationData
@@ -169,3 +173,187 @@ impl Serialize for ItemVariationStore {
         .to_bytes(data)
     }
 }
+
+/// Returns the contribution of one axis of a region at a normalized coordinate
+fn axis_scalar(v: f32, region: &RegionAxisCoordinates) -> f32 {
+    let (lower, peak, upper) = (region.startCoord, region.peakCoord, region.endCoord);
+    if peak == 0.0 || lower > peak || peak > upper || (lower < 0.0 && upper > 0.0) {
+        return 1.0;
+    }
+    if (v - peak).abs() < f32::EPSILON {
+        1.0
+    } else if v <= lower || upper <= v {
+        0.0
+    } else if v < peak {
+        (v - lower) / (peak - lower)
+    } else {
+        (v - upper) / (peak - upper)
+    }
+}
+
+/// Describes how the deltas of an [`ItemVariationData`] are redistributed
+/// when some axes of a variation store are pinned to a location or limited
+/// to a range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PinnedRegions {
+    /// For each region of the original variation data, the columns of the
+    /// new variation data which its deltas now contribute to, each with the
+    /// factor by which they are scaled, and the factor by which they
+    /// contribute to the default value.
+    pub columns: Vec<(Vec<(usize, f32)>, f32)>,
+    /// The number of regions in the new variation data.
+    pub region_count: usize,
+}
+
+impl PinnedRegions {
+    /// Redistributes a row of deltas, returning the change to the default
+    /// value and the new row of deltas.
+    pub fn apply(&self, deltas: &[f32]) -> (f32, Vec<f32>) {
+        let mut default = 0.0;
+        let mut new_deltas = vec![0.0; self.region_count];
+        for (&delta, (targets, default_scalar)) in deltas.iter().zip(self.columns.iter()) {
+            default += delta * default_scalar;
+            for &(column, scalar) in targets {
+                new_deltas[column] += delta * scalar;
+            }
+        }
+        (default, new_deltas)
+    }
+}
+
+impl ItemVariationStore {
+    /// Returns the contribution of a region at the given location.
+    pub fn region_scalar(&self, region_index: usize, location: &NormalizedLocation) -> f32 {
+        self.variationRegions
+            .get(region_index)
+            .map_or(0.0, |region| {
+                region
+                    .iter()
+                    .enumerate()
+                    .map(|(axis, coords)| {
+                        axis_scalar(*location.0.get(axis).unwrap_or(&0.0), coords)
+                    })
+                    .product()
+            })
+    }
+
+    /// Returns the contributions of the regions referenced by one item
+    /// variation data subtable at the given location.
+    pub fn region_scalars(&self, outer: usize, location: &NormalizedLocation) -> Vec<f32> {
+        self.variationData.get(outer).map_or(vec![], |data| {
+            data.region_indexes
+                .iter()
+                .map(|&ix| self.region_scalar(ix as usize, location))
+                .collect()
+        })
+    }
+
+    /// Returns the interpolated delta of an item at the given location.
+    pub fn get_delta(&self, outer: usize, inner: usize, location: &NormalizedLocation) -> f32 {
+        let scalars = self.region_scalars(outer, location);
+        self.variationData
+            .get(outer)
+            .and_then(|data| data.delta_values.get(inner))
+            .map_or(0.0, |row| {
+                row.iter()
+                    .zip(scalars.iter())
+                    .map(|(&delta, scalar)| delta as f32 * scalar)
+                    .sum()
+            })
+    }
+
+    /// Pins some axes of the variation store to a location.
+    ///
+    /// `location` maps axis indices to normalized coordinates. Pinned axes
+    /// are removed from the store, and regions which no longer vary are
+    /// merged or dropped. The delta values of each item variation data
+    /// subtable are redistributed (any contribution to the default value is
+    /// discarded); the returned [`PinnedRegions`] describe how, so that
+    /// callers can apply the same transformation to deltas stored elsewhere
+    /// and adjust the default values.
+    pub fn pin_axes(&self, location: &BTreeMap<usize, f32>) -> (Self, Vec<PinnedRegions>) {
+        // Work out the reduced form of each region, and its scalar on the
+        // pinned axes.
+        let mut new_regions: Vec<Vec<RegionAxisCoordinates>> = vec![];
+        let region_map: Vec<(Option<usize>, f32)> = self
+            .variationRegions
+            .iter()
+            .map(|region| {
+                let mut scalar = 1.0;
+                let mut reduced = vec![];
+                for (axis, coords) in region.iter().enumerate() {
+                    match location.get(&axis) {
+                        Some(&v) => scalar *= axis_scalar(v, coords),
+                        None => reduced.push(coords.clone()),
+                    }
+                }
+                if scalar == 0.0 || reduced.iter().all(|c| c.peakCoord == 0.0) {
+                    return (None, scalar);
+                }
+                let index = new_regions
+                    .iter()
+                    .position(|r| *r == reduced)
+                    .unwrap_or_else(|| {
+                        new_regions.push(reduced);
+                        new_regions.len() - 1
+                    });
+                (Some(index), scalar)
+            })
+            .collect();
+
+        let mut mappings = vec![];
+        let mut variation_data = vec![];
+        for data in &self.variationData {
+            let mut region_indexes: Vec<uint16> = vec![];
+            let columns: Vec<(Vec<(usize, f32)>, f32)> = data
+                .region_indexes
+                .iter()
+                .map(|&ix| match region_map.get(ix as usize) {
+                    Some(&(Some(new_region), scalar)) => {
+                        let column = region_indexes
+                            .iter()
+                            .position(|&r| r as usize == new_region)
+                            .unwrap_or_else(|| {
+                                region_indexes.push(new_region as uint16);
+                                region_indexes.len() - 1
+                            });
+                        (vec![(column, scalar)], 0.0)
+                    }
+                    Some(&(None, scalar)) => (vec![], scalar),
+                    None => (vec![], 0.0),
+                })
+                .collect();
+            let mapping = PinnedRegions {
+                columns,
+                region_count: region_indexes.len(),
+            };
+            let delta_values = data
+                .delta_values
+                .iter()
+                .map(|row| {
+                    let row: Vec<f32> = row.iter().map(|&d| d as f32).collect();
+                    mapping
+                        .apply(&row)
+                        .1
+                        .iter()
+                        .map(|&d| ot_round(d) as int16)
+                        .collect()
+                })
+                .collect();
+            variation_data.push(ItemVariationData {
+                region_indexes,
+                delta_values,
+            });
+            mappings.push(mapping);
+        }
+        (
+            ItemVariationStore {
+                format: self.format,
+                axisCount: self.axisCount - location.len() as uint16,
+                variationRegions: new_regions,
+                variationData: variation_data,
+            },
+            mappings,
+        )
+    }
+}
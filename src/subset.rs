@@ -0,0 +1,786 @@
+use crate::font::Font;
+use crate::tables::glyf::Glyph;
+use crate::tables::hmtx::Metric;
+use crate::tables::CFF::Charset;
+use crate::tables::MATH::{MathGlyphConstruction, MATH};
+use crate::tag;
+use otspec::types::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+/// Subsetting of OpenType Layout tables
+mod layout;
+
+/// Tables which refer to glyph IDs but which the subsetter cannot rewrite;
+/// these are dropped from the subset font.
+const DROPPED_TABLES: [Tag; 19] = [
+    tag!("BASE"),
+    tag!("CBDT"),
+    tag!("CBLC"),
+    tag!("COLR"),
+    tag!("DSIG"),
+    tag!("EBDT"),
+    tag!("EBLC"),
+    tag!("EBSC"),
+    tag!("HVAR"),
+    tag!("JSTF"),
+    tag!("LTSH"),
+    tag!("SVG "),
+    tag!("VORG"),
+    tag!("VVAR"),
+    tag!("hdmx"),
+    tag!("kern"),
+    tag!("sbix"),
+    tag!("vhea"),
+    tag!("vmtx"),
+];
+
+/// TrueType hinting tables, dropped if hinting is not retained.
+const HINTING_TABLES: [Tag; 4] = [tag!("cvt "), tag!("fpgm"), tag!("prep"), tag!("VDMX")];
+
+/// Options controlling how a font is subset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubsetOptions {
+    /// Keep the glyph IDs of retained glyphs unchanged, emptying the glyphs
+    /// which are not retained instead of removing them.
+    pub retain_gids: bool,
+    /// The layout features to keep, or `None` to keep all features.
+    pub layout_features: Option<BTreeSet<Tag>>,
+    /// The name IDs to keep, or `None` to keep all names. Names referred to
+    /// by the `fvar` and `STAT` tables and by feature parameters are always
+    /// kept.
+    pub name_ids: Option<BTreeSet<uint16>>,
+    /// The name language IDs to keep, or `None` to keep all languages.
+    pub name_languages: Option<BTreeSet<uint16>>,
+    /// Keep name records for platforms other than Windows.
+    pub name_legacy: bool,
+    /// Keep glyph names in the `post` table.
+    pub glyph_names: bool,
+    /// Keep TrueType hinting instructions.
+    pub hinting: bool,
+}
+
+impl Default for SubsetOptions {
+    fn default() -> Self {
+        SubsetOptions {
+            retain_gids: false,
+            layout_features: None,
+            name_ids: Some((0..=6).collect()),
+            name_languages: Some(std::iter::once(0x409).collect()),
+            name_legacy: false,
+            glyph_names: true,
+            hinting: true,
+        }
+    }
+}
+
+/// Adds the glyphs used in MATH variants and assemblies to a glyph set.
+fn math_closure(math: &MATH, glyphs: &mut BTreeSet<GlyphID>) -> bool {
+    let mut new = BTreeSet::new();
+    for (gid, construction) in math
+        .vertical_extensions
+        .iter()
+        .chain(math.horizontal_extensions.iter())
+    {
+        if !glyphs.contains(gid) {
+            continue;
+        }
+        new.extend(
+            construction
+                .mathGlyphVariantRecord
+                .iter()
+                .map(|v| v.variantGlyph),
+        );
+        if let Some(assembly) = &construction.glyphAssembly.link {
+            new.extend(assembly.partRecords.iter().map(|p| p.glyphID));
+        }
+    }
+    let before = glyphs.len();
+    glyphs.extend(new);
+    glyphs.len() != before
+}
+
+/// Computes the set of glyphs needed to render the given codepoints and
+/// glyphs.
+///
+/// The closure includes the `.notdef` glyph, the glyphs mapped from the
+/// codepoints in the `cmap` table, and any glyphs reachable from those
+/// through the retained GSUB features, MATH variants and composite glyph
+/// components.
+pub fn closure(
+    font: &Font,
+    unicodes: &BTreeSet<u32>,
+    glyphs: &BTreeSet<GlyphID>,
+    options: &SubsetOptions,
+) -> Result<BTreeSet<GlyphID>, Box<dyn Error>> {
+    let num_glyphs = font
+        .tables
+        .maxp()?
+        .ok_or_else(|| crate::flavor::error("Font has no maxp table"))?
+        .num_glyphs();
+    let mut closure: BTreeSet<GlyphID> =
+        glyphs.iter().copied().filter(|&g| g < num_glyphs).collect();
+    closure.insert(0);
+    if let Some(cmap) = font.tables.cmap()? {
+        for subtable in cmap.subtables.iter().filter(|st| st.is_unicode()) {
+            closure.extend(
+                subtable
+                    .mapping
+                    .iter()
+                    .filter(|(u, _)| unicodes.contains(u))
+                    .map(|(_, g)| *g),
+            );
+            if let Some(uvs) = &subtable.uvs_mapping {
+                closure.extend(
+                    uvs.iter()
+                        .filter(|((u, _), _)| unicodes.contains(u))
+                        .map(|(_, g)| *g),
+                );
+            }
+        }
+    }
+
+    let gsub = font.tables.GSUB()?;
+    let math = font.tables.MATH()?;
+    let features: Option<Vec<Tag>> = options
+        .layout_features
+        .as_ref()
+        .map(|tags| tags.iter().copied().collect());
+    loop {
+        let mut changed = false;
+        if let Some(gsub) = &gsub {
+            let closed = gsub.closure_glyphs(&closure, features.as_deref());
+            changed |= closed.len() != closure.len();
+            closure = closed;
+        }
+        if let Some(math) = &math {
+            changed |= math_closure(math, &mut closure);
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    if let Some(glyf) = font.tables.glyf()? {
+        let mut todo: Vec<GlyphID> = closure.iter().copied().collect();
+        while let Some(gid) = todo.pop() {
+            if let Some(glyph) = glyf.glyphs.get(gid as usize) {
+                for component in &glyph.components {
+                    if closure.insert(component.glyph_index) {
+                        todo.push(component.glyph_index);
+                    }
+                }
+            }
+        }
+    }
+    closure.retain(|&g| g < num_glyphs);
+    Ok(closure)
+}
+
+/// Puts per-glyph data into the new glyph order, using `empty` for glyphs
+/// which are not retained.
+fn reorder<T: Clone>(items: &[T], order: &[Option<GlyphID>], empty: impl Fn() -> T) -> Vec<T> {
+    order
+        .iter()
+        .map(|old| {
+            old.and_then(|g| items.get(g as usize))
+                .cloned()
+                .unwrap_or_else(&empty)
+        })
+        .collect()
+}
+
+fn remap_construction(
+    construction: &MathGlyphConstruction,
+    glyph_map: &BTreeMap<GlyphID, GlyphID>,
+) -> MathGlyphConstruction {
+    let mut construction = construction.clone();
+    construction
+        .mathGlyphVariantRecord
+        .retain(|v| glyph_map.contains_key(&v.variantGlyph));
+    for variant in construction.mathGlyphVariantRecord.iter_mut() {
+        variant.variantGlyph = glyph_map[&variant.variantGlyph];
+    }
+    if let Some(assembly) = construction.glyphAssembly.link.as_mut() {
+        for part in assembly.partRecords.iter_mut() {
+            part.glyphID = glyph_map.get(&part.glyphID).copied().unwrap_or(0);
+        }
+    }
+    construction
+}
+
+fn subset_math(math: &MATH, glyph_map: &BTreeMap<GlyphID, GlyphID>) -> MATH {
+    fn keys<T: Clone>(
+        map: &BTreeMap<GlyphID, T>,
+        glyph_map: &BTreeMap<GlyphID, GlyphID>,
+    ) -> BTreeMap<GlyphID, T> {
+        map.iter()
+            .filter_map(|(g, v)| glyph_map.get(g).map(|&n| (n, v.clone())))
+            .collect()
+    }
+    let extensions = |map: &BTreeMap<GlyphID, MathGlyphConstruction>| {
+        map.iter()
+            .filter_map(|(g, c)| {
+                glyph_map
+                    .get(g)
+                    .map(|&n| (n, remap_construction(c, glyph_map)))
+            })
+            .collect()
+    };
+    MATH {
+        constants: math.constants.clone(),
+        italic_correction: keys(&math.italic_correction, glyph_map),
+        top_accent_attachment: keys(&math.top_accent_attachment, glyph_map),
+        extended_shapes: math
+            .extended_shapes
+            .iter()
+            .filter_map(|g| glyph_map.get(g).copied())
+            .collect(),
+        kerning: keys(&math.kerning, glyph_map),
+        min_overlap: math.min_overlap,
+        vertical_extensions: extensions(&math.vertical_extensions),
+        horizontal_extensions: extensions(&math.horizontal_extensions),
+    }
+}
+
+/// Subsets a font to the given codepoints and glyphs.
+///
+/// Returns the mapping from old to new glyph IDs of the retained glyphs.
+pub(crate) fn subset(
+    font: &mut Font,
+    unicodes: &BTreeSet<u32>,
+    glyphs: &BTreeSet<GlyphID>,
+    options: &SubsetOptions,
+) -> Result<BTreeMap<GlyphID, GlyphID>, Box<dyn Error>> {
+    let retained = closure(font, unicodes, glyphs, options)?;
+    let (glyph_map, order): (BTreeMap<GlyphID, GlyphID>, Vec<Option<GlyphID>>) =
+        if options.retain_gids {
+            let last = retained.iter().next_back().copied().unwrap_or(0);
+            (
+                retained.iter().map(|&g| (g, g)).collect(),
+                (0..=last)
+                    .map(|g| Some(g).filter(|g| retained.contains(g)))
+                    .collect(),
+            )
+        } else {
+            (
+                retained
+                    .iter()
+                    .enumerate()
+                    .map(|(new, &old)| (old, new as GlyphID))
+                    .collect(),
+                retained.iter().map(|&g| Some(g)).collect(),
+            )
+        };
+
+    // Load everything up front, as some tables need others to deserialize.
+    let tables = &font.tables;
+    let (cmap, hmtx, glyf, gvar, post, cff, cff2) = (
+        tables.cmap()?,
+        tables.hmtx()?,
+        tables.glyf()?,
+        tables.gvar()?,
+        tables.post()?,
+        tables.CFF()?,
+        tables.CFF2()?,
+    );
+    let (gdef, gsub, gpos, math) = (
+        tables.GDEF()?,
+        tables.GSUB()?,
+        tables.GPOS()?,
+        tables.MATH()?,
+    );
+    let (maxp, name, os2, fvar, stat) = (
+        tables.maxp()?,
+        tables.name()?,
+        tables.os2()?,
+        tables.fvar()?,
+        tables.STAT()?,
+    );
+
+    let mut new_mapping: BTreeMap<u32, u16> = BTreeMap::new();
+    if let Some(mut cmap) = cmap {
+        for subtable in cmap.subtables.iter_mut() {
+            let keep = |u: &u32, g: &u16| {
+                (unicodes.contains(u) || glyphs.contains(g)) && glyph_map.contains_key(g)
+            };
+            subtable.mapping = subtable
+                .mapping
+                .iter()
+                .filter(|(u, g)| keep(u, g))
+                .map(|(&u, g)| (u, glyph_map[g]))
+                .collect();
+            if let Some(uvs) = subtable.uvs_mapping.as_mut() {
+                *uvs = uvs
+                    .iter()
+                    .filter(|((u, _), g)| keep(u, g))
+                    .map(|(&k, g)| (k, glyph_map[g]))
+                    .collect();
+            }
+            if subtable.is_unicode() {
+                new_mapping.extend(subtable.mapping.iter());
+            }
+        }
+        cmap.subtables.retain(|st| {
+            !st.mapping.is_empty() || st.uvs_mapping.as_ref().is_some_and(|m| !m.is_empty())
+        });
+        font.tables.insert(cmap);
+    }
+
+    if let Some(mut hmtx) = hmtx {
+        hmtx.metrics = reorder(&hmtx.metrics, &order, || Metric {
+            advanceWidth: 0,
+            lsb: 0,
+        });
+        font.tables.insert(hmtx);
+    }
+
+    if let Some(mut gvar) = gvar {
+        gvar.variations = reorder(&gvar.variations, &order, || None);
+        font.tables.insert(gvar);
+    }
+
+    if let Some(mut glyf) = glyf {
+        let empty = || Glyph {
+            xMin: 0,
+            xMax: 0,
+            yMin: 0,
+            yMax: 0,
+            contours: vec![],
+            instructions: vec![],
+            components: vec![],
+            overlap: false,
+        };
+        glyf.glyphs = reorder(&glyf.glyphs, &order, empty);
+        for glyph in glyf.glyphs.iter_mut() {
+            for component in glyph.components.iter_mut() {
+                component.glyph_index = glyph_map[&component.glyph_index];
+            }
+            if !options.hinting {
+                glyph.instructions.clear();
+            }
+        }
+        font.tables.insert(glyf);
+    }
+
+    if let Some(mut cff) = cff {
+        let is_cid = cff.is_cid();
+        let used: BTreeSet<u16> = order
+            .iter()
+            .flatten()
+            .filter_map(|&g| cff.charset.sid(g as usize))
+            .collect();
+        let mut free_cids = (1..=u16::MAX).filter(|cid| !used.contains(cid));
+        let mut sids = Vec::with_capacity(order.len());
+        for (new, old) in order.iter().enumerate() {
+            let sid = match old.and_then(|g| cff.charset.sid(g as usize)) {
+                Some(sid) => sid,
+                // Empty glyphs left in place of dropped ones still need a
+                // name (or CID) of their own.
+                None if is_cid => free_cids.next().ok_or("Too many glyphs for a CID font")?,
+                None => cff.add_string(&format!("glyph{:05}", new)),
+            };
+            sids.push(sid);
+        }
+        cff.charstrings = reorder(&cff.charstrings, &order, || vec![14]); // endchar
+        if !cff.fd_select.is_empty() {
+            cff.fd_select = reorder(&cff.fd_select, &order, || 0);
+        }
+        cff.charset = Charset::Custom(sids[1..].to_vec());
+        cff.encoding = None;
+        font.tables.insert(cff);
+    }
+
+    if let Some(mut cff2) = cff2 {
+        cff2.charstrings = reorder(&cff2.charstrings, &order, Vec::new);
+        if let Some(fd_select) = cff2.fd_select.as_mut() {
+            *fd_select = reorder(fd_select, &order, || 0);
+        }
+        font.tables.insert(cff2);
+    }
+
+    if let Some(mut post) = post {
+        if !options.glyph_names {
+            post.set_version(3.0);
+            post.glyphnames = None;
+        } else if let Some(names) = &post.glyphnames {
+            post.glyphnames = Some(
+                order
+                    .iter()
+                    .enumerate()
+                    .map(|(new, old)| {
+                        old.and_then(|g| names.get(g as usize))
+                            .cloned()
+                            .unwrap_or_else(|| format!("glyph{:05}", new))
+                    })
+                    .collect(),
+            );
+        }
+        font.tables.insert(post);
+    }
+
+    if let Some(mut maxp) = maxp {
+        maxp.set_num_glyphs(order.len() as u16);
+        font.tables.insert(maxp);
+    }
+
+    if let Some(gdef) = gdef {
+        font.tables.insert(layout::subset_gdef(&gdef, &glyph_map));
+    }
+    if let Some(math) = math {
+        font.tables.insert(subset_math(&math, &glyph_map));
+    }
+
+    let mut name_ids: BTreeSet<uint16> = BTreeSet::new();
+    if let Some(gsub) = gsub {
+        let features = layout::retained_features(&gsub.features, options.layout_features.as_ref());
+        let gsub = layout::subset_layout(&gsub, &glyph_map, &features);
+        name_ids.extend(
+            gsub.features
+                .iter()
+                .filter_map(|(_, _, params)| params.as_ref())
+                .flat_map(|params| params.name_ids()),
+        );
+        font.tables.insert(gsub);
+    }
+    if let Some(gpos) = gpos {
+        let features = layout::retained_features(&gpos.features, options.layout_features.as_ref());
+        let gpos = layout::subset_layout(&gpos, &glyph_map, &features);
+        name_ids.extend(
+            gpos.features
+                .iter()
+                .filter_map(|(_, _, params)| params.as_ref())
+                .flat_map(|params| params.name_ids()),
+        );
+        font.tables.insert(gpos);
+    }
+
+    if let Some(mut name) = name {
+        if let Some(fvar) = fvar {
+            name_ids.extend(fvar.axes.iter().map(|a| a.axisNameID));
+            for instance in &fvar.instances {
+                name_ids.insert(instance.subfamilyNameID);
+                name_ids.extend(instance.postscriptNameID);
+            }
+        }
+        if let Some(stat) = stat {
+            name_ids.extend(stat.design_axes.iter().map(|a| a.axisNameID));
+            name_ids.extend(stat.axis_values.iter().map(|v| v.name_id));
+            name_ids.extend(stat.elided_fallback_name_id);
+        }
+        name.records.retain(|record| {
+            (options.name_legacy || record.platformID == 3)
+                && options
+                    .name_languages
+                    .as_ref()
+                    .is_none_or(|langs| langs.contains(&record.languageID))
+                && (options
+                    .name_ids
+                    .as_ref()
+                    .is_none_or(|ids| ids.contains(&record.nameID))
+                    || name_ids.contains(&record.nameID))
+        });
+        font.tables.insert(name);
+    }
+
+    if let Some(mut os2) = os2 {
+        if let (Some(first), Some(last)) = (
+            new_mapping.keys().next().copied(),
+            new_mapping.keys().next_back().copied(),
+        ) {
+            os2.usFirstCharIndex = first.min(0xFFFF) as u16;
+            os2.usLastCharIndex = last.min(0xFFFF) as u16;
+            os2.calc_unicode_ranges(&new_mapping);
+        }
+        font.tables.insert(os2);
+    }
+
+    for tag in DROPPED_TABLES.iter() {
+        font.tables.remove(*tag);
+    }
+    if !options.hinting {
+        for tag in HINTING_TABLES.iter() {
+            font.tables.remove(*tag);
+        }
+    }
+    Ok(glyph_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::common::{FeatureList, LanguageSystem, Lookup, LookupFlags};
+    use crate::layout::common::{Script, ScriptList, GPOSGSUB};
+    use crate::layout::gpos2::PairPos;
+    use crate::layout::gsub1::SingleSubst;
+    use crate::tables;
+    use crate::tables::cmap::CmapSubtable;
+    use crate::tables::glyf::{Component, ComponentFlags, Point};
+    use crate::tables::GPOS::Positioning;
+    use crate::tables::GSUB::Substitution;
+    use crate::testing;
+    use kurbo::Affine;
+    use otspec::btreemap;
+    use otspec::layout::valuerecord::ValueRecord;
+    use std::iter::FromIterator;
+
+    fn triangle() -> Glyph {
+        Glyph {
+            xMin: 0,
+            xMax: 200,
+            yMin: 0,
+            yMax: 200,
+            contours: vec![vec![
+                Point {
+                    x: 0,
+                    y: 0,
+                    on_curve: true,
+                },
+                Point {
+                    x: 200,
+                    y: 0,
+                    on_curve: true,
+                },
+                Point {
+                    x: 100,
+                    y: 200,
+                    on_curve: true,
+                },
+            ]],
+            instructions: vec![],
+            components: vec![],
+            overlap: false,
+        }
+    }
+
+    fn composite(components: &[GlyphID]) -> Glyph {
+        let mut glyph = triangle();
+        glyph.contours.clear();
+        glyph.components = components
+            .iter()
+            .map(|&glyph_index| Component {
+                glyph_index,
+                transformation: Affine::IDENTITY,
+                match_points: None,
+                flags: ComponentFlags::empty(),
+            })
+            .collect();
+        glyph
+    }
+
+    fn layout<T>(tag: Tag, rule: T) -> GPOSGSUB<T> {
+        GPOSGSUB {
+            lookups: vec![Lookup {
+                flags: LookupFlags::empty(),
+                mark_filtering_set: None,
+                rule,
+            }],
+            scripts: ScriptList {
+                scripts: btreemap!(tag!("DFLT") => Script {
+                    default_language_system: Some(LanguageSystem {
+                        required_feature: None,
+                        feature_indices: vec![0],
+                    }),
+                    language_systems: BTreeMap::new(),
+                }),
+            },
+            features: FeatureList::new(vec![(tag, vec![0], None)]),
+            feature_variations: vec![],
+        }
+    }
+
+    /// A font with the glyphs .notdef, a, b, a.sc, adieresis (a composite of
+    /// a and dieresis) and dieresis.
+    fn test_font() -> Font {
+        let mut font = testing::test_font(6);
+        let glyphs = vec![
+            triangle(),
+            triangle(),
+            triangle(),
+            triangle(),
+            composite(&[1, 5]),
+            triangle(),
+        ];
+        font.tables.insert(tables::glyf::glyf { glyphs });
+        font.tables.insert(tables::hmtx::hmtx {
+            metrics: (0..6)
+                .map(|gid| Metric {
+                    advanceWidth: 100 * gid,
+                    lsb: 0,
+                })
+                .collect(),
+        });
+        font.tables
+            .insert(tables::maxp::maxp::new10(6, 3, 1, 6, 2, 0, 1));
+        let names = [".notdef", "a", "b", "a.sc", "adieresis", "dieresis"];
+        font.tables.insert(tables::post::post::new(
+            2.0,
+            0.0,
+            -100,
+            50,
+            false,
+            Some(names.iter().map(|n| n.to_string()).collect()),
+        ));
+        font.tables.insert(tables::cmap::cmap {
+            subtables: vec![CmapSubtable {
+                format: 4,
+                platformID: 3,
+                encodingID: 1,
+                languageID: 0,
+                mapping: btreemap!(0x61 => 1, 0x62 => 2, 0xE4 => 4, 0xA8 => 5),
+                uvs_mapping: None,
+            }],
+        });
+        font.tables.insert(layout(
+            tag!("smcp"),
+            Substitution::Single(vec![SingleSubst {
+                mapping: btreemap!(1 => 3),
+            }]),
+        ));
+        let kern = ValueRecord {
+            xAdvance: Some(-50),
+            ..Default::default()
+        };
+        font.tables.insert(layout(
+            tag!("kern"),
+            Positioning::Pair(vec![PairPos {
+                mapping: btreemap!((1, 2) => (kern, ValueRecord::default())),
+            }]),
+        ));
+        font
+    }
+
+    #[test]
+    fn test_closure() {
+        let font = test_font();
+        let unicodes = btreeset(&[0x61, 0xE4]);
+        let options = SubsetOptions::default();
+        let glyphs = closure(&font, &unicodes, &BTreeSet::new(), &options).unwrap();
+        assert_eq!(glyphs, btreeset(&[0, 1, 3, 4, 5]));
+
+        let options = SubsetOptions {
+            layout_features: Some(BTreeSet::new()),
+            ..Default::default()
+        };
+        let glyphs = closure(&font, &unicodes, &btreeset(&[2]), &options).unwrap();
+        assert_eq!(glyphs, btreeset(&[0, 1, 2, 4, 5]));
+    }
+
+    fn btreeset<T: Ord + Copy>(items: &[T]) -> BTreeSet<T> {
+        items.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_subset() {
+        let mut font = test_font();
+        let glyph_map = font
+            .subset(
+                &btreeset(&[0x61, 0xE4]),
+                &BTreeSet::new(),
+                &SubsetOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(glyph_map, btreemap!(0 => 0, 1 => 1, 3 => 2, 4 => 3, 5 => 4));
+        assert_eq!(font.num_glyphs(), 5);
+
+        let mut data = vec![];
+        font.write(&mut data).unwrap();
+        let font = Font::from_bytes(&data).unwrap();
+        let cmap = font.tables.cmap().unwrap().unwrap();
+        assert_eq!(cmap.subtables[0].mapping, btreemap!(0x61 => 1, 0xE4 => 3));
+        let glyf = font.tables.glyf().unwrap().unwrap();
+        assert_eq!(glyf.glyphs.len(), 5);
+        let components: Vec<GlyphID> = glyf.glyphs[3]
+            .components
+            .iter()
+            .map(|c| c.glyph_index)
+            .collect();
+        assert_eq!(components, vec![1, 4]);
+        let hmtx = font.tables.hmtx().unwrap().unwrap();
+        let advances: Vec<u16> = hmtx.metrics.iter().map(|m| m.advanceWidth).collect();
+        assert_eq!(advances, vec![0, 100, 300, 400, 500]);
+        let post = font.tables.post().unwrap().unwrap();
+        assert_eq!(
+            post.glyphnames.as_ref().unwrap(),
+            &vec![".notdef", "a", "a.sc", "adieresis", "dieresis"]
+        );
+
+        let gsub = font.tables.GSUB().unwrap().unwrap();
+        assert_eq!(
+            gsub.lookups[0].rule,
+            Substitution::Single(vec![SingleSubst {
+                mapping: btreemap!(1 => 2),
+            }])
+        );
+        // The kerning pair involved "b", so the lookup and feature are gone
+        let gpos = font.tables.GPOS().unwrap().unwrap();
+        assert!(gpos.lookups.is_empty());
+        assert_eq!(gpos.features.len(), 0);
+        assert!(gpos.scripts.scripts[&tag!("DFLT")]
+            .default_language_system
+            .as_ref()
+            .unwrap()
+            .feature_indices
+            .is_empty());
+    }
+
+    #[test]
+    fn test_subset_retain_gids() {
+        let mut font = test_font();
+        let options = SubsetOptions {
+            retain_gids: true,
+            glyph_names: false,
+            ..Default::default()
+        };
+        let glyph_map = font
+            .subset(&btreeset(&[0x62]), &BTreeSet::new(), &options)
+            .unwrap();
+        assert_eq!(glyph_map, btreemap!(0 => 0, 2 => 2));
+        assert_eq!(font.num_glyphs(), 3);
+        let glyf = font.tables.glyf().unwrap().unwrap();
+        assert!(glyf.glyphs[1].is_empty());
+        assert!(!glyf.glyphs[2].is_empty());
+        assert!(font.tables.post().unwrap().unwrap().glyphnames.is_none());
+        assert!(font.tables.GSUB().unwrap().unwrap().lookups.is_empty());
+    }
+
+    #[test]
+    fn test_subset_cff_retain_gids() {
+        let mut font = testing::cff_test_font(5);
+        font.tables.insert(tables::CFF::tests::test_font());
+        font.tables.insert(tables::hmtx::hmtx {
+            metrics: vec![
+                Metric {
+                    advanceWidth: 500,
+                    lsb: 0,
+                };
+                5
+            ],
+        });
+        let options = SubsetOptions {
+            retain_gids: true,
+            ..Default::default()
+        };
+        font.subset(&BTreeSet::new(), &btreeset(&[4]), &options)
+            .unwrap();
+
+        let mut data = vec![];
+        font.write(&mut data).unwrap();
+        let font = Font::from_bytes(&data).unwrap();
+        let cff = font.tables.CFF().unwrap().unwrap();
+        assert_eq!(
+            cff.glyph_names(),
+            vec![
+                ".notdef",
+                "glyph00001",
+                "glyph00002",
+                "glyph00003",
+                "square"
+            ]
+        );
+        let hmtx = font.tables.hmtx().unwrap().unwrap();
+        let advances: Vec<u16> = hmtx.metrics.iter().map(|m| m.advanceWidth).collect();
+        assert_eq!(advances, vec![500, 0, 0, 0, 500]);
+        assert_eq!(font.tables.hhea().unwrap().unwrap().numberOfHMetrics, 5);
+    }
+}
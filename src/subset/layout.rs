@@ -0,0 +1,553 @@
+use crate::layout::common::{FeatureList, FeatureVariation, Lookup, ScriptList, GPOSGSUB};
+use crate::layout::contextual::{
+    ChainedSequenceContext, ChainedSequenceContextRule, SequenceContext, SequenceContextRule, Slot,
+};
+use crate::layout::gpos1::SinglePos;
+use crate::layout::gpos2::PairPos;
+use crate::layout::gpos3::CursivePos;
+use crate::layout::gpos4::MarkBasePos;
+use crate::layout::gpos5::MarkLigPos;
+use crate::layout::gpos6::MarkMarkPos;
+use crate::layout::gsub1::SingleSubst;
+use crate::layout::gsub2::MultipleSubst;
+use crate::layout::gsub3::AlternateSubst;
+use crate::layout::gsub4::LigatureSubst;
+use crate::layout::gsub8::ReverseChainSubst;
+use crate::tables::GDEF::GDEF;
+use crate::tables::GPOS::Positioning;
+use crate::tables::GSUB::Substitution;
+use otspec::layout::anchor::Anchor;
+use otspec::types::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The glyph and lookup renumbering used when subsetting a layout table
+pub(crate) struct LayoutPlan<'a> {
+    glyph_map: &'a BTreeMap<GlyphID, GlyphID>,
+    lookup_map: BTreeMap<usize, usize>,
+}
+
+impl LayoutPlan<'_> {
+    fn glyph(&self, gid: GlyphID) -> Option<GlyphID> {
+        self.glyph_map.get(&gid).copied()
+    }
+
+    fn glyphs(&self, gids: &[GlyphID]) -> Option<Vec<GlyphID>> {
+        gids.iter().map(|&g| self.glyph(g)).collect()
+    }
+
+    fn slot(&self, slot: &Slot) -> Option<Slot> {
+        let new: Slot = slot.iter().filter_map(|&g| self.glyph(g)).collect();
+        if new.is_empty() {
+            None
+        } else {
+            Some(new)
+        }
+    }
+
+    fn slots(&self, slots: &[Slot]) -> Option<Vec<Slot>> {
+        slots.iter().map(|s| self.slot(s)).collect()
+    }
+
+    fn lookups(&self, lookups: &[uint16]) -> Vec<uint16> {
+        lookups
+            .iter()
+            .filter_map(|&l| self.lookup_map.get(&(l as usize)).map(|&n| n as uint16))
+            .collect()
+    }
+
+    /// Remaps the keys of a glyph-keyed map, dropping removed glyphs
+    fn keys<T: Clone>(&self, map: &BTreeMap<GlyphID, T>) -> BTreeMap<GlyphID, T> {
+        map.iter()
+            .filter_map(|(&g, v)| self.glyph(g).map(|n| (n, v.clone())))
+            .collect()
+    }
+}
+
+/// A layout structure which can be reduced to a set of glyphs
+pub(crate) trait Subset: Sized {
+    /// Returns the subset structure, or `None` if nothing of it remains
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self>;
+}
+
+fn non_empty<T>(map: T, is_empty: bool) -> Option<T> {
+    if is_empty {
+        None
+    } else {
+        Some(map)
+    }
+}
+
+impl Subset for SingleSubst {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        let mapping: BTreeMap<GlyphID, GlyphID> = self
+            .mapping
+            .iter()
+            .filter_map(|(&l, &r)| Some((plan.glyph(l)?, plan.glyph(r)?)))
+            .collect();
+        let empty = mapping.is_empty();
+        non_empty(SingleSubst { mapping }, empty)
+    }
+}
+
+impl Subset for MultipleSubst {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        let mapping: BTreeMap<GlyphID, Vec<GlyphID>> = self
+            .mapping
+            .iter()
+            .filter_map(|(&l, r)| Some((plan.glyph(l)?, plan.glyphs(r)?)))
+            .collect();
+        let empty = mapping.is_empty();
+        non_empty(MultipleSubst { mapping }, empty)
+    }
+}
+
+impl Subset for AlternateSubst {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        let mapping: BTreeMap<GlyphID, Vec<GlyphID>> = self
+            .mapping
+            .iter()
+            .filter_map(|(&l, r)| {
+                let alternates: Vec<GlyphID> = r.iter().filter_map(|&g| plan.glyph(g)).collect();
+                if alternates.is_empty() {
+                    None
+                } else {
+                    Some((plan.glyph(l)?, alternates))
+                }
+            })
+            .collect();
+        let empty = mapping.is_empty();
+        non_empty(AlternateSubst { mapping }, empty)
+    }
+}
+
+impl Subset for LigatureSubst {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        let mapping: BTreeMap<Vec<GlyphID>, GlyphID> = self
+            .mapping
+            .iter()
+            .filter_map(|(l, &r)| Some((plan.glyphs(l)?, plan.glyph(r)?)))
+            .collect();
+        let empty = mapping.is_empty();
+        non_empty(LigatureSubst { mapping }, empty)
+    }
+}
+
+impl Subset for ReverseChainSubst {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        let mapping: BTreeMap<GlyphID, GlyphID> = self
+            .mapping
+            .iter()
+            .filter_map(|(&l, &r)| Some((plan.glyph(l)?, plan.glyph(r)?)))
+            .collect();
+        if mapping.is_empty() {
+            return None;
+        }
+        Some(ReverseChainSubst {
+            mapping,
+            backtrack: plan.slots(&self.backtrack)?,
+            lookahead: plan.slots(&self.lookahead)?,
+        })
+    }
+}
+
+fn subset_rule(rule: &SequenceContextRule, plan: &LayoutPlan) -> Option<SequenceContextRule> {
+    rule.iter()
+        .map(|(slot, lookups)| Some((plan.slot(slot)?, plan.lookups(lookups))))
+        .collect()
+}
+
+impl Subset for SequenceContext {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        let rules: Vec<SequenceContextRule> = self
+            .rules
+            .iter()
+            .filter_map(|rule| subset_rule(rule, plan))
+            .collect();
+        let empty = rules.is_empty();
+        non_empty(SequenceContext { rules }, empty)
+    }
+}
+
+impl Subset for ChainedSequenceContext {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        let rules: Vec<ChainedSequenceContextRule> = self
+            .rules
+            .iter()
+            .filter_map(|rule| {
+                Some(ChainedSequenceContextRule {
+                    backtrack: plan.slots(&rule.backtrack)?,
+                    lookahead: plan.slots(&rule.lookahead)?,
+                    input: subset_rule(&rule.input, plan)?,
+                })
+            })
+            .collect();
+        let empty = rules.is_empty();
+        non_empty(ChainedSequenceContext { rules }, empty)
+    }
+}
+
+impl Subset for SinglePos {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        let mapping = plan.keys(&self.mapping);
+        let empty = mapping.is_empty();
+        non_empty(SinglePos { mapping }, empty)
+    }
+}
+
+impl Subset for PairPos {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        let mapping: BTreeMap<_, _> = self
+            .mapping
+            .iter()
+            .filter_map(|(&(l, r), v)| Some(((plan.glyph(l)?, plan.glyph(r)?), v.clone())))
+            .collect();
+        let empty = mapping.is_empty();
+        non_empty(PairPos { mapping }, empty)
+    }
+}
+
+impl Subset for CursivePos {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        let mapping = plan.keys(&self.mapping);
+        let empty = mapping.is_empty();
+        non_empty(CursivePos { mapping }, empty)
+    }
+}
+
+/// Renumbers the mark classes which are still in use after subsetting, and
+/// returns the mapping from old to new classes.
+fn compact_mark_classes(marks: &mut BTreeMap<GlyphID, (uint16, Anchor)>) -> BTreeMap<u16, u16> {
+    let used: BTreeSet<u16> = marks.values().map(|(class, _)| *class).collect();
+    let class_map: BTreeMap<u16, u16> = used
+        .into_iter()
+        .enumerate()
+        .map(|(new, old)| (old, new as u16))
+        .collect();
+    for (class, _) in marks.values_mut() {
+        *class = class_map[class];
+    }
+    class_map
+}
+
+fn remap_anchor_classes(
+    anchors: &BTreeMap<uint16, Anchor>,
+    class_map: &BTreeMap<u16, u16>,
+) -> BTreeMap<uint16, Anchor> {
+    anchors
+        .iter()
+        .filter_map(|(class, anchor)| class_map.get(class).map(|&new| (new, anchor.clone())))
+        .collect()
+}
+
+impl Subset for MarkBasePos {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        let mut marks = plan.keys(&self.marks);
+        let class_map = compact_mark_classes(&mut marks);
+        let bases: BTreeMap<_, _> = plan
+            .keys(&self.bases)
+            .into_iter()
+            .map(|(g, anchors)| (g, remap_anchor_classes(&anchors, &class_map)))
+            .collect();
+        let empty = marks.is_empty() || bases.is_empty();
+        non_empty(MarkBasePos { bases, marks }, empty)
+    }
+}
+
+impl Subset for MarkLigPos {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        let mut marks = plan.keys(&self.marks);
+        let class_map = compact_mark_classes(&mut marks);
+        let ligatures: BTreeMap<_, _> = plan
+            .keys(&self.ligatures)
+            .into_iter()
+            .map(|(g, components)| {
+                let components = components
+                    .iter()
+                    .map(|anchors| remap_anchor_classes(anchors, &class_map))
+                    .collect();
+                (g, components)
+            })
+            .collect();
+        let empty = marks.is_empty() || ligatures.is_empty();
+        non_empty(MarkLigPos { ligatures, marks }, empty)
+    }
+}
+
+impl Subset for MarkMarkPos {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        let mut combining_marks = plan.keys(&self.combining_marks);
+        let class_map = compact_mark_classes(&mut combining_marks);
+        let base_marks: BTreeMap<_, _> = plan
+            .keys(&self.base_marks)
+            .into_iter()
+            .map(|(g, anchors)| (g, remap_anchor_classes(&anchors, &class_map)))
+            .collect();
+        let empty = combining_marks.is_empty() || base_marks.is_empty();
+        non_empty(
+            MarkMarkPos {
+                base_marks,
+                combining_marks,
+            },
+            empty,
+        )
+    }
+}
+
+fn subset_subtables<T: Subset>(subtables: &[T], plan: &LayoutPlan) -> Option<Vec<T>> {
+    let new: Vec<T> = subtables.iter().filter_map(|s| s.subset(plan)).collect();
+    if new.is_empty() {
+        None
+    } else {
+        Some(new)
+    }
+}
+
+/// A GSUB or GPOS lookup type
+pub(crate) trait LayoutRule: Subset {
+    /// The indices of the lookups called from contextual rules
+    fn nested_lookups(&self) -> BTreeSet<usize>;
+}
+
+fn context_lookups(rules: &[SequenceContextRule]) -> BTreeSet<usize> {
+    rules
+        .iter()
+        .flatten()
+        .flat_map(|(_, lookups)| lookups.iter().map(|&l| l as usize))
+        .collect()
+}
+
+impl Subset for Substitution {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        Some(match self {
+            Substitution::Single(s) => Substitution::Single(subset_subtables(s, plan)?),
+            Substitution::Multiple(s) => Substitution::Multiple(subset_subtables(s, plan)?),
+            Substitution::Alternate(s) => Substitution::Alternate(subset_subtables(s, plan)?),
+            Substitution::Ligature(s) => Substitution::Ligature(subset_subtables(s, plan)?),
+            Substitution::Contextual(s) => Substitution::Contextual(subset_subtables(s, plan)?),
+            Substitution::ChainedContextual(s) => {
+                Substitution::ChainedContextual(subset_subtables(s, plan)?)
+            }
+            Substitution::ReverseChainContextual(s) => {
+                Substitution::ReverseChainContextual(subset_subtables(s, plan)?)
+            }
+        })
+    }
+}
+
+impl LayoutRule for Substitution {
+    fn nested_lookups(&self) -> BTreeSet<usize> {
+        match self {
+            Substitution::Contextual(s) => {
+                let rules: Vec<_> = s.iter().flat_map(|st| st.rules.clone()).collect();
+                context_lookups(&rules)
+            }
+            Substitution::ChainedContextual(s) => {
+                let rules: Vec<_> = s
+                    .iter()
+                    .flat_map(|st| st.rules.iter().map(|r| r.input.clone()))
+                    .collect();
+                context_lookups(&rules)
+            }
+            _ => BTreeSet::new(),
+        }
+    }
+}
+
+impl Subset for Positioning {
+    fn subset(&self, plan: &LayoutPlan) -> Option<Self> {
+        Some(match self {
+            Positioning::Single(s) => Positioning::Single(subset_subtables(s, plan)?),
+            Positioning::Pair(s) => Positioning::Pair(subset_subtables(s, plan)?),
+            Positioning::Cursive(s) => Positioning::Cursive(subset_subtables(s, plan)?),
+            Positioning::MarkToBase(s) => Positioning::MarkToBase(subset_subtables(s, plan)?),
+            Positioning::MarkToLig(s) => Positioning::MarkToLig(subset_subtables(s, plan)?),
+            Positioning::MarkToMark(s) => Positioning::MarkToMark(subset_subtables(s, plan)?),
+            Positioning::Contextual(s) => Positioning::Contextual(subset_subtables(s, plan)?),
+            Positioning::ChainedContextual(s) => {
+                Positioning::ChainedContextual(subset_subtables(s, plan)?)
+            }
+        })
+    }
+}
+
+impl LayoutRule for Positioning {
+    fn nested_lookups(&self) -> BTreeSet<usize> {
+        match self {
+            Positioning::Contextual(s) => {
+                let rules: Vec<_> = s.iter().flat_map(|st| st.rules.clone()).collect();
+                context_lookups(&rules)
+            }
+            Positioning::ChainedContextual(s) => {
+                let rules: Vec<_> = s
+                    .iter()
+                    .flat_map(|st| st.rules.iter().map(|r| r.input.clone()))
+                    .collect();
+                context_lookups(&rules)
+            }
+            _ => BTreeSet::new(),
+        }
+    }
+}
+
+/// The indices of the features with the given tags, or of all features.
+pub(crate) fn retained_features(
+    features: &FeatureList,
+    tags: Option<&BTreeSet<Tag>>,
+) -> BTreeSet<usize> {
+    features
+        .iter()
+        .enumerate()
+        .filter(|(_, (tag, _, _))| tags.is_none_or(|tags| tags.contains(tag)))
+        .map(|(ix, _)| ix)
+        .collect()
+}
+
+/// The indices of the lookups used by the given features, including
+/// lookups called from contextual lookups.
+pub(crate) fn reachable_lookups<T: LayoutRule>(
+    table: &GPOSGSUB<T>,
+    features: &BTreeSet<usize>,
+) -> BTreeSet<usize> {
+    let mut lookups: BTreeSet<usize> = features
+        .iter()
+        .flat_map(|&ix| table.all_feature_lookups(ix))
+        .filter(|&l| l < table.lookups.len())
+        .collect();
+    let mut todo: Vec<usize> = lookups.iter().copied().collect();
+    while let Some(ix) = todo.pop() {
+        for nested in table.lookups[ix].rule.nested_lookups() {
+            if nested < table.lookups.len() && lookups.insert(nested) {
+                todo.push(nested);
+            }
+        }
+    }
+    lookups
+}
+
+fn subset_lookup<T: LayoutRule + Clone>(
+    lookup: &Lookup<T>,
+    plan: &LayoutPlan,
+) -> Option<Lookup<T>> {
+    Some(Lookup {
+        flags: lookup.flags,
+        mark_filtering_set: lookup.mark_filtering_set,
+        rule: lookup.rule.subset(plan)?,
+    })
+}
+
+/// Subsets a GSUB or GPOS table, keeping the given features, dropping
+/// lookups which are unused or become empty, and renumbering glyphs.
+pub(crate) fn subset_layout<T: LayoutRule + Clone>(
+    table: &GPOSGSUB<T>,
+    glyph_map: &BTreeMap<GlyphID, GlyphID>,
+    features: &BTreeSet<usize>,
+) -> GPOSGSUB<T> {
+    let reachable = reachable_lookups(table, features);
+    // Find out which lookups still do something once the glyphs are gone
+    let plan = LayoutPlan {
+        glyph_map,
+        lookup_map: reachable.iter().map(|&l| (l, l)).collect(),
+    };
+    let non_empty: BTreeSet<usize> = reachable
+        .iter()
+        .copied()
+        .filter(|&ix| table.lookups[ix].rule.subset(&plan).is_some())
+        .collect();
+    let plan = LayoutPlan {
+        glyph_map,
+        lookup_map: non_empty
+            .iter()
+            .enumerate()
+            .map(|(new, &old)| (old, new))
+            .collect(),
+    };
+    let lookups: Vec<Lookup<T>> = non_empty
+        .iter()
+        .filter_map(|&ix| subset_lookup(&table.lookups[ix], &plan))
+        .collect();
+
+    let subset_lookups = |lookups: &[usize]| -> Vec<usize> {
+        lookups
+            .iter()
+            .filter_map(|l| plan.lookup_map.get(l).copied())
+            .collect()
+    };
+
+    // Drop features which have no lookups left, even in feature variations
+    let mut feature_map: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut new_features = FeatureList::default();
+    for &ix in features {
+        if let Some((tag, feature_lookups, params)) = table.features.get(ix) {
+            let all_lookups: Vec<usize> = table.all_feature_lookups(ix).into_iter().collect();
+            if subset_lookups(&all_lookups).is_empty() {
+                continue;
+            }
+            let feature_lookups = subset_lookups(feature_lookups);
+            feature_map.insert(ix, new_features.len());
+            new_features.push((*tag, feature_lookups, params.clone()));
+        }
+    }
+
+    let mut scripts = ScriptList::default();
+    for (tag, script) in &table.scripts.scripts {
+        let mut script = script.clone();
+        for langsys in script
+            .default_language_system
+            .iter_mut()
+            .chain(script.language_systems.values_mut())
+        {
+            langsys.required_feature = langsys
+                .required_feature
+                .and_then(|f| feature_map.get(&f).copied());
+            langsys.feature_indices = langsys
+                .feature_indices
+                .iter()
+                .filter_map(|f| feature_map.get(f).copied())
+                .collect();
+        }
+        scripts.scripts.insert(*tag, script);
+    }
+
+    let feature_variations = table
+        .feature_variations
+        .iter()
+        .map(|variation| FeatureVariation {
+            conditions: variation.conditions.clone(),
+            substitution: variation
+                .substitution
+                .iter()
+                .filter_map(|(feature, lookups)| {
+                    Some((*feature_map.get(feature)?, subset_lookups(lookups)))
+                })
+                .collect(),
+        })
+        .filter(|variation| !variation.substitution.is_empty())
+        .collect();
+
+    GPOSGSUB {
+        lookups,
+        scripts,
+        features: new_features,
+        feature_variations,
+    }
+}
+
+/// Subsets and renumbers the glyphs in a GDEF table.
+pub(crate) fn subset_gdef(gdef: &GDEF, glyph_map: &BTreeMap<GlyphID, GlyphID>) -> GDEF {
+    let plan = LayoutPlan {
+        glyph_map,
+        lookup_map: BTreeMap::new(),
+    };
+    GDEF {
+        glyph_class: plan.keys(&gdef.glyph_class),
+        attachment_point_list: plan.keys(&gdef.attachment_point_list),
+        ligature_caret_list: plan.keys(&gdef.ligature_caret_list),
+        mark_attachment_class: plan.keys(&gdef.mark_attachment_class),
+        mark_glyph_sets: gdef.mark_glyph_sets.as_ref().map(|sets| {
+            sets.iter()
+                .map(|set| set.iter().filter_map(|&g| plan.glyph(g)).collect())
+                .collect()
+        }),
+        item_variation_store: gdef.item_variation_store.clone(),
+    }
+}
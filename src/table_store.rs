@@ -8,12 +8,12 @@
 use std::borrow::Borrow;
 use std::cell::RefCell;
 use std::collections::BTreeMap;
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryInto;
 use std::fmt::Debug;
 use std::ops::Deref;
 use std::rc::Rc;
 
-use otspec::types::Tag;
+use otspec::types::{uint16, GlyphID, Tag};
 use otspec::{DeserializationError, ReaderContext, SerializationError, Serialize};
 
 use crate::tables;
@@ -73,8 +73,14 @@ pub struct Table {
 pub enum LoadedTable {
     /// Contains an axis variations table.
     avar(Rc<tables::avar::avar>),
+    /// Contains a compact font format table.
+    CFF(Rc<tables::CFF::CFF>),
+    /// Contains a compact font format version 2 table.
+    CFF2(Rc<tables::CFF2::CFF2>),
     /// Contains a character to glyph index mapping table.
     cmap(Rc<tables::cmap::cmap>),
+    /// Contains a CVT variations table.
+    cvar(Rc<tables::cvar::cvar>),
     /// Contains a control value table.
     cvt(Rc<tables::cvt::cvt>),
     /// Contains a font program table.
@@ -99,12 +105,16 @@ pub enum LoadedTable {
     hhea(Rc<tables::hhea::hhea>),
     /// Contains a horizontal metrics table.
     hmtx(Rc<tables::hmtx::hmtx>),
+    /// Contains a horizontal metrics variations table.
+    HVAR(Rc<tables::HVAR::HVAR>),
     /// Contains an index-to-location table.
     loca(Rc<tables::loca::loca>),
     /// Contains a math typesetting table.
     MATH(Rc<tables::MATH::MATH>),
     /// Contains a maximum profile table.
     maxp(Rc<tables::maxp::maxp>),
+    /// Contains a metrics variations table.
+    MVAR(Rc<tables::MVAR::MVAR>),
     /// Contains a naming table.
     name(Rc<tables::name::name>),
     /// Contains an OS/2 and Windows metrics table.
@@ -115,6 +125,12 @@ pub enum LoadedTable {
     prep(Rc<tables::prep::prep>),
     /// Contains a style attributes table.
     STAT(Rc<tables::STAT::STAT>),
+    /// Contains a vertical header table.
+    vhea(Rc<tables::vhea::vhea>),
+    /// Contains a vertical metrics table.
+    vmtx(Rc<tables::vmtx::vmtx>),
+    /// Contains a vertical metrics variations table.
+    VVAR(Rc<tables::VVAR::VVAR>),
     /// Any unknown table.
     Unknown(Rc<[u8]>),
 }
@@ -326,6 +342,8 @@ impl TableSet {
     fn deserialize_table(&self, tag: Tag, data: Rc<[u8]>) -> Result<Table, DeserializationError> {
         let typed_data: LoadedTable = match tag.as_bytes() {
             b"avar" => otspec::de::from_bytes::<tables::avar::avar>(&data)?.into(),
+            b"CFF " => otspec::de::from_bytes::<tables::CFF::CFF>(&data)?.into(),
+            b"CFF2" => otspec::de::from_bytes::<tables::CFF2::CFF2>(&data)?.into(),
             b"cmap" => otspec::de::from_bytes::<tables::cmap::cmap>(&data)?.into(),
             b"cvt " => otspec::de::from_bytes::<tables::cvt::cvt>(&data)?.into(),
             b"fpgm" => otspec::de::from_bytes::<tables::fpgm::fpgm>(&data)?.into(),
@@ -348,13 +366,17 @@ impl TableSet {
             }
             b"head" => otspec::de::from_bytes::<tables::head::head>(&data)?.into(),
             b"hhea" => otspec::de::from_bytes::<tables::hhea::hhea>(&data)?.into(),
+            b"HVAR" => otspec::de::from_bytes::<tables::HVAR::HVAR>(&data)?.into(),
             b"MATH" => otspec::de::from_bytes::<tables::MATH::MATH>(&data)?.into(),
             b"maxp" => otspec::de::from_bytes::<tables::maxp::maxp>(&data)?.into(),
+            b"MVAR" => otspec::de::from_bytes::<tables::MVAR::MVAR>(&data)?.into(),
             b"name" => otspec::de::from_bytes::<tables::name::name>(&data)?.into(),
             b"OS/2" => otspec::de::from_bytes::<tables::os2::os2>(&data)?.into(),
             b"post" => otspec::de::from_bytes::<tables::post::post>(&data)?.into(),
             b"prep" => otspec::de::from_bytes::<tables::prep::prep>(&data)?.into(),
             b"STAT" => otspec::de::from_bytes::<tables::STAT::STAT>(&data)?.into(),
+            b"vhea" => otspec::de::from_bytes::<tables::vhea::vhea>(&data)?.into(),
+            b"VVAR" => otspec::de::from_bytes::<tables::VVAR::VVAR>(&data)?.into(),
             b"hmtx" => {
                 let number_of_hmetrics = self
                     //TODO: dear reviewer: this loads the table if missing. do
@@ -370,6 +392,17 @@ impl TableSet {
                 )?
                 .into()
             }
+            b"vmtx" => {
+                let number_of_vmetrics = self
+                    .vhea()?
+                    .map(|vhea| vhea.numOfLongVerMetrics)
+                    .ok_or_else(|| DeserializationError("deserialize vhea before vmtx".into()))?;
+                tables::vmtx::from_bytes(
+                    &mut ReaderContext::new(data.to_vec()),
+                    number_of_vmetrics,
+                )?
+                .into()
+            }
             b"loca" => {
                 let is_32bit = self
                     .head()?
@@ -395,6 +428,17 @@ impl TableSet {
 
                 tables::gvar::from_bytes(&data, coords_and_ends)?.into()
             }
+            b"cvar" => {
+                let axis_count = self
+                    .fvar()?
+                    .map(|fvar| fvar.axes.len() as u16)
+                    .ok_or_else(|| DeserializationError("deserialize fvar before cvar".into()))?;
+                let cvt_count = self
+                    .cvt()?
+                    .map(|cvt| cvt.0.len() as u16)
+                    .ok_or_else(|| DeserializationError("deserialize cvt before cvar".into()))?;
+                tables::cvar::from_bytes(&data, axis_count, cvt_count)?.into()
+            }
             _ => LoadedTable::Unknown(data.clone()),
         };
 
@@ -414,100 +458,160 @@ impl TableSet {
             })
     }
 
-    pub(crate) fn compile_glyf_loca_maxp(&mut self) {
+    /// Serialize a single table, resolving its dependencies on other tables.
+    ///
+    /// Some tables cannot be compiled in isolation: `loca` is derived from
+    /// the glyph data in `glyf`, `gvar` uses the `glyf` outlines to decide which
+    /// deltas can be inferred, and the layout tables need the glyph count from
+    /// `maxp`. This method looks those up in the rest of the set. Tables which
+    /// have not been modified since they were loaded are returned unchanged.
+    ///
+    /// Note that this does not modify the set, so derived values in other
+    /// tables (such as `head.indexToLocFormat` or `maxp.numGlyphs`) are not
+    /// updated; they are only brought up to date when the whole font is written.
+    ///
+    /// Returns `None` if the table is not present.
+    pub fn serialize_table(&self, tag: Tag) -> Result<Option<Vec<u8>>, SerializationError> {
+        let serialized = match self.is_serialized(tag) {
+            Some(serialized) => serialized,
+            None => return Ok(None),
+        };
+        // loca is only up to date if the glyf table it points into is.
+        let unchanged = serialized
+            && (tag != tables::loca::TAG || self.is_serialized(tables::glyf::TAG).unwrap_or(true));
+
+        let mut data = vec![];
+        if unchanged {
+            self.write_table(tag, &mut data)?;
+            return Ok(Some(data));
+        }
+
+        match tag.as_bytes() {
+            b"glyf" | b"loca" => {
+                let glyf = self.glyf().map_err(ser_error)?.ok_or_else(|| {
+                    SerializationError("loca table cannot be compiled without glyf".into())
+                })?;
+                let (glyf_data, offsets) = glyf.to_bytes_and_offsets()?;
+                data = if tag == tables::glyf::TAG {
+                    glyf_data
+                } else {
+                    tables::loca::compile(&offsets).0
+                };
+            }
+            b"gvar" => {
+                let gvar = self.gvar().map_err(ser_error)?.unwrap();
+                let glyf = self.glyf().map_err(ser_error)?;
+                // An empty gvar has no deltas to take the axis count from
+                let axis_count = match self.fvar().map_err(ser_error)? {
+                    Some(fvar) => fvar.axes.len() as uint16,
+                    None => gvar.axis_count(),
+                };
+                data = gvar.to_bytes_with_axis_count(glyf.as_deref(), axis_count);
+            }
+            b"GPOS" => {
+                let gpos = self.GPOS().map_err(ser_error)?.unwrap();
+                tables::GPOS::to_bytes(&gpos, &mut data, self.num_glyphs_for_layout()?)?;
+            }
+            b"GSUB" => {
+                let gsub = self.GSUB().map_err(ser_error)?.unwrap();
+                tables::GSUB::to_bytes(&gsub, &mut data, self.num_glyphs_for_layout()?)?;
+            }
+            _ => self.write_table(tag, &mut data)?,
+        }
+        Ok(Some(data))
+    }
+
+    fn num_glyphs_for_layout(&self) -> Result<u16, SerializationError> {
+        self.maxp()
+            .map_err(ser_error)?
+            .map(|maxp| maxp.num_glyphs())
+            .ok_or_else(|| SerializationError("layout tables require a maxp table".into()))
+    }
+
+    pub(crate) fn compile_glyf_loca_maxp(&mut self) -> Result<(), SerializationError> {
         // leave early if we have no work to do.
         if self.is_serialized(tables::glyf::TAG).unwrap_or(true)
             && self.is_serialized(tables::loca::TAG).unwrap_or(true)
             && self.is_serialized(tables::maxp::TAG).unwrap_or(true)
         {
-            return;
+            return Ok(());
         }
-        let glyf = match self.glyf().unwrap() {
+        let glyf = match self.glyf().map_err(ser_error)? {
             Some(table) => table,
             None => {
                 log::warn!("No glyf table");
-                return;
+                return Ok(());
             }
         };
         let glyf_count = glyf.glyphs.len();
-        let mut glyf_output: Vec<u8> = vec![];
-        let mut loca_indices: Vec<u32> = vec![];
-
-        for g in &glyf.glyphs {
-            let cur_len: u32 = glyf_output.len().try_into().unwrap();
-            loca_indices.push(cur_len);
-            if g.is_empty() {
-                continue;
-            }
-            glyf_output.extend(otspec::ser::to_bytes(&g).unwrap());
-            // Add multiple-of-four padding
-            while glyf_output.len() % 4 != 0 {
-                glyf_output.push(0);
-            }
-        }
-        if glyf_output.is_empty() {
-            // Sad special case
-            glyf_output.push(0);
-        }
-        loca_indices.push(glyf_output.len().try_into().unwrap());
-        let loca_is32bit = u16::try_from(glyf_output.len()).is_err();
-
-        let loca_data = if loca_is32bit {
-            otspec::ser::to_bytes(&loca_indices).unwrap()
-        } else {
-            let mut data = Vec::with_capacity(loca_indices.len() * 2);
-            loca_indices
-                .iter()
-                .map(|x| ((*x / 2) as u16))
-                .for_each(|x| x.to_bytes(&mut data).unwrap());
-            data
-        };
+        let (glyf_output, loca_indices) = glyf.to_bytes_and_offsets()?;
+        let (loca_data, loca_is32bit) = tables::loca::compile(&loca_indices);
 
         self.insert_raw(tables::glyf::TAG, glyf_output);
         self.insert_raw(tables::loca::TAG, loca_data);
 
-        let mut maxp = self.maxp().unwrap().unwrap();
-        maxp.set_num_glyphs(glyf_count.try_into().unwrap());
+        if let Some(mut maxp) = self.maxp().map_err(ser_error)? {
+            maxp.set_num_glyphs(glyf_count.try_into().map_err(|_| {
+                SerializationError(format!("Too many glyphs ({}) for maxp", glyf_count))
+            })?);
+            self.insert(maxp);
+        }
 
-        let mut head = self.head().unwrap().unwrap();
-        head.indexToLocFormat = if loca_is32bit { 1 } else { 0 };
-        self.insert(head);
+        if let Some(mut head) = self.head().map_err(ser_error)? {
+            head.indexToLocFormat = if loca_is32bit { 1 } else { 0 };
+            self.insert(head);
+        }
+        Ok(())
+    }
 
-        if let Some(hmetric_count) = self.hmtx().unwrap().map(|t| t.number_of_hmetrics()) {
-            if let Some(mut hhea) = self.hhea().unwrap() {
+    pub(crate) fn compile_hhea(&mut self) -> Result<(), SerializationError> {
+        // An unchanged hmtx is written as it was read, so hhea still matches it.
+        if self.is_serialized(tables::hmtx::TAG).unwrap_or(true) {
+            return Ok(());
+        }
+        let hmetric_count = match self.hmtx().map_err(ser_error)? {
+            Some(hmtx) => hmtx.number_of_hmetrics(),
+            None => return Ok(()),
+        };
+        if let Some(mut hhea) = self.hhea().map_err(ser_error)? {
+            if hhea.numberOfHMetrics != hmetric_count {
                 hhea.numberOfHMetrics = hmetric_count;
                 self.insert(hhea);
             }
         }
+        Ok(())
     }
 
-    pub(crate) fn compile_gsub_gpos(&mut self) {
-        let num_glyphs = self.maxp().unwrap().unwrap().num_glyphs();
-        if !self.is_serialized(tables::GPOS::TAG).unwrap_or(true) {
-            if let Some(gpos) = self.GPOS().unwrap() {
-                let mut gpos_data = vec![];
-                if tables::GPOS::to_bytes(&gpos, &mut gpos_data, num_glyphs).is_err() {
-                    log::error!("GPOS table overflow");
-                }
-                self.insert_raw(tables::GPOS::TAG, gpos_data)
+    pub(crate) fn compile_vhea(&mut self) -> Result<(), SerializationError> {
+        if self.is_serialized(tables::vmtx::TAG).unwrap_or(true) {
+            return Ok(());
+        }
+        let vmetric_count = match self.vmtx().map_err(ser_error)? {
+            Some(vmtx) => vmtx.number_of_vmetrics(),
+            None => return Ok(()),
+        };
+        if let Some(mut vhea) = self.vhea().map_err(ser_error)? {
+            if vhea.numOfLongVerMetrics != vmetric_count {
+                vhea.numOfLongVerMetrics = vmetric_count;
+                self.insert(vhea);
             }
         }
-        if !self.is_serialized(tables::GSUB::TAG).unwrap_or(true) {
-            if let Some(gsub) = self.GSUB().unwrap() {
-                let mut gsub_data = vec![];
-                if tables::GSUB::to_bytes(&gsub, &mut gsub_data, num_glyphs).is_err() {
-                    log::error!("GSUB table overflow");
-                }
-                self.insert_raw(tables::GSUB::TAG, gsub_data)
+        Ok(())
+    }
+
+    pub(crate) fn compile_gsub_gpos(&mut self) -> Result<(), SerializationError> {
+        for tag in [tables::GPOS::TAG, tables::GSUB::TAG] {
+            if self.is_serialized(tag).unwrap_or(true) {
+                continue;
+            }
+            if let Some(data) = self.serialize_table(tag)? {
+                self.insert_raw(tag, data)
             }
         }
+        Ok(())
     }
 
-    pub(crate) fn write_table(
-        &self,
-        tag: Tag,
-        buffer: &mut Vec<u8>,
-    ) -> Result<(), SerializationError> {
+    fn write_table(&self, tag: Tag, buffer: &mut Vec<u8>) -> Result<(), SerializationError> {
         let table = match self.tables.get(&tag) {
             Some(table) => table,
             None => return Ok(()),
@@ -523,6 +627,10 @@ impl TableSet {
     }
 }
 
+fn ser_error(e: DeserializationError) -> SerializationError {
+    SerializationError(format!("while loading dependent table: {}", e.0))
+}
+
 impl LazyItem {
     fn loaded(&self) -> Option<Table> {
         match self {
@@ -612,12 +720,18 @@ macro_rules! table_boilerplate {
     };
 }
 
+table_boilerplate!(tables::CFF::CFF, CFF);
+table_boilerplate!(tables::CFF2::CFF2, CFF2);
 table_boilerplate!(tables::GDEF::GDEF, GDEF);
 table_boilerplate!(tables::GPOS::GPOS, GPOS);
 table_boilerplate!(tables::GSUB::GSUB, GSUB);
+table_boilerplate!(tables::HVAR::HVAR, HVAR);
+table_boilerplate!(tables::MVAR::MVAR, MVAR);
 table_boilerplate!(tables::STAT::STAT, STAT);
+table_boilerplate!(tables::VVAR::VVAR, VVAR);
 table_boilerplate!(tables::avar::avar, avar);
 table_boilerplate!(tables::cmap::cmap, cmap);
+table_boilerplate!(tables::cvar::cvar, cvar);
 table_boilerplate!(tables::cvt::cvt, cvt);
 table_boilerplate!(tables::fpgm::fpgm, fpgm);
 table_boilerplate!(tables::fvar::fvar, fvar);
@@ -633,34 +747,54 @@ table_boilerplate!(tables::name::name, name);
 table_boilerplate!(tables::os2::os2, os2);
 table_boilerplate!(tables::post::post, post);
 table_boilerplate!(tables::prep::prep, prep);
+table_boilerplate!(tables::vhea::vhea, vhea);
+table_boilerplate!(tables::vmtx::vmtx, vmtx);
 table_boilerplate!(tables::MATH::MATH, MATH);
 
+/// Serializes a table on its own.
+///
+/// Tables which depend on other tables in the font are written as well as can
+/// be done in isolation: layout tables are written without reference to the
+/// glyph count, and `gvar` without optimizing inferrable deltas. The `loca`
+/// table cannot be written without its `glyf` table, and returns an error; use
+/// [`TableSet::serialize_table`] to compile tables with their dependencies.
 impl Serialize for LoadedTable {
     fn to_bytes(&self, data: &mut Vec<u8>) -> Result<(), otspec::SerializationError> {
+        // The maximum glyph ID is only consulted when interpreting class 0 of
+        // class-based subtables on reading, so any value will do for writing.
+        let max_glyph_id = GlyphID::MAX;
         match self {
             LoadedTable::Unknown(expr) => expr.to_bytes(data),
             LoadedTable::avar(expr) => expr.to_bytes(data),
+            LoadedTable::CFF(expr) => expr.to_bytes(data),
+            LoadedTable::CFF2(expr) => expr.to_bytes(data),
             LoadedTable::cmap(expr) => expr.to_bytes(data),
+            LoadedTable::cvar(expr) => expr.to_bytes(data),
             LoadedTable::cvt(expr) => expr.to_bytes(data),
             LoadedTable::fpgm(expr) => expr.to_bytes(data),
             LoadedTable::fvar(expr) => expr.to_bytes(data),
             LoadedTable::gasp(expr) => expr.to_bytes(data),
             LoadedTable::GDEF(expr) => expr.to_bytes(data),
-            LoadedTable::GPOS(_) => unimplemented!(),
-            LoadedTable::GSUB(_) => unimplemented!(),
-            LoadedTable::gvar(_) => unimplemented!(),
+            LoadedTable::GPOS(expr) => tables::GPOS::to_bytes(expr, data, max_glyph_id),
+            LoadedTable::GSUB(expr) => tables::GSUB::to_bytes(expr, data, max_glyph_id),
+            LoadedTable::gvar(expr) => Serialize::to_bytes(expr.as_ref(), data),
             LoadedTable::head(expr) => expr.to_bytes(data),
             LoadedTable::hhea(expr) => expr.to_bytes(data),
-            LoadedTable::hmtx(_) => unimplemented!(),
-            LoadedTable::glyf(_) => unimplemented!(),
-            LoadedTable::loca(_) => unimplemented!(),
+            LoadedTable::hmtx(expr) => Serialize::to_bytes(expr.as_ref(), data),
+            LoadedTable::HVAR(expr) => expr.to_bytes(data),
+            LoadedTable::glyf(expr) => expr.to_bytes(data),
+            LoadedTable::loca(expr) => expr.to_bytes(data),
             LoadedTable::maxp(expr) => expr.to_bytes(data),
-            LoadedTable::MATH(_) => unimplemented!(),
+            LoadedTable::MVAR(expr) => expr.to_bytes(data),
+            LoadedTable::MATH(expr) => expr.to_bytes(data),
             LoadedTable::name(expr) => expr.to_bytes(data),
             LoadedTable::os2(expr) => expr.to_bytes(data),
             LoadedTable::post(expr) => expr.to_bytes(data),
             LoadedTable::prep(expr) => expr.to_bytes(data),
             LoadedTable::STAT(expr) => expr.to_bytes(data),
+            LoadedTable::vhea(expr) => expr.to_bytes(data),
+            LoadedTable::vmtx(expr) => Serialize::to_bytes(expr.as_ref(), data),
+            LoadedTable::VVAR(expr) => expr.to_bytes(data),
         }
     }
 }
@@ -1,3 +1,9 @@
+/// The `CFF ` (Compact Font Format) table
+#[allow(non_snake_case)]
+pub mod CFF;
+/// The `CFF2` (Compact Font Format version 2) table
+#[allow(non_snake_case)]
+pub mod CFF2;
 /// The `GDEF` (Glyph definition) table
 #[allow(non_snake_case)]
 pub mod GDEF;
@@ -7,16 +13,27 @@ pub mod GPOS;
 /// The `GSUB` (Glyph substitution) table
 #[allow(non_snake_case)]
 pub mod GSUB;
+/// The `HVAR` (Horizontal metrics variations) table
+#[allow(non_snake_case)]
+pub mod HVAR;
 /// The `MATH` (Mathematical typesetting) table
 #[allow(non_snake_case)]
 pub mod MATH;
+/// The `MVAR` (Metrics variations) table
+#[allow(non_snake_case)]
+pub mod MVAR;
 /// The `STAT` (Style attributes) table
 #[allow(non_snake_case)]
 pub mod STAT;
+/// The `VVAR` (Vertical metrics variations) table
+#[allow(non_snake_case)]
+pub mod VVAR;
 /// The `avar` (Axis variations) table
 pub mod avar;
 /// The `cmap` (Character To Glyph Index Mapping) table
 pub mod cmap;
+/// The `cvar` (CVT variations) table
+pub mod cvar;
 /// The `cvt ` (Control Value) table
 pub mod cvt;
 /// The `fpgm` (Font program) table
@@ -47,6 +64,10 @@ pub mod os2;
 pub mod post;
 /// The `prep` (Control Value Program) table
 pub mod prep;
+/// The `vhea` (Vertical header) table
+pub mod vhea;
+/// The `vmtx` (Vertical metrics) table
+pub mod vmtx;
 
 #[macro_export]
 /// A macro that allows a high-level table structure to delegate serialization and
@@ -0,0 +1,728 @@
+use otspec::types::*;
+use otspec::{DeserializationError, Deserialize, ReaderContext, SerializationError, Serialize};
+
+/// Charsets, mapping glyph IDs to names
+mod charset;
+/// The Type 2 charstring interpreter
+pub mod charstring;
+/// DICT structures and operators
+pub mod dict;
+/// Encodings, mapping character codes to glyphs
+mod encoding;
+/// FDSelect structures for CID-keyed fonts
+pub(crate) mod fdselect;
+/// INDEX structures
+pub(crate) mod index;
+/// Predefined strings, charsets and encodings
+mod standard;
+/// Factoring repeated charstring sequences into subroutines
+pub(crate) mod subroutinizer;
+
+pub use charset::Charset;
+pub use charstring::Outline;
+pub use dict::{operators, Dict, Operand};
+pub use encoding::Encoding;
+pub use standard::STANDARD_STRINGS;
+
+use charstring::{Context, Seac, Token, MAX_SUBR_NESTING};
+use fdselect::{read_fd_select, write_fd_select};
+use index::{read_index, write_index};
+use std::convert::TryInto;
+
+/// The 'CFF ' OpenType tag.
+pub const TAG: Tag = crate::tag!("CFF ");
+
+/// A Private DICT, holding hinting information and the local subroutines.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PrivateDict {
+    /// The Private DICT, excluding the `Subrs` operator.
+    pub dict: Dict,
+    /// The local subroutines.
+    pub subrs: Vec<Vec<u8>>,
+}
+
+impl PrivateDict {
+    /// Reads a Private DICT and its subroutines. CFF2 subroutines are stored
+    /// in an INDEX with a 32-bit count.
+    pub(crate) fn read(
+        data: &[u8],
+        size: usize,
+        offset: usize,
+        cff2: bool,
+    ) -> Result<Self, DeserializationError> {
+        let dict_data = data
+            .get(offset..offset + size)
+            .ok_or_else(|| DeserializationError("Private DICT out of range".to_string()))?;
+        let mut dict = Dict::from_bytes(dict_data)?;
+        let subrs = match dict.remove(operators::SUBRS) {
+            Some(operands) => {
+                let subrs_offset = operands.first().map_or(0, |o| o.as_f64() as usize);
+                if cff2 {
+                    index::read_index2(data, offset + subrs_offset)?.0
+                } else {
+                    read_index(data, offset + subrs_offset)?.0
+                }
+            }
+            None => vec![],
+        };
+        Ok(PrivateDict { dict, subrs })
+    }
+
+    /// Serializes the Private DICT followed by its subroutines, returning the
+    /// combined data and the size of the DICT alone.
+    pub(crate) fn to_bytes(&self, cff2: bool) -> (Vec<u8>, usize) {
+        let mut out = self.dict.to_bytes();
+        if !self.subrs.is_empty() {
+            // The Subrs offset is relative to the start of the Private DICT,
+            // and the subroutines follow immediately after it.
+            let size = out.len() + 6;
+            dict::encode_offset(size as i32, &mut out);
+            dict::encode_operator(operators::SUBRS, &mut out);
+        }
+        let size = out.len();
+        if cff2 {
+            out.extend(index::write_index2(&self.subrs));
+        } else {
+            out.extend(index::write_index(&self.subrs));
+        }
+        (out, size)
+    }
+
+    /// The width of glyphs which do not specify a width.
+    pub fn default_width_x(&self) -> f64 {
+        self.dict.get_f64(operators::DEFAULT_WIDTH_X).unwrap_or(0.0)
+    }
+
+    /// The value added to widths specified in charstrings.
+    pub fn nominal_width_x(&self) -> f64 {
+        self.dict.get_f64(operators::NOMINAL_WIDTH_X).unwrap_or(0.0)
+    }
+}
+
+/// A Font DICT in a CID-keyed font's FDArray.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FontDict {
+    /// The Font DICT, excluding the `Private` operator.
+    pub dict: Dict,
+    /// The Private DICT used by glyphs which select this Font DICT.
+    pub private: PrivateDict,
+}
+
+/// The Compact Font Format table
+///
+/// Although the format allows several fonts in one table, OpenType requires
+/// exactly one, so this represents a single font. Operators in the Top DICT
+/// which refer to other structures (`charset`, `Encoding`, `CharStrings`,
+/// `Private`, `FDArray` and `FDSelect`) are removed when the table is read
+/// and regenerated when it is written.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CFF {
+    /// The PostScript name of the font.
+    pub name: String,
+    /// The Top DICT.
+    pub top_dict: Dict,
+    /// Strings other than the standard strings, with SIDs starting at 391.
+    pub strings: Vec<String>,
+    /// Global subroutines, shared by all glyphs.
+    pub global_subrs: Vec<Vec<u8>>,
+    /// The charstring of each glyph.
+    pub charstrings: Vec<Vec<u8>>,
+    /// The names (or, in CID-keyed fonts, CIDs) of the glyphs.
+    pub charset: Charset,
+    /// The encoding, for fonts which are not CID-keyed.
+    pub encoding: Option<Encoding>,
+    /// The Private DICT, for fonts which are not CID-keyed.
+    pub private: Option<PrivateDict>,
+    /// The Font DICTs of a CID-keyed font.
+    pub fd_array: Vec<FontDict>,
+    /// The index into `fd_array` for each glyph of a CID-keyed font.
+    pub fd_select: Vec<u16>,
+}
+
+impl CFF {
+    /// Returns `true` if this is a CID-keyed font.
+    pub fn is_cid(&self) -> bool {
+        self.top_dict.contains(operators::ROS)
+    }
+
+    /// The number of glyphs in the font.
+    pub fn num_glyphs(&self) -> usize {
+        self.charstrings.len()
+    }
+
+    /// Returns the string with the given string ID.
+    pub fn string(&self, sid: u16) -> Option<&str> {
+        let sid = sid as usize;
+        if sid < STANDARD_STRINGS.len() {
+            Some(STANDARD_STRINGS[sid])
+        } else {
+            self.strings
+                .get(sid - STANDARD_STRINGS.len())
+                .map(|s| s.as_str())
+        }
+    }
+
+    /// Returns the string ID for a string, adding it to the string INDEX if
+    /// it is not already present.
+    pub fn add_string(&mut self, s: &str) -> u16 {
+        if let Some(sid) = STANDARD_STRINGS.iter().position(|&x| x == s) {
+            return sid as u16;
+        }
+        let index = self.strings.iter().position(|x| x == s).unwrap_or_else(|| {
+            self.strings.push(s.to_string());
+            self.strings.len() - 1
+        });
+        (index + STANDARD_STRINGS.len()) as u16
+    }
+
+    /// The name of a glyph. Glyphs in CID-keyed fonts are named after their
+    /// CID, as `cid01234`.
+    pub fn glyph_name(&self, gid: usize) -> Option<String> {
+        let sid = self.charset.sid(gid)?;
+        if self.is_cid() {
+            Some(format!("cid{:05}", sid))
+        } else {
+            self.string(sid).map(|s| s.to_string())
+        }
+    }
+
+    /// The names of all glyphs in the font.
+    pub fn glyph_names(&self) -> Vec<String> {
+        (0..self.num_glyphs())
+            .map(|gid| {
+                self.glyph_name(gid)
+                    .unwrap_or_else(|| format!("glyph{:05}", gid))
+            })
+            .collect()
+    }
+
+    /// The Private DICT which applies to a glyph.
+    pub fn private_dict(&self, gid: usize) -> Option<&PrivateDict> {
+        if self.is_cid() {
+            let fd = *self.fd_select.get(gid)? as usize;
+            self.fd_array.get(fd).map(|fd| &fd.private)
+        } else {
+            self.private.as_ref()
+        }
+    }
+
+    /// The font matrix, which maps glyph space to text space.
+    pub fn font_matrix(&self) -> [f64; 6] {
+        font_matrix(&self.top_dict)
+    }
+
+    fn context(&self, gid: usize) -> Context<'_> {
+        let private = self.private_dict(gid);
+        Context {
+            global_subrs: &self.global_subrs,
+            local_subrs: private.map_or(&[], |p| &p.subrs),
+            default_width_x: private.map_or(0.0, |p| p.default_width_x()),
+            nominal_width_x: private.map_or(0.0, |p| p.nominal_width_x()),
+            variations: None,
+        }
+    }
+
+    fn charstring(&self, gid: usize) -> Result<&[u8], DeserializationError> {
+        self.charstrings
+            .get(gid)
+            .map(|c| c.as_slice())
+            .ok_or_else(|| DeserializationError(format!("Glyph {} not found", gid)))
+    }
+
+    fn execute(&self, gid: usize) -> Result<(Outline, Option<Seac>), DeserializationError> {
+        charstring::execute(self.charstring(gid)?, self.context(gid))
+    }
+
+    /// Rewrites every charstring so that it makes no subroutine calls, and
+    /// removes all subroutines.
+    pub fn desubroutinize(&mut self) -> Result<(), DeserializationError> {
+        let glyphs = self.desubroutinized_glyphs()?;
+        self.charstrings = glyphs.iter().map(|g| charstring::encode(g)).collect();
+        self.global_subrs.clear();
+        for private in self.private.iter_mut() {
+            private.subrs.clear();
+        }
+        for fd in self.fd_array.iter_mut() {
+            fd.private.subrs.clear();
+        }
+        Ok(())
+    }
+
+    fn desubroutinized_glyphs(&self) -> Result<Vec<Vec<Token>>, DeserializationError> {
+        (0..self.num_glyphs())
+            .map(|gid| charstring::desubroutinize(self.charstring(gid)?, self.context(gid)))
+            .collect()
+    }
+
+    /// Rewrites the charstrings so that repeated sequences of operators are
+    /// shared in subroutines, replacing any existing subroutines.
+    ///
+    /// Subroutines used by glyphs in more than one Font DICT of a CID-keyed
+    /// font become global subroutines, and the rest become local
+    /// subroutines of their Font DICT.
+    pub fn subroutinize(&mut self) -> Result<(), DeserializationError> {
+        let glyphs = self.desubroutinized_glyphs()?;
+        let (fd_select, fd_count) = if self.is_cid() {
+            let fds = self.fd_select.iter().map(|&fd| fd as usize).collect();
+            (fds, self.fd_array.len())
+        } else {
+            (vec![0; glyphs.len()], 1)
+        };
+        let result =
+            subroutinizer::subroutinize(&glyphs, &fd_select, fd_count, false, MAX_SUBR_NESTING);
+        self.charstrings = result.charstrings;
+        self.global_subrs = result.global_subrs;
+        let mut local_subrs = result.local_subrs.into_iter();
+        if self.is_cid() {
+            for (fd, subrs) in self.fd_array.iter_mut().zip(local_subrs) {
+                fd.private.subrs = subrs;
+            }
+        } else {
+            self.private.get_or_insert_with(PrivateDict::default).subrs =
+                local_subrs.next().unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    /// Executes the charstring of a glyph, returning its outline, advance
+    /// width and hints.
+    pub fn outline(&self, gid: usize) -> Result<Outline, DeserializationError> {
+        let (mut outline, seac) = self.execute(gid)?;
+        if let Some(seac) = seac {
+            // Build the accented glyph from the base and accent glyphs. These
+            // may not themselves be accented glyphs.
+            let component = |code: u8| {
+                let sid = standard::STANDARD_ENCODING[code as usize];
+                let gid = self.charset.gid(sid).ok_or_else(|| {
+                    DeserializationError(format!("seac component {} not found", code))
+                })?;
+                self.execute(gid).map(|(outline, _)| outline.path)
+            };
+            let base = component(seac.base)?;
+            let accent = component(seac.accent)?;
+            outline.path.extend(base);
+            outline
+                .path
+                .extend(kurbo::Affine::translate((seac.adx, seac.ady)) * accent);
+        }
+        Ok(outline)
+    }
+}
+
+impl Deserialize for CFF {
+    fn from_bytes(c: &mut ReaderContext) -> Result<Self, DeserializationError> {
+        let data = &c.input[c.ptr..];
+        if data.len() < 4 {
+            return Err(DeserializationError("CFF header too short".to_string()));
+        }
+        if data[0] != 1 {
+            return Err(DeserializationError(format!(
+                "Unknown CFF major version {}",
+                data[0]
+            )));
+        }
+        let header_size = data[2] as usize;
+        let (names, pos) = read_index(data, header_size)?;
+        let (top_dicts, pos) = read_index(data, pos)?;
+        let (strings, pos) = read_index(data, pos)?;
+        let (global_subrs, _) = read_index(data, pos)?;
+        let name = names
+            .first()
+            .map(|n| String::from_utf8_lossy(n).to_string())
+            .ok_or_else(|| DeserializationError("No fonts in CFF table".to_string()))?;
+        let strings = strings
+            .iter()
+            .map(|s| String::from_utf8_lossy(s).to_string())
+            .collect();
+        let mut top_dict = Dict::from_bytes(
+            top_dicts
+                .first()
+                .ok_or_else(|| DeserializationError("No Top DICT in CFF table".to_string()))?,
+        )?;
+
+        let offset_of = |dict: &mut Dict, op: u16| {
+            dict.remove(op)
+                .and_then(|operands| operands.last().map(|o| o.as_f64() as usize))
+        };
+        let charstrings_offset = offset_of(&mut top_dict, operators::CHAR_STRINGS)
+            .ok_or_else(|| DeserializationError("No CharStrings in CFF table".to_string()))?;
+        let charstrings = read_index(data, charstrings_offset)?.0;
+        let num_glyphs = charstrings.len();
+        let charset_offset = offset_of(&mut top_dict, operators::CHARSET).unwrap_or(0);
+        let charset = Charset::read(data, charset_offset, num_glyphs)?;
+        let encoding_offset = offset_of(&mut top_dict, operators::ENCODING);
+        let private_operands = top_dict.remove(operators::PRIVATE);
+        let fd_array_offset = offset_of(&mut top_dict, operators::FD_ARRAY);
+        let fd_select_offset = offset_of(&mut top_dict, operators::FD_SELECT);
+
+        let read_private = |operands: Option<Vec<Operand>>| match operands.as_deref() {
+            Some([size, offset]) => PrivateDict::read(
+                data,
+                size.as_f64() as usize,
+                offset.as_f64() as usize,
+                false,
+            ),
+            _ => Err(DeserializationError(
+                "Bad Private DICT operands".to_string(),
+            )),
+        };
+
+        let mut encoding = None;
+        let mut private = None;
+        let mut fd_array = vec![];
+        let mut fd_select = vec![];
+        if top_dict.contains(operators::ROS) {
+            let fd_array_offset = fd_array_offset
+                .ok_or_else(|| DeserializationError("CID font has no FDArray".to_string()))?;
+            for font_dict in read_index(data, fd_array_offset)?.0 {
+                let mut dict = Dict::from_bytes(&font_dict)?;
+                let private = read_private(dict.remove(operators::PRIVATE))?;
+                fd_array.push(FontDict { dict, private });
+            }
+            fd_select = match fd_select_offset {
+                Some(offset) => read_fd_select(data, offset, num_glyphs)?,
+                None => vec![0; num_glyphs],
+            };
+        } else {
+            encoding = Some(Encoding::read(data, encoding_offset.unwrap_or(0))?);
+            private = Some(read_private(private_operands)?);
+        }
+        c.ptr = c.input.len();
+        Ok(CFF {
+            name,
+            top_dict,
+            strings,
+            global_subrs,
+            charstrings,
+            charset,
+            encoding,
+            private,
+            fd_array,
+            fd_select,
+        })
+    }
+}
+
+/// Reads the font matrix from a Top DICT, or returns the default matrix.
+pub(crate) fn font_matrix(top_dict: &Dict) -> [f64; 6] {
+    match top_dict.get(operators::FONT_MATRIX) {
+        Some(operands) if operands.len() == 6 => {
+            let mut matrix = [0.0; 6];
+            for (m, o) in matrix.iter_mut().zip(operands) {
+                *m = o.as_f64();
+            }
+            matrix
+        }
+        _ => [0.001, 0.0, 0.0, 0.001, 0.0, 0.0],
+    }
+}
+
+/// Appends an operator whose operands are offsets (or sizes) to a DICT's
+/// binary data, using the fixed-size integer encoding.
+pub(crate) fn put_offsets(
+    out: &mut Vec<u8>,
+    op: u16,
+    operands: &[usize],
+) -> Result<(), SerializationError> {
+    for &operand in operands {
+        let operand: i32 = operand
+            .try_into()
+            .map_err(|_| SerializationError("CFF table too large".to_string()))?;
+        dict::encode_offset(operand, out);
+    }
+    dict::encode_operator(op, out);
+    Ok(())
+}
+
+impl Serialize for CFF {
+    fn to_bytes(&self, data: &mut Vec<u8>) -> Result<(), SerializationError> {
+        let is_cid = self.is_cid();
+        if is_cid && self.fd_select.len() != self.charstrings.len() {
+            return Err(SerializationError(
+                "FDSelect does not cover every glyph".to_string(),
+            ));
+        }
+        let charset = self.charset.to_bytes();
+        let encoding = match &self.encoding {
+            Some(encoding) if !is_cid => encoding.to_bytes(),
+            _ => vec![],
+        };
+        let fd_select = if is_cid {
+            write_fd_select(&self.fd_select)
+        } else {
+            vec![]
+        };
+        let charstrings = write_index(&self.charstrings);
+        let privates: Vec<(Vec<u8>, usize)> = if is_cid {
+            self.fd_array
+                .iter()
+                .map(|fd| fd.private.to_bytes(false))
+                .collect()
+        } else {
+            vec![self.private.clone().unwrap_or_default().to_bytes(false)]
+        };
+
+        // The ROS operator must come first in the Top DICT of a CID font.
+        let mut ordered_top_dict = self.top_dict.clone();
+        if let Some(ros) = ordered_top_dict.remove(operators::ROS) {
+            ordered_top_dict.entries.insert(0, (operators::ROS, ros));
+        }
+        // Offsets are written with a fixed size, so we can lay out the DICTs
+        // before we know where everything will go.
+        let top_dict = |offsets: &[usize]| -> Result<Vec<u8>, SerializationError> {
+            let mut out = ordered_top_dict.to_bytes();
+            put_offsets(&mut out, operators::CHARSET, &offsets[0..1])?;
+            put_offsets(&mut out, operators::CHAR_STRINGS, &offsets[1..2])?;
+            if is_cid {
+                put_offsets(&mut out, operators::FD_ARRAY, &offsets[2..3])?;
+                put_offsets(&mut out, operators::FD_SELECT, &offsets[3..4])?;
+            } else {
+                put_offsets(&mut out, operators::ENCODING, &offsets[2..3])?;
+                put_offsets(&mut out, operators::PRIVATE, &[privates[0].1, offsets[3]])?;
+            }
+            Ok(out)
+        };
+        let fd_array = |private_offsets: &[usize]| -> Result<Vec<u8>, SerializationError> {
+            let dicts = self
+                .fd_array
+                .iter()
+                .zip(privates.iter().zip(private_offsets))
+                .map(|(fd, ((_, size), &offset))| {
+                    let mut out = fd.dict.to_bytes();
+                    put_offsets(&mut out, operators::PRIVATE, &[*size, offset])?;
+                    Ok(out)
+                })
+                .collect::<Result<Vec<Vec<u8>>, SerializationError>>()?;
+            Ok(if is_cid { write_index(&dicts) } else { vec![] })
+        };
+
+        let header = [1, 0, 4, 4];
+        let names = write_index(&[self.name.as_bytes()]);
+        let strings: Vec<&[u8]> = self.strings.iter().map(|s| s.as_bytes()).collect();
+        let strings = write_index(&strings);
+        let global_subrs = write_index(&self.global_subrs);
+        let top_dict_size = write_index(&[top_dict(&[0; 4])?]).len();
+        let fd_array_size = fd_array(&vec![0; privates.len()])?.len();
+
+        let mut pos =
+            header.len() + names.len() + top_dict_size + strings.len() + global_subrs.len();
+        let charset_offset = match self.charset.predefined_offset() {
+            Some(offset) => offset as usize,
+            None => pos,
+        };
+        pos += charset.len();
+        let encoding_offset = match &self.encoding {
+            None => 0,
+            Some(encoding) => encoding.predefined_offset().map_or(pos, |o| o as usize),
+        };
+        pos += encoding.len();
+        let fd_select_offset = pos;
+        pos += fd_select.len();
+        let charstrings_offset = pos;
+        pos += charstrings.len();
+        let fd_array_offset = pos;
+        pos += fd_array_size;
+        let mut private_offsets = vec![];
+        for (private, _) in &privates {
+            private_offsets.push(pos);
+            pos += private.len();
+        }
+
+        let top_dict = if is_cid {
+            top_dict(&[
+                charset_offset,
+                charstrings_offset,
+                fd_array_offset,
+                fd_select_offset,
+            ])?
+        } else {
+            top_dict(&[
+                charset_offset,
+                charstrings_offset,
+                encoding_offset,
+                private_offsets[0],
+            ])?
+        };
+
+        data.extend_from_slice(&header);
+        data.extend(names);
+        data.extend(write_index(&[top_dict]));
+        data.extend(strings);
+        data.extend(global_subrs);
+        data.extend(charset);
+        data.extend(encoding);
+        data.extend(fd_select);
+        data.extend(charstrings);
+        data.extend(fd_array(&private_offsets)?);
+        for (private, _) in privates {
+            data.extend(private);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use kurbo::PathEl;
+
+    /// A font with the glyphs .notdef, A, acute, Aacute (a seac of A and
+    /// acute) and square.
+    pub fn test_font() -> CFF {
+        let mut top_dict = Dict::new();
+        top_dict.set(
+            operators::FONT_BBOX,
+            vec![0.into(), 0.into(), 500.into(), 500.into()],
+        );
+        let mut private = PrivateDict::default();
+        private.dict.set(
+            operators::BLUE_VALUES,
+            vec![(-10).into(), 0.into(), 500.into(), 510.into()],
+        );
+        private
+            .dict
+            .set(operators::DEFAULT_WIDTH_X, vec![500.into()]);
+        private
+            .dict
+            .set(operators::NOMINAL_WIDTH_X, vec![600.into()]);
+        // A local subr which draws a 100-unit square
+        private.subrs = vec![vec![239, 6, 239, 7, 39, 6, 11]];
+        let mut font = CFF {
+            name: "TestFont".to_string(),
+            top_dict,
+            strings: vec![],
+            global_subrs: vec![],
+            charstrings: vec![
+                vec![14],
+                // 0 0 rmoveto, callsubr 0, endchar
+                vec![139, 139, 21, 32, 10, 14],
+                // A width of 700: 100 0 0 rmoveto, callsubr 0, endchar
+                vec![239, 139, 139, 21, 32, 10, 14],
+                // 100 200 65 194 endchar: A with acute at (100, 200)
+                vec![239, 247, 92, 204, 247, 86, 14],
+                vec![139, 139, 21, 32, 10, 14],
+            ],
+            charset: Charset::Custom(vec![]),
+            encoding: Some(Encoding::Standard),
+            private: Some(private),
+            fd_array: vec![],
+            fd_select: vec![],
+        };
+        let names = ["A", "acute", "Aacute", "square"];
+        let sids = names.iter().map(|n| font.add_string(n)).collect();
+        font.charset = Charset::Custom(sids);
+        font
+    }
+
+    #[test]
+    fn test_cff_roundtrip() {
+        let mut font = test_font();
+        assert_eq!(font.add_string("Regular"), 388);
+        assert_eq!(font.add_string("square"), 391);
+        assert_eq!(
+            font.glyph_names(),
+            vec![".notdef", "A", "acute", "Aacute", "square"]
+        );
+        let binary = otspec::ser::to_bytes(&font).unwrap();
+        assert_eq!(&binary[0..4], &[1, 0, 4, 4]);
+        let deserialized: CFF = otspec::de::from_bytes(&binary).unwrap();
+        assert_eq!(deserialized, font);
+        assert_eq!(deserialized.strings, vec!["square".to_string()]);
+        // Serializing again gives the same result
+        assert_eq!(otspec::ser::to_bytes(&deserialized).unwrap(), binary);
+    }
+
+    #[test]
+    fn test_cff_outlines() {
+        let font = test_font();
+        let square = font.outline(1).unwrap();
+        assert_eq!(square.width, 500.0);
+        assert_eq!(
+            square.path.elements(),
+            &[
+                PathEl::MoveTo((0.0, 0.0).into()),
+                PathEl::LineTo((100.0, 0.0).into()),
+                PathEl::LineTo((100.0, 100.0).into()),
+                PathEl::LineTo((0.0, 100.0).into()),
+                PathEl::ClosePath,
+            ]
+        );
+        assert_eq!(font.outline(0).unwrap().path.elements(), &[]);
+        assert_eq!(font.outline(2).unwrap().width, 700.0);
+
+        // The seac glyph is built from "A" and "acute"
+        let composite = font.outline(3).unwrap();
+        assert_eq!(composite.width, 500.0);
+        let mut expected = square.path.clone();
+        expected.extend(kurbo::Affine::translate((100.0, 200.0)) * square.path);
+        assert_eq!(composite.path, expected);
+        assert!(font.outline(5).is_err());
+    }
+
+    #[test]
+    fn test_cff_subroutinize() {
+        let mut font = test_font();
+        // Make the glyphs long enough to be worth sharing
+        for gid in &[1, 2, 4] {
+            let charstring = &mut font.charstrings[*gid];
+            let after_moveto = charstring.iter().position(|&b| b == 21).unwrap() + 1;
+            charstring.splice(after_moveto..after_moveto, vec![32, 10, 32, 10, 32, 10]);
+        }
+        let outlines: Vec<Outline> = (0..font.num_glyphs())
+            .map(|gid| font.outline(gid).unwrap())
+            .collect();
+        font.desubroutinize().unwrap();
+        assert!(font.private.as_ref().unwrap().subrs.is_empty());
+        font.subroutinize().unwrap();
+        assert!(!font.private.as_ref().unwrap().subrs.is_empty());
+        let binary = otspec::ser::to_bytes(&font).unwrap();
+        let deserialized: CFF = otspec::de::from_bytes(&binary).unwrap();
+        for (gid, outline) in outlines.iter().enumerate() {
+            assert_eq!(&deserialized.outline(gid).unwrap(), outline);
+        }
+    }
+
+    #[test]
+    fn test_cid_roundtrip() {
+        let mut font = test_font();
+        let registry = font.add_string("Adobe") as i32;
+        let ordering = font.add_string("Identity") as i32;
+        font.top_dict.entries.insert(
+            0,
+            (
+                operators::ROS,
+                vec![registry.into(), ordering.into(), 0.into()],
+            ),
+        );
+        let private = font.private.take().unwrap();
+        let mut other_private = private.clone();
+        other_private.subrs.clear();
+        let mut font_dict = Dict::new();
+        font_dict.set(
+            operators::FONT_NAME,
+            vec![(font.add_string("TestFont-A") as i32).into()],
+        );
+        font.fd_array = vec![
+            FontDict {
+                dict: font_dict.clone(),
+                private,
+            },
+            FontDict {
+                dict: font_dict,
+                private: other_private,
+            },
+        ];
+        font.fd_select = vec![1, 0, 0, 1, 1];
+        font.encoding = None;
+        font.charset = Charset::Custom(vec![1, 2, 3, 1000]);
+
+        let binary = otspec::ser::to_bytes(&font).unwrap();
+        let deserialized: CFF = otspec::de::from_bytes(&binary).unwrap();
+        assert_eq!(deserialized, font);
+        assert!(deserialized.is_cid());
+        assert_eq!(deserialized.glyph_name(4), Some("cid01000".to_string()));
+        assert!(deserialized.private_dict(4).unwrap().subrs.is_empty());
+        assert_eq!(deserialized.outline(2).unwrap().path.elements().len(), 5);
+    }
+}
@@ -0,0 +1,185 @@
+//! Charsets, which map glyph IDs to glyph names (or CIDs).
+use super::standard::{EXPERT_CHARSET, EXPERT_SUBSET_CHARSET};
+use otspec::DeserializationError;
+
+/// A charset, assigning a string ID (or, in CID-keyed fonts, a CID) to each glyph.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Charset {
+    /// The predefined ISOAdobe charset, in which glyph ID and SID are the same.
+    ISOAdobe,
+    /// The predefined Expert charset.
+    Expert,
+    /// The predefined ExpertSubset charset.
+    ExpertSubset,
+    /// A custom charset, holding the SIDs (or CIDs) of every glyph after `.notdef`.
+    Custom(Vec<u16>),
+}
+
+impl Charset {
+    /// The SID (or CID) of the given glyph.
+    pub fn sid(&self, gid: usize) -> Option<u16> {
+        if gid == 0 {
+            return Some(0);
+        }
+        match self {
+            Charset::ISOAdobe if gid <= 228 => Some(gid as u16),
+            Charset::ISOAdobe => None,
+            Charset::Expert => EXPERT_CHARSET.get(gid).copied(),
+            Charset::ExpertSubset => EXPERT_SUBSET_CHARSET.get(gid).copied(),
+            Charset::Custom(sids) => sids.get(gid - 1).copied(),
+        }
+    }
+
+    /// The glyph ID of the glyph with the given SID (or CID).
+    pub fn gid(&self, sid: u16) -> Option<usize> {
+        if sid == 0 {
+            return Some(0);
+        }
+        match self {
+            Charset::ISOAdobe if sid <= 228 => Some(sid as usize),
+            Charset::ISOAdobe => None,
+            Charset::Expert => EXPERT_CHARSET.iter().position(|&s| s == sid),
+            Charset::ExpertSubset => EXPERT_SUBSET_CHARSET.iter().position(|&s| s == sid),
+            Charset::Custom(sids) => sids.iter().position(|&s| s == sid).map(|p| p + 1),
+        }
+    }
+
+    /// The offset used in the Top DICT to refer to a predefined charset.
+    pub(crate) fn predefined_offset(&self) -> Option<i32> {
+        match self {
+            Charset::ISOAdobe => Some(0),
+            Charset::Expert => Some(1),
+            Charset::ExpertSubset => Some(2),
+            Charset::Custom(_) => None,
+        }
+    }
+
+    /// Reads a charset from the given offset, or the predefined charset if
+    /// the offset is 0, 1 or 2.
+    pub(crate) fn read(
+        data: &[u8],
+        offset: usize,
+        num_glyphs: usize,
+    ) -> Result<Charset, DeserializationError> {
+        match offset {
+            0 => return Ok(Charset::ISOAdobe),
+            1 => return Ok(Charset::Expert),
+            2 => return Ok(Charset::ExpertSubset),
+            _ => {}
+        }
+        let eof = || DeserializationError("Charset extends past end of table".to_string());
+        let u16_at = |pos: usize| {
+            data.get(pos..pos + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .ok_or_else(eof)
+        };
+        let format = *data.get(offset).ok_or_else(eof)?;
+        let mut sids = Vec::with_capacity(num_glyphs.saturating_sub(1));
+        let mut pos = offset + 1;
+        match format {
+            0 => {
+                for _ in 1..num_glyphs {
+                    sids.push(u16_at(pos)?);
+                    pos += 2;
+                }
+            }
+            1 | 2 => {
+                while sids.len() + 1 < num_glyphs {
+                    let first = u16_at(pos)?;
+                    let n_left = if format == 1 {
+                        *data.get(pos + 2).ok_or_else(eof)? as u16
+                    } else {
+                        u16_at(pos + 2)?
+                    };
+                    pos += if format == 1 { 3 } else { 4 };
+                    sids.extend((0..=n_left).map(|i| first.wrapping_add(i)));
+                }
+                sids.truncate(num_glyphs.saturating_sub(1));
+            }
+            _ => {
+                return Err(DeserializationError(format!(
+                    "Unknown charset format {}",
+                    format
+                )))
+            }
+        }
+        Ok(Charset::Custom(sids))
+    }
+
+    /// Serializes a custom charset, choosing the most compact format.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let sids = match self {
+            Charset::Custom(sids) => sids,
+            _ => return vec![],
+        };
+        let mut ranges: Vec<(u16, u16)> = vec![];
+        for &sid in sids {
+            match ranges.last_mut() {
+                Some((first, n_left)) if first.wrapping_add(*n_left + 1) == sid => *n_left += 1,
+                _ => ranges.push((sid, 0)),
+            }
+        }
+        let format0_size = 2 * sids.len();
+        let format1_size: usize = ranges
+            .iter()
+            .map(|(_, n)| 3 * (1 + *n as usize / 256))
+            .sum();
+        let format2_size = 4 * ranges.len();
+
+        let mut out = vec![];
+        if format0_size <= format1_size && format0_size <= format2_size {
+            out.push(0);
+            for sid in sids {
+                out.extend_from_slice(&sid.to_be_bytes());
+            }
+        } else if format1_size <= format2_size {
+            out.push(1);
+            for &(first, n_left) in &ranges {
+                let mut start = first;
+                let mut remaining = n_left as u32 + 1;
+                while remaining > 0 {
+                    let count = remaining.min(256);
+                    out.extend_from_slice(&start.to_be_bytes());
+                    out.push((count - 1) as u8);
+                    start = start.wrapping_add(count as u16);
+                    remaining -= count;
+                }
+            }
+        } else {
+            out.push(2);
+            for (first, n_left) in ranges {
+                out.extend_from_slice(&first.to_be_bytes());
+                out.extend_from_slice(&n_left.to_be_bytes());
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_charset_formats() {
+        let scattered = Charset::Custom(vec![5, 9, 2]);
+        let binary = scattered.to_bytes();
+        assert_eq!(binary, vec![0, 0, 5, 0, 9, 0, 2]);
+        assert_eq!(Charset::read(&binary, 0, 4).unwrap(), Charset::ISOAdobe);
+        let mut padded = vec![0, 0, 0];
+        padded.extend(binary);
+        assert_eq!(Charset::read(&padded, 3, 4).unwrap(), scattered);
+
+        let cids = Charset::Custom((1..=300).chain(1000..1002).collect());
+        let binary = cids.to_bytes();
+        assert_eq!(binary, vec![2, 0, 1, 1, 43, 3, 232, 0, 1]);
+        let mut padded = vec![0, 0, 0];
+        padded.extend(binary);
+        assert_eq!(Charset::read(&padded, 3, 303).unwrap(), cids);
+        assert_eq!(cids.gid(1000), Some(301));
+        assert_eq!(cids.sid(302), Some(1001));
+
+        assert_eq!(Charset::Expert.sid(2), Some(229));
+        assert_eq!(Charset::ExpertSubset.gid(346), Some(86));
+    }
+}
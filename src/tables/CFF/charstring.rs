@@ -0,0 +1,1248 @@
+//! A Type 2 charstring interpreter.
+//!
+//! Executing a charstring produces the glyph's outline as a [`kurbo::BezPath`],
+//! along with its advance width and stem hints. CFF2 charstrings, which may
+//! contain `blend` and `vsindex` operators, are executed by providing the
+//! region scalars of a location in the design space.
+use crate::otvar::PinnedRegions;
+use kurbo::{BezPath, PathEl, Point, QuadBez};
+use otspec::DeserializationError;
+use std::convert::TryFrom;
+
+/// The maximum depth of nested subroutine calls.
+pub const MAX_SUBR_NESTING: usize = 10;
+/// The maximum number of values on the argument stack.
+const MAX_STACK: usize = 48;
+/// The maximum number of values on the argument stack in CFF2 charstrings.
+const MAX_STACK_CFF2: usize = 513;
+
+/// Charstring operators
+pub mod operators {
+    #![allow(missing_docs)]
+    pub const HSTEM: u16 = 1;
+    pub const VSTEM: u16 = 3;
+    pub const VMOVETO: u16 = 4;
+    pub const RLINETO: u16 = 5;
+    pub const HLINETO: u16 = 6;
+    pub const VLINETO: u16 = 7;
+    pub const RRCURVETO: u16 = 8;
+    pub const CALLSUBR: u16 = 10;
+    pub const RETURN: u16 = 11;
+    pub const ENDCHAR: u16 = 14;
+    pub const VSINDEX: u16 = 15;
+    pub const BLEND: u16 = 16;
+    pub const HSTEMHM: u16 = 18;
+    pub const HINTMASK: u16 = 19;
+    pub const CNTRMASK: u16 = 20;
+    pub const RMOVETO: u16 = 21;
+    pub const HMOVETO: u16 = 22;
+    pub const VSTEMHM: u16 = 23;
+    pub const RCURVELINE: u16 = 24;
+    pub const RLINECURVE: u16 = 25;
+    pub const VVCURVETO: u16 = 26;
+    pub const HHCURVETO: u16 = 27;
+    pub const CALLGSUBR: u16 = 29;
+    pub const VHCURVETO: u16 = 30;
+    pub const HVCURVETO: u16 = 31;
+    pub const HFLEX: u16 = 0x0c22;
+    pub const FLEX: u16 = 0x0c23;
+    pub const HFLEX1: u16 = 0x0c24;
+    pub const FLEX1: u16 = 0x0c25;
+}
+
+/// The result of executing a charstring.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Outline {
+    /// The outline of the glyph.
+    pub path: BezPath,
+    /// The advance width of the glyph.
+    pub width: f64,
+    /// Horizontal stem hints, as (position, width) pairs.
+    pub hstems: Vec<(f64, f64)>,
+    /// Vertical stem hints, as (position, width) pairs.
+    pub vstems: Vec<(f64, f64)>,
+}
+
+/// The resources a charstring needs from its font in order to be executed.
+#[derive(Debug, Clone, Copy)]
+pub struct Context<'a> {
+    /// The global subroutines of the font.
+    pub global_subrs: &'a [Vec<u8>],
+    /// The local subroutines from the glyph's Private DICT.
+    pub local_subrs: &'a [Vec<u8>],
+    /// The width of glyphs whose charstring does not specify a width.
+    pub default_width_x: f64,
+    /// The value which is added to any width specified in a charstring.
+    pub nominal_width_x: f64,
+    /// The variation data for CFF2 charstrings, or `None` for CFF charstrings.
+    pub variations: Option<Variations<'a>>,
+}
+
+/// The variation data needed to execute CFF2 charstrings.
+#[derive(Debug, Clone, Copy)]
+pub struct Variations<'a> {
+    /// For each item variation data subtable in the font's variation store,
+    /// the scalars of its regions at the location being evaluated.
+    pub region_scalars: &'a [Vec<f32>],
+    /// The item variation data subtable used when a charstring has no
+    /// `vsindex` operator.
+    pub vsindex: usize,
+}
+
+/// Replaces the arguments of a `blend` operator at the top of the stack
+/// with their blended values, given the scalars of the regions of the
+/// current item variation data. Returns `false` if there are too few
+/// arguments.
+pub(crate) fn apply_blend(stack: &mut Vec<f64>, scalars: &[f32]) -> bool {
+    let n = match stack.pop() {
+        Some(n) if n >= 0.0 => n as usize,
+        _ => return false,
+    };
+    let k = scalars.len();
+    let start = match stack.len().checked_sub(n * (k + 1)) {
+        Some(start) => start,
+        None => return false,
+    };
+    let args = stack.split_off(start);
+    let (defaults, deltas) = args.split_at(n);
+    for (i, default) in defaults.iter().enumerate() {
+        let delta: f64 = deltas[i * k..(i + 1) * k]
+            .iter()
+            .zip(scalars)
+            .map(|(d, &s)| d * s as f64)
+            .sum();
+        stack.push(default + delta);
+    }
+    true
+}
+
+/// The arguments of an `endchar` operator used as `seac`, building an accented
+/// glyph from two glyphs in the Standard encoding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Seac {
+    /// The horizontal offset of the accent
+    pub adx: f64,
+    /// The vertical offset of the accent
+    pub ady: f64,
+    /// The Standard encoding code of the base glyph
+    pub base: u8,
+    /// The Standard encoding code of the accent glyph
+    pub accent: u8,
+}
+
+/// Computes the bias which is added to subroutine numbers.
+pub fn subr_bias(count: usize) -> i32 {
+    if count < 1240 {
+        107
+    } else if count < 33900 {
+        1131
+    } else {
+        32768
+    }
+}
+
+struct Interpreter<'a> {
+    context: Context<'a>,
+    stack: Vec<f64>,
+    transient: [f64; 32],
+    outline: Outline,
+    current: Point,
+    open: bool,
+    num_stems: usize,
+    width_parsed: bool,
+    seac: Option<Seac>,
+    random_state: u32,
+    vsindex: usize,
+}
+
+fn error(message: &str) -> DeserializationError {
+    DeserializationError(format!("Bad charstring: {}", message))
+}
+
+/// Decodes the number at position `i` of a charstring, returning its value
+/// and the position after it, or `None` if there is an operator there.
+fn decode_number(code: &[u8], i: usize) -> Result<Option<(f64, usize)>, DeserializationError> {
+    let byte = |i: usize| code.get(i).copied().ok_or_else(|| error("unexpected end"));
+    let b0 = byte(i)?;
+    Ok(match b0 {
+        28 => {
+            let value = i16::from_be_bytes([byte(i + 1)?, byte(i + 2)?]);
+            Some((value as f64, i + 3))
+        }
+        32..=246 => Some((b0 as f64 - 139.0, i + 1)),
+        247..=250 => {
+            let value = (b0 as i32 - 247) * 256 + byte(i + 1)? as i32 + 108;
+            Some((value as f64, i + 2))
+        }
+        251..=254 => {
+            let value = -(b0 as i32 - 251) * 256 - byte(i + 1)? as i32 - 108;
+            Some((value as f64, i + 2))
+        }
+        255 => {
+            let value =
+                i32::from_be_bytes([byte(i + 1)?, byte(i + 2)?, byte(i + 3)?, byte(i + 4)?]);
+            Some((value as f64 / 65536.0, i + 5))
+        }
+        _ => None,
+    })
+}
+
+impl<'a> Interpreter<'a> {
+    fn pop(&mut self) -> Result<f64, DeserializationError> {
+        self.stack.pop().ok_or_else(|| error("stack underflow"))
+    }
+
+    fn push(&mut self, value: f64) -> Result<(), DeserializationError> {
+        let max_stack = if self.context.variations.is_some() {
+            MAX_STACK_CFF2
+        } else {
+            MAX_STACK
+        };
+        if self.stack.len() >= max_stack {
+            return Err(error("stack overflow"));
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Takes the width from the bottom of the stack, if this is the first
+    /// stack-clearing operator and the stack has an extra argument.
+    fn parse_width(&mut self, has_width: bool) {
+        if !self.width_parsed {
+            self.width_parsed = true;
+            if has_width && !self.stack.is_empty() {
+                self.outline.width = self.context.nominal_width_x + self.stack.remove(0);
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        if self.open {
+            self.outline.path.close_path();
+            self.open = false;
+        }
+    }
+
+    fn move_to(&mut self, dx: f64, dy: f64) {
+        self.close();
+        self.current = Point::new(self.current.x + dx, self.current.y + dy);
+        self.outline.path.move_to(self.current);
+        self.open = true;
+    }
+
+    fn line_to(&mut self, dx: f64, dy: f64) -> Result<(), DeserializationError> {
+        if !self.open {
+            return Err(error("drawing operator without moveto"));
+        }
+        self.current = Point::new(self.current.x + dx, self.current.y + dy);
+        self.outline.path.line_to(self.current);
+        Ok(())
+    }
+
+    #[allow(clippy::many_single_char_names)]
+    fn curve_to(
+        &mut self,
+        dxa: f64,
+        dya: f64,
+        dxb: f64,
+        dyb: f64,
+        dxc: f64,
+        dyc: f64,
+    ) -> Result<(), DeserializationError> {
+        if !self.open {
+            return Err(error("drawing operator without moveto"));
+        }
+        let a = Point::new(self.current.x + dxa, self.current.y + dya);
+        let b = Point::new(a.x + dxb, a.y + dyb);
+        let c = Point::new(b.x + dxc, b.y + dyc);
+        self.outline.path.curve_to(a, b, c);
+        self.current = c;
+        Ok(())
+    }
+
+    fn stems(&mut self, horizontal: bool) {
+        let args = std::mem::take(&mut self.stack);
+        let mut position = 0.0;
+        for pair in args.chunks_exact(2) {
+            position += pair[0];
+            let stem = (position, pair[1]);
+            position += pair[1];
+            if horizontal {
+                self.outline.hstems.push(stem);
+            } else {
+                self.outline.vstems.push(stem);
+            }
+        }
+        self.num_stems += args.len() / 2;
+    }
+
+    /// Alternating horizontal and vertical lines, as used by `hlineto` and
+    /// `vlineto`.
+    fn alternating_lines(&mut self, mut horizontal: bool) -> Result<(), DeserializationError> {
+        let args = std::mem::take(&mut self.stack);
+        for d in args {
+            if horizontal {
+                self.line_to(d, 0.0)?;
+            } else {
+                self.line_to(0.0, d)?;
+            }
+            horizontal = !horizontal;
+        }
+        Ok(())
+    }
+
+    /// Alternating curves starting horizontally or vertically, as used by
+    /// `hvcurveto` and `vhcurveto`.
+    fn alternating_curves(&mut self, mut horizontal: bool) -> Result<(), DeserializationError> {
+        let args = std::mem::take(&mut self.stack);
+        let mut i = 0;
+        while i + 4 <= args.len() {
+            let a = &args[i..i + 4];
+            // The last curve may have a fifth argument
+            let last = if args.len() - i == 5 {
+                args[i + 4]
+            } else {
+                0.0
+            };
+            if horizontal {
+                self.curve_to(a[0], 0.0, a[1], a[2], last, a[3])?;
+            } else {
+                self.curve_to(0.0, a[0], a[1], a[2], a[3], last)?;
+            }
+            horizontal = !horizontal;
+            i += 4;
+        }
+        Ok(())
+    }
+
+    fn call_subr(&mut self, global: bool, depth: usize) -> Result<bool, DeserializationError> {
+        let subrs = if global {
+            self.context.global_subrs
+        } else {
+            self.context.local_subrs
+        };
+        let index = self.pop()? as i32 + subr_bias(subrs.len());
+        let subr = usize::try_from(index)
+            .ok()
+            .and_then(|i| subrs.get(i))
+            .ok_or_else(|| error(&format!("subroutine {} out of range", index)))?;
+        if depth >= MAX_SUBR_NESTING {
+            return Err(error("subroutines nested too deeply"));
+        }
+        self.run(subr, depth + 1)
+    }
+
+    /// Executes a charstring or subroutine, returning `true` if `endchar`
+    /// was reached.
+    fn run(&mut self, code: &[u8], depth: usize) -> Result<bool, DeserializationError> {
+        let mut i = 0;
+        let byte = |i: usize| code.get(i).copied().ok_or_else(|| error("unexpected end"));
+        while i < code.len() {
+            if let Some((value, next)) = decode_number(code, i)? {
+                self.push(value)?;
+                i = next;
+                continue;
+            }
+            let b0 = code[i];
+            i += 1;
+            match b0 {
+                1 | 18 => {
+                    // hstem, hstemhm
+                    self.parse_width(self.stack.len() % 2 == 1);
+                    self.stems(true);
+                }
+                3 | 23 => {
+                    // vstem, vstemhm
+                    self.parse_width(self.stack.len() % 2 == 1);
+                    self.stems(false);
+                }
+                19 | 20 => {
+                    // hintmask, cntrmask: any arguments are implicit vstems
+                    self.parse_width(self.stack.len() % 2 == 1);
+                    self.stems(false);
+                    i += self.num_stems.div_ceil(8);
+                }
+                21 => {
+                    // rmoveto
+                    self.parse_width(self.stack.len() > 2);
+                    let dy = self.pop()?;
+                    let dx = self.pop()?;
+                    self.move_to(dx, dy);
+                    self.stack.clear();
+                }
+                22 | 4 => {
+                    // hmoveto, vmoveto
+                    self.parse_width(self.stack.len() > 1);
+                    let d = self.pop()?;
+                    if b0 == 22 {
+                        self.move_to(d, 0.0);
+                    } else {
+                        self.move_to(0.0, d);
+                    }
+                    self.stack.clear();
+                }
+                5 => {
+                    // rlineto
+                    let args = std::mem::take(&mut self.stack);
+                    for pair in args.chunks_exact(2) {
+                        self.line_to(pair[0], pair[1])?;
+                    }
+                }
+                6 => self.alternating_lines(true)?,  // hlineto
+                7 => self.alternating_lines(false)?, // vlineto
+                8 => {
+                    // rrcurveto
+                    let args = std::mem::take(&mut self.stack);
+                    for a in args.chunks_exact(6) {
+                        self.curve_to(a[0], a[1], a[2], a[3], a[4], a[5])?;
+                    }
+                }
+                24 => {
+                    // rcurveline
+                    let args = std::mem::take(&mut self.stack);
+                    if args.len() < 8 {
+                        return Err(error("too few arguments to rcurveline"));
+                    }
+                    let (curves, line) = args.split_at(args.len() - 2);
+                    for a in curves.chunks_exact(6) {
+                        self.curve_to(a[0], a[1], a[2], a[3], a[4], a[5])?;
+                    }
+                    self.line_to(line[0], line[1])?;
+                }
+                25 => {
+                    // rlinecurve
+                    let args = std::mem::take(&mut self.stack);
+                    if args.len() < 8 {
+                        return Err(error("too few arguments to rlinecurve"));
+                    }
+                    let (lines, a) = args.split_at(args.len() - 6);
+                    for pair in lines.chunks_exact(2) {
+                        self.line_to(pair[0], pair[1])?;
+                    }
+                    self.curve_to(a[0], a[1], a[2], a[3], a[4], a[5])?;
+                }
+                26 => {
+                    // vvcurveto
+                    let mut args = std::mem::take(&mut self.stack);
+                    let mut dx1 = if args.len() % 2 == 1 {
+                        args.remove(0)
+                    } else {
+                        0.0
+                    };
+                    for a in args.chunks_exact(4) {
+                        self.curve_to(dx1, a[0], a[1], a[2], 0.0, a[3])?;
+                        dx1 = 0.0;
+                    }
+                }
+                27 => {
+                    // hhcurveto
+                    let mut args = std::mem::take(&mut self.stack);
+                    let mut dy1 = if args.len() % 2 == 1 {
+                        args.remove(0)
+                    } else {
+                        0.0
+                    };
+                    for a in args.chunks_exact(4) {
+                        self.curve_to(a[0], dy1, a[1], a[2], a[3], 0.0)?;
+                        dy1 = 0.0;
+                    }
+                }
+                30 => self.alternating_curves(false)?, // vhcurveto
+                31 => self.alternating_curves(true)?,  // hvcurveto
+                10 | 29 => {
+                    // callsubr, callgsubr
+                    if self.call_subr(b0 == 29, depth)? {
+                        return Ok(true);
+                    }
+                }
+                11 => return Ok(false), // return
+                15 if self.context.variations.is_some() => {
+                    // vsindex
+                    self.vsindex = self.pop()? as usize;
+                    self.stack.clear();
+                }
+                16 => {
+                    // blend
+                    let variations = self
+                        .context
+                        .variations
+                        .ok_or_else(|| error("blend operator in a CFF charstring"))?;
+                    let scalars = variations
+                        .region_scalars
+                        .get(self.vsindex)
+                        .ok_or_else(|| error("vsindex out of range"))?;
+                    if !apply_blend(&mut self.stack, scalars) {
+                        return Err(error("too few arguments to blend"));
+                    }
+                }
+                14 => {
+                    // endchar
+                    self.parse_width(self.stack.len() == 1 || self.stack.len() == 5);
+                    if self.stack.len() == 4 {
+                        self.seac = Some(Seac {
+                            adx: self.stack[0],
+                            ady: self.stack[1],
+                            base: self.stack[2] as u8,
+                            accent: self.stack[3] as u8,
+                        });
+                    }
+                    self.stack.clear();
+                    self.close();
+                    return Ok(true);
+                }
+                12 => {
+                    let b1 = byte(i)?;
+                    i += 1;
+                    self.escaped_operator(b1)?;
+                }
+                _ => return Err(error(&format!("unknown operator {}", b0))),
+            }
+        }
+        Ok(false)
+    }
+
+    fn escaped_operator(&mut self, op: u8) -> Result<(), DeserializationError> {
+        match op {
+            0 => self.stack.clear(), // dotsection (deprecated)
+            35 => {
+                // flex
+                if self.stack.len() < 13 {
+                    return Err(error("too few arguments to flex"));
+                }
+                let a = std::mem::take(&mut self.stack);
+                self.curve_to(a[0], a[1], a[2], a[3], a[4], a[5])?;
+                self.curve_to(a[6], a[7], a[8], a[9], a[10], a[11])?;
+            }
+            34 => {
+                // hflex
+                if self.stack.len() < 7 {
+                    return Err(error("too few arguments to hflex"));
+                }
+                let a = std::mem::take(&mut self.stack);
+                self.curve_to(a[0], 0.0, a[1], a[2], a[3], 0.0)?;
+                self.curve_to(a[4], 0.0, a[5], -a[2], a[6], 0.0)?;
+            }
+            36 => {
+                // hflex1
+                if self.stack.len() < 9 {
+                    return Err(error("too few arguments to hflex1"));
+                }
+                let a = std::mem::take(&mut self.stack);
+                self.curve_to(a[0], a[1], a[2], a[3], a[4], 0.0)?;
+                self.curve_to(a[5], 0.0, a[6], a[7], a[8], -(a[1] + a[3] + a[7]))?;
+            }
+            37 => {
+                // flex1
+                if self.stack.len() < 11 {
+                    return Err(error("too few arguments to flex1"));
+                }
+                let a = std::mem::take(&mut self.stack);
+                let dx: f64 = a[0..10].iter().step_by(2).sum();
+                let dy: f64 = a[1..10].iter().step_by(2).sum();
+                let (dx6, dy6) = if dx.abs() > dy.abs() {
+                    (a[10], -dy)
+                } else {
+                    (-dx, a[10])
+                };
+                self.curve_to(a[0], a[1], a[2], a[3], a[4], a[5])?;
+                self.curve_to(a[6], a[7], a[8], a[9], dx6, dy6)?;
+            }
+            3 | 4 | 10 | 11 | 12 | 15 | 24 => {
+                // and, or, add, sub, div, eq, mul
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let result = match op {
+                    3 => (a != 0.0 && b != 0.0) as u8 as f64,
+                    4 => (a != 0.0 || b != 0.0) as u8 as f64,
+                    10 => a + b,
+                    11 => a - b,
+                    12 => a / b,
+                    15 => (a == b) as u8 as f64,
+                    _ => a * b,
+                };
+                self.push(result)?;
+            }
+            5 | 9 | 14 | 26 => {
+                // not, abs, neg, sqrt
+                let a = self.pop()?;
+                let result = match op {
+                    5 => (a == 0.0) as u8 as f64,
+                    9 => a.abs(),
+                    14 => -a,
+                    _ => a.sqrt(),
+                };
+                self.push(result)?;
+            }
+            18 => {
+                // drop
+                self.pop()?;
+            }
+            20 => {
+                // put
+                let index = self.pop()? as usize;
+                let value = self.pop()?;
+                *self
+                    .transient
+                    .get_mut(index)
+                    .ok_or_else(|| error("transient array index out of range"))? = value;
+            }
+            21 => {
+                // get
+                let index = self.pop()? as usize;
+                let value = *self
+                    .transient
+                    .get(index)
+                    .ok_or_else(|| error("transient array index out of range"))?;
+                self.push(value)?;
+            }
+            22 => {
+                // ifelse
+                let v2 = self.pop()?;
+                let v1 = self.pop()?;
+                let s2 = self.pop()?;
+                let s1 = self.pop()?;
+                self.push(if v1 <= v2 { s1 } else { s2 })?;
+            }
+            23 => {
+                // random: a deterministic value in (0, 1]
+                self.random_state = self
+                    .random_state
+                    .wrapping_mul(1_103_515_245)
+                    .wrapping_add(12345);
+                let value = ((self.random_state >> 16) & 0x7fff) as f64 + 1.0;
+                self.push(value / 32768.0)?;
+            }
+            27 => {
+                // dup
+                let a = self.pop()?;
+                self.push(a)?;
+                self.push(a)?;
+            }
+            28 => {
+                // exch
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(b)?;
+                self.push(a)?;
+            }
+            29 => {
+                // index
+                let i = self.pop()?;
+                let len = self.stack.len();
+                if len == 0 {
+                    return Err(error("stack underflow"));
+                }
+                let i = if i < 0.0 {
+                    0
+                } else {
+                    (i as usize).min(len - 1)
+                };
+                self.push(self.stack[len - 1 - i])?;
+            }
+            30 => {
+                // roll
+                let j = self.pop()? as i64;
+                let n = self.pop()? as usize;
+                if n > self.stack.len() {
+                    return Err(error("stack underflow"));
+                }
+                if n > 0 {
+                    let start = self.stack.len() - n;
+                    let shift = j.rem_euclid(n as i64) as usize;
+                    self.stack[start..].rotate_right(shift);
+                }
+            }
+            _ => return Err(error(&format!("unknown operator 12 {}", op))),
+        }
+        Ok(())
+    }
+}
+
+/// Executes a Type 2 charstring, returning the glyph outline and any `seac`
+/// accent composition which the caller needs to resolve.
+pub(crate) fn execute(
+    charstring: &[u8],
+    context: Context,
+) -> Result<(Outline, Option<Seac>), DeserializationError> {
+    let mut interpreter = Interpreter {
+        context,
+        stack: vec![],
+        transient: [0.0; 32],
+        outline: Outline {
+            width: context.default_width_x,
+            ..Default::default()
+        },
+        current: Point::ZERO,
+        open: false,
+        num_stems: 0,
+        // CFF2 charstrings have no widths
+        width_parsed: context.variations.is_some(),
+        seac: None,
+        random_state: 1,
+        vsindex: context.variations.map_or(0, |v| v.vsindex),
+    };
+    interpreter.run(charstring, 0)?;
+    interpreter.close();
+    Ok((interpreter.outline, interpreter.seac))
+}
+
+/// A single element of a charstring.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// A number pushed onto the argument stack
+    Number(f64),
+    /// An operator; escaped operators `12 x` are stored as `0x0c00 | x`
+    Operator(u16),
+    /// The mask bytes following a `hintmask` or `cntrmask` operator
+    Mask(Vec<u8>),
+}
+
+/// Inlines the subroutine calls of a charstring.
+struct Desubroutinizer<'a> {
+    context: Context<'a>,
+    tokens: Vec<Token>,
+    /// The number of values on the argument stack, used to count stems
+    depth: usize,
+    num_stems: usize,
+    vsindex: usize,
+}
+
+impl<'a> Desubroutinizer<'a> {
+    /// The value of the number token at the end of the output.
+    fn last_number(&self) -> Result<f64, DeserializationError> {
+        match self.tokens.last() {
+            Some(Token::Number(n)) => Ok(*n),
+            _ => Err(error("operator argument is not a literal number")),
+        }
+    }
+
+    /// Flattens a charstring or subroutine, returning `true` if `endchar`
+    /// was reached.
+    fn flatten(&mut self, code: &[u8], nesting: usize) -> Result<bool, DeserializationError> {
+        let mut i = 0;
+        while i < code.len() {
+            if let Some((value, next)) = decode_number(code, i)? {
+                self.tokens.push(Token::Number(value));
+                self.depth += 1;
+                i = next;
+                continue;
+            }
+            let b0 = code[i];
+            i += 1;
+            let op = if b0 == 12 {
+                let b1 = *code.get(i).ok_or_else(|| error("unexpected end"))?;
+                i += 1;
+                0x0c00 | b1 as u16
+            } else {
+                b0 as u16
+            };
+            match op {
+                operators::CALLSUBR | operators::CALLGSUBR => {
+                    let subrs = if op == operators::CALLGSUBR {
+                        self.context.global_subrs
+                    } else {
+                        self.context.local_subrs
+                    };
+                    let index = self.last_number()? as i32 + subr_bias(subrs.len());
+                    self.tokens.pop();
+                    self.depth -= 1;
+                    let subr = usize::try_from(index)
+                        .ok()
+                        .and_then(|i| subrs.get(i))
+                        .ok_or_else(|| error(&format!("subroutine {} out of range", index)))?;
+                    if nesting >= MAX_SUBR_NESTING {
+                        return Err(error("subroutines nested too deeply"));
+                    }
+                    if self.flatten(subr, nesting + 1)? {
+                        return Ok(true);
+                    }
+                }
+                operators::RETURN => return Ok(false),
+                operators::ENDCHAR => {
+                    self.tokens.push(Token::Operator(op));
+                    return Ok(true);
+                }
+                operators::HINTMASK | operators::CNTRMASK => {
+                    self.num_stems += self.depth / 2;
+                    self.depth = 0;
+                    let mask_len = self.num_stems.div_ceil(8);
+                    let mask = code
+                        .get(i..i + mask_len)
+                        .ok_or_else(|| error("unexpected end"))?;
+                    i += mask_len;
+                    self.tokens.push(Token::Operator(op));
+                    self.tokens.push(Token::Mask(mask.to_vec()));
+                }
+                operators::HSTEM | operators::VSTEM | operators::HSTEMHM | operators::VSTEMHM => {
+                    self.num_stems += self.depth / 2;
+                    self.depth = 0;
+                    self.tokens.push(Token::Operator(op));
+                }
+                operators::VSINDEX if self.context.variations.is_some() => {
+                    self.vsindex = self.last_number()? as usize;
+                    self.depth = 0;
+                    self.tokens.push(Token::Operator(op));
+                }
+                operators::BLEND => {
+                    let region_count = self
+                        .context
+                        .variations
+                        .and_then(|v| v.region_scalars.get(self.vsindex))
+                        .ok_or_else(|| error("blend without variation data"))?
+                        .len();
+                    let n = self.last_number()? as usize;
+                    self.depth = (self.depth + n).saturating_sub(n * (region_count + 1) + 1);
+                    self.tokens.push(Token::Operator(op));
+                }
+                0x0c00..=0x0cff => {
+                    // Arithmetic operators change the stack depth; the
+                    // remaining escaped operators clear it.
+                    self.depth = match op & 0xff {
+                        5 | 9 | 14 | 21 | 26 | 28 | 29 => self.depth,
+                        23 | 27 => self.depth + 1,
+                        3 | 4 | 10 | 11 | 12 | 15 | 18 | 24 => self.depth.saturating_sub(1),
+                        20 | 30 => self.depth.saturating_sub(2),
+                        22 => self.depth.saturating_sub(3),
+                        _ => 0,
+                    };
+                    self.tokens.push(Token::Operator(op));
+                }
+                _ => {
+                    self.depth = 0;
+                    self.tokens.push(Token::Operator(op));
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Decodes a charstring into tokens, inlining all subroutine calls.
+///
+/// Subroutine indices must be literal numbers immediately preceding the
+/// call, which is always the case in practice.
+pub fn desubroutinize(
+    charstring: &[u8],
+    context: Context,
+) -> Result<Vec<Token>, DeserializationError> {
+    let mut desubroutinizer = Desubroutinizer {
+        context,
+        tokens: vec![],
+        depth: 0,
+        num_stems: 0,
+        vsindex: context.variations.map_or(0, |v| v.vsindex),
+    };
+    desubroutinizer.flatten(charstring, 0)?;
+    Ok(desubroutinizer.tokens)
+}
+
+/// Encodes a number using the most compact charstring representation.
+pub(crate) fn encode_number(value: f64, out: &mut Vec<u8>) {
+    if value.fract() != 0.0 || value < i16::MIN as f64 || value > i16::MAX as f64 {
+        out.push(255);
+        out.extend_from_slice(&((value * 65536.0).round() as i32).to_be_bytes());
+        return;
+    }
+    let v = value as i32;
+    match v {
+        -107..=107 => out.push((v + 139) as u8),
+        108..=1131 => {
+            let v = v - 108;
+            out.extend_from_slice(&[(v / 256 + 247) as u8, (v % 256) as u8]);
+        }
+        -1131..=-108 => {
+            let v = -v - 108;
+            out.extend_from_slice(&[(v / 256 + 251) as u8, (v % 256) as u8]);
+        }
+        _ => {
+            out.push(28);
+            out.extend_from_slice(&(v as i16).to_be_bytes());
+        }
+    }
+}
+
+/// Encodes a sequence of tokens as a charstring.
+pub fn encode(tokens: &[Token]) -> Vec<u8> {
+    let mut out = vec![];
+    for token in tokens {
+        match token {
+            Token::Number(value) => encode_number(*value, &mut out),
+            Token::Operator(op) if *op >= 0x0c00 => out.extend_from_slice(&[12, *op as u8]),
+            Token::Operator(op) => out.push(*op as u8),
+            Token::Mask(mask) => out.extend_from_slice(mask),
+        }
+    }
+    out
+}
+
+/// The maximum number of arguments used by [`path_to_tokens`] for one
+/// operator, which is the size of the CFF argument stack.
+const PATH_ARGS_LIMIT: usize = MAX_STACK;
+
+/// Converts an outline to charstring tokens using the `rmoveto`, `rlineto`
+/// and `rrcurveto` operators. Quadratic curves are converted to cubic
+/// curves, and lines which close a subpath are omitted.
+///
+/// If `width` is given, it is placed before the first operator, as in CFF
+/// charstrings; the nominal width should already have been subtracted from
+/// it. No `endchar` operator is added.
+pub fn path_to_tokens(path: &BezPath, width: Option<f64>) -> Vec<Token> {
+    fn flush(tokens: &mut Vec<Token>, args: &mut Vec<f64>, pending: &mut Option<u16>) {
+        if let Some(op) = pending.take() {
+            tokens.extend(args.drain(..).map(Token::Number));
+            tokens.push(Token::Operator(op));
+        }
+    }
+    let mut tokens = vec![];
+    let mut args: Vec<f64> = width.into_iter().collect();
+    let mut pending: Option<u16> = None;
+    let mut current = Point::ZERO;
+    let mut start = Point::ZERO;
+    let elements = path.elements();
+    for (i, el) in elements.iter().enumerate() {
+        let (op, points) = match *el {
+            PathEl::MoveTo(p) => {
+                start = p;
+                (operators::RMOVETO, vec![p])
+            }
+            PathEl::LineTo(p) => {
+                // The closing line is implied, and does not move the current point
+                if p == start && matches!(elements.get(i + 1), Some(PathEl::ClosePath)) {
+                    continue;
+                }
+                (operators::RLINETO, vec![p])
+            }
+            PathEl::QuadTo(p1, p2) => {
+                let cubic = QuadBez::new(current, p1, p2).raise();
+                (operators::RRCURVETO, vec![cubic.p1, cubic.p2, cubic.p3])
+            }
+            PathEl::CurveTo(p1, p2, p3) => (operators::RRCURVETO, vec![p1, p2, p3]),
+            PathEl::ClosePath => continue,
+        };
+        if pending != Some(op)
+            || op == operators::RMOVETO
+            || args.len() + 2 * points.len() > PATH_ARGS_LIMIT
+        {
+            flush(&mut tokens, &mut args, &mut pending);
+        }
+        for p in points {
+            args.push(p.x - current.x);
+            args.push(p.y - current.y);
+            current = p;
+        }
+        pending = Some(op);
+    }
+    flush(&mut tokens, &mut args, &mut pending);
+    // An empty outline may still have a width
+    tokens.extend(args.into_iter().map(Token::Number));
+    tokens
+}
+
+/// Rewrites the `blend` operators in a sequence of tokens after some axes of
+/// the variation store have been pinned or limited, using the
+/// [`PinnedRegions`] of each item variation data subtable, such as those
+/// returned by
+/// [`ItemVariationStore::pin_axes`](crate::otvar::ItemVariationStore::pin_axes).
+///
+/// Values whose deltas are all zero after pinning no longer need a `blend`.
+/// If `keep_vsindex` is false, `vsindex` operators are removed, which is
+/// appropriate when the variation store has been dropped altogether.
+pub(crate) fn pin_blends(
+    tokens: &[Token],
+    vsindex: usize,
+    mappings: &[PinnedRegions],
+    keep_vsindex: bool,
+) -> Result<Vec<Token>, DeserializationError> {
+    // Each value on the stack is a default and its (pinned) deltas
+    let mut stack: Vec<(f64, Vec<f32>)> = vec![];
+    let mut out = vec![];
+    let mut vsindex = vsindex;
+    let literal = |value: Option<(f64, Vec<f32>)>| match value {
+        Some((n, deltas)) if deltas.is_empty() => Ok(n),
+        _ => Err(error("operator argument is not a literal number")),
+    };
+    for token in tokens {
+        match token {
+            Token::Number(n) => stack.push((*n, vec![])),
+            Token::Operator(operators::BLEND) => {
+                let mapping = mappings
+                    .get(vsindex)
+                    .ok_or_else(|| error("vsindex out of range"))?;
+                let k = mapping.columns.len();
+                let n = literal(stack.pop())? as usize;
+                let start = stack
+                    .len()
+                    .checked_sub(n * (k + 1))
+                    .ok_or_else(|| error("too few arguments to blend"))?;
+                let args = stack
+                    .split_off(start)
+                    .into_iter()
+                    .map(|arg| literal(Some(arg)))
+                    .collect::<Result<Vec<f64>, DeserializationError>>()?;
+                let (defaults, deltas) = args.split_at(n);
+                for (i, default) in defaults.iter().enumerate() {
+                    let row: Vec<f32> = deltas[i * k..(i + 1) * k]
+                        .iter()
+                        .map(|&d| d as f32)
+                        .collect();
+                    let (default_delta, new_deltas) = mapping.apply(&row);
+                    stack.push((default + default_delta as f64, new_deltas));
+                }
+            }
+            Token::Operator(operators::VSINDEX) => {
+                vsindex = literal(stack.last().cloned())? as usize;
+                if keep_vsindex {
+                    flush_blends(&mut stack, &mut out);
+                    out.push(token.clone());
+                } else {
+                    stack.clear();
+                }
+            }
+            _ => {
+                flush_blends(&mut stack, &mut out);
+                out.push(token.clone());
+            }
+        }
+    }
+    flush_blends(&mut stack, &mut out);
+    Ok(out)
+}
+
+/// Writes out a stack of values and their deltas, grouping runs of values
+/// which vary into `blend` operators.
+fn flush_blends(stack: &mut Vec<(f64, Vec<f32>)>, out: &mut Vec<Token>) {
+    let mut values = std::mem::take(stack).into_iter().peekable();
+    let varies = |deltas: &[f32]| deltas.iter().any(|&d| d != 0.0);
+    while let Some((n, deltas)) = values.next() {
+        if !varies(&deltas) {
+            out.push(Token::Number(n));
+            continue;
+        }
+        let mut run = vec![(n, deltas)];
+        while let Some((n, deltas)) = values.next_if(|(_, deltas)| varies(deltas)) {
+            run.push((n, deltas));
+        }
+        out.extend(run.iter().map(|(n, _)| Token::Number(*n)));
+        for (_, deltas) in &run {
+            out.extend(deltas.iter().map(|&d| Token::Number(d as f64)));
+        }
+        out.push(Token::Number(run.len() as f64));
+        out.push(Token::Operator(operators::BLEND));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kurbo::PathEl;
+
+    fn context<'a>(global_subrs: &'a [Vec<u8>], local_subrs: &'a [Vec<u8>]) -> Context<'a> {
+        Context {
+            global_subrs,
+            local_subrs,
+            default_width_x: 500.0,
+            nominal_width_x: 600.0,
+            variations: None,
+        }
+    }
+
+    #[test]
+    fn test_execute_charstring() {
+        // 100 hstem: a width of 600 + 100, then a box drawn with
+        // rmoveto/hlineto/vlineto and a curve
+        let charstring = vec![
+            239, 139, 189, 1, // 100 0 50 hstem
+            149, 149, 21, // 10 10 rmoveto
+            239, 239, 6, // 100 100 hlineto
+            8, // rrcurveto with no args does nothing
+            139, 139, 239, 139, 239, 239, 8, // 0 0 100 0 100 100 rrcurveto
+            14,
+        ];
+        let (outline, seac) = execute(&charstring, context(&[], &[])).unwrap();
+        assert_eq!(seac, None);
+        assert_eq!(outline.width, 700.0);
+        assert_eq!(outline.hstems, vec![(0.0, 50.0)]);
+        assert_eq!(
+            outline.path.elements(),
+            &[
+                PathEl::MoveTo((10.0, 10.0).into()),
+                PathEl::LineTo((110.0, 10.0).into()),
+                PathEl::LineTo((110.0, 110.0).into()),
+                PathEl::CurveTo(
+                    (110.0, 110.0).into(),
+                    (210.0, 110.0).into(),
+                    (310.0, 210.0).into()
+                ),
+                PathEl::ClosePath,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_subroutines() {
+        // The local subr draws a line; the global subr calls it.
+        let local_subrs = vec![vec![239, 139, 5, 11]];
+        let global_subrs = vec![vec![32, 10, 11]];
+        // 0 0 rmoveto, callgsubr 0 (biased: -107), endchar
+        let charstring = vec![139, 139, 21, 32, 29, 14];
+        let (outline, _) = execute(&charstring, context(&global_subrs, &local_subrs)).unwrap();
+        assert_eq!(outline.width, 500.0);
+        assert_eq!(
+            outline.path.elements(),
+            &[
+                PathEl::MoveTo((0.0, 0.0).into()),
+                PathEl::LineTo((100.0, 0.0).into()),
+                PathEl::ClosePath,
+            ]
+        );
+
+        // Infinite recursion is caught
+        let global_subrs = vec![vec![32, 29, 11]];
+        assert!(execute(&[32, 29, 14], context(&global_subrs, &[])).is_err());
+    }
+
+    #[test]
+    fn test_hintmask_and_seac() {
+        // 0 10 20 10 hstemhm, 0 5 hintmask (implicit vstem) + 1 mask byte,
+        // 0 0 rmoveto, then seac 10 20 65 55 endchar
+        let charstring = vec![
+            139, 149, 159, 149, 18, 139, 144, 19, 0xff, 139, 139, 21, 149, 159, 204, 194, 14,
+        ];
+        let (outline, seac) = execute(&charstring, context(&[], &[])).unwrap();
+        assert_eq!(outline.hstems, vec![(0.0, 10.0), (30.0, 10.0)]);
+        assert_eq!(outline.vstems, vec![(0.0, 5.0)]);
+        assert_eq!(
+            seac,
+            Some(Seac {
+                adx: 10.0,
+                ady: 20.0,
+                base: 65,
+                accent: 55
+            })
+        );
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        // 3 4 add 2 mul dup rmoveto endchar
+        let charstring = vec![142, 143, 12, 10, 141, 12, 24, 12, 27, 21, 14];
+        let (outline, _) = execute(&charstring, context(&[], &[])).unwrap();
+        assert_eq!(
+            outline.path.elements(),
+            &[PathEl::MoveTo((14.0, 14.0).into()), PathEl::ClosePath]
+        );
+
+        // 1 2 3 3 1 roll exch sub rmoveto endchar
+        let charstring = vec![140, 141, 142, 142, 140, 12, 30, 12, 28, 12, 11, 21, 14];
+        let (outline, _) = execute(&charstring, context(&[], &[])).unwrap();
+        assert_eq!(
+            outline.path.elements(),
+            &[PathEl::MoveTo((3.0, 1.0).into()), PathEl::ClosePath]
+        );
+    }
+
+    #[test]
+    fn test_cff2_blend() {
+        // 1 vsindex, 10 20 (5 -5) (2 2) 2 blend rmoveto: two regions
+        let charstring = vec![140, 15, 149, 159, 144, 134, 141, 141, 141, 16, 21];
+        let region_scalars = vec![vec![1.0], vec![0.5, 1.0]];
+        let mut context = context(&[], &[]);
+        context.variations = Some(Variations {
+            region_scalars: &region_scalars,
+            vsindex: 0,
+        });
+        let (outline, _) = execute(&charstring, context).unwrap();
+        // There is no width in CFF2 charstrings, so the extra arguments
+        // are not taken as one
+        assert_eq!(outline.width, 500.0);
+        assert_eq!(
+            outline.path.elements(),
+            &[PathEl::MoveTo((7.5, 23.0).into()), PathEl::ClosePath]
+        );
+        // blend is not allowed in CFF charstrings
+        assert!(execute(&charstring, self::context(&[], &[])).is_err());
+    }
+
+    #[test]
+    fn test_desubroutinize() {
+        let local_subrs = vec![vec![239, 139, 5, 11]];
+        let global_subrs = vec![vec![32, 10, 11]];
+        // 0 5 vstem, hintmask, 0 0 rmoveto, callgsubr 0, 1.5 0 rlineto, endchar
+        let charstring = vec![
+            139, 144, 3, 19, 0x80, 139, 139, 21, 32, 29, 255, 0, 1, 128, 0, 139, 5, 14,
+        ];
+        let tokens = desubroutinize(&charstring, context(&global_subrs, &local_subrs)).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Number(0.0),
+                Token::Number(5.0),
+                Token::Operator(operators::VSTEM),
+                Token::Operator(operators::HINTMASK),
+                Token::Mask(vec![0x80]),
+                Token::Number(0.0),
+                Token::Number(0.0),
+                Token::Operator(operators::RMOVETO),
+                Token::Number(100.0),
+                Token::Number(0.0),
+                Token::Operator(operators::RLINETO),
+                Token::Number(1.5),
+                Token::Number(0.0),
+                Token::Operator(operators::RLINETO),
+                Token::Operator(operators::ENDCHAR),
+            ]
+        );
+        assert_eq!(
+            encode(&tokens),
+            vec![139, 144, 3, 19, 0x80, 139, 139, 21, 239, 139, 5, 255, 0, 1, 128, 0, 139, 5, 14]
+        );
+
+        let mut out = vec![];
+        for n in &[-1131.0, -108.0, 108.0, 1131.0, 1132.0, -32768.0] {
+            encode_number(*n, &mut out);
+        }
+        assert_eq!(
+            out,
+            vec![254, 255, 251, 0, 247, 0, 250, 255, 28, 4, 108, 28, 128, 0]
+        );
+    }
+
+    #[test]
+    fn test_path_to_tokens() {
+        let mut path = BezPath::new();
+        path.move_to((10.0, 10.0));
+        path.line_to((110.0, 10.0));
+        path.quad_to((110.0, 70.0), (50.0, 70.0));
+        path.line_to((10.0, 10.0));
+        path.close_path();
+        let mut tokens = path_to_tokens(&path, Some(-100.0));
+        tokens.push(Token::Operator(operators::ENDCHAR));
+        let (outline, _) = execute(&encode(&tokens), context(&[], &[])).unwrap();
+        assert_eq!(outline.width, 500.0);
+        assert_eq!(
+            outline.path.elements(),
+            &[
+                PathEl::MoveTo((10.0, 10.0).into()),
+                PathEl::LineTo((110.0, 10.0).into()),
+                PathEl::CurveTo(
+                    (110.0, 50.0).into(),
+                    (90.0, 70.0).into(),
+                    (50.0, 70.0).into()
+                ),
+                PathEl::ClosePath,
+            ]
+        );
+        assert_eq!(
+            path_to_tokens(&BezPath::new(), Some(10.0)),
+            vec![Token::Number(10.0)]
+        );
+    }
+
+    #[test]
+    fn test_subr_bias() {
+        assert_eq!(subr_bias(0), 107);
+        assert_eq!(subr_bias(1239), 107);
+        assert_eq!(subr_bias(1240), 1131);
+        assert_eq!(subr_bias(33900), 32768);
+    }
+}
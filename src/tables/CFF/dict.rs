@@ -0,0 +1,414 @@
+//! Reading and writing DICT structures.
+//!
+//! A DICT is a list of operand/operator pairs, where each operator is
+//! preceded by its operands. Operators are represented here as `u16` values;
+//! two-byte (escaped) operators `12 x` are stored as `0x0c00 | x`.
+use super::charstring::apply_blend;
+use otspec::DeserializationError;
+
+/// DICT operators
+pub mod operators {
+    #![allow(missing_docs)]
+    pub const VERSION: u16 = 0;
+    pub const NOTICE: u16 = 1;
+    pub const FULL_NAME: u16 = 2;
+    pub const FAMILY_NAME: u16 = 3;
+    pub const WEIGHT: u16 = 4;
+    pub const FONT_BBOX: u16 = 5;
+    pub const BLUE_VALUES: u16 = 6;
+    pub const OTHER_BLUES: u16 = 7;
+    pub const FAMILY_BLUES: u16 = 8;
+    pub const FAMILY_OTHER_BLUES: u16 = 9;
+    pub const STD_HW: u16 = 10;
+    pub const STD_VW: u16 = 11;
+    pub const UNIQUE_ID: u16 = 13;
+    pub const XUID: u16 = 14;
+    pub const CHARSET: u16 = 15;
+    pub const ENCODING: u16 = 16;
+    pub const CHAR_STRINGS: u16 = 17;
+    pub const PRIVATE: u16 = 18;
+    pub const SUBRS: u16 = 19;
+    pub const DEFAULT_WIDTH_X: u16 = 20;
+    pub const NOMINAL_WIDTH_X: u16 = 21;
+    // CFF2 only
+    pub const VSINDEX: u16 = 22;
+    pub const BLEND: u16 = 23;
+    pub const VSTORE: u16 = 24;
+    pub const MAXSTACK: u16 = 25;
+    pub const COPYRIGHT: u16 = 0x0c00;
+    pub const IS_FIXED_PITCH: u16 = 0x0c01;
+    pub const ITALIC_ANGLE: u16 = 0x0c02;
+    pub const UNDERLINE_POSITION: u16 = 0x0c03;
+    pub const UNDERLINE_THICKNESS: u16 = 0x0c04;
+    pub const PAINT_TYPE: u16 = 0x0c05;
+    pub const CHARSTRING_TYPE: u16 = 0x0c06;
+    pub const FONT_MATRIX: u16 = 0x0c07;
+    pub const STROKE_WIDTH: u16 = 0x0c08;
+    pub const BLUE_SCALE: u16 = 0x0c09;
+    pub const BLUE_SHIFT: u16 = 0x0c0a;
+    pub const BLUE_FUZZ: u16 = 0x0c0b;
+    pub const STEM_SNAP_H: u16 = 0x0c0c;
+    pub const STEM_SNAP_V: u16 = 0x0c0d;
+    pub const FORCE_BOLD: u16 = 0x0c0e;
+    pub const LANGUAGE_GROUP: u16 = 0x0c11;
+    pub const EXPANSION_FACTOR: u16 = 0x0c12;
+    pub const INITIAL_RANDOM_SEED: u16 = 0x0c13;
+    pub const SYNTHETIC_BASE: u16 = 0x0c14;
+    pub const POSTSCRIPT: u16 = 0x0c15;
+    pub const BASE_FONT_NAME: u16 = 0x0c16;
+    pub const BASE_FONT_BLEND: u16 = 0x0c17;
+    pub const ROS: u16 = 0x0c1e;
+    pub const CID_FONT_VERSION: u16 = 0x0c1f;
+    pub const CID_FONT_REVISION: u16 = 0x0c20;
+    pub const CID_FONT_TYPE: u16 = 0x0c21;
+    pub const CID_COUNT: u16 = 0x0c22;
+    pub const UID_BASE: u16 = 0x0c23;
+    pub const FD_ARRAY: u16 = 0x0c24;
+    pub const FD_SELECT: u16 = 0x0c25;
+    pub const FONT_NAME: u16 = 0x0c26;
+}
+
+/// An operand in a DICT.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    /// An integer operand
+    Integer(i32),
+    /// A real number operand
+    Real(f64),
+    /// The CFF2 `blend` operator, which computes variable values from the
+    /// operands preceding it.
+    Blend,
+}
+
+impl Operand {
+    /// The value of this operand as a floating point number.
+    ///
+    /// The `blend` operator has no value, and returns zero.
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            Operand::Integer(i) => *i as f64,
+            Operand::Real(r) => *r,
+            Operand::Blend => 0.0,
+        }
+    }
+}
+
+impl From<i32> for Operand {
+    fn from(i: i32) -> Self {
+        Operand::Integer(i)
+    }
+}
+
+impl From<f64> for Operand {
+    fn from(f: f64) -> Self {
+        if f.fract() == 0.0 && f.abs() < i32::MAX as f64 {
+            Operand::Integer(f as i32)
+        } else {
+            Operand::Real(f)
+        }
+    }
+}
+
+/// A DICT: an ordered list of operators and their operands.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Dict {
+    /// The entries in this dictionary, in the order they appear.
+    pub entries: Vec<(u16, Vec<Operand>)>,
+}
+
+const REAL_NIBBLES: &[u8; 16] = b"0123456789.E?\0-\0";
+
+impl Dict {
+    /// Create an empty dictionary.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the operands of the given operator, if present.
+    pub fn get(&self, op: u16) -> Option<&[Operand]> {
+        self.entries
+            .iter()
+            .find(|(o, _)| *o == op)
+            .map(|(_, operands)| operands.as_slice())
+    }
+
+    /// Returns the first operand of the given operator as a number, if present.
+    pub fn get_f64(&self, op: u16) -> Option<f64> {
+        self.get(op)
+            .and_then(|operands| operands.first())
+            .map(|o| o.as_f64())
+    }
+
+    /// Returns the first operand of the given operator as an integer, if present.
+    pub fn get_i32(&self, op: u16) -> Option<i32> {
+        self.get_f64(op).map(|f| f as i32)
+    }
+
+    /// Returns the operands of the given operator, evaluating any `blend`
+    /// operators using the given region scalars.
+    ///
+    /// Returns `None` if the operator is not present or its blends are
+    /// malformed.
+    pub fn get_blended(&self, op: u16, scalars: &[f32]) -> Option<Vec<f64>> {
+        let mut stack: Vec<f64> = vec![];
+        for operand in self.get(op)? {
+            if *operand != Operand::Blend {
+                stack.push(operand.as_f64());
+                continue;
+            }
+            if !apply_blend(&mut stack, scalars) {
+                return None;
+            }
+        }
+        Some(stack)
+    }
+
+    /// Returns `true` if the dictionary contains the given operator.
+    pub fn contains(&self, op: u16) -> bool {
+        self.get(op).is_some()
+    }
+
+    /// Set the operands of an operator, replacing any existing value.
+    pub fn set(&mut self, op: u16, operands: Vec<Operand>) {
+        if let Some(entry) = self.entries.iter_mut().find(|(o, _)| *o == op) {
+            entry.1 = operands;
+        } else {
+            self.entries.push((op, operands));
+        }
+    }
+
+    /// Remove an operator from the dictionary, returning its operands.
+    pub fn remove(&mut self, op: u16) -> Option<Vec<Operand>> {
+        let index = self.entries.iter().position(|(o, _)| *o == op)?;
+        Some(self.entries.remove(index).1)
+    }
+
+    /// Parse a dictionary from its binary representation.
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<Dict, DeserializationError> {
+        let eof = || DeserializationError("DICT data ended unexpectedly".to_string());
+        let mut entries = vec![];
+        let mut operands = vec![];
+        let mut i = 0;
+        while i < data.len() {
+            let b0 = data[i];
+            i += 1;
+            match b0 {
+                23 => operands.push(Operand::Blend),
+                12 => {
+                    let b1 = *data.get(i).ok_or_else(eof)?;
+                    i += 1;
+                    entries.push((0x0c00 | b1 as u16, std::mem::take(&mut operands)));
+                }
+                0..=27 => entries.push((b0 as u16, std::mem::take(&mut operands))),
+                28 => {
+                    let b = data.get(i..i + 2).ok_or_else(eof)?;
+                    operands.push(Operand::Integer(i16::from_be_bytes([b[0], b[1]]) as i32));
+                    i += 2;
+                }
+                29 => {
+                    let b = data.get(i..i + 4).ok_or_else(eof)?;
+                    operands.push(Operand::Integer(i32::from_be_bytes([
+                        b[0], b[1], b[2], b[3],
+                    ])));
+                    i += 4;
+                }
+                30 => {
+                    let mut s = String::new();
+                    'real: loop {
+                        let b = *data.get(i).ok_or_else(eof)?;
+                        i += 1;
+                        for nibble in [b >> 4, b & 0xf] {
+                            match nibble {
+                                0xf => break 'real,
+                                0xc => s.push_str("E-"),
+                                0xd => {
+                                    return Err(DeserializationError("Bad real number".to_string()))
+                                }
+                                _ => s.push(REAL_NIBBLES[nibble as usize] as char),
+                            }
+                        }
+                    }
+                    let value = s
+                        .parse::<f64>()
+                        .map_err(|_| DeserializationError(format!("Bad real number {}", s)))?;
+                    operands.push(Operand::Real(value));
+                }
+                32..=246 => operands.push(Operand::Integer(b0 as i32 - 139)),
+                247..=250 => {
+                    let b1 = *data.get(i).ok_or_else(eof)? as i32;
+                    i += 1;
+                    operands.push(Operand::Integer((b0 as i32 - 247) * 256 + b1 + 108));
+                }
+                251..=254 => {
+                    let b1 = *data.get(i).ok_or_else(eof)? as i32;
+                    i += 1;
+                    operands.push(Operand::Integer(-(b0 as i32 - 251) * 256 - b1 - 108));
+                }
+                _ => {
+                    return Err(DeserializationError(format!(
+                        "Reserved byte {} in DICT data",
+                        b0
+                    )))
+                }
+            }
+        }
+        Ok(Dict { entries })
+    }
+
+    /// Serialize the dictionary into its binary representation.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        for (op, operands) in &self.entries {
+            for operand in operands {
+                match operand {
+                    Operand::Integer(i) => encode_integer(*i, &mut out),
+                    Operand::Real(r) => encode_real(*r, &mut out),
+                    Operand::Blend => encode_operator(operators::BLEND, &mut out),
+                }
+            }
+            encode_operator(*op, &mut out);
+        }
+        out
+    }
+}
+
+pub(crate) fn encode_operator(op: u16, out: &mut Vec<u8>) {
+    if op >= 0x0c00 {
+        out.push(12);
+    }
+    out.push((op & 0xff) as u8);
+}
+
+/// Encodes an integer using the shortest available representation.
+pub(crate) fn encode_integer(i: i32, out: &mut Vec<u8>) {
+    match i {
+        -107..=107 => out.push((i + 139) as u8),
+        108..=1131 => {
+            let v = i - 108;
+            out.push((v / 256 + 247) as u8);
+            out.push((v % 256) as u8);
+        }
+        -1131..=-108 => {
+            let v = -i - 108;
+            out.push((v / 256 + 251) as u8);
+            out.push((v % 256) as u8);
+        }
+        -32768..=32767 => {
+            out.push(28);
+            out.extend_from_slice(&(i as i16).to_be_bytes());
+        }
+        _ => encode_offset(i, out),
+    }
+}
+
+/// Encodes an integer as a fixed-size five byte value.
+///
+/// This is used for offsets, which have to be written before the final
+/// layout of the table is known.
+pub(crate) fn encode_offset(i: i32, out: &mut Vec<u8>) {
+    out.push(29);
+    out.extend_from_slice(&i.to_be_bytes());
+}
+
+fn encode_real(r: f64, out: &mut Vec<u8>) {
+    let plain = format!("{}", r);
+    let scientific = format!("{:e}", r);
+    let s = if scientific.len() < plain.len() {
+        scientific
+    } else {
+        plain
+    };
+    let s = s
+        .strip_prefix("0.")
+        .map_or(s.clone(), |rest| format!(".{}", rest));
+    let s = s.replace("-0.", "-.");
+    let mut nibbles = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        nibbles.push(match c {
+            '0'..='9' => c as u8 - b'0',
+            '.' => 0xa,
+            'e' | 'E' if chars.peek() == Some(&'-') => {
+                chars.next();
+                0xc
+            }
+            'e' | 'E' => 0xb,
+            _ => 0xe, // '-'
+        });
+    }
+    nibbles.push(0xf);
+    if nibbles.len() % 2 == 1 {
+        nibbles.push(0xf);
+    }
+    out.push(30);
+    out.extend(nibbles.chunks(2).map(|pair| pair[0] << 4 | pair[1]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::operators::*;
+    use super::*;
+
+    #[test]
+    fn test_dict_roundtrip() {
+        // Examples from the CFF specification, section 4
+        let binary = vec![
+            0x8b, 0xef, 0x27, 0xfa, 0x7c, 0xfe, 0x7c, 0x1c, 0x27, 0x10, 0x1d, 0x00, 0x01, 0x86,
+            0xa0, 0x1e, 0xe2, 0xa2, 0x5f, 0x0c, 0x07,
+        ];
+        let dict = Dict::from_bytes(&binary).unwrap();
+        assert_eq!(
+            dict.get(FONT_MATRIX).unwrap(),
+            &[
+                Operand::Integer(0),
+                Operand::Integer(100),
+                Operand::Integer(-100),
+                Operand::Integer(1000),
+                Operand::Integer(-1000),
+                Operand::Integer(10000),
+                Operand::Integer(100000),
+                Operand::Real(-2.25),
+            ]
+        );
+        assert_eq!(dict.to_bytes(), binary);
+
+        let binary = vec![0x1e, 0x0a, 0x14, 0x05, 0x41, 0xc3, 0xff, 0x0c, 0x09];
+        let dict = Dict::from_bytes(&binary).unwrap();
+        assert_eq!(dict.get_f64(BLUE_SCALE), Some(0.140541e-3));
+        assert_eq!(
+            Dict::from_bytes(&dict.to_bytes()).unwrap(),
+            dict,
+            "Reals survive a roundtrip"
+        );
+    }
+
+    #[test]
+    fn test_dict_blend() {
+        // BlueValues: -10 0 with deltas -5 for each value, blended with one region
+        let binary = vec![129, 139, 134, 134, 141, 23, 6];
+        let dict = Dict::from_bytes(&binary).unwrap();
+        assert_eq!(dict.get(BLUE_VALUES).unwrap().last(), Some(&Operand::Blend));
+        assert_eq!(dict.get_f64(BLUE_VALUES), Some(-10.0));
+        assert_eq!(
+            dict.get_blended(BLUE_VALUES, &[0.5]),
+            Some(vec![-12.5, -2.5])
+        );
+        assert_eq!(dict.get_blended(BLUE_VALUES, &[0.5, 0.5]), None);
+        assert_eq!(dict.to_bytes(), binary);
+    }
+
+    #[test]
+    fn test_dict_set() {
+        let mut dict = Dict::new();
+        dict.set(CHARSET, vec![0.into()]);
+        dict.set(
+            FONT_BBOX,
+            vec![0.into(), (-120).into(), 500.into(), 700.into()],
+        );
+        dict.set(CHARSET, vec![1.into()]);
+        assert_eq!(dict.get_i32(CHARSET), Some(1));
+        assert_eq!(dict.entries[0].0, CHARSET);
+        assert_eq!(dict.remove(CHARSET), Some(vec![Operand::Integer(1)]));
+        assert!(!dict.contains(CHARSET));
+    }
+}
@@ -0,0 +1,154 @@
+//! Encodings, which map character codes to glyphs in non-CID fonts.
+use otspec::DeserializationError;
+
+/// An encoding, assigning character codes to glyphs.
+///
+/// Encodings are a holdover from standalone PostScript fonts; in OpenType
+/// fonts, the `cmap` table is used instead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Encoding {
+    /// The predefined Standard encoding.
+    Standard,
+    /// The predefined Expert encoding.
+    Expert,
+    /// A custom encoding.
+    Custom {
+        /// The codes of the glyphs after `.notdef`, in glyph order. Glyphs
+        /// beyond the end of this list are unencoded.
+        codes: Vec<u8>,
+        /// Additional codes mapped to glyphs by SID.
+        supplements: Vec<(u8, u16)>,
+    },
+}
+
+impl Encoding {
+    /// The offset used in the Top DICT to refer to a predefined encoding.
+    pub(crate) fn predefined_offset(&self) -> Option<i32> {
+        match self {
+            Encoding::Standard => Some(0),
+            Encoding::Expert => Some(1),
+            Encoding::Custom { .. } => None,
+        }
+    }
+
+    /// Reads an encoding from the given offset, or the predefined encoding if
+    /// the offset is 0 or 1.
+    pub(crate) fn read(data: &[u8], offset: usize) -> Result<Encoding, DeserializationError> {
+        match offset {
+            0 => return Ok(Encoding::Standard),
+            1 => return Ok(Encoding::Expert),
+            _ => {}
+        }
+        let eof = || DeserializationError("Encoding extends past end of table".to_string());
+        let byte_at = |pos: usize| data.get(pos).copied().ok_or_else(eof);
+        let format = byte_at(offset)?;
+        let mut pos = offset + 1;
+        let mut codes = vec![];
+        match format & 0x7f {
+            0 => {
+                let n_codes = byte_at(pos)?;
+                pos += 1;
+                for _ in 0..n_codes {
+                    codes.push(byte_at(pos)?);
+                    pos += 1;
+                }
+            }
+            1 => {
+                let n_ranges = byte_at(pos)?;
+                pos += 1;
+                for _ in 0..n_ranges {
+                    let first = byte_at(pos)?;
+                    let n_left = byte_at(pos + 1)?;
+                    pos += 2;
+                    codes.extend((0..=n_left).map(|i| first.wrapping_add(i)));
+                }
+            }
+            _ => {
+                return Err(DeserializationError(format!(
+                    "Unknown encoding format {}",
+                    format
+                )))
+            }
+        }
+        let mut supplements = vec![];
+        if format & 0x80 != 0 {
+            let n_sups = byte_at(pos)?;
+            pos += 1;
+            for _ in 0..n_sups {
+                let code = byte_at(pos)?;
+                let sid = u16::from_be_bytes([byte_at(pos + 1)?, byte_at(pos + 2)?]);
+                supplements.push((code, sid));
+                pos += 3;
+            }
+        }
+        Ok(Encoding::Custom { codes, supplements })
+    }
+
+    /// Serializes a custom encoding, choosing the most compact format.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let (codes, supplements) = match self {
+            Encoding::Custom { codes, supplements } => (codes, supplements),
+            _ => return vec![],
+        };
+        let mut ranges: Vec<(u8, u8)> = vec![];
+        for &code in codes {
+            match ranges.last_mut() {
+                Some((first, n_left))
+                    if *n_left < 255 && first.wrapping_add(*n_left + 1) == code =>
+                {
+                    *n_left += 1
+                }
+                _ => ranges.push((code, 0)),
+            }
+        }
+        let supplement_flag = if supplements.is_empty() { 0 } else { 0x80 };
+        let mut out = vec![];
+        if codes.len() <= 2 * ranges.len() {
+            out.push(supplement_flag);
+            out.push(codes.len() as u8);
+            out.extend(codes);
+        } else {
+            out.push(1 | supplement_flag);
+            out.push(ranges.len() as u8);
+            for (first, n_left) in ranges {
+                out.push(first);
+                out.push(n_left);
+            }
+        }
+        if !supplements.is_empty() {
+            out.push(supplements.len() as u8);
+            for (code, sid) in supplements {
+                out.push(*code);
+                out.extend_from_slice(&sid.to_be_bytes());
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding_roundtrip() {
+        let encoding = Encoding::Custom {
+            codes: (65..=90).collect(),
+            supplements: vec![(32, 1)],
+        };
+        let binary = encoding.to_bytes();
+        assert_eq!(binary, vec![0x81, 1, 65, 25, 1, 32, 0, 1]);
+        let mut padded = vec![0, 0];
+        padded.extend(binary);
+        assert_eq!(Encoding::read(&padded, 2).unwrap(), encoding);
+
+        let encoding = Encoding::Custom {
+            codes: vec![3, 1, 2],
+            supplements: vec![],
+        };
+        let mut padded = vec![0, 0];
+        padded.extend(encoding.to_bytes());
+        assert_eq!(padded, vec![0, 0, 0, 3, 3, 1, 2]);
+        assert_eq!(Encoding::read(&padded, 2).unwrap(), encoding);
+    }
+}
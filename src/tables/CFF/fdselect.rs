@@ -0,0 +1,125 @@
+//! FDSelect structures, which assign glyphs to font DICTs in CID-keyed fonts.
+use otspec::DeserializationError;
+
+/// Reads an FDSelect structure, returning the font DICT index of each glyph.
+pub(crate) fn read_fd_select(
+    data: &[u8],
+    offset: usize,
+    num_glyphs: usize,
+) -> Result<Vec<u16>, DeserializationError> {
+    let eof = || DeserializationError("FDSelect extends past end of table".to_string());
+    let u16_at = |pos: usize| {
+        data.get(pos..pos + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+            .ok_or_else(eof)
+    };
+    let u32_at = |pos: usize| {
+        data.get(pos..pos + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or_else(eof)
+    };
+    let format = *data.get(offset).ok_or_else(eof)?;
+    match format {
+        0 => data
+            .get(offset + 1..offset + 1 + num_glyphs)
+            .map(|fds| fds.iter().map(|&fd| fd as u16).collect())
+            .ok_or_else(eof),
+        3 | 4 => {
+            // Format 4, used in CFF2, has 32-bit glyph IDs and 16-bit FD indices
+            let (n_ranges, ranges_start, range_size) = if format == 3 {
+                (u16_at(offset + 1)?, offset + 3, 3)
+            } else {
+                (u32_at(offset + 1)?, offset + 5, 6)
+            };
+            let glyph_at = |pos| {
+                if format == 3 {
+                    u16_at(pos)
+                } else {
+                    u32_at(pos)
+                }
+            };
+            let mut fds = Vec::with_capacity(num_glyphs);
+            for i in 0..n_ranges {
+                let pos = ranges_start + i * range_size;
+                let first = glyph_at(pos)?;
+                let fd = if format == 3 {
+                    *data.get(pos + 2).ok_or_else(eof)? as u16
+                } else {
+                    u16_at(pos + 4)? as u16
+                };
+                // The first glyph of the next range, or the sentinel
+                let next = glyph_at(pos + range_size)?;
+                if first != fds.len() || next < first {
+                    return Err(DeserializationError("Bad FDSelect ranges".to_string()));
+                }
+                fds.resize(next, fd);
+            }
+            fds.resize(num_glyphs, 0);
+            Ok(fds)
+        }
+        _ => Err(DeserializationError(format!(
+            "Unknown FDSelect format {}",
+            format
+        ))),
+    }
+}
+
+/// Serializes an FDSelect structure, choosing the most compact format.
+///
+/// Format 4 is only used if it is required, which can only happen in CFF2.
+pub(crate) fn write_fd_select(fds: &[u16]) -> Vec<u8> {
+    let mut ranges: Vec<(usize, u16)> = vec![];
+    for (gid, &fd) in fds.iter().enumerate() {
+        if ranges.last().map(|r| r.1) != Some(fd) {
+            ranges.push((gid, fd));
+        }
+    }
+    let mut out = vec![];
+    if fds.len() > 0xffff || fds.iter().any(|&fd| fd > 0xff) {
+        out.push(4);
+        out.extend_from_slice(&(ranges.len() as u32).to_be_bytes());
+        for (first, fd) in ranges {
+            out.extend_from_slice(&(first as u32).to_be_bytes());
+            out.extend_from_slice(&fd.to_be_bytes());
+        }
+        out.extend_from_slice(&(fds.len() as u32).to_be_bytes());
+    } else if fds.len() <= 4 + 3 * ranges.len() {
+        out.push(0);
+        out.extend(fds.iter().map(|&fd| fd as u8));
+    } else {
+        out.push(3);
+        out.extend_from_slice(&(ranges.len() as u16).to_be_bytes());
+        for (first, fd) in ranges {
+            out.extend_from_slice(&(first as u16).to_be_bytes());
+            out.push(fd as u8);
+        }
+        out.extend_from_slice(&(fds.len() as u16).to_be_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fd_select_roundtrip() {
+        let fds: Vec<u16> = [0; 10].iter().chain([1; 10].iter()).copied().collect();
+        let binary = write_fd_select(&fds);
+        assert_eq!(binary, vec![3, 0, 2, 0, 0, 0, 0, 10, 1, 0, 20]);
+        assert_eq!(read_fd_select(&binary, 0, fds.len()).unwrap(), fds);
+
+        let fds = vec![0, 1, 0];
+        let binary = write_fd_select(&fds);
+        assert_eq!(binary, vec![0, 0, 1, 0]);
+        assert_eq!(read_fd_select(&binary, 0, fds.len()).unwrap(), fds);
+
+        let fds = vec![0, 300];
+        let binary = write_fd_select(&fds);
+        assert_eq!(
+            binary,
+            vec![4, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 44, 0, 0, 0, 2]
+        );
+        assert_eq!(read_fd_select(&binary, 0, fds.len()).unwrap(), fds);
+    }
+}
@@ -0,0 +1,127 @@
+//! Reading and writing INDEX structures.
+//!
+//! An INDEX is an array of variable-sized objects: a count, an offset size,
+//! an array of offsets and the object data itself.
+use otspec::DeserializationError;
+
+fn read_number(data: &[u8], pos: usize, size: usize) -> Result<usize, DeserializationError> {
+    let bytes = data
+        .get(pos..pos + size)
+        .ok_or_else(|| DeserializationError("INDEX extends past end of table".to_string()))?;
+    Ok(bytes.iter().fold(0, |acc, &b| (acc << 8) | b as usize))
+}
+
+fn read_index_with_count_size(
+    data: &[u8],
+    pos: usize,
+    count_size: usize,
+) -> Result<(Vec<Vec<u8>>, usize), DeserializationError> {
+    let count = read_number(data, pos, count_size)?;
+    if count == 0 {
+        return Ok((vec![], pos + count_size));
+    }
+    let off_size = read_number(data, pos + count_size, 1)?;
+    if !(1..=4).contains(&off_size) {
+        return Err(DeserializationError(format!(
+            "Bad INDEX offset size {}",
+            off_size
+        )));
+    }
+    let offsets_start = pos + count_size + 1;
+    let offsets = (0..=count)
+        .map(|i| read_number(data, offsets_start + i * off_size, off_size))
+        .collect::<Result<Vec<usize>, DeserializationError>>()?;
+    // Offsets are one-based, relative to the byte before the object data.
+    let base = offsets_start + (count + 1) * off_size - 1;
+    let items = offsets
+        .windows(2)
+        .map(|w| {
+            if w[0] == 0 || w[1] < w[0] {
+                return Err(DeserializationError("Bad INDEX offsets".to_string()));
+            }
+            data.get(base + w[0]..base + w[1])
+                .map(|item| item.to_vec())
+                .ok_or_else(|| DeserializationError("INDEX extends past end of table".to_string()))
+        })
+        .collect::<Result<Vec<Vec<u8>>, DeserializationError>>()?;
+    Ok((items, base + offsets[count]))
+}
+
+/// Reads a CFF INDEX starting at `pos`, returning its items and the
+/// position of the first byte after it.
+pub(crate) fn read_index(
+    data: &[u8],
+    pos: usize,
+) -> Result<(Vec<Vec<u8>>, usize), DeserializationError> {
+    read_index_with_count_size(data, pos, 2)
+}
+
+/// Reads a CFF2 INDEX, which has a 32-bit count, starting at `pos`.
+pub(crate) fn read_index2(
+    data: &[u8],
+    pos: usize,
+) -> Result<(Vec<Vec<u8>>, usize), DeserializationError> {
+    read_index_with_count_size(data, pos, 4)
+}
+
+fn write_index_with_count_size<T: AsRef<[u8]>>(items: &[T], count_size: usize) -> Vec<u8> {
+    let count = items.len();
+    let mut out: Vec<u8> = (count as u64).to_be_bytes()[8 - count_size..].to_vec();
+    if count == 0 {
+        return out;
+    }
+    let last_offset = 1 + items.iter().map(|i| i.as_ref().len()).sum::<usize>();
+    let off_size = match last_offset {
+        0..=0xff => 1,
+        0x100..=0xffff => 2,
+        0x10000..=0xffffff => 3,
+        _ => 4,
+    };
+    out.push(off_size as u8);
+    let mut offset = 1;
+    out.extend_from_slice(&(offset as u64).to_be_bytes()[8 - off_size..]);
+    for item in items {
+        offset += item.as_ref().len();
+        out.extend_from_slice(&(offset as u64).to_be_bytes()[8 - off_size..]);
+    }
+    for item in items {
+        out.extend_from_slice(item.as_ref());
+    }
+    out
+}
+
+/// Writes a list of items as a CFF INDEX, using the smallest offset size
+/// which can address the data.
+pub(crate) fn write_index<T: AsRef<[u8]>>(items: &[T]) -> Vec<u8> {
+    write_index_with_count_size(items, 2)
+}
+
+/// Writes a list of items as a CFF2 INDEX.
+pub(crate) fn write_index2<T: AsRef<[u8]>>(items: &[T]) -> Vec<u8> {
+    write_index_with_count_size(items, 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_roundtrip() {
+        let items = vec![b"abc".to_vec(), vec![], b"de".to_vec()];
+        let binary = write_index(&items);
+        assert_eq!(
+            binary,
+            vec![0, 3, 1, 1, 4, 4, 6, b'a', b'b', b'c', b'd', b'e']
+        );
+        assert_eq!(read_index(&binary, 0).unwrap(), (items, binary.len()));
+
+        let empty: Vec<Vec<u8>> = vec![];
+        assert_eq!(write_index(&empty), vec![0, 0]);
+        assert_eq!(read_index(&[0, 0], 0).unwrap(), (empty, 2));
+
+        let big = vec![vec![0; 300]];
+        let binary = write_index2(&big);
+        assert_eq!(&binary[0..9], &[0, 0, 0, 1, 2, 0, 1, 1, 45]);
+        assert_eq!(read_index2(&binary, 0).unwrap(), (big, binary.len()));
+    }
+}
@@ -0,0 +1,589 @@
+//! A charstring subroutinizer.
+//!
+//! Charstrings are split into commands (an operator and its arguments), and
+//! sequences of commands which are repeated across glyphs are factored out
+//! into subroutines. The search is greedy: each round, the candidate
+//! sequences which would save the most space are turned into subroutines
+//! first. Later rounds also look inside existing subroutines, which gives
+//! nested subroutines.
+use super::charstring::{encode, operators, subr_bias, Token, MAX_SUBR_NESTING};
+use std::collections::{BTreeSet, HashMap};
+
+/// The longest sequence of commands considered for a subroutine.
+const MAX_CANDIDATE_LENGTH: usize = 32;
+/// The number of times the search for repeated sequences is run.
+const ROUNDS: usize = 3;
+/// The estimated size of a subroutine call: the subroutine number and the
+/// operator.
+const CALL_COST: usize = 3;
+/// The estimated size of a subroutine besides its body: the `return`
+/// operator and its entry in the INDEX.
+const SUBR_OVERHEAD: usize = 3;
+/// The number of subroutine numbers which can be encoded in one byte.
+const ONE_BYTE_SUBRS: usize = 215;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Item {
+    /// A command, as an index into the table of distinct commands
+    Command(usize),
+    /// A call to a subroutine, by index into the list of subroutines
+    Call(usize),
+}
+
+/// The result of subroutinizing a font.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Subroutinized {
+    /// The new charstring of each glyph.
+    pub charstrings: Vec<Vec<u8>>,
+    /// The global subroutines.
+    pub global_subrs: Vec<Vec<u8>>,
+    /// The local subroutines of each Font DICT (or of the only Private DICT
+    /// in fonts which are not CID-keyed).
+    pub local_subrs: Vec<Vec<Vec<u8>>>,
+}
+
+struct Subroutinizer {
+    commands: Vec<Vec<Token>>,
+    command_sizes: Vec<usize>,
+    glyphs: Vec<Vec<Item>>,
+    subrs: Vec<Vec<Item>>,
+    max_nesting: usize,
+}
+
+/// Splits a charstring into commands. Each command ends with an operator
+/// which clears the argument stack, so that a subroutine call can be placed
+/// between any two commands.
+fn split_commands(tokens: &[Token]) -> Vec<Vec<Token>> {
+    let mut commands = vec![];
+    let mut current = vec![];
+    let mut iter = tokens.iter().peekable();
+    while let Some(token) = iter.next() {
+        current.push(token.clone());
+        let clears_stack = match token {
+            Token::Operator(operators::BLEND) => false,
+            Token::Operator(op) if *op >= 0x0c00 => {
+                matches!(
+                    *op,
+                    operators::HFLEX | operators::FLEX | operators::HFLEX1 | operators::FLEX1
+                )
+            }
+            Token::Operator(_) => true,
+            _ => false,
+        };
+        if clears_stack {
+            if let Some(Token::Mask(_)) = iter.peek() {
+                current.push(iter.next().unwrap().clone());
+            }
+            commands.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        commands.push(current);
+    }
+    commands
+}
+
+/// Finds the non-overlapping occurrences of `key` in `seq`.
+fn occurrences(seq: &[Item], key: &[Item]) -> Vec<usize> {
+    let mut found = vec![];
+    let mut i = 0;
+    while i + key.len() <= seq.len() {
+        if seq[i..i + key.len()] == *key {
+            found.push(i);
+            i += key.len();
+        } else {
+            i += 1;
+        }
+    }
+    found
+}
+
+fn replace(seq: &[Item], key_len: usize, positions: &[usize], item: Item) -> Vec<Item> {
+    let mut out = Vec::with_capacity(seq.len());
+    let mut i = 0;
+    for &pos in positions {
+        out.extend_from_slice(&seq[i..pos]);
+        out.push(item);
+        i = pos + key_len;
+    }
+    out.extend_from_slice(&seq[i..]);
+    out
+}
+
+fn calls(seq: &[Item]) -> impl Iterator<Item = usize> + '_ {
+    seq.iter().filter_map(|item| match item {
+        Item::Call(subr) => Some(*subr),
+        _ => None,
+    })
+}
+
+impl Subroutinizer {
+    fn item_size(&self, item: &Item) -> usize {
+        match item {
+            Item::Command(c) => self.command_sizes[*c],
+            Item::Call(_) => CALL_COST,
+        }
+    }
+
+    fn size(&self, seq: &[Item]) -> usize {
+        seq.iter().map(|i| self.item_size(i)).sum()
+    }
+
+    /// The length of the longest chain of calls starting at each subroutine,
+    /// counting the subroutine itself.
+    fn heights(&self) -> Vec<usize> {
+        let mut heights: Vec<Option<usize>> = vec![None; self.subrs.len()];
+        fn visit(s: &Subroutinizer, subr: usize, heights: &mut Vec<Option<usize>>) -> usize {
+            if let Some(h) = heights[subr] {
+                return h;
+            }
+            let h = 1 + calls(&s.subrs[subr])
+                .map(|callee| visit(s, callee, heights))
+                .max()
+                .unwrap_or(0);
+            heights[subr] = Some(h);
+            h
+        }
+        for subr in 0..self.subrs.len() {
+            visit(self, subr, &mut heights);
+        }
+        heights.into_iter().map(|h| h.unwrap_or(1)).collect()
+    }
+
+    /// The nesting level at which each subroutine is called; a subroutine
+    /// called from a charstring is at level 1.
+    fn depths(&self, heights: &[usize]) -> Vec<usize> {
+        // Callers are always taller than their callees, so visiting
+        // subroutines from the tallest gives every caller's depth first.
+        let mut order: Vec<usize> = (0..self.subrs.len()).collect();
+        order.sort_by_key(|&s| std::cmp::Reverse(heights[s]));
+        let mut depths = vec![0; self.subrs.len()];
+        for glyph in &self.glyphs {
+            for callee in calls(glyph) {
+                depths[callee] = 1;
+            }
+        }
+        for subr in order {
+            let depth = depths[subr];
+            for callee in calls(&self.subrs[subr]) {
+                depths[callee] = depths[callee].max(depth + 1);
+            }
+        }
+        depths
+    }
+
+    /// Finds repeated sequences, with an estimate of the space saved by
+    /// turning each into a subroutine, most promising first.
+    fn candidates(&self) -> Vec<Vec<Item>> {
+        // For each sequence: the number of non-overlapping occurrences, and
+        // the sequence and position after the last one counted.
+        let mut counts: HashMap<&[Item], (usize, usize, usize)> = HashMap::new();
+        let sequences = self.glyphs.iter().chain(self.subrs.iter());
+        for (seq_ix, seq) in sequences.enumerate() {
+            for start in 0..seq.len() {
+                let max_len = MAX_CANDIDATE_LENGTH.min(seq.len() - start);
+                for len in 1..=max_len {
+                    let key = &seq[start..start + len];
+                    if len == 1 && matches!(key[0], Item::Call(_)) {
+                        continue;
+                    }
+                    if seq_ix >= self.glyphs.len() && len == seq.len() {
+                        // This is an entire subroutine
+                        continue;
+                    }
+                    let entry = counts.entry(key).or_insert((0, usize::MAX, 0));
+                    if entry.1 != seq_ix || entry.2 <= start {
+                        *entry = (entry.0 + 1, seq_ix, start + len);
+                    }
+                }
+            }
+        }
+        let mut scored: Vec<(usize, &[Item])> = counts
+            .into_iter()
+            .filter_map(|(key, (count, _, _))| {
+                let saving = self.saving(key, count);
+                if saving > 0 {
+                    Some((saving, key))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        scored.into_iter().map(|(_, key)| key.to_vec()).collect()
+    }
+
+    /// The space saved by replacing `count` occurrences of a sequence with
+    /// calls to a new subroutine.
+    fn saving(&self, key: &[Item], count: usize) -> usize {
+        let size = self.size(key);
+        if count < 2 || size <= CALL_COST {
+            return 0;
+        }
+        (count * (size - CALL_COST)).saturating_sub(size + SUBR_OVERHEAD)
+    }
+
+    /// Turns a sequence into a subroutine, if that still saves space and
+    /// does not nest subroutines too deeply.
+    fn apply(&mut self, key: &[Item]) {
+        let heights = self.heights();
+        let height = 1 + calls(key).map(|c| heights[c]).max().unwrap_or(0);
+        if height > self.max_nesting {
+            return;
+        }
+        let depths = self.depths(&heights);
+        let glyph_hits: Vec<Vec<usize>> = self.glyphs.iter().map(|g| occurrences(g, key)).collect();
+        let subr_hits: Vec<Vec<usize>> = self
+            .subrs
+            .iter()
+            .enumerate()
+            .map(|(ix, body)| {
+                if body.len() == key.len() || depths[ix] + height > self.max_nesting {
+                    vec![]
+                } else {
+                    occurrences(body, key)
+                }
+            })
+            .collect();
+        let count: usize = glyph_hits
+            .iter()
+            .chain(subr_hits.iter())
+            .map(|h| h.len())
+            .sum();
+        if self.saving(key, count) == 0 {
+            return;
+        }
+        let call = Item::Call(self.subrs.len());
+        for (glyph, hits) in self.glyphs.iter_mut().zip(glyph_hits) {
+            if !hits.is_empty() {
+                *glyph = replace(glyph, key.len(), &hits, call);
+            }
+        }
+        for (body, hits) in self.subrs.iter_mut().zip(subr_hits) {
+            if !hits.is_empty() {
+                *body = replace(body, key.len(), &hits, call);
+            }
+        }
+        self.subrs.push(key.to_vec());
+    }
+
+    /// The number of calls to each subroutine.
+    fn call_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.subrs.len()];
+        for seq in self.glyphs.iter().chain(self.subrs.iter()) {
+            for callee in calls(seq) {
+                counts[callee] += 1;
+            }
+        }
+        counts
+    }
+
+    /// Inlines subroutines which are only called once, which can happen
+    /// when all their callers have been absorbed into a larger subroutine.
+    fn inline_single_calls(&mut self) {
+        loop {
+            let counts = self.call_counts();
+            let single = match (0..self.subrs.len()).find(|&s| counts[s] == 1) {
+                Some(s) => s,
+                None => break,
+            };
+            let body = std::mem::take(&mut self.subrs[single]);
+            let inline = |seq: &mut Vec<Item>| {
+                if let Some(pos) = seq.iter().position(|&i| i == Item::Call(single)) {
+                    seq.splice(pos..pos + 1, body.iter().copied());
+                }
+            };
+            self.glyphs.iter_mut().for_each(inline);
+            self.subrs.iter_mut().for_each(inline);
+        }
+    }
+}
+
+/// Factors repeated sequences of commands in the given charstrings out into
+/// global and local subroutines.
+///
+/// `fd_select` gives the Font DICT used by each glyph. Subroutines used by
+/// glyphs with more than one Font DICT are made global, as are all
+/// subroutines they call; the rest are local. In fonts with a single Private
+/// DICT, subroutines are shared between the two lists so that as many as
+/// possible have short subroutine numbers.
+///
+/// CFF2 subroutines do not end with a `return` operator.
+pub(crate) fn subroutinize(
+    glyphs: &[Vec<Token>],
+    fd_select: &[usize],
+    fd_count: usize,
+    cff2: bool,
+    max_nesting: usize,
+) -> Subroutinized {
+    let mut commands: Vec<Vec<Token>> = vec![];
+    let mut command_sizes = vec![];
+    let mut command_ids: HashMap<Vec<u8>, usize> = HashMap::new();
+    let glyph_items = glyphs
+        .iter()
+        .map(|tokens| {
+            split_commands(tokens)
+                .into_iter()
+                .map(|command| {
+                    // Tokens are not hashable, so key on the encoded command
+                    let encoded = encode(&command);
+                    let size = encoded.len();
+                    let id = *command_ids.entry(encoded).or_insert_with(|| {
+                        commands.push(command);
+                        command_sizes.push(size);
+                        commands.len() - 1
+                    });
+                    Item::Command(id)
+                })
+                .collect()
+        })
+        .collect();
+    let mut s = Subroutinizer {
+        commands,
+        command_sizes,
+        glyphs: glyph_items,
+        subrs: vec![],
+        max_nesting: max_nesting.min(MAX_SUBR_NESTING),
+    };
+    for _ in 0..ROUNDS {
+        let before = s.subrs.len();
+        for candidate in s.candidates() {
+            s.apply(&candidate);
+        }
+        if s.subrs.len() == before {
+            break;
+        }
+    }
+    s.inline_single_calls();
+
+    // Work out which Font DICTs use each subroutine
+    let mut users: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); s.subrs.len()];
+    let mut stack: Vec<(usize, usize)> = vec![];
+    for (gid, glyph) in s.glyphs.iter().enumerate() {
+        let fd = fd_select.get(gid).copied().unwrap_or(0);
+        stack.extend(calls(glyph).map(|c| (c, fd)));
+    }
+    while let Some((subr, fd)) = stack.pop() {
+        if users[subr].insert(fd) {
+            stack.extend(calls(&s.subrs[subr]).map(|c| (c, fd)));
+        }
+    }
+    let counts = s.call_counts();
+    let mut live: Vec<usize> = (0..s.subrs.len()).filter(|&i| counts[i] > 0).collect();
+    live.sort_by_key(|&i| (std::cmp::Reverse(counts[i]), i));
+
+    // Assign each subroutine to a list: None for global, or a Font DICT
+    let mut assignment: Vec<Option<usize>> = vec![None; s.subrs.len()];
+    if fd_count > 1 {
+        for &subr in &live {
+            if users[subr].len() == 1 {
+                assignment[subr] = users[subr].iter().next().copied();
+            }
+        }
+        // Global subroutines can only call global subroutines
+        let mut stack: Vec<usize> = live
+            .iter()
+            .copied()
+            .filter(|&i| assignment[i].is_none())
+            .collect();
+        while let Some(subr) = stack.pop() {
+            for callee in calls(&s.subrs[subr]) {
+                if assignment[callee].is_some() {
+                    assignment[callee] = None;
+                    stack.push(callee);
+                }
+            }
+        }
+    } else {
+        for (rank, &subr) in live.iter().enumerate() {
+            if (rank / ONE_BYTE_SUBRS).is_multiple_of(2) {
+                assignment[subr] = Some(0);
+            }
+        }
+    }
+    let global: Vec<usize> = live
+        .iter()
+        .copied()
+        .filter(|&i| assignment[i].is_none())
+        .collect();
+    let locals: Vec<Vec<usize>> = (0..fd_count.max(1))
+        .map(|fd| {
+            live.iter()
+                .copied()
+                .filter(|&i| assignment[i] == Some(fd))
+                .collect()
+        })
+        .collect();
+    let mut numbers = vec![0; s.subrs.len()];
+    for list in std::iter::once(&global).chain(locals.iter()) {
+        let bias = subr_bias(list.len());
+        for (ix, &subr) in list.iter().enumerate() {
+            numbers[subr] = ix as i32 - bias;
+        }
+    }
+
+    let to_tokens = |seq: &[Item]| -> Vec<Token> {
+        let mut tokens = vec![];
+        for item in seq {
+            match item {
+                Item::Command(c) => tokens.extend(s.commands[*c].iter().cloned()),
+                Item::Call(subr) => {
+                    tokens.push(Token::Number(numbers[*subr] as f64));
+                    tokens.push(Token::Operator(if assignment[*subr].is_some() {
+                        operators::CALLSUBR
+                    } else {
+                        operators::CALLGSUBR
+                    }));
+                }
+            }
+        }
+        tokens
+    };
+    let subr_bytes = |subr: &usize| {
+        let mut tokens = to_tokens(&s.subrs[*subr]);
+        if !cff2 && tokens.last() != Some(&Token::Operator(operators::ENDCHAR)) {
+            tokens.push(Token::Operator(operators::RETURN));
+        }
+        encode(&tokens)
+    };
+    Subroutinized {
+        charstrings: s.glyphs.iter().map(|g| encode(&to_tokens(g))).collect(),
+        global_subrs: global.iter().map(subr_bytes).collect(),
+        local_subrs: locals
+            .iter()
+            .map(|list| list.iter().map(subr_bytes).collect())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::charstring::{execute, Context};
+    use super::*;
+
+    /// Glyphs drawn from a few repeated shapes, in a pseudo-random order.
+    fn test_glyphs(count: usize) -> Vec<Vec<Token>> {
+        let shapes: Vec<Vec<Token>> = (0..6)
+            .map(|shape| {
+                let mut tokens = vec![];
+                for i in 0..4 {
+                    for j in 0..6 {
+                        tokens.push(Token::Number((shape * 100 + i * 10 + j) as f64));
+                    }
+                    tokens.push(Token::Operator(operators::RRCURVETO));
+                }
+                tokens
+            })
+            .collect();
+        let mut state: usize = 7;
+        (0..count)
+            .map(|_| {
+                let mut glyph = vec![
+                    Token::Number(10.0),
+                    Token::Number(20.0),
+                    Token::Operator(operators::RMOVETO),
+                ];
+                for _ in 0..5 {
+                    state = (state * 1103515245 + 12345) % 2147483648;
+                    glyph.extend(shapes[(state >> 16) % shapes.len()].iter().cloned());
+                }
+                glyph.push(Token::Operator(operators::ENDCHAR));
+                glyph
+            })
+            .collect()
+    }
+
+    fn context<'a>(global_subrs: &'a [Vec<u8>], local_subrs: &'a [Vec<u8>]) -> Context<'a> {
+        Context {
+            global_subrs,
+            local_subrs,
+            default_width_x: 0.0,
+            nominal_width_x: 0.0,
+            variations: None,
+        }
+    }
+
+    #[test]
+    fn test_subroutinize() {
+        let glyphs = test_glyphs(30);
+        let result = subroutinize(&glyphs, &[0; 30], 1, false, MAX_SUBR_NESTING);
+        assert!(!result.global_subrs.is_empty() || !result.local_subrs[0].is_empty());
+        let before: usize = glyphs.iter().map(|g| encode(g).len()).sum();
+        let after: usize = result
+            .charstrings
+            .iter()
+            .chain(result.global_subrs.iter())
+            .chain(result.local_subrs[0].iter())
+            .map(|c| c.len())
+            .sum();
+        assert!(
+            after * 2 < before,
+            "{} is not much less than {}",
+            after,
+            before
+        );
+        for (glyph, charstring) in glyphs.iter().zip(result.charstrings.iter()) {
+            let expected = execute(&encode(glyph), context(&[], &[])).unwrap();
+            let actual = execute(
+                charstring,
+                context(&result.global_subrs, &result.local_subrs[0]),
+            )
+            .unwrap();
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn test_subroutinize_nesting() {
+        let glyphs = test_glyphs(30);
+        let result = subroutinize(&glyphs, &[0; 30], 1, false, 1);
+        let subrs = result
+            .global_subrs
+            .iter()
+            .chain(result.local_subrs[0].iter());
+        for subr in subrs {
+            let tokens = super::super::charstring::desubroutinize(subr, context(&[], &[]));
+            // Any call would fail, as there are no subroutines in the context
+            assert!(tokens.is_ok());
+        }
+    }
+
+    #[test]
+    fn test_subroutinize_cid() {
+        // The first ten glyphs use Font DICT 0, the rest Font DICT 1. Only
+        // the shapes which are used in both can be global.
+        let mut glyphs = test_glyphs(20);
+        let private_shape: Vec<Token> = (0..12)
+            .map(|i| Token::Number(i as f64 + 500.0))
+            .chain(std::iter::once(Token::Operator(operators::RRCURVETO)))
+            .collect();
+        for glyph in glyphs.iter_mut().skip(10) {
+            glyph.splice(3..3, private_shape.iter().cloned());
+        }
+        let fd_select: Vec<usize> = (0..20).map(|gid| gid / 10).collect();
+        let result = subroutinize(&glyphs, &fd_select, 2, false, MAX_SUBR_NESTING);
+        assert!(!result.global_subrs.is_empty());
+        assert!(!result.local_subrs[1].is_empty());
+        for (gid, glyph) in glyphs.iter().enumerate() {
+            let expected = execute(&encode(glyph), context(&[], &[])).unwrap();
+            let local_subrs = &result.local_subrs[fd_select[gid]];
+            let actual = execute(
+                &result.charstrings[gid],
+                context(&result.global_subrs, local_subrs),
+            )
+            .unwrap();
+            assert_eq!(expected, actual);
+        }
+        // The shape only used by Font DICT 1 is not in a global subroutine
+        let private_bytes = encode(&private_shape);
+        for subr in result
+            .global_subrs
+            .iter()
+            .chain(result.local_subrs[0].iter())
+        {
+            assert!(!subr
+                .windows(private_bytes.len())
+                .any(|w| w == private_bytes));
+        }
+    }
+}
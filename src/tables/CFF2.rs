@@ -0,0 +1,545 @@
+use crate::otvar::{ItemVariationStore, NormalizedLocation, PinnedRegions};
+use crate::tables::CFF::charstring::{self, Context, Outline, Token, Variations};
+use crate::tables::CFF::fdselect::{read_fd_select, write_fd_select};
+use crate::tables::CFF::index::{read_index2, write_index2};
+use crate::tables::CFF::subroutinizer;
+use crate::tables::CFF::{operators, put_offsets, Dict, FontDict, Operand, PrivateDict};
+use otspec::types::*;
+use otspec::{DeserializationError, Deserialize, ReaderContext, SerializationError, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+/// The 'CFF2' OpenType tag.
+pub const TAG: Tag = crate::tag!("CFF2");
+
+/// The Compact Font Format (version 2) table
+///
+/// Unlike CFF, CFF2 has no names, strings, charset or encoding, and every
+/// glyph uses a Font DICT from the FDArray. Operators in the Top DICT which
+/// refer to other structures (`CharStrings`, `vstore`, `FDArray` and
+/// `FDSelect`) are removed when the table is read and regenerated when it
+/// is written.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CFF2 {
+    /// The Top DICT.
+    pub top_dict: Dict,
+    /// Global subroutines, shared by all glyphs.
+    pub global_subrs: Vec<Vec<u8>>,
+    /// The charstring of each glyph.
+    pub charstrings: Vec<Vec<u8>>,
+    /// The variation store used by `blend` operators.
+    pub variation_store: Option<ItemVariationStore>,
+    /// The Font DICTs.
+    pub fd_array: Vec<FontDict>,
+    /// The index into `fd_array` for each glyph, if there is more than one
+    /// Font DICT.
+    pub fd_select: Option<Vec<u16>>,
+}
+
+/// Converts the operands of a DICT entry to charstring tokens, so that
+/// blends can be processed in the same way.
+fn operands_to_tokens(operands: &[Operand]) -> Vec<Token> {
+    operands
+        .iter()
+        .map(|operand| match operand {
+            Operand::Blend => Token::Operator(charstring::operators::BLEND),
+            other => Token::Number(other.as_f64()),
+        })
+        .collect()
+}
+
+fn tokens_to_operands(tokens: &[Token]) -> Vec<Operand> {
+    tokens
+        .iter()
+        .map(|token| match token {
+            Token::Number(n) if n.fract() == 0.0 => Operand::Integer(*n as i32),
+            Token::Number(n) => Operand::Real(*n),
+            _ => Operand::Blend,
+        })
+        .collect()
+}
+
+impl CFF2 {
+    /// The number of glyphs in the font.
+    pub fn num_glyphs(&self) -> usize {
+        self.charstrings.len()
+    }
+
+    /// The Private DICT which applies to a glyph.
+    pub fn private_dict(&self, gid: usize) -> Option<&PrivateDict> {
+        let fd = match &self.fd_select {
+            Some(fd_select) => *fd_select.get(gid)? as usize,
+            None => 0,
+        };
+        self.fd_array.get(fd).map(|fd| &fd.private)
+    }
+
+    /// The font matrix, which maps glyph space to text space.
+    pub fn font_matrix(&self) -> [f64; 6] {
+        super::CFF::font_matrix(&self.top_dict)
+    }
+
+    /// The scalars of the regions of each item variation data subtable at a
+    /// location.
+    fn region_scalars(&self, location: &NormalizedLocation) -> Vec<Vec<f32>> {
+        self.variation_store.as_ref().map_or(vec![], |store| {
+            (0..store.variationData.len())
+                .map(|outer| store.region_scalars(outer, location))
+                .collect()
+        })
+    }
+
+    /// Executes the charstring of a glyph at the default location, returning
+    /// its outline and hints. CFF2 charstrings do not contain advance
+    /// widths, so the width of the outline is always zero.
+    pub fn outline(&self, gid: usize) -> Result<Outline, DeserializationError> {
+        self.outline_at(gid, &NormalizedLocation(vec![]))
+    }
+
+    /// Executes the charstring of a glyph at the given location in the
+    /// design space, returning its outline and hints.
+    pub fn outline_at(
+        &self,
+        gid: usize,
+        location: &NormalizedLocation,
+    ) -> Result<Outline, DeserializationError> {
+        let charstring = self
+            .charstrings
+            .get(gid)
+            .ok_or_else(|| DeserializationError(format!("Glyph {} not found", gid)))?;
+        let region_scalars = self.region_scalars(location);
+        let context = self.context(gid, &region_scalars);
+        charstring::execute(charstring, context).map(|(outline, _)| outline)
+    }
+
+    /// The item variation data used by a glyph's charstring if it has no
+    /// `vsindex` operator.
+    fn vsindex(&self, gid: usize) -> usize {
+        self.private_dict(gid)
+            .and_then(|p| p.dict.get_i32(operators::VSINDEX))
+            .unwrap_or(0) as usize
+    }
+
+    fn context<'a>(&'a self, gid: usize, region_scalars: &'a [Vec<f32>]) -> Context<'a> {
+        Context {
+            global_subrs: &self.global_subrs,
+            local_subrs: self.private_dict(gid).map_or(&[], |p| &p.subrs),
+            default_width_x: 0.0,
+            nominal_width_x: 0.0,
+            variations: Some(Variations {
+                region_scalars,
+                vsindex: self.vsindex(gid),
+            }),
+        }
+    }
+
+    fn desubroutinized_glyphs(&self) -> Result<Vec<Vec<Token>>, DeserializationError> {
+        // Only the number of regions matters when desubroutinizing
+        let region_counts: Vec<Vec<f32>> = self.variation_store.as_ref().map_or(vec![], |store| {
+            store
+                .variationData
+                .iter()
+                .map(|data| vec![0.0; data.region_indexes.len()])
+                .collect()
+        });
+        self.charstrings
+            .iter()
+            .enumerate()
+            .map(|(gid, charstring)| {
+                charstring::desubroutinize(charstring, self.context(gid, &region_counts))
+            })
+            .collect()
+    }
+
+    /// Rewrites the charstrings so that repeated sequences of operators are
+    /// shared in subroutines, replacing any existing subroutines.
+    ///
+    /// Subroutines used by glyphs with more than one Font DICT become global
+    /// subroutines, and the rest become local subroutines of their Font DICT.
+    pub fn subroutinize(&mut self) -> Result<(), DeserializationError> {
+        let glyphs = self.desubroutinized_glyphs()?;
+        let fd_select: Vec<usize> = match &self.fd_select {
+            Some(fds) => fds.iter().map(|&fd| fd as usize).collect(),
+            None => vec![0; glyphs.len()],
+        };
+        let result = subroutinizer::subroutinize(
+            &glyphs,
+            &fd_select,
+            self.fd_array.len(),
+            true,
+            charstring::MAX_SUBR_NESTING,
+        );
+        self.charstrings = result.charstrings;
+        self.global_subrs = result.global_subrs;
+        for (fd, subrs) in self.fd_array.iter_mut().zip(result.local_subrs) {
+            fd.private.subrs = subrs;
+        }
+        Ok(())
+    }
+
+    /// Pins some axes of the font to a location.
+    ///
+    /// `location` maps axis indices to normalized coordinates. The
+    /// charstrings are desubroutinized, and their blends (and those in the
+    /// Private DICTs) are rewritten to vary only along the remaining axes.
+    /// If all axes are pinned, the variation store is removed.
+    pub fn instantiate(
+        &mut self,
+        location: &BTreeMap<usize, f32>,
+    ) -> Result<(), DeserializationError> {
+        let store = match &self.variation_store {
+            Some(store) => store,
+            None => return Ok(()),
+        };
+        let (new_store, mappings) = store.pin_axes(location);
+        self.rebase_blends(new_store, &mappings)
+    }
+
+    /// Replaces the variation store, rewriting the blends of the
+    /// charstrings and Private DICTs with the [`PinnedRegions`] describing
+    /// how the deltas of each item variation data subtable move to the new
+    /// store. If the new store has no axes, it is removed.
+    pub(crate) fn rebase_blends(
+        &mut self,
+        new_store: ItemVariationStore,
+        mappings: &[PinnedRegions],
+    ) -> Result<(), DeserializationError> {
+        let keep_store = new_store.axisCount > 0;
+        let mut charstrings = Vec::with_capacity(self.charstrings.len());
+        for (gid, tokens) in self.desubroutinized_glyphs()?.iter().enumerate() {
+            let tokens = charstring::pin_blends(tokens, self.vsindex(gid), mappings, keep_store)?;
+            charstrings.push(charstring::encode(&tokens));
+        }
+
+        for fd in self.fd_array.iter_mut() {
+            let dict = &mut fd.private.dict;
+            let vsindex = dict.get_i32(operators::VSINDEX).unwrap_or(0) as usize;
+            for (_, operands) in dict.entries.iter_mut() {
+                if operands.contains(&Operand::Blend) {
+                    let tokens = operands_to_tokens(operands);
+                    let tokens = charstring::pin_blends(&tokens, vsindex, mappings, true)?;
+                    *operands = tokens_to_operands(&tokens);
+                }
+            }
+            if !keep_store {
+                dict.remove(operators::VSINDEX);
+            }
+            fd.private.subrs.clear();
+        }
+        self.charstrings = charstrings;
+        self.global_subrs.clear();
+        self.variation_store = if keep_store { Some(new_store) } else { None };
+        Ok(())
+    }
+}
+
+impl Deserialize for CFF2 {
+    fn from_bytes(c: &mut ReaderContext) -> Result<Self, DeserializationError> {
+        let data = &c.input[c.ptr..];
+        if data.len() < 5 {
+            return Err(DeserializationError("CFF2 header too short".to_string()));
+        }
+        if data[0] != 2 {
+            return Err(DeserializationError(format!(
+                "Unknown CFF2 major version {}",
+                data[0]
+            )));
+        }
+        let header_size = data[2] as usize;
+        let top_dict_length = u16::from_be_bytes([data[3], data[4]]) as usize;
+        let mut top_dict = Dict::from_bytes(
+            data.get(header_size..header_size + top_dict_length)
+                .ok_or_else(|| DeserializationError("Top DICT out of range".to_string()))?,
+        )?;
+        let (global_subrs, _) = read_index2(data, header_size + top_dict_length)?;
+
+        let offset_of = |dict: &mut Dict, op: u16| {
+            dict.remove(op)
+                .and_then(|operands| operands.last().map(|o| o.as_f64() as usize))
+        };
+        let charstrings_offset = offset_of(&mut top_dict, operators::CHAR_STRINGS)
+            .ok_or_else(|| DeserializationError("No CharStrings in CFF2 table".to_string()))?;
+        let charstrings = read_index2(data, charstrings_offset)?.0;
+        let num_glyphs = charstrings.len();
+        let variation_store = match offset_of(&mut top_dict, operators::VSTORE) {
+            Some(offset) => {
+                let length = data
+                    .get(offset..offset + 2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+                    .ok_or_else(|| DeserializationError("vstore out of range".to_string()))?;
+                let store_data = data
+                    .get(offset + 2..offset + 2 + length)
+                    .ok_or_else(|| DeserializationError("vstore out of range".to_string()))?;
+                Some(otspec::de::from_bytes(store_data)?)
+            }
+            None => None,
+        };
+        let fd_array_offset = offset_of(&mut top_dict, operators::FD_ARRAY)
+            .ok_or_else(|| DeserializationError("No FDArray in CFF2 table".to_string()))?;
+        let fd_select = match offset_of(&mut top_dict, operators::FD_SELECT) {
+            Some(offset) => Some(read_fd_select(data, offset, num_glyphs)?),
+            None => None,
+        };
+        let mut fd_array = vec![];
+        for font_dict in read_index2(data, fd_array_offset)?.0 {
+            let mut dict = Dict::from_bytes(&font_dict)?;
+            let private = match dict.remove(operators::PRIVATE).as_deref() {
+                Some([size, offset]) => {
+                    PrivateDict::read(data, size.as_f64() as usize, offset.as_f64() as usize, true)?
+                }
+                _ => {
+                    return Err(DeserializationError(
+                        "Bad Private DICT operands".to_string(),
+                    ))
+                }
+            };
+            fd_array.push(FontDict { dict, private });
+        }
+        c.ptr = c.input.len();
+        Ok(CFF2 {
+            top_dict,
+            global_subrs,
+            charstrings,
+            variation_store,
+            fd_array,
+            fd_select,
+        })
+    }
+}
+
+impl Serialize for CFF2 {
+    fn to_bytes(&self, data: &mut Vec<u8>) -> Result<(), SerializationError> {
+        if let Some(fd_select) = &self.fd_select {
+            if fd_select.len() != self.charstrings.len() {
+                return Err(SerializationError(
+                    "FDSelect does not cover every glyph".to_string(),
+                ));
+            }
+        }
+        let variation_store = match &self.variation_store {
+            Some(store) => {
+                let store = otspec::ser::to_bytes(store)?;
+                let length: u16 = store
+                    .len()
+                    .try_into()
+                    .map_err(|_| SerializationError("vstore too large".to_string()))?;
+                let mut out = length.to_be_bytes().to_vec();
+                out.extend(store);
+                out
+            }
+            None => vec![],
+        };
+        let fd_select = self
+            .fd_select
+            .as_ref()
+            .map_or(vec![], |fds| write_fd_select(fds));
+        let charstrings = write_index2(&self.charstrings);
+        let privates: Vec<(Vec<u8>, usize)> = self
+            .fd_array
+            .iter()
+            .map(|fd| fd.private.to_bytes(true))
+            .collect();
+
+        // Offsets are written with a fixed size, so we can lay out the DICTs
+        // before we know where everything will go.
+        let top_dict = |offsets: &[usize]| -> Result<Vec<u8>, SerializationError> {
+            let mut out = self.top_dict.to_bytes();
+            put_offsets(&mut out, operators::CHAR_STRINGS, &offsets[0..1])?;
+            put_offsets(&mut out, operators::FD_ARRAY, &offsets[1..2])?;
+            if self.fd_select.is_some() {
+                put_offsets(&mut out, operators::FD_SELECT, &offsets[2..3])?;
+            }
+            if self.variation_store.is_some() {
+                put_offsets(&mut out, operators::VSTORE, &offsets[3..4])?;
+            }
+            Ok(out)
+        };
+        let fd_array = |private_offsets: &[usize]| -> Result<Vec<u8>, SerializationError> {
+            let dicts = self
+                .fd_array
+                .iter()
+                .zip(privates.iter().zip(private_offsets))
+                .map(|(fd, ((_, size), &offset))| {
+                    let mut out = fd.dict.to_bytes();
+                    put_offsets(&mut out, operators::PRIVATE, &[*size, offset])?;
+                    Ok(out)
+                })
+                .collect::<Result<Vec<Vec<u8>>, SerializationError>>()?;
+            Ok(write_index2(&dicts))
+        };
+
+        let header_size = 5;
+        let top_dict_size = top_dict(&[0; 4])?.len();
+        let global_subrs = write_index2(&self.global_subrs);
+        let fd_array_size = fd_array(&vec![0; privates.len()])?.len();
+
+        let mut pos = header_size + top_dict_size + global_subrs.len();
+        let vstore_offset = pos;
+        pos += variation_store.len();
+        let fd_select_offset = pos;
+        pos += fd_select.len();
+        let charstrings_offset = pos;
+        pos += charstrings.len();
+        let fd_array_offset = pos;
+        pos += fd_array_size;
+        let mut private_offsets = vec![];
+        for (private, _) in &privates {
+            private_offsets.push(pos);
+            pos += private.len();
+        }
+
+        let top_dict = top_dict(&[
+            charstrings_offset,
+            fd_array_offset,
+            fd_select_offset,
+            vstore_offset,
+        ])?;
+        let top_dict_length: u16 = top_dict
+            .len()
+            .try_into()
+            .map_err(|_| SerializationError("Top DICT too large".to_string()))?;
+        data.extend_from_slice(&[2, 0, header_size as u8]);
+        data.extend_from_slice(&top_dict_length.to_be_bytes());
+        data.extend(top_dict);
+        data.extend(global_subrs);
+        data.extend(variation_store);
+        data.extend(fd_select);
+        data.extend(charstrings);
+        data.extend(fd_array(&private_offsets)?);
+        for (private, _) in privates {
+            data.extend(private);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otvar::{ItemVariationData, RegionAxisCoordinates};
+    use kurbo::PathEl;
+
+    fn test_font() -> CFF2 {
+        let region = |peak: f32| RegionAxisCoordinates {
+            startCoord: 0.0,
+            peakCoord: peak,
+            endCoord: peak,
+        };
+        let variation_store = ItemVariationStore {
+            format: 1,
+            axisCount: 2,
+            variationRegions: vec![
+                vec![region(1.0), region(0.0)],
+                vec![region(0.0), region(1.0)],
+            ],
+            variationData: vec![ItemVariationData {
+                region_indexes: vec![0, 1],
+                delta_values: vec![],
+            }],
+        };
+        let mut private = PrivateDict::default();
+        // BlueValues: -10 0, varying by -10 0 along the first axis
+        private.dict.set(
+            operators::BLUE_VALUES,
+            vec![
+                (-10).into(),
+                0.into(),
+                (-10).into(),
+                0.into(),
+                0.into(),
+                0.into(),
+                2.into(),
+                Operand::Blend,
+            ],
+        );
+        // A local subr which draws two sides of a box whose width varies
+        // along both axes: 100 2 1 1 blend 100 hlineto
+        private.subrs = vec![vec![239, 141, 140, 140, 16, 239, 6]];
+        CFF2 {
+            top_dict: Dict::new(),
+            global_subrs: vec![],
+            charstrings: vec![
+                vec![],
+                // 0 0 rmoveto, callsubr 0
+                vec![139, 139, 21, 32, 10],
+            ],
+            variation_store: Some(variation_store),
+            fd_array: vec![FontDict {
+                dict: Dict::new(),
+                private,
+            }],
+            fd_select: None,
+        }
+    }
+
+    #[test]
+    fn test_cff2_roundtrip() {
+        let font = test_font();
+        let binary = otspec::ser::to_bytes(&font).unwrap();
+        assert_eq!(&binary[0..3], &[2, 0, 5]);
+        let deserialized: CFF2 = otspec::de::from_bytes(&binary).unwrap();
+        assert_eq!(deserialized, font);
+        assert_eq!(otspec::ser::to_bytes(&deserialized).unwrap(), binary);
+
+        let mut font = font;
+        font.fd_select = Some(vec![0, 0]);
+        let binary = otspec::ser::to_bytes(&font).unwrap();
+        let deserialized: CFF2 = otspec::de::from_bytes(&binary).unwrap();
+        assert_eq!(deserialized, font);
+    }
+
+    #[test]
+    fn test_cff2_outline_at() {
+        let font = test_font();
+        let square = |width: f64| {
+            vec![
+                PathEl::MoveTo((0.0, 0.0).into()),
+                PathEl::LineTo((width, 0.0).into()),
+                PathEl::LineTo((width, 100.0).into()),
+                PathEl::ClosePath,
+            ]
+        };
+        assert_eq!(font.outline(1).unwrap().path.elements(), square(100.0));
+        let outline = font
+            .outline_at(1, &NormalizedLocation(vec![0.5, 1.0]))
+            .unwrap();
+        assert_eq!(outline.width, 0.0);
+        assert_eq!(outline.path.elements(), square(102.0));
+        assert_eq!(font.outline(0).unwrap().path.elements(), &[]);
+    }
+
+    #[test]
+    fn test_cff2_instantiate() {
+        // Pinning one axis keeps the other's deltas
+        let mut font = test_font();
+        let location: BTreeMap<usize, f32> = vec![(1, 1.0)].into_iter().collect();
+        font.instantiate(&location).unwrap();
+        assert!(font.global_subrs.is_empty() && font.fd_array[0].private.subrs.is_empty());
+        assert_eq!(font.variation_store.as_ref().unwrap().axisCount, 1);
+        // 0 0 rmoveto 101 2 1 blend 100 hlineto
+        assert_eq!(
+            font.charstrings[1],
+            vec![139, 139, 21, 240, 141, 140, 16, 239, 6]
+        );
+        let outline = font.outline_at(1, &NormalizedLocation(vec![0.0])).unwrap();
+        assert_eq!(
+            outline.path.elements()[1],
+            PathEl::LineTo((101.0, 0.0).into())
+        );
+
+        // Pinning all axes removes the variation store and all blends
+        let mut font = test_font();
+        let location: BTreeMap<usize, f32> = vec![(0, 0.5), (1, 0.0)].into_iter().collect();
+        font.instantiate(&location).unwrap();
+        assert_eq!(font.variation_store, None);
+        assert_eq!(
+            font.fd_array[0].private.dict.get(operators::BLUE_VALUES),
+            Some(&[(-15).into(), 0.into()][..])
+        );
+        assert_eq!(font.charstrings[1], vec![139, 139, 21, 240, 239, 6]);
+        let binary = otspec::ser::to_bytes(&font).unwrap();
+        let deserialized: CFF2 = otspec::de::from_bytes(&binary).unwrap();
+        assert_eq!(deserialized, font);
+    }
+}
@@ -1,4 +1,5 @@
-use crate::layout::common::{FromLowlevel, Lookup, ToLowlevel, GPOSGSUB};
+use crate::font::Font;
+use crate::layout::common::{FromLowlevel, Lookup, ToLowlevel, Versioned, GPOSGSUB};
 use crate::layout::contextual::{ChainedSequenceContext, SequenceContext};
 use crate::layout::gpos1::SinglePos;
 use crate::layout::gpos2::PairPos;
@@ -6,16 +7,22 @@ use crate::layout::gpos3::CursivePos;
 use crate::layout::gpos4::MarkBasePos;
 use crate::layout::gpos5::MarkLigPos;
 use crate::layout::gpos6::MarkMarkPos;
+use crate::layout::overflow::{self, split_subtable, subtable_origins, Packable};
+use otspec::layout::common::FeatureVariations;
 use otspec::tables::GPOS::{
-    ExtensionPosFormat1, GPOSLookup as GPOSLookupLowlevel, GPOSSubtable, GPOS10,
+    ExtensionPosFormat1, GPOSLookup as GPOSLookupLowlevel, GPOSSubtable, GPOS10, GPOS11,
 };
 use otspec::types::*;
 use otspec::utils::is_all_the_same;
-use otspec::{DeserializationError, Deserializer, ReaderContext, SerializationError, Serialize};
+use otspec::{DeserializationError, Deserializer, ReaderContext, SerializationError};
+use std::collections::BTreeSet;
+use std::error::Error;
 
 /// The 'GPOS' OpenType tag.
 pub const TAG: Tag = crate::tag!("GPOS");
 
+pub(crate) type GPOSLowlevel = Versioned<GPOS10, GPOS11>;
+
 /// A container which represents a generic positioning rule
 ///
 /// Each rule is expressed as a vector of subtables.
@@ -55,6 +62,29 @@ impl Positioning {
     }
 }
 
+impl FromLowlevel<GPOS11> for GPOS {
+    fn from_lowlevel(val: GPOS11, max_glyph_id: GlyphID) -> Self {
+        let mut gpos = GPOS::from_lowlevel(
+            GPOS10 {
+                majorVersion: val.majorVersion,
+                minorVersion: 0,
+                scriptList: val.scriptList,
+                featureList: val.featureList,
+                lookupList: val.lookupList,
+            },
+            max_glyph_id,
+        );
+        gpos.feature_variations = val
+            .featureVariations
+            .link
+            .iter()
+            .flat_map(|variations| variations.featureVariationRecords.iter())
+            .map(|record| record.into())
+            .collect();
+        gpos
+    }
+}
+
 impl Lookup<Positioning> {
     /// Returns the GPOS lookup type for this subtable
     pub fn lookup_type(&self) -> u16 {
@@ -75,6 +105,14 @@ impl Lookup<Positioning> {
 /// The Glyph Positioning table
 pub type GPOS = GPOSGSUB<Positioning>;
 
+impl GPOS {
+    /// Decompiles the table to feature file syntax, naming glyphs from the
+    /// font's `post` table where possible.
+    pub fn to_fea(&self, font: &Font) -> Result<String, Box<dyn Error>> {
+        crate::feature_file::to_fea(self, font)
+    }
+}
+
 pub(crate) fn from_bytes(
     c: &mut ReaderContext,
     max_glyph_id: GlyphID,
@@ -84,10 +122,10 @@ pub(crate) fn from_bytes(
             let internal: GPOS10 = c.de()?;
             Ok(GPOS::from_lowlevel(internal, max_glyph_id))
         }
-        // [0x00, 0x01, 0x00, 0x01] => {
-        //     let internal: GPOS11 = c.de()?;
-        //     Ok(internal.into())
-        // }
+        [0x00, 0x01, 0x00, 0x01] => {
+            let internal: GPOS11 = c.de()?;
+            Ok(GPOS::from_lowlevel(internal, max_glyph_id))
+        }
         _ => Err(DeserializationError(
             "Invalid GPOS table version".to_string(),
         )),
@@ -203,97 +241,198 @@ impl FromLowlevel<GPOS10> for GPOS {
             lookups,
             scripts: val.scriptList.link.unwrap_or_default().into(),
             features: val.featureList.link.unwrap_or_default().into(),
+            feature_variations: vec![],
         }
     }
 }
 
-impl ToLowlevel<GPOSLookupLowlevel> for Lookup<Positioning> {
-    fn to_lowlevel(&self, max_glyph_id: GlyphID) -> GPOSLookupLowlevel {
-        let subtables: Vec<Offset16<GPOSSubtable>> = match &self.rule {
+impl Lookup<Positioning> {
+    /// Converts each subtable of the lookup to one or more lowlevel subtables
+    pub(crate) fn to_lowlevel_subtables(&self, max_glyph_id: GlyphID) -> Vec<Vec<GPOSSubtable>> {
+        match &self.rule {
             Positioning::Single(sp) => sp
                 .iter()
-                .map(|subtable| Offset16::to(subtable.to_lowlevel(max_glyph_id)))
+                .map(|subtable| vec![subtable.to_lowlevel(max_glyph_id)])
                 .collect(),
             Positioning::Pair(pp) => pp
                 .iter()
-                .map(|subtable| {
-                    subtable
-                        .to_lowlevel_subtables(max_glyph_id)
-                        .into_iter()
-                        .map(Offset16::to)
-                })
-                .flatten()
+                .enumerate()
+                .map(|(ix, subtable)| subtable.to_lowlevel_subtables(max_glyph_id, &pp[ix + 1..]))
                 .collect(),
             Positioning::Cursive(curs) => curs
                 .iter()
-                .map(|subtable| Offset16::to(subtable.to_lowlevel(max_glyph_id)))
+                .map(|subtable| vec![subtable.to_lowlevel(max_glyph_id)])
                 .collect(),
             Positioning::MarkToBase(markbase) => markbase
                 .iter()
-                .map(|subtable| Offset16::to(subtable.to_lowlevel(max_glyph_id)))
+                .map(|subtable| vec![subtable.to_lowlevel(max_glyph_id)])
                 .collect(),
             Positioning::MarkToLig(marklig) => marklig
                 .iter()
-                .map(|subtable| Offset16::to(subtable.to_lowlevel(max_glyph_id)))
+                .map(|subtable| vec![subtable.to_lowlevel(max_glyph_id)])
                 .collect(),
             Positioning::MarkToMark(markmark) => markmark
                 .iter()
-                .map(|subtable| Offset16::to(subtable.to_lowlevel(max_glyph_id)))
+                .map(|subtable| vec![subtable.to_lowlevel(max_glyph_id)])
                 .collect(),
             Positioning::Contextual(contextual) => contextual
                 .iter()
-                .map(|subtable| {
-                    subtable
-                        .to_lowlevel_subtables_gpos(max_glyph_id)
-                        .into_iter()
-                        .map(Offset16::to)
-                })
-                .flatten()
+                .map(|subtable| subtable.to_lowlevel_subtables_gpos(max_glyph_id))
                 .collect(),
             Positioning::ChainedContextual(chainedcontextual) => chainedcontextual
                 .iter()
+                .map(|subtable| subtable.to_lowlevel_subtables_gpos(max_glyph_id))
+                .collect(),
+        }
+    }
+
+    /// Builds the lowlevel lookup from its lowlevel subtables, optionally
+    /// wrapping them in extension subtables.
+    fn to_lowlevel_with_subtables(
+        &self,
+        subtables: Vec<GPOSSubtable>,
+        extension: bool,
+    ) -> GPOSLookupLowlevel {
+        let (lookup_type, subtables) = if extension {
+            let subtables = subtables
+                .into_iter()
                 .map(|subtable| {
-                    subtable
-                        .to_lowlevel_subtables_gpos(max_glyph_id)
-                        .into_iter()
-                        .map(Offset16::to)
+                    GPOSSubtable::GPOS9_1(Box::new(ExtensionPosFormat1 {
+                        substFormat: 1,
+                        extensionLookupType: self.lookup_type(),
+                        extension: Offset32::to(subtable),
+                    }))
                 })
-                .flatten()
-                .collect(),
+                .collect();
+            (9, subtables)
+        } else {
+            (self.lookup_type(), subtables)
         };
+        let subtables: Vec<Offset16<GPOSSubtable>> =
+            subtables.into_iter().map(Offset16::to).collect();
         GPOSLookupLowlevel {
-            lookupType: self.lookup_type(),
+            lookupType: lookup_type,
             lookupFlag: self.flags,
             subtables: subtables.into(),
             markFilteringSet: self.mark_filtering_set,
         }
     }
 }
-impl ToLowlevel<GPOS10> for GPOS {
-    fn to_lowlevel(&self, max_glyph_id: GlyphID) -> GPOS10 {
-        let lookups: Vec<Offset16<GPOSLookupLowlevel>> = self
+
+impl ToLowlevel<GPOSLookupLowlevel> for Lookup<Positioning> {
+    fn to_lowlevel(&self, max_glyph_id: GlyphID) -> GPOSLookupLowlevel {
+        let subtables = self.to_lowlevel_subtables(max_glyph_id);
+        self.to_lowlevel_with_subtables(subtables.into_iter().flatten().collect(), false)
+    }
+}
+
+impl ToLowlevel<GPOSLowlevel> for GPOS {
+    fn to_lowlevel(&self, max_glyph_id: GlyphID) -> GPOSLowlevel {
+        Positioning::to_lowlevel_packed(self, max_glyph_id, &BTreeSet::new()).0
+    }
+}
+
+impl Packable for Positioning {
+    type Lowlevel = GPOSLowlevel;
+    const TAG: Tag = TAG;
+
+    fn to_lowlevel_packed(
+        table: &GPOS,
+        max_glyph_id: GlyphID,
+        extensions: &BTreeSet<usize>,
+    ) -> (GPOSLowlevel, Vec<Vec<usize>>) {
+        let mut origins = vec![];
+        let lookups: Vec<Offset16<GPOSLookupLowlevel>> = table
             .lookups
             .iter()
-            .map(|x| Offset16::to(x.to_lowlevel(max_glyph_id)))
+            .enumerate()
+            .map(|(ix, lookup)| {
+                let subtables = lookup.to_lowlevel_subtables(max_glyph_id);
+                origins.push(subtable_origins(&subtables));
+                Offset16::to(lookup.to_lowlevel_with_subtables(
+                    subtables.into_iter().flatten().collect(),
+                    extensions.contains(&ix),
+                ))
+            })
             .collect();
-        GPOS10 {
-            majorVersion: 1,
-            minorVersion: 0,
-            scriptList: Offset16::to((&self.scripts).into()),
-            featureList: Offset16::to((&self.features).into()),
-            lookupList: Offset16::to(otspec::tables::GPOS::GPOSLookupList {
-                lookups: lookups.into(),
-            }),
+        let script_list = Offset16::to((&table.scripts).into());
+        let feature_list = Offset16::to((&table.features).into());
+        let lookup_list = Offset16::to(otspec::tables::GPOS::GPOSLookupList {
+            lookups: lookups.into(),
+        });
+        let lowlevel = if table.feature_variations.is_empty() {
+            Versioned::V10(GPOS10 {
+                majorVersion: 1,
+                minorVersion: 0,
+                scriptList: script_list,
+                featureList: feature_list,
+                lookupList: lookup_list,
+            })
+        } else {
+            Versioned::V11(GPOS11 {
+                majorVersion: 1,
+                minorVersion: 1,
+                scriptList: script_list,
+                featureList: feature_list,
+                lookupList: lookup_list,
+                featureVariations: Offset32::to(FeatureVariations {
+                    majorVersion: 1,
+                    minorVersion: 0,
+                    featureVariationRecords: table
+                        .feature_variations
+                        .iter()
+                        .map(|variation| variation.into())
+                        .collect(),
+                }),
+            })
+        };
+        (lowlevel, origins)
+    }
+
+    fn lookup_offsets(
+        table: &GPOSLowlevel,
+    ) -> Vec<(&dyn OffsetMarkerTrait, Vec<&dyn OffsetMarkerTrait>)> {
+        let lookup_list = match table {
+            Versioned::V10(table) => &table.lookupList,
+            Versioned::V11(table) => &table.lookupList,
+        };
+        lookup_list
+            .iter()
+            .flat_map(|list| list.lookups.v.iter())
+            .map(|lookup| {
+                let subtables = lookup
+                    .iter()
+                    .flat_map(|lookup| lookup.subtables.v.iter())
+                    .map(|subtable| subtable as &dyn OffsetMarkerTrait)
+                    .collect();
+                (lookup as &dyn OffsetMarkerTrait, subtables)
+            })
+            .collect()
+    }
+
+    fn split_subtable(&mut self, index: usize) -> bool {
+        match self {
+            Positioning::Pair(subtables) => split_subtable(subtables, index, PairPos::split),
+            Positioning::MarkToBase(subtables) => {
+                split_subtable(subtables, index, MarkBasePos::split)
+            }
+            Positioning::Contextual(subtables) => {
+                split_subtable(subtables, index, SequenceContext::split)
+            }
+            Positioning::ChainedContextual(subtables) => {
+                split_subtable(subtables, index, ChainedSequenceContext::split)
+            }
+            _ => false,
         }
     }
 }
+
 pub(crate) fn to_bytes(
     gpos: &GPOS,
     data: &mut Vec<u8>,
     max_glyph_id: GlyphID,
 ) -> Result<(), SerializationError> {
-    let gpos10 = gpos.to_lowlevel(max_glyph_id);
-    gpos10.to_bytes(data)
+    overflow::to_bytes(gpos, data, max_glyph_id)
 }
 
 #[cfg(test)]
@@ -323,6 +462,7 @@ pub(crate) mod tests {
                 ),
             },
             features: FeatureList::new(vec![(tag!("test"), vec![0], None)]),
+            feature_variations: vec![],
         }
     }
 
@@ -1,20 +1,29 @@
-use crate::layout::common::{FromLowlevel, Lookup, ToLowlevel, GPOSGSUB};
-use crate::layout::contextual::{ChainedSequenceContext, SequenceContext};
+use crate::font::Font;
+use crate::layout::common::{
+    ConditionSet, FromLowlevel, Lookup, LookupFlags, ToLowlevel, Versioned, GPOSGSUB,
+};
+use crate::layout::contextual::{ChainedSequenceContext, SequenceContext, SequenceContextRule};
 use crate::layout::gsub1::SingleSubst;
 use crate::layout::gsub2::MultipleSubst;
 use crate::layout::gsub3::AlternateSubst;
 use crate::layout::gsub4::LigatureSubst;
 use crate::layout::gsub8::ReverseChainSubst;
+use crate::layout::overflow::{self, split_subtable, subtable_origins, Packable};
+use otspec::layout::common::FeatureVariations;
 use otspec::tables::GSUB::{
-    ExtensionSubstFormat1, GSUBLookup as GSUBLookupLowlevel, GSUBSubtable, GSUB10,
+    ExtensionSubstFormat1, GSUBLookup as GSUBLookupLowlevel, GSUBSubtable, GSUB10, GSUB11,
 };
 use otspec::types::*;
 use otspec::utils::is_all_the_same;
-use otspec::{DeserializationError, Deserializer, ReaderContext, SerializationError, Serialize};
+use otspec::{DeserializationError, Deserializer, ReaderContext, SerializationError};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
 
 /// The 'GSUB' OpenType tag.
 pub const TAG: Tag = crate::tag!("GSUB");
 
+pub(crate) type GSUBLowlevel = Versioned<GSUB10, GSUB11>;
+
 /// A container which represents a generic substitution rule
 ///
 /// Each rule is expressed as a vector of subtables.
@@ -52,6 +61,29 @@ impl Substitution {
     }
 }
 
+impl FromLowlevel<GSUB11> for GSUB {
+    fn from_lowlevel(val: GSUB11, max_glyph_id: GlyphID) -> Self {
+        let mut gsub = GSUB::from_lowlevel(
+            GSUB10 {
+                majorVersion: val.majorVersion,
+                minorVersion: 0,
+                scriptList: val.scriptList,
+                featureList: val.featureList,
+                lookupList: val.lookupList,
+            },
+            max_glyph_id,
+        );
+        gsub.feature_variations = val
+            .featureVariations
+            .link
+            .iter()
+            .flat_map(|variations| variations.featureVariationRecords.iter())
+            .map(|record| record.into())
+            .collect();
+        gsub
+    }
+}
+
 impl Lookup<Substitution> {
     /// Returns the GSUB lookup type for this subtable
     pub fn lookup_type(&self) -> u16 {
@@ -71,6 +103,181 @@ impl Lookup<Substitution> {
 /// The Glyph Substitution table
 pub type GSUB = GPOSGSUB<Substitution>;
 
+/// Returns true if any glyph in the slot is in the glyph set
+fn intersects(slot: &BTreeSet<GlyphID>, glyphs: &BTreeSet<GlyphID>) -> bool {
+    slot.iter().any(|g| glyphs.contains(g))
+}
+
+impl GSUB {
+    /// Decompiles the table to feature file syntax, naming glyphs from the
+    /// font's `post` table where possible.
+    pub fn to_fea(&self, font: &Font) -> Result<String, Box<dyn Error>> {
+        crate::feature_file::to_fea(self, font)
+    }
+
+    /// Adds a single substitution which is applied by the given feature only
+    /// where all the conditions hold, such as swapping `dollar` for
+    /// `dollar.rvrn` in the `rvrn` feature above a certain weight. Returns
+    /// the index of the new lookup.
+    ///
+    /// See [`GPOSGSUB::add_feature_variation`] for how the feature and the
+    /// variation are found or created.
+    pub fn add_conditional_substitution(
+        &mut self,
+        conditions: ConditionSet,
+        feature: Tag,
+        mapping: BTreeMap<GlyphID, GlyphID>,
+    ) -> usize {
+        self.add_feature_variation(
+            conditions,
+            feature,
+            Lookup {
+                flags: LookupFlags::empty(),
+                mark_filtering_set: None,
+                rule: Substitution::Single(vec![SingleSubst { mapping }]),
+            },
+        )
+    }
+
+    /// Computes the set of glyphs which can be produced from the input glyphs
+    /// by the substitutions in this table.
+    ///
+    /// Only the features reachable through the script list are considered,
+    /// and if `features` is given, only features with those tags. Contextual
+    /// rules are applied when every glyph class in their context is matched
+    /// by some glyph in the set. The returned set includes the input glyphs.
+    pub fn closure_glyphs(
+        &self,
+        input: &BTreeSet<GlyphID>,
+        features: Option<&[Tag]>,
+    ) -> BTreeSet<GlyphID> {
+        let lookups = self.feature_lookups(features);
+        let mut glyphs = input.clone();
+        loop {
+            let mut new = BTreeSet::new();
+            for &ix in &lookups {
+                self.lookup_closure(ix, &glyphs, &glyphs, &mut new, 0);
+            }
+            let before = glyphs.len();
+            glyphs.extend(new);
+            if glyphs.len() == before {
+                return glyphs;
+            }
+        }
+    }
+
+    /// Adds the glyphs produced by a lookup to `out`, when it is applied at a
+    /// position holding any of `current`, with `glyphs` being the glyphs
+    /// which may appear around it.
+    fn lookup_closure(
+        &self,
+        ix: usize,
+        current: &BTreeSet<GlyphID>,
+        glyphs: &BTreeSet<GlyphID>,
+        out: &mut BTreeSet<GlyphID>,
+        depth: usize,
+    ) {
+        if depth > 64 {
+            log::warn!("Extremely deeply nested lookup {}. Possible loop?", ix);
+            return;
+        }
+        let lookup = match self.lookups.get(ix) {
+            Some(lookup) => lookup,
+            None => return,
+        };
+        match &lookup.rule {
+            Substitution::Single(subtables) => {
+                for (l, r) in subtables.iter().flat_map(|st| st.mapping.iter()) {
+                    if current.contains(l) {
+                        out.insert(*r);
+                    }
+                }
+            }
+            Substitution::Multiple(subtables) => {
+                for (l, r) in subtables.iter().flat_map(|st| st.mapping.iter()) {
+                    if current.contains(l) {
+                        out.extend(r.iter().copied());
+                    }
+                }
+            }
+            Substitution::Alternate(subtables) => {
+                for (l, r) in subtables.iter().flat_map(|st| st.mapping.iter()) {
+                    if current.contains(l) {
+                        out.extend(r.iter().copied());
+                    }
+                }
+            }
+            Substitution::Ligature(subtables) => {
+                for (l, r) in subtables.iter().flat_map(|st| st.mapping.iter()) {
+                    if l.first().is_some_and(|g| current.contains(g))
+                        && l.iter().skip(1).all(|g| glyphs.contains(g))
+                    {
+                        out.insert(*r);
+                    }
+                }
+            }
+            Substitution::ReverseChainContextual(subtables) => {
+                for st in subtables {
+                    if !st
+                        .backtrack
+                        .iter()
+                        .chain(st.lookahead.iter())
+                        .all(|slot| intersects(slot, glyphs))
+                    {
+                        continue;
+                    }
+                    for (l, r) in &st.mapping {
+                        if current.contains(l) {
+                            out.insert(*r);
+                        }
+                    }
+                }
+            }
+            Substitution::Contextual(subtables) => {
+                for rule in subtables.iter().flat_map(|st| st.rules.iter()) {
+                    self.rule_closure(rule, current, glyphs, out, depth);
+                }
+            }
+            Substitution::ChainedContextual(subtables) => {
+                for rule in subtables.iter().flat_map(|st| st.rules.iter()) {
+                    if rule
+                        .backtrack
+                        .iter()
+                        .chain(rule.lookahead.iter())
+                        .all(|slot| intersects(slot, glyphs))
+                    {
+                        self.rule_closure(&rule.input, current, glyphs, out, depth);
+                    }
+                }
+            }
+        }
+    }
+
+    fn rule_closure(
+        &self,
+        rule: &SequenceContextRule,
+        current: &BTreeSet<GlyphID>,
+        glyphs: &BTreeSet<GlyphID>,
+        out: &mut BTreeSet<GlyphID>,
+        depth: usize,
+    ) {
+        let matches = rule
+            .iter()
+            .enumerate()
+            .all(|(pos, (slot, _))| intersects(slot, if pos == 0 { current } else { glyphs }));
+        if !matches {
+            return;
+        }
+        for (pos, (slot, lookups)) in rule.iter().enumerate() {
+            let available = if pos == 0 { current } else { glyphs };
+            let position: BTreeSet<GlyphID> = slot.intersection(available).copied().collect();
+            for &nested in lookups {
+                self.lookup_closure(nested as usize, &position, glyphs, out, depth + 1);
+            }
+        }
+    }
+}
+
 pub(crate) fn from_bytes(
     c: &mut ReaderContext,
     max_glyph_id: GlyphID,
@@ -80,10 +287,10 @@ pub(crate) fn from_bytes(
             let internal: GSUB10 = c.de()?;
             Ok(GSUB::from_lowlevel(internal, max_glyph_id))
         }
-        // [0x00, 0x01, 0x00, 0x01] => {
-        //     let internal: GSUB11 = c.de()?;
-        //     Ok(internal.into())
-        // }
+        [0x00, 0x01, 0x00, 0x01] => {
+            let internal: GSUB11 = c.de()?;
+            Ok(GSUB::from_lowlevel(internal, max_glyph_id))
+        }
         _ => Err(DeserializationError(
             "Invalid GSUB table version".to_string(),
         )),
@@ -197,96 +404,204 @@ impl FromLowlevel<GSUB10> for GSUB {
             lookups,
             scripts: val.scriptList.link.unwrap_or_default().into(),
             features: val.featureList.link.unwrap_or_default().into(),
+            feature_variations: vec![],
         }
     }
 }
 
-impl ToLowlevel<GSUBLookupLowlevel> for Lookup<Substitution> {
-    fn to_lowlevel(&self, max_glyph_id: GlyphID) -> GSUBLookupLowlevel {
-        let subtables: Vec<Offset16<GSUBSubtable>> = match &self.rule {
+impl Lookup<Substitution> {
+    /// Converts each subtable of the lookup to one or more lowlevel subtables
+    pub(crate) fn to_lowlevel_subtables(&self, max_glyph_id: GlyphID) -> Vec<Vec<GSUBSubtable>> {
+        match &self.rule {
             Substitution::Single(ss) => ss
                 .iter()
-                .map(|subtable| Offset16::to(subtable.to_lowlevel(max_glyph_id)))
+                .map(|subtable| vec![subtable.to_lowlevel(max_glyph_id)])
                 .collect(),
             Substitution::Multiple(ms) => ms
                 .iter()
-                .map(|subtable| Offset16::to(subtable.to_lowlevel(max_glyph_id)))
+                .map(|subtable| vec![subtable.to_lowlevel(max_glyph_id)])
                 .collect(),
             Substitution::Alternate(alts) => alts
                 .iter()
-                .map(|subtable| Offset16::to(subtable.to_lowlevel(max_glyph_id)))
+                .map(|subtable| vec![subtable.to_lowlevel(max_glyph_id)])
                 .collect(),
             Substitution::Ligature(ls) => ls
                 .iter()
-                .map(|subtable| Offset16::to(subtable.to_lowlevel(max_glyph_id)))
+                .map(|subtable| vec![subtable.to_lowlevel(max_glyph_id)])
                 .collect(),
             Substitution::Contextual(contextual) => contextual
                 .iter()
-                .map(|subtable| {
-                    subtable
-                        .to_lowlevel_subtables_gsub(max_glyph_id)
-                        .into_iter()
-                        .map(Offset16::to)
-                })
-                .flatten()
+                .map(|subtable| subtable.to_lowlevel_subtables_gsub(max_glyph_id))
                 .collect(),
             Substitution::ChainedContextual(chainedcontextual) => chainedcontextual
                 .iter()
-                .map(|subtable| {
-                    subtable
-                        .to_lowlevel_subtables_gsub(max_glyph_id)
-                        .into_iter()
-                        .map(Offset16::to)
-                })
-                .flatten()
+                .map(|subtable| subtable.to_lowlevel_subtables_gsub(max_glyph_id))
                 .collect(),
             Substitution::ReverseChainContextual(rs) => rs
                 .iter()
-                .map(|subtable| Offset16::to(subtable.to_lowlevel(max_glyph_id)))
+                .map(|subtable| vec![subtable.to_lowlevel(max_glyph_id)])
                 .collect(),
+        }
+    }
+
+    /// Builds the lowlevel lookup from its lowlevel subtables, optionally
+    /// wrapping them in extension subtables.
+    fn to_lowlevel_with_subtables(
+        &self,
+        subtables: Vec<GSUBSubtable>,
+        extension: bool,
+    ) -> GSUBLookupLowlevel {
+        let (lookup_type, subtables) = if extension {
+            let subtables = subtables
+                .into_iter()
+                .map(|subtable| {
+                    GSUBSubtable::GSUB7_1(Box::new(ExtensionSubstFormat1 {
+                        substFormat: 1,
+                        extensionLookupType: self.lookup_type(),
+                        extension: Offset32::to(subtable),
+                    }))
+                })
+                .collect();
+            (7, subtables)
+        } else {
+            (self.lookup_type(), subtables)
         };
+        let subtables: Vec<Offset16<GSUBSubtable>> =
+            subtables.into_iter().map(Offset16::to).collect();
         GSUBLookupLowlevel {
-            lookupType: self.lookup_type(),
+            lookupType: lookup_type,
             lookupFlag: self.flags,
             subtables: subtables.into(),
             markFilteringSet: self.mark_filtering_set,
         }
     }
 }
-impl ToLowlevel<GSUB10> for GSUB {
-    fn to_lowlevel(&self, max_glyph_id: GlyphID) -> GSUB10 {
-        let lookups: Vec<Offset16<GSUBLookupLowlevel>> = self
+
+impl ToLowlevel<GSUBLookupLowlevel> for Lookup<Substitution> {
+    fn to_lowlevel(&self, max_glyph_id: GlyphID) -> GSUBLookupLowlevel {
+        let subtables = self.to_lowlevel_subtables(max_glyph_id);
+        self.to_lowlevel_with_subtables(subtables.into_iter().flatten().collect(), false)
+    }
+}
+
+impl ToLowlevel<GSUBLowlevel> for GSUB {
+    fn to_lowlevel(&self, max_glyph_id: GlyphID) -> GSUBLowlevel {
+        Substitution::to_lowlevel_packed(self, max_glyph_id, &BTreeSet::new()).0
+    }
+}
+
+impl Packable for Substitution {
+    type Lowlevel = GSUBLowlevel;
+    const TAG: Tag = TAG;
+
+    fn to_lowlevel_packed(
+        table: &GSUB,
+        max_glyph_id: GlyphID,
+        extensions: &BTreeSet<usize>,
+    ) -> (GSUBLowlevel, Vec<Vec<usize>>) {
+        let mut origins = vec![];
+        let lookups: Vec<Offset16<GSUBLookupLowlevel>> = table
             .lookups
             .iter()
-            .map(|x| Offset16::to(x.to_lowlevel(max_glyph_id)))
+            .enumerate()
+            .map(|(ix, lookup)| {
+                let subtables = lookup.to_lowlevel_subtables(max_glyph_id);
+                origins.push(subtable_origins(&subtables));
+                Offset16::to(lookup.to_lowlevel_with_subtables(
+                    subtables.into_iter().flatten().collect(),
+                    extensions.contains(&ix),
+                ))
+            })
             .collect();
-        GSUB10 {
-            majorVersion: 1,
-            minorVersion: 0,
-            scriptList: Offset16::to((&self.scripts).into()),
-            featureList: Offset16::to((&self.features).into()),
-            lookupList: Offset16::to(otspec::tables::GSUB::GSUBLookupList {
-                lookups: lookups.into(),
-            }),
+        let script_list = Offset16::to((&table.scripts).into());
+        let feature_list = Offset16::to((&table.features).into());
+        let lookup_list = Offset16::to(otspec::tables::GSUB::GSUBLookupList {
+            lookups: lookups.into(),
+        });
+        let lowlevel = if table.feature_variations.is_empty() {
+            Versioned::V10(GSUB10 {
+                majorVersion: 1,
+                minorVersion: 0,
+                scriptList: script_list,
+                featureList: feature_list,
+                lookupList: lookup_list,
+            })
+        } else {
+            Versioned::V11(GSUB11 {
+                majorVersion: 1,
+                minorVersion: 1,
+                scriptList: script_list,
+                featureList: feature_list,
+                lookupList: lookup_list,
+                featureVariations: Offset32::to(FeatureVariations {
+                    majorVersion: 1,
+                    minorVersion: 0,
+                    featureVariationRecords: table
+                        .feature_variations
+                        .iter()
+                        .map(|variation| variation.into())
+                        .collect(),
+                }),
+            })
+        };
+        (lowlevel, origins)
+    }
+
+    fn lookup_offsets(
+        table: &GSUBLowlevel,
+    ) -> Vec<(&dyn OffsetMarkerTrait, Vec<&dyn OffsetMarkerTrait>)> {
+        let lookup_list = match table {
+            Versioned::V10(table) => &table.lookupList,
+            Versioned::V11(table) => &table.lookupList,
+        };
+        lookup_list
+            .iter()
+            .flat_map(|list| list.lookups.v.iter())
+            .map(|lookup| {
+                let subtables = lookup
+                    .iter()
+                    .flat_map(|lookup| lookup.subtables.v.iter())
+                    .map(|subtable| subtable as &dyn OffsetMarkerTrait)
+                    .collect();
+                (lookup as &dyn OffsetMarkerTrait, subtables)
+            })
+            .collect()
+    }
+
+    fn split_subtable(&mut self, index: usize) -> bool {
+        match self {
+            Substitution::Ligature(subtables) => {
+                split_subtable(subtables, index, LigatureSubst::split)
+            }
+            Substitution::Contextual(subtables) => {
+                split_subtable(subtables, index, SequenceContext::split)
+            }
+            Substitution::ChainedContextual(subtables) => {
+                split_subtable(subtables, index, ChainedSequenceContext::split)
+            }
+            _ => false,
         }
     }
 }
+
 pub(crate) fn to_bytes(
     gsub: &GSUB,
     data: &mut Vec<u8>,
     max_glyph_id: GlyphID,
 ) -> Result<(), SerializationError> {
-    let gsub10 = gsub.to_lowlevel(max_glyph_id);
-    gsub10.to_bytes(data)
+    overflow::to_bytes(gsub, data, max_glyph_id)
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
-    use crate::layout::common::{FeatureList, LanguageSystem, LookupFlags, Script, ScriptList};
+    use crate::layout::common::{Condition, FeatureList, LanguageSystem, Script, ScriptList};
+    use crate::layout::feature_params::{
+        CharacterVariantParams, FeatureParams, StylisticSetParams,
+    };
+    use crate::tables::name::name;
     use crate::tag;
     use otspec::btreemap;
-    use std::collections::BTreeMap;
     use std::iter::FromIterator;
 
     pub fn expected_gsub(lookups: Vec<Lookup<Substitution>>) -> GSUB {
@@ -305,6 +620,7 @@ pub(crate) mod tests {
                 ),
             },
             features: FeatureList::new(vec![(tag!("test"), vec![0], None)]),
+            feature_variations: vec![],
         }
     }
 
@@ -344,4 +660,239 @@ pub(crate) mod tests {
         }]);
         assert_can_deserialize(binary_gsub, &expected);
     }
+
+    #[test]
+    fn test_closure_glyphs() {
+        use crate::layout::contextual::ChainedSequenceContextRule;
+        let lookup = |rule| Lookup {
+            flags: LookupFlags::empty(),
+            mark_filtering_set: None,
+            rule,
+        };
+        let mut gsub = expected_gsub(vec![
+            lookup(Substitution::Ligature(vec![LigatureSubst {
+                mapping: btreemap!(vec![1, 2] => 3),
+            }])),
+            lookup(Substitution::ChainedContextual(vec![
+                ChainedSequenceContext {
+                    rules: vec![ChainedSequenceContextRule {
+                        backtrack: vec![BTreeSet::from_iter(vec![1])],
+                        lookahead: vec![],
+                        input: vec![(BTreeSet::from_iter(vec![2]), vec![2])],
+                    }],
+                },
+            ])),
+            lookup(Substitution::Single(vec![SingleSubst {
+                mapping: btreemap!(2 => 4),
+            }])),
+            lookup(Substitution::Single(vec![SingleSubst {
+                mapping: btreemap!(5 => 6),
+            }])),
+        ]);
+        // ss01 is not used by any language system
+        gsub.features = FeatureList::new(vec![
+            (tag!("liga"), vec![0], None),
+            (tag!("calt"), vec![1], None),
+            (tag!("ss01"), vec![3], None),
+        ]);
+        gsub.scripts
+            .scripts
+            .get_mut(&tag!("DFLT"))
+            .unwrap()
+            .default_language_system
+            .as_mut()
+            .unwrap()
+            .feature_indices = vec![0, 1];
+
+        let set = |glyphs: &[GlyphID]| BTreeSet::from_iter(glyphs.iter().copied());
+        assert_eq!(gsub.closure_glyphs(&set(&[1, 2]), None), set(&[1, 2, 3, 4]));
+        assert_eq!(gsub.closure_glyphs(&set(&[2]), None), set(&[2]));
+        assert_eq!(
+            gsub.closure_glyphs(&set(&[1, 2]), Some(&[tag!("liga")])),
+            set(&[1, 2, 3])
+        );
+        assert_eq!(gsub.closure_glyphs(&set(&[5]), None), set(&[5]));
+    }
+
+    #[test]
+    fn test_feature_variations() {
+        // wght >= 0.75 => dollar -> dollar.rvrn
+        let mut gsub = GSUB::default();
+        gsub.add_conditional_substitution(
+            vec![Condition {
+                axis_index: 0,
+                min: 0.75,
+                max: 1.0,
+            }],
+            tag!("rvrn"),
+            btreemap!(3 => 4),
+        );
+        let expected = vec![
+            0x00, 0x01, 0x00, 0x01, // GSUB 1.1
+            0x00, 0x0e, 0x00, 0x22, 0x00, 0x2e, // script, feature and lookup lists
+            0x00, 0x00, 0x00, 0x46, // feature variations
+            /* 14 */ 0x00, 0x01, 0x44, 0x46, 0x4c, 0x54, 0x00, 0x08, // DFLT
+            /* 22 */ 0x00, 0x04, 0x00, 0x00, // script
+            /* 26 */ 0x00, 0x00, 0xff, 0xff, 0x00, 0x01, 0x00, 0x00, // langsys
+            /* 34 */ 0x00, 0x01, 0x72, 0x76, 0x72, 0x6e, 0x00, 0x08, // rvrn
+            /* 42 */ 0x00, 0x00, 0x00, 0x00, // with no lookups by default
+            /* 46 */ 0x00, 0x01, 0x00, 0x04, // lookup list
+            /* 50 */ 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x08, // lookup
+            /* 58 */ 0x00, 0x01, 0x00, 0x06, 0x00, 0x01, // single substitution
+            /* 64 */ 0x00, 0x01, 0x00, 0x01, 0x00, 0x03, // coverage
+            /* 70 */ 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x01, // feature variations
+            0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x1e, // record
+            /* 86 */ 0x00, 0x01, 0x00, 0x00, 0x00, 0x06, // condition set
+            /* 92 */ 0x00, 0x01, 0x00, 0x00, 0x30, 0x00, 0x40,
+            0x00, // 0.75 <= wght <= 1.0
+            /* 100 */ 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, // feature table substitution
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, // replacing feature 0
+            /* 112 */ 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, // with lookup 0
+        ];
+        let mut data = vec![];
+        to_bytes(&gsub, &mut data, 10).unwrap();
+        assert_eq!(data, expected);
+
+        let read = from_bytes(&mut ReaderContext::new(data), 10).unwrap();
+        assert_eq!(read, gsub);
+        assert_eq!(
+            read.feature_variations[0].substitution,
+            btreemap!(0 => vec![0])
+        );
+        assert!(read
+            .closure_glyphs(&BTreeSet::from_iter(vec![3]), None)
+            .contains(&4));
+    }
+
+    #[test]
+    fn test_overlapping_feature_variations() {
+        let wght = |min, max| {
+            vec![Condition {
+                axis_index: 0,
+                min,
+                max,
+            }]
+        };
+        let mut gsub = GSUB::default();
+        gsub.add_conditional_substitution(wght(0.5, 1.0), tag!("rvrn"), BTreeMap::from([(3, 4)]));
+        // Within the first region, so both substitutions apply there
+        gsub.add_conditional_substitution(wght(0.75, 1.0), tag!("rvrn"), BTreeMap::from([(5, 6)]));
+        // Partly overlapping the first region
+        gsub.add_conditional_substitution(wght(-1.0, 0.6), tag!("rvrn"), BTreeMap::from([(7, 8)]));
+        let variations: Vec<(ConditionSet, Vec<usize>)> = gsub
+            .feature_variations
+            .iter()
+            .map(|v| (v.conditions.clone(), v.substitution[&0].clone()))
+            .collect();
+        assert_eq!(
+            variations,
+            vec![
+                (wght(0.75, 1.0), vec![0, 1]),
+                (wght(0.5, 0.6), vec![0, 2]),
+                (wght(0.5, 1.0), vec![0]),
+                (wght(-1.0, 0.6), vec![2]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_feature_params() {
+        let mut gsub = expected_gsub(vec![Lookup {
+            flags: LookupFlags::empty(),
+            mark_filtering_set: None,
+            rule: Substitution::Single(vec![SingleSubst {
+                mapping: btreemap!(66 => 67),
+            }]),
+        }]);
+        gsub.features = FeatureList::new(vec![
+            (
+                tag!("ss01"),
+                vec![0],
+                Some(FeatureParams::StylisticSet(StylisticSetParams {
+                    ui_name_id: 256,
+                })),
+            ),
+            (
+                tag!("cv01"),
+                vec![0],
+                Some(FeatureParams::CharacterVariant(CharacterVariantParams {
+                    label_name_id: 257,
+                    first_param_label_name_id: 258,
+                    num_named_parameters: 1,
+                    characters: vec![0x42, 0x1f600],
+                    ..Default::default()
+                })),
+            ),
+        ]);
+        let mut data = vec![];
+        to_bytes(&gsub, &mut data, 200).unwrap();
+        assert_eq!(
+            data[0x1e..0x50],
+            [
+                0x00, 0x02, // FeatureList.featureCount
+                0x73, 0x73, 0x30, 0x31, 0x00, 0x0e, // ss01
+                0x63, 0x76, 0x30, 0x31, 0x00, 0x18, // cv01
+                0x00, 0x06, 0x00, 0x01, 0x00, 0x00, // ss01 FeatureTable
+                0x00, 0x00, 0x01, 0x00, // ss01 FeatureParams
+                0x00, 0x06, 0x00, 0x01, 0x00, 0x00, // cv01 FeatureTable
+                0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, // cv01 label, tooltip, sample
+                0x00, 0x01, 0x01, 0x02, // cv01 named parameters
+                0x00, 0x02, 0x00, 0x00, 0x42, 0x01, 0xf6, 0x00, // cv01 characters
+            ]
+        );
+        let read = from_bytes(&mut ReaderContext::new(data), 200).unwrap();
+        assert_eq!(read, gsub);
+
+        // ssty is not a stylistic set, and has no parameters
+        let mut ssty = gsub.clone();
+        ssty.features = FeatureList::new(vec![(tag!("ssty"), vec![0], None)]);
+        let mut data = vec![];
+        to_bytes(&ssty, &mut data, 200).unwrap();
+        assert_eq!(
+            data[0x1e..0x2c],
+            [
+                0x00, 0x01, // FeatureList.featureCount
+                0x73, 0x73, 0x74, 0x79, 0x00, 0x08, // ssty
+                0x00, 0x00, 0x00, 0x01, 0x00, 0x00, // ssty FeatureTable
+            ]
+        );
+        let read = from_bytes(&mut ReaderContext::new(data.clone()), 200).unwrap();
+        assert_eq!(read, ssty);
+        // Nor are any parameters it points to read as a stylistic set's
+        data[0x26..0x28].copy_from_slice(&[0x00, 0x06]);
+        let read = from_bytes(&mut ReaderContext::new(data), 200).unwrap();
+        assert_eq!(read.features.get(0), Some(&(tag!("ssty"), vec![0], None)));
+
+        let mut names = name { records: vec![] };
+        assert_eq!(
+            gsub.missing_feature_names(&names),
+            vec![
+                (tag!("ss01"), 256),
+                (tag!("cv01"), 257),
+                (tag!("cv01"), 258)
+            ]
+        );
+        assert_eq!(
+            gsub.set_stylistic_set_name(tag!("ss01"), &mut names, "Alternate digits"),
+            Some(256)
+        );
+        assert_eq!(
+            gsub.stylistic_set_name(tag!("ss01"), &names),
+            Some("Alternate digits")
+        );
+        assert_eq!(
+            gsub.set_stylistic_set_name(tag!("ss01"), &mut names, "Oldstyle digits"),
+            Some(256)
+        );
+        assert_eq!(names.records.len(), 1);
+        assert_eq!(
+            gsub.stylistic_set_name(tag!("ss01"), &names),
+            Some("Oldstyle digits")
+        );
+        assert_eq!(
+            gsub.set_stylistic_set_name(tag!("ss02"), &mut names, "Nothing"),
+            None
+        );
+    }
 }
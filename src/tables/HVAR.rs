@@ -0,0 +1,142 @@
+use crate::otvar::{DeltaSetIndexMap, ItemVariationStore};
+use crate::table_delegate;
+use otspec::types::*;
+use otspec::Deserializer;
+use otspec_macros::tables;
+
+/// The 'HVAR' OpenType tag.
+pub const TAG: Tag = crate::tag!("HVAR");
+
+tables!(
+    HVARcore {
+        uint16 majorVersion
+        uint16 minorVersion
+        Offset32(ItemVariationStore) itemVariationStore
+        Offset32(DeltaSetIndexMap) advanceWidthMapping
+        Offset32(DeltaSetIndexMap) lsbMapping
+        Offset32(DeltaSetIndexMap) rsbMapping
+    }
+);
+
+/// Horizontal Metrics Variations table
+///
+/// Describes how the advance widths and side bearings of glyphs vary across
+/// the designspace.
+#[derive(Debug, PartialEq, Clone)]
+pub struct HVAR {
+    /// The variation store holding the deltas
+    pub variation_store: ItemVariationStore,
+    /// Maps glyph IDs to the deltas for their advance widths. If not present,
+    /// the glyph ID is the inner index within the first variation data
+    /// subtable.
+    pub advance_mapping: Option<DeltaSetIndexMap>,
+    /// Maps glyph IDs to the deltas for their left side bearings
+    pub lsb_mapping: Option<DeltaSetIndexMap>,
+    /// Maps glyph IDs to the deltas for their right side bearings
+    pub rsb_mapping: Option<DeltaSetIndexMap>,
+}
+
+impl HVAR {
+    /// The (outer, inner) index of the deltas for a glyph's advance width
+    pub fn advance_index(&self, gid: GlyphID) -> (uint16, uint16) {
+        self.advance_mapping
+            .as_ref()
+            .and_then(|map| map.get(gid.into()))
+            .unwrap_or((0, gid))
+    }
+
+    /// The (outer, inner) index of the deltas for a glyph's left side
+    /// bearing, if the table has them
+    pub fn lsb_index(&self, gid: GlyphID) -> Option<(uint16, uint16)> {
+        self.lsb_mapping
+            .as_ref()
+            .and_then(|map| map.get(gid.into()))
+    }
+
+    /// The (outer, inner) index of the deltas for a glyph's right side
+    /// bearing, if the table has them
+    pub fn rsb_index(&self, gid: GlyphID) -> Option<(uint16, uint16)> {
+        self.rsb_mapping
+            .as_ref()
+            .and_then(|map| map.get(gid.into()))
+    }
+}
+
+impl From<HVARcore> for HVAR {
+    fn from(core: HVARcore) -> Self {
+        HVAR {
+            variation_store: core.itemVariationStore.link.unwrap_or(ItemVariationStore {
+                format: 1,
+                axisCount: 0,
+                variationRegions: vec![],
+                variationData: vec![],
+            }),
+            advance_mapping: core.advanceWidthMapping.link,
+            lsb_mapping: core.lsbMapping.link,
+            rsb_mapping: core.rsbMapping.link,
+        }
+    }
+}
+
+impl From<&HVAR> for HVARcore {
+    fn from(hvar: &HVAR) -> Self {
+        let mapping = |map: &Option<DeltaSetIndexMap>| {
+            map.clone().map_or_else(Offset32::to_nothing, Offset32::to)
+        };
+        HVARcore {
+            majorVersion: 1,
+            minorVersion: 0,
+            itemVariationStore: Offset32::to(hvar.variation_store.clone()),
+            advanceWidthMapping: mapping(&hvar.advance_mapping),
+            lsbMapping: mapping(&hvar.lsb_mapping),
+            rsbMapping: mapping(&hvar.rsb_mapping),
+        }
+    }
+}
+
+table_delegate!(HVAR, HVARcore);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otvar::{ItemVariationData, RegionAxisCoordinates};
+
+    #[test]
+    fn hvar_serde() {
+        let hvar = HVAR {
+            variation_store: ItemVariationStore {
+                format: 1,
+                axisCount: 1,
+                variationRegions: vec![vec![RegionAxisCoordinates {
+                    startCoord: 0.0,
+                    peakCoord: 1.0,
+                    endCoord: 1.0,
+                }]],
+                variationData: vec![ItemVariationData {
+                    region_indexes: vec![0],
+                    delta_values: vec![vec![0], vec![50]],
+                }],
+            },
+            advance_mapping: Some(DeltaSetIndexMap {
+                entries: vec![(0, 0), (0, 1), (0, 1)],
+            }),
+            lsb_mapping: None,
+            rsb_mapping: None,
+        };
+        let binary_hvar = otspec::ser::to_bytes(&hvar).unwrap();
+        assert_eq!(
+            binary_hvar[0..20],
+            [
+                0x00, 0x01, 0x00, 0x00, /* version 1.0 */
+                0x00, 0x00, 0x00, 0x14, /* itemVariationStore */
+                0x00, 0x00, 0x00, 0x36, /* advanceWidthMapping */
+                0x00, 0x00, 0x00, 0x00, /* lsbMapping */
+                0x00, 0x00, 0x00, 0x00, /* rsbMapping */
+            ]
+        );
+        let deserialized: HVAR = otspec::de::from_bytes(&binary_hvar).unwrap();
+        assert_eq!(deserialized, hvar);
+        assert_eq!(deserialized.advance_index(2), (0, 1));
+        assert_eq!(deserialized.lsb_index(2), None);
+    }
+}
@@ -22,6 +22,7 @@ tables!(
         Offset16(MathVariants) mathVariants
     }
     MathConstants {
+        [offset_base]
         int16   scriptPercentScaleDown
         int16   scriptScriptPercentScaleDown
         UFWORD  delimitedSubFormulaMinHeight
@@ -87,10 +88,12 @@ tables!(
         Offset16(MathKernInfo) mathKernInfo
     }
     MathItalicsCorrectionInfo {
+        [offset_base]
         Offset16(Coverage) italicsCorrectionCoverage
         Counted(MathValueRecord) italicsCorrection
     }
     MathTopAccentAttachment {
+        [offset_base]
         Offset16(Coverage) topAccentCoverage
         Counted(MathValueRecord) topAccentAttachment
     }
@@ -116,6 +119,7 @@ tables!(
         UFWORD  advanceMeasurement
     }
     GlyphAssembly {
+        [offset_base]
         MathValueRecord italicsCorrection
         Counted(GlyphPartRecord) partRecords
     }
@@ -188,6 +192,9 @@ impl Serialize for MathVariants {
         }
         v
     }
+    fn ot_binary_size(&self) -> usize {
+        10 + 2 * (self.vertGlyphConstruction.len() + self.horizGlyphConstruction.len())
+    }
     fn to_bytes(&self, data: &mut Vec<u8>) -> Result<(), otspec::SerializationError> {
         data.put(&self.minConnectorOverlap)?;
         data.put(&self.vertGlyphCoverage)?;
@@ -341,6 +348,115 @@ impl Deserialize for MATH {
     }
 }
 
+impl MATH {
+    fn glyph_info_to_offset(&self) -> Offset16<MathGlyphInfo> {
+        if self.italic_correction.is_empty()
+            && self.top_accent_attachment.is_empty()
+            && self.extended_shapes.is_empty()
+            && self.kerning.is_empty()
+        {
+            return Offset16::to_nothing();
+        }
+        let italics_correction_info = if self.italic_correction.is_empty() {
+            Offset16::to_nothing()
+        } else {
+            Offset16::to(MathItalicsCorrectionInfo {
+                italicsCorrectionCoverage: Offset16::to(Coverage {
+                    glyphs: self.italic_correction.keys().copied().collect(),
+                }),
+                italicsCorrection: self.italic_correction.values().cloned().collect(),
+            })
+        };
+        let top_accent_attachment = if self.top_accent_attachment.is_empty() {
+            Offset16::to_nothing()
+        } else {
+            Offset16::to(MathTopAccentAttachment {
+                topAccentCoverage: Offset16::to(Coverage {
+                    glyphs: self.top_accent_attachment.keys().copied().collect(),
+                }),
+                topAccentAttachment: self.top_accent_attachment.values().cloned().collect(),
+            })
+        };
+        let extended_shape_coverage = if self.extended_shapes.is_empty() {
+            Offset16::to_nothing()
+        } else {
+            Offset16::to(Coverage {
+                glyphs: self.extended_shapes.iter().copied().collect(),
+            })
+        };
+        let kern_info = if self.kerning.is_empty() {
+            Offset16::to_nothing()
+        } else {
+            Offset16::to(MathKernInfo {
+                mathKernCoverage: Offset16::to(Coverage {
+                    glyphs: self.kerning.keys().copied().collect(),
+                }),
+                mathKernInfoRecords: self.kerning.values().cloned().collect(),
+            })
+        };
+        Offset16::to(MathGlyphInfo {
+            mathItalicsCorrectionInfo: italics_correction_info,
+            mathTopAccentAttachment: top_accent_attachment,
+            extendedShapeCoverage: extended_shape_coverage,
+            mathKernInfo: kern_info,
+        })
+    }
+
+    fn variants_to_offset(&self) -> Offset16<MathVariants> {
+        if self.min_overlap.is_none()
+            && self.vertical_extensions.is_empty()
+            && self.horizontal_extensions.is_empty()
+        {
+            return Offset16::to_nothing();
+        }
+        let coverage = |map: &BTreeMap<GlyphID, MathGlyphConstruction>| {
+            if map.is_empty() {
+                Offset16::to_nothing()
+            } else {
+                Offset16::to(Coverage {
+                    glyphs: map.keys().copied().collect(),
+                })
+            }
+        };
+        Offset16::to(MathVariants {
+            minConnectorOverlap: self.min_overlap.unwrap_or(0),
+            vertGlyphCoverage: coverage(&self.vertical_extensions),
+            horizGlyphCoverage: coverage(&self.horizontal_extensions),
+            vertGlyphCount: self.vertical_extensions.len() as uint16,
+            horizGlyphCount: self.horizontal_extensions.len() as uint16,
+            vertGlyphConstruction: self
+                .vertical_extensions
+                .values()
+                .map(|x| Offset16::to(x.clone()))
+                .collect(),
+            horizGlyphConstruction: self
+                .horizontal_extensions
+                .values()
+                .map(|x| Offset16::to(x.clone()))
+                .collect(),
+        })
+    }
+}
+
+impl From<&MATH> for MATHinternal {
+    fn from(math: &MATH) -> Self {
+        MATHinternal {
+            majorVersion: 1,
+            minorVersion: 0,
+            mathConstants: Offset16::to(math.constants.clone()),
+            mathGlyphInfo: math.glyph_info_to_offset(),
+            mathVariants: math.variants_to_offset(),
+        }
+    }
+}
+
+impl Serialize for MATH {
+    fn to_bytes(&self, data: &mut Vec<u8>) -> Result<(), otspec::SerializationError> {
+        let internal: MATHinternal = self.into();
+        internal.to_bytes(data)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use otspec::btreemap;
@@ -561,4 +677,95 @@ mod tests {
             },
         )
     }
+
+    #[test]
+    fn test_math_roundtrip() {
+        let math = MATH {
+            constants: MathConstants {
+                scriptPercentScaleDown: 80,
+                scriptScriptPercentScaleDown: 60,
+                delimitedSubFormulaMinHeight: 1500,
+                displayOperatorMinHeight: 1250,
+                mathLeading: MathValueRecord::new(150),
+                axisHeight: MathValueRecord::new(290),
+                accentBaseHeight: MathValueRecord::new(480),
+                flattenedAccentBaseHeight: MathValueRecord::new(647),
+                subscriptShiftDown: MathValueRecord::new(210),
+                subscriptTopMax: MathValueRecord::new(360),
+                subscriptBaselineDropMin: MathValueRecord::new(160),
+                superscriptShiftUp: MathValueRecord::new(375),
+                superscriptShiftUpCramped: MathValueRecord::new(310),
+                superscriptBottomMin: MathValueRecord::new(120),
+                superscriptBaselineDropMax: MathValueRecord::new(230),
+                subSuperscriptGapMin: MathValueRecord::new(150),
+                superscriptBottomMaxWithSubscript: MathValueRecord::new(380),
+                spaceAfterScript: MathValueRecord::new(43),
+                upperLimitGapMin: MathValueRecord::new(65),
+                upperLimitBaselineRiseMin: MathValueRecord::new(250),
+                lowerLimitGapMin: MathValueRecord::new(65),
+                lowerLimitBaselineDropMin: MathValueRecord::new(620),
+                stackTopShiftUp: MathValueRecord::new(470),
+                stackTopDisplayStyleShiftUp: MathValueRecord::new(750),
+                stackBottomShiftDown: MathValueRecord::new(380),
+                stackBottomDisplayStyleShiftDown: MathValueRecord::new(700),
+                stackGapMin: MathValueRecord::new(200),
+                stackDisplayStyleGapMin: MathValueRecord::new(330),
+                stretchStackTopShiftUp: MathValueRecord::new(800),
+                stretchStackBottomShiftDown: MathValueRecord::new(600),
+                stretchStackGapAboveMin: MathValueRecord::new(65),
+                stretchStackGapBelowMin: MathValueRecord::new(65),
+                fractionNumeratorShiftUp: MathValueRecord::new(600),
+                fractionNumeratorDisplayStyleShiftUp: MathValueRecord::new(800),
+                fractionDenominatorShiftDown: MathValueRecord::new(550),
+                fractionDenominatorDisplayStyleShiftDown: MathValueRecord::new(700),
+                fractionNumeratorGapMin: MathValueRecord::new(65),
+                fractionNumDisplayStyleGapMin: MathValueRecord::new(130),
+                fractionRuleThickness: MathValueRecord::new(65),
+                fractionDenominatorGapMin: MathValueRecord::new(65),
+                fractionDenomDisplayStyleGapMin: MathValueRecord::new(130),
+                skewedFractionHorizontalGap: MathValueRecord::new(400),
+                skewedFractionVerticalGap: MathValueRecord::new(65),
+                overbarVerticalGap: MathValueRecord::new(120),
+                overbarRuleThickness: MathValueRecord::new(40),
+                overbarExtraAscender: MathValueRecord::new(40),
+                underbarVerticalGap: MathValueRecord::new(120),
+                underbarRuleThickness: MathValueRecord::new(40),
+                underbarExtraDescender: MathValueRecord::new(40),
+                radicalVerticalGap: MathValueRecord::new(90),
+                radicalDisplayStyleVerticalGap: MathValueRecord::new(170),
+                radicalRuleThickness: MathValueRecord::new(65),
+                radicalExtraAscender: MathValueRecord::new(65),
+                radicalKernBeforeDegree: MathValueRecord::new(65),
+                radicalKernAfterDegree: MathValueRecord::new(-320),
+                radicalDegreeBottomRaisePercent: 33,
+            },
+            italic_correction: btreemap!(12 => MathValueRecord::new(25)),
+            top_accent_attachment: btreemap!(
+                12 => MathValueRecord::new(300),
+                13 => MathValueRecord::new(310)
+            ),
+            extended_shapes: BTreeSet::from_iter(vec![20, 21]),
+            kerning: BTreeMap::new(),
+            min_overlap: Some(100),
+            vertical_extensions: btreemap!(
+                9 => MathGlyphConstruction {
+                    glyphAssembly: Offset16::to_nothing(),
+                    mathGlyphVariantRecord: vec![
+                        MathGlyphVariantRecord {
+                            variantGlyph: 9,
+                            advanceMeasurement: 800,
+                        },
+                        MathGlyphVariantRecord {
+                            variantGlyph: 2000,
+                            advanceMeasurement: 1200,
+                        },
+                    ],
+                }
+            ),
+            horizontal_extensions: BTreeMap::new(),
+        };
+        let binary = otspec::ser::to_bytes(&math).unwrap();
+        let deserialized: MATH = otspec::de::from_bytes(&binary).unwrap();
+        assert_eq!(deserialized, math);
+    }
 }
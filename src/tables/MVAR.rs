@@ -0,0 +1,141 @@
+use crate::otvar::ItemVariationStore;
+use otspec::types::*;
+use otspec::{
+    DeserializationError, Deserialize, Deserializer, ReaderContext, SerializationError, Serialize,
+    Serializer,
+};
+use otspec_macros::tables;
+use std::collections::BTreeMap;
+
+/// The 'MVAR' OpenType tag.
+pub const TAG: Tag = crate::tag!("MVAR");
+
+tables!(
+    MVARcore {
+        uint16 majorVersion
+        uint16 minorVersion
+        uint16 reserved
+        uint16 valueRecordSize
+        uint16 valueRecordCount
+        uint16 itemVariationStoreOffset
+    }
+    ValueRecord {
+        Tag valueTag
+        uint16 deltaSetOuterIndex
+        uint16 deltaSetInnerIndex
+    }
+);
+
+/// Metrics Variations table
+///
+/// Describes how font-wide metrics, such as the typographic ascender or the
+/// underline position, vary across the designspace.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct MVAR {
+    /// The variation store holding the deltas
+    pub variation_store: Option<ItemVariationStore>,
+    /// Maps the tag of each varying metric (for example `hasc` for the
+    /// typographic ascender) to the (outer, inner) index of its deltas
+    pub values: BTreeMap<Tag, (uint16, uint16)>,
+}
+
+impl Deserialize for MVAR {
+    fn from_bytes(c: &mut ReaderContext) -> Result<Self, DeserializationError> {
+        c.push();
+        let core: MVARcore = c.de()?;
+        let mut values = BTreeMap::new();
+        for _ in 0..core.valueRecordCount {
+            let record: ValueRecord = c.de()?;
+            // Records may be larger than we know about
+            c.skip((core.valueRecordSize as usize).saturating_sub(8));
+            values.insert(
+                record.valueTag,
+                (record.deltaSetOuterIndex, record.deltaSetInnerIndex),
+            );
+        }
+        let variation_store = if core.itemVariationStoreOffset > 0 {
+            c.ptr = c.top_of_table() + core.itemVariationStoreOffset as usize;
+            Some(c.de()?)
+        } else {
+            None
+        };
+        c.pop();
+        Ok(MVAR {
+            variation_store,
+            values,
+        })
+    }
+}
+
+impl Serialize for MVAR {
+    fn to_bytes(&self, data: &mut Vec<u8>) -> Result<(), SerializationError> {
+        let store_offset = if self.variation_store.is_some() {
+            12 + 8 * self.values.len()
+        } else {
+            0
+        };
+        MVARcore {
+            majorVersion: 1,
+            minorVersion: 0,
+            reserved: 0,
+            valueRecordSize: 8,
+            valueRecordCount: self.values.len() as uint16,
+            itemVariationStoreOffset: store_offset as uint16,
+        }
+        .to_bytes(data)?;
+        for (&tag, &(outer, inner)) in &self.values {
+            ValueRecord {
+                valueTag: tag,
+                deltaSetOuterIndex: outer,
+                deltaSetInnerIndex: inner,
+            }
+            .to_bytes(data)?;
+        }
+        if let Some(store) = &self.variation_store {
+            data.put(store)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otvar::{ItemVariationData, RegionAxisCoordinates};
+
+    #[test]
+    fn mvar_serde() {
+        let binary_mvar = vec![
+            0x00, 0x01, 0x00, 0x00, /* version 1.0 */
+            0x00, 0x00, /* reserved */
+            0x00, 0x08, /* valueRecordSize */
+            0x00, 0x02, /* valueRecordCount */
+            0x00, 0x1c, /* itemVariationStoreOffset */
+            0x68, 0x61, 0x73, 0x63, 0x00, 0x00, 0x00, 0x00, /* hasc */
+            0x78, 0x68, 0x67, 0x74, 0x00, 0x00, 0x00, 0x01, /* xhgt */
+            /* Item variation store */
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x01, 0x00, 0x00, 0x00, 0x16, 0x00, 0x01,
+            0x00, 0x01, 0x00, 0x00, 0x40, 0x00, 0x40, 0x00, 0x00, 0x02, 0x00, 0x01, 0x00, 0x01,
+            0x00, 0x00, 0x00, 0x32, 0x00, 0x0a,
+        ];
+        let mvar = MVAR {
+            variation_store: Some(ItemVariationStore {
+                format: 1,
+                axisCount: 1,
+                variationRegions: vec![vec![RegionAxisCoordinates {
+                    startCoord: 0.0,
+                    peakCoord: 1.0,
+                    endCoord: 1.0,
+                }]],
+                variationData: vec![ItemVariationData {
+                    region_indexes: vec![0],
+                    delta_values: vec![vec![50], vec![10]],
+                }],
+            }),
+            values: BTreeMap::from([(crate::tag!("hasc"), (0, 0)), (crate::tag!("xhgt"), (0, 1))]),
+        };
+        let deserialized: MVAR = otspec::de::from_bytes(&binary_mvar).unwrap();
+        assert_eq!(deserialized, mvar);
+        assert_eq!(otspec::ser::to_bytes(&mvar).unwrap(), binary_mvar);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::otvar::{DeltaSetIndexMap, ItemVariationStore};
+use crate::table_delegate;
+use otspec::types::*;
+use otspec::Deserializer;
+use otspec_macros::tables;
+
+/// The 'VVAR' OpenType tag.
+pub const TAG: Tag = crate::tag!("VVAR");
+
+tables!(
+    VVARcore {
+        uint16 majorVersion
+        uint16 minorVersion
+        Offset32(ItemVariationStore) itemVariationStore
+        Offset32(DeltaSetIndexMap) advanceHeightMapping
+        Offset32(DeltaSetIndexMap) tsbMapping
+        Offset32(DeltaSetIndexMap) bsbMapping
+        Offset32(DeltaSetIndexMap) vOrgMapping
+    }
+);
+
+/// Vertical Metrics Variations table
+///
+/// Describes how the advance heights, side bearings and vertical origins of
+/// glyphs vary across the designspace.
+#[derive(Debug, PartialEq, Clone)]
+pub struct VVAR {
+    /// The variation store holding the deltas
+    pub variation_store: ItemVariationStore,
+    /// Maps glyph IDs to the deltas for their advance heights. If not
+    /// present, the glyph ID is the inner index within the first variation
+    /// data subtable.
+    pub advance_mapping: Option<DeltaSetIndexMap>,
+    /// Maps glyph IDs to the deltas for their top side bearings
+    pub tsb_mapping: Option<DeltaSetIndexMap>,
+    /// Maps glyph IDs to the deltas for their bottom side bearings
+    pub bsb_mapping: Option<DeltaSetIndexMap>,
+    /// Maps glyph IDs to the deltas for their vertical origins
+    pub vorg_mapping: Option<DeltaSetIndexMap>,
+}
+
+impl VVAR {
+    /// The (outer, inner) index of the deltas for a glyph's advance height
+    pub fn advance_index(&self, gid: GlyphID) -> (uint16, uint16) {
+        self.advance_mapping
+            .as_ref()
+            .and_then(|map| map.get(gid.into()))
+            .unwrap_or((0, gid))
+    }
+
+    /// The (outer, inner) index of the deltas for a glyph's top side
+    /// bearing, if the table has them
+    pub fn tsb_index(&self, gid: GlyphID) -> Option<(uint16, uint16)> {
+        self.tsb_mapping
+            .as_ref()
+            .and_then(|map| map.get(gid.into()))
+    }
+}
+
+impl From<VVARcore> for VVAR {
+    fn from(core: VVARcore) -> Self {
+        VVAR {
+            variation_store: core.itemVariationStore.link.unwrap_or(ItemVariationStore {
+                format: 1,
+                axisCount: 0,
+                variationRegions: vec![],
+                variationData: vec![],
+            }),
+            advance_mapping: core.advanceHeightMapping.link,
+            tsb_mapping: core.tsbMapping.link,
+            bsb_mapping: core.bsbMapping.link,
+            vorg_mapping: core.vOrgMapping.link,
+        }
+    }
+}
+
+impl From<&VVAR> for VVARcore {
+    fn from(vvar: &VVAR) -> Self {
+        let mapping = |map: &Option<DeltaSetIndexMap>| {
+            map.clone().map_or_else(Offset32::to_nothing, Offset32::to)
+        };
+        VVARcore {
+            majorVersion: 1,
+            minorVersion: 0,
+            itemVariationStore: Offset32::to(vvar.variation_store.clone()),
+            advanceHeightMapping: mapping(&vvar.advance_mapping),
+            tsbMapping: mapping(&vvar.tsb_mapping),
+            bsbMapping: mapping(&vvar.bsb_mapping),
+            vOrgMapping: mapping(&vvar.vorg_mapping),
+        }
+    }
+}
+
+table_delegate!(VVAR, VVARcore);
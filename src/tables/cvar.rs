@@ -0,0 +1,200 @@
+use crate::otvar::{
+    Delta, TupleIndexFlags, TupleVariation, TupleVariationHeader, TupleVariationStore,
+};
+use otspec::types::*;
+use otspec::{
+    DeserializationError, Deserializer, ReaderContext, SerializationError, Serialize, Serializer,
+};
+
+/// The 'cvar' OpenType tag.
+pub const TAG: Tag = crate::tag!("cvar");
+
+/// How the values of the `cvt ` table vary at one region of the design space.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DeltaSet {
+    /// The peak location at which this region is active.
+    pub peak: Tuple,
+    /// The location at which this region begins to be active.
+    pub start: Tuple,
+    /// The location at which this region is no longer active.
+    pub end: Tuple,
+    /// A delta for each value in the `cvt ` table, to be applied at the peak
+    /// of this region.
+    pub deltas: Vec<int16>,
+}
+
+impl DeltaSet {
+    // A region is stored as intermediate unless its start and end are
+    // those implied by its peak.
+    fn is_intermediate(&self) -> bool {
+        self.peak
+            .iter()
+            .zip(self.start.iter())
+            .zip(self.end.iter())
+            .any(|((&peak, &start), &end)| {
+                let (implied_start, implied_end) =
+                    if peak > 0.0 { (0.0, 1.0) } else { (-1.0, 0.0) };
+                start != implied_start || end != implied_end
+            })
+    }
+
+    fn to_tuple_variation(&self) -> TupleVariation {
+        let mut flags = TupleIndexFlags::EMBEDDED_PEAK_TUPLE;
+        let intermediate = self.is_intermediate();
+        if intermediate {
+            flags |= TupleIndexFlags::INTERMEDIATE_REGION;
+        }
+        let tvh = TupleVariationHeader {
+            size: 0, // This will be filled in when serializing the TVS
+            flags,
+            sharedTupleIndex: 0,
+            peakTuple: Some(self.peak.clone()),
+            startTuple: intermediate.then(|| self.start.clone()),
+            endTuple: intermediate.then(|| self.end.clone()),
+        };
+        // Values which don't vary can be left out, but at least one must
+        // be present.
+        let deltas: Vec<Option<Delta>> = if self.deltas.iter().all(|&d| d == 0) {
+            self.deltas
+                .iter()
+                .map(|&d| Some(Delta::Delta1D(d)))
+                .collect()
+        } else {
+            self.deltas
+                .iter()
+                .map(|&d| (d != 0).then_some(Delta::Delta1D(d)))
+                .collect()
+        };
+        TupleVariation(tvh, deltas)
+    }
+
+    pub(crate) fn combine(&self, other: &Self) -> Self {
+        let mut new = self.clone();
+        if new.deltas.len() != other.deltas.len() {
+            panic!("Tried to add deltas with different lengths")
+        }
+        for (delta, other) in new.deltas.iter_mut().zip(other.deltas.iter()) {
+            *delta += other;
+        }
+        new
+    }
+
+    pub(crate) fn scale_deltas(&mut self, factor: f32) {
+        for delta in self.deltas.iter_mut() {
+            *delta = ot_round(*delta as f32 * factor) as i16;
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[allow(non_camel_case_types)]
+/// A CVT Variations table, describing how the values of the `cvt ` table
+/// vary across the designspace.
+pub struct cvar {
+    /// Deltas for the `cvt ` values at particular designspace regions.
+    pub deltasets: Vec<DeltaSet>,
+}
+
+/// Constructs a `cvar` object from a binary table, given the number of axes
+/// in the `fvar` table and the number of values in the `cvt ` table.
+pub fn from_bytes(
+    s: &[u8],
+    axis_count: uint16,
+    cvt_count: uint16,
+) -> Result<cvar, DeserializationError> {
+    let mut c = ReaderContext::new(s.to_vec());
+    let _major_version: uint16 = c.de()?;
+    let _minor_version: uint16 = c.de()?;
+    let tvs = TupleVariationStore::from_bytes(&mut c, axis_count, false, cvt_count)?;
+    let mut deltasets = vec![];
+    for TupleVariation(header, deltas) in tvs.0 {
+        let peak = header.peakTuple.ok_or_else(|| {
+            DeserializationError("cvar tuple variation has no embedded peak".to_string())
+        })?;
+        let start = header.startTuple.unwrap_or_else(|| {
+            peak.iter()
+                .map(|&x| if x > 0.0 { 0.0 } else { -1.0 })
+                .collect()
+        });
+        let end = header.endTuple.unwrap_or_else(|| {
+            peak.iter()
+                .map(|&x| if x > 0.0 { 1.0 } else { 0.0 })
+                .collect()
+        });
+        let deltas = deltas
+            .iter()
+            .map(|delta| match delta {
+                Some(Delta::Delta1D(d)) => *d,
+                _ => 0,
+            })
+            .collect();
+        deltasets.push(DeltaSet {
+            peak,
+            start,
+            end,
+            deltas,
+        });
+    }
+    Ok(cvar { deltasets })
+}
+
+impl Serialize for cvar {
+    fn to_bytes(&self, data: &mut Vec<u8>) -> Result<(), SerializationError> {
+        data.put(1_u16)?;
+        data.put(0_u16)?;
+        let tvs = TupleVariationStore(
+            self.deltasets
+                .iter()
+                .map(|ds| ds.to_tuple_variation())
+                .collect(),
+        );
+        let mut tvs_data = otspec::ser::to_bytes(&tvs)?;
+        // The offset to the serialized data is from the start of the table,
+        // not from the start of the tuple variation store.
+        let data_offset = u16::from_be_bytes([tvs_data[2], tvs_data[3]]) + 4;
+        tvs_data[2..4].copy_from_slice(&data_offset.to_be_bytes());
+        data.extend(tvs_data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cvar_serde() {
+        let binary_cvar = vec![
+            0x00, 0x01, 0x00, 0x00, /* version 1.0 */
+            0x80, 0x02, /* Two tuples, shared points */
+            0x00, 0x14, /* Offset to data */
+            0x00, 0x04, 0x80, 0x00, 0x40, 0x00, /* Peak at 1.0, 4 bytes of data */
+            0x00, 0x07, 0xa0, 0x00, 0xc0, 0x00, /* Peak at -1.0, private points */
+            0x00, /* Shared points: all of them */
+            0x02, 0x0a, 0x00, 0xf6, /* Deltas: 10, 0, -10 */
+            0x02, 0x01, 0x00, 0x02, /* Private points: 0, 2 */
+            0x01, 0x05, 0x05, /* Deltas: 5, 5 */
+        ];
+        let expected = cvar {
+            deltasets: vec![
+                DeltaSet {
+                    peak: vec![1.0],
+                    start: vec![0.0],
+                    end: vec![1.0],
+                    deltas: vec![10, 0, -10],
+                },
+                DeltaSet {
+                    peak: vec![-1.0],
+                    start: vec![-1.0],
+                    end: vec![0.0],
+                    deltas: vec![5, 0, 5],
+                },
+            ],
+        };
+        let deserialized = from_bytes(&binary_cvar, 1, 3).unwrap();
+        assert_eq!(deserialized, expected);
+        let serialized = otspec::ser::to_bytes(&deserialized).unwrap();
+        let deserialized_again = from_bytes(&serialized, 1, 3).unwrap();
+        assert_eq!(deserialized_again, expected);
+    }
+}
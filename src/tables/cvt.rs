@@ -10,7 +10,7 @@ pub const TAG: Tag = crate::tag!("cvt ");
 /// Represents a font's cvt (Control Value) table
 #[derive(Clone, Debug, PartialEq)]
 #[allow(non_camel_case_types)]
-pub struct cvt(Vec<FWORD>);
+pub struct cvt(pub Vec<FWORD>);
 
 impl Deserialize for cvt {
     fn from_bytes(c: &mut ReaderContext) -> Result<Self, DeserializationError> {
@@ -2,12 +2,15 @@ use self::glyph::CompositeMaxpValues;
 use core::cmp::max;
 
 use super::maxp::maxp;
-use otspec::{DeserializationError, Deserializer, ReaderContext};
+use otspec::{DeserializationError, Deserializer, ReaderContext, SerializationError, Serialize};
+use std::convert::TryInto;
 
 /// Structures for handling components within a composite glyph
 mod component;
 /// Utilities for handling contours
 pub mod contourutils;
+/// Compatible conversion between cubic and quadratic curves
+pub mod cu2qu;
 /// Structures for handling simple glyph descriptions
 mod glyph;
 /// A representation of a contour point
@@ -166,6 +169,38 @@ impl glyf {
         }
     }
 
+    /// Serializes the glyph data, returning the binary table together with
+    /// the offset of each glyph within it.
+    ///
+    /// The offsets include the final end-of-table offset, and so are suitable
+    /// for building a `loca` table with [`loca::compile`](super::loca::compile).
+    pub fn to_bytes_and_offsets(&self) -> Result<(Vec<u8>, Vec<u32>), SerializationError> {
+        let mut glyf_output: Vec<u8> = vec![];
+        let mut loca_indices: Vec<u32> = Vec::with_capacity(self.glyphs.len() + 1);
+
+        for g in &self.glyphs {
+            loca_indices.push(glyf_output.len().try_into().map_err(|_| {
+                SerializationError("glyf table too large for a loca offset".to_string())
+            })?);
+            if g.is_empty() {
+                continue;
+            }
+            g.to_bytes(&mut glyf_output)?;
+            // Add multiple-of-four padding
+            while !glyf_output.len().is_multiple_of(4) {
+                glyf_output.push(0);
+            }
+        }
+        if glyf_output.is_empty() {
+            // Sad special case
+            glyf_output.push(0);
+        }
+        loca_indices.push(glyf_output.len().try_into().map_err(|_| {
+            SerializationError("glyf table too large for a loca offset".to_string())
+        })?);
+        Ok((glyf_output, loca_indices))
+    }
+
     /// Returns a maxp version 1.0 table reflecting the statistics in this glyf table
     pub fn as_maxp10(&self) -> maxp {
         let num_glyphs = self.glyphs.len() as u16;
@@ -211,6 +246,14 @@ impl glyf {
     }
 }
 
+impl Serialize for glyf {
+    fn to_bytes(&self, data: &mut Vec<u8>) -> Result<(), SerializationError> {
+        let (bytes, _) = self.to_bytes_and_offsets()?;
+        data.extend(bytes);
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::font;
@@ -501,6 +544,24 @@ mod tests {
                 on_curve: true
             }
         );
+
+        // Modify the glyf table and check that glyf and loca are compiled
+        // together through the table set.
+        let mut font = deserialized;
+        let mut modified = glyf.into_owned();
+        modified.glyphs[4] = modified.glyphs[0].clone();
+        font.tables.insert(modified.clone());
+        let glyf_data = font.tables.serialize_table(super::TAG).unwrap().unwrap();
+        let loca_data = font
+            .tables
+            .serialize_table(crate::tables::loca::TAG)
+            .unwrap()
+            .unwrap();
+        let loca =
+            crate::tables::loca::from_bytes(&mut otspec::ReaderContext::new(loca_data), false)
+                .unwrap();
+        let reloaded = super::from_bytes(&glyf_data, &loca.indices).unwrap();
+        assert_eq!(reloaded, modified);
     }
 
     #[test]
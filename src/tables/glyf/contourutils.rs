@@ -99,6 +99,19 @@ pub fn glyf_contour_to_kurbo_contour(contour: &[Point]) -> kurbo::BezPath {
     let mut path = kurbo::BezPath::new();
     let mut contour = contour.to_vec();
     insert_explicit_oncurves(&mut contour);
+    // A contour which starts with an off-curve point begins at the (implied
+    // or explicit) on-curve point before it.
+    if !contour[0].on_curve {
+        let last = contour[contour.len() - 1];
+        if !last.on_curve {
+            contour.push(Point {
+                on_curve: true,
+                x: (contour[0].x + last.x) / 2,
+                y: (contour[0].y + last.y) / 2,
+            });
+        }
+        contour.rotate_right(1);
+    }
     path.move_to((contour[0].x as f64, contour[0].y as f64));
     let mut segment: Vec<&Point> = vec![];
     for pt in &contour[1..] {
@@ -0,0 +1,647 @@
+use super::contourutils::glyf_contour_to_kurbo_contour;
+use super::Point;
+use kurbo::{BezPath, CubicBez, ParamCurve, PathEl, QuadBez, Vec2};
+use otspec::types::ot_round;
+
+/// The maximum number of quadratic segments used to approximate a cubic curve
+pub const MAX_N: usize = 100;
+
+/// The number of points sampled on each quadratic segment when measuring
+/// the error of an approximation
+const ERROR_SAMPLES: usize = 16;
+
+/// An error which occurred while converting curves
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cu2QuError(pub String);
+
+impl std::fmt::Display for Cu2QuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Cu2QuError {}
+
+/// Checks whether a cubic curve lies within a circle of the given radius
+/// around the origin. Used on the difference between two curves.
+fn cubic_farthest_fit_inside(p0: Vec2, p1: Vec2, p2: Vec2, p3: Vec2, tolerance: f64) -> bool {
+    if p1.hypot() <= tolerance && p2.hypot() <= tolerance {
+        return true;
+    }
+    let mid = (p0 + (p1 + p2) * 3.0 + p3) * 0.125;
+    if mid.hypot() > tolerance {
+        return false;
+    }
+    let deriv3 = (p3 + p2 - p1 - p0) * 0.125;
+    cubic_farthest_fit_inside(p0, (p0 + p1) * 0.5, mid - deriv3, mid, tolerance)
+        && cubic_farthest_fit_inside(mid, mid + deriv3, (p2 + p3) * 0.5, p3, tolerance)
+}
+
+/// The intersection of the line through `a` and `b` with the line through
+/// `c` and `d`, if they are not parallel.
+fn calc_intersect(a: Vec2, b: Vec2, c: Vec2, d: Vec2) -> Option<Vec2> {
+    let ab = b - a;
+    let cd = d - c;
+    let p = Vec2::new(-ab.y, ab.x);
+    let denominator = p.dot(cd);
+    if denominator == 0.0 {
+        return None;
+    }
+    let h = p.dot(a - c) / denominator;
+    Some(c + cd * h)
+}
+
+/// Approximates a cubic curve with a single quadratic curve.
+fn cubic_approx_quadratic(cubic: &CubicBez, tolerance: f64) -> Option<Vec<Vec2>> {
+    let (c0, c1, c2, c3) = (
+        cubic.p0.to_vec2(),
+        cubic.p1.to_vec2(),
+        cubic.p2.to_vec2(),
+        cubic.p3.to_vec2(),
+    );
+    let q1 = calc_intersect(c0, c1, c2, c3)?;
+    if !q1.x.is_finite() || !q1.y.is_finite() {
+        return None;
+    }
+    let new_c1 = c0 + (q1 - c0) * (2.0 / 3.0);
+    let new_c2 = c3 + (q1 - c3) * (2.0 / 3.0);
+    if !cubic_farthest_fit_inside(Vec2::ZERO, new_c1 - c1, new_c2 - c2, Vec2::ZERO, tolerance) {
+        return None;
+    }
+    Some(vec![c0, q1, c3])
+}
+
+/// Approximates a cubic curve with a spline of `n` quadratic curves,
+/// returning the start point, the `n` off-curve points and the end point.
+/// The on-curve points between the quadratic curves are implied.
+fn cubic_approx_spline(cubic: &CubicBez, n: usize, tolerance: f64) -> Option<Vec<Vec2>> {
+    if n == 1 {
+        return cubic_approx_quadratic(cubic, tolerance);
+    }
+    let pieces: Vec<CubicBez> = (0..n)
+        .map(|i| cubic.subsegment(i as f64 / n as f64..(i + 1) as f64 / n as f64))
+        .collect();
+    let control = |t: f64, piece: &CubicBez| {
+        let p1 = piece.p0 + (piece.p1 - piece.p0) * 1.5;
+        let p2 = piece.p3 + (piece.p2 - piece.p3) * 1.5;
+        (p1 + (p2 - p1) * t).to_vec2()
+    };
+    let mut next_q1 = control(0.0, &pieces[0]);
+    let mut q2 = cubic.p0.to_vec2();
+    let mut d1 = Vec2::ZERO;
+    let mut spline = vec![cubic.p0.to_vec2(), next_q1];
+    for i in 1..=n {
+        let piece = &pieces[i - 1];
+        let q0 = q2;
+        let q1 = next_q1;
+        if i < n {
+            next_q1 = control(i as f64 / (n - 1) as f64, &pieces[i]);
+            spline.push(next_q1);
+            q2 = (q1 + next_q1) * 0.5;
+        } else {
+            q2 = piece.p3.to_vec2();
+        }
+        let d0 = d1;
+        d1 = q2 - piece.p3.to_vec2();
+        if d1.hypot() > tolerance
+            || !cubic_farthest_fit_inside(
+                d0,
+                q0 + (q1 - q0) * (2.0 / 3.0) - piece.p1.to_vec2(),
+                q2 + (q1 - q2) * (2.0 / 3.0) - piece.p2.to_vec2(),
+                d1,
+                tolerance,
+            )
+        {
+            return None;
+        }
+    }
+    spline.push(cubic.p3.to_vec2());
+    Some(spline)
+}
+
+/// The quadratic curves making up a spline, with their implied on-curve
+/// points made explicit.
+fn spline_quads(spline: &[kurbo::Point]) -> Vec<QuadBez> {
+    let n = spline.len() - 2;
+    (0..n)
+        .map(|k| {
+            let start = if k == 0 {
+                spline[0]
+            } else {
+                spline[k].midpoint(spline[k + 1])
+            };
+            let end = if k == n - 1 {
+                spline[n + 1]
+            } else {
+                spline[k + 1].midpoint(spline[k + 2])
+            };
+            QuadBez::new(start, spline[k + 1], end)
+        })
+        .collect()
+}
+
+/// Measures the largest distance between a cubic curve and a quadratic
+/// spline approximating it, by sampling corresponding points.
+fn spline_error(cubic: &CubicBez, spline: &[kurbo::Point]) -> f64 {
+    let quads = spline_quads(spline);
+    let n = quads.len() as f64;
+    let mut error: f64 = 0.0;
+    for (k, quad) in quads.iter().enumerate() {
+        for s in 0..=ERROR_SAMPLES {
+            let t = s as f64 / ERROR_SAMPLES as f64;
+            let on_cubic = cubic.eval((k as f64 + t) / n);
+            error = error.max((quad.eval(t) - on_cubic).hypot());
+        }
+    }
+    error
+}
+
+/// Approximates a cubic curve from each master with a quadratic spline,
+/// using the same number of quadratic curves in every master.
+///
+/// Each spline is returned as its start point, its off-curve points and its
+/// end point; the on-curve points between its quadratic curves are implied,
+/// so the splines are interpolation-compatible. `max_errors` gives the
+/// tolerance for each master.
+pub fn curves_to_quadratic(
+    curves: &[CubicBez],
+    max_errors: &[f64],
+) -> Result<Vec<Vec<kurbo::Point>>, Cu2QuError> {
+    if curves.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut splines: Vec<Option<Vec<Vec2>>> = vec![None; curves.len()];
+    let mut n = 1;
+    let mut i = 0;
+    let mut last_i = 0;
+    loop {
+        match cubic_approx_spline(&curves[i], n, max_errors[i]) {
+            Some(spline) => {
+                splines[i] = Some(spline);
+                i = (i + 1) % curves.len();
+                if i == last_i {
+                    break;
+                }
+            }
+            None => {
+                if n == MAX_N {
+                    return Err(Cu2QuError(format!(
+                        "No quadratic approximation found for {:?}",
+                        curves
+                    )));
+                }
+                n += 1;
+                last_i = i;
+            }
+        }
+    }
+    Ok(splines
+        .into_iter()
+        .map(|s| s.unwrap().into_iter().map(|v| v.to_point()).collect())
+        .collect())
+}
+
+/// The point at which a path element ends.
+fn end_point(el: &PathEl) -> Option<kurbo::Point> {
+    match *el {
+        PathEl::MoveTo(p) | PathEl::LineTo(p) | PathEl::QuadTo(_, p) | PathEl::CurveTo(_, _, p) => {
+            Some(p)
+        }
+        PathEl::ClosePath => None,
+    }
+}
+
+/// A segment of a path, in every master
+enum Segment {
+    Line(Vec<kurbo::Point>),
+    Quad(Vec<(kurbo::Point, kurbo::Point)>),
+    Cubic(Vec<CubicBez>),
+}
+
+/// A subpath, in every master
+struct Subpath {
+    starts: Vec<kurbo::Point>,
+    closed: bool,
+    segments: Vec<Segment>,
+}
+
+/// Splits compatible paths into subpaths of compatible segments.
+fn compatible_subpaths(paths: &[BezPath]) -> Result<Vec<Subpath>, Cu2QuError> {
+    let elements: Vec<&[PathEl]> = paths.iter().map(|p| p.elements()).collect();
+    let len = elements[0].len();
+    if elements.iter().any(|e| e.len() != len) {
+        return Err(Cu2QuError("Paths have different lengths".to_string()));
+    }
+    let mut subpaths: Vec<Subpath> = vec![];
+    let mut starts = vec![kurbo::Point::ZERO; paths.len()];
+    let mut currents = vec![kurbo::Point::ZERO; paths.len()];
+    for ix in 0..len {
+        let els: Vec<PathEl> = elements.iter().map(|e| e[ix]).collect();
+        let first = els[0];
+        if els
+            .iter()
+            .any(|el| std::mem::discriminant(el) != std::mem::discriminant(&first))
+        {
+            return Err(Cu2QuError(format!(
+                "Paths are not compatible at element {}",
+                ix
+            )));
+        }
+        let segment = match first {
+            PathEl::MoveTo(_) => {
+                for (m, el) in els.iter().enumerate() {
+                    if let PathEl::MoveTo(p) = el {
+                        starts[m] = *p;
+                        currents[m] = *p;
+                    }
+                }
+                subpaths.push(Subpath {
+                    starts: starts.clone(),
+                    closed: false,
+                    segments: vec![],
+                });
+                continue;
+            }
+            PathEl::ClosePath => {
+                // Close the subpath explicitly, unless it already returns
+                // to its start in every master
+                let closing = if currents.iter().zip(starts.iter()).all(|(c, s)| c == s) {
+                    None
+                } else {
+                    Some(Segment::Line(starts.clone()))
+                };
+                if let Some(subpath) = subpaths.last_mut() {
+                    subpath.closed = true;
+                    subpath.segments.extend(closing);
+                }
+                currents = starts.clone();
+                continue;
+            }
+            PathEl::LineTo(_) => Segment::Line(
+                els.iter()
+                    .map(|el| match el {
+                        PathEl::LineTo(p) => *p,
+                        _ => unreachable!(),
+                    })
+                    .collect(),
+            ),
+            PathEl::QuadTo(_, _) => Segment::Quad(
+                els.iter()
+                    .map(|el| match el {
+                        PathEl::QuadTo(p1, p2) => (*p1, *p2),
+                        _ => unreachable!(),
+                    })
+                    .collect(),
+            ),
+            PathEl::CurveTo(_, _, _) => Segment::Cubic(
+                els.iter()
+                    .zip(currents.iter())
+                    .map(|(el, current)| match el {
+                        PathEl::CurveTo(p1, p2, p3) => CubicBez::new(*current, *p1, *p2, *p3),
+                        _ => unreachable!(),
+                    })
+                    .collect(),
+            ),
+        };
+        for (m, el) in els.iter().enumerate() {
+            if let Some(p) = end_point(el) {
+                currents[m] = p;
+            }
+        }
+        match subpaths.last_mut() {
+            Some(subpath) => subpath.segments.push(segment),
+            None => return Err(Cu2QuError("Path does not start with a move".to_string())),
+        }
+    }
+    Ok(subpaths)
+}
+
+/// Quadratic contours converted from a set of compatible paths
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuadraticContours {
+    /// For each master, its contours as TrueType points. The contours of
+    /// all masters have the same number of points, with the same on-curve
+    /// flags.
+    pub contours: Vec<Vec<Vec<Point>>>,
+    /// For each contour, the largest error of each of its segments across
+    /// all masters. Lines and quadratic curves have no error.
+    pub errors: Vec<Vec<f64>>,
+}
+
+/// Converts a set of interpolation-compatible paths (one for each master)
+/// to quadratic TrueType contours with identical point structure.
+///
+/// Each cubic curve is approximated, in all masters together, by a spline
+/// with the same number of quadratic curves, within `max_error` font units.
+/// Contour direction is preserved; subpaths without any segments are
+/// dropped.
+pub fn paths_to_quadratic(
+    paths: &[BezPath],
+    max_error: f64,
+) -> Result<QuadraticContours, Cu2QuError> {
+    let mut result = QuadraticContours {
+        contours: vec![vec![]; paths.len()],
+        errors: vec![],
+    };
+    if paths.is_empty() {
+        return Ok(result);
+    }
+    let point = |p: kurbo::Point, on_curve: bool| Point {
+        x: ot_round(p.x) as i16,
+        y: ot_round(p.y) as i16,
+        on_curve,
+    };
+    let max_errors = vec![max_error; paths.len()];
+    for subpath in compatible_subpaths(paths)? {
+        if subpath.segments.is_empty() {
+            continue;
+        }
+        // A closed contour ends at its start point, which is not repeated
+        let mut contours: Vec<Vec<Point>> = if subpath.closed {
+            vec![vec![]; paths.len()]
+        } else {
+            subpath
+                .starts
+                .iter()
+                .map(|&p| vec![point(p, true)])
+                .collect()
+        };
+        let mut errors = vec![];
+        for segment in subpath.segments {
+            match segment {
+                Segment::Line(points) => {
+                    for (contour, p) in contours.iter_mut().zip(points) {
+                        contour.push(point(p, true));
+                    }
+                    errors.push(0.0);
+                }
+                Segment::Quad(points) => {
+                    for (contour, (p1, p2)) in contours.iter_mut().zip(points) {
+                        contour.push(point(p1, false));
+                        contour.push(point(p2, true));
+                    }
+                    errors.push(0.0);
+                }
+                Segment::Cubic(cubics) => {
+                    let splines = curves_to_quadratic(&cubics, &max_errors)?;
+                    let mut error: f64 = 0.0;
+                    for ((contour, spline), cubic) in
+                        contours.iter_mut().zip(splines).zip(cubics.iter())
+                    {
+                        error = error.max(spline_error(cubic, &spline));
+                        let last = spline.len() - 1;
+                        contour.extend(spline[1..last].iter().map(|&p| point(p, false)));
+                        contour.push(point(spline[last], true));
+                    }
+                    errors.push(error);
+                }
+            }
+        }
+        for (master, mut contour) in contours.into_iter().enumerate() {
+            if subpath.closed {
+                contour.rotate_right(1);
+            }
+            result.contours[master].push(contour);
+        }
+        result.errors.push(errors);
+    }
+    Ok(result)
+}
+
+/// Converts the quadratic curves `quads[start..end]` to a single cubic
+/// curve, if it stays within `tolerance` of all of them.
+fn merge_quads(quads: &[CubicBez], tolerance: f64) -> Option<CubicBez> {
+    let (first, last) = (quads[0], quads[quads.len() - 1]);
+    if quads.len() == 1 {
+        return Some(first);
+    }
+    // Reconstruct the parameters at which the cubic was split, from the
+    // ratios of the tangent lengths on either side of each join
+    let mut lengths = vec![1.0];
+    for pair in quads.windows(2) {
+        let before = (pair[0].p3 - pair[0].p2).hypot();
+        let after = (pair[1].p1 - pair[1].p0).hypot();
+        if before == 0.0 || after == 0.0 {
+            return None;
+        }
+        lengths.push(lengths[lengths.len() - 1] * after / before);
+    }
+    let total: f64 = lengths.iter().sum();
+    let mut ts = vec![0.0];
+    for length in &lengths {
+        ts.push(ts[ts.len() - 1] + length / total);
+    }
+    let (t1, t2) = (ts[1], ts[ts.len() - 2]);
+    let cubic = CubicBez::new(
+        first.p0,
+        first.p0 + (first.p1 - first.p0) / t1,
+        last.p3 + (last.p2 - last.p3) / (1.0 - t2),
+        last.p3,
+    );
+    for (quad, range) in quads.iter().zip(ts.windows(2)) {
+        let piece = cubic.subsegment(range[0]..range[1]);
+        if !cubic_farthest_fit_inside(
+            piece.p0 - quad.p0,
+            piece.p1 - quad.p1,
+            piece.p2 - quad.p2,
+            piece.p3 - quad.p3,
+            tolerance,
+        ) {
+            return None;
+        }
+    }
+    Some(cubic)
+}
+
+/// Converts a set of interpolation-compatible TrueType contours (one for
+/// each master) to cubic paths, the inverse of [`paths_to_quadratic`].
+///
+/// Runs of quadratic curves are merged into as few cubic curves as
+/// possible, as long as the result is within `max_error` font units of the
+/// original in every master, so the resulting paths are also compatible.
+pub fn contours_to_cubic(
+    contours: &[Vec<Point>],
+    max_error: f64,
+) -> Result<Vec<BezPath>, Cu2QuError> {
+    if contours.is_empty() {
+        return Ok(vec![]);
+    }
+    let structure: Vec<bool> = contours[0].iter().map(|p| p.on_curve).collect();
+    if contours
+        .iter()
+        .any(|c| c.iter().map(|p| p.on_curve).ne(structure.iter().copied()))
+    {
+        return Err(Cu2QuError("Contours are not compatible".to_string()));
+    }
+    let quadratic: Vec<BezPath> = contours
+        .iter()
+        .map(|c| glyf_contour_to_kurbo_contour(c))
+        .collect();
+    let elements: Vec<&[PathEl]> = quadratic.iter().map(|p| p.elements()).collect();
+    let mut paths: Vec<BezPath> = vec![BezPath::new(); contours.len()];
+    let mut ix = 0;
+    while ix < elements[0].len() {
+        if !matches!(elements[0][ix], PathEl::QuadTo(_, _)) {
+            for (path, els) in paths.iter_mut().zip(elements.iter()) {
+                path.push(els[ix]);
+            }
+            ix += 1;
+            continue;
+        }
+        // Collect the run of quadratic curves, raised to cubic curves
+        let mut end = ix;
+        while end < elements[0].len() && matches!(elements[0][end], PathEl::QuadTo(_, _)) {
+            end += 1;
+        }
+        let runs: Vec<Vec<CubicBez>> = elements
+            .iter()
+            .map(|els| {
+                let mut current = end_point(&els[ix - 1]).unwrap_or_default();
+                els[ix..end]
+                    .iter()
+                    .map(|el| match el {
+                        PathEl::QuadTo(p1, p2) => {
+                            let cubic = QuadBez::new(current, *p1, *p2).raise();
+                            current = *p2;
+                            cubic
+                        }
+                        _ => unreachable!(),
+                    })
+                    .collect()
+            })
+            .collect();
+        // Greedily merge as many quadratic curves as possible
+        let mut start = 0;
+        while start < end - ix {
+            let mut merged = None;
+            for stop in (start + 1..=end - ix).rev() {
+                let cubics: Option<Vec<CubicBez>> = runs
+                    .iter()
+                    .map(|run| merge_quads(&run[start..stop], max_error))
+                    .collect();
+                if let Some(cubics) = cubics {
+                    merged = Some((stop, cubics));
+                    break;
+                }
+            }
+            // A single curve always converts
+            let (stop, cubics) = merged.unwrap();
+            for (path, cubic) in paths.iter_mut().zip(cubics) {
+                path.curve_to(cubic.p1, cubic.p2, cubic.p3);
+            }
+            start = stop;
+        }
+        ix = end;
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kurbo::ParamCurveNearest;
+
+    fn pt(x: i16, y: i16, on_curve: bool) -> Point {
+        Point { x, y, on_curve }
+    }
+
+    #[test]
+    fn test_cubic_approx_exact() {
+        // A cubic which is really a quadratic needs a single curve
+        let cubic = QuadBez::new((0.0, 0.0), (50.0, 100.0), (100.0, 0.0)).raise();
+        let splines = curves_to_quadratic(&[cubic], &[0.1]).unwrap();
+        let expected = [
+            kurbo::Point::new(0.0, 0.0),
+            kurbo::Point::new(50.0, 100.0),
+            kurbo::Point::new(100.0, 0.0),
+        ];
+        for (p, q) in splines[0].iter().zip(expected.iter()) {
+            assert!((*p - *q).hypot() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_paths_to_quadratic_compatible() {
+        let mut light = BezPath::new();
+        light.move_to((0.0, 0.0));
+        light.curve_to((0.0, 100.0), (100.0, 100.0), (100.0, 0.0));
+        light.close_path();
+        // The bold master needs more curves
+        let mut bold = BezPath::new();
+        bold.move_to((0.0, 0.0));
+        bold.curve_to((0.0, 400.0), (900.0, -300.0), (1000.0, 0.0));
+        bold.close_path();
+
+        let result = paths_to_quadratic(&[light, bold], 1.0).unwrap();
+        assert_eq!(result.contours.len(), 2);
+        let structure = |contours: &Vec<Vec<Point>>| -> Vec<Vec<bool>> {
+            contours
+                .iter()
+                .map(|c| c.iter().map(|p| p.on_curve).collect())
+                .collect()
+        };
+        assert_eq!(
+            structure(&result.contours[0]),
+            structure(&result.contours[1])
+        );
+        // Start point, end point, and at least two off-curve points
+        assert!(result.contours[0][0].len() > 3);
+        assert_eq!(result.contours[0][0][0], pt(0, 0, true));
+        assert_eq!(result.errors.len(), 1);
+        // The curve and the closing line
+        assert_eq!(result.errors[0].len(), 2);
+        assert!(result.errors[0][0] <= 1.0);
+        assert_eq!(result.errors[0][1], 0.0);
+
+        let mut other = BezPath::new();
+        other.move_to((0.0, 0.0));
+        other.line_to((100.0, 0.0));
+        other.close_path();
+        let mut light = BezPath::new();
+        light.move_to((0.0, 0.0));
+        light.curve_to((0.0, 100.0), (100.0, 100.0), (100.0, 0.0));
+        light.close_path();
+        assert!(paths_to_quadratic(&[light, other], 1.0).is_err());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut light = BezPath::new();
+        light.move_to((0.0, 0.0));
+        light.curve_to((0.0, 200.0), (300.0, 200.0), (300.0, 0.0));
+        light.close_path();
+        let mut bold = BezPath::new();
+        bold.move_to((0.0, 0.0));
+        bold.curve_to((0.0, 300.0), (500.0, 300.0), (500.0, 0.0));
+        bold.close_path();
+        let quadratic = paths_to_quadratic(&[light.clone(), bold.clone()], 0.5).unwrap();
+        let contours: Vec<Vec<Point>> = quadratic.contours.iter().map(|c| c[0].clone()).collect();
+        let cubic = contours_to_cubic(&contours, 1.0).unwrap();
+        for (path, original) in cubic.iter().zip([light, bold].iter()) {
+            // The spline is merged back into fewer cubic curves
+            let curves: Vec<CubicBez> = path
+                .segments()
+                .filter_map(|seg| match seg {
+                    kurbo::PathSeg::Cubic(c) => Some(c),
+                    _ => None,
+                })
+                .collect();
+            assert!(curves.len() < contours[0].len() - 2);
+            let original = match original.segments().next() {
+                Some(kurbo::PathSeg::Cubic(c)) => c,
+                _ => panic!("Expected a cubic curve"),
+            };
+            // The merged curves stay close to the original curve
+            for curve in curves {
+                for t in 0..=10 {
+                    let point = curve.eval(t as f64 / 10.0);
+                    let nearest = original.nearest(point, 1e-6).distance_sq.sqrt();
+                    assert!(nearest < 2.0);
+                }
+            }
+        }
+
+        let incompatible = vec![contours[0].clone(), vec![pt(0, 0, true), pt(10, 0, true)]];
+        assert!(contours_to_cubic(&incompatible, 1.0).is_err());
+    }
+}
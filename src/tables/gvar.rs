@@ -138,8 +138,8 @@ impl DeltaSet {
 
     pub(crate) fn scale_deltas(&mut self, factor: f32) {
         for (x, y) in self.deltas.iter_mut() {
-            *x = (*x as f32 * factor) as i16;
-            *y = (*y as f32 * factor) as i16;
+            *x = ot_round(*x as f32 * factor) as i16;
+            *y = ot_round(*y as f32 * factor) as i16;
         }
     }
 }
@@ -184,7 +184,7 @@ pub fn from_bytes(
     /* Shared tuples */
     let mut shared_tuples: Vec<Tuple> = Vec::with_capacity(core.sharedTupleCount as usize);
     c.ptr = c.top_of_table() + (core.sharedTuplesOffset as usize);
-    for _ in 0..core.sharedTupleCount + 1 {
+    for _ in 0..core.sharedTupleCount {
         // println!("Trying to deserialize shared tuple array {:?}", bytes);
         let tuple: Vec<F2DOT14> = c.de_counted(axis_count)?;
         let tuple_f32: Vec<f32> = tuple.iter().map(|t| (*t).into()).collect();
@@ -218,7 +218,7 @@ pub fn from_bytes(
             for tvh in tvs.0 {
                 let deltas = tvh.iup_delta(&coords_and_ends[i].0, &coords_and_ends[i].1);
                 let index = tvh.0.sharedTupleIndex as usize;
-                if index > shared_tuples.len() {
+                if tvh.0.peakTuple.is_none() && index >= shared_tuples.len() {
                     return Err(DeserializationError(format!(
                         "Invalid shared tuple index {:}",
                         index
@@ -257,15 +257,42 @@ pub fn from_bytes(
 }
 
 impl gvar {
+    /// Returns `true` if any glyph in this table has variation data.
+    pub fn has_variations(&self) -> bool {
+        self.variations
+            .iter()
+            .flatten()
+            .any(|v| !v.deltasets.is_empty())
+    }
+
+    /// The number of axes the variation data refers to, or zero if there is
+    /// no variation data.
+    pub fn axis_count(&self) -> uint16 {
+        self.variations
+            .iter()
+            .flatten()
+            .find_map(|v| v.deltasets.first())
+            .map_or(0, |ds| ds.peak.len() as uint16)
+    }
+
     /// Serializes this table to binary, given a reference to the `glyf` table.
+    ///
+    /// The axis count is taken from the variation data; a table without any
+    /// should be written with
+    /// [`to_bytes_with_axis_count`](Self::to_bytes_with_axis_count) so that
+    /// it matches the `fvar` table.
     pub fn to_bytes(&self, glyf: Option<&glyf>) -> Vec<u8> {
+        self.to_bytes_with_axis_count(glyf, self.axis_count())
+    }
+
+    /// Serializes this table to binary, given a reference to the `glyf` table
+    /// and the number of axes in the `fvar` table.
+    pub fn to_bytes_with_axis_count(&self, glyf: Option<&glyf>, axis_count: uint16) -> Vec<u8> {
         let mut out: Vec<u8> = vec![];
         // Determine all the shared tuples.
         let mut shared_tuple_counter: Counter<Vec<u8>> = Counter::new();
-        let mut axis_count: uint16 = 0;
         for var in self.variations.iter().flatten() {
             for ds in &var.deltasets {
-                axis_count = ds.peak.len() as uint16;
                 // println!("Peak: {:?}", ds.peak);
                 let mut tuple: Vec<u8> = vec![];
                 for t in &ds.peak {
@@ -276,9 +303,6 @@ impl gvar {
         }
         // shared_tuple_counter.retain(|_, &mut v| v > 1);
         let most_common_tuples: Vec<(Vec<u8>, usize)> = shared_tuple_counter.most_common();
-        if most_common_tuples.is_empty() {
-            panic!("Some more sensible error checking here for null case");
-        }
         let shared_tuple_count = most_common_tuples.len() as u16;
         let flags = 1; // XXX
 
@@ -322,6 +346,10 @@ impl gvar {
                     .collect();
 
                 let tvs = TupleVariationStore(tuple_variations);
+                // Glyphs without variations have no data at all
+                if tvs.0.is_empty() {
+                    continue;
+                }
                 serialized_tvs.extend(otspec::ser::to_bytes(&tvs).unwrap());
                 // Add a byte of padding
                 if (serialized_tvs.len() % 2) != 0 {
@@ -366,9 +394,14 @@ impl gvar {
 //      A DeltaSet consists of peak/start/end and (i16,i16) deltas.
 //      Each TupleVariation consists of the TupleVariationHeader and a Vec<Option<Delta>>
 
+/// Serializes the table without reference to the `glyf` table, and so without
+/// optimizing deltas which could be inferred. Prefer
+/// [`TableSet::serialize_table`](crate::table_store::TableSet::serialize_table),
+/// which takes the glyph outlines into account.
 impl Serialize for gvar {
-    fn to_bytes(&self, _data: &mut Vec<u8>) -> Result<(), SerializationError> {
-        panic!("Don't call this serializer, call the one in Font instead")
+    fn to_bytes(&self, data: &mut Vec<u8>) -> Result<(), SerializationError> {
+        data.extend(gvar::to_bytes(self, None));
+        Ok(())
     }
 }
 
@@ -573,4 +606,55 @@ mod tests {
 
         // assert_eq!(serialized, binary_gvar); // Are they the same binary?
     }
+
+    #[test]
+    fn gvar_invalid_shared_tuple_index() {
+        let mut binary_gvar = vec![
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x02, 0x00, 0x00, 0x00, 0x1e, 0x00, 0x04,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x26, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0d,
+            0x00, 0x24, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x80, 0x02, 0x00, 0x0c,
+            0x00, 0x06, 0x00, 0x00, 0x00, 0x06, 0x00, 0x01, 0x00, 0x86, 0x02, 0xd2, 0xd2, 0x2e,
+            0x83, 0x02, 0x52, 0xae, 0xf7, 0x83, 0x86, 0x00, 0x80, 0x03, 0x00, 0x14, 0x00, 0x0a,
+            0x20, 0x00, 0x00, 0x07, 0x00, 0x01, 0x00, 0x07, 0x80, 0x00, 0x40, 0x00, 0x40, 0x00,
+            0x00, 0x02, 0x01, 0x01, 0x02, 0x01, 0x26, 0xda, 0x01, 0x83, 0x7d, 0x03, 0x26, 0x26,
+            0xda, 0xda, 0x83, 0x87, 0x03, 0x13, 0x13, 0xed, 0xed, 0x83, 0x87, 0x00,
+        ];
+        let points = vec![
+            (vec![], vec![]),
+            (vec![], vec![]),
+            (vec![(0, 0); 7], vec![2, 3, 4, 5, 6]),
+            (vec![(0, 0); 8], vec![3, 4, 5, 6, 7]),
+        ];
+        assert!(super::from_bytes(&binary_gvar, points.clone()).is_ok());
+        // Only the first shared tuple remains, but the glyphs refer to both
+        binary_gvar[7] = 0x01;
+        assert!(super::from_bytes(&binary_gvar, points).is_err());
+    }
+
+    #[test]
+    fn gvar_empty_roundtrip() {
+        let empty = super::gvar {
+            variations: vec![None, None],
+        };
+        let serialized = empty.to_bytes_with_axis_count(None, 2);
+        assert_eq!(
+            serialized,
+            vec![
+                0x00, 0x01, 0x00, 0x00, // version 1.0
+                0x00, 0x02, // axisCount
+                0x00, 0x00, // sharedTupleCount
+                0x00, 0x00, 0x00, 0x20, // sharedTuplesOffset
+                0x00, 0x02, // glyphCount
+                0x00, 0x01, // flags
+                0x00, 0x00, 0x00, 0x20, // glyphVariationDataArrayOffset
+                0x00, 0x00, 0x00, 0x00, // glyphVariationDataOffsets
+                0x00, 0x00, 0x00, 0x00, //
+                0x00, 0x00, 0x00, 0x00, //
+            ]
+        );
+        let points = vec![(vec![], vec![]), (vec![], vec![])];
+        let re_de: super::gvar = super::from_bytes(&serialized, points).unwrap();
+        assert_eq!(re_de, empty);
+        assert!(otspec::ser::to_bytes(&empty).is_ok());
+    }
 }
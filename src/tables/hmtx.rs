@@ -70,14 +70,16 @@ impl hmtx {
     }
 }
 
+/// Serializes the metrics alone. The corresponding `numberOfHMetrics` value
+/// for the `hhea` table can be obtained from [`hmtx::number_of_hmetrics`].
 impl Serialize for hmtx {
     fn to_bytes(
         &self,
-        _: &mut std::vec::Vec<u8>,
+        data: &mut std::vec::Vec<u8>,
     ) -> std::result::Result<(), otspec::SerializationError> {
-        Err(otspec::SerializationError(
-            "Can't serialize hmtx directly".to_string(),
-        ))
+        let (bytes, _) = hmtx::to_bytes(self);
+        data.extend(bytes);
+        Ok(())
     }
 }
 
@@ -158,4 +160,30 @@ mod tests {
         // println!("{:?}", fhmtx);
         assert_eq!(fhmtx.metrics, metrics);
     }
+
+    #[test]
+    fn hmtx_ser() {
+        let fhmtx = hmtx {
+            metrics: vec![
+                Metric {
+                    advanceWidth: 756,
+                    lsb: 5,
+                },
+                Metric {
+                    advanceWidth: 600,
+                    lsb: 29,
+                },
+                Metric {
+                    advanceWidth: 600,
+                    lsb: -141,
+                },
+            ],
+        };
+        let serialized = otspec::ser::to_bytes(&fhmtx).unwrap();
+        assert_eq!(
+            serialized,
+            vec![0x02, 0xf4, 0x00, 0x05, 0x02, 0x58, 0x00, 0x1d, 0xff, 0x73]
+        );
+        assert_eq!(fhmtx.number_of_hmetrics(), 2);
+    }
 }
@@ -1,3 +1,5 @@
+use std::convert::TryFrom;
+
 use otspec::{DeserializationError, Deserializer, ReaderContext, Serialize};
 
 /// The 'loca' OpenType tag.
@@ -46,13 +48,33 @@ pub(crate) fn from_bytes(
     Ok(res)
 }
 
+/// Builds a binary `loca` table from a list of glyph offsets.
+///
+/// The offsets must include the final end-of-table offset, as returned by
+/// [`glyf::to_bytes_and_offsets`](super::glyf::glyf::to_bytes_and_offsets).
+/// Returns the binary table and whether the long (32-bit) format was used,
+/// which must be reflected in the `head` table's `indexToLocFormat` field.
+pub fn compile(offsets: &[u32]) -> (Vec<u8>, bool) {
+    let is_32bit = u16::try_from(offsets.last().copied().unwrap_or(0)).is_err();
+    if is_32bit {
+        (otspec::ser::to_bytes(&offsets.to_vec()).unwrap(), true)
+    } else {
+        let mut data = Vec::with_capacity(offsets.len() * 2);
+        offsets
+            .iter()
+            .map(|x| (*x / 2) as u16)
+            .for_each(|x| x.to_bytes(&mut data).unwrap());
+        (data, false)
+    }
+}
+
 impl Serialize for loca {
     fn to_bytes(
         &self,
         _: &mut std::vec::Vec<u8>,
     ) -> std::result::Result<(), otspec::SerializationError> {
         Err(otspec::SerializationError(
-            "Can't serialize loca directly".to_string(),
+            "loca depends on glyf; serialize it through TableSet::serialize_table".to_string(),
         ))
     }
 }
@@ -70,4 +92,14 @@ mod tests {
         // println!("{:?}", floca);
         assert_eq!(floca.indices, locations);
     }
+
+    #[test]
+    fn loca_compile() {
+        let (data, is_32bit) = super::compile(&[0, 608, 608, 664]);
+        assert!(!is_32bit);
+        assert_eq!(data, vec![0x00, 0x00, 0x01, 0x30, 0x01, 0x30, 0x01, 0x4c]);
+        let (data, is_32bit) = super::compile(&[0, 0x10000]);
+        assert!(is_32bit);
+        assert_eq!(data, vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00]);
+    }
 }
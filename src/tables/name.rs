@@ -167,6 +167,101 @@ pub struct name {
     pub records: Vec<NameRecord>,
 }
 
+impl name {
+    /// Returns `true` if the table has a record with the given name ID.
+    pub fn contains(&self, name_id: uint16) -> bool {
+        self.records.iter().any(|r| r.nameID == name_id)
+    }
+
+    /// Returns the string with the given name ID, preferring the Windows
+    /// English record to records for other platforms and languages.
+    pub fn get(&self, name_id: uint16) -> Option<&str> {
+        let mut records = self.records.iter().filter(|r| r.nameID == name_id);
+        records
+            .clone()
+            .find(|r| r.platformID == 3 && r.languageID == 0x409)
+            .or_else(|| records.next())
+            .map(|r| r.string.as_str())
+    }
+
+    /// Sets the Windows English record with the given name ID, adding it if
+    /// needed. Records for other platforms and languages are left alone.
+    pub fn set(&mut self, name_id: uint16, string: &str) {
+        let record = NameRecord::windows_unicode(name_id, string);
+        match self
+            .records
+            .iter_mut()
+            .find(|r| r.nameID == name_id && r.platformID == 3 && r.languageID == 0x409)
+        {
+            Some(existing) => *existing = record,
+            None => self.records.push(record),
+        }
+    }
+
+    /// Returns the string with the given name ID for a platform, given as a
+    /// (platform ID, encoding ID, language ID) triple.
+    pub fn get_for_platform(
+        &self,
+        name_id: uint16,
+        (platform_id, encoding_id, language_id): (uint16, uint16, uint16),
+    ) -> Option<&str> {
+        self.records
+            .iter()
+            .find(|r| {
+                r.nameID == name_id
+                    && r.platformID == platform_id
+                    && r.encodingID == encoding_id
+                    && r.languageID == language_id
+            })
+            .map(|r| r.string.as_str())
+    }
+
+    /// Sets the record with the given name ID for a platform, given as a
+    /// (platform ID, encoding ID, language ID) triple, adding it if needed.
+    pub fn set_for_platform(
+        &mut self,
+        name_id: uint16,
+        (platform_id, encoding_id, language_id): (uint16, uint16, uint16),
+        string: &str,
+    ) {
+        let record = NameRecord {
+            platformID: platform_id,
+            encodingID: encoding_id,
+            languageID: language_id,
+            nameID: name_id,
+            string: string.to_string(),
+        };
+        match self.records.iter_mut().find(|r| {
+            r.nameID == name_id
+                && r.platformID == platform_id
+                && r.encodingID == encoding_id
+                && r.languageID == language_id
+        }) {
+            Some(existing) => *existing = record,
+            None => self.records.push(record),
+        }
+    }
+
+    /// Removes all records with the given name ID.
+    pub fn remove(&mut self, name_id: uint16) {
+        self.records.retain(|r| r.nameID != name_id);
+    }
+
+    /// Adds a Windows English record with a new font-specific name ID (256
+    /// or above), returning the ID.
+    pub fn add(&mut self, string: &str) -> uint16 {
+        let name_id = self
+            .records
+            .iter()
+            .map(|r| r.nameID)
+            .max()
+            .map_or(256, |max| (max + 1).max(256));
+        self.records
+            .push(NameRecord::windows_unicode(name_id, string));
+        name_id
+    }
+}
+
 impl Deserialize for name {
     fn from_bytes(c: &mut ReaderContext) -> Result<Self, DeserializationError> {
         c.skip(2);
@@ -0,0 +1,47 @@
+use otspec::types::*;
+use otspec::Deserializer;
+use otspec_macros::tables;
+
+/// The 'vhea' OpenType tag.
+pub const TAG: Tag = crate::tag!("vhea");
+
+tables!(vhea {
+    uint16 majorVersion
+    uint16 minorVersion
+    FWORD ascent
+    FWORD descent
+    FWORD lineGap
+    UFWORD  advanceHeightMax
+    FWORD   minTopSideBearing
+    FWORD   minBottomSideBearing
+    FWORD   yMaxExtent
+    int16   caretSlopeRise
+    int16   caretSlopeRun
+    int16   caretOffset
+    int16   reserved0
+    int16   reserved1
+    int16   reserved2
+    int16   reserved3
+    int16   metricDataFormat
+    uint16  numOfLongVerMetrics
+});
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn vhea_serde() {
+        let binary_vhea = vec![
+            0x00, 0x01, 0x10, 0x00, 0x01, 0xf4, 0xfe, 0x0c, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x32,
+            0x00, 0x14, 0x03, 0xb6, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+        ];
+        let fvhea: super::vhea = otspec::de::from_bytes(&binary_vhea).unwrap();
+        assert_eq!(fvhea.minorVersion, 0x1000);
+        assert_eq!(fvhea.ascent, 500);
+        assert_eq!(fvhea.descent, -500);
+        assert_eq!(fvhea.advanceHeightMax, 1000);
+        assert_eq!(fvhea.caretSlopeRun, 1);
+        assert_eq!(fvhea.numOfLongVerMetrics, 2);
+        assert_eq!(otspec::ser::to_bytes(&fvhea).unwrap(), binary_vhea);
+    }
+}
@@ -0,0 +1,123 @@
+use std::convert::TryInto;
+
+use otspec::types::*;
+use otspec::{DeserializationError, Deserializer, ReaderContext, Serialize};
+use otspec_macros::{Deserialize, Serialize};
+
+/// The 'vmtx' OpenType tag.
+pub const TAG: Tag = crate::tag!("vmtx");
+
+/// A single vertical metric
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub struct Metric {
+    /// The full vertical advance height of the glyph
+    pub advanceHeight: u16,
+    /// The top side bearing of the glyph
+    pub tsb: int16,
+}
+
+/// The vertical metrics table
+#[derive(Clone, Debug, PartialEq)]
+#[allow(non_camel_case_types)]
+pub struct vmtx {
+    /// The list of metrics, corresponding to the glyph order
+    pub metrics: Vec<Metric>,
+}
+
+impl vmtx {
+    /// The number of vertical metrics (to be stored in the `vhea` table)
+    pub fn number_of_vmetrics(&self) -> uint16 {
+        let last = match self.metrics.last() {
+            Some(metric) => metric.advanceHeight,
+            None => return 0,
+        };
+
+        let dupe_heights = self
+            .metrics
+            .iter()
+            .rev()
+            .skip(1)
+            .take_while(|m| m.advanceHeight == last)
+            .count();
+        (self.metrics.len() - dupe_heights).try_into().unwrap()
+    }
+}
+
+/// Serializes the metrics alone. The corresponding `numOfLongVerMetrics`
+/// value for the `vhea` table can be obtained from
+/// [`vmtx::number_of_vmetrics`].
+impl Serialize for vmtx {
+    fn to_bytes(
+        &self,
+        data: &mut std::vec::Vec<u8>,
+    ) -> std::result::Result<(), otspec::SerializationError> {
+        let long_metrics = self.number_of_vmetrics() as usize;
+        for (i, metric) in self.metrics.iter().enumerate() {
+            if i < long_metrics {
+                metric.to_bytes(data)?;
+            } else {
+                metric.tsb.to_bytes(data)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Deserializes a Vertical Metrics Table given a binary vector and the
+/// `numOfLongVerMetrics` field of the `vhea` table.
+pub fn from_bytes(
+    c: &mut ReaderContext,
+    number_of_v_metrics: uint16,
+) -> Result<vmtx, DeserializationError> {
+    let mut res = vmtx {
+        metrics: Vec::new(),
+    };
+    for _ in 0..number_of_v_metrics {
+        let metric: Metric = c.de()?;
+        res.metrics.push(metric)
+    }
+    let other_metrics: Vec<int16> = c.de()?;
+    if !other_metrics.is_empty() {
+        let last = res
+            .metrics
+            .last()
+            .ok_or_else(|| DeserializationError("Must be one advance height in vmtx".into()))?
+            .advanceHeight;
+        res.metrics.extend(other_metrics.iter().map(|&tsb| Metric {
+            advanceHeight: last,
+            tsb,
+        }))
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vmtx_serde() {
+        let binary_vmtx = vec![0x03, 0xe8, 0x00, 0x32, 0x03, 0x84, 0x00, 0x64, 0xff, 0x9c];
+        let fvmtx = from_bytes(&mut ReaderContext::new(binary_vmtx.clone()), 2).unwrap();
+        assert_eq!(
+            fvmtx.metrics,
+            vec![
+                Metric {
+                    advanceHeight: 1000,
+                    tsb: 50,
+                },
+                Metric {
+                    advanceHeight: 900,
+                    tsb: 100,
+                },
+                Metric {
+                    advanceHeight: 900,
+                    tsb: -100,
+                },
+            ]
+        );
+        assert_eq!(fvmtx.number_of_vmetrics(), 2);
+        assert_eq!(otspec::ser::to_bytes(&fvmtx).unwrap(), binary_vmtx);
+    }
+}
@@ -0,0 +1,51 @@
+//! Fonts and tables shared by the unit tests
+
+use crate::font::{Font, SfntVersion};
+use crate::tables;
+use otspec::types::*;
+
+/// An `hhea` table with the given ascender, a descender of -200 and a
+/// vertical caret. The metrics summary fields and `numberOfHMetrics` are
+/// left at zero.
+pub fn hhea(ascender: FWORD) -> tables::hhea::hhea {
+    tables::hhea::hhea {
+        majorVersion: 1,
+        minorVersion: 0,
+        ascender,
+        descender: -200,
+        lineGap: 0,
+        advanceWidthMax: 0,
+        minLeftSideBearing: 0,
+        minRightSideBearing: 0,
+        xMaxExtent: 0,
+        caretSlopeRise: 1,
+        caretSlopeRun: 0,
+        caretOffset: 0,
+        reserved0: 0,
+        reserved1: 0,
+        reserved2: 0,
+        reserved3: 0,
+        metricDataFormat: 0,
+        numberOfHMetrics: 0,
+    }
+}
+
+/// A TrueType font with `head`, `hhea` and `maxp` tables for the given
+/// number of glyphs, to which tests add the tables they need.
+pub fn test_font(num_glyphs: uint16) -> Font {
+    font_with_version(SfntVersion::TrueType, num_glyphs)
+}
+
+/// Like [`test_font`], but for a font with CFF outlines.
+pub fn cff_test_font(num_glyphs: uint16) -> Font {
+    font_with_version(SfntVersion::OpenType, num_glyphs)
+}
+
+fn font_with_version(sfnt_version: SfntVersion, num_glyphs: uint16) -> Font {
+    let mut font = Font::new(sfnt_version);
+    font.tables
+        .insert(tables::head::new(1.0, 1000, 0, 0, 500, 700));
+    font.tables.insert(hhea(800));
+    font.tables.insert(tables::maxp::maxp::new05(num_glyphs));
+    font
+}
@@ -0,0 +1,341 @@
+//! WOFF 1.0 web font support.
+//!
+//! A [WOFF] file wraps an OpenType font, compressing each table individually
+//! with zlib, and optionally attaching an XML metadata block and a block of
+//! private data.
+//!
+//! WOFF files are recognised automatically by [`Font::load`] and friends;
+//! use [`decode`] to also retrieve the metadata and private blocks. To write
+//! a WOFF file, use [`Font::save_woff`] or [`Font::write_woff`].
+//!
+//! # Example
+//! ```no_run
+//! use fonttools::font::Font;
+//! use fonttools::woff::WoffOptions;
+//!
+//! let mut myfont = Font::load("Test.otf").expect("Could not load font");
+//! let options = WoffOptions {
+//!     metadata: Some("<?xml version=\"1.0\" encoding=\"UTF-8\"?>...".to_string()),
+//!     ..Default::default()
+//! };
+//! myfont.save_woff("Test.woff", &options).expect("Could not save");
+//! ```
+//!
+//! [WOFF]: https://www.w3.org/TR/WOFF/
+
+use crate::font::{Font, SfntVersion};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use otspec::types::*;
+use otspec::{DeserializationError, Deserializer, ReaderContext, SerializationError, Serializer};
+use otspec_macros::{Deserialize, Serialize};
+use std::convert::{TryFrom, TryInto};
+use std::io::{Read, Write};
+use std::rc::Rc;
+
+/// The signature which begins a WOFF file.
+pub const SIGNATURE: Tag = crate::tag!("wOFF");
+
+/// The WOFF file header
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct WoffHeader {
+    signature: Tag,
+    flavor: uint32,
+    length: uint32,
+    numTables: uint16,
+    reserved: uint16,
+    totalSfntSize: uint32,
+    majorVersion: uint16,
+    minorVersion: uint16,
+    metaOffset: uint32,
+    metaLength: uint32,
+    metaOrigLength: uint32,
+    privOffset: uint32,
+    privLength: uint32,
+}
+
+/// An entry in the WOFF table directory
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct WoffTableDirectoryEntry {
+    tag: Tag,
+    offset: uint32,
+    compLength: uint32,
+    origLength: uint32,
+    origChecksum: uint32,
+}
+
+const HEADER_SIZE: usize = 44;
+const DIRECTORY_ENTRY_SIZE: usize = 20;
+
+/// The parts of a WOFF file which are not part of the font itself.
+///
+/// These are returned by [`decode`], and may be supplied when writing a
+/// WOFF file with [`Font::save_woff`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WoffOptions {
+    /// Major version of the WOFF file (this is the font's version, not
+    /// the version of the WOFF format)
+    pub major_version: uint16,
+    /// Minor version of the WOFF file
+    pub minor_version: uint16,
+    /// The extended metadata block: an XML document, stored compressed
+    pub metadata: Option<String>,
+    /// The private data block, stored as-is
+    pub private_data: Option<Vec<u8>>,
+}
+
+/// Returns `true` if the given binary data starts with a WOFF signature.
+pub fn is_woff(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && bytes[0..4] == *SIGNATURE.as_bytes()
+}
+
+fn decompress(data: &[u8], expected_length: usize) -> Result<Vec<u8>, DeserializationError> {
+    let mut output = Vec::with_capacity(expected_length);
+    ZlibDecoder::new(data)
+        .read_to_end(&mut output)
+        .map_err(|e| DeserializationError(format!("Bad zlib data: {}", e)))?;
+    if output.len() != expected_length {
+        return Err(DeserializationError(format!(
+            "Decompressed data had length {}, expected {}",
+            output.len(),
+            expected_length
+        )));
+    }
+    Ok(output)
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>, SerializationError> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| SerializationError(format!("Could not compress data: {}", e)))
+}
+
+fn block(input: &[u8], offset: u32, length: u32) -> Result<&[u8], DeserializationError> {
+    let start = offset as usize;
+    let end = start + length as usize;
+    input
+        .get(start..end)
+        .ok_or_else(|| DeserializationError("WOFF block extends beyond end of file".to_string()))
+}
+
+/// Decodes a WOFF file into a font, along with its metadata and private data.
+pub fn decode(bytes: &[u8]) -> Result<(Font, WoffOptions), DeserializationError> {
+    let mut c = ReaderContext::new(bytes.to_vec());
+    let header: WoffHeader = c.de()?;
+    if header.signature != SIGNATURE {
+        return Err(DeserializationError(
+            "WOFF file must begin with 'wOFF'".to_string(),
+        ));
+    }
+    let version = SfntVersion::try_from(header.flavor).map_err(|_| {
+        DeserializationError("WOFF file does not contain a valid font flavor".to_string())
+    })?;
+    let entries: Vec<WoffTableDirectoryEntry> = c.de_counted(header.numTables as usize)?;
+
+    let mut raw_tables = crate::table_store::TableLoader::default();
+    for entry in entries {
+        let data = block(bytes, entry.offset, entry.compLength)?;
+        let table: Rc<[u8]> = match entry.compLength.cmp(&entry.origLength) {
+            std::cmp::Ordering::Less => decompress(data, entry.origLength as usize)?.into(),
+            std::cmp::Ordering::Equal => data.into(),
+            std::cmp::Ordering::Greater => {
+                return Err(DeserializationError(format!(
+                    "Compressed length of table {} is larger than its original length",
+                    entry.tag
+                )))
+            }
+        };
+        raw_tables.add(entry.tag, table);
+    }
+    let mut font = Font::new(version);
+    font.tables = raw_tables.finish()?;
+
+    let metadata =
+        if header.metaLength > 0 {
+            let xml = decompress(
+                block(bytes, header.metaOffset, header.metaLength)?,
+                header.metaOrigLength as usize,
+            )?;
+            Some(String::from_utf8(xml).map_err(|_| {
+                DeserializationError("WOFF metadata is not valid UTF-8".to_string())
+            })?)
+        } else {
+            None
+        };
+    let private_data = if header.privLength > 0 {
+        Some(block(bytes, header.privOffset, header.privLength)?.to_vec())
+    } else {
+        None
+    };
+    Ok((
+        font,
+        WoffOptions {
+            major_version: header.majorVersion,
+            minor_version: header.minorVersion,
+            metadata,
+            private_data,
+        },
+    ))
+}
+
+fn pad_to_four_bytes(data: &mut Vec<u8>) {
+    while !data.len().is_multiple_of(4) {
+        data.push(0);
+    }
+}
+
+/// Encodes a font as a WOFF file.
+///
+/// The font should already have been compiled; see [`Font::write_woff`].
+pub(crate) fn encode(font: &Font, options: &WoffOptions) -> Result<Vec<u8>, SerializationError> {
+    let tables = font.compiled_tables()?;
+    let num_tables: u16 = tables.len().try_into().unwrap();
+
+    let mut directory: Vec<u8> = vec![];
+    let mut table_data: Vec<u8> = vec![];
+    let data_start = HEADER_SIZE + DIRECTORY_ENTRY_SIZE * tables.len();
+    let mut total_sfnt_size = 12 + 16 * tables.len();
+    for table in &tables {
+        let compressed = compress(&table.data)?;
+        // Tables are only stored compressed if this makes them smaller.
+        let stored = if compressed.len() < table.data.len() {
+            &compressed[..]
+        } else {
+            &table.data[..]
+        };
+        directory.put(WoffTableDirectoryEntry {
+            tag: table.tag,
+            offset: (data_start + table_data.len()) as u32,
+            compLength: stored.len() as u32,
+            origLength: table.data.len() as u32,
+            origChecksum: table.checksum,
+        })?;
+        table_data.extend_from_slice(stored);
+        pad_to_four_bytes(&mut table_data);
+        total_sfnt_size += (table.data.len() + 3) & !3;
+    }
+
+    let mut output_blocks = table_data;
+    let (meta_offset, meta_length, meta_orig_length) = match &options.metadata {
+        Some(xml) => {
+            let compressed = compress(xml.as_bytes())?;
+            let offset = data_start + output_blocks.len();
+            output_blocks.extend(&compressed);
+            (offset, compressed.len(), xml.len())
+        }
+        None => (0, 0, 0),
+    };
+    let (priv_offset, priv_length) = match &options.private_data {
+        Some(private_data) => {
+            // The private block must begin on a four-byte boundary.
+            pad_to_four_bytes(&mut output_blocks);
+            let offset = data_start + output_blocks.len();
+            output_blocks.extend(private_data);
+            (offset, private_data.len())
+        }
+        None => (0, 0),
+    };
+
+    let header = WoffHeader {
+        signature: SIGNATURE,
+        flavor: font.sfnt_version() as u32,
+        length: (data_start + output_blocks.len()) as u32,
+        numTables: num_tables,
+        reserved: 0,
+        totalSfntSize: total_sfnt_size as u32,
+        majorVersion: options.major_version,
+        minorVersion: options.minor_version,
+        metaOffset: meta_offset as u32,
+        metaLength: meta_length as u32,
+        metaOrigLength: meta_orig_length as u32,
+        privOffset: priv_offset as u32,
+        privLength: priv_length as u32,
+    };
+    let mut output: Vec<u8> = vec![];
+    output.put(header)?;
+    output.extend(directory);
+    output.extend(output_blocks);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tables;
+    use crate::testing;
+
+    fn test_font() -> Font {
+        let mut font = testing::test_font(5);
+        font.tables.insert(tables::name::name {
+            records: vec![tables::name::NameRecord::windows_unicode(
+                tables::name::NameRecordID::Copyright,
+                "Copyright ".repeat(20),
+            )],
+        });
+        font
+    }
+
+    #[test]
+    fn test_woff_roundtrip() {
+        let mut font = test_font();
+        let options = WoffOptions {
+            major_version: 1,
+            minor_version: 2,
+            metadata: Some("<metadata version=\"1.0\"></metadata>".to_string()),
+            private_data: Some(vec![1, 2, 3]),
+        };
+        let mut binary = vec![];
+        font.write_woff(&mut binary, &options).unwrap();
+        assert!(is_woff(&binary));
+
+        let mut c = ReaderContext::new(binary.clone());
+        let header: WoffHeader = c.de().unwrap();
+        assert_eq!(header.length as usize, binary.len());
+        assert_eq!(header.numTables, 4);
+        assert_eq!(header.privOffset % 4, 0);
+        let entries: Vec<WoffTableDirectoryEntry> = c.de_counted(4).unwrap();
+        let name_entry = entries.iter().find(|e| e.tag == tables::name::TAG).unwrap();
+        assert!(name_entry.compLength < name_entry.origLength);
+
+        let (decoded, decoded_options) = decode(&binary).unwrap();
+        assert_eq!(decoded_options, options);
+        assert_eq!(decoded.sfnt_version(), SfntVersion::TrueType);
+        assert_eq!(
+            *decoded.tables.name().unwrap().unwrap(),
+            *font.tables.name().unwrap().unwrap()
+        );
+
+        // The decoded font is the same as the original font written out directly.
+        let mut sfnt = vec![];
+        font.write(&mut sfnt).unwrap();
+        assert_eq!(header.totalSfntSize as usize, sfnt.len());
+        let mut loaded = Font::from_bytes(&binary).unwrap();
+        let mut resaved = vec![];
+        loaded.write(&mut resaved).unwrap();
+        assert_eq!(sfnt, resaved);
+    }
+
+    #[test]
+    fn test_woff_decode() {
+        // A single "test" table containing 100 zeros, compressed with zlib.
+        let binary = vec![
+            0x77, 0x4f, 0x46, 0x46, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x4c, 0x00, 0x01,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x74, 0x65, 0x73, 0x74, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x0c,
+            0x00, 0x00, 0x00, 0x64, 0x00, 0x00, 0x00, 0x00, 0x78, 0xda, 0x63, 0x60, 0xa0, 0x3d,
+            0x00, 0x00, 0x00, 0x64, 0x00, 0x01,
+        ];
+        let (font, options) = decode(&binary).unwrap();
+        assert_eq!(options, WoffOptions::default());
+        assert_eq!(
+            font.tables.serialize_table(crate::tag!("test")).unwrap(),
+            Some(vec![0; 100])
+        );
+    }
+}
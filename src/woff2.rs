@@ -0,0 +1,887 @@
+//! WOFF2 web font support.
+//!
+//! A [WOFF2] file compresses all the tables of a font (or of a font
+//! collection) together as a single Brotli stream. Before compression, the
+//! `glyf` and `loca` tables are transformed into a more compressible form,
+//! and the `hmtx` table may drop side bearings which can be recovered from
+//! the glyph outlines.
+//!
+//! WOFF2 files are recognised automatically by [`Font::load`] and
+//! [`FontCollection::load`]; use [`decode`] or [`decode_collection`] to also
+//! retrieve the metadata and private blocks. To write a WOFF2 file, use
+//! [`Font::save_woff2`] or [`FontCollection::save_woff2`].
+//!
+//! # Example
+//! ```no_run
+//! use fonttools::font::Font;
+//!
+//! let mut myfont = Font::load("Test.ttf").expect("Could not load font");
+//! myfont.save_woff2("Test.woff2", &Default::default()).expect("Could not save");
+//! ```
+//!
+//! [WOFF2]: https://www.w3.org/TR/WOFF2/
+
+use crate::collection::FontCollection;
+use crate::font::{Font, SfntVersion};
+use crate::tables;
+use crate::woff::WoffOptions;
+use brotli::enc::backward_references::BrotliEncoderMode;
+use brotli::enc::BrotliEncoderParams;
+use otspec::types::*;
+use otspec::{DeserializationError, Deserializer, ReaderContext, SerializationError, Serializer};
+use otspec_macros::{Deserialize, Serialize};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::{TryFrom, TryInto};
+use std::io::Read;
+use std::rc::Rc;
+
+/// The `glyf`/`loca` transform
+mod glyf;
+/// The `hmtx` transform
+mod hmtx;
+
+/// The signature which begins a WOFF2 file.
+pub const SIGNATURE: Tag = crate::tag!("wOF2");
+
+/// The WOFF2 file header
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct Woff2Header {
+    signature: Tag,
+    flavor: uint32,
+    length: uint32,
+    numTables: uint16,
+    reserved: uint16,
+    totalSfntSize: uint32,
+    totalCompressedSize: uint32,
+    majorVersion: uint16,
+    minorVersion: uint16,
+    metaOffset: uint32,
+    metaLength: uint32,
+    metaOrigLength: uint32,
+    privOffset: uint32,
+    privLength: uint32,
+}
+
+/// Tags which can be encoded in the table directory as a single index.
+const KNOWN_TAGS: [Tag; 63] = [
+    crate::tag!("cmap"),
+    crate::tag!("head"),
+    crate::tag!("hhea"),
+    crate::tag!("hmtx"),
+    crate::tag!("maxp"),
+    crate::tag!("name"),
+    crate::tag!("OS/2"),
+    crate::tag!("post"),
+    crate::tag!("cvt "),
+    crate::tag!("fpgm"),
+    crate::tag!("glyf"),
+    crate::tag!("loca"),
+    crate::tag!("prep"),
+    crate::tag!("CFF "),
+    crate::tag!("VORG"),
+    crate::tag!("EBDT"),
+    crate::tag!("EBLC"),
+    crate::tag!("gasp"),
+    crate::tag!("hdmx"),
+    crate::tag!("kern"),
+    crate::tag!("LTSH"),
+    crate::tag!("PCLT"),
+    crate::tag!("VDMX"),
+    crate::tag!("vhea"),
+    crate::tag!("vmtx"),
+    crate::tag!("BASE"),
+    crate::tag!("GDEF"),
+    crate::tag!("GPOS"),
+    crate::tag!("GSUB"),
+    crate::tag!("EBSC"),
+    crate::tag!("JSTF"),
+    crate::tag!("MATH"),
+    crate::tag!("CBDT"),
+    crate::tag!("CBLC"),
+    crate::tag!("COLR"),
+    crate::tag!("CPAL"),
+    crate::tag!("SVG "),
+    crate::tag!("sbix"),
+    crate::tag!("acnt"),
+    crate::tag!("avar"),
+    crate::tag!("bdat"),
+    crate::tag!("bloc"),
+    crate::tag!("bsln"),
+    crate::tag!("cvar"),
+    crate::tag!("fdsc"),
+    crate::tag!("feat"),
+    crate::tag!("fmtx"),
+    crate::tag!("fvar"),
+    crate::tag!("gvar"),
+    crate::tag!("hsty"),
+    crate::tag!("just"),
+    crate::tag!("lcar"),
+    crate::tag!("mort"),
+    crate::tag!("morx"),
+    crate::tag!("opbd"),
+    crate::tag!("prop"),
+    crate::tag!("trak"),
+    crate::tag!("Zapf"),
+    crate::tag!("Silf"),
+    crate::tag!("Glat"),
+    crate::tag!("Gloc"),
+    crate::tag!("Feat"),
+    crate::tag!("Sill"),
+];
+
+/// Marks a table directory entry whose tag is given explicitly.
+const ARBITRARY_TAG: u8 = 0x3f;
+
+const HEADER_SIZE: usize = 48;
+
+/// Reads a `UIntBase128` variable-length integer.
+pub(crate) fn read_uint_base128(c: &mut ReaderContext) -> Result<u32, DeserializationError> {
+    let mut accum: u32 = 0;
+    for i in 0..5 {
+        let byte: u8 = c.de()?;
+        if i == 0 && byte == 0x80 {
+            return Err(DeserializationError(
+                "UIntBase128 value has leading zeros".to_string(),
+            ));
+        }
+        if accum & 0xFE00_0000 != 0 {
+            return Err(DeserializationError(
+                "UIntBase128 value overflows".to_string(),
+            ));
+        }
+        accum = (accum << 7) | (byte & 0x7f) as u32;
+        if byte & 0x80 == 0 {
+            return Ok(accum);
+        }
+    }
+    Err(DeserializationError(
+        "UIntBase128 value is too long".to_string(),
+    ))
+}
+
+/// Writes a `UIntBase128` variable-length integer.
+pub(crate) fn write_uint_base128(data: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        bytes.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    data.extend(bytes.iter().rev());
+}
+
+/// Reads a `255UInt16` variable-length integer.
+pub(crate) fn read_255_uint16(c: &mut ReaderContext) -> Result<u16, DeserializationError> {
+    let code: u8 = c.de()?;
+    Ok(match code {
+        253 => c.de()?,
+        254 => {
+            let value: u8 = c.de()?;
+            value as u16 + 506
+        }
+        255 => {
+            let value: u8 = c.de()?;
+            value as u16 + 253
+        }
+        _ => code as u16,
+    })
+}
+
+/// Writes a `255UInt16` variable-length integer.
+pub(crate) fn write_255_uint16(data: &mut Vec<u8>, value: u16) {
+    match value {
+        0..=252 => data.push(value as u8),
+        253..=505 => data.extend(&[255, (value - 253) as u8]),
+        506..=761 => data.extend(&[254, (value - 506) as u8]),
+        _ => {
+            data.push(253);
+            data.extend(&value.to_be_bytes());
+        }
+    }
+}
+
+/// An entry in the WOFF2 table directory
+#[derive(Debug, Clone, PartialEq)]
+struct TableDirectoryEntry {
+    tag: Tag,
+    transform_version: u8,
+    orig_length: u32,
+    transform_length: Option<u32>,
+}
+
+impl TableDirectoryEntry {
+    /// Whether this table's data is stored transformed.
+    ///
+    /// Version 0 is the null transform for every table except `glyf` and
+    /// `loca`, for which version 3 is the null transform.
+    fn is_transformed(tag: Tag, transform_version: u8) -> bool {
+        if tag == tables::glyf::TAG || tag == tables::loca::TAG {
+            transform_version != 3
+        } else {
+            transform_version != 0
+        }
+    }
+
+    /// The length of the table's data in the decompressed stream.
+    fn stored_length(&self) -> u32 {
+        self.transform_length.unwrap_or(self.orig_length)
+    }
+
+    fn read(c: &mut ReaderContext) -> Result<Self, DeserializationError> {
+        let flags: u8 = c.de()?;
+        let tag = if flags & ARBITRARY_TAG == ARBITRARY_TAG {
+            c.de()?
+        } else {
+            KNOWN_TAGS[(flags & ARBITRARY_TAG) as usize]
+        };
+        let transform_version = flags >> 6;
+        let orig_length = read_uint_base128(c)?;
+        let transform_length = if Self::is_transformed(tag, transform_version) {
+            Some(read_uint_base128(c)?)
+        } else {
+            None
+        };
+        Ok(TableDirectoryEntry {
+            tag,
+            transform_version,
+            orig_length,
+            transform_length,
+        })
+    }
+
+    fn write(&self, data: &mut Vec<u8>) -> Result<(), SerializationError> {
+        let version_bits = self.transform_version << 6;
+        match KNOWN_TAGS.iter().position(|&t| t == self.tag) {
+            Some(index) => data.put(index as u8 | version_bits)?,
+            None => {
+                data.put(ARBITRARY_TAG | version_bits)?;
+                data.put(self.tag)?;
+            }
+        }
+        write_uint_base128(data, self.orig_length);
+        if let Some(transform_length) = self.transform_length {
+            write_uint_base128(data, transform_length);
+        }
+        Ok(())
+    }
+}
+
+/// Returns `true` if the given binary data starts with a WOFF2 signature.
+pub fn is_woff2(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && bytes[0..4] == *SIGNATURE.as_bytes()
+}
+
+fn decompress(data: &[u8], expected_length: usize) -> Result<Vec<u8>, DeserializationError> {
+    let mut output = Vec::with_capacity(expected_length);
+    brotli::Decompressor::new(data, 4096)
+        .read_to_end(&mut output)
+        .map_err(|e| DeserializationError(format!("Bad Brotli data: {}", e)))?;
+    if output.len() != expected_length {
+        return Err(DeserializationError(format!(
+            "Decompressed data had length {}, expected {}",
+            output.len(),
+            expected_length
+        )));
+    }
+    Ok(output)
+}
+
+fn compress(data: &[u8], mode: BrotliEncoderMode) -> Result<Vec<u8>, SerializationError> {
+    let params = BrotliEncoderParams {
+        quality: 11,
+        mode,
+        ..Default::default()
+    };
+    let mut output = vec![];
+    brotli::BrotliCompress(&mut &data[..], &mut output, &params)
+        .map_err(|e| SerializationError(format!("Could not compress data: {}", e)))?;
+    Ok(output)
+}
+
+fn block(input: &[u8], offset: u32, length: u32) -> Result<&[u8], DeserializationError> {
+    let start = offset as usize;
+    let end = start + length as usize;
+    input
+        .get(start..end)
+        .ok_or_else(|| DeserializationError("WOFF2 block extends beyond end of file".to_string()))
+}
+
+/// The decoded contents of a WOFF2 file, before the fonts are assembled.
+struct Woff2Contents {
+    flavor: uint32,
+    entries: Vec<TableDirectoryEntry>,
+    /// For each font, its flavor and the indices of its tables in `entries`.
+    fonts: Vec<(uint32, Vec<usize>)>,
+    /// The decompressed table data, indexed like `entries`.
+    tables: Vec<Rc<[u8]>>,
+    options: WoffOptions,
+}
+
+impl Woff2Contents {
+    fn read(bytes: &[u8]) -> Result<Self, DeserializationError> {
+        let mut c = ReaderContext::new(bytes.to_vec());
+        let header: Woff2Header = c.de()?;
+        if header.signature != SIGNATURE {
+            return Err(DeserializationError(
+                "WOFF2 file must begin with 'wOF2'".to_string(),
+            ));
+        }
+        let entries = (0..header.numTables)
+            .map(|_| TableDirectoryEntry::read(&mut c))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let fonts = if header.flavor == u32::from_be_bytes(*crate::collection::TAG.as_bytes()) {
+            let _version: uint32 = c.de()?;
+            let num_fonts = read_255_uint16(&mut c)?;
+            let mut fonts = Vec::with_capacity(num_fonts as usize);
+            for _ in 0..num_fonts {
+                let num_tables = read_255_uint16(&mut c)?;
+                let flavor: uint32 = c.de()?;
+                let mut indices = Vec::with_capacity(num_tables as usize);
+                for _ in 0..num_tables {
+                    let index = read_255_uint16(&mut c)? as usize;
+                    if index >= entries.len() {
+                        return Err(DeserializationError(format!(
+                            "Collection refers to missing table {}",
+                            index
+                        )));
+                    }
+                    indices.push(index);
+                }
+                fonts.push((flavor, indices));
+            }
+            fonts
+        } else {
+            vec![(header.flavor, (0..entries.len()).collect())]
+        };
+
+        let stored_length: usize = entries.iter().map(|e| e.stored_length() as usize).sum();
+        let stream = decompress(
+            block(bytes, c.ptr as u32, header.totalCompressedSize)?,
+            stored_length,
+        )?;
+        let mut start = 0;
+        let tables = entries
+            .iter()
+            .map(|entry| {
+                let end = start + entry.stored_length() as usize;
+                let data: Rc<[u8]> = stream[start..end].into();
+                start = end;
+                data
+            })
+            .collect();
+
+        let metadata = if header.metaLength > 0 {
+            let xml = decompress(
+                block(bytes, header.metaOffset, header.metaLength)?,
+                header.metaOrigLength as usize,
+            )?;
+            Some(String::from_utf8(xml).map_err(|_| {
+                DeserializationError("WOFF2 metadata is not valid UTF-8".to_string())
+            })?)
+        } else {
+            None
+        };
+        let private_data = if header.privLength > 0 {
+            Some(block(bytes, header.privOffset, header.privLength)?.to_vec())
+        } else {
+            None
+        };
+        Ok(Woff2Contents {
+            flavor: header.flavor,
+            entries,
+            fonts,
+            tables,
+            options: WoffOptions {
+                major_version: header.majorVersion,
+                minor_version: header.minorVersion,
+                metadata,
+                private_data,
+            },
+        })
+    }
+
+    /// Assembles the fonts, reconstructing any transformed tables.
+    ///
+    /// Fonts in a collection which share a `glyf` table also share its
+    /// reconstruction.
+    fn fonts(&self) -> Result<Vec<Font>, DeserializationError> {
+        let mut reconstructed_glyfs: BTreeMap<usize, (tables::glyf::glyf, Vec<u32>)> =
+            BTreeMap::new();
+        let mut fonts = Vec::with_capacity(self.fonts.len());
+        for (flavor, indices) in &self.fonts {
+            let version = SfntVersion::try_from(*flavor).map_err(|_| {
+                DeserializationError("WOFF2 file does not contain a valid font flavor".to_string())
+            })?;
+            let mut raw_tables = crate::table_store::TableLoader::default();
+            let mut transformed = BTreeMap::new();
+            for &index in indices {
+                let entry = &self.entries[index];
+                if entry.transform_length.is_some() {
+                    transformed.insert(entry.tag, index);
+                } else {
+                    raw_tables.add(entry.tag, self.tables[index].clone());
+                }
+            }
+            let mut font = Font::new(version);
+            font.tables = raw_tables.finish()?;
+
+            if let Some(&index) = transformed.get(&tables::glyf::TAG) {
+                if let Entry::Vacant(slot) = reconstructed_glyfs.entry(index) {
+                    let (glyf, _) = glyf::reconstruct(&self.tables[index])?;
+                    let (_, offsets) = glyf
+                        .to_bytes_and_offsets()
+                        .map_err(|e| DeserializationError(e.0))?;
+                    slot.insert((glyf, offsets));
+                }
+                let (glyf, offsets) = &reconstructed_glyfs[&index];
+                font.tables.insert(glyf.clone());
+                if transformed.contains_key(&tables::loca::TAG) {
+                    font.tables.insert(tables::loca::loca {
+                        indices: offsets
+                            .windows(2)
+                            .map(|w| if w[0] == w[1] { None } else { Some(w[0]) })
+                            .collect(),
+                    });
+                }
+            } else if transformed.contains_key(&tables::loca::TAG) {
+                return Err(DeserializationError(
+                    "Transformed loca table without a transformed glyf table".to_string(),
+                ));
+            }
+
+            if let Some(&index) = transformed.get(&tables::hmtx::TAG) {
+                let (num_glyphs, number_of_hmetrics) = match (
+                    font.tables.maxp()?,
+                    font.tables.hhea()?,
+                    transformed.get(&tables::glyf::TAG),
+                ) {
+                    (Some(maxp), Some(hhea), Some(_)) => (maxp.num_glyphs(), hhea.numberOfHMetrics),
+                    _ => {
+                        return Err(DeserializationError(
+                            "Transformed hmtx table needs maxp, hhea and transformed glyf"
+                                .to_string(),
+                        ))
+                    }
+                };
+                let glyf = &reconstructed_glyfs[&transformed[&tables::glyf::TAG]].0;
+                font.tables.insert(hmtx::reconstruct(
+                    &self.tables[index],
+                    num_glyphs,
+                    number_of_hmetrics,
+                    glyf,
+                )?);
+            }
+
+            for (&tag, &index) in &transformed {
+                if ![tables::glyf::TAG, tables::loca::TAG, tables::hmtx::TAG].contains(&tag) {
+                    return Err(DeserializationError(format!(
+                        "Unknown transform version {} for table {}",
+                        self.entries[index].transform_version, tag
+                    )));
+                }
+            }
+            fonts.push(font);
+        }
+        Ok(fonts)
+    }
+}
+
+/// Decodes a WOFF2 file into a font, along with its metadata and private data.
+///
+/// Returns an error if the file contains a font collection; use
+/// [`decode_collection`] for those.
+pub fn decode(bytes: &[u8]) -> Result<(Font, WoffOptions), DeserializationError> {
+    let contents = Woff2Contents::read(bytes)?;
+    if contents.fonts.len() != 1 || contents.flavor != contents.fonts[0].0 {
+        return Err(DeserializationError(
+            "WOFF2 file contains a font collection".to_string(),
+        ));
+    }
+    let font = contents.fonts()?.pop().unwrap();
+    Ok((font, contents.options))
+}
+
+/// Decodes a WOFF2 file into a font collection, along with its metadata
+/// and private data.
+///
+/// If the file contains a single font, a collection of one font is returned.
+pub fn decode_collection(
+    bytes: &[u8],
+) -> Result<(FontCollection, WoffOptions), DeserializationError> {
+    let contents = Woff2Contents::read(bytes)?;
+    let fonts = contents.fonts()?;
+    Ok((FontCollection::new(fonts), contents.options))
+}
+
+/// A table to be written into the WOFF2 file.
+struct StoredTable {
+    entry: TableDirectoryEntry,
+    /// The table data as it would appear in an OpenType font
+    original: Rc<[u8]>,
+    /// The (possibly transformed) data to be compressed
+    data: Vec<u8>,
+}
+
+/// Identifies a table when sharing tables between the fonts of a collection:
+/// its tag, its data, and the `glyf` data that its transform depends on.
+type TableKey = (Tag, Rc<[u8]>, Option<Rc<[u8]>>);
+
+/// Transforms a font's tables where possible.
+fn stored_tables(font: &Font) -> Result<Vec<StoredTable>, SerializationError> {
+    let to_ser_error = |e: DeserializationError| SerializationError(e.0);
+    let mut compiled = font.compiled_tables()?;
+    let has_glyf = compiled.iter().any(|t| t.tag == tables::glyf::TAG);
+    let has_loca = compiled.iter().any(|t| t.tag == tables::loca::TAG);
+    let transform_glyf = has_glyf && has_loca;
+    // Keep loca immediately after glyf, so that decoders can rebuild them together.
+    if transform_glyf {
+        let loca_pos = compiled
+            .iter()
+            .position(|t| t.tag == tables::loca::TAG)
+            .unwrap();
+        let loca = compiled.remove(loca_pos);
+        let glyf_pos = compiled
+            .iter()
+            .position(|t| t.tag == tables::glyf::TAG)
+            .unwrap();
+        compiled.insert(glyf_pos + 1, loca);
+    }
+
+    let glyf_table = if transform_glyf {
+        font.tables.glyf().map_err(to_ser_error)?
+    } else {
+        None
+    };
+    let mut stored = Vec::with_capacity(compiled.len());
+    for table in compiled {
+        let orig_length = table.data.len() as u32;
+        let untransformed = |transform_version| StoredTable {
+            entry: TableDirectoryEntry {
+                tag: table.tag,
+                transform_version,
+                orig_length,
+                transform_length: None,
+            },
+            original: table.data.clone(),
+            data: table.data.to_vec(),
+        };
+        let transformed = |data: Vec<u8>, transform_version| StoredTable {
+            entry: TableDirectoryEntry {
+                tag: table.tag,
+                transform_version,
+                orig_length,
+                transform_length: Some(data.len() as u32),
+            },
+            original: table.data.clone(),
+            data,
+        };
+        let stored_table = match (table.tag, &glyf_table) {
+            (tables::glyf::TAG, Some(glyf_table)) => {
+                let index_format = font
+                    .tables
+                    .head()
+                    .map_err(to_ser_error)?
+                    .map_or(0, |head| head.indexToLocFormat as uint16);
+                transformed(glyf::transform(glyf_table, index_format)?, 0)
+            }
+            (tables::loca::TAG, Some(_)) => transformed(vec![], 0),
+            (tables::glyf::TAG, None) | (tables::loca::TAG, None) => untransformed(3),
+            (tables::hmtx::TAG, Some(glyf_table)) => {
+                let hmtx_table = font.tables.hmtx().map_err(to_ser_error)?.unwrap();
+                match hmtx::transform(&hmtx_table, glyf_table)? {
+                    Some(data) => transformed(data, 1),
+                    None => untransformed(0),
+                }
+            }
+            _ => untransformed(0),
+        };
+        stored.push(stored_table);
+    }
+    Ok(stored)
+}
+
+fn pad_to_four_bytes(data: &mut Vec<u8>) {
+    while !data.len().is_multiple_of(4) {
+        data.push(0);
+    }
+}
+
+/// Encodes one or more fonts as a WOFF2 file.
+///
+/// The fonts should already have been compiled. If `collection` is true, a
+/// font collection is written, and tables which are identical between fonts
+/// are stored once.
+pub(crate) fn encode(
+    fonts: &[Font],
+    options: &WoffOptions,
+    collection: bool,
+) -> Result<Vec<u8>, SerializationError> {
+    if fonts.is_empty() || (!collection && fonts.len() > 1) {
+        return Err(SerializationError(
+            "A WOFF2 file must contain a single font or a collection".to_string(),
+        ));
+    }
+    let mut stored_tables_list: Vec<StoredTable> = vec![];
+    // Identical tables are shared, but a transformed hmtx table is only
+    // valid alongside the glyf table it was transformed against.
+    let mut seen: HashMap<TableKey, usize> = HashMap::new();
+    let mut font_entries: Vec<(uint32, Vec<usize>)> = vec![];
+    let mut total_sfnt_size = if collection { 12 + 4 * fonts.len() } else { 0 };
+    for font in fonts {
+        let tables = stored_tables(font)?;
+        let glyf_data = tables
+            .iter()
+            .find(|stored| stored.entry.tag == tables::glyf::TAG)
+            .map(|stored| stored.original.clone());
+        total_sfnt_size += 12 + 16 * tables.len();
+        let mut indices = Vec::with_capacity(tables.len());
+        for stored in tables {
+            let tag = stored.entry.tag;
+            let depends_on = if tag == tables::hmtx::TAG || tag == tables::loca::TAG {
+                glyf_data.clone()
+            } else {
+                None
+            };
+            let key = (tag, stored.original.clone(), depends_on);
+            let index = *seen.entry(key).or_insert_with(|| {
+                total_sfnt_size += (stored.entry.orig_length as usize + 3) & !3;
+                stored_tables_list.push(stored);
+                stored_tables_list.len() - 1
+            });
+            indices.push(index);
+        }
+        font_entries.push((font.sfnt_version() as uint32, indices));
+    }
+
+    let mut directory: Vec<u8> = vec![];
+    for stored in &stored_tables_list {
+        stored.entry.write(&mut directory)?;
+    }
+    if collection {
+        directory.put(0x0001_0000_u32)?;
+        write_255_uint16(&mut directory, font_entries.len().try_into().unwrap());
+        for (flavor, indices) in &font_entries {
+            write_255_uint16(&mut directory, indices.len().try_into().unwrap());
+            directory.put(*flavor)?;
+            for &index in indices {
+                write_255_uint16(&mut directory, index.try_into().unwrap());
+            }
+        }
+    }
+
+    let stream: Vec<u8> = stored_tables_list
+        .iter()
+        .flat_map(|stored| stored.data.iter().copied())
+        .collect();
+    let mut blocks = compress(&stream, BrotliEncoderMode::BROTLI_MODE_FONT)?;
+    let data_start = HEADER_SIZE + directory.len();
+    let total_compressed_size = blocks.len();
+    let (meta_offset, meta_length, meta_orig_length) = match &options.metadata {
+        Some(xml) => {
+            pad_to_four_bytes(&mut blocks);
+            let compressed = compress(xml.as_bytes(), BrotliEncoderMode::BROTLI_MODE_TEXT)?;
+            let offset = data_start + blocks.len();
+            blocks.extend(&compressed);
+            (offset, compressed.len(), xml.len())
+        }
+        None => (0, 0, 0),
+    };
+    let (priv_offset, priv_length) = match &options.private_data {
+        Some(private_data) => {
+            pad_to_four_bytes(&mut blocks);
+            let offset = data_start + blocks.len();
+            blocks.extend(private_data);
+            (offset, private_data.len())
+        }
+        None => (0, 0),
+    };
+
+    let header = Woff2Header {
+        signature: SIGNATURE,
+        flavor: if collection {
+            u32::from_be_bytes(*crate::collection::TAG.as_bytes())
+        } else {
+            font_entries[0].0
+        },
+        length: (data_start + blocks.len()) as u32,
+        numTables: stored_tables_list.len().try_into().unwrap(),
+        reserved: 0,
+        totalSfntSize: total_sfnt_size as u32,
+        totalCompressedSize: total_compressed_size as u32,
+        majorVersion: options.major_version,
+        minorVersion: options.minor_version,
+        metaOffset: meta_offset as u32,
+        metaLength: meta_length as u32,
+        metaOrigLength: meta_orig_length as u32,
+        privOffset: priv_offset as u32,
+        privLength: priv_length as u32,
+    };
+    let mut output: Vec<u8> = vec![];
+    output.put(header)?;
+    output.extend(directory);
+    output.extend(blocks);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tables::glyf::{Glyph, Point};
+    use crate::tables::hmtx::Metric;
+    use crate::testing;
+
+    #[test]
+    fn test_variable_length_integers() {
+        for &value in &[0, 127, 128, 16383, 16384, 0x0fff_ffff, u32::MAX] {
+            let mut data = vec![];
+            write_uint_base128(&mut data, value);
+            let mut c = ReaderContext::new(data.clone());
+            assert_eq!(read_uint_base128(&mut c).unwrap(), value);
+            assert_eq!(c.ptr, data.len());
+        }
+        let mut data = vec![];
+        write_uint_base128(&mut data, 63);
+        assert_eq!(data, vec![0x3f]);
+        assert!(read_uint_base128(&mut ReaderContext::new(vec![0x80, 0x01])).is_err());
+
+        for &(value, ref expected) in &[
+            (252, vec![252]),
+            (253, vec![255, 0]),
+            (506, vec![254, 0]),
+            (762, vec![253, 2, 250]),
+        ] {
+            let mut data = vec![];
+            write_255_uint16(&mut data, value);
+            assert_eq!(&data, expected);
+            assert_eq!(
+                read_255_uint16(&mut ReaderContext::new(data)).unwrap(),
+                value
+            );
+        }
+    }
+
+    fn square(x_min: int16, size: int16) -> Glyph {
+        let point = |x, y| Point {
+            x,
+            y,
+            on_curve: true,
+        };
+        Glyph {
+            xMin: x_min,
+            xMax: x_min + size,
+            yMin: 0,
+            yMax: size,
+            contours: vec![vec![
+                point(x_min, 0),
+                point(x_min, size),
+                point(x_min + size, size),
+                point(x_min + size, 0),
+            ]],
+            instructions: vec![],
+            components: vec![],
+            overlap: false,
+        }
+    }
+
+    fn test_font(width: uint16) -> Font {
+        let mut font = testing::test_font(3);
+        let mut hhea = testing::hhea(800);
+        hhea.advanceWidthMax = width;
+        font.tables.insert(hhea);
+        font.tables
+            .insert(tables::maxp::maxp::new10(3, 4, 1, 0, 0, 0, 0));
+        font.tables.insert(tables::glyf::glyf {
+            glyphs: vec![square(0, 0), square(50, 400), square(20, 300)],
+        });
+        font.tables.insert(tables::loca::loca { indices: vec![] });
+        font.tables.insert(tables::hmtx::hmtx {
+            metrics: vec![
+                Metric {
+                    advanceWidth: width,
+                    lsb: 0,
+                },
+                Metric {
+                    advanceWidth: width,
+                    lsb: 50,
+                },
+                Metric {
+                    advanceWidth: width,
+                    lsb: 20,
+                },
+            ],
+        });
+        font
+    }
+
+    fn standalone(font: &mut Font) -> Vec<u8> {
+        let mut binary = vec![];
+        font.write(&mut binary).unwrap();
+        binary
+    }
+
+    #[test]
+    fn test_woff2_roundtrip() {
+        let mut font = test_font(500);
+        let options = WoffOptions {
+            metadata: Some("<metadata version=\"1.0\"></metadata>".to_string()),
+            private_data: Some(vec![1, 2, 3]),
+            ..Default::default()
+        };
+        let mut binary = vec![];
+        font.write_woff2(&mut binary, &options).unwrap();
+        assert!(is_woff2(&binary));
+
+        let contents = Woff2Contents::read(&binary).unwrap();
+        let transformed: Vec<Tag> = contents
+            .entries
+            .iter()
+            .filter(|e| e.transform_length.is_some())
+            .map(|e| e.tag)
+            .collect();
+        assert_eq!(
+            transformed,
+            vec![tables::glyf::TAG, tables::loca::TAG, tables::hmtx::TAG]
+        );
+        let glyf_pos = contents
+            .entries
+            .iter()
+            .position(|e| e.tag == tables::glyf::TAG)
+            .unwrap();
+        assert_eq!(contents.entries[glyf_pos + 1].tag, tables::loca::TAG);
+
+        let (mut decoded, decoded_options) = decode(&binary).unwrap();
+        assert_eq!(decoded_options, options);
+        assert_eq!(standalone(&mut decoded), standalone(&mut font));
+
+        let mut loaded = Font::from_bytes(&binary).unwrap();
+        assert_eq!(standalone(&mut loaded), standalone(&mut font));
+        assert!(decode_collection(&binary).is_ok());
+    }
+
+    #[test]
+    fn test_woff2_collection() {
+        let mut collection = FontCollection::new(vec![test_font(500), test_font(600)]);
+        let head = collection.fonts[0].tables.head().unwrap().unwrap();
+        collection.fonts[1].tables.insert(head);
+        let mut binary = vec![];
+        collection
+            .write_woff2(&mut binary, &Default::default())
+            .unwrap();
+        assert!(decode(&binary).is_err());
+
+        // glyf, loca and maxp are shared; the head tables differ in their
+        // checksum adjustment.
+        let contents = Woff2Contents::read(&binary).unwrap();
+        assert_eq!(contents.entries.len(), 9);
+        assert_eq!(contents.fonts.len(), 2);
+
+        let mut reloaded = FontCollection::from_bytes(&binary).unwrap();
+        assert_eq!(reloaded.len(), 2);
+        for (font, original) in reloaded.fonts.iter_mut().zip(collection.fonts.iter_mut()) {
+            assert_eq!(standalone(font), standalone(original));
+        }
+    }
+}
@@ -0,0 +1,482 @@
+//! The WOFF2 `glyf`/`loca` transform.
+//!
+//! The transformed `glyf` table splits the glyph data into separate streams
+//! of contour counts, point counts, flags, coordinates, components, bounding
+//! boxes and instructions, which compress much better than the original
+//! interleaved data. The `loca` table is dropped entirely, and rebuilt from
+//! the reconstructed glyphs.
+use super::{read_255_uint16, write_255_uint16};
+use crate::tables::glyf::{glyf, Component, ComponentFlags, Glyph, Point};
+use otspec::types::*;
+use otspec::{DeserializationError, Deserializer, ReaderContext, SerializationError, Serializer};
+use otspec_macros::{Deserialize, Serialize};
+
+/// The header of the transformed `glyf` table
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct TransformedGlyfHeader {
+    reserved: uint16,
+    optionFlags: uint16,
+    numGlyphs: uint16,
+    indexFormat: uint16,
+    nContourStreamSize: uint32,
+    nPointsStreamSize: uint32,
+    flagStreamSize: uint32,
+    glyphStreamSize: uint32,
+    compositeStreamSize: uint32,
+    bboxStreamSize: uint32,
+    instructionStreamSize: uint32,
+}
+
+const HEADER_SIZE: usize = 36;
+/// Set in `optionFlags` when an overlapSimpleBitmap follows the streams.
+const OVERLAP_SIMPLE_BITMAP: uint16 = 1;
+
+fn bbox_bitmap_size(num_glyphs: usize) -> usize {
+    ((num_glyphs + 31) >> 5) << 2
+}
+
+fn with_sign(flag: u8, base: i32) -> i32 {
+    if flag & 1 != 0 {
+        base
+    } else {
+        -base
+    }
+}
+
+/// Reads a coordinate delta encoded as a flag and a triplet of bytes.
+fn read_triplet(flag: u8, c: &mut ReaderContext) -> Result<(i32, i32), DeserializationError> {
+    let flag = flag & 0x7f;
+    let mut byte = || -> Result<i32, DeserializationError> {
+        let b: u8 = c.de()?;
+        Ok(b as i32)
+    };
+    Ok(if flag < 10 {
+        (0, with_sign(flag, (((flag & 14) as i32) << 7) + byte()?))
+    } else if flag < 20 {
+        (
+            with_sign(flag, ((((flag - 10) & 14) as i32) << 7) + byte()?),
+            0,
+        )
+    } else if flag < 84 {
+        let b0 = (flag - 20) as i32;
+        let b1 = byte()?;
+        (
+            with_sign(flag, 1 + (b0 & 0x30) + (b1 >> 4)),
+            with_sign(flag >> 1, 1 + ((b0 & 0x0c) << 2) + (b1 & 0x0f)),
+        )
+    } else if flag < 120 {
+        let b0 = (flag - 84) as i32;
+        let (b1, b2) = (byte()?, byte()?);
+        (
+            with_sign(flag, 1 + ((b0 / 12) << 8) + b1),
+            with_sign(flag >> 1, 1 + (((b0 % 12) >> 2) << 8) + b2),
+        )
+    } else if flag < 124 {
+        let (b1, b2, b3) = (byte()?, byte()?, byte()?);
+        (
+            with_sign(flag, (b1 << 4) + (b2 >> 4)),
+            with_sign(flag >> 1, ((b2 & 0x0f) << 8) + b3),
+        )
+    } else {
+        let (b1, b2, b3, b4) = (byte()?, byte()?, byte()?, byte()?);
+        (
+            with_sign(flag, (b1 << 8) + b2),
+            with_sign(flag >> 1, (b3 << 8) + b4),
+        )
+    })
+}
+
+/// Writes a coordinate delta, returning the flag byte for the point.
+fn write_triplet(on_curve: bool, dx: i32, dy: i32, glyph_stream: &mut Vec<u8>) -> u8 {
+    let (abs_x, abs_y) = (dx.abs(), dy.abs());
+    let on_curve_bit = if on_curve { 0 } else { 128 };
+    let x_sign_bit = if dx < 0 { 0 } else { 1 };
+    let y_sign_bit = if dy < 0 { 0 } else { 1 };
+    let xy_sign_bits = x_sign_bit + 2 * y_sign_bit;
+    let (flag, bytes) = if dx == 0 && abs_y < 1280 {
+        (((abs_y & 0xf00) >> 7) + y_sign_bit, vec![abs_y & 0xff])
+    } else if dy == 0 && abs_x < 1280 {
+        (10 + ((abs_x & 0xf00) >> 7) + x_sign_bit, vec![abs_x & 0xff])
+    } else if abs_x < 65 && abs_y < 65 {
+        (
+            20 + ((abs_x - 1) & 0x30) + (((abs_y - 1) & 0x30) >> 2) + xy_sign_bits,
+            vec![(((abs_x - 1) & 0xf) << 4) | ((abs_y - 1) & 0xf)],
+        )
+    } else if abs_x < 769 && abs_y < 769 {
+        (
+            84 + 12 * (((abs_x - 1) & 0x300) >> 8) + (((abs_y - 1) & 0x300) >> 6) + xy_sign_bits,
+            vec![(abs_x - 1) & 0xff, (abs_y - 1) & 0xff],
+        )
+    } else if abs_x < 4096 && abs_y < 4096 {
+        (
+            120 + xy_sign_bits,
+            vec![
+                abs_x >> 4,
+                ((abs_x & 0xf) << 4) | (abs_y >> 8),
+                abs_y & 0xff,
+            ],
+        )
+    } else {
+        (
+            124 + xy_sign_bits,
+            vec![abs_x >> 8, abs_x & 0xff, abs_y >> 8, abs_y & 0xff],
+        )
+    };
+    glyph_stream.extend(bytes.into_iter().map(|b| b as u8));
+    (on_curve_bit + flag) as u8
+}
+
+fn stream(
+    data: &[u8],
+    start: &mut usize,
+    length: usize,
+) -> Result<ReaderContext, DeserializationError> {
+    let end = *start + length;
+    let slice = data.get(*start..end).ok_or_else(|| {
+        DeserializationError("Transformed glyf stream extends beyond table".to_string())
+    })?;
+    *start = end;
+    Ok(ReaderContext::new(slice.to_vec()))
+}
+
+fn points_bounds(contours: &[Vec<Point>]) -> (int16, int16, int16, int16) {
+    let mut points = contours.iter().flatten();
+    let first = match points.next() {
+        Some(p) => p,
+        None => return (0, 0, 0, 0),
+    };
+    points.fold(
+        (first.x, first.y, first.x, first.y),
+        |(x_min, y_min, x_max, y_max), p| {
+            (
+                x_min.min(p.x),
+                y_min.min(p.y),
+                x_max.max(p.x),
+                y_max.max(p.y),
+            )
+        },
+    )
+}
+
+/// Rebuilds a `glyf` table from its transformed representation.
+///
+/// Returns the table along with the `indexFormat` recorded for the
+/// original `loca` table.
+pub(crate) fn reconstruct(data: &[u8]) -> Result<(glyf, uint16), DeserializationError> {
+    let header: TransformedGlyfHeader =
+        otspec::de::from_bytes(&data[..HEADER_SIZE.min(data.len())])?;
+    let num_glyphs = header.numGlyphs as usize;
+    let mut start = HEADER_SIZE;
+    let mut n_contour_stream = stream(data, &mut start, header.nContourStreamSize as usize)?;
+    let mut n_points_stream = stream(data, &mut start, header.nPointsStreamSize as usize)?;
+    let mut flag_stream = stream(data, &mut start, header.flagStreamSize as usize)?;
+    let mut glyph_stream = stream(data, &mut start, header.glyphStreamSize as usize)?;
+    let mut composite_stream = stream(data, &mut start, header.compositeStreamSize as usize)?;
+    let mut bbox_stream = stream(data, &mut start, header.bboxStreamSize as usize)?;
+    let mut instruction_stream = stream(data, &mut start, header.instructionStreamSize as usize)?;
+    let overlap_bitmap = if header.optionFlags & OVERLAP_SIMPLE_BITMAP != 0 {
+        stream(data, &mut start, (num_glyphs + 7) >> 3)?.input
+    } else {
+        vec![]
+    };
+    let bbox_bitmap: Vec<u8> = bbox_stream.de_counted(bbox_bitmap_size(num_glyphs))?;
+
+    let mut glyphs = Vec::with_capacity(num_glyphs);
+    for gid in 0..num_glyphs {
+        let has_bbox = bbox_bitmap[gid >> 3] & (0x80 >> (gid & 7)) != 0;
+        let n_contours: int16 = n_contour_stream.de()?;
+        let mut glyph = Glyph {
+            xMin: 0,
+            xMax: 0,
+            yMin: 0,
+            yMax: 0,
+            contours: vec![],
+            instructions: vec![],
+            components: vec![],
+            overlap: overlap_bitmap
+                .get(gid >> 3)
+                .is_some_and(|byte| byte & (0x80 >> (gid & 7)) != 0),
+        };
+        if n_contours == 0 {
+            if has_bbox {
+                return Err(DeserializationError(format!(
+                    "Empty glyph {} has an explicit bounding box",
+                    gid
+                )));
+            }
+            glyphs.push(glyph);
+            continue;
+        }
+        let mut has_instructions = n_contours > 0;
+        if n_contours < 0 {
+            if !has_bbox {
+                return Err(DeserializationError(format!(
+                    "Composite glyph {} has no bounding box",
+                    gid
+                )));
+            }
+            loop {
+                let component: Component = composite_stream.de()?;
+                let flags = component.flags;
+                if flags.contains(ComponentFlags::OVERLAP_COMPOUND) {
+                    glyph.overlap = true;
+                }
+                if flags.contains(ComponentFlags::WE_HAVE_INSTRUCTIONS) {
+                    has_instructions = true;
+                }
+                glyph.components.push(component);
+                if !flags.contains(ComponentFlags::MORE_COMPONENTS) {
+                    break;
+                }
+            }
+        } else {
+            let (mut x, mut y) = (0_i32, 0_i32);
+            for _ in 0..n_contours {
+                let n_points = read_255_uint16(&mut n_points_stream)?;
+                let mut contour = Vec::with_capacity(n_points as usize);
+                for _ in 0..n_points {
+                    let flag: u8 = flag_stream.de()?;
+                    let (dx, dy) = read_triplet(flag, &mut glyph_stream)?;
+                    x += dx;
+                    y += dy;
+                    contour.push(Point {
+                        x: x as int16,
+                        y: y as int16,
+                        on_curve: flag & 0x80 == 0,
+                    });
+                }
+                glyph.contours.push(contour);
+            }
+        }
+        if has_instructions {
+            let instruction_count = read_255_uint16(&mut glyph_stream)?;
+            glyph.instructions = instruction_stream.de_counted(instruction_count as usize)?;
+        }
+        let (x_min, y_min, x_max, y_max) = if has_bbox {
+            (
+                bbox_stream.de()?,
+                bbox_stream.de()?,
+                bbox_stream.de()?,
+                bbox_stream.de()?,
+            )
+        } else {
+            points_bounds(&glyph.contours)
+        };
+        glyph.xMin = x_min;
+        glyph.yMin = y_min;
+        glyph.xMax = x_max;
+        glyph.yMax = y_max;
+        glyphs.push(glyph);
+    }
+    Ok((glyf { glyphs }, header.indexFormat))
+}
+
+/// Applies the WOFF2 transform to a `glyf` table.
+///
+/// `index_format` is the `indexToLocFormat` of the font's `head` table.
+pub(crate) fn transform(table: &glyf, index_format: uint16) -> Result<Vec<u8>, SerializationError> {
+    let num_glyphs = table.glyphs.len();
+    let mut n_contour_stream: Vec<u8> = vec![];
+    let mut n_points_stream: Vec<u8> = vec![];
+    let mut flag_stream: Vec<u8> = vec![];
+    let mut glyph_stream: Vec<u8> = vec![];
+    let mut composite_stream: Vec<u8> = vec![];
+    let mut bbox_bitmap: Vec<u8> = vec![0; bbox_bitmap_size(num_glyphs)];
+    let mut bbox_stream: Vec<u8> = vec![];
+    let mut instruction_stream: Vec<u8> = vec![];
+    let mut overlap_bitmap: Vec<u8> = vec![0; (num_glyphs + 7) >> 3];
+
+    for (gid, glyph) in table.glyphs.iter().enumerate() {
+        let explicit_bbox = if glyph.is_empty() {
+            n_contour_stream.put(0_i16)?;
+            continue;
+        } else if glyph.has_components() {
+            n_contour_stream.put(-1_i16)?;
+            // The component records are stored exactly as in the glyf table,
+            // without the glyph header and trailing instructions.
+            let mut binary = otspec::ser::to_bytes(glyph)?;
+            if !glyph.instructions.is_empty() {
+                binary.truncate(binary.len() - 2 - glyph.instructions.len());
+            }
+            composite_stream.extend(&binary[10..]);
+            if !glyph.instructions.is_empty() {
+                write_255_uint16(&mut glyph_stream, glyph.instructions.len() as u16);
+            }
+            true
+        } else {
+            n_contour_stream.put(glyph.contours.len() as i16)?;
+            let (mut x, mut y) = (0_i32, 0_i32);
+            for contour in &glyph.contours {
+                write_255_uint16(&mut n_points_stream, contour.len() as u16);
+                for point in contour {
+                    let (dx, dy) = (point.x as i32 - x, point.y as i32 - y);
+                    flag_stream.push(write_triplet(point.on_curve, dx, dy, &mut glyph_stream));
+                    x = point.x as i32;
+                    y = point.y as i32;
+                }
+            }
+            write_255_uint16(&mut glyph_stream, glyph.instructions.len() as u16);
+            if glyph.overlap {
+                overlap_bitmap[gid >> 3] |= 0x80 >> (gid & 7);
+            }
+            points_bounds(&glyph.contours) != (glyph.xMin, glyph.yMin, glyph.xMax, glyph.yMax)
+        };
+        instruction_stream.extend(&glyph.instructions);
+        if explicit_bbox {
+            bbox_bitmap[gid >> 3] |= 0x80 >> (gid & 7);
+            bbox_stream.put(vec![glyph.xMin, glyph.yMin, glyph.xMax, glyph.yMax])?;
+        }
+    }
+    bbox_bitmap.extend(bbox_stream);
+    let has_overlaps = overlap_bitmap.iter().any(|&byte| byte != 0);
+
+    let mut data = vec![];
+    data.put(TransformedGlyfHeader {
+        reserved: 0,
+        optionFlags: if has_overlaps {
+            OVERLAP_SIMPLE_BITMAP
+        } else {
+            0
+        },
+        numGlyphs: num_glyphs as uint16,
+        indexFormat: index_format,
+        nContourStreamSize: n_contour_stream.len() as uint32,
+        nPointsStreamSize: n_points_stream.len() as uint32,
+        flagStreamSize: flag_stream.len() as uint32,
+        glyphStreamSize: glyph_stream.len() as uint32,
+        compositeStreamSize: composite_stream.len() as uint32,
+        bboxStreamSize: bbox_bitmap.len() as uint32,
+        instructionStreamSize: instruction_stream.len() as uint32,
+    })?;
+    data.extend(n_contour_stream);
+    data.extend(n_points_stream);
+    data.extend(flag_stream);
+    data.extend(glyph_stream);
+    data.extend(composite_stream);
+    data.extend(bbox_bitmap);
+    data.extend(instruction_stream);
+    if has_overlaps {
+        data.extend(overlap_bitmap);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kurbo::Affine;
+
+    #[test]
+    fn test_triplets() {
+        for &(dx, dy) in &[
+            (0, 0),
+            (0, -1279),
+            (1000, 0),
+            (-64, 64),
+            (1, -1),
+            (768, -700),
+            (-4095, 4000),
+            (20000, -32000),
+        ] {
+            for &on_curve in &[true, false] {
+                let mut glyph_stream = vec![];
+                let flag = write_triplet(on_curve, dx, dy, &mut glyph_stream);
+                assert_eq!(flag & 0x80 == 0, on_curve);
+                let mut c = ReaderContext::new(glyph_stream.clone());
+                assert_eq!(read_triplet(flag, &mut c).unwrap(), (dx, dy));
+                assert_eq!(c.ptr, glyph_stream.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_glyf_transform_roundtrip() {
+        let point = |x, y, on_curve| Point { x, y, on_curve };
+        let table = glyf {
+            glyphs: vec![
+                Glyph {
+                    xMin: 0,
+                    xMax: 0,
+                    yMin: 0,
+                    yMax: 0,
+                    contours: vec![],
+                    instructions: vec![],
+                    components: vec![],
+                    overlap: false,
+                },
+                Glyph {
+                    xMin: 10,
+                    xMax: 500,
+                    yMin: -20,
+                    yMax: 700,
+                    contours: vec![
+                        vec![
+                            point(10, -20, true),
+                            point(500, -20, true),
+                            point(500, 700, false),
+                        ],
+                        vec![point(100, 100, true), point(200, 150, true)],
+                    ],
+                    instructions: vec![0xb0, 0x01],
+                    components: vec![],
+                    overlap: true,
+                },
+                // This bounding box doesn't match the points, so must be stored.
+                Glyph {
+                    xMin: 0,
+                    xMax: 600,
+                    yMin: 0,
+                    yMax: 600,
+                    contours: vec![vec![point(5, 5, true), point(300, 5, true)]],
+                    instructions: vec![],
+                    components: vec![],
+                    overlap: false,
+                },
+                Glyph {
+                    xMin: 10,
+                    xMax: 800,
+                    yMin: -20,
+                    yMax: 700,
+                    contours: vec![],
+                    instructions: vec![0x01],
+                    components: vec![
+                        Component {
+                            glyph_index: 1,
+                            transformation: Affine::IDENTITY,
+                            match_points: None,
+                            flags: ComponentFlags::empty(),
+                        },
+                        Component {
+                            glyph_index: 2,
+                            transformation: Affine::new([1.0, 0.0, 0.0, 1.0, 300.0, 0.0]),
+                            match_points: None,
+                            flags: ComponentFlags::empty(),
+                        },
+                    ],
+                    overlap: false,
+                },
+            ],
+        };
+        let transformed = transform(&table, 1).unwrap();
+        let (reconstructed, index_format) = reconstruct(&transformed).unwrap();
+        assert_eq!(index_format, 1);
+        assert_eq!(reconstructed.glyphs.len(), 4);
+        for (new, old) in reconstructed.glyphs.iter().zip(table.glyphs.iter()) {
+            assert_eq!(new.contours, old.contours);
+            assert_eq!(new.instructions, old.instructions);
+            assert_eq!(new.overlap, old.overlap);
+            assert_eq!(new.bounds_rect(), old.bounds_rect());
+            assert_eq!(new.components.len(), old.components.len());
+            for (c1, c2) in new.components.iter().zip(old.components.iter()) {
+                assert_eq!(c1.glyph_index, c2.glyph_index);
+                assert_eq!(c1.transformation, c2.transformation);
+            }
+        }
+        // Both tables compile to the same binary.
+        assert_eq!(
+            otspec::ser::to_bytes(&reconstructed).unwrap(),
+            otspec::ser::to_bytes(&table).unwrap()
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! The WOFF2 `hmtx` transform.
+//!
+//! Left side bearings which are equal to the `xMin` of their glyph's
+//! bounding box can be omitted from the transformed table, and recovered
+//! from the reconstructed `glyf` table.
+use crate::tables::glyf::{glyf, Glyph};
+use crate::tables::hmtx::{hmtx, Metric};
+use otspec::types::*;
+use otspec::{DeserializationError, Deserializer, ReaderContext, SerializationError, Serializer};
+
+/// Set when the left side bearings of proportional glyphs are omitted.
+const PROPORTIONAL_LSBS_OMITTED: u8 = 1;
+/// Set when the left side bearings of monospaced glyphs are omitted.
+const MONOSPACED_LSBS_OMITTED: u8 = 2;
+
+fn x_min(glyph: Option<&Glyph>) -> int16 {
+    match glyph {
+        Some(glyph) if !glyph.is_empty() => glyph.xMin,
+        _ => 0,
+    }
+}
+
+/// Applies the WOFF2 transform to a `hmtx` table.
+///
+/// Returns `None` if no side bearings can be omitted, in which case the
+/// table should be stored untransformed.
+pub(crate) fn transform(table: &hmtx, glyf: &glyf) -> Result<Option<Vec<u8>>, SerializationError> {
+    let number_of_hmetrics = table.number_of_hmetrics() as usize;
+    let lsb_is_xmin = |(gid, metric): (usize, &Metric)| metric.lsb == x_min(glyf.glyphs.get(gid));
+    let (proportional, monospaced) = table.metrics.split_at(number_of_hmetrics);
+    let mut flags = 0;
+    if proportional.iter().enumerate().all(lsb_is_xmin) {
+        flags |= PROPORTIONAL_LSBS_OMITTED;
+    }
+    if monospaced
+        .iter()
+        .enumerate()
+        .all(|(i, metric)| lsb_is_xmin((i + number_of_hmetrics, metric)))
+    {
+        flags |= MONOSPACED_LSBS_OMITTED;
+    }
+    if flags == 0 {
+        return Ok(None);
+    }
+
+    let mut data: Vec<u8> = vec![];
+    data.put(flags)?;
+    for metric in proportional {
+        data.put(metric.advanceWidth)?;
+    }
+    if flags & PROPORTIONAL_LSBS_OMITTED == 0 {
+        for metric in proportional {
+            data.put(metric.lsb)?;
+        }
+    }
+    if flags & MONOSPACED_LSBS_OMITTED == 0 {
+        for metric in monospaced {
+            data.put(metric.lsb)?;
+        }
+    }
+    Ok(Some(data))
+}
+
+/// Rebuilds a `hmtx` table from its transformed representation.
+pub(crate) fn reconstruct(
+    data: &[u8],
+    num_glyphs: uint16,
+    number_of_hmetrics: uint16,
+    glyf: &glyf,
+) -> Result<hmtx, DeserializationError> {
+    let mut c = ReaderContext::new(data.to_vec());
+    let flags: u8 = c.de()?;
+    if number_of_hmetrics == 0 || number_of_hmetrics > num_glyphs {
+        return Err(DeserializationError(
+            "Bad numberOfHMetrics for transformed hmtx".to_string(),
+        ));
+    }
+    let advance_widths: Vec<uint16> = c.de_counted(number_of_hmetrics as usize)?;
+    let proportional_lsbs: Vec<int16> = if flags & PROPORTIONAL_LSBS_OMITTED == 0 {
+        c.de_counted(number_of_hmetrics as usize)?
+    } else {
+        (0..number_of_hmetrics as usize)
+            .map(|gid| x_min(glyf.glyphs.get(gid)))
+            .collect()
+    };
+    let monospaced_lsbs: Vec<int16> = if flags & MONOSPACED_LSBS_OMITTED == 0 {
+        c.de_counted((num_glyphs - number_of_hmetrics) as usize)?
+    } else {
+        (number_of_hmetrics as usize..num_glyphs as usize)
+            .map(|gid| x_min(glyf.glyphs.get(gid)))
+            .collect()
+    };
+    let last_advance = *advance_widths.last().unwrap();
+    let advance_widths = advance_widths
+        .into_iter()
+        .chain(std::iter::repeat(last_advance));
+    Ok(hmtx {
+        metrics: advance_widths
+            .zip(proportional_lsbs.into_iter().chain(monospaced_lsbs))
+            .map(|(advance_width, lsb)| Metric {
+                advanceWidth: advance_width,
+                lsb,
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tables::glyf::Point;
+
+    fn glyph(x_min: int16) -> Glyph {
+        Glyph {
+            xMin: x_min,
+            xMax: 300,
+            yMin: 0,
+            yMax: 300,
+            contours: vec![vec![
+                Point {
+                    x: x_min,
+                    y: 0,
+                    on_curve: true,
+                },
+                Point {
+                    x: 300,
+                    y: 300,
+                    on_curve: true,
+                },
+            ]],
+            instructions: vec![],
+            components: vec![],
+            overlap: false,
+        }
+    }
+
+    #[test]
+    fn test_hmtx_transform() {
+        let glyf = glyf {
+            glyphs: vec![glyph(10), glyph(20), glyph(30)],
+        };
+        let mut table = hmtx {
+            metrics: vec![
+                Metric {
+                    advanceWidth: 500,
+                    lsb: 10,
+                },
+                Metric {
+                    advanceWidth: 600,
+                    lsb: 20,
+                },
+                Metric {
+                    advanceWidth: 600,
+                    lsb: 5,
+                },
+            ],
+        };
+        let transformed = transform(&table, &glyf).unwrap().unwrap();
+        assert_eq!(transformed, vec![0x01, 0x01, 0xf4, 0x02, 0x58, 0x00, 0x05]);
+        assert_eq!(reconstruct(&transformed, 3, 2, &glyf).unwrap(), table);
+
+        table.metrics[0].lsb = 0;
+        table.metrics[2].lsb = 30;
+        let transformed = transform(&table, &glyf).unwrap().unwrap();
+        assert_eq!(transformed[0], MONOSPACED_LSBS_OMITTED);
+        assert_eq!(reconstruct(&transformed, 3, 2, &glyf).unwrap(), table);
+
+        table.metrics[2].lsb = 0;
+        assert_eq!(transform(&table, &glyf).unwrap(), None);
+    }
+}